@@ -18,6 +18,18 @@ fn dot_avx(c: &mut Criterion) {
     });
 }
 
+#[cfg(feature = "avx512")]
+fn dot_avx512(c: &mut Criterion) {
+    let u = random_array(ARRAY_SIZE);
+    let v = random_array(ARRAY_SIZE);
+    c.bench_function("dot_avx512", move |b| {
+        b.iter(|| black_box(unsafe { vec_simd::avx512::dot(u.view(), v.view()) }))
+    });
+}
+
+#[cfg(not(feature = "avx512"))]
+fn dot_avx512(_c: &mut Criterion) {}
+
 fn dot_fma(c: &mut Criterion) {
     let u = random_array(ARRAY_SIZE);
     let v = random_array(ARRAY_SIZE);
@@ -56,6 +68,7 @@ fn dot_unvectorized(c: &mut Criterion) {
 criterion_group!(
     benches,
     dot_avx,
+    dot_avx512,
     dot_fma,
     dot_ndarray,
     dot_sse,
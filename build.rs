@@ -14,5 +14,12 @@ fn main() {
             env!("CARGO_PKG_VERSION"),
             desc
         );
+
+        if let Ok(commit) = repo.head().and_then(|head| head.peel_to_commit()) {
+            println!(
+                "cargo:rustc-env=MAYBE_FINALFRONTIER_GIT_REVISION={}",
+                commit.id()
+            );
+        }
     }
 }
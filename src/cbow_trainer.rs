@@ -0,0 +1,353 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::iter::FusedIterator;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use rand::{Rng, RngCore, SeedableRng};
+use serde::Serialize;
+
+use crate::idx::{BagOfWordsIdx, WordIdx};
+use crate::sampling::{sample_context_window, NegativeSamplingRangeGenerator, RangeGenerator};
+use crate::skipgram_trainer::SkipgramMetadata;
+use crate::train_model::{NegativeSamples, OutputLabels, Subsampling, TrainIterFrom, Trainer};
+use crate::util::{Reseed, ReseedOnCloneRng};
+use crate::{CommonConfig, SkipGramConfig, Vocab};
+
+/// Continuous bag-of-words Trainer
+///
+/// The `CbowTrainer` holds the information and logic necessary to transform a tokenized
+/// sentence into an iterator of context bags and focus word tuples. The struct is cheap to
+/// clone because the vocabulary is shared between clones.
+#[derive(Clone)]
+pub struct CbowTrainer<R, V> {
+    vocab: Arc<V>,
+    rng: R,
+    range_gen: NegativeSamplingRangeGenerator<R>,
+    common_config: CommonConfig,
+    skipgram_config: SkipGramConfig,
+    subsampling: bool,
+}
+
+impl<R, V> CbowTrainer<ReseedOnCloneRng<R>, V>
+where
+    R: Rng + Clone + SeedableRng,
+    V: Vocab,
+{
+    /// Constructs a new `CbowTrainer`.
+    pub fn new(
+        vocab: V,
+        rng: R,
+        common_config: CommonConfig,
+        skipgram_config: SkipGramConfig,
+    ) -> Self {
+        let vocab = Arc::new(vocab);
+        let rng = ReseedOnCloneRng::new(rng);
+        let range_gen = NegativeSamplingRangeGenerator::new(
+            rng.clone(),
+            vocab.types().iter().map(|word| word.count()),
+            &common_config,
+        );
+        CbowTrainer {
+            vocab,
+            rng,
+            range_gen,
+            common_config,
+            skipgram_config,
+            subsampling: true,
+        }
+    }
+}
+
+impl<'a, S, R, V, I> TrainIterFrom<'a, [S]> for CbowTrainer<R, V>
+where
+    S: Hash + Eq,
+    R: Rng + Clone,
+    V: Vocab<IdxType = I>,
+    V::VocabType: Borrow<S>,
+    I: WordIdx,
+    for<'b> &'b I: IntoIterator<Item = u64>,
+{
+    type Iter = CbowIter<R, I>;
+    type Focus = BagOfWordsIdx;
+    type Contexts = Vec<usize>;
+
+    fn train_iter_from(&mut self, sequence: &[S]) -> Self::Iter {
+        let mut ids = Vec::new();
+        for t in sequence {
+            if let Some(mut idx) = self.vocab.idx(t) {
+                if !self.subsampling
+                    || self.rng.gen_range(0f32..1f32) < self.vocab.discard(idx.word_idx() as usize)
+                {
+                    if self.subsampling {
+                        idx.drop_subwords(&mut self.rng, self.common_config.subword_dropout);
+                    }
+                    ids.push(idx);
+                }
+            }
+        }
+        CbowIter::new(self.rng.clone(), ids, self.skipgram_config)
+    }
+}
+
+impl<R, V> Subsampling for CbowTrainer<R, V> {
+    fn set_subsampling(&mut self, subsampling: bool) {
+        self.subsampling = subsampling;
+    }
+}
+
+impl<R, V> NegativeSamples for CbowTrainer<R, V>
+where
+    R: Rng,
+{
+    fn negative_sample(&mut self, output: usize) -> usize {
+        loop {
+            let negative = self.range_gen.next().unwrap();
+            if negative != output {
+                return negative;
+            }
+        }
+    }
+
+    fn noise_prob(&self, output: usize) -> f32 {
+        self.range_gen.prob(output) as f32
+    }
+}
+
+impl<R, V> Reseed for CbowTrainer<R, V>
+where
+    R: Reseed + RngCore,
+{
+    fn reseed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+        self.range_gen.reseed(self.rng.next_u64());
+    }
+}
+
+impl<R, V> OutputLabels for CbowTrainer<R, V>
+where
+    V: Vocab,
+    V::VocabType: ToString,
+{
+    fn output_label(&self, output: usize) -> String {
+        self.vocab.types()[output].label().to_string()
+    }
+}
+
+impl<R, V> Trainer for CbowTrainer<R, V>
+where
+    R: Rng + Clone,
+    V: Vocab,
+    V::Config: Serialize,
+{
+    type InputVocab = V;
+    type Metadata = SkipgramMetadata<V::Config>;
+
+    fn input_vocab(&self) -> &V {
+        &self.vocab
+    }
+
+    fn try_into_input_vocab(self) -> Result<V> {
+        match Arc::try_unwrap(self.vocab) {
+            Ok(vocab) => Ok(vocab),
+            Err(_) => bail!("Cannot unwrap input vocab."),
+        }
+    }
+
+    fn n_input_types(&self) -> usize {
+        self.input_vocab().n_input_types()
+    }
+
+    fn n_output_types(&self) -> usize {
+        self.vocab.len()
+    }
+
+    fn config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+
+    fn to_metadata(&self) -> SkipgramMetadata<V::Config> {
+        SkipgramMetadata::new(
+            self.common_config,
+            self.skipgram_config,
+            self.vocab.config(),
+        )
+    }
+}
+
+/// Iterator over the context bag and focus identifier of each token in a sentence.
+///
+/// The input of a training instance is the bag of all indices of the context words
+/// (including their subword units). The output is the focus word.
+pub struct CbowIter<R, I> {
+    ids: Vec<I>,
+    rng: R,
+    i: usize,
+    ctx_size: usize,
+}
+
+impl<R, I> CbowIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+{
+    /// Constructs a new `CbowIter`.
+    ///
+    /// The `rng` is used to determine the window size for each focus token.
+    pub fn new(rng: R, ids: Vec<I>, skip_config: SkipGramConfig) -> Self {
+        CbowIter {
+            ids,
+            rng,
+            i: 0,
+            ctx_size: skip_config.context_size as usize,
+        }
+    }
+}
+
+impl<R, I> Iterator for CbowIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+    for<'a> &'a I: IntoIterator<Item = u64>,
+{
+    type Item = (BagOfWordsIdx, Vec<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.i < self.ids.len() {
+            let window =
+                sample_context_window(&mut self.rng, self.i, self.ids.len(), self.ctx_size);
+
+            let mut bag = BagOfWordsIdx::default();
+            for idx in window.filter(|&idx| idx != self.i) {
+                bag.extend(&self.ids[idx]);
+            }
+
+            let focus = self.ids[self.i].word_idx() as usize;
+            self.i += 1;
+
+            // Sentences with a single token do not have a context.
+            if !bag.is_empty() {
+                return Some((bag, vec![focus]));
+            }
+        }
+
+        None
+    }
+}
+
+impl<R, I> FusedIterator for CbowIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+    for<'a> &'a I: IntoIterator<Item = u64>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::{CbowIter, CbowTrainer};
+    use crate::idx::{SingleIdx, WordIdx};
+    use crate::train_model::{NegativeSamples, Trainer};
+    use crate::util::{all_close, close};
+    use crate::{
+        CommonConfig, Cutoff, ModelType, SimpleVocab, SimpleVocabConfig, SkipGramConfig, Vocab,
+        VocabBuilder,
+    };
+
+    const TEST_SKIP_CONFIG: SkipGramConfig = SkipGramConfig {
+        context_size: 3,
+        model: ModelType::Cbow,
+        harmonic_weighting: false,
+        context_cutoff: None,
+        word_ngrams: 1,
+    };
+
+    #[test]
+    fn bags_contain_the_window_around_the_focus() {
+        const LEN: usize = 20_000;
+
+        // The index of a word is its position in the sentence.
+        let ids = (0..LEN as u64).map(SingleIdx::from_word_idx).collect();
+        let iter = CbowIter::new(XorShiftRng::seed_from_u64(42), ids, TEST_SKIP_CONFIG);
+
+        // Count the contexts at each distance.
+        let mut hits = vec![0; 3];
+        let mut n_instances = 0;
+        for (bag, outputs) in iter {
+            assert_eq!(outputs.len(), 1);
+            let focus = outputs[0];
+            assert_eq!(focus, n_instances);
+            n_instances += 1;
+
+            // The bag is the window without the focus word.
+            let contexts = (&bag)
+                .into_iter()
+                .map(|idx| idx as usize)
+                .collect::<Vec<_>>();
+            assert!(!contexts.contains(&focus));
+            let left = contexts[0].min(focus);
+            let right = contexts[contexts.len() - 1].max(focus);
+            assert_eq!(contexts.len(), right - left);
+            assert!(focus - left <= 3 && right - focus <= 3);
+
+            if focus < 3 || focus >= LEN - 3 {
+                continue;
+            }
+
+            // Dynamic windows are symmetric.
+            assert_eq!(focus - left, right - focus);
+            hits[focus - left - 1] += 1;
+        }
+        assert_eq!(n_instances, LEN);
+
+        // A context at distance d is in the window if the sampled window
+        // size is at least d.
+        let n_windows = (LEN - 6) as f32;
+        let probs = (0..3)
+            .map(|distance| hits[distance..].iter().sum::<usize>() as f32 / n_windows)
+            .collect::<Vec<_>>();
+        assert!(all_close(&[1.0, 2. / 3., 1. / 3.], &probs, 1e-2));
+    }
+
+    #[test]
+    fn single_token_sentences_have_no_instances() {
+        let ids = vec![SingleIdx::from_word_idx(0)];
+        let mut iter = CbowIter::new(XorShiftRng::seed_from_u64(42), ids, TEST_SKIP_CONFIG);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn trainer_types_and_noise_distribution() {
+        let mut builder: VocabBuilder<SimpleVocabConfig, String> =
+            VocabBuilder::new(SimpleVocabConfig {
+                discard_threshold: 1e-4,
+                cutoff: Cutoff::MinCount(1),
+            });
+        for word in &["a", "a", "a", "b", "b", "c"] {
+            builder.count(word.to_string());
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        let n_types = vocab.len();
+        let trainer = CbowTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            CommonConfig::for_test(),
+            TEST_SKIP_CONFIG,
+        );
+
+        // Without subwords, the inputs and outputs are the words.
+        assert_eq!(trainer.n_input_types(), n_types);
+        assert_eq!(trainer.n_output_types(), n_types);
+
+        // The noise distribution is a distribution over the outputs that
+        // prefers frequent words.
+        let probs = (0..n_types)
+            .map(|output| trainer.noise_prob(output))
+            .collect::<Vec<_>>();
+        assert!(close(probs.iter().sum::<f32>(), 1., 1e-5));
+        assert!(probs.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+}
@@ -0,0 +1,493 @@
+//! Checkpoints of the training state.
+//!
+//! Checkpoints are written and resumed by the `skipgram` subcommand. The
+//! other training subcommands do not support checkpoints.
+
+use std::io::{Read, Write};
+
+use anyhow::{bail, ensure, Context, Result};
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2};
+
+use crate::optimizer::OptimizerState;
+
+/// Magic number of checkpoint files.
+const CHECKPOINT_MAGIC: [u8; 4] = *b"FFCP";
+
+/// Version of the checkpoint format.
+///
+/// Version 1 checkpoints do not store the positions of the training
+/// threads. They can still be read.
+const CHECKPOINT_VERSION: u32 = 2;
+
+/// Optimizer identifiers in checkpoint files.
+const OPTIMIZER_SGD: u8 = 0;
+const OPTIMIZER_ADAGRAD: u8 = 1;
+const OPTIMIZER_ADAM: u8 = 2;
+
+/// Training checkpoint.
+///
+/// A checkpoint stores the state of stochastic gradient descent: the
+/// vocabulary, the input and output matrices, the optimizer states, and
+/// the number of processed tokens. Checkpoints are taken with
+/// `Sgd::checkpoint` and restored with `Sgd::restore`.
+///
+/// The positions of the training threads and the seed of the validation
+/// loss are set by the training loop, so that training can continue
+/// where it stopped.
+///
+/// When embeddings are tied, the output matrix and its optimizer are the
+/// input matrix and its optimizer, so they are not stored separately.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub(crate) vocab: Vec<(String, usize)>,
+    pub(crate) n_tokens_processed: usize,
+    pub(crate) n_examples: usize,
+    pub(crate) loss: f32,
+    pub(crate) input: Array2<f32>,
+    pub(crate) output: Option<Array2<f32>>,
+    pub(crate) input_optimizer: OptimizerState,
+    pub(crate) output_optimizer: Option<OptimizerState>,
+    pub(crate) threads: Vec<ThreadCheckpoint>,
+    pub(crate) validation_seed: u64,
+}
+
+impl Checkpoint {
+    /// Get the number of tokens that were processed before the checkpoint.
+    pub fn n_tokens_processed(&self) -> usize {
+        self.n_tokens_processed
+    }
+
+    /// Get the positions of the training threads.
+    ///
+    /// Returns an empty slice when the checkpoint does not store the
+    /// positions of the training threads.
+    pub fn threads(&self) -> &[ThreadCheckpoint] {
+        &self.threads
+    }
+
+    /// Get the seed of the validation loss.
+    pub fn validation_seed(&self) -> u64 {
+        self.validation_seed
+    }
+
+    /// Set the positions of the training threads.
+    pub fn with_threads(mut self, threads: Vec<ThreadCheckpoint>) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Set the seed of the validation loss.
+    pub fn with_validation_seed(mut self, validation_seed: u64) -> Self {
+        self.validation_seed = validation_seed;
+        self
+    }
+
+    /// Read a checkpoint.
+    pub fn read<R>(read: &mut R) -> Result<Self>
+    where
+        R: Read,
+    {
+        let mut magic = [0u8; 4];
+        read.read_exact(&mut magic)
+            .context("Cannot read checkpoint magic")?;
+        ensure!(magic == CHECKPOINT_MAGIC, "File is not a checkpoint");
+        let version = read_u32(read)?;
+        ensure!(
+            version == 1 || version == CHECKPOINT_VERSION,
+            "Unsupported checkpoint version: {}",
+            version
+        );
+
+        let n_types = read_u64(read)? as usize;
+        let mut vocab = Vec::with_capacity(n_types);
+        for _ in 0..n_types {
+            let mut word = vec![0; read_u32(read)? as usize];
+            read.read_exact(&mut word)?;
+            let word = String::from_utf8(word).context("Checkpoint word is not UTF-8")?;
+            vocab.push((word, read_u64(read)? as usize));
+        }
+
+        let n_tokens_processed = read_u64(read)? as usize;
+        let n_examples = read_u64(read)? as usize;
+        let mut loss = [0u8; 4];
+        read.read_exact(&mut loss)?;
+
+        let input = read_array2(read)?;
+        let output = if read_u8(read)? != 0 {
+            Some(read_array2(read)?)
+        } else {
+            None
+        };
+        let input_optimizer = read_optimizer_state(read)?;
+        let output_optimizer = if read_u8(read)? != 0 {
+            Some(read_optimizer_state(read)?)
+        } else {
+            None
+        };
+
+        let (threads, validation_seed) = if version == 1 {
+            (Vec::new(), 0)
+        } else {
+            let validation_seed = read_u64(read)?;
+            let n_threads = read_u64(read)? as usize;
+            let threads = (0..n_threads)
+                .map(|_| ThreadCheckpoint::read(read))
+                .collect::<Result<Vec<_>>>()?;
+            (threads, validation_seed)
+        };
+
+        Ok(Checkpoint {
+            vocab,
+            n_tokens_processed,
+            n_examples,
+            loss: f32::from_le_bytes(loss),
+            input,
+            output,
+            input_optimizer,
+            output_optimizer,
+            threads,
+            validation_seed,
+        })
+    }
+
+    /// Write the checkpoint.
+    pub fn write<W>(&self, write: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        write.write_all(&CHECKPOINT_MAGIC)?;
+        write.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+
+        write.write_all(&(self.vocab.len() as u64).to_le_bytes())?;
+        for (word, count) in &self.vocab {
+            write.write_all(&(word.len() as u32).to_le_bytes())?;
+            write.write_all(word.as_bytes())?;
+            write.write_all(&(*count as u64).to_le_bytes())?;
+        }
+
+        write.write_all(&(self.n_tokens_processed as u64).to_le_bytes())?;
+        write.write_all(&(self.n_examples as u64).to_le_bytes())?;
+        write.write_all(&self.loss.to_le_bytes())?;
+
+        write_array2(write, self.input.view())?;
+        match &self.output {
+            Some(output) => {
+                write.write_all(&[1])?;
+                write_array2(write, output.view())?;
+            }
+            None => write.write_all(&[0])?,
+        }
+        write_optimizer_state(write, &self.input_optimizer)?;
+        match &self.output_optimizer {
+            Some(output_optimizer) => {
+                write.write_all(&[1])?;
+                write_optimizer_state(write, output_optimizer)?;
+            }
+            None => write.write_all(&[0])?,
+        }
+
+        write.write_all(&self.validation_seed.to_le_bytes())?;
+        write.write_all(&(self.threads.len() as u64).to_le_bytes())?;
+        for thread in &self.threads {
+            thread.write(write)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Position of a training thread.
+///
+/// The position consists of the seeds of the thread's random number
+/// generators and the number of sentences that the thread has read. A
+/// thread continues from its position by skipping the sentences of the
+/// current pass that it has already trained on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThreadCheckpoint {
+    /// Seed of the corpus shuffling, the pass number is added for each pass.
+    pub corpus_seed: u64,
+
+    /// Seed of the trainer, the sentence number is added for each sentence.
+    pub trainer_seed: u64,
+
+    /// Number of completed passes over the thread's part of the corpus.
+    pub pass: u64,
+
+    /// Number of sentences that were read in the current pass.
+    pub pass_sentences: u64,
+
+    /// Number of sentences that the thread has trained on.
+    pub n_sentences: u64,
+}
+
+impl ThreadCheckpoint {
+    /// Construct the position of a thread that has not started training.
+    pub fn new(corpus_seed: u64, trainer_seed: u64) -> Self {
+        ThreadCheckpoint {
+            corpus_seed,
+            trainer_seed,
+            pass: 0,
+            pass_sentences: 0,
+            n_sentences: 0,
+        }
+    }
+
+    fn read<R>(read: &mut R) -> Result<Self>
+    where
+        R: Read,
+    {
+        Ok(ThreadCheckpoint {
+            corpus_seed: read_u64(read)?,
+            trainer_seed: read_u64(read)?,
+            pass: read_u64(read)?,
+            pass_sentences: read_u64(read)?,
+            n_sentences: read_u64(read)?,
+        })
+    }
+
+    fn write<W>(&self, write: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        for v in &[
+            self.corpus_seed,
+            self.trainer_seed,
+            self.pass,
+            self.pass_sentences,
+            self.n_sentences,
+        ] {
+            write.write_all(&v.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn read_optimizer_state<R>(read: &mut R) -> Result<OptimizerState>
+where
+    R: Read,
+{
+    match read_u8(read)? {
+        OPTIMIZER_SGD => Ok(OptimizerState::Sgd),
+        OPTIMIZER_ADAGRAD => Ok(OptimizerState::AdaGrad(read_array1(read)?)),
+        OPTIMIZER_ADAM => {
+            let m = read_array2(read)?;
+            let v = read_array2(read)?;
+            let n_steps = read_u64(read)? as usize;
+            let steps = (0..n_steps)
+                .map(|_| read_u64(read))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(OptimizerState::Adam {
+                m,
+                v,
+                steps: steps.into(),
+            })
+        }
+        optimizer => bail!("Unknown optimizer in checkpoint: {}", optimizer),
+    }
+}
+
+fn write_optimizer_state<W>(write: &mut W, state: &OptimizerState) -> Result<()>
+where
+    W: Write,
+{
+    match state {
+        OptimizerState::Sgd => write.write_all(&[OPTIMIZER_SGD])?,
+        OptimizerState::AdaGrad(grad_sq) => {
+            write.write_all(&[OPTIMIZER_ADAGRAD])?;
+            write_array1(write, grad_sq.view())?;
+        }
+        OptimizerState::Adam { m, v, steps } => {
+            write.write_all(&[OPTIMIZER_ADAM])?;
+            write_array2(write, m.view())?;
+            write_array2(write, v.view())?;
+            write.write_all(&(steps.len() as u64).to_le_bytes())?;
+            for &step in steps {
+                write.write_all(&step.to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn read_array1<R>(read: &mut R) -> Result<Array1<f32>>
+where
+    R: Read,
+{
+    let len = read_u64(read)? as usize;
+    Ok(read_f32s(read, len)?.into())
+}
+
+fn write_array1<W>(write: &mut W, array: ArrayView1<f32>) -> Result<()>
+where
+    W: Write,
+{
+    write.write_all(&(array.len() as u64).to_le_bytes())?;
+    for &v in array {
+        write.write_all(&v.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn read_array2<R>(read: &mut R) -> Result<Array2<f32>>
+where
+    R: Read,
+{
+    let rows = read_u64(read)? as usize;
+    let cols = read_u64(read)? as usize;
+    let data = read_f32s(read, rows * cols)?;
+    Ok(Array2::from_shape_vec((rows, cols), data)?)
+}
+
+fn write_array2<W>(write: &mut W, array: ArrayView2<f32>) -> Result<()>
+where
+    W: Write,
+{
+    write.write_all(&(array.nrows() as u64).to_le_bytes())?;
+    write.write_all(&(array.ncols() as u64).to_le_bytes())?;
+    for &v in array {
+        write.write_all(&v.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn read_f32s<R>(read: &mut R, len: usize) -> Result<Vec<f32>>
+where
+    R: Read,
+{
+    let mut data = vec![0u8; len * 4];
+    read.read_exact(&mut data)
+        .context("Cannot read checkpoint matrix")?;
+    Ok(data
+        .chunks_exact(4)
+        .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+        .collect())
+}
+
+fn read_u8<R>(read: &mut R) -> Result<u8>
+where
+    R: Read,
+{
+    let mut buf = [0u8; 1];
+    read.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R>(read: &mut R) -> Result<u32>
+where
+    R: Read,
+{
+    let mut buf = [0u8; 4];
+    read.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R>(read: &mut R) -> Result<u64>
+where
+    R: Read,
+{
+    let mut buf = [0u8; 8];
+    read.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ndarray::{array, Array2};
+
+    use super::{Checkpoint, ThreadCheckpoint};
+    use crate::optimizer::OptimizerState;
+
+    #[test]
+    fn checkpoint_write_read_roundtrip() {
+        let checkpoint = Checkpoint {
+            vocab: vec![("a".to_string(), 3), ("über".to_string(), 1)],
+            n_tokens_processed: 42,
+            n_examples: 80,
+            loss: 12.5,
+            input: array![[1., 2.], [3., 4.], [5., 6.]],
+            output: Some(array![[-1., 0.], [0.5, 2.]]),
+            input_optimizer: OptimizerState::AdaGrad(array![1., 2., 3.]),
+            output_optimizer: Some(OptimizerState::Adam {
+                m: Array2::ones((2, 2)),
+                v: Array2::zeros((2, 2)),
+                steps: array![1, 7],
+            }),
+            threads: vec![
+                ThreadCheckpoint::new(1, 2),
+                ThreadCheckpoint {
+                    corpus_seed: 3,
+                    trainer_seed: 4,
+                    pass: 2,
+                    pass_sentences: 17,
+                    n_sentences: 51,
+                },
+            ],
+            validation_seed: 5,
+        };
+
+        let mut data = Vec::new();
+        checkpoint.write(&mut data).unwrap();
+        let read = Checkpoint::read(&mut Cursor::new(data)).unwrap();
+        assert_eq!(read, checkpoint);
+    }
+
+    #[test]
+    fn checkpoint_tied_roundtrip() {
+        let checkpoint = Checkpoint {
+            vocab: vec![("a".to_string(), 1)],
+            n_tokens_processed: 1,
+            n_examples: 1,
+            loss: 0.5,
+            input: array![[1., 2.]],
+            output: None,
+            input_optimizer: OptimizerState::Sgd,
+            output_optimizer: None,
+            threads: Vec::new(),
+            validation_seed: 0,
+        };
+
+        let mut data = Vec::new();
+        checkpoint.write(&mut data).unwrap();
+        let read = Checkpoint::read(&mut Cursor::new(data)).unwrap();
+        assert_eq!(read, checkpoint);
+    }
+
+    #[test]
+    fn checkpoint_rejects_other_files() {
+        assert!(Checkpoint::read(&mut Cursor::new(b"FFHN\x01\0\0\0".to_vec())).is_err());
+    }
+
+    #[test]
+    fn checkpoint_reads_version_1() {
+        let checkpoint = Checkpoint {
+            vocab: vec![("a".to_string(), 1)],
+            n_tokens_processed: 1,
+            n_examples: 1,
+            loss: 0.5,
+            input: array![[1., 2.]],
+            output: None,
+            input_optimizer: OptimizerState::Sgd,
+            output_optimizer: None,
+            threads: vec![ThreadCheckpoint::new(1, 2)],
+            validation_seed: 3,
+        };
+
+        // A version 1 checkpoint lacks the validation seed and thread
+        // positions at the end.
+        let mut data = Vec::new();
+        checkpoint.write(&mut data).unwrap();
+        data.truncate(data.len() - 16 - 40);
+        data[4..8].copy_from_slice(&1u32.to_le_bytes());
+
+        let read = Checkpoint::read(&mut Cursor::new(data)).unwrap();
+        assert!(read.threads().is_empty());
+        assert_eq!(read.validation_seed(), 0);
+        assert_eq!(read.input, checkpoint.input);
+    }
+}
@@ -1,7 +1,12 @@
+use std::borrow::Cow;
 use std::convert::TryFrom;
 
 use anyhow::{bail, Error, Result};
-use serde::Serialize;
+use rand::SeedableRng;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use udgraph::token::Token;
+use unicode_normalization::UnicodeNormalization;
 
 use crate::io::EmbeddingFormat;
 use crate::vocab::Cutoff;
@@ -17,6 +22,15 @@ pub enum ModelType {
 
     // The directional skip-gram model (Song et al., 2018).
     DirectionalSkipgram,
+
+    // The continuous bag-of-words model (Mikolov, 2013).
+    Cbow,
+
+    // The position-weighted continuous bag-of-words model (Mikolov et al., 2018).
+    PositionWeighted,
+
+    // The continuous window model (Ling et al., 2015).
+    ConcatenatedWindow,
 }
 
 impl TryFrom<u8> for ModelType {
@@ -27,6 +41,9 @@ impl TryFrom<u8> for ModelType {
             0 => Ok(ModelType::SkipGram),
             1 => Ok(ModelType::StructuredSkipGram),
             2 => Ok(ModelType::DirectionalSkipgram),
+            3 => Ok(ModelType::Cbow),
+            4 => Ok(ModelType::PositionWeighted),
+            5 => Ok(ModelType::ConcatenatedWindow),
             _ => bail!("Unknown model type: {}", model),
         }
     }
@@ -40,16 +57,54 @@ impl TryFrom<&str> for ModelType {
             "skipgram" => Ok(ModelType::SkipGram),
             "structgram" => Ok(ModelType::StructuredSkipGram),
             "dirgram" => Ok(ModelType::DirectionalSkipgram),
+            "cbow" => Ok(ModelType::Cbow),
+            "poswise" => Ok(ModelType::PositionWeighted),
+            "cwindow" => Ok(ModelType::ConcatenatedWindow),
             _ => bail!("Unknown model type: {}", model),
         }
     }
 }
 
+/// Paragraph vector model types.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum Doc2vecModelType {
+    // The distributed memory model (Le and Mikolov, 2014).
+    DistributedMemory,
+
+    // The distributed bag-of-words model (Le and Mikolov, 2014).
+    DistributedBagOfWords,
+}
+
+impl TryFrom<&str> for Doc2vecModelType {
+    type Error = Error;
+
+    fn try_from(model: &str) -> Result<Doc2vecModelType> {
+        match model {
+            "dm" => Ok(Doc2vecModelType::DistributedMemory),
+            "dbow" => Ok(Doc2vecModelType::DistributedBagOfWords),
+            _ => bail!("Unknown paragraph vector model type: {}", model),
+        }
+    }
+}
+
 /// Losses.
 #[derive(Copy, Clone, Debug, Serialize)]
 pub enum LossType {
     /// Logistic regression with negative sampling.
     LogisticNegativeSampling,
+
+    /// Weighted least squares regression on log co-occurrence counts.
+    WeightedLeastSquares,
+
+    /// Noise-contrastive estimation (Gutmann and Hyvärinen, 2012).
+    Nce,
+
+    /// Margin ranking of the positive output and negative samples.
+    MarginRanking,
+
+    /// InfoNCE with in-batch and sampled negatives (van den Oord et al.,
+    /// 2018).
+    InfoNce,
 }
 
 impl TryFrom<u8> for LossType {
@@ -58,11 +113,614 @@ impl TryFrom<u8> for LossType {
     fn try_from(model: u8) -> Result<LossType> {
         match model {
             0 => Ok(LossType::LogisticNegativeSampling),
+            1 => Ok(LossType::WeightedLeastSquares),
+            2 => Ok(LossType::Nce),
+            3 => Ok(LossType::MarginRanking),
+            4 => Ok(LossType::InfoNce),
             _ => bail!("Unknown model type: {}", model),
         }
     }
 }
 
+impl TryFrom<&str> for LossType {
+    type Error = Error;
+
+    fn try_from(loss: &str) -> Result<LossType> {
+        match loss {
+            "infonce" => Ok(LossType::InfoNce),
+            "ns" => Ok(LossType::LogisticNegativeSampling),
+            "nce" => Ok(LossType::Nce),
+            "margin" => Ok(LossType::MarginRanking),
+            _ => bail!("Unknown loss type: {}", loss),
+        }
+    }
+}
+
+/// Learning rate schedules.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum LrScheduleType {
+    /// Constant learning rate.
+    Constant,
+
+    /// Linear decay to zero.
+    Linear,
+
+    /// Cosine decay to zero (Loshchilov and Hutter, 2017).
+    Cosine,
+
+    /// Halve the learning rate after every epoch.
+    Step,
+}
+
+impl TryFrom<&str> for LrScheduleType {
+    type Error = Error;
+
+    fn try_from(schedule: &str) -> Result<LrScheduleType> {
+        match schedule {
+            "constant" => Ok(LrScheduleType::Constant),
+            "linear" => Ok(LrScheduleType::Linear),
+            "cosine" => Ok(LrScheduleType::Cosine),
+            "step" => Ok(LrScheduleType::Step),
+            _ => bail!("Unknown learning rate schedule: {}", schedule),
+        }
+    }
+}
+
+/// Distributions of negative samples.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum NegativeSamplingDistribution {
+    /// Zipfian distribution over the frequency ranks of outputs.
+    Zipf,
+
+    /// Unigram distribution of outputs raised to a power (Mikolov et al., 2013).
+    Unigram,
+}
+
+impl TryFrom<&str> for NegativeSamplingDistribution {
+    type Error = Error;
+
+    fn try_from(distribution: &str) -> Result<NegativeSamplingDistribution> {
+        match distribution {
+            "zipf" => Ok(NegativeSamplingDistribution::Zipf),
+            "unigram" => Ok(NegativeSamplingDistribution::Unigram),
+            _ => bail!("Unknown negative sampling distribution: {}", distribution),
+        }
+    }
+}
+
+/// Optimizers.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum OptimizerType {
+    /// Stochastic gradient descent.
+    Sgd,
+
+    /// AdaGrad (Duchi et al., 2011) with per-row accumulators.
+    AdaGrad,
+
+    /// Adam (Kingma and Ba, 2015) with lazy updates.
+    Adam,
+}
+
+impl TryFrom<&str> for OptimizerType {
+    type Error = Error;
+
+    fn try_from(optimizer: &str) -> Result<OptimizerType> {
+        match optimizer {
+            "sgd" => Ok(OptimizerType::Sgd),
+            "adagrad" => Ok(OptimizerType::AdaGrad),
+            "adam" => Ok(OptimizerType::Adam),
+            _ => bail!("Unknown optimizer: {}", optimizer),
+        }
+    }
+}
+
+/// Case folding of tokens.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum CaseFolding {
+    /// Tokens are used as-is.
+    None,
+
+    /// Tokens are lowercased.
+    Lowercase,
+
+    /// Tokens are folded using Unicode default case folding.
+    ///
+    /// In contrast to lowercasing, case folding also maps characters
+    /// such as *ß* to *ss* and final sigma to sigma.
+    Casefold,
+}
+
+impl CaseFolding {
+    /// Apply case folding to a string.
+    pub fn fold(self, s: &str) -> Cow<str> {
+        match self {
+            CaseFolding::None => Cow::Borrowed(s),
+            CaseFolding::Lowercase => Cow::Owned(s.to_lowercase()),
+            CaseFolding::Casefold => Cow::Owned(caseless::default_case_fold_str(s)),
+        }
+    }
+}
+
+/// Unicode normalization forms.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum NormalizationForm {
+    /// Tokens are not normalized.
+    None,
+
+    /// Canonical decomposition followed by canonical composition.
+    Nfc,
+
+    /// Canonical decomposition.
+    Nfd,
+
+    /// Compatibility decomposition followed by canonical composition.
+    Nfkc,
+
+    /// Compatibility decomposition.
+    Nfkd,
+}
+
+impl NormalizationForm {
+    /// Normalize a string.
+    pub fn normalize(self, s: &str) -> Cow<str> {
+        match self {
+            NormalizationForm::None => Cow::Borrowed(s),
+            NormalizationForm::Nfc => Cow::Owned(s.nfc().collect()),
+            NormalizationForm::Nfd => Cow::Owned(s.nfd().collect()),
+            NormalizationForm::Nfkc => Cow::Owned(s.nfkc().collect()),
+            NormalizationForm::Nfkd => Cow::Owned(s.nfkd().collect()),
+        }
+    }
+}
+
+impl TryFrom<&str> for NormalizationForm {
+    type Error = Error;
+
+    fn try_from(form: &str) -> Result<NormalizationForm> {
+        match form {
+            "none" => Ok(NormalizationForm::None),
+            "nfc" => Ok(NormalizationForm::Nfc),
+            "nfd" => Ok(NormalizationForm::Nfd),
+            "nfkc" => Ok(NormalizationForm::Nfkc),
+            "nfkd" => Ok(NormalizationForm::Nfkd),
+            _ => bail!("Unknown normalization form: {}", form),
+        }
+    }
+}
+
+/// The token that replaces numbers with `NumberNormalization::Placeholder`.
+pub const NUMBER_PLACEHOLDER: &str = "<num>";
+
+/// Normalization of numbers.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum NumberNormalization {
+    /// Numbers are used as-is.
+    None,
+
+    /// Every digit is replaced by *0*.
+    ///
+    /// Numbers of the same shape, such as *1984* and *2021*, are then
+    /// mapped to the same token.
+    Digits,
+
+    /// Tokens that are numbers are replaced by `NUMBER_PLACEHOLDER`.
+    Placeholder,
+}
+
+impl NumberNormalization {
+    /// Normalize the numbers in a token.
+    pub fn normalize(self, token: &str) -> Cow<str> {
+        match self {
+            NumberNormalization::None => Cow::Borrowed(token),
+            NumberNormalization::Digits => {
+                if token.chars().any(char::is_numeric) {
+                    Cow::Owned(
+                        token
+                            .chars()
+                            .map(|c| if c.is_numeric() { '0' } else { c })
+                            .collect(),
+                    )
+                } else {
+                    Cow::Borrowed(token)
+                }
+            }
+            NumberNormalization::Placeholder => {
+                if is_number(token) {
+                    Cow::Borrowed(NUMBER_PLACEHOLDER)
+                } else {
+                    Cow::Borrowed(token)
+                }
+            }
+        }
+    }
+}
+
+impl TryFrom<&str> for NumberNormalization {
+    type Error = Error;
+
+    fn try_from(numbers: &str) -> Result<NumberNormalization> {
+        match numbers {
+            "none" => Ok(NumberNormalization::None),
+            "digits" => Ok(NumberNormalization::Digits),
+            "placeholder" => Ok(NumberNormalization::Placeholder),
+            _ => bail!("Unknown number normalization: {}", numbers),
+        }
+    }
+}
+
+/// Check whether a token is a number.
+///
+/// A number consists of digits, optionally preceded by a sign and with
+/// decimal or thousands separators between digits, e.g. *-1,000.5*.
+fn is_number(token: &str) -> bool {
+    let digits = token.trim_start_matches(|c| c == '-' || c == '+');
+    let mut prev_digit = false;
+    for c in digits.chars() {
+        if c.is_numeric() {
+            prev_digit = true;
+        } else if (c == '.' || c == ',') && prev_digit {
+            prev_digit = false;
+        } else {
+            return false;
+        }
+    }
+
+    prev_digit
+}
+
+/// The number of scripts in `Script`.
+const N_SCRIPTS: usize = 11;
+
+/// Unicode scripts that can have their own n-gram lengths.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum Script {
+    Arabic,
+    Cyrillic,
+    Devanagari,
+    Greek,
+    Han,
+    Hangul,
+    Hebrew,
+    Hiragana,
+    Katakana,
+    Latin,
+    Thai,
+}
+
+impl Script {
+    const ALL: [Script; N_SCRIPTS] = [
+        Script::Arabic,
+        Script::Cyrillic,
+        Script::Devanagari,
+        Script::Greek,
+        Script::Han,
+        Script::Hangul,
+        Script::Hebrew,
+        Script::Hiragana,
+        Script::Katakana,
+        Script::Latin,
+        Script::Thai,
+    ];
+
+    /// Get the script of a character.
+    ///
+    /// Returns `None` for characters that do not belong to one of the
+    /// scripts, such as digits and punctuation.
+    pub fn of(c: char) -> Option<Script> {
+        match c {
+            'A'..='Z' | 'a'..='z' => Some(Script::Latin),
+            '\u{c0}'..='\u{d6}'
+            | '\u{d8}'..='\u{f6}'
+            | '\u{f8}'..='\u{24f}'
+            | '\u{1e00}'..='\u{1eff}' => Some(Script::Latin),
+            '\u{370}'..='\u{3ff}' | '\u{1f00}'..='\u{1fff}' => Some(Script::Greek),
+            '\u{400}'..='\u{52f}' => Some(Script::Cyrillic),
+            '\u{591}'..='\u{5f4}' => Some(Script::Hebrew),
+            '\u{600}'..='\u{6ff}' | '\u{750}'..='\u{77f}' => Some(Script::Arabic),
+            '\u{900}'..='\u{97f}' => Some(Script::Devanagari),
+            '\u{e01}'..='\u{e5b}' => Some(Script::Thai),
+            '\u{1100}'..='\u{11ff}' | '\u{3130}'..='\u{318f}' | '\u{ac00}'..='\u{d7af}' => {
+                Some(Script::Hangul)
+            }
+            '\u{3041}'..='\u{309f}' => Some(Script::Hiragana),
+            '\u{30a0}'..='\u{30ff}' | '\u{31f0}'..='\u{31ff}' => Some(Script::Katakana),
+            '\u{3400}'..='\u{4dbf}'
+            | '\u{4e00}'..='\u{9fff}'
+            | '\u{f900}'..='\u{faff}'
+            | '\u{20000}'..='\u{2fa1f}' => Some(Script::Han),
+            _ => None,
+        }
+    }
+
+    /// Get the script of a word.
+    ///
+    /// The script of a word is the script of most of its characters.
+    /// Returns `None` if none of the characters belong to a script.
+    pub fn of_word(word: &str) -> Option<Script> {
+        let mut counts = [0usize; N_SCRIPTS];
+        for script in word.chars().filter_map(Script::of) {
+            counts[script as usize] += 1;
+        }
+
+        Script::ALL
+            .iter()
+            .zip(counts.iter())
+            .filter(|(_, &count)| count != 0)
+            .max_by_key(|(_, &count)| count)
+            .map(|(&script, _)| script)
+    }
+
+    /// Get the name of the script.
+    pub fn name(self) -> &'static str {
+        match self {
+            Script::Arabic => "arabic",
+            Script::Cyrillic => "cyrillic",
+            Script::Devanagari => "devanagari",
+            Script::Greek => "greek",
+            Script::Han => "han",
+            Script::Hangul => "hangul",
+            Script::Hebrew => "hebrew",
+            Script::Hiragana => "hiragana",
+            Script::Katakana => "katakana",
+            Script::Latin => "latin",
+            Script::Thai => "thai",
+        }
+    }
+}
+
+impl TryFrom<&str> for Script {
+    type Error = Error;
+
+    fn try_from(script: &str) -> Result<Script> {
+        match Script::ALL.iter().find(|s| s.name() == script) {
+            Some(&script) => Ok(script),
+            None => bail!("Unknown script: {}", script),
+        }
+    }
+}
+
+/// N-gram lengths of subword units per script.
+///
+/// Words of a script without n-gram lengths use the global n-gram
+/// lengths of the vocabulary.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScriptNGrams {
+    lengths: [Option<(u32, u32)>; N_SCRIPTS],
+}
+
+impl ScriptNGrams {
+    /// Construct n-gram lengths without script-specific lengths.
+    pub const fn new() -> Self {
+        ScriptNGrams {
+            lengths: [None; N_SCRIPTS],
+        }
+    }
+
+    /// Get the minimum and maximum n-gram length of a script.
+    pub fn get(&self, script: Script) -> Option<(u32, u32)> {
+        self.lengths[script as usize]
+    }
+
+    /// Set the minimum and maximum n-gram length of a script.
+    pub fn set(&mut self, script: Script, min_n: u32, max_n: u32) {
+        self.lengths[script as usize] = Some((min_n, max_n));
+    }
+
+    /// Returns `true` if no script has its own n-gram lengths.
+    pub fn is_empty(&self) -> bool {
+        self.lengths.iter().all(Option::is_none)
+    }
+
+    /// Iterate over the scripts with their n-gram lengths.
+    pub fn iter(&self) -> impl Iterator<Item = (Script, u32, u32)> + '_ {
+        Script::ALL
+            .iter()
+            .zip(self.lengths.iter())
+            .filter_map(|(&script, lengths)| lengths.map(|(min_n, max_n)| (script, min_n, max_n)))
+    }
+}
+
+impl Serialize for ScriptNGrams {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        for (script, min_n, max_n) in self.iter() {
+            map.serialize_entry(script.name(), &[min_n, max_n])?;
+        }
+        map.end()
+    }
+}
+
+/// Parse n-gram lengths per script.
+///
+/// The lengths are specified as comma-separated *SCRIPT:MIN-MAX*
+/// triples, e.g. *han:1-2,latin:3-6*.
+impl TryFrom<&str> for ScriptNGrams {
+    type Error = Error;
+
+    fn try_from(lengths: &str) -> Result<ScriptNGrams> {
+        let mut script_ngrams = ScriptNGrams::default();
+        for script_lengths in lengths.split(',') {
+            let (script, min_n, max_n) = match parse_script_ngrams(script_lengths) {
+                Some(parts) => parts,
+                None => bail!(
+                    "Cannot parse script n-gram lengths, expected SCRIPT:MIN-MAX: {}",
+                    script_lengths
+                ),
+            };
+            let script = Script::try_from(script)?;
+            if min_n == 0 || min_n > max_n {
+                bail!(
+                    "Invalid n-gram lengths for script {}: {}-{}",
+                    script.name(),
+                    min_n,
+                    max_n
+                );
+            }
+            script_ngrams.set(script, min_n, max_n);
+        }
+
+        Ok(script_ngrams)
+    }
+}
+
+fn parse_script_ngrams(script_lengths: &str) -> Option<(&str, u32, u32)> {
+    let mut parts = script_lengths.trim().splitn(2, ':');
+    let script = parts.next()?;
+    let mut lengths = parts.next()?.splitn(2, '-');
+    let min_n = lengths.next()?.parse().ok()?;
+    let max_n = lengths.next()?.parse().ok()?;
+    Some((script, min_n, max_n))
+}
+
+/// Word boundary markers of n-gram subword units.
+///
+/// The markers are added to the beginning and end of a word before its
+/// n-grams are extracted, so that n-grams at the word boundaries are
+/// distinct from n-grams within words.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct WordBoundaries {
+    /// Beginning-of-word marker, no marker is added when absent.
+    pub bow: Option<char>,
+
+    /// End-of-word marker, no marker is added when absent.
+    pub eow: Option<char>,
+}
+
+impl WordBoundaries {
+    /// The default markers *<* and *>*, as used by fastText.
+    pub const BRACKETS: WordBoundaries = WordBoundaries {
+        bow: Some('<'),
+        eow: Some('>'),
+    };
+
+    /// Add the boundary markers to a word.
+    pub fn bracket(self, word: &str) -> String {
+        let mut bracketed = String::with_capacity(word.len() + 8);
+        bracketed.extend(self.bow);
+        bracketed.push_str(word);
+        bracketed.extend(self.eow);
+        bracketed
+    }
+}
+
+impl Default for WordBoundaries {
+    fn default() -> Self {
+        WordBoundaries::BRACKETS
+    }
+}
+
+/// Parse word boundary markers.
+///
+/// The markers are specified as a string of two characters, the
+/// beginning-of-word and end-of-word markers. The string *none*
+/// disables the markers.
+impl TryFrom<&str> for WordBoundaries {
+    type Error = Error;
+
+    fn try_from(markers: &str) -> Result<WordBoundaries> {
+        if markers == "none" {
+            return Ok(WordBoundaries {
+                bow: None,
+                eow: None,
+            });
+        }
+
+        let chars = markers.chars().collect::<Vec<_>>();
+        match chars.as_slice() {
+            &[bow, eow] => Ok(WordBoundaries {
+                bow: Some(bow),
+                eow: Some(eow),
+            }),
+            _ => bail!(
+                "Word boundaries should consist of two characters, was: {}",
+                markers
+            ),
+        }
+    }
+}
+
+/// Normalization of corpus tokens.
+///
+/// Tokens are first normalized to the Unicode normalization form, then
+/// case folding and number normalization are applied.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct TokenNormalization {
+    /// The Unicode normalization form.
+    pub form: NormalizationForm,
+
+    /// The case folding.
+    pub case_folding: CaseFolding,
+
+    /// The normalization of numbers.
+    pub numbers: NumberNormalization,
+}
+
+impl TokenNormalization {
+    /// Normalize a token.
+    pub fn normalize(self, token: &str) -> Cow<str> {
+        let token = self.form.normalize(token);
+        let token = and_then(token, |token| self.case_folding.fold(token));
+        and_then(token, |token| self.numbers.normalize(token))
+    }
+
+    /// Returns `true` if tokens are used as-is.
+    pub fn is_identity(self) -> bool {
+        self.form == NormalizationForm::None
+            && self.case_folding == CaseFolding::None
+            && self.numbers == NumberNormalization::None
+    }
+}
+
+impl Default for TokenNormalization {
+    fn default() -> Self {
+        TokenNormalization {
+            form: NormalizationForm::None,
+            case_folding: CaseFolding::None,
+            numbers: NumberNormalization::None,
+        }
+    }
+}
+
+/// Apply a transformation to a possibly-borrowed string.
+fn and_then<'a, F>(s: Cow<'a, str>, f: F) -> Cow<'a, str>
+where
+    F: FnOnce(&str) -> Cow<str>,
+{
+    match s {
+        Cow::Borrowed(s) => f(s),
+        Cow::Owned(s) => Cow::Owned(f(&s).into_owned()),
+    }
+}
+
+/// Precision of stored embeddings.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum Precision {
+    /// Single-precision floating point.
+    F32,
+
+    /// IEEE 754 half-precision floating point.
+    F16,
+
+    /// Brain floating point, the upper half of a single-precision float.
+    BF16,
+}
+
+impl TryFrom<&str> for Precision {
+    type Error = Error;
+
+    fn try_from(precision: &str) -> Result<Precision> {
+        match precision {
+            "f32" => Ok(Precision::F32),
+            "f16" => Ok(Precision::F16),
+            "bf16" => Ok(Precision::BF16),
+            _ => bail!("Unknown precision: {}", precision),
+        }
+    }
+}
+
 /// Bucket Indexer Types
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
 pub enum BucketIndexerType {
@@ -84,32 +742,263 @@ impl TryFrom<&str> for BucketIndexerType {
     }
 }
 
+/// Hyperparameters of product quantization.
+///
+/// Product quantization is used by the `EmbeddingFormat::FinalFusionQuantized`
+/// output format.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct QuantizerConfig {
+    /// The number of subquantizers.
+    ///
+    /// When absent, half the number of embedding dimensions is used.
+    pub n_subquantizers: Option<u32>,
+
+    /// The number of bits of the quantization codes of subquantizers.
+    pub n_subquantizer_bits: u32,
+
+    /// The number of k-means iterations.
+    pub n_iterations: u32,
+
+    /// The number of k-means attempts, of which the best is used.
+    pub n_attempts: u32,
+}
+
+impl Default for QuantizerConfig {
+    fn default() -> Self {
+        QuantizerConfig {
+            n_subquantizers: None,
+            n_subquantizer_bits: 8,
+            n_iterations: 100,
+            n_attempts: 1,
+        }
+    }
+}
+
 /// Common embedding model hyperparameters.
 #[derive(Clone, Copy, Debug, Serialize)]
 pub struct CommonConfig {
     /// The loss function used for the model.
     pub loss: LossType,
 
+    /// The margin by which the positive output should outscore negative
+    /// samples in the margin ranking loss.
+    pub margin: f32,
+
+    /// The number of in-batch negatives per output in the InfoNCE loss.
+    ///
+    /// The outputs of the other training instances of a sentence are
+    /// used as in-batch negatives.
+    pub batch_negatives: u32,
+
     /// Word embedding dimensionality.
     pub dims: u32,
 
     /// The number of training epochs.
     pub epochs: u32,
 
+    /// The number of final epochs with quantization-aware training.
+    ///
+    /// In these epochs, predictions use input embeddings with simulated
+    /// 8-bit quantization, so that the embeddings lose less accuracy
+    /// when they are quantized after training.
+    pub qat_epochs: u32,
+
     /// The output format.
     #[serde(skip)]
     pub format: EmbeddingFormat,
 
+    /// The precision in which the embeddings are written.
+    ///
+    /// This is independent of the precision in which the embedding
+    /// matrices are stored during training. Only the npz format
+    /// supports half precision.
+    pub output_precision: Precision,
+
+    /// Only write the embeddings of the `write_top_k` most frequent words.
+    ///
+    /// The subword embeddings are written in full, so that the words that
+    /// are not written are still covered by their subword units.
+    pub write_top_k: Option<usize>,
+
     /// Number of negative samples to use for each context word.
     pub negative_samples: u32,
 
     /// The initial learning rate.
     pub lr: f32,
 
+    /// The learning rate schedule.
+    pub lr_schedule: LrScheduleType,
+
+    /// The number of training steps in which the learning rate is
+    /// linearly increased to the initial learning rate.
+    pub warmup: u64,
+
+    /// The optimizer used to update the parameters.
+    pub optimizer: OptimizerType,
+
+    /// The precision in which the embedding matrices are stored.
+    ///
+    /// Computations are always done in single precision.
+    pub precision: Precision,
+
+    /// Maximum norm of gradients.
+    ///
+    /// Gradients with a larger norm are rescaled to this norm. By default,
+    /// the gradient of a training instance is clipped.
+    pub clip_norm: Option<f32>,
+
+    /// Clip the gradient of every updated embedding separately.
+    pub clip_per_row: bool,
+
+    /// Probability of dropping a subword unit of an input word.
+    ///
+    /// Subword units are dropped from the input representations during
+    /// training as regularization.
+    pub subword_dropout: f32,
+
+    /// Weight decay of embeddings.
+    ///
+    /// Embeddings are decayed by this factor, scaled by the learning rate,
+    /// before they are updated. Embeddings that are not updated are not
+    /// decayed.
+    pub weight_decay: f32,
+
+    /// Tie the output embeddings to the input embeddings.
+    ///
+    /// Output *i* is then represented by the embedding of input *i*. This
+    /// requires that the outputs of the model are the words of the input
+    /// vocabulary.
+    pub tied: bool,
+
+    /// The distribution from which negative samples are drawn.
+    pub ns_distribution: NegativeSamplingDistribution,
+
+    /// Reject negative samples that are outputs of the same focus.
+    ///
+    /// The true output is always rejected. With this option, the other
+    /// outputs of a focus (e.g. the words in its context window) are
+    /// rejected as well.
+    pub ns_exclude_window: bool,
+
+    /// Share negative samples across the outputs of a focus.
+    ///
+    /// One set of negative samples is drawn per focus and reused for
+    /// all of its outputs (e.g. all positions in the context window).
+    pub ns_shared: bool,
+
+    /// Power to which output counts are raised in the unigram distribution.
+    pub unigram_power: f64,
+
     /// Exponent in zipfian distribution.
     ///
     /// This is s in *f(k) = 1 / (k^s H_{N, s})*.
     pub zipf_exponent: f64,
+
+    /// Normalization that is applied to corpus tokens.
+    ///
+    /// Words should be normalized in the same manner when the embeddings
+    /// are looked up.
+    pub normalization: TokenNormalization,
+
+    /// Seed of the random number generators.
+    ///
+    /// If a seed is set, initialization, subsampling, negative sampling,
+    /// and shuffling are deterministic. If no seed is set, the random
+    /// number generators are seeded from entropy.
+    pub seed: Option<u64>,
+}
+
+impl CommonConfig {
+    /// Construct a random number generator for `stream`.
+    ///
+    /// If a seed is configured, the generator is seeded from the seed and
+    /// the stream, so that every stream gets a different, reproducible
+    /// sequence. Otherwise, the generator is seeded from entropy.
+    pub fn rng<R>(&self, stream: RngStream) -> R
+    where
+        R: SeedableRng,
+    {
+        match self.seed {
+            Some(seed) => R::seed_from_u64(seed.wrapping_add(stream.offset())),
+            None => R::from_entropy(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl CommonConfig {
+    /// Configuration for unit tests.
+    ///
+    /// Tests override the hyperparameters that they depend on with
+    /// struct update syntax.
+    pub(crate) const fn for_test() -> Self {
+        CommonConfig {
+            dims: 3,
+            epochs: 5,
+            qat_epochs: 0,
+            format: EmbeddingFormat::FinalFusion,
+            output_precision: Precision::F32,
+            write_top_k: None,
+            loss: LossType::LogisticNegativeSampling,
+            margin: 1.0,
+            batch_negatives: 0,
+            lr: 0.05,
+            lr_schedule: LrScheduleType::Linear,
+            optimizer: OptimizerType::Sgd,
+            precision: Precision::F32,
+            clip_norm: None,
+            clip_per_row: false,
+            subword_dropout: 0.,
+            tied: false,
+            weight_decay: 0.,
+            warmup: 0,
+            negative_samples: 5,
+            ns_distribution: NegativeSamplingDistribution::Zipf,
+            ns_exclude_window: false,
+            ns_shared: false,
+            unigram_power: 0.75,
+            zipf_exponent: 0.5,
+            normalization: TokenNormalization {
+                form: NormalizationForm::None,
+                case_folding: CaseFolding::None,
+                numbers: NumberNormalization::None,
+            },
+            seed: None,
+        }
+    }
+}
+
+/// Purpose of a random number generator.
+///
+/// Random number generators for different purposes are seeded
+/// differently, see `CommonConfig::rng`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RngStream {
+    /// Initialization of the embedding matrices.
+    Init,
+
+    /// Sampling by a trainer, such as subsampling and negative sampling.
+    Trainer,
+
+    /// Shuffling of the corpus by a training thread.
+    Corpus(usize),
+
+    /// Shuffling of the validation corpus.
+    Validation,
+}
+
+impl RngStream {
+    fn offset(self) -> u64 {
+        // Spread the streams, since adjacent seeds are common.
+        const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+        let stream = match self {
+            RngStream::Init => 0,
+            RngStream::Trainer => 1,
+            RngStream::Validation => 2,
+            RngStream::Corpus(thread) => 3 + thread as u64,
+        };
+        stream.wrapping_mul(GOLDEN_GAMMA)
+    }
 }
 
 /// Hyperparameters for Dependency Embeddings.
@@ -133,6 +1022,193 @@ pub struct DepembedsConfig {
     ///
     /// Only takes the attached word-form into account.
     pub untyped: bool,
+
+    /// Use lemmas rather than forms as words.
+    pub lemmas: bool,
+
+    /// Use the form for tokens that do not have a lemma.
+    ///
+    /// Tokens without a lemma are dropped when this option is not
+    /// enabled. Only used in conjunction with `lemmas`.
+    pub lemma_backoff: bool,
+
+    /// Part-of-speech tags that are attached to words.
+    pub pos_tags: PosTags,
+}
+
+impl DepembedsConfig {
+    /// Get the word of a token.
+    ///
+    /// This is the lemma if lemmas are used, the form otherwise. If the
+    /// token does not have a lemma, the form is used with lemma backoff
+    /// and `None` is returned without lemma backoff. The part-of-speech
+    /// tag is not attached.
+    pub fn token_word<'a>(&self, token: &'a Token) -> Option<&'a str> {
+        if !self.lemmas {
+            return Some(token.form());
+        }
+
+        match token.lemma() {
+            Some(lemma) => Some(lemma),
+            None if self.lemma_backoff => Some(token.form()),
+            None => None,
+        }
+    }
+
+    /// Get the part-of-speech tag that is attached to the word of a token.
+    ///
+    /// Returns `None` if no tags are attached or the token does not have
+    /// a tag.
+    pub fn pos_tag<'a>(&self, token: &'a Token) -> Option<&'a str> {
+        match self.pos_tags {
+            PosTags::None => None,
+            PosTags::Upos => token.upos(),
+            PosTags::Xpos => token.xpos(),
+        }
+    }
+}
+
+/// Part-of-speech tags that are attached to words.
+///
+/// With tags, a token is represented as *word_TAG*, such that e.g. the
+/// noun and verb readings of *can* get separate embeddings.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum PosTags {
+    /// Tags are not attached.
+    None,
+
+    /// Universal part-of-speech tags.
+    Upos,
+
+    /// Language-specific part-of-speech tags.
+    Xpos,
+}
+
+impl TryFrom<&str> for PosTags {
+    type Error = Error;
+
+    fn try_from(tags: &str) -> Result<PosTags> {
+        match tags {
+            "none" => Ok(PosTags::None),
+            "upos" => Ok(PosTags::Upos),
+            "xpos" => Ok(PosTags::Xpos),
+            _ => bail!("Unknown part-of-speech tags: {}", tags),
+        }
+    }
+}
+
+/// Hyperparameters for paragraph vectors.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename = "Doc2vec")]
+pub struct Doc2vecConfig {
+    /// The paragraph vector model.
+    pub model: Doc2vecModelType,
+
+    /// The number of preceding and succeeding tokens that will be
+    /// combined with the document to predict a focus token.
+    ///
+    /// Only used by the distributed memory model.
+    pub context_size: u32,
+}
+
+/// Hyperparameters for GloVe embeddings.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename = "Glove")]
+pub struct GloveConfig {
+    /// The number of preceding and succeeding tokens that are counted
+    /// as co-occurring with a focus token.
+    pub context_size: u32,
+
+    /// Co-occurrence count at which the weighting function saturates.
+    pub x_max: f32,
+
+    /// Exponent of the weighting function.
+    pub alpha: f32,
+}
+
+/// Hyperparameters for PMI embeddings.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename = "Pmi")]
+pub struct PmiConfig {
+    /// The number of preceding and succeeding tokens that are counted
+    /// as co-occurring with a focus token.
+    pub context_size: u32,
+
+    /// Exponent used to smooth the context distribution.
+    pub context_smoothing: f32,
+
+    /// Shift of the PMI values, log(*shift*) is subtracted from every PMI
+    /// value. This corresponds to the number of negative samples in
+    /// skip-gram with negative sampling (Levy and Goldberg, 2014).
+    pub shift: f32,
+
+    /// Exponent of the singular values that weight the singular vectors.
+    pub eigenvalue_weight: f32,
+
+    /// The number of power iterations of the randomized SVD.
+    pub power_iterations: u32,
+
+    /// The number of additional random projections of the randomized SVD.
+    pub oversampling: u32,
+}
+
+/// Hyperparameters for retrofitting.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct RetrofitConfig {
+    /// Weight of the original embedding of a word, relative to the
+    /// embeddings of its neighbors.
+    pub alpha: f32,
+
+    /// The number of retrofitting iterations.
+    pub iterations: u32,
+}
+
+/// Hyperparameters for sent2vec embeddings.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename = "Sent2vec")]
+pub struct Sent2vecConfig {
+    /// Maximum length of word n-grams that are used as context features.
+    ///
+    /// A value of 1 disables word n-gram features.
+    pub word_ngrams: u32,
+}
+
+/// Hyperparameters for supervised classifiers.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename = "Supervised")]
+pub struct SupervisedConfig {
+    /// Maximum length of word n-grams that are used as input features.
+    ///
+    /// A value of 1 disables word n-gram features.
+    pub word_ngrams: u32,
+}
+
+/// Hyperparameters for multi-sense embeddings.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename = "Multisense")]
+pub struct MultisenseConfig {
+    /// The number of preceding and succeeding tokens that will be consider
+    /// as context during training.
+    ///
+    /// For example, a context size of 5 will consider the 5 tokens preceding
+    /// and the 5 tokens succeeding the focus token.
+    pub context_size: u32,
+
+    /// The maximum number of senses per word.
+    pub senses: u32,
+
+    /// Similarity threshold for allocating a new sense.
+    ///
+    /// A new sense is allocated for an occurrence of a word when the cosine
+    /// similarity between its context and the contexts of all existing
+    /// senses is below this threshold.
+    pub sense_threshold: f32,
 }
 
 /// Hyperparameters for Subword vocabs.
@@ -159,10 +1235,54 @@ pub struct SubwordVocabConfig<V> {
     /// Maximum n-gram length for subword units (inclusive).
     pub max_n: u32,
 
+    /// Include the word itself in its representation.
+    ///
+    /// If `false`, a word is represented by its subword units only. Words
+    /// without subword units are still represented by their own
+    /// embedding.
+    pub include_word: bool,
+
+    /// N-gram lengths per script.
+    ///
+    /// These lengths override `min_n` and `max_n` for words of a
+    /// script, e.g. to use shorter n-grams for Chinese characters.
+    pub script_ngrams: ScriptNGrams,
+
+    /// Subword units of compounds are extracted from their constituents.
+    ///
+    /// This is set by `SubwordVocab::split_compounds` when it splits a
+    /// word, so that the model metadata records the splitting.
+    pub split_compounds: bool,
+
+    /// Word boundary markers of n-grams.
+    pub boundaries: WordBoundaries,
+
     /// Indexer specific parameters.
     pub indexer: V,
 }
 
+impl<V> SubwordVocabConfig<V> {
+    /// Get the minimum and maximum n-gram length for a word.
+    pub fn ngram_lengths(&self, word: &str) -> (usize, usize) {
+        let (min_n, max_n) = Script::of_word(word)
+            .and_then(|script| self.script_ngrams.get(script))
+            .unwrap_or((self.min_n, self.max_n));
+        (min_n as usize, max_n as usize)
+    }
+
+    /// Get the smallest minimum and largest maximum n-gram length.
+    ///
+    /// This is the n-gram range that covers the n-gram lengths of all
+    /// scripts.
+    pub fn ngram_bounds(&self) -> (u32, u32) {
+        self.script_ngrams
+            .iter()
+            .fold((self.min_n, self.max_n), |(min, max), (_, min_n, max_n)| {
+                (min.min(min_n), max.max(max_n))
+            })
+    }
+}
+
 /// Hyperparameters for bucket-vocabs.
 #[derive(Clone, Copy, Debug, Serialize)]
 #[serde(rename = "Buckets")]
@@ -188,6 +1308,27 @@ pub struct NGramConfig {
     pub cutoff: Cutoff,
 }
 
+/// Hyperparameters for byte pair encoding vocabs.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename = "Bpe")]
+#[serde(tag = "type")]
+pub struct BpeConfig {
+    /// The number of byte pair encoding merges.
+    ///
+    /// The number of merges that is learned from the vocabulary. Each
+    /// merge adds a subword unit.
+    pub merges: u32,
+}
+
+/// Hyperparameters for SentencePiece vocabs.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename = "SentencePiece")]
+#[serde(tag = "type")]
+pub struct SentencePieceConfig {
+    /// The number of pieces of the SentencePiece model.
+    pub pieces: u32,
+}
+
 /// Hyperparameters for simple vocabs.
 #[derive(Clone, Copy, Debug, Serialize)]
 #[serde(rename = "SimpleVocab")]
@@ -221,4 +1362,28 @@ pub struct SkipGramConfig {
     /// For example, a context size of 5 will consider the 5 tokens preceding
     /// and the 5 tokens succeeding the focus token.
     pub context_size: u32,
+
+    /// Weight context tokens by the inverse of their distance to the focus
+    /// token.
+    ///
+    /// When enabled, the full context size is used for every focus token
+    /// and a context token at distance *d* is used with probability *1/d*,
+    /// rather than sampling the context size uniformly.
+    pub harmonic_weighting: bool,
+
+    /// Cutoff of the context vocabulary.
+    ///
+    /// The context vocabulary consists of the words of the vocabulary
+    /// that are retained by this cutoff. Other words are not used as
+    /// contexts, which reduces the size of the output matrix. When absent,
+    /// all words of the vocabulary are used as contexts.
+    pub context_cutoff: Option<Cutoff>,
+
+    /// The maximum length of word n-grams that are added to the focus word.
+    ///
+    /// The word n-grams of length *[2, word_ngrams]* that end in the
+    /// focus word are added to its representation. Word n-grams are
+    /// hashed into the subword buckets. A value of *1* disables word
+    /// n-grams.
+    pub word_ngrams: u32,
 }
@@ -0,0 +1,148 @@
+use std::borrow::Borrow;
+
+use crate::idx::BagOfWordsIdx;
+use crate::{Lexicon, Vocab};
+
+/// Lexical constraint.
+///
+/// A lexical constraint is a pair of words whose input embeddings should
+/// be similar (attract) or dissimilar (repel). The words are stored as
+/// their input indices, including the indices of their subword units.
+#[derive(Clone)]
+pub struct LexicalConstraint {
+    first: BagOfWordsIdx,
+    second: BagOfWordsIdx,
+    attract: bool,
+}
+
+impl LexicalConstraint {
+    /// Get the input indices of the first word.
+    pub fn first(&self) -> &BagOfWordsIdx {
+        &self.first
+    }
+
+    /// Get the input indices of the second word.
+    pub fn second(&self) -> &BagOfWordsIdx {
+        &self.second
+    }
+
+    /// Returns `true` if the embeddings of the words should attract.
+    pub fn attract(&self) -> bool {
+        self.attract
+    }
+}
+
+/// Set of lexical constraints.
+///
+/// Lexical constraints add an auxiliary loss to training that pulls
+/// the embeddings of synonyms together and pushes the embeddings of
+/// antonyms apart (Mrkšić et al., 2017). The auxiliary loss of a
+/// constraint is the logistic loss of the inner product of the input
+/// embeddings of the two words.
+#[derive(Clone)]
+pub struct LexicalConstraints {
+    constraints: Vec<LexicalConstraint>,
+    weight: f32,
+}
+
+impl LexicalConstraints {
+    /// Construct an empty set of constraints.
+    ///
+    /// The learning rate of constraint updates is scaled by `weight`.
+    pub fn new(weight: f32) -> Self {
+        LexicalConstraints {
+            constraints: Vec::new(),
+            weight,
+        }
+    }
+
+    /// Add the word pairs of a lexicon as constraints.
+    ///
+    /// Every word of the lexicon forms a pair with each of its neighbors.
+    /// The pairs attract if `attract` is `true`, otherwise they repel.
+    /// Pairs with a word that is not in the vocabulary are ignored.
+    ///
+    /// Returns the number of constraints that were added.
+    pub fn add_lexicon<V>(&mut self, lexicon: &Lexicon, vocab: &V, attract: bool) -> usize
+    where
+        V: Vocab,
+        V::VocabType: Borrow<str>,
+        for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+    {
+        let n_constraints = self.constraints.len();
+
+        for (word, neighbors) in lexicon.iter() {
+            let first = match vocab.idx(word) {
+                Some(idx) => BagOfWordsIdx::new((&idx).into_iter().collect::<Vec<_>>()),
+                None => continue,
+            };
+
+            for neighbor in neighbors {
+                if let Some(idx) = vocab.idx(neighbor.as_str()) {
+                    self.constraints.push(LexicalConstraint {
+                        first: first.clone(),
+                        second: BagOfWordsIdx::new((&idx).into_iter().collect::<Vec<_>>()),
+                        attract,
+                    });
+                }
+            }
+        }
+
+        self.constraints.len() - n_constraints
+    }
+
+    /// Get the constraint at `idx`.
+    pub fn get(&self, idx: usize) -> Option<&LexicalConstraint> {
+        self.constraints.get(idx)
+    }
+
+    /// Returns `true` if there are no constraints.
+    pub fn is_empty(&self) -> bool {
+        self.constraints.is_empty()
+    }
+
+    /// Get the number of constraints.
+    pub fn len(&self) -> usize {
+        self.constraints.len()
+    }
+
+    /// Get the weight of the constraints.
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LexicalConstraints;
+    use crate::idx::WordIdx;
+    use crate::{Cutoff, Lexicon, SimpleVocab, SimpleVocabConfig, Vocab, VocabBuilder};
+
+    #[test]
+    fn add_lexicon() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            discard_threshold: 1e-4,
+            cutoff: Cutoff::MinCount(1),
+        });
+        for word in &["happy", "glad", "sad"] {
+            builder.count(word.to_string());
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let lexicon = Lexicon::read("happy glad cheerful\nsad happy\n".as_bytes()).unwrap();
+        let mut constraints = LexicalConstraints::new(1.0);
+
+        // "cheerful" is not in the vocabulary.
+        assert_eq!(constraints.add_lexicon(&lexicon, &vocab, false), 2);
+        assert_eq!(constraints.len(), 2);
+
+        let happy = vocab.idx("happy").unwrap().word_idx();
+        let glad = vocab.idx("glad").unwrap().word_idx();
+        let constraint = (0..constraints.len())
+            .map(|idx| constraints.get(idx).unwrap())
+            .find(|constraint| constraint.first().into_iter().eq(Some(happy)))
+            .unwrap();
+        assert!(constraint.second().into_iter().eq(Some(glad)));
+        assert!(!constraint.attract());
+    }
+}
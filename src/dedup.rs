@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+
+use anyhow::{anyhow, Error, Result};
+use fnv::FnvHasher;
+use serde::Serialize;
+
+/// The number of hash functions of the Bloom filter.
+const BLOOM_N_HASHES: u64 = 7;
+
+/// Approximate memory use of an entry of the exact filter.
+///
+/// An entry consists of a 64-bit hash, a control byte, and unused
+/// capacity of the hash table.
+const EXACT_ENTRY_SIZE: usize = 16;
+
+/// Sentence deduplication methods.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dedup {
+    /// Remember the hashes of all sentences.
+    Exact,
+
+    /// Remember sentences in a Bloom filter.
+    ///
+    /// A Bloom filter uses a fixed amount of memory, but may
+    /// occasionally consider a sentence to be a duplicate when it is
+    /// not.
+    Bloom,
+}
+
+impl TryFrom<&str> for Dedup {
+    type Error = Error;
+
+    fn try_from(dedup: &str) -> Result<Self> {
+        match dedup {
+            "bloom" => Ok(Dedup::Bloom),
+            "exact" => Ok(Dedup::Exact),
+            unknown => Err(anyhow!("Unknown deduplication method: {}", unknown)),
+        }
+    }
+}
+
+/// Filter of duplicate sentences.
+///
+/// The filter uses at most (roughly) the amount of memory that it was
+/// constructed with. When the exact filter is full, sentences that were
+/// not seen before are not remembered anymore.
+pub enum DuplicateFilter {
+    Exact { seen: HashSet<u64>, max_len: usize },
+    Bloom { bits: Vec<u64> },
+}
+
+impl DuplicateFilter {
+    /// Construct a filter that uses at most `max_memory` bytes.
+    pub fn new(dedup: Dedup, max_memory: usize) -> Self {
+        match dedup {
+            Dedup::Exact => DuplicateFilter::Exact {
+                seen: HashSet::new(),
+                max_len: max_memory / EXACT_ENTRY_SIZE,
+            },
+            Dedup::Bloom => DuplicateFilter::Bloom {
+                bits: vec![0; (max_memory / 8).max(1)],
+            },
+        }
+    }
+
+    /// Check whether a sentence is a duplicate.
+    ///
+    /// Returns `true` if the sentence was seen before. Otherwise, the
+    /// sentence is remembered and `false` is returned.
+    pub fn is_duplicate(&mut self, sentence: &[String]) -> bool {
+        let hash = sentence_hash(sentence, 0);
+
+        match self {
+            DuplicateFilter::Exact { seen, max_len } => {
+                if seen.contains(&hash) {
+                    return true;
+                }
+
+                if seen.len() < *max_len {
+                    seen.insert(hash);
+                }
+
+                false
+            }
+            DuplicateFilter::Bloom { bits } => {
+                // Double hashing, the second hash must be odd to visit
+                // different bits.
+                let hash2 = sentence_hash(sentence, 1) | 1;
+                let n_bits = bits.len() as u64 * 64;
+
+                let mut duplicate = true;
+                for i in 0..BLOOM_N_HASHES {
+                    let bit = hash.wrapping_add(i.wrapping_mul(hash2)) % n_bits;
+                    let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+                    if bits[word] & mask == 0 {
+                        duplicate = false;
+                        bits[word] |= mask;
+                    }
+                }
+
+                duplicate
+            }
+        }
+    }
+}
+
+fn sentence_hash(sentence: &[String], seed: u64) -> u64 {
+    let mut hasher = FnvHasher::default();
+    seed.hash(&mut hasher);
+    sentence.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dedup, DuplicateFilter};
+
+    fn sentence(sentence: &str) -> Vec<String> {
+        sentence.split_whitespace().map(ToOwned::to_owned).collect()
+    }
+
+    #[test]
+    fn duplicate_filter_test() {
+        for &dedup in &[Dedup::Exact, Dedup::Bloom] {
+            let mut filter = DuplicateFilter::new(dedup, 1 << 16);
+            assert!(!filter.is_duplicate(&sentence("Click here to subscribe")));
+            assert!(!filter.is_duplicate(&sentence("This is a sentence .")));
+            assert!(filter.is_duplicate(&sentence("Click here to subscribe")));
+            assert!(!filter.is_duplicate(&sentence("Click here to unsubscribe")));
+            assert!(filter.is_duplicate(&sentence("This is a sentence .")));
+        }
+    }
+
+    #[test]
+    fn exact_filter_full_test() {
+        let mut filter = DuplicateFilter::new(Dedup::Exact, 16);
+        assert!(!filter.is_duplicate(&sentence("first")));
+        assert!(!filter.is_duplicate(&sentence("second")));
+        assert!(filter.is_duplicate(&sentence("first")));
+        assert!(!filter.is_duplicate(&sentence("second")));
+    }
+}
@@ -2,14 +2,14 @@ use std::borrow::Borrow;
 use std::sync::Arc;
 
 use anyhow::{bail, Result};
-use rand::{Rng, SeedableRng};
+use rand::{Rng, RngCore, SeedableRng};
 use serde::Serialize;
 use udgraph::graph::Sentence;
 
 use crate::idx::WordIdx;
-use crate::sampling::ZipfRangeGenerator;
+use crate::sampling::{NegativeSamplingRangeGenerator, RangeGenerator};
 use crate::train_model::{NegativeSamples, TrainIterFrom};
-use crate::util::ReseedOnCloneRng;
+use crate::util::{Reseed, ReseedOnCloneRng};
 use crate::{
     CommonConfig, DepembedsConfig, Dependency, DependencyIterator, SimpleVocab, SimpleVocabConfig,
     Trainer, Vocab,
@@ -26,7 +26,7 @@ pub struct DepembedsTrainer<R, V> {
     common_config: CommonConfig,
     input_vocab: Arc<V>,
     output_vocab: Arc<SimpleVocab<Dependency>>,
-    range_gen: ZipfRangeGenerator<R>,
+    range_gen: NegativeSamplingRangeGenerator<R>,
     rng: R,
 }
 
@@ -48,11 +48,11 @@ where
         dep_config: DepembedsConfig,
         rng: R,
     ) -> Self {
-        let rng = ReseedOnCloneRng(rng);
-        let range_gen = ZipfRangeGenerator::new_with_exponent(
+        let rng = ReseedOnCloneRng::new(rng);
+        let range_gen = NegativeSamplingRangeGenerator::new(
             rng.clone(),
-            output_vocab.len(),
-            common_config.zipf_exponent,
+            output_vocab.types().iter().map(|word| word.count()),
+            &common_config,
         );
         DepembedsTrainer {
             common_config,
@@ -77,6 +77,20 @@ where
             }
         }
     }
+
+    fn noise_prob(&self, output: usize) -> f32 {
+        self.range_gen.prob(output) as f32
+    }
+}
+
+impl<R, V> Reseed for DepembedsTrainer<R, V>
+where
+    R: Reseed + RngCore,
+{
+    fn reseed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+        self.range_gen.reseed(self.rng.next_u64());
+    }
 }
 
 impl<'a, R, V> TrainIterFrom<'a, Sentence> for DepembedsTrainer<R, V>
@@ -94,10 +108,11 @@ where
         let invalid_idx = self.input_vocab.len() as u64;
         let mut tokens = vec![WordIdx::from_word_idx(invalid_idx); sentence.len() - 1];
         for (idx, token) in sentence.iter().filter_map(|node| node.token()).enumerate() {
-            if let Some(vocab_idx) = self.input_vocab.idx(token.form()) {
+            if let Some(mut vocab_idx) = self.input_vocab.idx(token.form()) {
                 if self.rng.gen_range(0f32..1f32)
                     < self.input_vocab.discard(vocab_idx.word_idx() as usize)
                 {
+                    vocab_idx.drop_subwords(&mut self.rng, self.common_config.subword_dropout);
                     tokens[idx] = vocab_idx
                 }
             }
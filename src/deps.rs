@@ -1,6 +1,6 @@
 use udgraph::graph::{DepGraph, DepTriple};
 
-use crate::DepembedsConfig;
+use crate::{DepembedsConfig, HeapSize};
 
 /// Trait to provide iterators over the path in a tree from `start` to the root.
 pub trait PathIter {
@@ -53,6 +53,14 @@ pub enum Dependency {
 
 // Constructors for convenience
 impl Dependency {
+    /// Get the form that is attached through the dependency.
+    pub fn form(&self) -> &str {
+        match self {
+            Dependency::Typed { form, .. } => form,
+            Dependency::Untyped(form) => form,
+        }
+    }
+
     fn regular<S, T>(depth: usize, dep_label: S, form: T) -> Self
     where
         S: Into<String>,
@@ -79,6 +87,17 @@ impl Dependency {
     }
 }
 
+impl HeapSize for Dependency {
+    fn heap_size(&self) -> usize {
+        match self {
+            Dependency::Typed {
+                dep_label, form, ..
+            } => dep_label.heap_size() + form.heap_size(),
+            Dependency::Untyped(form) => form.heap_size(),
+        }
+    }
+}
+
 /// Enum to denote the direction of a dependency relation.
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub enum DependencyDirection {
@@ -317,6 +336,7 @@ mod tests {
     use std::io::Cursor;
 
     use crate::deps::{DepIter, Dependency, Dependency::Untyped, DependencyIterator, PathIter};
+    use crate::{DepembedsConfig, PosTags};
 
     use conllu::io::{ReadSentence, Reader};
     use udgraph::graph::Node;
@@ -511,4 +531,46 @@ mod tests {
             assert_eq!(dep, target_dep);
         }
     }
+
+    #[test]
+    fn token_words_with_lemmas_and_tags() {
+        let c = Cursor::new(
+            "1\tcans\tcan\tNOUN\tNNS\t_\t0\tROOT\t_\t_\n\
+             2\tthe\t_\tDET\t_\t_\t1\tDET\t_\t_\n"
+                .as_bytes()
+                .to_vec(),
+        );
+        let mut reader = Reader::new(c);
+        let sentence = reader.read_sentence().unwrap().unwrap();
+        let tokens = sentence.iter().filter_map(Node::token).collect::<Vec<_>>();
+
+        let mut config = DepembedsConfig {
+            depth: 1,
+            use_root: false,
+            normalize: false,
+            projectivize: false,
+            untyped: false,
+            lemmas: false,
+            lemma_backoff: false,
+            pos_tags: PosTags::None,
+        };
+        assert_eq!(config.token_word(tokens[0]), Some("cans"));
+        assert_eq!(config.pos_tag(tokens[0]), None);
+
+        config.lemmas = true;
+        config.pos_tags = PosTags::Upos;
+        assert_eq!(config.token_word(tokens[0]), Some("can"));
+        assert_eq!(config.pos_tag(tokens[0]), Some("NOUN"));
+
+        // Tokens without a lemma are dropped without backoff.
+        assert_eq!(config.token_word(tokens[1]), None);
+
+        // The form is used with backoff.
+        config.lemma_backoff = true;
+        assert_eq!(config.token_word(tokens[1]), Some("the"));
+
+        config.pos_tags = PosTags::Xpos;
+        assert_eq!(config.pos_tag(tokens[0]), Some("NNS"));
+        assert_eq!(config.pos_tag(tokens[1]), None);
+    }
 }
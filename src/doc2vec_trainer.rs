@@ -0,0 +1,410 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::io::{Seek, Write};
+use std::iter::FusedIterator;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use finalfusion::io::WriteEmbeddings;
+use finalfusion::norms::NdNorms;
+use finalfusion::prelude::Embeddings;
+use finalfusion::storage::NdArray;
+use finalfusion::vocab::SimpleVocab as FiFuSimpleVocab;
+use ndarray::{Array1, Array2};
+use rand::{Rng, RngCore, SeedableRng};
+use serde::Serialize;
+
+use crate::idx::{BagOfWordsIdx, WordIdx};
+use crate::sampling::{sample_context_window, NegativeSamplingRangeGenerator, RangeGenerator};
+use crate::train_model::{NegativeSamples, TrainIterFrom, TrainModel, Trainer};
+use crate::util::{Reseed, ReseedOnCloneRng};
+use crate::vec_simd::l2_normalize;
+use crate::{CommonConfig, Doc2vecConfig, Doc2vecModelType, Vocab};
+
+/// A document with its tag.
+///
+/// The tag is the index of the document's embedding in the document
+/// matrix.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TaggedDocument<S> {
+    tag: usize,
+    tokens: Vec<S>,
+}
+
+impl<S> TaggedDocument<S> {
+    /// Construct a new tagged document.
+    pub fn new(tag: usize, tokens: Vec<S>) -> Self {
+        TaggedDocument { tag, tokens }
+    }
+
+    /// Get the document tag.
+    pub fn tag(&self) -> usize {
+        self.tag
+    }
+
+    /// Get the tokens of the document.
+    pub fn tokens(&self) -> &[S] {
+        &self.tokens
+    }
+}
+
+/// Paragraph vector Trainer
+///
+/// The `Doc2vecTrainer` holds the information and logic necessary to
+/// transform a tagged document into an iterator of input bags and focus
+/// word tuples (Le and Mikolov, 2014). Every document has its own input
+/// embedding, which is stored after the word and subword embeddings in
+/// the input matrix. The struct is cheap to clone because the vocabulary
+/// is shared between clones.
+#[derive(Clone)]
+pub struct Doc2vecTrainer<R, V> {
+    vocab: Arc<V>,
+    rng: R,
+    range_gen: NegativeSamplingRangeGenerator<R>,
+    common_config: CommonConfig,
+    doc2vec_config: Doc2vecConfig,
+    n_docs: usize,
+}
+
+impl<R, V> Doc2vecTrainer<ReseedOnCloneRng<R>, V>
+where
+    R: Rng + Clone + SeedableRng,
+    V: Vocab,
+{
+    /// Constructs a new `Doc2vecTrainer` for `n_docs` documents.
+    pub fn new(
+        vocab: V,
+        rng: R,
+        common_config: CommonConfig,
+        doc2vec_config: Doc2vecConfig,
+        n_docs: usize,
+    ) -> Self {
+        let vocab = Arc::new(vocab);
+        let rng = ReseedOnCloneRng::new(rng);
+        let range_gen = NegativeSamplingRangeGenerator::new(
+            rng.clone(),
+            vocab.types().iter().map(|word| word.count()),
+            &common_config,
+        );
+        Doc2vecTrainer {
+            vocab,
+            rng,
+            range_gen,
+            common_config,
+            doc2vec_config,
+            n_docs,
+        }
+    }
+}
+
+impl<R, V> Doc2vecTrainer<R, V>
+where
+    V: Vocab,
+{
+    /// Get the number of documents.
+    pub fn n_docs(&self) -> usize {
+        self.n_docs
+    }
+
+    /// Get the input index of a document.
+    fn document_idx(&self, tag: usize) -> u64 {
+        assert!(
+            tag < self.n_docs,
+            "Document {} out of index [0, {})",
+            tag,
+            self.n_docs
+        );
+
+        (self.vocab.n_input_types() + tag) as u64
+    }
+}
+
+impl<'a, S, R, V, I> TrainIterFrom<'a, TaggedDocument<S>> for Doc2vecTrainer<R, V>
+where
+    S: Hash + Eq,
+    R: Rng + Clone,
+    V: Vocab<IdxType = I>,
+    V::VocabType: Borrow<S>,
+    I: WordIdx,
+    for<'b> &'b I: IntoIterator<Item = u64>,
+{
+    type Iter = Doc2vecIter<R, I>;
+    type Focus = BagOfWordsIdx;
+    type Contexts = Vec<usize>;
+
+    fn train_iter_from(&mut self, document: &TaggedDocument<S>) -> Self::Iter {
+        let mut ids = Vec::new();
+        for t in document.tokens() {
+            if let Some(mut idx) = self.vocab.idx(t) {
+                if self.rng.gen_range(0f32..1f32) < self.vocab.discard(idx.word_idx() as usize) {
+                    idx.drop_subwords(&mut self.rng, self.common_config.subword_dropout);
+                    ids.push(idx);
+                }
+            }
+        }
+
+        Doc2vecIter::new(
+            self.rng.clone(),
+            ids,
+            self.document_idx(document.tag()),
+            self.doc2vec_config,
+        )
+    }
+}
+
+impl<R, V> NegativeSamples for Doc2vecTrainer<R, V>
+where
+    R: Rng,
+{
+    fn negative_sample(&mut self, output: usize) -> usize {
+        loop {
+            let negative = self.range_gen.next().unwrap();
+            if negative != output {
+                return negative;
+            }
+        }
+    }
+
+    fn noise_prob(&self, output: usize) -> f32 {
+        self.range_gen.prob(output) as f32
+    }
+}
+
+impl<R, V> Reseed for Doc2vecTrainer<R, V>
+where
+    R: Reseed + RngCore,
+{
+    fn reseed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+        self.range_gen.reseed(self.rng.next_u64());
+    }
+}
+
+impl<R, V> Trainer for Doc2vecTrainer<R, V>
+where
+    R: Rng + Clone,
+    V: Vocab,
+    V::Config: Serialize,
+{
+    type InputVocab = V;
+    type Metadata = Doc2vecMetadata<V::Config>;
+
+    fn input_vocab(&self) -> &V {
+        &self.vocab
+    }
+
+    fn try_into_input_vocab(self) -> Result<V> {
+        match Arc::try_unwrap(self.vocab) {
+            Ok(vocab) => Ok(vocab),
+            Err(_) => bail!("Cannot unwrap input vocab."),
+        }
+    }
+
+    fn n_input_types(&self) -> usize {
+        self.input_vocab().n_input_types() + self.n_docs
+    }
+
+    fn n_output_types(&self) -> usize {
+        self.vocab.len()
+    }
+
+    fn config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+
+    fn to_metadata(&self) -> Doc2vecMetadata<V::Config> {
+        Doc2vecMetadata {
+            common_config: self.common_config,
+            doc2vec_config: self.doc2vec_config,
+            vocab_config: self.vocab.config(),
+        }
+    }
+}
+
+impl<R, V> TrainModel<Doc2vecTrainer<R, V>>
+where
+    R: Rng + Clone,
+    V: Vocab,
+    V::Config: Serialize,
+{
+    /// Get the document embeddings.
+    ///
+    /// Row *i* of the returned matrix is the embedding of the document
+    /// with tag *i*.
+    pub fn document_embeddings(&mut self) -> Array2<f32> {
+        let dims = self.config().dims as usize;
+        let offset = self.input_vocab().n_input_types();
+        let n_docs = self.trainer().n_docs();
+
+        let mut embeds = Array2::zeros((n_docs, dims));
+        for (tag, mut embed) in embeds.outer_iter_mut().enumerate() {
+            embed.assign(&self.input_embedding(offset + tag));
+        }
+
+        embeds
+    }
+
+    /// Write the document embeddings in finalfusion format.
+    ///
+    /// The vocabulary of the written embeddings consists of the document
+    /// tags, so that the embedding of document *i* can be looked up as
+    /// `"i"`.
+    pub fn write_document_embeddings<W>(&mut self, write: &mut W) -> Result<()>
+    where
+        W: Seek + Write,
+    {
+        let mut embeds = self.document_embeddings();
+
+        let mut norms = Array1::zeros((embeds.nrows(),));
+        for (mut embed, norm) in embeds.outer_iter_mut().zip(norms.iter_mut()) {
+            *norm = l2_normalize(embed.view_mut());
+        }
+
+        let tags = (0..embeds.nrows())
+            .map(|tag| tag.to_string())
+            .collect::<Vec<_>>();
+        Embeddings::new(
+            None,
+            FiFuSimpleVocab::new(tags),
+            NdArray::new(embeds),
+            NdNorms::new(norms),
+        )
+        .write_embeddings(write)?;
+
+        Ok(())
+    }
+}
+
+/// Metadata for paragraph vectors.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Doc2vecMetadata<V> {
+    common_config: CommonConfig,
+    #[serde(rename = "model_config")]
+    doc2vec_config: Doc2vecConfig,
+    vocab_config: V,
+}
+
+/// Iterator over the input bag and focus identifier of each token in a document.
+///
+/// In the distributed memory model, the input of a training instance is the
+/// document embedding and the indices of the context words (including their
+/// subword units). In the distributed bag-of-words model, the input is only
+/// the document embedding. In both models, the output is the focus word.
+pub struct Doc2vecIter<R, I> {
+    ids: Vec<I>,
+    rng: R,
+    document: u64,
+    i: usize,
+    model: Doc2vecModelType,
+    ctx_size: usize,
+}
+
+impl<R, I> Doc2vecIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+{
+    /// Constructs a new `Doc2vecIter`.
+    ///
+    /// `document` is the input index of the document embedding. The `rng`
+    /// is used to determine the window size for each focus token.
+    pub fn new(rng: R, ids: Vec<I>, document: u64, doc2vec_config: Doc2vecConfig) -> Self {
+        Doc2vecIter {
+            ids,
+            rng,
+            document,
+            i: 0,
+            model: doc2vec_config.model,
+            ctx_size: doc2vec_config.context_size as usize,
+        }
+    }
+}
+
+impl<R, I> Iterator for Doc2vecIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+    for<'a> &'a I: IntoIterator<Item = u64>,
+{
+    type Item = (BagOfWordsIdx, Vec<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.i >= self.ids.len() {
+            return None;
+        }
+
+        let mut bag = BagOfWordsIdx::new(vec![self.document]);
+        if let Doc2vecModelType::DistributedMemory = self.model {
+            let window =
+                sample_context_window(&mut self.rng, self.i, self.ids.len(), self.ctx_size);
+
+            for idx in window.filter(|&idx| idx != self.i) {
+                bag.extend(&self.ids[idx]);
+            }
+        }
+
+        let focus = self.ids[self.i].word_idx() as usize;
+        self.i += 1;
+
+        Some((bag, vec![focus]))
+    }
+}
+
+impl<R, I> FusedIterator for Doc2vecIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+    for<'a> &'a I: IntoIterator<Item = u64>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::Doc2vecIter;
+    use crate::idx::{SingleIdx, WordIdx};
+    use crate::{Doc2vecConfig, Doc2vecModelType};
+
+    #[test]
+    fn dbow_iter_test() {
+        let ids = vec![SingleIdx::from_word_idx(0), SingleIdx::from_word_idx(1)];
+        let iter = Doc2vecIter::new(
+            XorShiftRng::seed_from_u64(42),
+            ids,
+            10,
+            Doc2vecConfig {
+                model: Doc2vecModelType::DistributedBagOfWords,
+                context_size: 5,
+            },
+        );
+
+        let instances = iter
+            .map(|(bag, focus)| (bag.into_iter().collect::<Vec<_>>(), focus))
+            .collect::<Vec<_>>();
+        assert_eq!(instances, vec![(vec![10], vec![0]), (vec![10], vec![1])]);
+    }
+
+    #[test]
+    fn dm_iter_test() {
+        let ids = vec![SingleIdx::from_word_idx(0), SingleIdx::from_word_idx(1)];
+        let iter = Doc2vecIter::new(
+            XorShiftRng::seed_from_u64(42),
+            ids,
+            10,
+            Doc2vecConfig {
+                model: Doc2vecModelType::DistributedMemory,
+                context_size: 1,
+            },
+        );
+
+        let instances = iter
+            .map(|(bag, focus)| (bag.into_iter().collect::<Vec<_>>(), focus))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            instances,
+            vec![(vec![10, 1], vec![0]), (vec![10, 0], vec![1])]
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use anyhow::{ensure, Context, Result};
+use ndarray::{Array1, Array2, Axis};
+
+use crate::train_model::{TrainModel, Trainer};
+use crate::vec_simd::{dot, l2_normalize};
+use crate::vocab::Vocab;
+
+/// Number of most frequent words that are answer candidates of analogies.
+///
+/// This follows the restriction of word2vec's `compute-accuracy`.
+const ANALOGY_VOCAB_SIZE: usize = 30_000;
+
+/// Word similarity dataset.
+///
+/// A word similarity dataset consists of word pairs with human similarity
+/// judgements, such as SimLex-999 or WordSim-353. Embeddings are
+/// evaluated by the correlation between the judgements and the cosine
+/// similarities of the word pairs.
+#[derive(Clone, Debug)]
+pub struct SimilarityDataset {
+    name: String,
+    pairs: Vec<(String, String, f32)>,
+}
+
+impl SimilarityDataset {
+    /// Read a word similarity dataset.
+    ///
+    /// Every line contains two words and their similarity, separated by
+    /// whitespace. Empty lines and lines starting with `#` are ignored.
+    pub fn read<R>(name: impl Into<String>, read: R) -> Result<Self>
+    where
+        R: BufRead,
+    {
+        let mut pairs = Vec::new();
+        for (idx, line) in read.lines().enumerate() {
+            let line = line.context("Cannot read similarity dataset")?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            ensure!(
+                parts.len() == 3,
+                "Similarity dataset line {} does not have three columns",
+                idx + 1
+            );
+            let similarity = parts[2].parse().with_context(|| {
+                format!("Cannot parse similarity on line {}: {}", idx + 1, parts[2])
+            })?;
+            pairs.push((parts[0].to_string(), parts[1].to_string(), similarity));
+        }
+
+        Ok(SimilarityDataset {
+            name: name.into(),
+            pairs,
+        })
+    }
+
+    /// Get the name of the dataset.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Evaluate the embeddings of a model.
+    ///
+    /// Returns Spearman's rank correlation between the similarities of
+    /// the dataset and the cosine similarities of the word embeddings.
+    /// Pairs with words without an embedding are skipped. Returns `None`
+    /// when fewer than two pairs can be evaluated.
+    pub fn evaluate<T, V>(&self, model: &TrainModel<T>) -> Option<f32>
+    where
+        T: Trainer<InputVocab = V>,
+        V: Vocab<VocabType = String>,
+        for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+    {
+        let (gold, predicted): (Vec<_>, Vec<_>) = self
+            .pairs
+            .iter()
+            .filter_map(|(word1, word2, similarity)| {
+                let mut embed1 = model.word_embedding(word1)?;
+                let mut embed2 = model.word_embedding(word2)?;
+                l2_normalize(embed1.view_mut());
+                l2_normalize(embed2.view_mut());
+                Some((*similarity, dot(embed1.view(), embed2.view())))
+            })
+            .unzip();
+
+        if gold.len() < 2 {
+            return None;
+        }
+
+        Some(spearman(&gold, &predicted))
+    }
+}
+
+/// Word analogy dataset.
+///
+/// A word analogy dataset consists of questions *a : b = c : d*, such as
+/// the word2vec analogy dataset. Embeddings are evaluated by the
+/// accuracy of predicting *d* as the nearest neighbor of *b - a + c*.
+#[derive(Clone, Debug)]
+pub struct AnalogyDataset {
+    name: String,
+    questions: Vec<[String; 4]>,
+}
+
+impl AnalogyDataset {
+    /// Read a word analogy dataset.
+    ///
+    /// Every line contains the four words of a question, separated by
+    /// whitespace. Empty lines and lines starting with `:` (section
+    /// headers) or `#` are ignored.
+    pub fn read<R>(name: impl Into<String>, read: R) -> Result<Self>
+    where
+        R: BufRead,
+    {
+        let mut questions = Vec::new();
+        for (idx, line) in read.lines().enumerate() {
+            let line = line.context("Cannot read analogy dataset")?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(':') || line.starts_with('#') {
+                continue;
+            }
+
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            ensure!(
+                parts.len() == 4,
+                "Analogy dataset line {} does not have four columns",
+                idx + 1
+            );
+            questions.push([
+                parts[0].to_string(),
+                parts[1].to_string(),
+                parts[2].to_string(),
+                parts[3].to_string(),
+            ]);
+        }
+
+        Ok(AnalogyDataset {
+            name: name.into(),
+            questions,
+        })
+    }
+
+    /// Get the name of the dataset.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Evaluate the embeddings of a model.
+    ///
+    /// Returns the accuracy of predicting the fourth word of a question
+    /// from the other three words. The answer candidates are the 30,000
+    /// most frequent words, excluding the question words. Questions with words that are not candidates are skipped.
+    /// Returns `None` when no question can be evaluated.
+    pub fn evaluate<T, V>(&self, model: &TrainModel<T>) -> Option<f32>
+    where
+        T: Trainer<InputVocab = V>,
+        V: Vocab<VocabType = String>,
+        for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+    {
+        let candidates = model
+            .input_vocab()
+            .types()
+            .iter()
+            .take(ANALOGY_VOCAB_SIZE)
+            .map(|t| t.label().as_str())
+            .collect::<Vec<_>>();
+        let candidate_indices = candidates
+            .iter()
+            .enumerate()
+            .map(|(idx, &word)| (word, idx))
+            .collect::<HashMap<_, _>>();
+
+        let questions = self
+            .questions
+            .iter()
+            .filter_map(|question| {
+                let mut indices = [0; 4];
+                for (idx, word) in indices.iter_mut().zip(question) {
+                    *idx = *candidate_indices.get(word.as_str())?;
+                }
+                Some(indices)
+            })
+            .collect::<Vec<_>>();
+        if questions.is_empty() {
+            return None;
+        }
+
+        let mut embeds = Array2::zeros((candidates.len(), model.config().dims as usize));
+        for (mut embed, word) in embeds.outer_iter_mut().zip(&candidates) {
+            if let Some(word_embed) = model.word_embedding(word) {
+                embed.assign(&word_embed);
+                l2_normalize(embed);
+            }
+        }
+
+        let n_correct = questions
+            .iter()
+            .filter(|&&[a, b, c, d]| {
+                let mut query: Array1<f32> = &embeds.index_axis(Axis(0), b)
+                    - &embeds.index_axis(Axis(0), a)
+                    + embeds.index_axis(Axis(0), c);
+                l2_normalize(query.view_mut());
+
+                let prediction = embeds
+                    .dot(&query)
+                    .iter()
+                    .enumerate()
+                    .filter(|&(idx, _)| idx != a && idx != b && idx != c)
+                    .max_by(|(_, s1), (_, s2)| s1.partial_cmp(s2).unwrap_or(Ordering::Equal))
+                    .map(|(idx, _)| idx);
+
+                prediction == Some(d)
+            })
+            .count();
+
+        Some(n_correct as f32 / questions.len() as f32)
+    }
+}
+
+/// Compute Spearman's rank correlation coefficient.
+fn spearman(u: &[f32], v: &[f32]) -> f32 {
+    pearson(&ranks(u), &ranks(v))
+}
+
+/// Compute Pearson's correlation coefficient.
+fn pearson(u: &[f32], v: &[f32]) -> f32 {
+    let n = u.len() as f32;
+    let u_mean = u.iter().sum::<f32>() / n;
+    let v_mean = v.iter().sum::<f32>() / n;
+
+    let mut cov = 0.;
+    let mut u_var = 0.;
+    let mut v_var = 0.;
+    for (&x, &y) in u.iter().zip(v) {
+        cov += (x - u_mean) * (y - v_mean);
+        u_var += (x - u_mean) * (x - u_mean);
+        v_var += (y - v_mean) * (y - v_mean);
+    }
+
+    if u_var == 0. || v_var == 0. {
+        return 0.;
+    }
+
+    cov / (u_var.sqrt() * v_var.sqrt())
+}
+
+/// Get the ranks of values, ties get the average of their ranks.
+fn ranks(values: &[f32]) -> Vec<f32> {
+    let mut order = (0..values.len()).collect::<Vec<_>>();
+    order.sort_by(|&i, &j| values[i].partial_cmp(&values[j]).unwrap_or(Ordering::Equal));
+
+    let mut ranks = vec![0.; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+
+        // Ranks are one-based, tied values get the mean rank.
+        let rank = (start + end + 1) as f32 / 2.;
+        for &idx in &order[start..end] {
+            ranks[idx] = rank;
+        }
+
+        start = end;
+    }
+
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{ranks, spearman, AnalogyDataset, SimilarityDataset};
+    use crate::util::{all_close, close};
+
+    #[test]
+    fn ranks_with_ties() {
+        assert!(all_close(
+            &ranks(&[0.5, 0.1, 0.5, 2.]),
+            &[2.5, 1., 2.5, 4.],
+            1e-6
+        ));
+    }
+
+    #[test]
+    fn spearman_test() {
+        assert!(close(spearman(&[1., 2., 3.], &[10., 20., 30.]), 1., 1e-6));
+        assert!(close(spearman(&[1., 2., 3.], &[3., 2., 1.]), -1., 1e-6));
+        assert!(close(
+            spearman(&[1., 2., 3., 4.], &[1., 3., 2., 4.]),
+            0.8,
+            1e-6
+        ));
+    }
+
+    #[test]
+    fn read_datasets() {
+        let similarity = SimilarityDataset::read(
+            "simlex",
+            Cursor::new("# comment\nold new 1.58\n\nbad awful\t8.42\n"),
+        )
+        .unwrap();
+        assert_eq!(similarity.name(), "simlex");
+        assert_eq!(similarity.pairs.len(), 2);
+        assert_eq!(similarity.pairs[1].1, "awful");
+
+        let analogy = AnalogyDataset::read(
+            "analogy",
+            Cursor::new(": capital-common-countries\nAthens Greece Baghdad Iraq\n"),
+        )
+        .unwrap();
+        assert_eq!(analogy.questions.len(), 1);
+        assert_eq!(analogy.questions[0][3], "Iraq");
+
+        assert!(SimilarityDataset::read("bad", Cursor::new("old new\n")).is_err());
+    }
+}
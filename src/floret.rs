@@ -0,0 +1,206 @@
+use std::io::Write;
+
+use anyhow::Result;
+use ndarray::{Array2, ArrayView1, Axis};
+
+use crate::vec_simd::{scale, scaled_add};
+
+/// The seed of the n-gram hash function.
+pub(crate) const FLORET_HASH_SEED: u32 = 2_166_136_261;
+
+/// Table of floret embeddings.
+///
+/// floret models do not have a vocabulary. The embedding of a word is the
+/// mean of the embeddings of the word and its n-grams, which are hashed
+/// into a fixed number of buckets with MurmurHash3. The table is constructed
+/// from the embeddings of the words and n-grams of a trained model. The
+/// embedding of a bucket is the mean of the embeddings that are hashed to
+/// it.
+pub(crate) struct FloretTable {
+    embeds: Array2<f32>,
+    counts: Vec<usize>,
+}
+
+impl FloretTable {
+    /// Construct an empty table with the given shape.
+    pub fn new(n_buckets: usize, dims: usize) -> Self {
+        FloretTable {
+            embeds: Array2::zeros((n_buckets, dims)),
+            counts: vec![0; n_buckets],
+        }
+    }
+
+    /// Add the embedding of a word or n-gram to its bucket.
+    pub fn add(&mut self, ngram: &str, embed: ArrayView1<f32>) {
+        let bucket = floret_bucket(ngram, self.counts.len());
+        scaled_add(self.embeds.index_axis_mut(Axis(0), bucket), embed, 1.0);
+        self.counts[bucket] += 1;
+    }
+
+    /// Write the table in floret's text format.
+    ///
+    /// The first line contains the shape of the table and the hashing
+    /// parameters. Each following line contains a bucket and its
+    /// embedding.
+    pub fn write<W>(
+        mut self,
+        write: &mut W,
+        min_n: usize,
+        max_n: usize,
+        bow: &str,
+        eow: &str,
+    ) -> Result<()>
+    where
+        W: Write,
+    {
+        writeln!(
+            write,
+            "{} {} {} {} 1 {} {} {}",
+            self.embeds.nrows(),
+            self.embeds.ncols(),
+            min_n,
+            max_n,
+            FLORET_HASH_SEED,
+            bow,
+            eow
+        )?;
+
+        for (bucket, (mut embed, &count)) in self
+            .embeds
+            .outer_iter_mut()
+            .zip(self.counts.iter())
+            .enumerate()
+        {
+            if count > 1 {
+                scale(embed.view_mut(), 1.0 / count as f32);
+            }
+
+            write!(write, "{}", bucket)?;
+            for v in embed.iter() {
+                write!(write, " {}", v)?;
+            }
+            writeln!(write)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the bucket of a word or n-gram.
+pub(crate) fn floret_bucket(ngram: &str, n_buckets: usize) -> usize {
+    let (h1, _) = murmurhash3_x64_128(ngram.as_bytes(), FLORET_HASH_SEED);
+    (h1 as u32) as usize % n_buckets
+}
+
+/// MurmurHash3 x64 128-bit hash function.
+fn murmurhash3_x64_128(data: &[u8], seed: u32) -> (u64, u64) {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+
+    let mut h1 = seed as u64;
+    let mut h2 = seed as u64;
+
+    let mut blocks = data.chunks_exact(16);
+    for block in &mut blocks {
+        let mut k1 = read_u64_le(&block[..8]);
+        let mut k2 = read_u64_le(&block[8..]);
+
+        k1 = k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+
+        k2 = k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+        h2 ^= k2;
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+
+    let tail = blocks.remainder();
+    if tail.len() > 8 {
+        let k2 = read_u64_le(&tail[8..]);
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+    }
+    if !tail.is_empty() {
+        let k1 = read_u64_le(&tail[..tail.len().min(8)]);
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    h1 = fmix64(h1);
+    h2 = fmix64(h2);
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    (h1, h2)
+}
+
+fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^= k >> 33;
+    k
+}
+
+/// Read a little-endian integer of at most 8 bytes.
+fn read_u64_le(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |v, &byte| (v << 8) | byte as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::{floret_bucket, murmurhash3_x64_128, FloretTable, FLORET_HASH_SEED};
+
+    #[test]
+    fn murmurhash3_x64_128_test() {
+        assert_eq!(murmurhash3_x64_128(b"", 0), (0, 0));
+        assert_eq!(
+            murmurhash3_x64_128(b"hello", 0),
+            (0xcbd8_a7b3_41bd_9b02, 0x5b1e_906a_48ae_1d19)
+        );
+        assert_eq!(
+            murmurhash3_x64_128(b"The quick brown fox jumps over the lazy dog", 0),
+            (0xe34b_bc7b_bc07_1b6c, 0x7a43_3ca9_c49a_9347)
+        );
+    }
+
+    #[test]
+    fn floret_table_test() {
+        let mut table = FloretTable::new(4, 2);
+        table.add("<a>", array![1., 2.].view());
+        table.add("<a>", array![3., 4.].view());
+
+        let mut data = Vec::new();
+        table.write(&mut data, 3, 6, "<", ">").unwrap();
+        let data = String::from_utf8(data).unwrap();
+        let mut lines = data.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            format!("4 2 3 6 1 {} < >", FLORET_HASH_SEED)
+        );
+
+        let bucket = floret_bucket("<a>", 4);
+        let lines = lines.collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[bucket], format!("{} 2 3", bucket));
+    }
+}
@@ -0,0 +1,105 @@
+use std::borrow::Cow;
+use std::io::Write;
+
+use anyhow::{ensure, Result};
+use ndarray::ArrayView2;
+
+/// Write word embeddings in a word2vec binary format that gensim can load.
+///
+/// The embeddings are written in the binary word2vec format, which is
+/// read by gensim's `KeyedVectors.load_word2vec_format` with
+/// `binary=True`. Words are written as UTF-8 without truncation. Since
+/// the format separates a word from its embedding with a space, ASCII
+/// whitespace in words is replaced by underscores. Row *i* of `embeds`
+/// is the embedding of `words[i]`.
+///
+/// ```
+/// use finalfrontier::write_word2vec_gensim;
+/// use finalfusion::compat::word2vec::ReadWord2Vec;
+/// use finalfusion::prelude::Embeddings;
+/// use finalfusion::storage::NdArray;
+/// use finalfusion::vocab::{SimpleVocab, Vocab};
+/// use ndarray::array;
+/// use std::io::Cursor;
+///
+/// let words = vec!["new york".to_string(), "Zürich".to_string()];
+/// let embeds = array![[1f32, 0.], [0., 1.]];
+///
+/// let mut data = Vec::new();
+/// write_word2vec_gensim(&mut data, &words, embeds.view()).unwrap();
+///
+/// let embeddings: Embeddings<SimpleVocab, NdArray> =
+///     Embeddings::read_word2vec_binary(&mut Cursor::new(data)).unwrap();
+/// assert_eq!(embeddings.vocab().words(), &["new_york", "Zürich"]);
+/// assert_eq!(embeddings.embedding("Zürich").unwrap(), array![0f32, 1.]);
+/// ```
+pub fn write_word2vec_gensim<W>(
+    write: &mut W,
+    words: &[String],
+    embeds: ArrayView2<f32>,
+) -> Result<()>
+where
+    W: Write,
+{
+    ensure!(
+        words.len() == embeds.nrows(),
+        "Number of words ({}) and embeddings ({}) differ",
+        words.len(),
+        embeds.nrows()
+    );
+
+    writeln!(write, "{} {}", embeds.nrows(), embeds.ncols())?;
+    for (word, embed) in words.iter().zip(embeds.outer_iter()) {
+        write.write_all(gensim_word(word).as_bytes())?;
+        write.write_all(b" ")?;
+        for &v in embed.iter() {
+            write.write_all(&v.to_le_bytes())?;
+        }
+        write.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+/// Replace ASCII whitespace in a word by underscores.
+fn gensim_word(word: &str) -> Cow<str> {
+    if word.bytes().any(|b| b.is_ascii_whitespace()) {
+        Cow::Owned(
+            word.chars()
+                .map(|c| if c.is_ascii_whitespace() { '_' } else { c })
+                .collect(),
+        )
+    } else {
+        Cow::Borrowed(word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::write_word2vec_gensim;
+
+    #[test]
+    fn write_word2vec_gensim_test() {
+        let words = vec!["a\tb".to_string(), "ß".to_string()];
+        let embeds = array![[1f32], [-2.]];
+
+        let mut data = Vec::new();
+        write_word2vec_gensim(&mut data, &words, embeds.view()).unwrap();
+
+        let mut expected = b"2 1\na_b ".to_vec();
+        expected.extend_from_slice(&1f32.to_le_bytes());
+        expected.extend_from_slice("\nß ".as_bytes());
+        expected.extend_from_slice(&(-2f32).to_le_bytes());
+        expected.push(b'\n');
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn write_word2vec_gensim_checks_shape() {
+        let words = vec!["a".to_string()];
+        let embeds = array![[1f32], [2.]];
+        assert!(write_word2vec_gensim(&mut Vec::new(), &words, embeds.view()).is_err());
+    }
+}
@@ -0,0 +1,408 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use ndarray::{Array1, Array2, ArrayView1, ArrayViewMut1, Axis};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Serialize;
+
+use crate::hogwild::{Hogwild, HogwildArray1, HogwildArray2};
+use crate::idx::WordIdx;
+use crate::loss::weighted_least_squares_loss;
+use crate::train_model::{TrainModel, Trainer};
+use crate::vec_simd::dot;
+use crate::{CommonConfig, GloveConfig, Vocab};
+
+/// A weighted co-occurrence of a focus and a context word.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cooccurrence {
+    /// Word index of the focus word.
+    pub focus: u32,
+
+    /// Word index of the context word.
+    pub context: u32,
+
+    /// Co-occurrence count, weighted by distance.
+    pub count: f32,
+}
+
+/// Sparse co-occurrence matrix.
+///
+/// Co-occurrences are added using the `count` method. The matrix can
+/// be converted into a shuffled list of co-occurrences for training.
+#[derive(Clone, Default)]
+pub struct CooccurrenceMatrix {
+    counts: HashMap<(u32, u32), f32>,
+}
+
+impl CooccurrenceMatrix {
+    /// Add a (weighted) co-occurrence of `focus` and `context`.
+    pub fn count(&mut self, focus: u32, context: u32, weight: f32) {
+        *self.counts.entry((focus, context)).or_insert(0.) += weight;
+    }
+
+    /// Get the number of non-zero cells.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Check whether the matrix has non-zero cells.
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Get the (weighted) co-occurrence count of `focus` and `context`.
+    pub fn get(&self, focus: u32, context: u32) -> f32 {
+        self.counts
+            .get(&(focus, context))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Get an iterator over the non-zero cells.
+    pub fn iter(&self) -> impl Iterator<Item = Cooccurrence> + '_ {
+        self.counts
+            .iter()
+            .map(|(&(focus, context), &count)| Cooccurrence {
+                focus,
+                context,
+                count,
+            })
+    }
+
+    /// Convert the matrix into a list of co-occurrences in random order.
+    pub fn into_shuffled<R>(self, rng: &mut R) -> Vec<Cooccurrence>
+    where
+        R: Rng,
+    {
+        let mut cooccurrences = self
+            .counts
+            .into_iter()
+            .map(|((focus, context), count)| Cooccurrence {
+                focus,
+                context,
+                count,
+            })
+            .collect::<Vec<_>>();
+        cooccurrences.shuffle(rng);
+        cooccurrences
+    }
+}
+
+/// GloVe Trainer.
+///
+/// The `GloveTrainer` holds the information and logic necessary to count
+/// the co-occurrences in a tokenized sentence (Pennington et al., 2014).
+/// The struct is cheap to clone because the vocabulary is shared between
+/// clones.
+#[derive(Clone)]
+pub struct GloveTrainer<V> {
+    vocab: Arc<V>,
+    common_config: CommonConfig,
+    glove_config: GloveConfig,
+}
+
+impl<V> GloveTrainer<V>
+where
+    V: Vocab,
+{
+    /// Constructs a new `GloveTrainer`.
+    pub fn new(vocab: V, common_config: CommonConfig, glove_config: GloveConfig) -> Self {
+        GloveTrainer {
+            vocab: Arc::new(vocab),
+            common_config,
+            glove_config,
+        }
+    }
+
+    /// Get the GloVe hyperparameters.
+    pub fn glove_config(&self) -> GloveConfig {
+        self.glove_config
+    }
+
+    /// Count the co-occurrences in a sentence.
+    ///
+    /// Tokens that are not in the vocabulary are removed from the sentence
+    /// before counting. Every co-occurrence within the context window is
+    /// weighted by the inverse of the distance between the two tokens.
+    pub fn count_cooccurrences<S>(&self, sequence: &[S], matrix: &mut CooccurrenceMatrix)
+    where
+        S: Hash + Eq,
+        V::VocabType: Borrow<S>,
+    {
+        let ids = sequence
+            .iter()
+            .filter_map(|t| self.vocab.idx(t))
+            .map(|idx| idx.word_idx() as u32)
+            .collect::<Vec<_>>();
+
+        let ctx_size = self.glove_config.context_size as usize;
+        for (i, &focus) in ids.iter().enumerate() {
+            for (distance, &context) in ids[i + 1..].iter().take(ctx_size).enumerate() {
+                let weight = 1.0 / (distance + 1) as f32;
+                matrix.count(focus, context, weight);
+                matrix.count(context, focus, weight);
+            }
+        }
+    }
+}
+
+impl<V> Trainer for GloveTrainer<V>
+where
+    V: Vocab,
+    V::Config: Serialize,
+{
+    type InputVocab = V;
+    type Metadata = GloveMetadata<V::Config>;
+
+    fn input_vocab(&self) -> &V {
+        &self.vocab
+    }
+
+    fn try_into_input_vocab(self) -> Result<V> {
+        match Arc::try_unwrap(self.vocab) {
+            Ok(vocab) => Ok(vocab),
+            Err(_) => bail!("Cannot unwrap input vocab."),
+        }
+    }
+
+    fn n_input_types(&self) -> usize {
+        self.input_vocab().n_input_types()
+    }
+
+    fn n_output_types(&self) -> usize {
+        self.vocab.len()
+    }
+
+    fn config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+
+    fn to_metadata(&self) -> GloveMetadata<V::Config> {
+        GloveMetadata {
+            common_config: self.common_config,
+            glove_config: self.glove_config,
+            vocab_config: self.vocab.config(),
+        }
+    }
+}
+
+/// AdaGrad optimization of the GloVe objective.
+///
+/// This data type trains GloVe embeddings from co-occurrences. The word
+/// vectors are stored in the input matrix of the model, the context
+/// vectors in the output matrix. Words and contexts also have biases.
+/// All parameters are updated with AdaGrad (Duchi et al., 2011) using
+/// Hogwild parallelization.
+#[derive(Clone)]
+pub struct GloveSgd<V>
+where
+    V: Vocab,
+{
+    model: TrainModel<GloveTrainer<V>>,
+    inputs: Arc<Vec<V::IdxType>>,
+    input_bias: HogwildArray1<f32>,
+    output_bias: HogwildArray1<f32>,
+    input_grad_sq: HogwildArray2<f32>,
+    output_grad_sq: HogwildArray2<f32>,
+    input_bias_grad_sq: HogwildArray1<f32>,
+    output_bias_grad_sq: HogwildArray1<f32>,
+    loss: Hogwild<f32>,
+    n_examples: Hogwild<usize>,
+}
+
+impl<V> GloveSgd<V>
+where
+    V: Vocab,
+    V::Config: Serialize,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    /// Construct a new GloVe optimizer.
+    pub fn new(model: TrainModel<GloveTrainer<V>>) -> Self {
+        let vocab = model.input_vocab();
+        let inputs = vocab
+            .types()
+            .iter()
+            .map(|word| vocab.idx(word.label()).unwrap())
+            .collect::<Vec<_>>();
+
+        let n_inputs = model.input_vocab().n_input_types();
+        let n_outputs = model.input_vocab().len();
+        let dims = model.config().dims as usize;
+
+        // AdaGrad accumulators are initialized to one, as in the reference
+        // GloVe implementation.
+        GloveSgd {
+            inputs: Arc::new(inputs),
+            input_bias: Array1::zeros((n_outputs,)).into(),
+            output_bias: Array1::zeros((n_outputs,)).into(),
+            input_grad_sq: Array2::ones((n_inputs, dims)).into(),
+            output_grad_sq: Array2::ones((n_outputs, dims)).into(),
+            input_bias_grad_sq: Array1::ones((n_outputs,)).into(),
+            output_bias_grad_sq: Array1::ones((n_outputs,)).into(),
+            model,
+            loss: Hogwild::default(),
+            n_examples: Hogwild::default(),
+        }
+    }
+
+    /// Get the training model associated with this optimizer.
+    pub fn model(&self) -> &TrainModel<GloveTrainer<V>> {
+        &self.model
+    }
+
+    /// Consume the optimizer and get the training model.
+    pub fn into_model(self) -> TrainModel<GloveTrainer<V>> {
+        self.model
+    }
+
+    /// Get the number of co-occurrences that are processed by this optimizer.
+    pub fn n_examples(&self) -> usize {
+        *self.n_examples
+    }
+
+    /// Get the average training loss of this optimizer.
+    ///
+    /// This returns the average training loss over all co-occurrences seen
+    /// by this optimizer since its construction.
+    pub fn train_loss(&self) -> f32 {
+        *self.loss / *self.n_examples as f32
+    }
+
+    /// Update the model parameters using the given co-occurrence.
+    pub fn update_cooccurrence(&mut self, cooccurrence: Cooccurrence, lr: f32) {
+        let glove_config = self.model.trainer().glove_config();
+        let focus = cooccurrence.focus as usize;
+        let context = cooccurrence.context as usize;
+
+        let input = &self.inputs[focus];
+        let input_embed = self.model.mean_input_embedding(input);
+        let output_embed = self.model.output_embedding(context).to_owned();
+
+        let prediction = dot(input_embed.view(), output_embed.view())
+            + self.input_bias.view()[focus]
+            + self.output_bias.view()[context];
+        let (loss, grad) = weighted_least_squares_loss(
+            prediction,
+            cooccurrence.count,
+            glove_config.x_max,
+            glove_config.alpha,
+        );
+        *self.loss += loss;
+        *self.n_examples += 1;
+
+        // Update the word embedding and its subword embeddings.
+        let input_grad = output_embed * grad;
+        for idx in input {
+            let grad_sq = self.input_grad_sq.subview_mut(Axis(0), idx as usize);
+            self.model.update_input_embedding(idx as usize, |embed| {
+                adagrad_update(embed, grad_sq, input_grad.view(), lr)
+            });
+        }
+
+        // Update the context embedding.
+        let output_grad = input_embed * grad;
+        let grad_sq = self.output_grad_sq.subview_mut(Axis(0), context);
+        self.model.update_output_embedding(context, |embed| {
+            adagrad_update(embed, grad_sq, output_grad.view(), lr)
+        });
+
+        // Update the biases.
+        adagrad_update_scalar(
+            &mut self.input_bias.view_mut()[focus],
+            &mut self.input_bias_grad_sq.view_mut()[focus],
+            grad,
+            lr,
+        );
+        adagrad_update_scalar(
+            &mut self.output_bias.view_mut()[context],
+            &mut self.output_bias_grad_sq.view_mut()[context],
+            grad,
+            lr,
+        );
+    }
+}
+
+/// Apply an AdaGrad update to a parameter vector.
+///
+/// The squared gradients are accumulated in `grad_sq`.
+fn adagrad_update(
+    mut param: ArrayViewMut1<f32>,
+    mut grad_sq: ArrayViewMut1<f32>,
+    grad: ArrayView1<f32>,
+    lr: f32,
+) {
+    for ((p, g_sq), &g) in param.iter_mut().zip(grad_sq.iter_mut()).zip(grad) {
+        *p -= lr * g / g_sq.sqrt();
+        *g_sq += g * g;
+    }
+}
+
+/// Apply an AdaGrad update to a scalar parameter.
+fn adagrad_update_scalar(param: &mut f32, grad_sq: &mut f32, grad: f32, lr: f32) {
+    *param -= lr * grad / grad_sq.sqrt();
+    *grad_sq += grad * grad;
+}
+
+/// Metadata for GloVe embeddings.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct GloveMetadata<V> {
+    common_config: CommonConfig,
+    #[serde(rename = "model_config")]
+    glove_config: GloveConfig,
+    vocab_config: V,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CooccurrenceMatrix, GloveTrainer};
+    use crate::util::close;
+    use crate::{
+        CommonConfig, Cutoff, GloveConfig, LossType, SimpleVocab, SimpleVocabConfig, Vocab,
+        VocabBuilder,
+    };
+
+    const TEST_COMMON_CONFIG: CommonConfig = CommonConfig {
+        loss: LossType::WeightedLeastSquares,
+        ..CommonConfig::for_test()
+    };
+
+    const TEST_GLOVE_CONFIG: GloveConfig = GloveConfig {
+        context_size: 2,
+        x_max: 100.,
+        alpha: 0.75,
+    };
+
+    #[test]
+    pub fn count_cooccurrences() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(SimpleVocabConfig {
+            cutoff: Cutoff::MinCount(2),
+            discard_threshold: 1e-4,
+        });
+        for token in &["a", "a", "a", "b", "b", "c", "c", "d"] {
+            builder.count(*token);
+        }
+        let vocab: SimpleVocab<&str> = builder.into();
+        let a = vocab.idx("a").unwrap().idx() as u32;
+        let b = vocab.idx("b").unwrap().idx() as u32;
+        let c = vocab.idx("c").unwrap().idx() as u32;
+
+        let trainer = GloveTrainer::new(vocab, TEST_COMMON_CONFIG, TEST_GLOVE_CONFIG);
+        let mut matrix = CooccurrenceMatrix::default();
+
+        // "d" is not in the vocabulary and removed before counting.
+        trainer.count_cooccurrences(&["a", "d", "b", "c", "a"], &mut matrix);
+
+        assert!(close(matrix.get(a, b), 1.5, 1e-5));
+        assert!(close(matrix.get(b, a), 1.5, 1e-5));
+        assert!(close(matrix.get(a, c), 1.5, 1e-5));
+        assert!(close(matrix.get(c, a), 1.5, 1e-5));
+        assert!(close(matrix.get(b, c), 1.0, 1e-5));
+        assert!(close(matrix.get(c, b), 1.0, 1e-5));
+        assert!(close(matrix.get(a, a), 0.0, 1e-5));
+        assert_eq!(matrix.len(), 6);
+    }
+}
@@ -0,0 +1,660 @@
+use std::sync::mpsc;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use ndarray::{Array2, ArrayView2};
+use wgpu::util::DeviceExt;
+
+/// Number of times that a negative that coincides with the positive
+/// output or is in the window of its instance is redrawn.
+const MAX_REJECTIONS: u32 = 10;
+
+/// Workgroup size of the inner product and sampling shaders.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Workgroup size of the scaled addition shader in each dimension.
+const SCALED_ADD_WORKGROUP_SIZE: u32 = 8;
+
+const DOT_SHADER: &str = r#"
+struct Dims {
+    n_pairs: u32,
+    k: u32,
+    padding0: u32,
+    padding1: u32,
+}
+
+@group(0) @binding(0) var<uniform> dims: Dims;
+@group(0) @binding(1) var<storage, read> a: array<f32>;
+@group(0) @binding(2) var<storage, read> b: array<f32>;
+@group(0) @binding(3) var<storage, read> pairs: array<vec2<u32>>;
+@group(0) @binding(4) var<storage, read_write> c: array<f32>;
+
+@compute @workgroup_size(64)
+fn dot_pairs(@builtin(global_invocation_id) id: vec3<u32>) {
+    let pair = id.x;
+    if (pair >= dims.n_pairs) {
+        return;
+    }
+
+    let a_offset = pairs[pair].x * dims.k;
+    let b_offset = pairs[pair].y * dims.k;
+    var sum = 0.0;
+    for (var i = 0u; i < dims.k; i = i + 1u) {
+        sum = sum + a[a_offset + i] * b[b_offset + i];
+    }
+    c[pair] = sum;
+}
+"#;
+
+const SCALED_ADD_SHADER: &str = r#"
+struct Dims {
+    n_rows: u32,
+    k: u32,
+    padding0: u32,
+    padding1: u32,
+}
+
+@group(0) @binding(0) var<uniform> dims: Dims;
+@group(0) @binding(1) var<storage, read> b: array<f32>;
+@group(0) @binding(2) var<storage, read> offsets: array<u32>;
+@group(0) @binding(3) var<storage, read> sources: array<u32>;
+@group(0) @binding(4) var<storage, read> coeffs: array<f32>;
+@group(0) @binding(5) var<storage, read_write> c: array<f32>;
+
+@compute @workgroup_size(8, 8)
+fn scaled_add(@builtin(global_invocation_id) id: vec3<u32>) {
+    let row = id.x;
+    let col = id.y;
+    if (row >= dims.n_rows || col >= dims.k) {
+        return;
+    }
+
+    var sum = 0.0;
+    for (var entry = offsets[row]; entry < offsets[row + 1u]; entry = entry + 1u) {
+        sum = sum + coeffs[entry] * b[sources[entry] * dims.k + col];
+    }
+    c[row * dims.k + col] = sum;
+}
+"#;
+
+const SAMPLE_SHADER: &str = r#"
+struct Params {
+    n_instances: u32,
+    n_negatives: u32,
+    n_outputs: u32,
+    max_rejections: u32,
+    seed_low: u32,
+    seed_high: u32,
+    padding0: u32,
+    padding1: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> cdf: array<f32>;
+@group(0) @binding(2) var<storage, read> positives: array<u32>;
+@group(0) @binding(3) var<storage, read> window_ranges: array<vec2<u32>>;
+@group(0) @binding(4) var<storage, read> windows: array<u32>;
+@group(0) @binding(5) var<storage, read_write> negatives: array<u32>;
+
+fn hash(x: u32) -> u32 {
+    var v = x;
+    v = v ^ (v >> 16u);
+    v = v * 0x7feb352du;
+    v = v ^ (v >> 15u);
+    v = v * 0x846ca68bu;
+    v = v ^ (v >> 16u);
+    return v;
+}
+
+// Uniform sample in [0, 1) for a draw of a negative.
+fn uniform(draw: u32, attempt: u32) -> f32 {
+    let h = hash(hash(hash(draw ^ params.seed_low) ^ params.seed_high) + attempt * 0x9e3779b9u);
+    return f32(h >> 8u) / 16777216.0;
+}
+
+// The first output whose cumulative probability exceeds u.
+fn output_at(u: f32) -> u32 {
+    var low = 0u;
+    var high = params.n_outputs - 1u;
+    while (low < high) {
+        let mid = (low + high) / 2u;
+        if (cdf[mid] > u) {
+            high = mid;
+        } else {
+            low = mid + 1u;
+        }
+    }
+    return low;
+}
+
+// Check whether output is in the sorted window of an instance.
+fn in_window(instance: u32, output: u32) -> bool {
+    let end = window_ranges[instance].x + window_ranges[instance].y;
+    var low = window_ranges[instance].x;
+    var high = end;
+    while (low < high) {
+        let mid = (low + high) / 2u;
+        if (windows[mid] < output) {
+            low = mid + 1u;
+        } else {
+            high = mid;
+        }
+    }
+    return low < end && windows[low] == output;
+}
+
+fn rejected(instance: u32, negative: u32) -> bool {
+    return negative == positives[instance] || in_window(instance, negative);
+}
+
+@compute @workgroup_size(64)
+fn sample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let draw = id.x;
+    if (draw >= params.n_instances * params.n_negatives) {
+        return;
+    }
+
+    let instance = draw / params.n_negatives;
+    var negative = output_at(uniform(draw, 0u));
+    for (var attempt = 1u; attempt <= params.max_rejections && rejected(instance, negative); attempt = attempt + 1u) {
+        negative = output_at(uniform(draw, attempt));
+    }
+    negatives[draw] = negative;
+}
+"#;
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Dims {
+    n: u32,
+    k: u32,
+    padding0: u32,
+    padding1: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SampleParams {
+    n_instances: u32,
+    n_negatives: u32,
+    n_outputs: u32,
+    max_rejections: u32,
+    seed_low: u32,
+    seed_high: u32,
+    padding0: u32,
+    padding1: u32,
+}
+
+/// GPU for batch operations.
+///
+/// The GPU computes the inner products and scaled additions of the
+/// embeddings of batches of training instances and samples their
+/// negatives. The noise distribution of the negatives is uploaded once,
+/// when the GPU is opened. A GPU can be shared by the training threads.
+pub struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    dot_pairs: wgpu::ComputePipeline,
+    scaled_add: wgpu::ComputePipeline,
+    sample: wgpu::ComputePipeline,
+    cdf: wgpu::Buffer,
+    n_outputs: usize,
+}
+
+impl Gpu {
+    /// Open the default GPU.
+    ///
+    /// `noise_probs` are the probabilities of drawing each output as a
+    /// negative sample.
+    pub fn new(noise_probs: &[f32]) -> Result<Self> {
+        ensure!(
+            !noise_probs.is_empty(),
+            "The noise distribution has no outputs"
+        );
+
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        }))
+        .ok_or_else(|| anyhow!("Cannot find a GPU"))?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("finalfrontier"),
+                required_features: wgpu::Features::empty(),
+                required_limits: adapter.limits(),
+                memory_hints: wgpu::MemoryHints::Performance,
+            },
+            None,
+        ))
+        .context("Cannot open GPU")?;
+
+        let dot_pairs = compute_pipeline(&device, "dot_pairs", DOT_SHADER);
+        let scaled_add = compute_pipeline(&device, "scaled_add", SCALED_ADD_SHADER);
+        let sample = compute_pipeline(&device, "sample", SAMPLE_SHADER);
+
+        // Outputs are drawn by searching the cumulative distribution. The
+        // last cumulative probability is set to one, so that rounding
+        // errors do not leave a gap at the end of the distribution.
+        let norm: f32 = noise_probs.iter().sum();
+        ensure!(norm > 0., "The noise distribution has no mass");
+        let mut cdf = noise_probs
+            .iter()
+            .scan(0., |sum, &prob| {
+                *sum += prob / norm;
+                Some(*sum)
+            })
+            .collect::<Vec<f32>>();
+        *cdf.last_mut().unwrap() = 1.;
+        let cdf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("cdf"),
+            contents: bytemuck::cast_slice(&cdf),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        Ok(Gpu {
+            device,
+            queue,
+            dot_pairs,
+            scaled_add,
+            sample,
+            cdf,
+            n_outputs: noise_probs.len(),
+        })
+    }
+
+    /// Compute the inner products of pairs of rows of `a` and `b`.
+    ///
+    /// Inner product *i* is the inner product of row `pairs[i].0` of `a`
+    /// and row `pairs[i].1` of `b`.
+    pub fn dot_pairs(
+        &self,
+        a: ArrayView2<f32>,
+        b: ArrayView2<f32>,
+        pairs: &[[u32; 2]],
+    ) -> Vec<f32> {
+        assert_eq!(a.ncols(), b.ncols(), "Incompatible matrix shapes");
+        let k = a.ncols();
+        if pairs.is_empty() || k == 0 {
+            return vec![0.; pairs.len()];
+        }
+
+        let dims = Dims {
+            n: pairs.len() as u32,
+            k: k as u32,
+            padding0: 0,
+            padding1: 0,
+        };
+
+        let dims = self.uniform_buffer(bytemuck::bytes_of(&dims));
+        let a = self.storage_buffer(bytemuck::cast_slice(&standard_layout(a)));
+        let b = self.storage_buffer(bytemuck::cast_slice(&standard_layout(b)));
+        let pairs_buffer = self.storage_buffer(bytemuck::cast_slice(pairs));
+        let c = self.output_buffer(pairs.len() * 4);
+
+        self.dispatch(
+            &self.dot_pairs,
+            &[&dims, &a, &b, &pairs_buffer, &c],
+            (workgroups(pairs.len(), WORKGROUP_SIZE), 1),
+        );
+
+        self.read_buffer(&c, pairs.len() * 4)
+    }
+
+    /// Compute sums of scaled rows of `b`.
+    ///
+    /// Row *r* of the result is the sum of the rows `sources[e]` of `b`
+    /// scaled by `coeffs[e]`, for *e* in `offsets[r]..offsets[r + 1]`.
+    /// The result has `offsets.len() - 1` rows.
+    pub fn scaled_add(
+        &self,
+        b: ArrayView2<f32>,
+        offsets: &[u32],
+        sources: &[u32],
+        coeffs: &[f32],
+    ) -> Array2<f32> {
+        assert_eq!(
+            sources.len(),
+            coeffs.len(),
+            "Sources and coefficients differ"
+        );
+        let (n_rows, k) = (offsets.len() - 1, b.ncols());
+        if sources.is_empty() || n_rows == 0 || k == 0 {
+            return Array2::zeros((n_rows, k));
+        }
+
+        let dims = Dims {
+            n: n_rows as u32,
+            k: k as u32,
+            padding0: 0,
+            padding1: 0,
+        };
+
+        let dims = self.uniform_buffer(bytemuck::bytes_of(&dims));
+        let b = self.storage_buffer(bytemuck::cast_slice(&standard_layout(b)));
+        let offsets = self.storage_buffer(bytemuck::cast_slice(offsets));
+        let sources = self.storage_buffer(bytemuck::cast_slice(sources));
+        let coeffs = self.storage_buffer(bytemuck::cast_slice(coeffs));
+        let c = self.output_buffer(n_rows * k * 4);
+
+        self.dispatch(
+            &self.scaled_add,
+            &[&dims, &b, &offsets, &sources, &coeffs, &c],
+            (
+                workgroups(n_rows, SCALED_ADD_WORKGROUP_SIZE),
+                workgroups(k, SCALED_ADD_WORKGROUP_SIZE),
+            ),
+        );
+
+        Array2::from_shape_vec((n_rows, k), self.read_buffer(&c, n_rows * k * 4))
+            .expect("Sum has incorrect shape")
+    }
+
+    /// Sample `n_negatives` negatives for each of the `positives`.
+    ///
+    /// The negatives of positive *i* are at *i * n_negatives..(i + 1) *
+    /// n_negatives*. The window of positive *i* is the sorted slice
+    /// `windows[start..start + len]`, where `[start, len]` is
+    /// `window_ranges[i]`. A negative that coincides with its positive or
+    /// is in its window is redrawn at most `MAX_REJECTIONS` times. The
+    /// draws are determined by `seed`.
+    pub fn sample_negatives(
+        &self,
+        positives: &[u32],
+        windows: &[u32],
+        window_ranges: &[[u32; 2]],
+        n_negatives: usize,
+        seed: u64,
+    ) -> Vec<u32> {
+        assert_eq!(
+            positives.len(),
+            window_ranges.len(),
+            "Number of positives and windows differ"
+        );
+        let n_draws = positives.len() * n_negatives;
+        if n_draws == 0 {
+            return Vec::new();
+        }
+
+        let params = SampleParams {
+            n_instances: positives.len() as u32,
+            n_negatives: n_negatives as u32,
+            n_outputs: self.n_outputs as u32,
+            max_rejections: MAX_REJECTIONS,
+            seed_low: seed as u32,
+            seed_high: (seed >> 32) as u32,
+            padding0: 0,
+            padding1: 0,
+        };
+
+        // Buffers cannot be empty, so an empty window list is padded.
+        let windows = if windows.is_empty() {
+            &[0][..]
+        } else {
+            windows
+        };
+
+        let params = self.uniform_buffer(bytemuck::bytes_of(&params));
+        let positives = self.storage_buffer(bytemuck::cast_slice(positives));
+        let window_ranges = self.storage_buffer(bytemuck::cast_slice(window_ranges));
+        let windows = self.storage_buffer(bytemuck::cast_slice(windows));
+        let negatives = self.output_buffer(n_draws * 4);
+
+        self.dispatch(
+            &self.sample,
+            &[
+                &params,
+                &self.cdf,
+                &positives,
+                &window_ranges,
+                &windows,
+                &negatives,
+            ],
+            (workgroups(n_draws, WORKGROUP_SIZE), 1),
+        );
+
+        self.read_buffer(&negatives, n_draws * 4)
+    }
+
+    fn uniform_buffer(&self, contents: &[u8]) -> wgpu::Buffer {
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents,
+                usage: wgpu::BufferUsages::UNIFORM,
+            })
+    }
+
+    fn storage_buffer(&self, contents: &[u8]) -> wgpu::Buffer {
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents,
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+    }
+
+    fn output_buffer(&self, size: usize) -> wgpu::Buffer {
+        self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Run `pipeline` on `buffers`, which are bound in order.
+    fn dispatch(
+        &self,
+        pipeline: &wgpu::ComputePipeline,
+        buffers: &[&wgpu::Buffer],
+        workgroups: (u32, u32),
+    ) {
+        let entries = buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>();
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        }
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    /// Read `size` bytes of `buffer`.
+    fn read_buffer<A>(&self, buffer: &wgpu::Buffer, size: usize) -> Vec<A>
+    where
+        A: bytemuck::Pod,
+    {
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size as u64);
+        self.queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        let slice = staging.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .expect("GPU buffer was not mapped")
+            .expect("Cannot map GPU buffer");
+
+        let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        staging.unmap();
+        data
+    }
+}
+
+/// Compile the compute shader `entry_point` of `source`.
+fn compute_pipeline(
+    device: &wgpu::Device,
+    entry_point: &str,
+    source: &str,
+) -> wgpu::ComputePipeline {
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(entry_point),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: None,
+        module: &module,
+        entry_point: Some(entry_point),
+        compilation_options: Default::default(),
+        cache: None,
+    })
+}
+
+/// Get the number of workgroups of size `workgroup_size` for `n` items.
+fn workgroups(n: usize, workgroup_size: u32) -> u32 {
+    (n as u32).div_ceil(workgroup_size)
+}
+
+/// Get the data of a matrix in standard layout.
+fn standard_layout(matrix: ArrayView2<f32>) -> Vec<f32> {
+    match matrix.as_slice() {
+        Some(data) => data.to_vec(),
+        None => matrix.iter().cloned().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+    use ndarray_rand::rand_distr::Uniform;
+    use ndarray_rand::RandomExt;
+
+    use super::Gpu;
+    use crate::util::{all_close, array_all_close};
+
+    /// Open the GPU, tests are skipped when there is no GPU.
+    fn gpu(noise_probs: &[f32]) -> Option<Gpu> {
+        Gpu::new(noise_probs).ok()
+    }
+
+    #[test]
+    fn dot_pairs_matches_cpu() {
+        let gpu = match gpu(&[1.]) {
+            Some(gpu) => gpu,
+            None => return,
+        };
+
+        let a = Array2::random((13, 7), Uniform::new_inclusive(-1.0, 1.0));
+        let b = Array2::random((11, 7), Uniform::new_inclusive(-1.0, 1.0));
+        let pairs = [[0, 0], [12, 3], [5, 10], [5, 10], [7, 0]];
+        let dots = gpu.dot_pairs(a.view(), b.view(), &pairs);
+        let expected = pairs
+            .iter()
+            .map(|&[row_a, row_b]| a.row(row_a as usize).dot(&b.row(row_b as usize)))
+            .collect::<Vec<_>>();
+        assert!(all_close(&dots, &expected, 1e-5));
+
+        // Operands that are not in standard layout are copied.
+        let dots = gpu.dot_pairs(a.view(), b.t().t(), &pairs);
+        assert!(all_close(&dots, &expected, 1e-5));
+        let c = Array2::random((7, 11), Uniform::new_inclusive(-1.0, 1.0));
+        let dots = gpu.dot_pairs(a.view(), c.t(), &[[1, 2]]);
+        assert!(all_close(&dots, &[a.row(1).dot(&c.column(2))], 1e-5));
+    }
+
+    #[test]
+    fn scaled_add_matches_cpu() {
+        let gpu = match gpu(&[1.]) {
+            Some(gpu) => gpu,
+            None => return,
+        };
+
+        let b = Array2::random((5, 9), Uniform::new_inclusive(-1.0, 1.0));
+        // Row 0 sums two rows, row 1 is empty, and row 2 adds a row twice.
+        let offsets = [0, 2, 2, 4];
+        let sources = [1, 4, 3, 3];
+        let coeffs = [0.5, -2., 1., 0.25];
+        let sums = gpu.scaled_add(b.view(), &offsets, &sources, &coeffs);
+
+        let mut expected = Array2::zeros((3, 9));
+        expected
+            .row_mut(0)
+            .assign(&(&b.row(1) * 0.5 + &b.row(4) * -2.));
+        expected.row_mut(2).assign(&(&b.row(3) * 1.25));
+        assert!(array_all_close(sums.view(), expected.view(), 1e-5));
+    }
+
+    #[test]
+    fn negatives_follow_noise_distribution() {
+        let gpu = match gpu(&[0., 0.25, 0., 0.75]) {
+            Some(gpu) => gpu,
+            None => return,
+        };
+
+        let positives = vec![0; 1000];
+        let no_windows = vec![[0, 0]; 1000];
+        let negatives = gpu.sample_negatives(&positives, &[], &no_windows, 4, 42);
+        assert_eq!(negatives.len(), 4000);
+        assert!(negatives
+            .iter()
+            .all(|&negative| negative == 1 || negative == 3));
+        let n_ones = negatives.iter().filter(|&&negative| negative == 1).count();
+        assert!((800..1200).contains(&n_ones));
+
+        // Draws are determined by the seed.
+        assert_eq!(
+            gpu.sample_negatives(&positives, &[], &no_windows, 4, 42),
+            negatives
+        );
+        assert_ne!(
+            gpu.sample_negatives(&positives, &[], &no_windows, 4, 43),
+            negatives
+        );
+
+        // Negatives are redrawn when they coincide with the positive.
+        let negatives = gpu.sample_negatives(&[1; 100], &[], &no_windows[..100], 2, 42);
+        assert!(negatives.iter().all(|&negative| negative == 3));
+    }
+
+    #[test]
+    fn negatives_are_redrawn_in_window() {
+        let gpu = match gpu(&[0.25, 0.25, 0.25, 0.25]) {
+            Some(gpu) => gpu,
+            None => return,
+        };
+
+        // The windows of the first 50 instances are [1, 2], the windows of
+        // the other instances are empty.
+        let positives = vec![0; 100];
+        let mut window_ranges = vec![[0, 2]; 50];
+        window_ranges.resize(100, [0, 0]);
+        let negatives = gpu.sample_negatives(&positives, &[1, 2], &window_ranges, 4, 42);
+
+        // 3 is the only output that is not rejected in the window, it is
+        // drawn unless all 11 draws are rejected: P(3) = 1 - (3/4)^11.
+        let (windowed, unwindowed) = negatives.split_at(200);
+        let n_threes = windowed.iter().filter(|&&negative| negative == 3).count();
+        assert!(n_threes > 180);
+        assert!(unwindowed.iter().all(|&negative| negative != 0));
+        assert!(unwindowed.contains(&1));
+    }
+}
@@ -0,0 +1,533 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::io::{Read, Write};
+use std::mem;
+
+use anyhow::{ensure, Result};
+use ndarray::{ArrayView1, ArrayView2};
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+
+use crate::vec_simd::dot;
+
+/// Magic number of HNSW index files.
+const HNSW_MAGIC: [u8; 4] = *b"FFHN";
+
+/// Version of the HNSW index format.
+const HNSW_VERSION: u32 = 1;
+
+/// Default maximum number of neighbors of a node in the upper layers.
+pub(crate) const HNSW_DEFAULT_M: usize = 16;
+
+/// Default size of the candidate list during construction.
+pub(crate) const HNSW_DEFAULT_EF_CONSTRUCTION: usize = 100;
+
+/// Seed of the random number generator that assigns layers to nodes.
+const HNSW_SEED: u64 = 42;
+
+/// Hierarchical navigable small world graph (Malkov and Yashunin, 2018).
+///
+/// This is an approximate nearest neighbor index over the rows of an
+/// embedding matrix. The rows must be normalized, so that the dot product
+/// is the cosine similarity. The index only stores the graph, the
+/// embeddings themselves are passed to `search`. Row *i* of the matrix is
+/// node *i* of the graph, so the same matrix must be used for
+/// construction and search.
+///
+/// Every node is assigned to the layers up to a randomly drawn level,
+/// where higher layers are exponentially sparser. A search starts in the
+/// top layer and greedily descends to the layer with all nodes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HnswIndex {
+    m: usize,
+    entry_point: Option<u32>,
+    // The neighbors of every node in each of its layers.
+    neighbors: Vec<Vec<Vec<u32>>>,
+}
+
+impl HnswIndex {
+    /// Build an index over the rows of `embeds`.
+    ///
+    /// Nodes have at most `m` neighbors in the upper layers and `2 * m`
+    /// neighbors in the bottom layer. `ef_construction` is the number of
+    /// candidate neighbors that is considered when inserting a node;
+    /// larger values give a more accurate index, but slow down
+    /// construction.
+    pub fn build(embeds: ArrayView2<f32>, m: usize, ef_construction: usize) -> Self {
+        assert!(m > 1, "Number of neighbors should be larger than one");
+
+        let mut index = HnswIndex {
+            m,
+            entry_point: None,
+            neighbors: Vec::with_capacity(embeds.nrows()),
+        };
+
+        let mut rng = XorShiftRng::seed_from_u64(HNSW_SEED);
+        let level_mult = 1. / (m as f64).ln();
+        let mut visited = Visited::new(embeds.nrows());
+        for idx in 0..embeds.nrows() {
+            let level = (-(1. - rng.gen::<f64>()).ln() * level_mult) as usize;
+            index.insert(embeds, idx as u32, level, ef_construction, &mut visited);
+        }
+
+        index
+    }
+
+    /// Get the number of nodes in the index.
+    pub fn len(&self) -> usize {
+        self.neighbors.len()
+    }
+
+    /// Check whether the index is empty.
+    pub fn is_empty(&self) -> bool {
+        self.neighbors.is_empty()
+    }
+
+    /// Find the approximate `k` nearest neighbors of `query`.
+    ///
+    /// `embeds` must be the matrix that the index was built from and
+    /// `query` must be normalized. `ef` is the number of candidates that
+    /// is considered in the bottom layer, it should be at least `k`.
+    /// Returns the indices of the neighbors and their cosine similarities
+    /// to `query`, from the most to the least similar.
+    pub fn search(
+        &self,
+        embeds: ArrayView2<f32>,
+        query: ArrayView1<f32>,
+        k: usize,
+        ef: usize,
+    ) -> Vec<(usize, f32)> {
+        let entry_point = match self.entry_point {
+            Some(entry_point) => entry_point,
+            None => return Vec::new(),
+        };
+
+        let mut nearest = Candidate {
+            distance: distance(query, embeds.row(entry_point as usize)),
+            idx: entry_point,
+        };
+        for layer in (1..=self.level(entry_point)).rev() {
+            nearest = self.greedy_search(embeds, query, nearest, layer);
+        }
+
+        let mut visited = Visited::new(embeds.nrows());
+        self.search_layer(embeds, query, &[nearest], ef.max(k), 0, &mut visited)
+            .into_iter()
+            .take(k)
+            .map(|candidate| (candidate.idx as usize, 1. - candidate.distance))
+            .collect()
+    }
+
+    /// Read an index.
+    pub fn read<R>(read: &mut R) -> Result<Self>
+    where
+        R: Read,
+    {
+        let mut magic = [0u8; 4];
+        read.read_exact(&mut magic)?;
+        ensure!(magic == HNSW_MAGIC, "File is not an HNSW index");
+        let version = read_u32(read)?;
+        ensure!(
+            version == HNSW_VERSION,
+            "Unsupported HNSW index version: {}",
+            version
+        );
+
+        let m = read_u32(read)? as usize;
+        let n_nodes = read_u32(read)? as usize;
+        let entry_point = match read_u32(read)? {
+            u32::MAX => None,
+            entry_point => Some(entry_point),
+        };
+
+        let mut neighbors = Vec::with_capacity(n_nodes);
+        for _ in 0..n_nodes {
+            let n_layers = read_u32(read)? as usize;
+            let mut layers = Vec::with_capacity(n_layers);
+            for _ in 0..n_layers {
+                let n_neighbors = read_u32(read)? as usize;
+                let layer = (0..n_neighbors)
+                    .map(|_| read_u32(read))
+                    .collect::<Result<Vec<_>>>()?;
+                layers.push(layer);
+            }
+            neighbors.push(layers);
+        }
+
+        Ok(HnswIndex {
+            m,
+            entry_point,
+            neighbors,
+        })
+    }
+
+    /// Write the index.
+    ///
+    /// The index is written in a simple little-endian binary format: a
+    /// header with the magic number, version, `m`, the number of nodes,
+    /// and the entry point, followed by the neighbor lists of every layer
+    /// of every node.
+    pub fn write<W>(&self, write: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        write.write_all(&HNSW_MAGIC)?;
+        write.write_all(&HNSW_VERSION.to_le_bytes())?;
+        write.write_all(&(self.m as u32).to_le_bytes())?;
+        write.write_all(&(self.neighbors.len() as u32).to_le_bytes())?;
+        write.write_all(&self.entry_point.unwrap_or(u32::MAX).to_le_bytes())?;
+
+        for layers in &self.neighbors {
+            write.write_all(&(layers.len() as u32).to_le_bytes())?;
+            for layer in layers {
+                write.write_all(&(layer.len() as u32).to_le_bytes())?;
+                for &neighbor in layer {
+                    write.write_all(&neighbor.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Insert the node `idx` in the layers up to `level`.
+    fn insert(
+        &mut self,
+        embeds: ArrayView2<f32>,
+        idx: u32,
+        level: usize,
+        ef_construction: usize,
+        visited: &mut Visited,
+    ) {
+        self.neighbors.push(vec![Vec::new(); level + 1]);
+
+        let entry_point = match self.entry_point {
+            Some(entry_point) => entry_point,
+            None => {
+                self.entry_point = Some(idx);
+                return;
+            }
+        };
+
+        let query = embeds.row(idx as usize);
+        let top_level = self.level(entry_point);
+
+        // Descend greedily to the top layer of the new node.
+        let mut nearest = Candidate {
+            distance: distance(query, embeds.row(entry_point as usize)),
+            idx: entry_point,
+        };
+        for layer in (level + 1..=top_level).rev() {
+            nearest = self.greedy_search(embeds, query, nearest, layer);
+        }
+
+        // Connect the node to its nearest neighbors in each of its layers.
+        let mut entry_points = vec![nearest];
+        for layer in (0..=level.min(top_level)).rev() {
+            let candidates = self.search_layer(
+                embeds,
+                query,
+                &entry_points,
+                ef_construction,
+                layer,
+                visited,
+            );
+            let selected = candidates
+                .iter()
+                .take(self.m)
+                .map(|candidate| candidate.idx)
+                .collect::<Vec<_>>();
+
+            let max_neighbors = self.max_neighbors(layer);
+            for &neighbor in &selected {
+                let neighbor_layer = &mut self.neighbors[neighbor as usize][layer];
+                neighbor_layer.push(idx);
+                if neighbor_layer.len() > max_neighbors {
+                    self.prune(embeds, neighbor, layer);
+                }
+            }
+
+            self.neighbors[idx as usize][layer] = selected;
+            entry_points = candidates;
+        }
+
+        if level > top_level {
+            self.entry_point = Some(idx);
+        }
+    }
+
+    /// Find the node that is nearest to `query` in a layer greedily.
+    fn greedy_search(
+        &self,
+        embeds: ArrayView2<f32>,
+        query: ArrayView1<f32>,
+        mut nearest: Candidate,
+        layer: usize,
+    ) -> Candidate {
+        loop {
+            let mut changed = false;
+            for &neighbor in &self.neighbors[nearest.idx as usize][layer] {
+                let distance = distance(query, embeds.row(neighbor as usize));
+                if distance < nearest.distance {
+                    nearest = Candidate {
+                        distance,
+                        idx: neighbor,
+                    };
+                    changed = true;
+                }
+            }
+
+            if !changed {
+                return nearest;
+            }
+        }
+    }
+
+    /// Find the `ef` nodes that are nearest to `query` in a layer.
+    ///
+    /// The nodes are returned from the nearest to the farthest node.
+    fn search_layer(
+        &self,
+        embeds: ArrayView2<f32>,
+        query: ArrayView1<f32>,
+        entry_points: &[Candidate],
+        ef: usize,
+        layer: usize,
+        visited: &mut Visited,
+    ) -> Vec<Candidate> {
+        visited.clear();
+
+        // Min-heap of the candidates to explore and max-heap of the
+        // nearest nodes that were found.
+        let mut candidates = BinaryHeap::new();
+        let mut nearest = BinaryHeap::new();
+        for &entry_point in entry_points {
+            if visited.insert(entry_point.idx) {
+                candidates.push(Reverse(entry_point));
+                nearest.push(entry_point);
+            }
+        }
+        while nearest.len() > ef {
+            nearest.pop();
+        }
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            let farthest = nearest.peek().map(|nearest| nearest.distance);
+            if nearest.len() >= ef && Some(candidate.distance) > farthest {
+                break;
+            }
+
+            for &neighbor in &self.neighbors[candidate.idx as usize][layer] {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+
+                let distance = distance(query, embeds.row(neighbor as usize));
+                if nearest.len() < ef || Some(distance) < nearest.peek().map(|c| c.distance) {
+                    let neighbor = Candidate {
+                        distance,
+                        idx: neighbor,
+                    };
+                    candidates.push(Reverse(neighbor));
+                    nearest.push(neighbor);
+                    if nearest.len() > ef {
+                        nearest.pop();
+                    }
+                }
+            }
+        }
+
+        nearest.into_sorted_vec()
+    }
+
+    /// Retain the nearest neighbors of a node in a layer.
+    fn prune(&mut self, embeds: ArrayView2<f32>, idx: u32, layer: usize) {
+        let embed = embeds.row(idx as usize);
+        let neighbors = mem::take(&mut self.neighbors[idx as usize][layer]);
+        let mut candidates = neighbors
+            .into_iter()
+            .map(|neighbor| Candidate {
+                distance: distance(embed, embeds.row(neighbor as usize)),
+                idx: neighbor,
+            })
+            .collect::<Vec<_>>();
+        candidates.sort();
+        candidates.truncate(self.max_neighbors(layer));
+        self.neighbors[idx as usize][layer] = candidates
+            .into_iter()
+            .map(|candidate| candidate.idx)
+            .collect();
+    }
+
+    /// Get the maximum number of neighbors of a node in a layer.
+    fn max_neighbors(&self, layer: usize) -> usize {
+        if layer == 0 {
+            2 * self.m
+        } else {
+            self.m
+        }
+    }
+
+    /// Get the top layer of a node.
+    fn level(&self, idx: u32) -> usize {
+        self.neighbors[idx as usize].len() - 1
+    }
+}
+
+/// A node with its distance to a query.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Candidate {
+    distance: f32,
+    idx: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .partial_cmp(&other.distance)
+            .unwrap_or(Ordering::Equal)
+            .then(self.idx.cmp(&other.idx))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Set of visited nodes.
+///
+/// The set is cleared in constant time by incrementing the generation,
+/// a node is visited when it is marked with the current generation.
+struct Visited {
+    marks: Vec<u32>,
+    generation: u32,
+}
+
+impl Visited {
+    fn new(n_nodes: usize) -> Self {
+        Visited {
+            marks: vec![0; n_nodes],
+            generation: 1,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        if self.generation == 0 {
+            self.marks.iter_mut().for_each(|mark| *mark = 0);
+            self.generation = 1;
+        }
+    }
+
+    /// Mark a node as visited, returns `false` if it was already visited.
+    fn insert(&mut self, idx: u32) -> bool {
+        let mark = &mut self.marks[idx as usize];
+        if *mark == self.generation {
+            false
+        } else {
+            *mark = self.generation;
+            true
+        }
+    }
+}
+
+/// Cosine distance of normalized embeddings.
+fn distance(u: ArrayView1<f32>, v: ArrayView1<f32>) -> f32 {
+    1. - dot(u, v)
+}
+
+fn read_u32<R>(read: &mut R) -> Result<u32>
+where
+    R: Read,
+{
+    let mut buf = [0u8; 4];
+    read.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ndarray::{Array2, Axis};
+    use ndarray_rand::rand_distr::Normal;
+    use ndarray_rand::RandomExt;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::HnswIndex;
+    use crate::vec_simd::{dot, l2_normalize};
+
+    fn random_embeds(n: usize, dims: usize) -> Array2<f32> {
+        let mut rng = XorShiftRng::seed_from_u64(42);
+        let mut embeds = Array2::random_using((n, dims), Normal::new(0., 1.).unwrap(), &mut rng);
+        for embed in embeds.outer_iter_mut() {
+            l2_normalize(embed);
+        }
+        embeds
+    }
+
+    #[test]
+    fn hnsw_finds_nearest_neighbors() {
+        let embeds = random_embeds(500, 16);
+        let index = HnswIndex::build(embeds.view(), 8, 50);
+        assert_eq!(index.len(), 500);
+
+        let mut n_found = 0;
+        for query in embeds.outer_iter().take(50) {
+            let mut exact = embeds
+                .outer_iter()
+                .map(|embed| dot(query, embed))
+                .enumerate()
+                .collect::<Vec<_>>();
+            exact.sort_by(|(_, s1), (_, s2)| s2.partial_cmp(s1).unwrap());
+
+            let approx = index.search(embeds.view(), query, 10, 50);
+            assert_eq!(approx.len(), 10);
+            n_found += exact
+                .iter()
+                .take(10)
+                .filter(|(idx, _)| approx.iter().any(|(approx_idx, _)| approx_idx == idx))
+                .count();
+        }
+
+        // Recall@10 of the approximate search.
+        assert!(n_found >= 450, "Recall too low: {}/500", n_found);
+    }
+
+    #[test]
+    fn hnsw_search_finds_query() {
+        let embeds = random_embeds(100, 8);
+        let index = HnswIndex::build(embeds.view(), 8, 50);
+        for (idx, query) in embeds.axis_iter(Axis(0)).enumerate() {
+            let nearest = index.search(embeds.view(), query, 1, 50);
+            assert_eq!(nearest[0].0, idx);
+            assert!((nearest[0].1 - 1.).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn hnsw_write_read_roundtrip() {
+        let embeds = random_embeds(50, 4);
+        let index = HnswIndex::build(embeds.view(), 4, 10);
+
+        let mut data = Vec::new();
+        index.write(&mut data).unwrap();
+        let read_index = HnswIndex::read(&mut Cursor::new(data)).unwrap();
+        assert_eq!(index, read_index);
+    }
+
+    #[test]
+    fn hnsw_empty() {
+        let embeds = Array2::zeros((0, 4));
+        let index = HnswIndex::build(embeds.view(), 4, 10);
+        assert!(index.is_empty());
+        assert!(index
+            .search(embeds.view(), ndarray::Array1::zeros(4).view(), 1, 10)
+            .is_empty());
+    }
+}
@@ -2,7 +2,7 @@ use std::cell::UnsafeCell;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 
-use ndarray::{Array, ArrayView, ArrayViewMut, Axis, Dimension, Ix, Ix2, RemoveAxis};
+use ndarray::{Array, ArrayView, ArrayViewMut, Axis, Dimension, Ix, Ix1, Ix2, RemoveAxis};
 
 /// Array for Hogwild parallel optimization.
 ///
@@ -66,6 +66,12 @@ where
     pub fn view(&self) -> ArrayView<A, D> {
         self.as_ref().view()
     }
+
+    /// Get a mutable view of the Hogwild array.
+    #[inline]
+    pub fn view_mut(&mut self) -> ArrayViewMut<A, D> {
+        self.as_mut().view_mut()
+    }
 }
 
 impl<A, D> From<Array<A, D>> for HogwildArray<A, D> {
@@ -78,6 +84,9 @@ unsafe impl<A, D> Send for HogwildArray<A, D> {}
 
 unsafe impl<A, D> Sync for HogwildArray<A, D> {}
 
+/// One-dimensional Hogwild array.
+pub type HogwildArray1<A> = HogwildArray<A, Ix1>;
+
 /// Two-dimensional Hogwild array.
 pub type HogwildArray2<A> = HogwildArray<A, Ix2>;
 
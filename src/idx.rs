@@ -1,6 +1,8 @@
-use std::iter::FusedIterator;
+use std::iter::{self, FusedIterator};
 use std::{option, slice};
 
+use rand::Rng;
+
 /// A single lookup index.
 #[derive(Copy, Clone)]
 pub struct SingleIdx {
@@ -22,6 +24,7 @@ impl SingleIdx {
 pub struct WordWithSubwordsIdx {
     word_idx: u64,
     subwords: Vec<u64>,
+    include_word: bool,
 }
 
 impl WordWithSubwordsIdx {
@@ -29,8 +32,74 @@ impl WordWithSubwordsIdx {
         WordWithSubwordsIdx {
             word_idx,
             subwords: subwords.into(),
+            include_word: true,
         }
     }
+
+    /// Set whether the word index is part of the input indices.
+    ///
+    /// If `false`, the input indices only consist of the subword indices,
+    /// unless there are no subword indices. The word index is still
+    /// available through `word_idx`.
+    pub fn with_word(mut self, include_word: bool) -> Self {
+        self.include_word = include_word;
+        self
+    }
+
+    /// Returns `true` if the word index is part of the input indices.
+    fn includes_word(&self) -> bool {
+        self.include_word || self.subwords.is_empty()
+    }
+}
+
+/// A bag of lookup indices.
+///
+/// This index type is used for inputs that consist of multiple words,
+/// such as the context window in the continuous bag-of-words model. The
+/// bag stores the indices of all words and their subword units.
+#[derive(Clone, Default)]
+pub struct BagOfWordsIdx {
+    indices: Vec<u64>,
+}
+
+impl BagOfWordsIdx {
+    pub fn new(indices: impl Into<Vec<u64>>) -> Self {
+        BagOfWordsIdx {
+            indices: indices.into(),
+        }
+    }
+
+    /// Add a single index to the bag.
+    pub fn push(&mut self, idx: u64) {
+        self.indices.push(idx);
+    }
+
+    /// Add the indices of a word to the bag.
+    pub fn extend<'a, I>(&mut self, idx: &'a I)
+    where
+        &'a I: IntoIterator<Item = u64>,
+    {
+        self.indices.extend(idx);
+    }
+
+    /// Return the number of indices.
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Return whether this is empty.
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a BagOfWordsIdx {
+    type Item = u64;
+    type IntoIter = iter::Copied<slice::Iter<'a, u64>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.indices.iter().copied()
+    }
 }
 
 /// Vocabulary indexing trait.
@@ -50,6 +119,23 @@ pub trait WordIdx: Clone {
     fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// Randomly drop subword indices.
+    ///
+    /// Every subword index is dropped with probability `p`. The word index
+    /// is always retained.
+    fn drop_subwords<R>(&mut self, rng: &mut R, p: f32)
+    where
+        R: Rng;
+
+    /// Add subword indices.
+    ///
+    /// This is used to add indices of other input features, such as word
+    /// n-grams, to the index. Index types without subwords ignore the
+    /// indices.
+    fn extend_subwords<I>(&mut self, indices: I)
+    where
+        I: IntoIterator<Item = u64>;
 }
 
 impl WordIdx for SingleIdx {
@@ -64,6 +150,18 @@ impl WordIdx for SingleIdx {
     fn len(&self) -> usize {
         1
     }
+
+    fn drop_subwords<R>(&mut self, _rng: &mut R, _p: f32)
+    where
+        R: Rng,
+    {
+    }
+
+    fn extend_subwords<I>(&mut self, _indices: I)
+    where
+        I: IntoIterator<Item = u64>,
+    {
+    }
 }
 
 impl<'a> IntoIterator for &'a SingleIdx {
@@ -84,11 +182,28 @@ impl WordIdx for WordWithSubwordsIdx {
         WordWithSubwordsIdx {
             word_idx,
             subwords: Vec::new(),
+            include_word: true,
         }
     }
 
     fn len(&self) -> usize {
-        1 + self.subwords.len()
+        self.includes_word() as usize + self.subwords.len()
+    }
+
+    fn drop_subwords<R>(&mut self, rng: &mut R, p: f32)
+    where
+        R: Rng,
+    {
+        if p > 0. {
+            self.subwords.retain(|_| rng.gen_range(0f32..1f32) >= p);
+        }
+    }
+
+    fn extend_subwords<I>(&mut self, indices: I)
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        self.subwords.extend(indices);
     }
 }
 
@@ -98,7 +213,7 @@ impl<'a> IntoIterator for &'a WordWithSubwordsIdx {
 
     fn into_iter(self) -> Self::IntoIter {
         IdxIter {
-            word_idx: Some(self.word_idx),
+            word_idx: Some(self.word_idx).filter(|_| self.includes_word()),
             subwords: self.subwords.iter(),
         }
     }
@@ -126,7 +241,10 @@ impl<'a> FusedIterator for IdxIter<'a> {}
 
 #[cfg(test)]
 mod test {
-    use crate::idx::{SingleIdx, WordIdx, WordWithSubwordsIdx};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use crate::idx::{BagOfWordsIdx, SingleIdx, WordIdx, WordWithSubwordsIdx};
 
     #[test]
     fn test_idx_iter() {
@@ -143,4 +261,59 @@ mod test {
         assert_eq!(0, idx_iter.next().unwrap());
         assert_eq!(0, single.word_idx());
     }
+
+    #[test]
+    fn test_bag_of_words_idx() {
+        let mut bag = BagOfWordsIdx::default();
+        assert!(bag.is_empty());
+        bag.extend(&WordWithSubwordsIdx::new(0, vec![24, 4]));
+        bag.extend(&SingleIdx::new(3));
+        assert_eq!(4, bag.len());
+        assert_eq!(vec![24, 4, 0, 3], (&bag).into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_drop_subwords() {
+        let mut rng = XorShiftRng::seed_from_u64(42);
+
+        let mut with_subwords = WordWithSubwordsIdx::new(0, vec![24, 4, 42]);
+        with_subwords.drop_subwords(&mut rng, 0.0);
+        assert_eq!(
+            (&with_subwords).into_iter().collect::<Vec<_>>(),
+            [24, 4, 42, 0]
+        );
+
+        with_subwords.drop_subwords(&mut rng, 1.0);
+        assert_eq!((&with_subwords).into_iter().collect::<Vec<_>>(), [0]);
+        assert_eq!(0, with_subwords.word_idx());
+    }
+
+    #[test]
+    fn test_extend_subwords() {
+        let mut with_subwords = WordWithSubwordsIdx::new(0, vec![24]);
+        with_subwords.extend_subwords(vec![4, 42]);
+        assert_eq!(
+            (&with_subwords).into_iter().collect::<Vec<_>>(),
+            [24, 4, 42, 0]
+        );
+
+        let mut single = SingleIdx::new(0);
+        single.extend_subwords(vec![4, 42]);
+        assert_eq!((&single).into_iter().collect::<Vec<_>>(), [0]);
+    }
+
+    #[test]
+    fn test_without_word() {
+        let mut rng = XorShiftRng::seed_from_u64(42);
+
+        let mut without_word = WordWithSubwordsIdx::new(0, vec![24, 4]).with_word(false);
+        assert_eq!(2, without_word.len());
+        assert_eq!((&without_word).into_iter().collect::<Vec<_>>(), [24, 4]);
+        assert_eq!(0, without_word.word_idx());
+
+        // The word index is used when there are no subword indices.
+        without_word.drop_subwords(&mut rng, 1.0);
+        assert_eq!(1, without_word.len());
+        assert_eq!((&without_word).into_iter().collect::<Vec<_>>(), [0]);
+    }
 }
@@ -1,12 +1,43 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::fs::File;
-use std::io::{self, BufRead, Lines, Read, Seek, SeekFrom, Write};
+use std::io::{self, BufRead, BufReader, Lines, Read, Seek, SeekFrom, Write};
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Instant;
 
-use anyhow::{anyhow, Context, Error, Result};
+use anyhow::{anyhow, ensure, Context, Error, Result};
 use chrono::{DateTime, Local};
 use indicatif::{ProgressBar, ProgressStyle};
 use memmap::{Mmap, MmapOptions};
+use rand::Rng;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tempfile::TempPath;
+
+use crate::{
+    Dedup, LanguageFilter, MultiwordLexicon, NoiseFilters, QuantizerConfig, TokenNormalization,
+    TokenRules,
+};
+
+#[cfg(feature = "parquet-corpus")]
+mod parquet_text;
+#[cfg(feature = "parquet-corpus")]
+pub use parquet_text::ParquetText;
+
+#[cfg(feature = "remote-corpus")]
+mod remote;
+#[cfg(feature = "remote-corpus")]
+use remote::remote_len;
+#[cfg(feature = "remote-corpus")]
+pub use remote::RemoteRead;
 
 pub struct FileProgress {
     inner: File,
@@ -17,9 +48,18 @@ pub struct FileProgress {
 pub enum EmbeddingFormat {
     FastText,
     FinalFusion,
+    FinalFusionQuantized(QuantizerConfig),
     Word2Vec,
     Text,
     TextDims,
+    Floret,
+    Gensim,
+    #[cfg(feature = "magnitude-output")]
+    Magnitude,
+    Npz,
+    PgVector,
+    #[cfg(feature = "sqlite-output")]
+    Sqlite,
 }
 
 impl TryFrom<&str> for EmbeddingFormat {
@@ -31,9 +71,26 @@ impl TryFrom<&str> for EmbeddingFormat {
         match format {
             "fasttext" => Ok(FastText),
             "finalfusion" => Ok(FinalFusion),
+            "finalfusion-quantized" => Ok(FinalFusionQuantized(QuantizerConfig::default())),
             "word2vec" => Ok(Word2Vec),
-            "text" => Ok(Text),
+            "text" | "glove" => Ok(Text),
             "textdims" => Ok(TextDims),
+            "floret" => Ok(Floret),
+            "gensim" => Ok(Gensim),
+            #[cfg(feature = "magnitude-output")]
+            "magnitude" => Ok(Magnitude),
+            #[cfg(not(feature = "magnitude-output"))]
+            "magnitude" => Err(anyhow!(
+                "Magnitude output is not supported, compile with the magnitude-output feature"
+            )),
+            "npz" => Ok(Npz),
+            "pgvector" => Ok(PgVector),
+            #[cfg(feature = "sqlite-output")]
+            "sqlite" => Ok(Sqlite),
+            #[cfg(not(feature = "sqlite-output"))]
+            "sqlite" => Err(anyhow!(
+                "SQLite output is not supported, compile with the sqlite-output feature"
+            )),
             unknown => Err(anyhow!("Unknown embedding format: {}", unknown)),
         }
     }
@@ -74,6 +131,129 @@ impl Drop for FileProgress {
     }
 }
 
+/// Corpus formats.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CorpusFormat {
+    /// One sentence per line, with tokens separated by whitespace.
+    Text,
+
+    /// CoNLL-U, sentences consist of the forms of the syntactic words.
+    Conllu,
+
+    /// JSON Lines, one JSON object per line with the text in a field.
+    Jsonl,
+
+    /// Parquet, the texts are in a column of the file.
+    ///
+    /// Reading Parquet files requires the `parquet-corpus` feature.
+    Parquet,
+
+    /// Vertical, one token per line with empty lines between sentences.
+    Vertical,
+}
+
+impl CorpusFormat {
+    /// Get the sentence separator of the format.
+    pub fn separator(self) -> SentenceSeparator {
+        match self {
+            CorpusFormat::Text | CorpusFormat::Jsonl | CorpusFormat::Parquet => {
+                SentenceSeparator::Line
+            }
+            CorpusFormat::Conllu | CorpusFormat::Vertical => SentenceSeparator::EmptyLine,
+        }
+    }
+}
+
+impl Default for CorpusFormat {
+    fn default() -> Self {
+        CorpusFormat::Text
+    }
+}
+
+impl TryFrom<&str> for CorpusFormat {
+    type Error = Error;
+
+    fn try_from(format: &str) -> Result<Self> {
+        match format {
+            "conllu" => Ok(CorpusFormat::Conllu),
+            "jsonl" => Ok(CorpusFormat::Jsonl),
+            "parquet" => Ok(CorpusFormat::Parquet),
+            "text" => Ok(CorpusFormat::Text),
+            "vertical" => Ok(CorpusFormat::Vertical),
+            unknown => Err(anyhow!("Unknown corpus format: {}", unknown)),
+        }
+    }
+}
+
+/// Devices for training.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Device {
+    /// Train on the CPU.
+    Cpu,
+
+    /// Run the batch operations on the GPU.
+    ///
+    /// Training on the GPU requires the `gpu` feature.
+    Gpu,
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Device::Cpu
+    }
+}
+
+impl TryFrom<&str> for Device {
+    type Error = Error;
+
+    fn try_from(device: &str) -> Result<Self> {
+        match device {
+            "cpu" => Ok(Device::Cpu),
+            "gpu" => Ok(Device::Gpu),
+            unknown => Err(anyhow!("Unknown device: {}", unknown)),
+        }
+    }
+}
+
+/// Tokenizers for raw text.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tokenizer {
+    /// Split tokens on whitespace, the corpus is pre-tokenized.
+    Whitespace,
+
+    /// Split tokens on whitespace and split off punctuation.
+    ///
+    /// Punctuation characters become separate tokens, unless they
+    /// occur between two alphanumeric characters (e.g. *don't*,
+    /// *e-mail*, or *3.14*).
+    Punctuation,
+}
+
+impl Tokenizer {
+    /// Split a line into tokens.
+    pub fn tokenize(self, line: &str) -> Vec<String> {
+        match self {
+            Tokenizer::Whitespace => whitespace_tokenize(line),
+            Tokenizer::Punctuation => punctuation_tokenize(line),
+        }
+    }
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Tokenizer::Whitespace
+    }
+}
+
+/// The default field that contains the text of a JSON Lines record.
+pub const DEFAULT_TEXT_FIELD: &str = "text";
+
+/// The default field that contains the weight of a JSON Lines record.
+pub const DEFAULT_WEIGHT_FIELD: &str = "weight";
+
 /// Sentence iterator.
 ///
 /// This iterator consumes a reader with tokenized sentences:
@@ -83,9 +263,24 @@ impl Drop for FileProgress {
 ///
 /// It produces `Vec`s with the tokens, adding an end-of-sentence marker
 /// to the end of the sentence. Lines that are empty or only consist of
-/// whitespace are discarded.
+/// whitespace are discarded. Sentences of which all tokens are dropped
+/// by the token rules are discarded as well.
+///
+/// CoNLL-U and JSON Lines corpora can be read as well, see
+/// `with_format`. Raw text can be tokenized while reading, see
+/// `with_tokenizer`. Sentences can have weights, see `with_weights`.
 pub struct SentenceIterator<R> {
     lines: Lines<R>,
+    format: CorpusFormat,
+    text_field: String,
+    weights: bool,
+    weight_field: String,
+    tokenizer: Tokenizer,
+    noise: NoiseFilters,
+    language: Option<LanguageFilter>,
+    normalization: TokenNormalization,
+    rules: Arc<TokenRules>,
+    multiword: Arc<MultiwordLexicon>,
 }
 
 impl<R> SentenceIterator<R>
@@ -95,8 +290,120 @@ where
     pub fn new(read: R) -> Self {
         SentenceIterator {
             lines: read.lines(),
+            format: CorpusFormat::Text,
+            text_field: DEFAULT_TEXT_FIELD.to_owned(),
+            weights: false,
+            weight_field: DEFAULT_WEIGHT_FIELD.to_owned(),
+            tokenizer: Tokenizer::default(),
+            noise: NoiseFilters::default(),
+            language: None,
+            normalization: TokenNormalization::default(),
+            rules: Arc::new(TokenRules::default()),
+            multiword: Arc::new(MultiwordLexicon::default()),
         }
     }
+
+    /// Set the format of the corpus.
+    ///
+    /// In CoNLL-U corpora, a sentence consists of the forms of its
+    /// syntactic words. Comments, multiword tokens, and empty nodes are
+    /// skipped.
+    ///
+    /// In vertical corpora, a sentence consists of the first
+    /// tab-separated column of its lines. Sentences are separated by
+    /// empty lines or `</s>` tags, other structural tags, such as
+    /// `<doc id="1">`, are skipped.
+    pub fn with_format(mut self, format: CorpusFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the field that contains the text of a JSON Lines record.
+    ///
+    /// The default field is `text`.
+    pub fn with_text_field(mut self, text_field: impl Into<String>) -> Self {
+        self.text_field = text_field.into();
+        self
+    }
+
+    /// Read sentence weights from the corpus.
+    ///
+    /// In text corpora, every line starts with the weight of the
+    /// sentence, followed by a tab. In JSON Lines corpora, the weight
+    /// is in the field set by `with_weight_field`. Records without
+    /// this field have weight 1. Sentences in other formats always
+    /// have weight 1.
+    ///
+    /// Use `weighted` to get the weights of the sentences.
+    pub fn with_weights(mut self, weights: bool) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Set the field that contains the weight of a JSON Lines record.
+    ///
+    /// The default field is `weight`.
+    pub fn with_weight_field(mut self, weight_field: impl Into<String>) -> Self {
+        self.weight_field = weight_field.into();
+        self
+    }
+
+    /// Get an iterator over the sentences and their weights.
+    pub fn weighted(self) -> WeightedSentences<R> {
+        WeightedSentences(self)
+    }
+
+    /// Set the tokenizer for lines of text.
+    ///
+    /// The tokenizer is not used for CoNLL-U and vertical corpora,
+    /// which are already tokenized.
+    pub fn with_tokenizer(mut self, tokenizer: Tokenizer) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Drop or replace noise tokens, such as URLs.
+    ///
+    /// In text, JSON Lines, and Parquet corpora, noise tokens are
+    /// recognized before tokenization, see `NoiseFilters::tokenize`.
+    pub fn with_noise(mut self, noise: NoiseFilters) -> Self {
+        self.noise = noise;
+        self
+    }
+
+    /// Skip sentences that are not in the language of `language`.
+    ///
+    /// The language is identified after the noise filters are applied
+    /// and before normalization. If the filter
+    /// has a language field, the language of JSON Lines records is read
+    /// from that field instead.
+    pub fn with_language(mut self, language: LanguageFilter) -> Self {
+        self.language = Some(language);
+        self
+    }
+
+    /// Apply normalization to the tokens.
+    pub fn with_normalization(mut self, normalization: TokenNormalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Apply token rules to the tokens.
+    ///
+    /// The rules are applied after normalization.
+    pub fn with_rules(mut self, rules: Arc<TokenRules>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Merge multi-word expressions into single tokens.
+    ///
+    /// Expressions are merged after normalization and the token rules
+    /// are applied.
+    pub fn with_multiword(mut self, multiword: Arc<MultiwordLexicon>) -> Self {
+        self.multiword = multiword;
+        self
+    }
 }
 
 impl<R> Iterator for SentenceIterator<R>
@@ -106,6 +413,80 @@ where
     type Item = Result<Vec<String>>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        self.next_weighted()
+            .map(|sentence| sentence.map(|(_, sentence)| sentence))
+    }
+}
+
+/// Iterator over sentences and their weights.
+///
+/// See `SentenceIterator::with_weights`.
+pub struct WeightedSentences<R>(SentenceIterator<R>);
+
+impl<R> Iterator for WeightedSentences<R>
+where
+    R: BufRead,
+{
+    type Item = Result<(f32, Vec<String>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_weighted()
+    }
+}
+
+impl<R> SentenceIterator<R>
+where
+    R: BufRead,
+{
+    /// Get the next sentence with its weight.
+    fn next_weighted(&mut self) -> Option<Result<(f32, Vec<String>)>> {
+        loop {
+            let tokens = match self.format {
+                // Parquet texts are read as lines, see `open_parquet`.
+                CorpusFormat::Text | CorpusFormat::Parquet => self.next_line(),
+                CorpusFormat::Conllu => self.next_conllu().map(|tokens| tokens.map(|t| (1., t))),
+                CorpusFormat::Jsonl => self.next_json(),
+                CorpusFormat::Vertical => {
+                    self.next_vertical().map(|tokens| tokens.map(|t| (1., t)))
+                }
+            };
+            let (weight, mut tokens) = match tokens? {
+                Ok(tokens) => tokens,
+                Err(err) => return Some(Err(err)),
+            };
+
+            // Noise in other formats is filtered during tokenization.
+            if matches!(self.format, CorpusFormat::Conllu | CorpusFormat::Vertical) {
+                tokens = self.noise.filter_tokens(tokens);
+            }
+
+            // The language of records with a language field is checked
+            // when they are read.
+            if let Some(language) = self.language.as_ref().filter(|l| l.field().is_none()) {
+                if !language.accepts(&tokens) {
+                    continue;
+                }
+            }
+
+            let normalization = self.normalization;
+            let rules = &self.rules;
+            let sentence: Vec<_> = tokens
+                .into_iter()
+                .filter_map(|token| {
+                    let token = normalization.normalize(&token);
+                    rules.apply(&token).map(|token| token.into_owned())
+                })
+                .collect();
+
+            // Skip sentences of which all tokens were dropped.
+            if !sentence.is_empty() {
+                return Some(Ok((weight, self.multiword.merge(sentence))));
+            }
+        }
+    }
+
+    /// Get the tokens of the next non-empty line.
+    fn next_line(&mut self) -> Option<Result<(f32, Vec<String>)>> {
         for line in &mut self.lines {
             let line = match line {
                 Ok(ref line) => line.trim(),
@@ -113,113 +494,2283 @@ where
             };
 
             // Skip empty lines.
-            if !line.is_empty() {
-                return Some(Ok(whitespace_tokenize(line)));
+            if line.is_empty() {
+                continue;
             }
+
+            if !self.weights {
+                return Some(Ok((1., self.noise.tokenize(line, self.tokenizer))));
+            }
+
+            let (weight, text) = match line.find('\t') {
+                Some(idx) => (&line[..idx], &line[idx + 1..]),
+                None => (line, ""),
+            };
+            let weight = match parse_weight(weight) {
+                Ok(weight) => weight,
+                Err(err) => return Some(Err(err).context(format!("Invalid line: {}", line))),
+            };
+
+            return Some(Ok((weight, self.noise.tokenize(text, self.tokenizer))));
         }
 
         None
     }
-}
 
-/// Get thread-specific data.
-///
-/// This function will return a memory map of the corpus data. The initial
-/// starting position for the given thread is also returned. This starting
-/// Position will always be the beginning of a sentence.
-pub fn thread_data_text(f: &File, thread: usize, n_threads: usize) -> Result<(Mmap, usize)> {
-    assert!(
-        thread < n_threads,
-        "Thread {} out of index [0, {})",
-        thread,
-        n_threads
-    );
+    /// Get the tokens of the text of the next JSON Lines record.
+    fn next_json(&mut self) -> Option<Result<(f32, Vec<String>)>> {
+        for line in &mut self.lines {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
 
-    let size = f.metadata().context("Cannot get file metadata")?.len();
-    let chunk_size = size as usize / n_threads;
+            // Skip empty lines.
+            if line.trim().is_empty() {
+                continue;
+            }
 
-    let mmap = unsafe { MmapOptions::new().map(&f)? };
+            let record: serde_json::Value = match serde_json::from_str(&line) {
+                Ok(record) => record,
+                Err(err) => {
+                    return Some(Err(err).context(format!("Invalid JSON Lines record: {}", line)))
+                }
+            };
 
-    if thread == 0 {
-        return Ok((mmap, 0));
+            let text = match record.get(&self.text_field) {
+                Some(serde_json::Value::String(text)) => text,
+                Some(serde_json::Value::Null) => continue,
+                Some(_) => {
+                    return Some(Err(anyhow!(
+                        "Field '{}' is not a string in record: {}",
+                        self.text_field,
+                        line
+                    )))
+                }
+                None => {
+                    return Some(Err(anyhow!(
+                        "Record does not have a '{}' field: {}",
+                        self.text_field,
+                        line
+                    )))
+                }
+            };
+
+            if let Some((language, field)) = self
+                .language
+                .as_ref()
+                .and_then(|language| language.field().map(|field| (language, field)))
+            {
+                match record.get(field) {
+                    Some(serde_json::Value::String(tag)) if language.accepts_tag(tag) => (),
+                    _ => continue,
+                }
+            }
+
+            let weight = if self.weights {
+                match record.get(&self.weight_field) {
+                    None | Some(serde_json::Value::Null) => 1.,
+                    Some(weight) => match weight.as_f64().map(|weight| weight as f32) {
+                        Some(weight) if weight.is_finite() && weight >= 0. => weight,
+                        _ => {
+                            return Some(Err(anyhow!(
+                                "Field '{}' is not a non-negative number in record: {}",
+                                self.weight_field,
+                                line
+                            )))
+                        }
+                    },
+                }
+            } else {
+                1.
+            };
+
+            return Some(Ok((weight, self.noise.tokenize(text, self.tokenizer))));
+        }
+
+        None
     }
 
-    let mut start = thread * chunk_size;
-    while start < mmap.len() {
-        let next = mmap[start];
-        start += 1;
-        if next == b'\n' {
-            break;
+    /// Get the forms of the next CoNLL-U sentence.
+    fn next_conllu(&mut self) -> Option<Result<Vec<String>>> {
+        let mut forms = Vec::new();
+        for line in &mut self.lines {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
+            let line = line.trim_end_matches('\r');
+
+            if line.trim().is_empty() {
+                if forms.is_empty() {
+                    continue;
+                }
+
+                return Some(Ok(forms));
+            }
+
+            if line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split('\t');
+            if !is_conllu_word(fields.next().unwrap()) {
+                continue;
+            }
+
+            match fields.next() {
+                Some(form) => forms.push(form.to_owned()),
+                None => return Some(Err(anyhow!("Invalid CoNLL-U line: {}", line))),
+            }
+        }
+
+        if forms.is_empty() {
+            None
+        } else {
+            Some(Ok(forms))
         }
     }
 
-    Ok((mmap, start))
+    /// Get the tokens of the next vertical sentence.
+    fn next_vertical(&mut self) -> Option<Result<Vec<String>>> {
+        let mut tokens = Vec::new();
+        for line in &mut self.lines {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
+            let line = line.trim_end_matches('\r');
+
+            if line.trim().is_empty() || line == "</s>" {
+                if tokens.is_empty() {
+                    continue;
+                }
+
+                return Some(Ok(tokens));
+            }
+
+            if is_structural_tag(line) {
+                continue;
+            }
+
+            let token = line.split('\t').next().unwrap().trim();
+            if !token.is_empty() {
+                tokens.push(token.to_owned());
+            }
+        }
+
+        if tokens.is_empty() {
+            None
+        } else {
+            Some(Ok(tokens))
+        }
+    }
 }
 
-/// Get thread-specific data for a CoNLL-U corpus.
+/// Check whether a line of a vertical corpus is a structural tag.
 ///
-/// This function will return a memory map of the corpus data. The initial
-/// starting position for the given thread is also returned. This starting
-/// Position will always be the beginning of a sentence.
-pub fn thread_data_conllu(f: &File, thread: usize, n_threads: usize) -> Result<(Mmap, usize)> {
-    assert!(
-        thread < n_threads,
-        "Thread {} out of index [0, {})",
-        thread,
-        n_threads
+/// Structural tags mark e.g. documents (`<doc>`), paragraphs (`<p>`), and
+/// sentences (`<s>`). A tag has a name and may have attributes.
+fn is_structural_tag(line: &str) -> bool {
+    if !line.starts_with('<') || !line.ends_with('>') || line.contains('\t') {
+        return false;
+    }
+
+    let name = line[1..].trim_start_matches('/');
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+}
+
+/// Parse a sentence weight, which should be a non-negative number.
+fn parse_weight(weight: &str) -> Result<f32> {
+    let weight: f32 = weight
+        .parse()
+        .with_context(|| format!("Cannot parse sentence weight: {}", weight))?;
+    ensure!(
+        weight.is_finite() && weight >= 0.,
+        "Sentence weight should be a non-negative number, was: {}",
+        weight
     );
+    Ok(weight)
+}
 
-    let size = f.metadata().context("Cannot get file metadata")?.len();
-    let chunk_size = size as usize / n_threads;
+/// Check whether a CoNLL-U identifier is the identifier of a syntactic word.
+///
+/// Multiword tokens have range identifiers (e.g. *1-2*) and empty nodes
+/// have decimal identifiers (e.g. *1.1*).
+fn is_conllu_word(id: &str) -> bool {
+    !id.contains(&['-', '.'][..])
+}
 
-    let mmap = unsafe { MmapOptions::new().map(&f)? };
+/// Reader that filters CoNLL-U data.
+///
+/// Multiword tokens and empty nodes are removed and the enhanced
+/// dependency column is cleared, so that only the basic dependency tree
+/// of a sentence remains.
+pub struct ConlluFilter<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+}
 
-    if thread == 0 {
-        return Ok((mmap, 0));
+impl<R> ConlluFilter<R>
+where
+    R: BufRead,
+{
+    /// Construct a filter of the CoNLL-U data in `inner`.
+    pub fn new(inner: R) -> Self {
+        ConlluFilter {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+        }
     }
 
-    let mut start = thread * chunk_size;
-    while start < mmap.len() - 1 {
-        let next = mmap[start];
-        start += 1;
-        if next == b'\n' && mmap[start] == b'\n' {
-            start += 1;
-            break;
+    /// Read the next line that is retained into the buffer.
+    ///
+    /// Returns `false` if there are no lines left.
+    fn read_line(&mut self) -> io::Result<bool> {
+        loop {
+            self.buf.clear();
+            self.pos = 0;
+
+            if self.inner.read_until(b'\n', &mut self.buf)? == 0 {
+                return Ok(false);
+            }
+
+            if self.buf.starts_with(b"#") || self.buf.iter().all(u8::is_ascii_whitespace) {
+                return Ok(true);
+            }
+
+            let field_ends = self
+                .buf
+                .iter()
+                .enumerate()
+                .filter(|&(_, &b)| b == b'\t' || b == b'\r' || b == b'\n')
+                .map(|(idx, _)| idx)
+                .chain(iter::once(self.buf.len()))
+                .collect::<Vec<_>>();
+            let id = &self.buf[..field_ends[0]];
+            if !str::from_utf8(id).map_or(true, is_conllu_word) {
+                continue;
+            }
+
+            // Clear the enhanced dependencies, which can refer to empty nodes.
+            if let (Some(&prev_end), Some(&deps_end)) = (field_ends.get(7), field_ends.get(8)) {
+                let deps = prev_end + 1..deps_end;
+                if self.buf[prev_end] == b'\t' && self.buf[deps.clone()] != b"_"[..] {
+                    self.buf.splice(deps, b"_".iter().copied());
+                }
+            }
+
+            return Ok(true);
         }
     }
+}
 
-    Ok((mmap, start))
+impl<R> Read for ConlluFilter<R>
+where
+    R: BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n_read = self.fill_buf()?.read(buf)?;
+        self.consume(n_read);
+        Ok(n_read)
+    }
 }
 
-/// Meta information about training.
-#[derive(Clone, Serialize)]
-pub struct TrainInfo {
-    corpus: String,
-    output: String,
-    n_threads: usize,
-    start_datetime: String,
-    end_datetime: Option<String>,
+impl<R> BufRead for ConlluFilter<R>
+where
+    R: BufRead,
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            self.read_line()?;
+        }
+
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
 }
 
-impl TrainInfo {
-    /// Construct new TrainInfo.
+/// Iterator that shuffles the items of another iterator in a buffer.
+///
+/// The buffer is first filled with `capacity` items. Then, each item
+/// that is returned is picked randomly from the buffer and replaced by
+/// the next item of the inner iterator. So, items are shuffled within
+/// a window of roughly `capacity` items, without reading the complete
+/// inner iterator in memory.
+pub struct ShuffleBuffer<I, R>
+where
+    I: Iterator,
+{
+    inner: I,
+    buffer: Vec<I::Item>,
+    capacity: usize,
+    rng: R,
+}
+
+impl<I, R> ShuffleBuffer<I, R>
+where
+    I: Iterator,
+    R: Rng,
+{
+    /// Shuffle the items of `inner` in a buffer of `capacity` items.
     ///
-    /// Constructs TrainInfo with `start_datetime` set to the current datetime. `end_datetime` is
-    /// set to `None` and can be set through `TrainInfo::set_end`.
-    pub fn new(corpus: String, output: String, n_threads: usize) -> Self {
-        let start_datetime: DateTime<Local> = Local::now();
-        TrainInfo {
+    /// A buffer with a capacity of one item does not change the order
+    /// of the items.
+    pub fn new(inner: I, capacity: usize, rng: R) -> Self {
+        assert!(capacity > 0, "Shuffle buffer capacity should be positive");
+
+        ShuffleBuffer {
+            inner,
+            buffer: Vec::with_capacity(capacity),
+            capacity,
+            rng,
+        }
+    }
+}
+
+impl<I, R> Iterator for ShuffleBuffer<I, R>
+where
+    I: Iterator,
+    R: Rng,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffer.len() < self.capacity {
+            match self.inner.next() {
+                Some(item) => self.buffer.push(item),
+                None => break,
+            }
+        }
+
+        if self.buffer.is_empty() {
+            return None;
+        }
+
+        let idx = self.rng.gen_range(0..self.buffer.len());
+        Some(self.buffer.swap_remove(idx))
+    }
+}
+
+/// Get thread-specific data.
+///
+/// This function will return a memory map of the corpus data. The initial
+/// starting position for the given thread is also returned. This starting
+/// Position will always be the beginning of a sentence.
+pub fn thread_data_text(f: &File, thread: usize, n_threads: usize) -> Result<(Mmap, usize)> {
+    thread_data(f, thread, n_threads, SentenceSeparator::Line)
+}
+
+/// Get thread-specific data for a CoNLL-U corpus.
+///
+/// This function will return a memory map of the corpus data. The initial
+/// starting position for the given thread is also returned. This starting
+/// Position will always be the beginning of a sentence.
+pub fn thread_data_conllu(f: &File, thread: usize, n_threads: usize) -> Result<(Mmap, usize)> {
+    thread_data(f, thread, n_threads, SentenceSeparator::EmptyLine)
+}
+
+fn thread_data(
+    f: &File,
+    thread: usize,
+    n_threads: usize,
+    separator: SentenceSeparator,
+) -> Result<(Mmap, usize)> {
+    assert!(
+        thread < n_threads,
+        "Thread {} out of index [0, {})",
+        thread,
+        n_threads
+    );
+
+    let mmap = unsafe { MmapOptions::new().map(&f)? };
+    let start = thread_start(&mmap, thread, n_threads, separator);
+
+    Ok((mmap, start))
+}
+
+/// Get the byte range of the sentences of a thread.
+///
+/// The data is divided in `n_threads` chunks of roughly equal size. The
+/// boundaries of the chunks are moved to the start of the next sentence,
+/// so that every sentence is in exactly one range.
+pub fn thread_range(
+    data: &[u8],
+    thread: usize,
+    n_threads: usize,
+    separator: SentenceSeparator,
+) -> Range<usize> {
+    let start = thread_start(data, thread, n_threads, separator);
+    let end = if thread + 1 == n_threads {
+        data.len()
+    } else {
+        thread_start(data, thread + 1, n_threads, separator)
+    };
+
+    start..end.max(start)
+}
+
+/// Get the position of the first sentence of a thread.
+fn thread_start(
+    data: &[u8],
+    thread: usize,
+    n_threads: usize,
+    separator: SentenceSeparator,
+) -> usize {
+    if thread == 0 {
+        return 0;
+    }
+
+    let chunk_size = data.len() / n_threads;
+    let mut start = thread * chunk_size;
+    match separator {
+        SentenceSeparator::Line => {
+            while start < data.len() {
+                let next = data[start];
+                start += 1;
+                if next == b'\n' {
+                    break;
+                }
+            }
+        }
+        SentenceSeparator::EmptyLine => {
+            while start + 1 < data.len() {
+                let next = data[start];
+                start += 1;
+                if next == b'\n' && data[start] == b'\n' {
+                    return start + 1;
+                }
+            }
+
+            // No sentence starts in the remainder of the data.
+            start = data.len();
+        }
+    }
+
+    start
+}
+
+/// Compression formats of corpora.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    Bzip2,
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Detect the compression format of a file.
+    ///
+    /// The format is detected by the extension of the file. If the
+    /// extension is not known, the format is detected by the magic bytes
+    /// at the start of the file.
+    pub fn detect(path: &Path) -> io::Result<Compression> {
+        if let Some(compression) = Self::from_extension(path) {
+            return Ok(compression);
+        }
+
+        let mut magic = Vec::with_capacity(6);
+        File::open(path)?.take(6).read_to_end(&mut magic)?;
+        Ok(Self::from_magic(&magic))
+    }
+
+    /// Get the compression format from the extension of a file.
+    ///
+    /// Returns `None` if the extension is not a known compression format.
+    pub fn from_extension(path: &Path) -> Option<Compression> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("bz2") => Some(Compression::Bzip2),
+            Some("gz") => Some(Compression::Gzip),
+            Some("xz") => Some(Compression::Xz),
+            Some("zst") => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn from_magic(magic: &[u8]) -> Compression {
+        match magic {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            [b'B', b'Z', b'h', b'1'..=b'9', ..] => Compression::Bzip2,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Compression::Xz,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Wrap a reader in a decompressor.
+    ///
+    /// Concatenated streams, as produced by e.g. parallel compressors,
+    /// are decompressed as a single stream.
+    pub fn decompress<'a, R>(self, read: R) -> io::Result<Box<dyn Read + 'a>>
+    where
+        R: Read + 'a,
+    {
+        Ok(match self {
+            Compression::None => Box::new(read),
+            Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(read)),
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(read)),
+            Compression::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(read)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(read)?),
+        })
+    }
+}
+
+/// Corpus path that reads the corpus from the standard input.
+pub const STDIN_CORPUS: &str = "-";
+
+/// Check whether a corpus path is the URL of a remote corpus.
+///
+/// Corpora can be read over HTTP(S) or from S3, see `open_remote`.
+pub fn is_remote_corpus(path: &str) -> bool {
+    ["http://", "https://", "s3://"]
+        .iter()
+        .any(|scheme| path.starts_with(scheme))
+}
+
+/// Open a remote corpus for reading.
+///
+/// See `RemoteRead` for how the corpus is read.
+#[cfg(feature = "remote-corpus")]
+pub fn open_remote(url: &str) -> Result<Box<dyn Read>> {
+    Ok(Box::new(RemoteRead::open(url)?))
+}
+
+/// Open a remote corpus for reading.
+///
+/// This version of finalfrontier is compiled without support for remote
+/// corpora, so this function always fails.
+#[cfg(not(feature = "remote-corpus"))]
+pub fn open_remote(_url: &str) -> Result<Box<dyn Read>> {
+    Err(remote_unsupported())
+}
+
+/// Open a remote corpus for reading, starting at byte `pos`.
+#[cfg(feature = "remote-corpus")]
+fn open_remote_at(url: &str, pos: u64) -> Result<Box<dyn Read>> {
+    Ok(Box::new(RemoteRead::open_at(url, pos)?))
+}
+
+#[cfg(not(feature = "remote-corpus"))]
+fn open_remote_at(_url: &str, _pos: u64) -> Result<Box<dyn Read>> {
+    Err(remote_unsupported())
+}
+
+#[cfg(not(feature = "remote-corpus"))]
+fn remote_len(_url: &str) -> Result<u64> {
+    Err(remote_unsupported())
+}
+
+#[cfg(not(feature = "remote-corpus"))]
+fn remote_unsupported() -> Error {
+    anyhow!("Remote corpora are not supported, compile with the remote-corpus feature")
+}
+
+/// Get the URL of a corpus path, if it is a remote corpus.
+fn remote_url(path: &Path) -> Option<&str> {
+    path.to_str().filter(|path| is_remote_corpus(path))
+}
+
+/// Open the (possibly compressed) data of a local or remote corpus file.
+fn open_raw(path: &Path) -> Result<Box<dyn Read>> {
+    match remote_url(path) {
+        Some(url) => open_remote(url),
+        None => Ok(Box::new(
+            File::open(path).context("Cannot open corpus for reading")?,
+        )),
+    }
+}
+
+/// Open a corpus for reading.
+///
+/// Compressed corpora are decompressed transparently. A progress bar
+/// shows the progress through the (compressed) file. If the path is
+/// `STDIN_CORPUS`, the corpus is read from the standard input. If the
+/// path is a URL, the corpus is read remotely, see `open_remote`. The
+/// compression format of a remote corpus is detected from its extension.
+pub fn open_corpus<P>(path: P) -> Result<Box<dyn BufRead>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    if path == Path::new(STDIN_CORPUS) {
+        return Ok(Box::new(BufReader::new(byte_progress(io::stdin()))));
+    }
+
+    if let Some(url) = remote_url(path) {
+        let compression = Compression::from_extension(path).unwrap_or(Compression::None);
+        let read = compression
+            .decompress(byte_progress(open_remote(url)?))
+            .context("Cannot decompress corpus")?;
+        return Ok(Box::new(BufReader::new(read)));
+    }
+
+    let compression = Compression::detect(path)
+        .with_context(|| format!("Cannot open corpus for reading: {}", path.display()))?;
+    let f = File::open(path)
+        .with_context(|| format!("Cannot open corpus for reading: {}", path.display()))?;
+    let file_progress = FileProgress::new(f).context("Cannot create progress bar")?;
+    let read = compression
+        .decompress(file_progress)
+        .context("Cannot decompress corpus")?;
+    Ok(Box::new(BufReader::new(read)))
+}
+
+/// Open a corpus that consists of multiple files for reading.
+///
+/// The files are read as if they were concatenated. See `open_corpus`
+/// for how each file is read.
+pub fn open_corpora<P>(paths: &[P], separator: SentenceSeparator) -> Result<Box<dyn BufRead>>
+where
+    P: AsRef<Path>,
+{
+    if let [path] = paths {
+        return open_corpus(path);
+    }
+
+    let paths = paths
+        .iter()
+        .map(|path| path.as_ref().to_owned())
+        .collect::<Vec<_>>();
+    Ok(Box::new(CorpusChain::new(
+        paths.into_iter().map(open_corpus),
+        separator,
+    )))
+}
+
+/// Open a text column of a Parquet file for reading.
+///
+/// Every text in the column is read as a line, see `ParquetText`.
+#[cfg(feature = "parquet-corpus")]
+pub fn open_parquet<P>(path: P, column: &str) -> Result<Box<dyn BufRead>>
+where
+    P: AsRef<Path>,
+{
+    Ok(Box::new(ParquetText::open(path.as_ref(), column)?))
+}
+
+/// Open a text column of a Parquet file for reading.
+///
+/// This version of finalfrontier is compiled without Parquet support,
+/// so this function always fails.
+#[cfg(not(feature = "parquet-corpus"))]
+pub fn open_parquet<P>(_path: P, _column: &str) -> Result<Box<dyn BufRead>>
+where
+    P: AsRef<Path>,
+{
+    Err(anyhow!(
+        "Parquet corpora are not supported, compile with the parquet-corpus feature"
+    ))
+}
+
+/// Open a Parquet corpus that consists of multiple files for reading.
+///
+/// The texts in `column` of the files are read as if they were lines
+/// of concatenated files.
+pub fn open_parquet_corpora<P>(paths: &[P], column: &str) -> Result<Box<dyn BufRead>>
+where
+    P: AsRef<Path>,
+{
+    let paths = paths
+        .iter()
+        .map(|path| path.as_ref().to_owned())
+        .collect::<Vec<_>>();
+    let column = column.to_owned();
+    Ok(Box::new(CorpusChain::new(
+        paths
+            .into_iter()
+            .map(move |path| open_parquet(path, &column)),
+        SentenceSeparator::Line,
+    )))
+}
+
+/// Open a stream of sentences for reading.
+///
+/// The stream is read from the standard input if the path is
+/// `STDIN_CORPUS`. Otherwise, the path is opened as a file, typically a
+/// named pipe that is fed by a long-running process, such as a consumer
+/// of a message queue. The stream is not decompressed and no progress
+/// is shown, since the length of a stream is not known.
+pub fn open_stream<P>(path: P) -> Result<Box<dyn BufRead + Send>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    if path == Path::new(STDIN_CORPUS) {
+        return Ok(Box::new(BufReader::new(io::stdin())));
+    }
+
+    let f = File::open(path)
+        .with_context(|| format!("Cannot open stream for reading: {}", path.display()))?;
+    Ok(Box::new(BufReader::new(f)))
+}
+
+/// Copy the corpus from the standard input to a temporary file.
+///
+/// Training makes multiple passes over the corpus, whereas the standard
+/// input can only be read once. The corpus is compressed with zstd to
+/// limit the disk space that is used by the copy. The file is removed
+/// when the returned path is dropped.
+pub fn spill_stdin() -> Result<TempPath> {
+    let spill = tempfile::Builder::new()
+        .prefix("finalfrontier-")
+        .suffix(".zst")
+        .tempfile()
+        .context("Cannot create temporary file for the corpus")?;
+    let (f, path) = spill.into_parts();
+
+    let mut encoder = zstd::stream::write::Encoder::new(f, zstd::DEFAULT_COMPRESSION_LEVEL)
+        .context("Cannot create corpus compressor")?;
+    io::copy(&mut byte_progress(io::stdin().lock()), &mut encoder)
+        .context("Cannot copy corpus from standard input")?;
+    encoder
+        .finish()
+        .context("Cannot finish corpus compression")?
+        .sync_all()
+        .context("Cannot write temporary corpus file")?;
+
+    Ok(path)
+}
+
+/// Wrap a reader in a progress spinner.
+///
+/// This is used for the standard input and remote corpora, of which
+/// the size is not known. So, only the number of bytes that was read
+/// is shown.
+fn byte_progress<R>(read: R) -> impl Read
+where
+    R: Read,
+{
+    let progress = ProgressBar::new_spinner();
+    progress.set_style(ProgressStyle::default_spinner().template("{spinner} {bytes} read"));
+    progress.wrap_read(read)
+}
+
+/// Construct a progress spinner for writing a model.
+///
+/// Writing large models can take a while. Since the size of a model
+/// depends on its output format, only the number of bytes that was
+/// written is shown. The model writer is wrapped with
+/// `ProgressBar::wrap_write`, the spinner should be finished when the
+/// model is written.
+pub(crate) fn write_progress() -> ProgressBar {
+    let progress = ProgressBar::new_spinner();
+    progress.set_style(
+        ProgressStyle::default_spinner().template("{spinner} {bytes} written ({bytes_per_sec})"),
+    );
+    progress
+}
+
+/// Thread-specific data of a corpus.
+///
+/// Uncompressed corpora are memory mapped and every thread reads the
+/// sentences in its own byte range of the corpus, see `thread_range`.
+/// The pages of the corpus are thus only read by one thread. Uncompressed
+/// remote corpora are divided in the same manner: every thread requests
+/// its own byte range, see `SentenceRange`.
+///
+/// Since compressed corpora cannot be divided in byte ranges, every
+/// thread decompresses the corpus and reads every *n*-th sentence, where
+/// *n* is the number of threads. Parquet corpora are read in the same
+/// manner. Compressed remote corpora are only downloaded once per pass,
+/// the sentences are distributed over the threads, see `SharedCorpus`.
+pub enum ThreadData {
+    Mapped {
+        data: Mmap,
+        range: Range<usize>,
+    },
+    Remote {
+        url: String,
+        separator: SentenceSeparator,
+        range: Range<u64>,
+    },
+    Compressed {
+        path: PathBuf,
+        compression: Compression,
+        separator: SentenceSeparator,
+        thread: usize,
+        n_threads: usize,
+    },
+    Shared(SharedCorpusThread),
+    Parquet {
+        path: PathBuf,
+        column: String,
+        thread: usize,
+        n_threads: usize,
+    },
+}
+
+impl ThreadData {
+    /// Get thread-specific data of a corpus with a sentence per line.
+    pub fn text<P>(path: P, thread: usize, n_threads: usize) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::new(path.as_ref(), thread, n_threads, SentenceSeparator::Line)
+    }
+
+    /// Get thread-specific data of a CoNLL-U corpus.
+    pub fn conllu<P>(path: P, thread: usize, n_threads: usize) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::new(
+            path.as_ref(),
+            thread,
+            n_threads,
+            SentenceSeparator::EmptyLine,
+        )
+    }
+
+    /// Get thread-specific data of the text column `column` of a Parquet file.
+    pub fn parquet<P>(path: P, column: &str, thread: usize, n_threads: usize) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        ThreadData::Parquet {
+            path: path.as_ref().to_owned(),
+            column: column.to_owned(),
+            thread,
+            n_threads,
+        }
+    }
+
+    fn new(
+        path: &Path,
+        thread: usize,
+        n_threads: usize,
+        separator: SentenceSeparator,
+    ) -> Result<Self> {
+        if let Some(url) = remote_url(path) {
+            return Self::remote(path, url, thread, n_threads, separator);
+        }
+
+        let compression = Compression::detect(path).context("Cannot open corpus for reading")?;
+        if compression != Compression::None {
+            return Ok(ThreadData::Compressed {
+                path: path.to_owned(),
+                compression,
+                separator,
+                thread,
+                n_threads,
+            });
+        }
+
+        let f = File::open(path).context("Cannot open corpus for reading")?;
+        let data =
+            unsafe { MmapOptions::new().map(&f) }.context("Could not get thread-specific data")?;
+        let mut range = thread_range(&data, thread, n_threads, separator);
+
+        // A corpus with fewer sentences than threads leaves some threads
+        // without sentences. These threads read the complete corpus.
+        if range.is_empty() {
+            range = 0..data.len();
+        }
+
+        Ok(ThreadData::Mapped { data, range })
+    }
+
+    fn remote(
+        path: &Path,
+        url: &str,
+        thread: usize,
+        n_threads: usize,
+        separator: SentenceSeparator,
+    ) -> Result<Self> {
+        let compression = Compression::from_extension(path).unwrap_or(Compression::None);
+        if compression == Compression::None {
+            let len = remote_len(url)?;
+            return Ok(ThreadData::Remote {
+                url: url.to_owned(),
+                separator,
+                range: chunk_range(len, thread, n_threads),
+            });
+        }
+
+        if n_threads == 1 {
+            return Ok(ThreadData::Compressed {
+                path: path.to_owned(),
+                compression,
+                separator,
+                thread,
+                n_threads,
+            });
+        }
+
+        Ok(ThreadData::Shared(SharedCorpusThread::new(
+            SharedCorpus::get(path, compression, separator, n_threads),
+            thread,
+        )))
+    }
+
+    /// Get the offset and stride of the sentences of the thread.
+    ///
+    /// A pass over the thread data reads the sentences at `offset`,
+    /// `offset + stride`, `offset + 2 * stride`, etc.
+    pub fn stride(&self) -> (usize, usize) {
+        match self {
+            ThreadData::Mapped { .. } | ThreadData::Remote { .. } => (0, 1),
+            ThreadData::Shared(shared) => (shared.thread, shared.corpus.n_threads),
+            ThreadData::Compressed {
+                thread, n_threads, ..
+            }
+            | ThreadData::Parquet {
+                thread, n_threads, ..
+            } => (*thread, *n_threads),
+        }
+    }
+
+    /// Read the data that precedes the sentences of the thread.
+    ///
+    /// This data is never read by the thread.
+    pub fn first_pass_prefix(&self) -> Result<Box<dyn BufRead + '_>> {
+        match self {
+            ThreadData::Mapped { data, range } => Ok(Box::new(&data[..range.start])),
+            ThreadData::Remote {
+                url,
+                separator,
+                range,
+            } => {
+                // The prefix consists of the sentences of the preceding
+                // threads. There is no prefix when the thread reads the
+                // complete corpus.
+                if range.start == 0 || Self::open_remote_range(url, *separator, range)?.is_none() {
+                    return Ok(Box::new(io::empty()));
+                }
+                let prefix = Self::open_remote_range(url, *separator, &(0..range.start))?
+                    .expect("The first range of a corpus cannot be empty");
+                Ok(Box::new(BufReader::new(prefix)))
+            }
+            ThreadData::Compressed { .. } | ThreadData::Shared(_) | ThreadData::Parquet { .. } => {
+                Ok(Box::new(io::empty()))
+            }
+        }
+    }
+
+    /// Read the data of the thread for the first pass over the corpus.
+    ///
+    /// The first pass reads the same data as subsequent passes.
+    pub fn first_pass(&self) -> Result<Box<dyn BufRead + '_>> {
+        self.pass()
+    }
+
+    /// Read the data of the thread for subsequent passes over the corpus.
+    pub fn pass(&self) -> Result<Box<dyn BufRead + '_>> {
+        match self {
+            ThreadData::Mapped { data, range } => Ok(Box::new(&data[range.clone()])),
+            ThreadData::Remote {
+                url,
+                separator,
+                range,
+            } => {
+                // A corpus with fewer sentences than threads leaves some
+                // threads without sentences. These threads read the
+                // complete corpus.
+                let read = match Self::open_remote_range(url, *separator, range)? {
+                    Some(read) => read,
+                    None => Self::open_remote_range(url, *separator, &(0..u64::MAX))?
+                        .expect("The complete corpus cannot be empty"),
+                };
+                Ok(Box::new(BufReader::new(read)))
+            }
+            ThreadData::Compressed {
+                path,
+                compression,
+                separator,
+                thread,
+                n_threads,
+            } => {
+                let read = compression
+                    .decompress(open_raw(path)?)
+                    .context("Cannot decompress corpus")?;
+                Ok(Box::new(StridedSentences::new(
+                    BufReader::new(read),
+                    *separator,
+                    *thread,
+                    *n_threads,
+                )))
+            }
+            ThreadData::Shared(shared) => Ok(Box::new(shared.pass())),
+            ThreadData::Parquet {
+                path,
+                column,
+                thread,
+                n_threads,
+            } => Ok(Box::new(StridedSentences::new(
+                open_parquet(path, column)?,
+                SentenceSeparator::Line,
+                *thread,
+                *n_threads,
+            ))),
+        }
+    }
+
+    /// Open the sentences that start in a byte range of a remote corpus.
+    ///
+    /// Returns `None` if no sentence starts in the range.
+    fn open_remote_range(
+        url: &str,
+        separator: SentenceSeparator,
+        range: &Range<u64>,
+    ) -> Result<Option<SentenceRange<BufReader<Box<dyn Read>>>>> {
+        let read = BufReader::new(open_remote_at(url, range.start)?);
+        SentenceRange::new(read, separator, range.clone()).context("Cannot read corpus")
+    }
+}
+
+/// Get the nominal byte range of a thread.
+///
+/// The data is divided in `n_threads` chunks of equal size. The range
+/// of the last thread is open-ended. See `SentenceRange` for how the
+/// range is aligned to sentence boundaries.
+fn chunk_range(len: u64, thread: usize, n_threads: usize) -> Range<u64> {
+    let chunk_size = len / n_threads as u64;
+    let start = thread as u64 * chunk_size;
+    if thread + 1 == n_threads {
+        start..u64::MAX
+    } else {
+        start..start + chunk_size
+    }
+}
+
+/// Finder of sentence boundaries in a stream of corpus data.
+struct SentenceBoundaries {
+    separator: SentenceSeparator,
+    pos: u64,
+    prev_newline: bool,
+}
+
+impl SentenceBoundaries {
+    /// Advance over the byte at the current position.
+    ///
+    /// If the byte ends a separator, the position of the first byte of
+    /// the separator is returned.
+    fn advance(&mut self, byte: u8) -> Option<u64> {
+        let pos = self.pos;
+        self.pos += 1;
+
+        let is_newline = byte == b'\n';
+        let prev_newline = mem::replace(&mut self.prev_newline, is_newline);
+        match self.separator {
+            SentenceSeparator::Line if is_newline => Some(pos),
+            SentenceSeparator::EmptyLine if is_newline && prev_newline => Some(pos - 1),
+            _ => None,
+        }
+    }
+}
+
+/// Reader of the sentences that start in a byte range of a corpus.
+///
+/// Sentence boundaries are found in the same manner as `thread_range`:
+/// a range starts after the first separator at or after its nominal
+/// start and ends after the first separator at or after its nominal
+/// end. So, the ranges `0..c1`, `c1..c2`, etc. cover every sentence
+/// of the corpus exactly once, without knowing where the sentences of
+/// the neighboring ranges start.
+struct SentenceRange<R> {
+    inner: R,
+    boundaries: SentenceBoundaries,
+    end: u64,
+    done: bool,
+}
+
+impl<R> SentenceRange<R>
+where
+    R: BufRead,
+{
+    /// Construct a reader from a reader of the corpus at `range.start`.
+    ///
+    /// Returns `None` if no sentence starts in the range.
+    fn new(inner: R, separator: SentenceSeparator, range: Range<u64>) -> io::Result<Option<Self>> {
+        let mut reader = SentenceRange {
+            inner,
+            boundaries: SentenceBoundaries {
+                separator,
+                pos: range.start,
+                prev_newline: false,
+            },
+            end: range.end,
+            done: false,
+        };
+
+        if range.start != 0 {
+            match reader.skip_to_sentence()? {
+                Some(separator_pos) if separator_pos < range.end => (),
+                _ => return Ok(None),
+            }
+        }
+
+        // The data may end with the separator.
+        if reader.inner.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(reader))
+    }
+
+    /// Skip to the start of the next sentence.
+    ///
+    /// Returns the position of the separator that was skipped, or `None`
+    /// if the data ended before a separator.
+    fn skip_to_sentence(&mut self) -> io::Result<Option<u64>> {
+        loop {
+            let data = self.inner.fill_buf()?;
+            if data.is_empty() {
+                return Ok(None);
+            }
+
+            let len = data.len();
+            for (idx, &byte) in data.iter().enumerate() {
+                if let Some(separator_pos) = self.boundaries.advance(byte) {
+                    self.inner.consume(idx + 1);
+                    return Ok(Some(separator_pos));
+                }
+            }
+            self.inner.consume(len);
+        }
+    }
+}
+
+impl<R> Read for SentenceRange<R>
+where
+    R: BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+
+        let data = self.inner.fill_buf()?;
+        let mut n_read = 0;
+        for &byte in data.iter().take(buf.len()) {
+            n_read += 1;
+            if let Some(separator_pos) = self.boundaries.advance(byte) {
+                if separator_pos >= self.end {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+
+        buf[..n_read].copy_from_slice(&data[..n_read]);
+        self.inner.consume(n_read);
+
+        Ok(n_read)
+    }
+}
+
+/// The size of the chunks of sentences that are sent to a thread.
+const SHARED_CHUNK_SIZE: usize = 1 << 16;
+
+/// The number of chunks that a thread can lag behind in a shared pass.
+const SHARED_CHANNEL_BOUND: usize = 64;
+
+/// Corpora of which the passes are shared by training threads.
+static SHARED_CORPORA: Mutex<Vec<Weak<SharedCorpus>>> = Mutex::new(Vec::new());
+
+/// Corpus that is read once per pass for all threads.
+///
+/// The sentences of a pass are distributed over the threads: thread *t*
+/// gets the sentences *t*, *t + n*, *t + 2n*, etc., where *n* is the
+/// number of threads. The corpus is read when the first thread starts
+/// a pass. The threads are kept in step by bounding the number of
+/// sentences that is buffered for a thread.
+struct SharedCorpus {
+    path: PathBuf,
+    compression: Compression,
+    separator: SentenceSeparator,
+    n_threads: usize,
+    state: Mutex<SharedCorpusState>,
+}
+
+struct SharedCorpusState {
+    /// Receivers of passes that were not started by every thread yet.
+    passes: Vec<(usize, Vec<Option<Receiver<io::Result<Vec<u8>>>>>)>,
+
+    /// Threads that stopped reading the corpus.
+    finished: Vec<bool>,
+}
+
+impl SharedCorpus {
+    /// Get the shared corpus at `path` for `n_threads` threads.
+    fn get(
+        path: &Path,
+        compression: Compression,
+        separator: SentenceSeparator,
+        n_threads: usize,
+    ) -> Arc<Self> {
+        let mut corpora = SHARED_CORPORA.lock().unwrap();
+        corpora.retain(|corpus| corpus.strong_count() > 0);
+
+        let existing = corpora.iter().filter_map(Weak::upgrade).find(|corpus| {
+            corpus.path == path && corpus.separator == separator && corpus.n_threads == n_threads
+        });
+        if let Some(corpus) = existing {
+            return corpus;
+        }
+
+        let corpus = Arc::new(SharedCorpus {
+            path: path.to_owned(),
+            compression,
+            separator,
+            n_threads,
+            state: Mutex::new(SharedCorpusState {
+                passes: Vec::new(),
+                finished: vec![false; n_threads],
+            }),
+        });
+        corpora.push(Arc::downgrade(&corpus));
+
+        corpus
+    }
+
+    /// Get the reader of pass `pass` of thread `thread`.
+    fn pass(&self, pass: usize, thread: usize) -> ChannelRead {
+        let mut state = self.state.lock().unwrap();
+
+        let idx = match state.passes.iter().position(|(p, _)| *p == pass) {
+            Some(idx) => idx,
+            None => {
+                let receivers = self
+                    .start_pass()
+                    .into_iter()
+                    .zip(&state.finished)
+                    .map(|(receiver, &finished)| if finished { None } else { Some(receiver) })
+                    .collect();
+                state.passes.push((pass, receivers));
+                state.passes.len() - 1
+            }
+        };
+
+        let receivers = &mut state.passes[idx].1;
+        let receiver = receivers[thread]
+            .take()
+            .expect("Thread started the same pass twice");
+        if receivers.iter().all(Option::is_none) {
+            state.passes.remove(idx);
+        }
+
+        ChannelRead::new(receiver)
+    }
+
+    /// Mark that thread `thread` stops reading the corpus.
+    fn finish(&self, thread: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.finished[thread] = true;
+
+        // Drop the receivers of passes that the thread did not start, so
+        // that the other threads are not held back.
+        for (_, receivers) in &mut state.passes {
+            receivers[thread] = None;
+        }
+        state
+            .passes
+            .retain(|(_, receivers)| receivers.iter().any(Option::is_some));
+    }
+
+    /// Start reading a pass over the corpus.
+    fn start_pass(&self) -> Vec<Receiver<io::Result<Vec<u8>>>> {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..self.n_threads)
+            .map(|_| mpsc::sync_channel(SHARED_CHANNEL_BOUND))
+            .unzip();
+
+        let path = self.path.clone();
+        let compression = self.compression;
+        let separator = self.separator;
+        thread::spawn(move || {
+            let mut senders = senders.into_iter().map(Some).collect::<Vec<_>>();
+            if let Err(err) = distribute_sentences(&path, compression, separator, &mut senders) {
+                for sender in senders.iter().flatten() {
+                    let _ = sender.send(Err(io::Error::new(err.kind(), err.to_string())));
+                }
+            }
+        });
+
+        receivers
+    }
+}
+
+/// Distribute the sentences of a corpus over threads.
+fn distribute_sentences(
+    path: &Path,
+    compression: Compression,
+    separator: SentenceSeparator,
+    senders: &mut [Option<SyncSender<io::Result<Vec<u8>>>>],
+) -> io::Result<()> {
+    let read = open_raw(path).map_err(io::Error::other)?;
+    let read = compression.decompress(read)?;
+    let mut sentences = StridedSentences::new(BufReader::new(read), separator, 0, 1);
+
+    let mut chunks = vec![Vec::new(); senders.len()];
+    let mut sentence_idx = 0;
+    while sentences.read_sentence()? {
+        let thread = sentence_idx % senders.len();
+        sentence_idx += 1;
+
+        let chunk = &mut chunks[thread];
+        chunk.extend_from_slice(&sentences.buf);
+        if chunk.len() >= SHARED_CHUNK_SIZE {
+            send_chunk(&mut senders[thread], mem::take(chunk));
+            if senders.iter().all(Option::is_none) {
+                // No thread is reading the corpus anymore.
+                return Ok(());
+            }
+        }
+    }
+
+    for (sender, chunk) in senders.iter_mut().zip(chunks) {
+        if !chunk.is_empty() {
+            send_chunk(sender, chunk);
+        }
+    }
+
+    Ok(())
+}
+
+/// Send a chunk of sentences to a thread.
+///
+/// The sender is removed when the thread stopped reading the corpus.
+fn send_chunk(sender: &mut Option<SyncSender<io::Result<Vec<u8>>>>, chunk: Vec<u8>) {
+    if let Some(s) = sender {
+        if s.send(Ok(chunk)).is_err() {
+            *sender = None;
+        }
+    }
+}
+
+/// A thread's view of a shared corpus.
+pub struct SharedCorpusThread {
+    corpus: Arc<SharedCorpus>,
+    thread: usize,
+    pass: Cell<usize>,
+}
+
+impl SharedCorpusThread {
+    fn new(corpus: Arc<SharedCorpus>, thread: usize) -> Self {
+        SharedCorpusThread {
+            corpus,
+            thread,
+            pass: Cell::new(0),
+        }
+    }
+
+    /// Start the next pass over the corpus.
+    fn pass(&self) -> ChannelRead {
+        let pass = self.pass.get();
+        self.pass.set(pass + 1);
+        self.corpus.pass(pass, self.thread)
+    }
+}
+
+impl Drop for SharedCorpusThread {
+    fn drop(&mut self) {
+        self.corpus.finish(self.thread);
+    }
+}
+
+/// Reader of data that is received over a channel.
+struct ChannelRead {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelRead {
+    fn new(receiver: Receiver<io::Result<Vec<u8>>>) -> Self {
+        ChannelRead {
+            receiver,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelRead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n_read = self.fill_buf()?.read(buf)?;
+        self.consume(n_read);
+        Ok(n_read)
+    }
+}
+
+impl BufRead for ChannelRead {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.buf.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.buf = chunk?;
+                    self.pos = 0;
+                }
+                // The pass is complete.
+                Err(_) => break,
+            }
+        }
+
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+/// Thread-specific data of a corpus that consists of multiple files.
+///
+/// When there are at least as many files as threads, the files are
+/// sharded across the threads and each thread reads every *n*-th file,
+/// where *n* is the number of threads. Otherwise, every file is shared
+/// by multiple threads, see `ThreadData`.
+pub struct ThreadCorpus {
+    files: Vec<ThreadData>,
+    separator: SentenceSeparator,
+}
+
+impl ThreadCorpus {
+    /// Get thread-specific data of a corpus with a sentence per line.
+    pub fn text<P>(paths: &[P], thread: usize, n_threads: usize) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::new(paths, thread, n_threads, SentenceSeparator::Line)
+    }
+
+    /// Get thread-specific data of a CoNLL-U corpus.
+    pub fn conllu<P>(paths: &[P], thread: usize, n_threads: usize) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::new(paths, thread, n_threads, SentenceSeparator::EmptyLine)
+    }
+
+    /// Get thread-specific data of the text column `column` of a Parquet corpus.
+    pub fn parquet<P>(paths: &[P], column: &str, thread: usize, n_threads: usize) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_files(
+            paths,
+            thread,
+            n_threads,
+            SentenceSeparator::Line,
+            |path, thread, n_threads| Ok(ThreadData::parquet(path, column, thread, n_threads)),
+        )
+    }
+
+    /// Get thread-specific data of a corpus with the given sentence separator.
+    pub fn new<P>(
+        paths: &[P],
+        thread: usize,
+        n_threads: usize,
+        separator: SentenceSeparator,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_files(
+            paths,
+            thread,
+            n_threads,
+            separator,
+            |path, thread, n_threads| ThreadData::new(path, thread, n_threads, separator),
+        )
+    }
+
+    /// Get thread-specific data, using `thread_data` to get the data of a file.
+    fn from_files<P, F>(
+        paths: &[P],
+        thread: usize,
+        n_threads: usize,
+        separator: SentenceSeparator,
+        thread_data: F,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        F: Fn(&Path, usize, usize) -> Result<ThreadData>,
+    {
+        assert!(
+            !paths.is_empty(),
+            "Corpus should consist of at least one file"
+        );
+        assert!(
+            thread < n_threads,
+            "Thread {} out of index [0, {})",
+            thread,
+            n_threads
+        );
+
+        let files = if paths.len() >= n_threads {
+            paths
+                .iter()
+                .skip(thread)
+                .step_by(n_threads)
+                .map(|path| thread_data(path.as_ref(), 0, 1))
+                .collect::<Result<_>>()?
+        } else {
+            // Threads thread, thread + n_files, thread + 2 * n_files, etc.
+            // share a file.
+            let n_files = paths.len();
+            let file_idx = thread % n_files;
+            let n_file_threads = (n_threads - file_idx + n_files - 1) / n_files;
+            vec![thread_data(
+                paths[file_idx].as_ref(),
+                thread / n_files,
+                n_file_threads,
+            )?]
+        };
+
+        Ok(ThreadCorpus { files, separator })
+    }
+
+    /// Read the data of the thread for the first pass over the corpus.
+    pub fn first_pass(&self) -> Result<Box<dyn BufRead + '_>> {
+        let (first, rest) = self.files.split_first().unwrap();
+        Ok(Box::new(CorpusChain::new(
+            iter::once(first.first_pass()).chain(rest.iter().map(ThreadData::pass)),
+            self.separator,
+        )))
+    }
+
+    /// Read the data of the thread for subsequent passes over the corpus.
+    pub fn pass(&self) -> Result<Box<dyn BufRead + '_>> {
+        Ok(Box::new(CorpusChain::new(
+            self.files.iter().map(ThreadData::pass),
+            self.separator,
+        )))
+    }
+}
+
+/// Concatenation of corpus files.
+///
+/// The files are opened lazily. Newlines are added to the end of a file
+/// when necessary, so that the last sentence of a file is not merged
+/// with the first sentence of the next file.
+struct CorpusChain<'a> {
+    readers: Box<dyn Iterator<Item = Result<Box<dyn BufRead + 'a>>> + 'a>,
+    current: Option<Box<dyn BufRead + 'a>>,
+    separator: SentenceSeparator,
+    file_is_empty: bool,
+    trailing_newlines: usize,
+    pending_newlines: usize,
+}
+
+impl<'a> CorpusChain<'a> {
+    fn new<I>(readers: I, separator: SentenceSeparator) -> Self
+    where
+        I: Iterator<Item = Result<Box<dyn BufRead + 'a>>> + 'a,
+    {
+        CorpusChain {
+            readers: Box::new(readers),
+            current: None,
+            separator,
+            file_is_empty: true,
+            trailing_newlines: 0,
+            pending_newlines: 0,
+        }
+    }
+
+    /// Track the number of newlines at the end of the data that was read.
+    fn track_newlines(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        let is_newline = |b: &&u8| **b == b'\n' || **b == b'\r';
+        let n_trailing = data
+            .iter()
+            .rev()
+            .take_while(is_newline)
+            .filter(|&&b| b == b'\n')
+            .count();
+        if data.iter().all(|b| is_newline(&b)) {
+            self.trailing_newlines += n_trailing;
+        } else {
+            self.trailing_newlines = n_trailing;
+        }
+    }
+}
+
+impl<'a> Read for CorpusChain<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n_read = self.fill_buf()?.read(buf)?;
+        self.consume(n_read);
+        Ok(n_read)
+    }
+}
+
+impl<'a> BufRead for CorpusChain<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        loop {
+            if self.pending_newlines != 0 {
+                return Ok(&b"\n\n"[..self.pending_newlines]);
+            }
+
+            match &mut self.current {
+                Some(current) => {
+                    if !current.fill_buf()?.is_empty() {
+                        break;
+                    }
+
+                    // Terminate the last sentence of the file.
+                    self.current = None;
+                    if !self.file_is_empty {
+                        self.pending_newlines = self
+                            .separator
+                            .n_newlines()
+                            .saturating_sub(self.trailing_newlines);
+                    }
+                    self.file_is_empty = true;
+                }
+                None => match self.readers.next() {
+                    Some(reader) => {
+                        self.current =
+                            Some(reader.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?)
+                    }
+                    None => return Ok(&[]),
+                },
+            }
+        }
+
+        self.current.as_mut().unwrap().fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.pending_newlines != 0 {
+            let amt = amt.min(self.pending_newlines);
+            self.pending_newlines -= amt;
+            self.trailing_newlines += amt;
+            return;
+        }
+
+        if let Some(mut current) = self.current.take() {
+            if let Ok(data) = current.fill_buf() {
+                let amt = amt.min(data.len());
+                self.file_is_empty &= amt == 0;
+                self.track_newlines(&data[..amt]);
+            }
+            current.consume(amt);
+            self.current = Some(current);
+        }
+    }
+}
+
+/// Separators of sentences in a corpus.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SentenceSeparator {
+    /// Every line is a sentence.
+    Line,
+
+    /// Sentences are separated by empty lines, as in CoNLL-U.
+    EmptyLine,
+}
+
+impl SentenceSeparator {
+    /// The number of newlines that end a sentence.
+    fn n_newlines(self) -> usize {
+        match self {
+            SentenceSeparator::Line => 1,
+            SentenceSeparator::EmptyLine => 2,
+        }
+    }
+}
+
+/// Reader of every *n*-th sentence of a corpus.
+pub struct StridedSentences<R> {
+    inner: R,
+    separator: SentenceSeparator,
+    offset: usize,
+    stride: usize,
+    sentence_idx: usize,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<R> StridedSentences<R>
+where
+    R: BufRead,
+{
+    /// Construct a reader of the sentences at `offset`, `offset + stride`,
+    /// `offset + 2 * stride`, etc.
+    pub fn new(inner: R, separator: SentenceSeparator, offset: usize, stride: usize) -> Self {
+        assert!(
+            offset < stride,
+            "Offset {} out of index [0, {})",
+            offset,
+            stride
+        );
+
+        StridedSentences {
+            inner,
+            separator,
+            offset,
+            stride,
+            sentence_idx: 0,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Read the next sentence into the buffer.
+    ///
+    /// Returns `false` if there are no sentences left.
+    fn read_sentence(&mut self) -> io::Result<bool> {
+        self.buf.clear();
+        self.pos = 0;
+
+        loop {
+            let len = self.buf.len();
+            if self.inner.read_until(b'\n', &mut self.buf)? == 0 {
+                return Ok(!self.buf.is_empty());
+            }
+
+            let line = &self.buf[len..];
+            let is_empty = line == b"\n" || line == b"\r\n";
+            match self.separator {
+                SentenceSeparator::Line => return Ok(true),
+                SentenceSeparator::EmptyLine if is_empty && len != 0 => return Ok(true),
+                SentenceSeparator::EmptyLine if is_empty => self.buf.clear(),
+                SentenceSeparator::EmptyLine => (),
+            }
+        }
+    }
+}
+
+impl<R> Read for StridedSentences<R>
+where
+    R: BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n_read = self.fill_buf()?.read(buf)?;
+        self.consume(n_read);
+        Ok(n_read)
+    }
+}
+
+impl<R> BufRead for StridedSentences<R>
+where
+    R: BufRead,
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.buf.len() {
+            if !self.read_sentence()? {
+                break;
+            }
+
+            let sentence_idx = self.sentence_idx;
+            self.sentence_idx += 1;
+            if sentence_idx % self.stride != self.offset {
+                self.buf.clear();
+            }
+        }
+
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+/// Meta information about training.
+#[derive(Clone, Serialize)]
+pub struct TrainInfo {
+    corpus: Vec<String>,
+    corpus_format: CorpusFormat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text_field: Option<String>,
+    tokenizer: Tokenizer,
+    #[serde(skip_serializing_if = "NoiseFilters::is_empty")]
+    noise: NoiseFilters,
+    weights: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weight_field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shuffle_buffer: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batch_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dedup: Option<Dedup>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dedup_memory: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language_field: Option<String>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapshot_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checkpoint_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resume: Option<String>,
+    save_every_epoch: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epoch: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation_loss: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    eval_similarity: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    eval_analogy: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    eval_interval: Option<usize>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    eval_scores: BTreeMap<String, f32>,
+    output: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context_output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ngram_output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vectors_npy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ann_index: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    config_output: Option<String>,
+    n_threads: usize,
+    numa: bool,
+    device: Device,
+    start_datetime: String,
+    end_datetime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    corpus_sha256: Option<String>,
+    #[serde(skip)]
+    start_instant: Instant,
+    #[serde(skip)]
+    language_filter: Option<LanguageFilter>,
+    #[serde(skip)]
+    corpus_paths: Vec<String>,
+    // Removes the temporary copy of the corpus when dropped.
+    #[serde(skip)]
+    _corpus_spill: Option<Arc<TempPath>>,
+}
+
+impl TrainInfo {
+    /// Construct new TrainInfo.
+    ///
+    /// Constructs TrainInfo with `start_datetime` set to the current datetime. `end_datetime` is
+    /// set to `None` and can be set through `TrainInfo::set_end`.
+    ///
+    /// If `corpus` only consists of `STDIN_CORPUS`, the corpus is copied from the standard
+    /// input to a temporary file, see `spill_stdin`.
+    pub fn new(corpus: Vec<String>, output: String, n_threads: usize) -> Result<Self> {
+        ensure!(!corpus.is_empty(), "No corpus files were given");
+        ensure!(
+            corpus.len() == 1 || corpus.iter().all(|path| path != STDIN_CORPUS),
+            "The standard input cannot be combined with other corpus files"
+        );
+
+        let (corpus_paths, corpus_spill) = if corpus == [STDIN_CORPUS] {
+            let spill = spill_stdin()?;
+            let spill_path = spill
+                .to_str()
+                .context("Temporary corpus path is not valid Unicode")?
+                .to_owned();
+            (vec![spill_path], Some(Arc::new(spill)))
+        } else {
+            (corpus.clone(), None)
+        };
+
+        let start_datetime: DateTime<Local> = Local::now();
+        Ok(TrainInfo {
             corpus,
+            corpus_format: CorpusFormat::default(),
+            text_field: None,
+            tokenizer: Tokenizer::default(),
+            noise: NoiseFilters::default(),
+            weights: false,
+            weight_field: None,
+            shuffle_buffer: None,
+            batch_size: None,
+            dedup: None,
+            dedup_memory: None,
+            language: None,
+            language_field: None,
+            stream: false,
+            snapshot_interval: None,
+            checkpoint_interval: None,
+            resume: None,
+            save_every_epoch: false,
+            epoch: None,
+            validation: None,
+            validation_interval: None,
+            validation_loss: None,
+            eval_similarity: Vec::new(),
+            eval_analogy: Vec::new(),
+            eval_interval: None,
+            eval_scores: BTreeMap::new(),
+            output,
+            context_output: None,
+            ngram_output: None,
+            vectors_npy: None,
+            ann_index: None,
+            config_output: None,
+            n_threads,
+            numa: false,
+            device: Device::Cpu,
+            start_datetime: start_datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+            end_datetime: None,
+            duration_secs: None,
+            corpus_sha256: None,
+            start_instant: Instant::now(),
+            language_filter: None,
+            corpus_paths,
+            _corpus_spill: corpus_spill,
+        })
+    }
+
+    /// Construct TrainInfo for training on a stream.
+    ///
+    /// The stream is read once, until it ends, see `open_stream`. In
+    /// contrast to `TrainInfo::new`, the standard input is not copied.
+    pub fn new_stream(corpus: String, output: String, n_threads: usize) -> Self {
+        let start_datetime: DateTime<Local> = Local::now();
+        TrainInfo {
+            corpus: vec![corpus.clone()],
+            corpus_format: CorpusFormat::default(),
+            text_field: None,
+            tokenizer: Tokenizer::default(),
+            noise: NoiseFilters::default(),
+            weights: false,
+            weight_field: None,
+            shuffle_buffer: None,
+            batch_size: None,
+            dedup: None,
+            dedup_memory: None,
+            language: None,
+            language_field: None,
+            stream: true,
+            snapshot_interval: None,
+            checkpoint_interval: None,
+            resume: None,
+            save_every_epoch: false,
+            epoch: None,
+            validation: None,
+            validation_interval: None,
+            validation_loss: None,
+            eval_similarity: Vec::new(),
+            eval_analogy: Vec::new(),
+            eval_interval: None,
+            eval_scores: BTreeMap::new(),
             output,
+            context_output: None,
+            ngram_output: None,
+            vectors_npy: None,
+            ann_index: None,
+            config_output: None,
             n_threads,
+            numa: false,
+            device: Device::Cpu,
             start_datetime: start_datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
             end_datetime: None,
+            duration_secs: None,
+            corpus_sha256: None,
+            start_instant: Instant::now(),
+            language_filter: None,
+            corpus_paths: vec![corpus],
+            _corpus_spill: None,
+        }
+    }
+
+    /// Get the corpus paths.
+    ///
+    /// This is the path of the temporary copy when the corpus is read from
+    /// the standard input.
+    pub fn corpus(&self) -> &[String] {
+        &self.corpus_paths
+    }
+
+    /// Set the format of the corpus.
+    pub fn with_corpus_format(mut self, corpus_format: CorpusFormat) -> Self {
+        self.corpus_format = corpus_format;
+        self
+    }
+
+    /// Get the format of the corpus.
+    pub fn corpus_format(&self) -> CorpusFormat {
+        self.corpus_format
+    }
+
+    /// Set the text field of a JSON Lines corpus.
+    pub fn with_text_field(mut self, text_field: impl Into<String>) -> Self {
+        self.text_field = Some(text_field.into());
+        self
+    }
+
+    /// Get the text field of a JSON Lines corpus.
+    pub fn text_field(&self) -> Option<&str> {
+        self.text_field.as_deref()
+    }
+
+    /// Set the tokenizer of the corpus.
+    pub fn with_tokenizer(mut self, tokenizer: Tokenizer) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Get the tokenizer of the corpus.
+    pub fn tokenizer(&self) -> Tokenizer {
+        self.tokenizer
+    }
+
+    /// Set the noise filters of the corpus.
+    pub fn with_noise(mut self, noise: NoiseFilters) -> Self {
+        self.noise = noise;
+        self
+    }
+
+    /// Get the noise filters of the corpus.
+    pub fn noise(&self) -> NoiseFilters {
+        self.noise
+    }
+
+    /// Read sentence weights from the corpus.
+    pub fn with_weights(mut self) -> Self {
+        self.weights = true;
+        self
+    }
+
+    /// Check whether sentence weights are read from the corpus.
+    pub fn weights(&self) -> bool {
+        self.weights
+    }
+
+    /// Set the weight field of a JSON Lines corpus.
+    pub fn with_weight_field(mut self, weight_field: impl Into<String>) -> Self {
+        self.weight_field = Some(weight_field.into());
+        self
+    }
+
+    /// Get the weight field of a JSON Lines corpus.
+    pub fn weight_field(&self) -> Option<&str> {
+        self.weight_field.as_deref()
+    }
+
+    /// Shuffle sentences in a buffer of `shuffle_buffer` sentences.
+    pub fn with_shuffle_buffer(mut self, shuffle_buffer: usize) -> Self {
+        self.shuffle_buffer = Some(shuffle_buffer);
+        self
+    }
+
+    /// Get the size of the sentence shuffle buffer.
+    pub fn shuffle_buffer(&self) -> Option<usize> {
+        self.shuffle_buffer
+    }
+
+    /// Update the parameters once per batch of `batch_size` training
+    /// instances.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = Some(batch_size);
+        self
+    }
+
+    /// Get the number of training instances per update.
+    pub fn batch_size(&self) -> Option<usize> {
+        self.batch_size
+    }
+
+    /// Skip duplicate sentences.
+    ///
+    /// The filters of all threads use at most `dedup_memory` bytes
+    /// together.
+    pub fn with_dedup(mut self, dedup: Dedup, dedup_memory: usize) -> Self {
+        self.dedup = Some(dedup);
+        self.dedup_memory = Some(dedup_memory);
+        self
+    }
+
+    /// Get the sentence deduplication method and its memory bound.
+    pub fn dedup(&self) -> Option<(Dedup, usize)> {
+        self.dedup.zip(self.dedup_memory)
+    }
+
+    /// Skip sentences that are not in the language of `language`.
+    pub fn with_language(mut self, language: LanguageFilter) -> Self {
+        self.language = Some(language.code());
+        self.language_field = language.field().map(ToOwned::to_owned);
+        self.language_filter = Some(language);
+        self
+    }
+
+    /// Get the language filter of the corpus.
+    pub fn language(&self) -> Option<&LanguageFilter> {
+        self.language_filter.as_ref()
+    }
+
+    /// Check whether the corpus is a stream.
+    pub fn stream(&self) -> bool {
+        self.stream
+    }
+
+    /// Write a snapshot of the model every `snapshot_interval` seconds.
+    pub fn with_snapshot_interval(mut self, snapshot_interval: u64) -> Self {
+        self.snapshot_interval = Some(snapshot_interval);
+        self
+    }
+
+    /// Get the interval between model snapshots in seconds.
+    pub fn snapshot_interval(&self) -> Option<u64> {
+        self.snapshot_interval
+    }
+
+    /// Write a training checkpoint every `checkpoint_interval` seconds.
+    pub fn with_checkpoint_interval(mut self, checkpoint_interval: u64) -> Self {
+        self.checkpoint_interval = Some(checkpoint_interval);
+        self
+    }
+
+    /// Get the interval between training checkpoints in seconds.
+    pub fn checkpoint_interval(&self) -> Option<u64> {
+        self.checkpoint_interval
+    }
+
+    /// Get the output file of training checkpoints.
+    ///
+    /// Checkpoints are written next to the embeddings output, with the
+    /// suffix `.checkpoint`.
+    pub fn checkpoint_output(&self) -> String {
+        format!("{}.checkpoint", self.output)
+    }
+
+    /// Resume training from the checkpoint in `resume`.
+    pub fn with_resume(mut self, resume: impl Into<String>) -> Self {
+        self.resume = Some(resume.into());
+        self
+    }
+
+    /// Get the checkpoint that training resumes from.
+    pub fn resume(&self) -> Option<&str> {
+        self.resume.as_deref()
+    }
+
+    /// Write a model file for every completed epoch.
+    pub fn with_save_every_epoch(mut self, save_every_epoch: bool) -> Self {
+        self.save_every_epoch = save_every_epoch;
+        self
+    }
+
+    /// Check whether a model file is written for every completed epoch.
+    pub fn save_every_epoch(&self) -> bool {
+        self.save_every_epoch
+    }
+
+    /// Get the training information of the model file of an epoch.
+    ///
+    /// The model file of epoch *n* is written next to the output file,
+    /// with `.epoch-n` inserted before the extension. The additional
+    /// outputs, such as context embeddings, are only written for the
+    /// final model.
+    pub fn epoch_snapshot(&self, epoch: u32) -> Self {
+        let output = Path::new(&self.output);
+        let mut file_name = output
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        file_name.push_str(&format!(".epoch-{}", epoch));
+        if let Some(extension) = output.extension() {
+            file_name.push('.');
+            file_name.push_str(&extension.to_string_lossy());
         }
+
+        let mut train_info = self.clone();
+        train_info.output = output
+            .with_file_name(file_name)
+            .to_string_lossy()
+            .into_owned();
+        train_info.epoch = Some(epoch);
+        train_info.context_output = None;
+        train_info.ngram_output = None;
+        train_info.vectors_npy = None;
+        train_info.ann_index = None;
+        train_info.config_output = None;
+        train_info
+    }
+
+    /// Get the epoch of a per-epoch model file.
+    pub fn epoch(&self) -> Option<u32> {
+        self.epoch
+    }
+
+    /// Compute the loss on the `validation` corpus every
+    /// `validation_interval` seconds.
+    pub fn with_validation(
+        mut self,
+        validation: impl Into<String>,
+        validation_interval: u64,
+    ) -> Self {
+        self.validation = Some(validation.into());
+        self.validation_interval = Some(validation_interval);
+        self
+    }
+
+    /// Get the validation corpus.
+    pub fn validation(&self) -> Option<&str> {
+        self.validation.as_deref()
+    }
+
+    /// Get the interval between validation loss computations in seconds.
+    pub fn validation_interval(&self) -> Option<u64> {
+        self.validation_interval
+    }
+
+    /// Set the validation loss of the trained model.
+    pub fn set_validation_loss(&mut self, validation_loss: f32) {
+        self.validation_loss = Some(validation_loss);
+    }
+
+    /// Get the validation loss of the trained model.
+    pub fn validation_loss(&self) -> Option<f32> {
+        self.validation_loss
+    }
+
+    /// Evaluate on the word similarity datasets `eval_similarity` and the
+    /// word analogy datasets `eval_analogy` every `eval_interval` tokens.
+    pub fn with_eval(
+        mut self,
+        eval_similarity: Vec<String>,
+        eval_analogy: Vec<String>,
+        eval_interval: usize,
+    ) -> Self {
+        self.eval_similarity = eval_similarity;
+        self.eval_analogy = eval_analogy;
+        self.eval_interval = Some(eval_interval);
+        self
+    }
+
+    /// Get the word similarity evaluation datasets.
+    pub fn eval_similarity(&self) -> &[String] {
+        &self.eval_similarity
+    }
+
+    /// Get the word analogy evaluation datasets.
+    pub fn eval_analogy(&self) -> &[String] {
+        &self.eval_analogy
+    }
+
+    /// Get the interval between evaluations in tokens.
+    pub fn eval_interval(&self) -> Option<usize> {
+        self.eval_interval
+    }
+
+    /// Set the evaluation score of the trained model on a dataset.
+    pub fn set_eval_score(&mut self, dataset: impl Into<String>, score: f32) {
+        self.eval_scores.insert(dataset.into(), score);
     }
 
-    /// Get the corpus path.
-    pub fn corpus(&self) -> &str {
-        &self.corpus
+    /// Get the evaluation scores of the trained model.
+    pub fn eval_scores(&self) -> &BTreeMap<String, f32> {
+        &self.eval_scores
     }
 
     /// Get the output file.
@@ -227,11 +2778,91 @@ impl TrainInfo {
         &self.output
     }
 
+    /// Write the output (context) embeddings to `context_output`.
+    pub fn with_context_output(mut self, context_output: impl Into<String>) -> Self {
+        self.context_output = Some(context_output.into());
+        self
+    }
+
+    /// Get the output file of the output (context) embeddings.
+    pub fn context_output(&self) -> Option<&str> {
+        self.context_output.as_deref()
+    }
+
+    /// Write the embeddings of the retained ngrams to `ngram_output`.
+    pub fn with_ngram_output(mut self, ngram_output: impl Into<String>) -> Self {
+        self.ngram_output = Some(ngram_output.into());
+        self
+    }
+
+    /// Get the output file of the ngram embeddings.
+    pub fn ngram_output(&self) -> Option<&str> {
+        self.ngram_output.as_deref()
+    }
+
+    /// Write the embedding matrix of gensim output to `vectors_npy`.
+    pub fn with_vectors_npy(mut self, vectors_npy: impl Into<String>) -> Self {
+        self.vectors_npy = Some(vectors_npy.into());
+        self
+    }
+
+    /// Get the output file of the embedding matrix of gensim output.
+    pub fn vectors_npy(&self) -> Option<&str> {
+        self.vectors_npy.as_deref()
+    }
+
+    /// Write an approximate nearest neighbor index of the word embeddings
+    /// to `ann_index`.
+    pub fn with_ann_index(mut self, ann_index: impl Into<String>) -> Self {
+        self.ann_index = Some(ann_index.into());
+        self
+    }
+
+    /// Get the output file of the approximate nearest neighbor index.
+    pub fn ann_index(&self) -> Option<&str> {
+        self.ann_index.as_deref()
+    }
+
+    /// Write the effective training configuration to `config_output`.
+    pub fn with_config_output(mut self, config_output: impl Into<String>) -> Self {
+        self.config_output = Some(config_output.into());
+        self
+    }
+
+    /// Get the output file of the training configuration.
+    pub fn config_output(&self) -> Option<&str> {
+        self.config_output.as_deref()
+    }
+
     /// Get the number of threads.
     pub fn n_threads(&self) -> usize {
         self.n_threads
     }
 
+    /// Place training threads and the embedding matrices on the NUMA
+    /// nodes of the machine.
+    pub fn with_numa(mut self, numa: bool) -> Self {
+        self.numa = numa;
+        self
+    }
+
+    /// Check whether training threads and the embedding matrices are
+    /// placed on NUMA nodes.
+    pub fn numa(&self) -> bool {
+        self.numa
+    }
+
+    /// Set the device that training runs on.
+    pub fn with_device(mut self, device: Device) -> Self {
+        self.device = device;
+        self
+    }
+
+    /// Get the device that training runs on.
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
     /// Get the start datetime.
     pub fn start_datetime(&self) -> &str {
         &self.start_datetime
@@ -243,9 +2874,47 @@ impl TrainInfo {
     }
 
     /// Set the end datetime to current datetime.
+    ///
+    /// This also records the wall-clock duration of training.
     pub fn set_end(&mut self) {
         let start_datetime: DateTime<Local> = Local::now();
         self.end_datetime = Some(start_datetime.format("%Y-%m-%d %H:%M:%S").to_string());
+        self.duration_secs = Some(self.start_instant.elapsed().as_secs());
+    }
+
+    /// Compute the SHA-256 digest of the corpus.
+    ///
+    /// The digest is computed over the concatenated bytes of the corpus
+    /// files, as they are stored (e.g. compressed). The digest is not
+    /// computed for streams and remote corpora, since they can only be
+    /// read once. The digest is not recomputed when it is already set.
+    pub fn compute_corpus_sha256(&mut self) -> Result<()> {
+        if self.corpus_sha256.is_some()
+            || self.stream
+            || self
+                .corpus_paths
+                .iter()
+                .any(|path| remote_url(Path::new(path)).is_some())
+        {
+            return Ok(());
+        }
+
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 1 << 16];
+        for path in &self.corpus_paths {
+            let mut f = File::open(path)
+                .with_context(|| format!("Cannot open corpus for hashing: {}", path))?;
+            loop {
+                let n = f.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+            }
+        }
+        self.corpus_sha256 = Some(format!("{:x}", hasher.finalize()));
+
+        Ok(())
     }
 }
 
@@ -268,6 +2937,43 @@ fn whitespace_tokenize(line: &str) -> Vec<String> {
         .collect::<Vec<_>>()
 }
 
+fn punctuation_tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+
+    for word in line.split_whitespace() {
+        let chars = word.char_indices().collect::<Vec<_>>();
+        let mut start = 0;
+        for (i, &(idx, c)) in chars.iter().enumerate() {
+            if c.is_alphanumeric() {
+                continue;
+            }
+
+            // Keep word-internal punctuation, such as in *don't*.
+            let internal = i > 0
+                && i + 1 < chars.len()
+                && chars[i - 1].1.is_alphanumeric()
+                && chars[i + 1].1.is_alphanumeric();
+            if internal {
+                continue;
+            }
+
+            if start < idx {
+                tokens.push(word[start..idx].to_owned());
+            }
+
+            let end = idx + c.len_utf8();
+            tokens.push(word[idx..end].to_owned());
+            start = end;
+        }
+
+        if start < word.len() {
+            tokens.push(word[start..].to_owned());
+        }
+    }
+
+    tokens
+}
+
 /// Trait for writing models in text format.
 pub trait WriteModelText<W>
 where
@@ -283,6 +2989,34 @@ where
     fn write_model_text(&self, write: &mut W, write_dims: bool) -> Result<()>;
 }
 
+/// Trait for writing models in fastText's binary format.
+pub trait WriteModelFastText<W>
+where
+    W: Write,
+{
+    /// Write the model in fastText's binary format.
+    ///
+    /// The trained input and output matrices are written as-is, together
+    /// with the hyperparameters and word counts, so that fastText can
+    /// load the model. This requires a skip-gram or CBOW model with a
+    /// fastText bucket vocabulary.
+    fn write_model_fasttext(self, write: &mut W) -> Result<()>;
+}
+
+/// Trait for writing models in floret's text format.
+pub trait WriteModelFloret<W>
+where
+    W: Write,
+{
+    /// Write the model as a floret table.
+    ///
+    /// floret tables do not have a vocabulary. The embeddings of the
+    /// words and n-grams are hashed into the buckets of the table, so
+    /// that the embedding of a word is approximately the mean of its
+    /// buckets. This requires a model with n-gram subword units.
+    fn write_model_floret(self, write: &mut W) -> Result<()>;
+}
+
 /// Trait for writing models in binary format.
 pub trait WriteModelWord2Vec<W>
 where
@@ -294,10 +3028,38 @@ where
 #[cfg(test)]
 mod tests {
     use std::fs::File;
-    use std::io::Cursor;
+    use std::io::{BufRead, Cursor, Read, Write};
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use flate2::write::GzEncoder;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use tempfile::NamedTempFile;
+
+    use super::{
+        chunk_range, Compression, ConlluFilter, CorpusChain, CorpusFormat, SentenceIterator,
+        SentenceRange, SentenceSeparator, SharedCorpus, SharedCorpusThread, ShuffleBuffer,
+        StridedSentences, ThreadCorpus, ThreadData, Tokenizer, TrainInfo,
+    };
+    use super::{is_remote_corpus, thread_data_conllu, thread_data_text, thread_range};
+    use crate::{
+        CaseFolding, MultiwordLexicon, NormalizationForm, NumberNormalization, TokenNormalization,
+        TokenRules,
+    };
 
-    use super::SentenceIterator;
-    use super::{thread_data_conllu, thread_data_text};
+    #[test]
+    fn train_info_epoch_snapshot() {
+        let train_info = TrainInfo::new_stream("-".to_string(), "out/model.fifu".to_string(), 1)
+            .with_ann_index("out/model.hnsw");
+        let epoch_info = train_info.epoch_snapshot(3);
+        assert_eq!(epoch_info.output(), "out/model.epoch-3.fifu");
+        assert_eq!(epoch_info.epoch(), Some(3));
+        assert!(epoch_info.ann_index().is_none());
+
+        let train_info = TrainInfo::new_stream("-".to_string(), "model".to_string(), 1);
+        assert_eq!(train_info.epoch_snapshot(1).output(), "model.epoch-1");
+    }
 
     #[test]
     fn sentence_iterator_test() {
@@ -347,6 +3109,326 @@ mod tests {
         assert!(iter.next().is_none());
     }
 
+    #[test]
+    fn sentence_iterator_case_folding_test() {
+        let v = "Die Straße ist NASS .\n".as_bytes().to_vec();
+
+        let mut iter =
+            SentenceIterator::new(Cursor::new(v.clone())).with_normalization(TokenNormalization {
+                case_folding: CaseFolding::Lowercase,
+                ..TokenNormalization::default()
+            });
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            vec!["die", "straße", "ist", "nass", "."]
+        );
+
+        let mut iter =
+            SentenceIterator::new(Cursor::new(v)).with_normalization(TokenNormalization {
+                case_folding: CaseFolding::Casefold,
+                ..TokenNormalization::default()
+            });
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            vec!["die", "strasse", "ist", "nass", "."]
+        );
+    }
+
+    #[test]
+    fn sentence_iterator_rules_test() {
+        let rules = TokenRules::read(
+            r#"
+[[rule]]
+action = "drop"
+pattern = "^</?p>$"
+
+[[rule]]
+action = "replace"
+pattern = "^@\\w+$"
+replacement = "@user"
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let v = "<p>\nThanks @Danieldk !\n</p>\n".as_bytes().to_vec();
+        let mut iter = SentenceIterator::new(Cursor::new(v))
+            .with_normalization(TokenNormalization {
+                case_folding: CaseFolding::Lowercase,
+                ..TokenNormalization::default()
+            })
+            .with_rules(Arc::new(rules));
+        assert_eq!(iter.next().unwrap().unwrap(), vec!["thanks", "@user", "!"]);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn sentence_iterator_multiword_test() {
+        let lexicon =
+            MultiwordLexicon::read("new york\n".as_bytes(), TokenNormalization::default()).unwrap();
+
+        let v = "In New York @nyc\n".as_bytes().to_vec();
+        let rules = TokenRules::read(
+            r#"
+[[rule]]
+action = "drop"
+pattern = "^@"
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let mut iter = SentenceIterator::new(Cursor::new(v))
+            .with_normalization(TokenNormalization {
+                case_folding: CaseFolding::Lowercase,
+                ..TokenNormalization::default()
+            })
+            .with_rules(Arc::new(rules))
+            .with_multiword(Arc::new(lexicon));
+        assert_eq!(iter.next().unwrap().unwrap(), vec!["in", "new_york"]);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn sentence_iterator_unicode_normalization_test() {
+        // "café" with a combining acute accent and the "ﬁ" ligature.
+        let v = "cafe\u{301} ﬁx\n".as_bytes().to_vec();
+
+        let mut iter =
+            SentenceIterator::new(Cursor::new(v.clone())).with_normalization(TokenNormalization {
+                form: NormalizationForm::Nfc,
+                ..TokenNormalization::default()
+            });
+        assert_eq!(iter.next().unwrap().unwrap(), vec!["caf\u{e9}", "ﬁx"]);
+
+        let mut iter =
+            SentenceIterator::new(Cursor::new(v)).with_normalization(TokenNormalization {
+                form: NormalizationForm::Nfkc,
+                ..TokenNormalization::default()
+            });
+        assert_eq!(iter.next().unwrap().unwrap(), vec!["caf\u{e9}", "fix"]);
+    }
+
+    #[test]
+    fn sentence_iterator_number_normalization_test() {
+        let v = "In 1984 , 3.5 % were 20-somethings .\n".as_bytes().to_vec();
+
+        let mut iter =
+            SentenceIterator::new(Cursor::new(v.clone())).with_normalization(TokenNormalization {
+                numbers: NumberNormalization::Digits,
+                ..TokenNormalization::default()
+            });
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            vec!["In", "0000", ",", "0.0", "%", "were", "00-somethings", "."]
+        );
+
+        let mut iter =
+            SentenceIterator::new(Cursor::new(v)).with_normalization(TokenNormalization {
+                numbers: NumberNormalization::Placeholder,
+                ..TokenNormalization::default()
+            });
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            vec![
+                "In",
+                "<num>",
+                ",",
+                "<num>",
+                "%",
+                "were",
+                "20-somethings",
+                "."
+            ]
+        );
+    }
+
+    #[test]
+    fn sentence_iterator_jsonl_test() {
+        let v = br#"{"id": 1, "body": "This is a sentence ."}
+
+{"id": 2, "body": null}
+{"id": 3, "body": "And \"another\" one."}
+"#
+        .to_vec();
+        let mut iter = SentenceIterator::new(Cursor::new(v))
+            .with_format(CorpusFormat::Jsonl)
+            .with_text_field("body")
+            .with_tokenizer(Tokenizer::Punctuation);
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            vec!["This", "is", "a", "sentence", "."]
+        );
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            vec!["And", "\"", "another", "\"", "one", "."]
+        );
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn sentence_iterator_weights_test() {
+        let v = b"2.5\tThis is a sentence .\n0\tAnd another one .\n1\t\n".to_vec();
+        let mut iter = SentenceIterator::new(Cursor::new(v))
+            .with_weights(true)
+            .weighted();
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            (
+                2.5,
+                vec![
+                    "This".to_string(),
+                    "is".into(),
+                    "a".into(),
+                    "sentence".into(),
+                    ".".into()
+                ]
+            )
+        );
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            (
+                0.,
+                vec![
+                    "And".to_string(),
+                    "another".into(),
+                    "one".into(),
+                    ".".into()
+                ]
+            )
+        );
+        assert!(iter.next().is_none());
+
+        let v = b"-1\tInvalid weight .\n".to_vec();
+        let mut iter = SentenceIterator::new(Cursor::new(v)).with_weights(true);
+        assert!(iter.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn sentence_iterator_jsonl_weights_test() {
+        let v = br#"{"text": "Hello world", "w": 3}
+{"text": "Goodbye"}
+{"text": "Invalid", "w": "high"}
+"#
+        .to_vec();
+        let mut iter = SentenceIterator::new(Cursor::new(v))
+            .with_format(CorpusFormat::Jsonl)
+            .with_weights(true)
+            .with_weight_field("w")
+            .weighted();
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            (3., vec!["Hello".to_string(), "world".into()])
+        );
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            (1., vec!["Goodbye".to_string()])
+        );
+        assert!(iter.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn sentence_iterator_jsonl_missing_field_test() {
+        let v = br#"{"id": 1, "text": "Hello world"}"#.to_vec();
+        let mut iter = SentenceIterator::new(Cursor::new(v))
+            .with_format(CorpusFormat::Jsonl)
+            .with_text_field("body");
+        assert!(iter.next().unwrap().is_err());
+    }
+
+    static CONLLU_TEST_DATA: &str = "# text = Im Haus.\n\
+1-2\tIm\t_\t_\t_\t_\t_\t_\t_\t_\n\
+1\tin\tin\tADP\t_\t_\t3\tcase\t3:case\t_\n\
+2\tdem\tder\tDET\t_\t_\t3\tdet\t3:det\t_\n\
+3\tHaus\tHaus\tNOUN\t_\t_\t0\troot\t0:root\t_\n\
+3.1\tist\tsein\tAUX\t_\t_\t_\t_\t3:cop\t_\n\
+4\t.\t.\tPUNCT\t_\t_\t3\tpunct\t3:punct\t_\n\
+\n\
+1\tJa\tja\tINTJ\t_\t_\t0\troot\t0:root\t_\n";
+
+    #[test]
+    fn sentence_iterator_tokenizer_test() {
+        let v = b"\"Don't panic,\" she said (again).\n".to_vec();
+        let mut iter = SentenceIterator::new(Cursor::new(v)).with_tokenizer(Tokenizer::Punctuation);
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            vec!["\"", "Don't", "panic", ",", "\"", "she", "said", "(", "again", ")", "."]
+        );
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn punctuation_tokenize_test() {
+        assert_eq!(
+            Tokenizer::Punctuation.tokenize("Pi is 3.14... e-mail: a@b.org!"),
+            vec!["Pi", "is", "3.14", ".", ".", ".", "e-mail", ":", "a@b.org", "!"]
+        );
+        assert!(Tokenizer::Punctuation.tokenize(" \t").is_empty());
+    }
+
+    #[test]
+    fn shuffle_buffer_test() {
+        let mut shuffled =
+            ShuffleBuffer::new(0..100, 10, XorShiftRng::seed_from_u64(42)).collect::<Vec<_>>();
+        assert_ne!(shuffled, (0..100).collect::<Vec<_>>());
+
+        // Items cannot be returned before they are read in the buffer.
+        for (idx, &item) in shuffled.iter().enumerate() {
+            assert!(item < idx + 10);
+        }
+
+        shuffled.sort_unstable();
+        assert_eq!(shuffled, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shuffle_buffer_identity_test() {
+        let items =
+            ShuffleBuffer::new(0..10, 1, XorShiftRng::seed_from_u64(42)).collect::<Vec<_>>();
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sentence_iterator_conllu_test() {
+        let mut iter =
+            SentenceIterator::new(Cursor::new(CONLLU_TEST_DATA)).with_format(CorpusFormat::Conllu);
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            vec!["in", "dem", "Haus", "."]
+        );
+        assert_eq!(iter.next().unwrap().unwrap(), vec!["Ja"]);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn sentence_iterator_vertical_test() {
+        let data = "<doc id=\"1\">\n<s>\nin\tAPPR\tin\ndem\tART\tder\nHaus\tNN\tHaus\n</s>\n\
+                    <s>\n<\t$(\t<\n.\t$.\t.\n</s>\n</doc>\nJa\n\nnein\n";
+        let mut iter = SentenceIterator::new(Cursor::new(data)).with_format(CorpusFormat::Vertical);
+        assert_eq!(iter.next().unwrap().unwrap(), vec!["in", "dem", "Haus"]);
+        assert_eq!(iter.next().unwrap().unwrap(), vec!["<", "."]);
+        assert_eq!(iter.next().unwrap().unwrap(), vec!["Ja"]);
+        assert_eq!(iter.next().unwrap().unwrap(), vec!["nein"]);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn conllu_filter_test() {
+        let mut filtered = String::new();
+        ConlluFilter::new(Cursor::new(CONLLU_TEST_DATA))
+            .read_to_string(&mut filtered)
+            .unwrap();
+        assert_eq!(
+            filtered,
+            "# text = Im Haus.\n\
+             1\tin\tin\tADP\t_\t_\t3\tcase\t_\t_\n\
+             2\tdem\tder\tDET\t_\t_\t3\tdet\t_\t_\n\
+             3\tHaus\tHaus\tNOUN\t_\t_\t0\troot\t_\t_\n\
+             4\t.\t.\tPUNCT\t_\t_\t3\tpunct\t_\t_\n\
+             \n\
+             1\tJa\tja\tINTJ\t_\t_\t0\troot\t_\t_\n"
+        );
+    }
+
     static CHUNKING_TEST_DATA: &str =
         "a b c\nd e f\ng h i\nj k l\nm n o\np q r\ns t u\nv w x\ny z\n";
 
@@ -414,10 +3496,273 @@ mod tests {
         assert_eq!(start, 39, "Incorrect start index");
     }
 
+    #[test]
+    fn thread_range_test() {
+        let data = CHUNKING_TEST_DATA.as_bytes();
+        assert_eq!(thread_range(data, 0, 3, SentenceSeparator::Line), 0..18);
+        assert_eq!(thread_range(data, 1, 3, SentenceSeparator::Line), 18..36);
+        assert_eq!(
+            thread_range(data, 2, 3, SentenceSeparator::Line),
+            36..data.len()
+        );
+
+        let data = CHUNKING_TEST_DATA_DEPS.as_bytes();
+        assert_eq!(
+            thread_range(data, 0, 3, SentenceSeparator::EmptyLine),
+            0..26
+        );
+        assert_eq!(
+            thread_range(data, 1, 3, SentenceSeparator::EmptyLine),
+            26..39
+        );
+        assert_eq!(
+            thread_range(data, 2, 3, SentenceSeparator::EmptyLine),
+            39..data.len()
+        );
+    }
+
+    #[test]
+    fn thread_data_ranges_test() {
+        let sentences = (0..3)
+            .map(|thread| {
+                let data = ThreadData::text("testdata/chunking.txt", thread, 3).unwrap();
+                let mut sentences = String::new();
+                data.pass().unwrap().read_to_string(&mut sentences).unwrap();
+                sentences
+            })
+            .collect::<String>();
+        assert_eq!(sentences, CHUNKING_TEST_DATA);
+    }
+
+    /// Read the sentences of `thread` with `SentenceRange`.
+    fn read_sentence_range(
+        data: &[u8],
+        thread: usize,
+        n_threads: usize,
+        separator: SentenceSeparator,
+    ) -> Option<Vec<u8>> {
+        let range = chunk_range(data.len() as u64, thread, n_threads);
+        let mut sentences = Vec::new();
+        SentenceRange::new(&data[range.start as usize..], separator, range)
+            .unwrap()?
+            .read_to_end(&mut sentences)
+            .unwrap();
+        Some(sentences)
+    }
+
+    #[test]
+    fn sentence_range_matches_thread_range() {
+        for &(data, separator) in &[
+            (CHUNKING_TEST_DATA, SentenceSeparator::Line),
+            (CHUNKING_TEST_DATA_DEPS, SentenceSeparator::EmptyLine),
+            ("a\n\n\nb\n\n\n\nc\nd\n\n", SentenceSeparator::EmptyLine),
+        ] {
+            let data = data.as_bytes();
+            for n_threads in 1..=data.len() {
+                for thread in 0..n_threads {
+                    let range = thread_range(data, thread, n_threads, separator);
+                    let sentences = read_sentence_range(data, thread, n_threads, separator);
+                    if range.is_empty() {
+                        assert_eq!(sentences, None);
+                    } else {
+                        assert_eq!(sentences.as_deref(), Some(&data[range]));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shared_corpus_distributes_sentences() {
+        let mut encoder = GzEncoder::new(NamedTempFile::new().unwrap(), Default::default());
+        encoder.write_all(CHUNKING_TEST_DATA.as_bytes()).unwrap();
+        let f = encoder.finish().unwrap();
+
+        let corpus = SharedCorpus::get(f.path(), Compression::Gzip, SentenceSeparator::Line, 2);
+        let threads = (0..2)
+            .map(|thread| SharedCorpusThread::new(corpus.clone(), thread))
+            .collect::<Vec<_>>();
+
+        for _ in 0..2 {
+            let mut readers = threads.iter().map(|t| t.pass()).collect::<Vec<_>>();
+            let mut even = String::new();
+            readers[0].read_to_string(&mut even).unwrap();
+            assert_eq!(even, "a b c\ng h i\nm n o\ns t u\ny z\n");
+            let mut odd = String::new();
+            readers[1].read_to_string(&mut odd).unwrap();
+            assert_eq!(odd, "d e f\nj k l\np q r\nv w x\n");
+        }
+
+        // A thread that stops reading does not hold back other threads.
+        let mut threads = threads.into_iter();
+        let thread0 = threads.next().unwrap();
+        drop(threads);
+        let mut even = String::new();
+        thread0.pass().read_to_string(&mut even).unwrap();
+        assert_eq!(even, "a b c\ng h i\nm n o\ns t u\ny z\n");
+    }
+
     #[should_panic]
     #[test]
     fn thread_data_out_of_bounds_test() {
         let f = File::open("testdata/chunking.txt").unwrap();
         let _ = thread_data_conllu(&f, 3, 3).unwrap();
     }
+
+    #[test]
+    fn strided_sentences_text_test() {
+        let data = b"a b\nc d\ne f\ng h\ni j\n";
+
+        let mut strided = String::new();
+        StridedSentences::new(&data[..], SentenceSeparator::Line, 0, 2)
+            .read_to_string(&mut strided)
+            .unwrap();
+        assert_eq!(strided, "a b\ne f\ni j\n");
+
+        let mut strided = String::new();
+        StridedSentences::new(&data[..], SentenceSeparator::Line, 1, 2)
+            .read_to_string(&mut strided)
+            .unwrap();
+        assert_eq!(strided, "c d\ng h\n");
+    }
+
+    #[test]
+    fn strided_sentences_conllu_test() {
+        let mut strided = String::new();
+        StridedSentences::new(
+            CHUNKING_TEST_DATA_DEPS.as_bytes(),
+            SentenceSeparator::EmptyLine,
+            1,
+            3,
+        )
+        .read_to_string(&mut strided)
+        .unwrap();
+        assert_eq!(strided, "g h i\nj k l\n\n");
+
+        let mut strided = String::new();
+        StridedSentences::new(
+            CHUNKING_TEST_DATA_DEPS.as_bytes(),
+            SentenceSeparator::EmptyLine,
+            0,
+            3,
+        )
+        .read_to_string(&mut strided)
+        .unwrap();
+        assert_eq!(strided, "a b c\nd e f\n\ns t u\nv w x\ny z\n");
+    }
+
+    #[test]
+    fn is_remote_corpus_test() {
+        assert!(is_remote_corpus("https://example.com/corpus.txt"));
+        assert!(is_remote_corpus("s3://corpora/corpus.txt"));
+        assert!(!is_remote_corpus("corpus.txt"));
+        assert!(!is_remote_corpus("/data/https/corpus.txt"));
+    }
+
+    #[test]
+    fn compression_from_magic_test() {
+        assert_eq!(
+            Compression::from_magic(&[0x1f, 0x8b, 0x08]),
+            Compression::Gzip
+        );
+        assert_eq!(Compression::from_magic(b"BZh91AY"), Compression::Bzip2);
+        assert_eq!(
+            Compression::from_magic(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+            Compression::Xz
+        );
+        assert_eq!(
+            Compression::from_magic(&[0x28, 0xb5, 0x2f, 0xfd]),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::from_magic(b"a b c\n"), Compression::None);
+        assert_eq!(Compression::from_magic(b""), Compression::None);
+    }
+
+    #[test]
+    fn decompress_gzip_test() {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(CHUNKING_TEST_DATA.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decompressed = String::new();
+        Compression::Gzip
+            .decompress(&compressed[..])
+            .unwrap()
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, CHUNKING_TEST_DATA);
+    }
+
+    fn read_chain(parts: &[&'static [u8]], separator: SentenceSeparator) -> String {
+        let readers = parts
+            .iter()
+            .map(|&part| -> Result<Box<dyn BufRead>> { Ok(Box::new(part)) })
+            .collect::<Vec<_>>();
+        let mut chained = String::new();
+        CorpusChain::new(readers.into_iter(), separator)
+            .read_to_string(&mut chained)
+            .unwrap();
+        chained
+    }
+
+    #[test]
+    fn corpus_chain_text_test() {
+        assert_eq!(
+            read_chain(&[b"a b\nc d", b"", b"e f\n"], SentenceSeparator::Line),
+            "a b\nc d\ne f\n"
+        );
+    }
+
+    #[test]
+    fn corpus_chain_conllu_test() {
+        assert_eq!(
+            read_chain(
+                &[b"a\nb\n", b"c\n\n", b"\n", b"d"],
+                SentenceSeparator::EmptyLine
+            ),
+            "a\nb\n\nc\n\n\nd\n\n"
+        );
+    }
+
+    #[test]
+    fn thread_corpus_test() {
+        let files = ["a b\n", "c d\n", "e f\n"]
+            .iter()
+            .map(|contents| {
+                let mut f = NamedTempFile::new().unwrap();
+                f.write_all(contents.as_bytes()).unwrap();
+                f
+            })
+            .collect::<Vec<_>>();
+        let paths = files.iter().map(|f| f.path()).collect::<Vec<_>>();
+
+        // Files are sharded when there are at least as many files as threads.
+        let mut data = String::new();
+        ThreadCorpus::text(&paths, 0, 2)
+            .unwrap()
+            .pass()
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "a b\ne f\n");
+
+        let mut data = String::new();
+        ThreadCorpus::text(&paths, 1, 2)
+            .unwrap()
+            .pass()
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "c d\n");
+
+        // Files are shared when there are more threads than files.
+        let mut data = String::new();
+        ThreadCorpus::text(&paths[..1], 1, 2)
+            .unwrap()
+            .pass()
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "a b\n");
+    }
 }
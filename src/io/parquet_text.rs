@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use arrow::array::{Array, GenericStringArray, StringOffsetSizeTrait};
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReader;
+use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+use parquet::file::reader::SerializedFileReader;
+
+/// The number of rows that is read at once.
+const BATCH_SIZE: usize = 8192;
+
+/// Reader of a text column of a Parquet file.
+///
+/// The file is read in record batches of the text column. Every text
+/// becomes a line, newlines within a text are replaced by spaces. Null
+/// values are skipped.
+pub struct ParquetText {
+    batches: ParquetRecordBatchReader,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl ParquetText {
+    /// Open the column `column` of the Parquet file at `path`.
+    pub fn open(path: &Path, column: &str) -> Result<Self> {
+        let f = File::open(path)
+            .with_context(|| format!("Cannot open corpus for reading: {}", path.display()))?;
+        let file_reader = SerializedFileReader::new(f)
+            .with_context(|| format!("Cannot read Parquet file: {}", path.display()))?;
+        let mut arrow_reader = ParquetFileArrowReader::new(Arc::new(file_reader));
+
+        let schema = arrow_reader
+            .get_schema()
+            .context("Cannot read Parquet schema")?;
+        let column_idx = schema
+            .index_of(column)
+            .with_context(|| format!("Parquet file does not have a '{}' column", column))?;
+        match schema.field(column_idx).data_type() {
+            DataType::Utf8 | DataType::LargeUtf8 => (),
+            data_type => bail!(
+                "Column '{}' does not contain strings, but: {:?}",
+                column,
+                data_type
+            ),
+        }
+
+        let batches = arrow_reader
+            .get_record_reader_by_columns(vec![column_idx], BATCH_SIZE)
+            .context("Cannot read Parquet record batches")?;
+
+        Ok(ParquetText {
+            batches,
+            buf: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Read the next non-empty batch into the buffer.
+    ///
+    /// The buffer is left empty if there are no batches left.
+    fn read_batch(&mut self) -> io::Result<()> {
+        self.buf.clear();
+        self.pos = 0;
+
+        while self.buf.is_empty() {
+            let batch = match self.batches.next() {
+                Some(batch) => batch.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?,
+                None => return Ok(()),
+            };
+
+            append_batch_lines(&batch, &mut self.buf);
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for ParquetText {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n_read = self.fill_buf()?.read(buf)?;
+        self.consume(n_read);
+        Ok(n_read)
+    }
+}
+
+impl BufRead for ParquetText {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            self.read_batch()?;
+        }
+
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+/// Append the texts of the single column of a batch as lines.
+fn append_batch_lines(batch: &RecordBatch, buf: &mut Vec<u8>) {
+    let column = batch.column(0);
+    match column.data_type() {
+        DataType::LargeUtf8 => append_lines::<i64>(column.as_ref(), buf),
+        _ => append_lines::<i32>(column.as_ref(), buf),
+    }
+}
+
+fn append_lines<O>(column: &dyn Array, buf: &mut Vec<u8>)
+where
+    O: StringOffsetSizeTrait,
+{
+    let texts = column
+        .as_any()
+        .downcast_ref::<GenericStringArray<O>>()
+        .expect("Column should be a string array");
+
+    for idx in 0..texts.len() {
+        if texts.is_null(idx) {
+            continue;
+        }
+
+        buf.extend(texts.value(idx).bytes().map(|b| match b {
+            b'\n' | b'\r' => b' ',
+            b => b,
+        }));
+        buf.push(b'\n');
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Result};
+use whatlang::{Detector, Lang};
+
+/// ISO 639-1 codes of the languages that can be identified.
+static ISO_639_1: &[(&str, Lang)] = &[
+    ("af", Lang::Afr),
+    ("ak", Lang::Aka),
+    ("am", Lang::Amh),
+    ("ar", Lang::Ara),
+    ("az", Lang::Aze),
+    ("be", Lang::Bel),
+    ("bg", Lang::Bul),
+    ("bn", Lang::Ben),
+    ("ca", Lang::Cat),
+    ("cs", Lang::Ces),
+    ("da", Lang::Dan),
+    ("de", Lang::Deu),
+    ("el", Lang::Ell),
+    ("en", Lang::Eng),
+    ("eo", Lang::Epo),
+    ("es", Lang::Spa),
+    ("et", Lang::Est),
+    ("fa", Lang::Pes),
+    ("fi", Lang::Fin),
+    ("fr", Lang::Fra),
+    ("gu", Lang::Guj),
+    ("he", Lang::Heb),
+    ("hi", Lang::Hin),
+    ("hr", Lang::Hrv),
+    ("hu", Lang::Hun),
+    ("hy", Lang::Hye),
+    ("id", Lang::Ind),
+    ("it", Lang::Ita),
+    ("ja", Lang::Jpn),
+    ("jv", Lang::Jav),
+    ("ka", Lang::Kat),
+    ("km", Lang::Khm),
+    ("kn", Lang::Kan),
+    ("ko", Lang::Kor),
+    ("la", Lang::Lat),
+    ("lt", Lang::Lit),
+    ("lv", Lang::Lav),
+    ("mk", Lang::Mkd),
+    ("ml", Lang::Mal),
+    ("mr", Lang::Mar),
+    ("my", Lang::Mya),
+    ("nb", Lang::Nob),
+    ("ne", Lang::Nep),
+    ("nl", Lang::Nld),
+    ("no", Lang::Nob),
+    ("or", Lang::Ori),
+    ("pa", Lang::Pan),
+    ("pl", Lang::Pol),
+    ("pt", Lang::Por),
+    ("ro", Lang::Ron),
+    ("ru", Lang::Rus),
+    ("si", Lang::Sin),
+    ("sk", Lang::Slk),
+    ("sl", Lang::Slv),
+    ("sn", Lang::Sna),
+    ("sr", Lang::Srp),
+    ("sv", Lang::Swe),
+    ("ta", Lang::Tam),
+    ("te", Lang::Tel),
+    ("th", Lang::Tha),
+    ("tk", Lang::Tuk),
+    ("tl", Lang::Tgl),
+    ("tr", Lang::Tur),
+    ("uk", Lang::Ukr),
+    ("ur", Lang::Urd),
+    ("uz", Lang::Uzb),
+    ("vi", Lang::Vie),
+    ("yi", Lang::Yid),
+    ("zh", Lang::Cmn),
+    ("zu", Lang::Zul),
+];
+
+/// Filter of sentences by language.
+///
+/// The language of a sentence is identified from its text. Alternatively,
+/// the language can be read from a field of JSON Lines records, see
+/// `with_field`. Languages are specified by their ISO 639-1 (e.g. *nl*)
+/// or ISO 639-3 (e.g. *nld*) codes.
+#[derive(Clone, Debug)]
+pub struct LanguageFilter {
+    lang: Lang,
+    field: Option<String>,
+}
+
+impl LanguageFilter {
+    /// Construct a filter that accepts sentences in the language `code`.
+    pub fn new(code: &str) -> Result<Self> {
+        let code = code.to_lowercase();
+        let lang = ISO_639_1
+            .iter()
+            .find(|(iso_639_1, _)| *iso_639_1 == code)
+            .map(|&(_, lang)| lang)
+            .or_else(|| Lang::from_code(&code))
+            .ok_or_else(|| anyhow!("Language cannot be identified: {}", code))?;
+
+        Ok(LanguageFilter { lang, field: None })
+    }
+
+    /// Read the language from a field of JSON Lines records.
+    ///
+    /// The language of a record is not identified from its text when the
+    /// field is set. Records without this field are rejected.
+    pub fn with_field(mut self, field: impl Into<String>) -> Self {
+        self.field = Some(field.into());
+        self
+    }
+
+    /// Get the field with the language of JSON Lines records.
+    pub fn field(&self) -> Option<&str> {
+        self.field.as_deref()
+    }
+
+    /// Get the ISO 639-3 code of the language.
+    pub fn code(&self) -> &'static str {
+        self.lang.code()
+    }
+
+    /// Check whether a sentence is in the language of the filter.
+    ///
+    /// Sentences of which the language cannot be identified, such as
+    /// sentences that only consist of numbers, are rejected.
+    pub fn accepts(&self, tokens: &[String]) -> bool {
+        Detector::new()
+            .detect_lang(&tokens.join(" "))
+            .map(|lang| lang == self.lang)
+            .unwrap_or(false)
+    }
+
+    /// Check whether a language tag is the language of the filter.
+    ///
+    /// The tag is an ISO 639-1 or ISO 639-3 code.
+    pub fn accepts_tag(&self, tag: &str) -> bool {
+        LanguageFilter::new(tag)
+            .map(|filter| filter.lang == self.lang)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LanguageFilter;
+
+    fn tokens(sentence: &str) -> Vec<String> {
+        sentence.split_whitespace().map(ToOwned::to_owned).collect()
+    }
+
+    #[test]
+    fn language_filter_test() {
+        let filter = LanguageFilter::new("nl").unwrap();
+        assert_eq!(filter.code(), "nld");
+        assert!(filter.accepts(&tokens(
+            "Het is vandaag een mooie dag om een wandeling in het bos te maken ."
+        )));
+        assert!(!filter.accepts(&tokens(
+            "Today is a beautiful day to take a walk in the forest ."
+        )));
+        assert!(!filter.accepts(&tokens("42 1337")));
+    }
+
+    #[test]
+    fn language_filter_tag_test() {
+        let filter = LanguageFilter::new("NLD").unwrap();
+        assert!(filter.accepts_tag("nl"));
+        assert!(filter.accepts_tag("nld"));
+        assert!(!filter.accepts_tag("de"));
+        assert!(!filter.accepts_tag("unknown"));
+        assert!(LanguageFilter::new("xx").is_err());
+    }
+}
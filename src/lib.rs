@@ -1,41 +1,144 @@
+pub(crate) mod cbow_trainer;
+pub use crate::cbow_trainer::CbowTrainer;
+
+mod checkpoint;
+pub use crate::checkpoint::{Checkpoint, ThreadCheckpoint};
+
+pub(crate) mod constraints;
+pub use crate::constraints::{LexicalConstraint, LexicalConstraints};
+
 mod config;
 pub use crate::config::{
-    BucketConfig, BucketIndexerType, CommonConfig, DepembedsConfig, LossType, ModelType,
-    NGramConfig, SimpleVocabConfig, SkipGramConfig, SubwordVocabConfig,
+    BpeConfig, BucketConfig, BucketIndexerType, CaseFolding, CommonConfig, DepembedsConfig,
+    Doc2vecConfig, Doc2vecModelType, GloveConfig, LossType, LrScheduleType, ModelType,
+    MultisenseConfig, NGramConfig, NegativeSamplingDistribution, NormalizationForm,
+    NumberNormalization, OptimizerType, PmiConfig, PosTags, Precision, QuantizerConfig,
+    RetrofitConfig, RngStream, Script, ScriptNGrams, Sent2vecConfig, SentencePieceConfig,
+    SimpleVocabConfig, SkipGramConfig, SubwordVocabConfig, SupervisedConfig, TokenNormalization,
+    WordBoundaries, NUMBER_PLACEHOLDER,
 };
 
+pub(crate) mod cwindow_trainer;
+pub use crate::cwindow_trainer::CwindowTrainer;
+
 mod deps;
 pub use crate::deps::{DepIter, Dependency, DependencyIterator};
 
 pub(crate) mod dep_trainer;
 pub use crate::dep_trainer::DepembedsTrainer;
 
+mod dedup;
+pub use crate::dedup::{Dedup, DuplicateFilter};
+
+pub(crate) mod doc2vec_trainer;
+pub use crate::doc2vec_trainer::{Doc2vecTrainer, TaggedDocument};
+
+mod eval;
+pub use crate::eval::{AnalogyDataset, SimilarityDataset};
+
+pub(crate) mod floret;
+
+mod gensim;
+pub use crate::gensim::write_word2vec_gensim;
+
+pub(crate) mod glove;
+pub use crate::glove::{Cooccurrence, CooccurrenceMatrix, GloveSgd, GloveTrainer};
+
+#[cfg(feature = "gpu")]
+pub(crate) mod gpu;
+
+pub(crate) mod hnsw;
+pub use crate::hnsw::HnswIndex;
+
 pub(crate) mod hogwild;
 
 pub mod idx;
 
 pub mod io;
-pub use io::{SentenceIterator, WriteModelBinary, WriteModelText, WriteModelWord2Vec};
+pub use io::{
+    SentenceIterator, WriteModelBinary, WriteModelFastText, WriteModelFloret, WriteModelText,
+    WriteModelWord2Vec,
+};
+
+mod language;
+pub use crate::language::LanguageFilter;
+
+#[cfg(feature = "magnitude-output")]
+pub(crate) mod magnitude;
 
 pub(crate) mod loss;
 
 pub(crate) mod sampling;
 
+pub(crate) mod lr_schedule;
+pub use crate::lr_schedule::LrSchedule;
+
+pub(crate) mod matrix;
+
+pub(crate) mod multisense_trainer;
+pub use crate::multisense_trainer::MultisenseTrainer;
+
+mod multiword;
+pub use crate::multiword::MultiwordLexicon;
+
+mod noise;
+pub use crate::noise::{
+    HtmlEntities, NoiseAction, NoiseFilters, EMAIL_PLACEHOLDER, HANDLE_PLACEHOLDER, URL_PLACEHOLDER,
+};
+
+pub(crate) mod npz;
+
+mod numa;
+pub use crate::numa::NumaTopology;
+
+pub(crate) mod optimizer;
+pub use crate::optimizer::{AdaGrad, Adam, Optimizer, OptimizerWrap, PlainSgd};
+
+pub(crate) mod pmi;
+pub use crate::pmi::{PmiTrainer, SparseMatrix};
+
+pub(crate) mod poswise_trainer;
+pub use crate::poswise_trainer::PoswiseTrainer;
+
+pub(crate) mod retrofit;
+pub use crate::retrofit::{retrofit, Lexicon};
+
 mod sgd;
 pub use crate::sgd::Sgd;
 
+mod sql;
+
 mod train_model;
-pub use crate::train_model::{TrainModel, Trainer};
+pub use crate::train_model::{
+    ConcatInputs, NegativeSamples, OutputLabels, SelectSense, Subsampling, TrainIterFrom,
+    TrainModel, Trainer,
+};
+
+pub(crate) mod sent2vec_trainer;
+pub use crate::sent2vec_trainer::Sent2vecTrainer;
 
 pub(crate) mod skipgram_trainer;
 pub use crate::skipgram_trainer::SkipgramTrainer;
 
+pub(crate) mod supervised_trainer;
+pub use crate::supervised_trainer::{SupervisedTrainer, LABEL_PREFIX};
+
+mod token_rules;
+pub use crate::token_rules::TokenRules;
+
 pub(crate) mod util;
+pub use crate::util::Reseed;
 
 #[doc(hidden)]
 pub mod vec_simd;
 
 mod vocab;
 pub use crate::vocab::{
-    simple::SimpleVocab, subword::SubwordVocab, CountedType, Cutoff, Vocab, VocabBuilder, Word,
+    bpe::read_bpe_merges,
+    compound::{CompoundSplitter, FrequencySplitter},
+    counts::{read_vocab_counts, write_subword_counts, write_vocab_counts},
+    sentencepiece::SentencePieceModel,
+    simple::SimpleVocab,
+    subword::{read_segmentations, BucketCollision, BucketStatistics, SubwordVocab},
+    CountedType, Cutoff, HeapSize, Vocab, VocabBuilder, Word,
 };
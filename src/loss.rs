@@ -19,6 +19,90 @@ use crate::vec_simd::dot;
 /// round.
 const LOGISTIC_ROUND_ACTIVATION: f32 = 10.0;
 
+/// Return the loss and gradient of an output in margin ranking.
+///
+/// The margin ranking (hinge) loss requires the positive output to score
+/// higher than every negative output by at least `margin`. The score of
+/// an output is the inner product *u·v* of the input and output
+/// embeddings. The loss of a negative output *v* is:
+///
+/// max(0, margin - s_p + u·v)
+///
+/// where *s_p* is the score of the positive output (`positive_score`).
+/// If the margin is violated, the partial gradient is -1. The loss of the
+/// positive output is the sum of the losses of the negatives, so this
+/// function returns a loss and partial gradient of zero for the positive
+/// output. Its partial gradient is the negated sum of the partial
+/// gradients of the negatives.
+pub fn margin_ranking_loss(
+    u: ArrayView1<f32>,
+    v: ArrayView1<f32>,
+    label: bool,
+    positive_score: f32,
+    margin: f32,
+) -> (f32, f32) {
+    margin_ranking_loss_from_score(dot(u, v), label, positive_score, margin)
+}
+
+/// Return the loss and gradient of an output in margin ranking.
+///
+/// This is `margin_ranking_loss` with the score *u·v* of the output
+/// already computed.
+pub fn margin_ranking_loss_from_score(
+    score: f32,
+    label: bool,
+    positive_score: f32,
+    margin: f32,
+) -> (f32, f32) {
+    if label {
+        return (0.0, 0.0);
+    }
+
+    let loss = margin - positive_score + score;
+    if loss > 0.0 {
+        (loss, -1.0)
+    } else {
+        (0.0, 0.0)
+    }
+}
+
+/// Return the loss and gradients of an InfoNCE prediction.
+///
+/// InfoNCE (van den Oord et al., 2018) treats the prediction of an output
+/// as a classification of the positive output among a set of candidate
+/// outputs. `scores` are the inner products *u·v_j* of the input embedding
+/// and the candidate output embeddings, where the first score is the
+/// score of the positive output. The loss is the negative log-likelihood
+/// of the positive output under the softmax over the candidates:
+///
+/// -s_0 + log Σ_j e^{s_j}
+///
+/// The partial gradient of candidate *j* is y_j - p_j, where *p_j* is the
+/// softmax probability of the candidate and *y_j* is 1 for the positive
+/// output and 0 otherwise. As in the other losses, the partial gradients
+/// are returned such that the caller can compute the gradients for all
+/// components of u and v_j.
+pub fn info_nce_loss(scores: &[f32]) -> (f32, Vec<f32>) {
+    let max_score = scores.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let mut probs = scores
+        .iter()
+        .map(|&score| (score - max_score).exp())
+        .collect::<Vec<_>>();
+    let partition = probs.iter().sum::<f32>();
+    probs.iter_mut().for_each(|prob| *prob /= partition);
+
+    // Use log-sum-exp, so that the loss does not underflow for a
+    // positive output with a low probability.
+    let loss = max_score + partition.ln() - scores[0];
+    let grads = probs
+        .iter()
+        .enumerate()
+        .map(|(idx, prob)| (idx == 0) as usize as f32 - prob)
+        .collect();
+
+    (loss, grads)
+}
+
 /// Return the loss and gradient of the co-occurence classification.
 ///
 /// This function returns the negative log likelihood and gradient of
@@ -75,8 +159,14 @@ const LOGISTIC_ROUND_ACTIVATION: f32 = 10.0;
 /// We return y - σ(u·v) as the gradient, so that the caller can compute
 /// the gradient for all components of u and v.
 pub fn log_logistic_loss(u: ArrayView1<f32>, v: ArrayView1<f32>, label: bool) -> (f32, f32) {
-    let dp = dot(u, v);
-    let lf = logistic_function(dp);
+    log_logistic_loss_from_score(dot(u, v), label)
+}
+
+/// Return the loss and gradient of the co-occurence classification.
+///
+/// This is `log_logistic_loss` with the score *u·v* already computed.
+pub fn log_logistic_loss_from_score(score: f32, label: bool) -> (f32, f32) {
+    let lf = logistic_function(score);
     let grad = (label as usize) as f32 - lf;
     let loss = if label {
         -util::safe_ln(lf)
@@ -87,6 +177,64 @@ pub fn log_logistic_loss(u: ArrayView1<f32>, v: ArrayView1<f32>, label: bool) ->
     (loss, grad)
 }
 
+/// Return the loss and gradient of noise-contrastive estimation.
+///
+/// Noise-contrastive estimation (Gutmann and Hyvärinen, 2012) trains a
+/// classifier to distinguish observed co-occurrences from samples of a
+/// noise distribution *q*. With *k* noise samples per observed
+/// co-occurrence, the probability that the output was drawn from the data
+/// rather than the noise distribution is:
+///
+/// P(1|x) = σ(u·v - log(k q(v)))
+///
+/// `log_noise` is *log(k q(v))*, the log of the expected number of noise
+/// samples of the output. Since the correction term is a constant, the
+/// gradient with respect to *u·v* has the same form as in
+/// `log_logistic_loss`:
+///
+/// y - σ(u·v - log(k q(v)))
+///
+/// Unlike negative sampling, NCE corrects for the noise distribution, so
+/// that *u·v* approximates the log probability of the output.
+pub fn nce_loss(u: ArrayView1<f32>, v: ArrayView1<f32>, label: bool, log_noise: f32) -> (f32, f32) {
+    log_logistic_loss_from_score(dot(u, v) - log_noise, label)
+}
+
+/// Return the loss and gradient of a weighted least squares prediction.
+///
+/// This function returns the loss and gradient of the GloVe objective
+/// (Pennington et al., 2014) for a single co-occurrence:
+///
+/// J = ½ f(X_ij) (u·v + b_u + b_v - log X_ij)^2
+///
+/// where `prediction` is *u·v + b_u + b_v* and `cooccurrence` is the
+/// co-occurrence count *X_ij*. The weighting function is
+///
+/// f(x) = (x/x_max)^α if x < x_max, 1 otherwise
+///
+/// The derivative with respect to the prediction is:
+///
+/// ∂J/∂p = f(X_ij) (p - log X_ij)
+///
+/// This derivative is returned as the gradient, the caller can then
+/// compute the gradients of the embeddings and biases.
+pub fn weighted_least_squares_loss(
+    prediction: f32,
+    cooccurrence: f32,
+    x_max: f32,
+    alpha: f32,
+) -> (f32, f32) {
+    let weight = if cooccurrence < x_max {
+        (cooccurrence / x_max).powf(alpha)
+    } else {
+        1.0
+    };
+    let diff = prediction - cooccurrence.ln();
+    let grad = weight * diff;
+
+    (0.5 * grad * diff, grad)
+}
+
 /// Compute the logistic function.
 ///
 /// **σ(a) = 1 / (1 + e^{-a})**
@@ -106,7 +254,10 @@ mod tests {
 
     use crate::util::{all_close, close};
 
-    use super::{log_logistic_loss, logistic_function};
+    use super::{
+        info_nce_loss, log_logistic_loss, logistic_function, margin_ranking_loss, nce_loss,
+        weighted_least_squares_loss,
+    };
 
     #[test]
     fn logistic_function_test() {
@@ -124,6 +275,29 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn info_nce_loss_test() {
+        // A single candidate is always predicted correctly.
+        let (loss, gradients) = info_nce_loss(&[2.0]);
+        assert!(close(loss, 0.0, 1e-5));
+        assert!(all_close(&gradients, &[0.0], 1e-5));
+
+        // Equal scores: p = 0.25 for each candidate.
+        let (loss, gradients) = info_nce_loss(&[1.0, 1.0, 1.0, 1.0]);
+        assert!(close(loss, 4f32.ln(), 1e-5));
+        assert!(all_close(&gradients, &[0.75, -0.25, -0.25, -0.25], 1e-5));
+
+        // Softmax of [2, 0]: p = [0.88080, 0.11920]
+        let (loss, gradients) = info_nce_loss(&[2.0, 0.0]);
+        assert!(close(loss, 0.12693, 1e-5));
+        assert!(all_close(&gradients, &[0.11920, -0.11920], 1e-5));
+
+        // Large scores do not overflow.
+        let (loss, gradients) = info_nce_loss(&[100.0, 200.0]);
+        assert!(close(loss, 100.0, 1e-3));
+        assert!(all_close(&gradients, &[-1.0, 1.0], 1e-5));
+    }
+
     #[test]
     fn log_logistic_loss_test() {
         let a = Array1::from_shape_vec((6,), vec![1., 1., 1., 0., 0., 0.]).unwrap();
@@ -150,4 +324,73 @@ mod tests {
         assert!(close(loss, 3.04838, 1e-5));
         assert!(close(gradient, 0.95257, 1e-5));
     }
+
+    #[test]
+    fn margin_ranking_loss_test() {
+        let a = Array1::from_shape_vec((6,), vec![1., 1., 1., 0., 0., 0.]).unwrap();
+        let a_orth = Array1::from_shape_vec((6,), vec![0., 0., 0., 1., 1., 1.]).unwrap();
+
+        // The loss of the positive output is accumulated by the negatives.
+        let (loss, gradient) = margin_ranking_loss(a.view(), a.view(), true, 3.0, 1.0);
+        assert!(close(loss, 0.0, 1e-5));
+        assert!(close(gradient, 0.0, 1e-5));
+
+        // The negative is within the margin: 1 - 0.5 + 0 = 0.5
+        let (loss, gradient) = margin_ranking_loss(a.view(), a_orth.view(), false, 0.5, 1.0);
+        assert!(close(loss, 0.5, 1e-5));
+        assert!(close(gradient, -1.0, 1e-5));
+
+        // The positive outscores the negative by more than the margin.
+        let (loss, gradient) = margin_ranking_loss(a.view(), a_orth.view(), false, 1.5, 1.0);
+        assert!(close(loss, 0.0, 1e-5));
+        assert!(close(gradient, 0.0, 1e-5));
+    }
+
+    #[test]
+    fn nce_loss_test() {
+        let a = Array1::from_shape_vec((6,), vec![1., 1., 1., 0., 0., 0.]).unwrap();
+        let a_orth = Array1::from_shape_vec((6,), vec![0., 0., 0., 1., 1., 1.]).unwrap();
+
+        // Without correction, NCE is equal to the log-logistic loss.
+        let (loss, gradient) = nce_loss(a.view(), a.view(), true, 0.0);
+        let (ll_loss, ll_gradient) = log_logistic_loss(a.view(), a.view(), true);
+        assert!(close(loss, ll_loss, 1e-5));
+        assert!(close(gradient, ll_gradient, 1e-5));
+
+        // log(k q(v)) = log(5 * 0.2) = 0
+        let (loss, gradient) = nce_loss(a.view(), a_orth.view(), true, (5. * 0.2f32).ln());
+        assert!(close(loss, 0.69312, 1e-5));
+        assert!(close(gradient, 0.5, 1e-5));
+
+        // Frequent noise words are penalized less: σ(0 - log(3)) = 0.25
+        let (loss, gradient) = nce_loss(a.view(), a_orth.view(), false, 3f32.ln());
+        assert!(close(loss, 0.28768, 1e-5));
+        assert!(close(gradient, -0.25, 1e-5));
+
+        let (loss, gradient) = nce_loss(a.view(), a_orth.view(), true, 3f32.ln());
+        assert!(close(loss, 1.38629, 1e-5));
+        assert!(close(gradient, 0.75, 1e-5));
+    }
+
+    #[test]
+    fn weighted_least_squares_loss_test() {
+        // Perfect prediction of the log count.
+        let (loss, gradient) = weighted_least_squares_loss(10f32.ln(), 10., 100., 0.75);
+        assert!(close(loss, 0.0, 1e-5));
+        assert!(close(gradient, 0.0, 1e-5));
+
+        // Below x_max: f(10) = (10/100)^0.75 = 0.17783
+        let (loss, gradient) = weighted_least_squares_loss(1.0, 10., 100., 0.75);
+        assert!(close(gradient, 0.17783 * (1.0 - 10f32.ln()), 1e-5));
+        assert!(close(
+            loss,
+            0.5 * 0.17783 * (1.0 - 10f32.ln()).powi(2),
+            1e-5
+        ));
+
+        // Above x_max the weight is 1.
+        let (loss, gradient) = weighted_least_squares_loss(1.0, 200., 100., 0.75);
+        assert!(close(gradient, 1.0 - 200f32.ln(), 1e-5));
+        assert!(close(loss, 0.5 * (1.0 - 200f32.ln()).powi(2), 1e-5));
+    }
 }
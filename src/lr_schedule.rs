@@ -0,0 +1,120 @@
+use std::f32::consts::PI;
+
+use crate::config::{CommonConfig, LrScheduleType};
+
+/// Factor by which the step schedule decays the learning rate every epoch.
+const STEP_DECAY: f32 = 0.5;
+
+/// Learning rate schedule.
+///
+/// The schedule determines the learning rate from the number of training
+/// steps (typically processed tokens) that were taken. During the warmup
+/// steps, the learning rate increases linearly from zero to the initial
+/// learning rate. After warmup, the learning rate decays from the initial
+/// learning rate following the schedule type.
+#[derive(Clone, Copy, Debug)]
+pub struct LrSchedule {
+    start_lr: f32,
+    schedule: LrScheduleType,
+    epochs: u32,
+    warmup: usize,
+    n_steps: usize,
+}
+
+impl LrSchedule {
+    /// Construct the schedule of `config` for training with `n_steps` steps.
+    pub fn new(config: &CommonConfig, n_steps: usize) -> Self {
+        LrSchedule {
+            start_lr: config.lr,
+            schedule: config.lr_schedule,
+            epochs: config.epochs,
+            warmup: (config.warmup as usize).min(n_steps),
+            n_steps,
+        }
+    }
+
+    /// Get the learning rate after `step` training steps.
+    pub fn lr(&self, step: usize) -> f32 {
+        if step < self.warmup {
+            return self.start_lr * step as f32 / self.warmup as f32;
+        }
+
+        let decay_steps = self.n_steps - self.warmup;
+        let progress = if decay_steps == 0 {
+            1.0
+        } else {
+            ((step - self.warmup) as f32 / decay_steps as f32).min(1.0)
+        };
+
+        let factor = match self.schedule {
+            LrScheduleType::Constant => 1.0,
+            LrScheduleType::Linear => 1.0 - progress,
+            LrScheduleType::Cosine => 0.5 * (1.0 + (PI * progress).cos()),
+            LrScheduleType::Step => {
+                let epoch = (progress * self.epochs as f32)
+                    .floor()
+                    .min(self.epochs as f32);
+                STEP_DECAY.powf(epoch)
+            }
+        };
+
+        self.start_lr * factor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LrSchedule;
+    use crate::util::close;
+    use crate::{CommonConfig, LrScheduleType};
+
+    fn test_config(lr_schedule: LrScheduleType, warmup: u64) -> CommonConfig {
+        CommonConfig {
+            epochs: 2,
+            lr: 0.1,
+            lr_schedule,
+            warmup,
+            ..CommonConfig::for_test()
+        }
+    }
+
+    #[test]
+    fn constant_schedule() {
+        let schedule = LrSchedule::new(&test_config(LrScheduleType::Constant, 0), 100);
+        assert!(close(schedule.lr(0), 0.1, 1e-6));
+        assert!(close(schedule.lr(99), 0.1, 1e-6));
+    }
+
+    #[test]
+    fn linear_schedule() {
+        let schedule = LrSchedule::new(&test_config(LrScheduleType::Linear, 0), 100);
+        assert!(close(schedule.lr(0), 0.1, 1e-6));
+        assert!(close(schedule.lr(25), 0.075, 1e-6));
+        assert!(close(schedule.lr(100), 0.0, 1e-6));
+    }
+
+    #[test]
+    fn cosine_schedule() {
+        let schedule = LrSchedule::new(&test_config(LrScheduleType::Cosine, 0), 100);
+        assert!(close(schedule.lr(0), 0.1, 1e-6));
+        assert!(close(schedule.lr(50), 0.05, 1e-6));
+        assert!(close(schedule.lr(100), 0.0, 1e-6));
+    }
+
+    #[test]
+    fn step_schedule() {
+        let schedule = LrSchedule::new(&test_config(LrScheduleType::Step, 0), 100);
+        assert!(close(schedule.lr(49), 0.1, 1e-6));
+        assert!(close(schedule.lr(50), 0.05, 1e-6));
+        assert!(close(schedule.lr(99), 0.05, 1e-6));
+    }
+
+    #[test]
+    fn warmup_schedule() {
+        let schedule = LrSchedule::new(&test_config(LrScheduleType::Linear, 20), 100);
+        assert!(close(schedule.lr(0), 0.0, 1e-6));
+        assert!(close(schedule.lr(10), 0.05, 1e-6));
+        assert!(close(schedule.lr(20), 0.1, 1e-6));
+        assert!(close(schedule.lr(60), 0.05, 1e-6));
+    }
+}
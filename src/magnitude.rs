@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::io::{self, Write};
+
+use anyhow::{ensure, Context, Result};
+use ndarray::ArrayView2;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection};
+use tempfile::NamedTempFile;
+
+/// Number of decimal digits of the stored embedding components.
+const MAGNITUDE_PRECISION: u32 = 7;
+
+/// Version of the Magnitude format.
+const MAGNITUDE_VERSION: i64 = 2;
+
+/// Write word embeddings in the Magnitude format.
+///
+/// Magnitude files are SQLite databases, which are read lazily by
+/// pymagnitude. The `magnitude` table has a row for every word, with the
+/// normalized embedding as fixed-point integers and the L2 norm of the
+/// embedding. Row *i* of `embeds` is the normalized embedding of
+/// `words[i]`, with the norm `norms[i]`.
+///
+/// The database is constructed in a temporary file, since SQLite cannot
+/// write to arbitrary writers, and copied to `write` afterwards.
+pub(crate) fn write_magnitude<W>(
+    write: &mut W,
+    words: &[String],
+    embeds: ArrayView2<f32>,
+    norms: &[f32],
+) -> Result<()>
+where
+    W: Write,
+{
+    ensure!(
+        words.len() == embeds.nrows() && words.len() == norms.len(),
+        "Number of words, embeddings, and norms differ"
+    );
+
+    let db_file = NamedTempFile::new().context("Cannot create temporary Magnitude database")?;
+    let mut db = Connection::open(db_file.path())?;
+    create_tables(&db, embeds.ncols())?;
+
+    let dim_columns = (0..embeds.ncols())
+        .map(|dim| format!("dim_{}", dim))
+        .collect::<Vec<_>>();
+    let placeholders = vec!["?"; embeds.ncols() + 2].join(", ");
+    let scale = 10f32.powi(MAGNITUDE_PRECISION as i32);
+
+    let transaction = db.transaction()?;
+    {
+        let mut insert = transaction.prepare(&format!(
+            "INSERT INTO `magnitude` (key, {}, magnitude) VALUES ({})",
+            dim_columns.join(", "),
+            placeholders
+        ))?;
+        for ((word, embed), &norm) in words.iter().zip(embeds.outer_iter()).zip(norms) {
+            let row = std::iter::once(Value::Text(word.clone()))
+                .chain(
+                    embed
+                        .iter()
+                        .map(|&v| Value::Integer((v * scale).round() as i64)),
+                )
+                .chain(std::iter::once(Value::Real(norm as f64)));
+            insert.execute(params_from_iter(row))?;
+        }
+    }
+    transaction.commit()?;
+
+    db.execute("CREATE INDEX `magnitude_key_idx` ON `magnitude` (key)", [])?;
+    let max_duplicate_keys: i64 = db.query_row(
+        "SELECT IFNULL(MAX(n), 1) FROM (SELECT COUNT(key) AS n FROM `magnitude` GROUP BY key)",
+        [],
+        |row| row.get(0),
+    )?;
+
+    let format = [
+        ("size", words.len() as i64),
+        ("dim", embeds.ncols() as i64),
+        ("precision", MAGNITUDE_PRECISION as i64),
+        ("version", MAGNITUDE_VERSION),
+        ("max_duplicate_keys", max_duplicate_keys),
+        ("subword", 0),
+        ("subword_start", 0),
+        ("subword_end", 0),
+        ("approx", 0),
+        ("elmo", 0),
+    ];
+    for (key, value) in &format {
+        db.execute(
+            "INSERT INTO `magnitude_format` (key, value) VALUES (?, ?)",
+            params![key, value],
+        )?;
+    }
+
+    db.close().map_err(|(_, err)| err)?;
+
+    io::copy(&mut File::open(db_file.path())?, write)?;
+
+    Ok(())
+}
+
+/// Create the tables of a Magnitude database.
+fn create_tables(db: &Connection, dims: usize) -> Result<()> {
+    let dim_columns = (0..dims)
+        .map(|dim| format!("dim_{} INTEGER", dim))
+        .collect::<Vec<_>>();
+    db.execute(
+        &format!(
+            "CREATE TABLE `magnitude` (key TEXT COLLATE NOCASE, {}, magnitude REAL)",
+            dim_columns.join(", ")
+        ),
+        [],
+    )?;
+    db.execute(
+        "CREATE TABLE `magnitude_format` (key TEXT COLLATE NOCASE, value INTEGER)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use ndarray::array;
+    use rusqlite::{params, Connection};
+    use tempfile::NamedTempFile;
+
+    use super::write_magnitude;
+
+    #[test]
+    fn write_magnitude_test() {
+        let words = vec!["Hello".to_string(), "hello".to_string()];
+        let embeds = array![[0.6f32, 0.8], [1., 0.]];
+
+        let db_file = NamedTempFile::new().unwrap();
+        write_magnitude(
+            &mut File::create(db_file.path()).unwrap(),
+            &words,
+            embeds.view(),
+            &[5., 2.],
+        )
+        .unwrap();
+
+        let db = Connection::open(db_file.path()).unwrap();
+        let (dim_0, dim_1, magnitude): (i64, i64, f64) = db
+            .query_row(
+                "SELECT dim_0, dim_1, magnitude FROM magnitude WHERE key = 'Hello' COLLATE BINARY",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((dim_0, dim_1, magnitude), (6_000_000, 8_000_000, 5.));
+
+        let format_value = |key: &str| -> i64 {
+            db.query_row(
+                "SELECT value FROM magnitude_format WHERE key = ?",
+                params![key],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(format_value("size"), 2);
+        assert_eq!(format_value("dim"), 2);
+        assert_eq!(format_value("max_duplicate_keys"), 2);
+    }
+}
@@ -14,7 +14,18 @@ static DEFAULT_CLAP_SETTINGS: &[AppSettings] = &[
 
 fn main() -> Result<()> {
     // Known subapplications.
-    let apps = vec![subcommands::DepsApp::app(), subcommands::SkipgramApp::app()];
+    let apps = vec![
+        subcommands::DepsApp::app(),
+        subcommands::Doc2vecApp::app(),
+        subcommands::GloveApp::app(),
+        subcommands::MultisenseApp::app(),
+        subcommands::PmiApp::app(),
+        subcommands::RetrofitApp::app(),
+        subcommands::Sent2vecApp::app(),
+        subcommands::SkipgramApp::app(),
+        subcommands::SupervisedApp::app(),
+        subcommands::VocabApp::app(),
+    ];
 
     let version = if let Some(git_desc) = option_env!("MAYBE_FINALFRONTIER_GIT_DESC") {
         git_desc
@@ -43,9 +54,33 @@ fn main() -> Result<()> {
             Ok(())
         }
         "deps" => subcommands::DepsApp::parse(matches.subcommand_matches("deps").unwrap())?.run(),
+        "doc2vec" => {
+            subcommands::Doc2vecApp::parse(matches.subcommand_matches("doc2vec").unwrap())?.run()
+        }
+        "glove" => {
+            subcommands::GloveApp::parse(matches.subcommand_matches("glove").unwrap())?.run()
+        }
+        "multisense" => {
+            subcommands::MultisenseApp::parse(matches.subcommand_matches("multisense").unwrap())?
+                .run()
+        }
+        "pmi" => subcommands::PmiApp::parse(matches.subcommand_matches("pmi").unwrap())?.run(),
+        "retrofit" => {
+            subcommands::RetrofitApp::parse(matches.subcommand_matches("retrofit").unwrap())?.run()
+        }
+        "sent2vec" => {
+            subcommands::Sent2vecApp::parse(matches.subcommand_matches("sent2vec").unwrap())?.run()
+        }
         "skipgram" => {
             subcommands::SkipgramApp::parse(matches.subcommand_matches("skipgram").unwrap())?.run()
         }
+        "supervised" => {
+            subcommands::SupervisedApp::parse(matches.subcommand_matches("supervised").unwrap())?
+                .run()
+        }
+        "vocab" => {
+            subcommands::VocabApp::parse(matches.subcommand_matches("vocab").unwrap())?.run()
+        }
         _unknown => unreachable!(),
     }
 }
@@ -0,0 +1,335 @@
+use anyhow::{ensure, Result};
+use ndarray::{Array2, ArrayView2, ArrayViewMut1, Axis, CowArray, Ix1};
+
+use crate::config::Precision;
+use crate::hogwild::HogwildArray2;
+use crate::numa::{page_aligned, NumaTopology};
+
+/// Embedding matrix.
+///
+/// An embedding matrix stores embeddings in single precision, half
+/// precision, or bfloat16. Embeddings are always read and updated in
+/// single precision. In lower precisions, an embedding is converted to
+/// single precision when it is read and converted back when it is
+/// updated. This halves the memory use of the matrix, while computations
+/// accumulate in single precision.
+///
+/// The matrix is stored as a `HogwildArray`, so clones share the same
+/// embeddings.
+#[derive(Clone)]
+pub(crate) enum EmbeddingMatrix {
+    F32(HogwildArray2<f32>),
+    F16(HogwildArray2<u16>),
+    BF16(HogwildArray2<u16>),
+}
+
+impl EmbeddingMatrix {
+    /// Construct an embedding matrix with the given precision.
+    pub fn new(matrix: Array2<f32>, precision: Precision) -> Self {
+        match precision {
+            Precision::F32 => EmbeddingMatrix::F32(matrix.into()),
+            Precision::F16 => EmbeddingMatrix::F16(matrix.mapv(f32_to_f16).into()),
+            Precision::BF16 => EmbeddingMatrix::BF16(matrix.mapv(f32_to_bf16).into()),
+        }
+    }
+
+    /// Get the number of rows.
+    pub fn nrows(&self) -> usize {
+        match self {
+            EmbeddingMatrix::F32(matrix) => matrix.view().nrows(),
+            EmbeddingMatrix::F16(matrix) | EmbeddingMatrix::BF16(matrix) => matrix.view().nrows(),
+        }
+    }
+
+    /// Get the number of columns.
+    pub fn ncols(&self) -> usize {
+        match self {
+            EmbeddingMatrix::F32(matrix) => matrix.view().ncols(),
+            EmbeddingMatrix::F16(matrix) | EmbeddingMatrix::BF16(matrix) => matrix.view().ncols(),
+        }
+    }
+
+    /// Get the embedding with the given index.
+    ///
+    /// The embedding is borrowed in single precision and converted
+    /// otherwise.
+    pub fn row(&self, idx: usize) -> CowArray<f32, Ix1> {
+        match self {
+            EmbeddingMatrix::F32(matrix) => matrix.subview(Axis(0), idx).into(),
+            EmbeddingMatrix::F16(matrix) => matrix.subview(Axis(0), idx).mapv(f16_to_f32).into(),
+            EmbeddingMatrix::BF16(matrix) => matrix.subview(Axis(0), idx).mapv(bf16_to_f32).into(),
+        }
+    }
+
+    /// Update the embedding with the given index.
+    ///
+    /// `update` is applied to the embedding in single precision.
+    pub fn update_row<F>(&mut self, idx: usize, update: F)
+    where
+        F: FnOnce(ArrayViewMut1<f32>),
+    {
+        match self {
+            EmbeddingMatrix::F32(matrix) => update(matrix.subview_mut(Axis(0), idx)),
+            EmbeddingMatrix::F16(matrix) => {
+                let mut row = matrix.subview_mut(Axis(0), idx);
+                let mut embed = row.mapv(f16_to_f32);
+                update(embed.view_mut());
+                row.zip_mut_with(&embed, |v, &e| *v = f32_to_f16(e));
+            }
+            EmbeddingMatrix::BF16(matrix) => {
+                let mut row = matrix.subview_mut(Axis(0), idx);
+                let mut embed = row.mapv(bf16_to_f32);
+                update(embed.view_mut());
+                row.zip_mut_with(&embed, |v, &e| *v = f32_to_bf16(e));
+            }
+        }
+    }
+
+    /// Copy the matrix in single precision.
+    ///
+    /// This can be used while the matrix is shared with other instances
+    /// that update it.
+    pub fn to_array(&self) -> Array2<f32> {
+        match self {
+            EmbeddingMatrix::F32(matrix) => matrix.view().to_owned(),
+            EmbeddingMatrix::F16(matrix) => matrix.view().mapv(f16_to_f32),
+            EmbeddingMatrix::BF16(matrix) => matrix.view().mapv(bf16_to_f32),
+        }
+    }
+
+    /// Replace the embeddings of the matrix.
+    ///
+    /// The embeddings are converted to the precision of the matrix. Since
+    /// the embeddings are replaced in place, clones of the matrix see the
+    /// new embeddings as well.
+    pub fn assign(&mut self, array: ArrayView2<f32>) -> Result<()> {
+        ensure!(
+            array.dim() == (self.nrows(), self.ncols()),
+            "Cannot assign a matrix with shape {:?} to a matrix with shape {:?}",
+            array.dim(),
+            (self.nrows(), self.ncols())
+        );
+
+        match self {
+            EmbeddingMatrix::F32(matrix) => matrix.view_mut().assign(&array),
+            EmbeddingMatrix::F16(matrix) => matrix
+                .view_mut()
+                .zip_mut_with(&array, |v, &e| *v = f32_to_f16(e)),
+            EmbeddingMatrix::BF16(matrix) => matrix
+                .view_mut()
+                .zip_mut_with(&array, |v, &e| *v = f32_to_bf16(e)),
+        }
+
+        Ok(())
+    }
+
+    /// Interleave the pages of the matrix over the NUMA nodes of
+    /// `topology`.
+    ///
+    /// The matrix is first copied to page-aligned memory, so that the
+    /// interleaved pages only hold the matrix. Clones of the matrix that
+    /// were made before do not share the interleaved matrix.
+    pub fn interleave(&mut self, topology: &NumaTopology) -> Result<()> {
+        if topology.n_nodes() == 1 {
+            return Ok(());
+        }
+
+        match self {
+            EmbeddingMatrix::F32(matrix) => {
+                *matrix = page_aligned(matrix.view()).into();
+                topology.interleave(
+                    matrix
+                        .view_mut()
+                        .as_slice_mut()
+                        .expect("Embedding matrix is not contiguous"),
+                )
+            }
+            EmbeddingMatrix::F16(matrix) | EmbeddingMatrix::BF16(matrix) => {
+                *matrix = page_aligned(matrix.view()).into();
+                topology.interleave(
+                    matrix
+                        .view_mut()
+                        .as_slice_mut()
+                        .expect("Embedding matrix is not contiguous"),
+                )
+            }
+        }
+    }
+}
+
+impl From<Array2<f32>> for EmbeddingMatrix {
+    fn from(matrix: Array2<f32>) -> Self {
+        EmbeddingMatrix::new(matrix, Precision::F32)
+    }
+}
+
+/// Convert a bfloat16 value to single precision.
+fn bf16_to_f32(value: u16) -> f32 {
+    f32::from_bits((value as u32) << 16)
+}
+
+/// Convert a single precision value to bfloat16.
+///
+/// The value is rounded to the nearest bfloat16, ties are rounded to
+/// even.
+fn f32_to_bf16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    if value.is_nan() {
+        // Keep NaNs quiet, truncation could turn them into infinity.
+        return ((bits >> 16) | 0x40) as u16;
+    }
+
+    let rounding = 0x7fff + ((bits >> 16) & 1);
+    ((bits + rounding) >> 16) as u16
+}
+
+/// Convert a half-precision value to single precision.
+fn f16_to_f32(value: u16) -> f32 {
+    let sign = ((value & 0x8000) as u32) << 16;
+    let exp = ((value >> 10) & 0x1f) as u32;
+    let man = (value & 0x3ff) as u32;
+
+    match exp {
+        // Zero and subnormal numbers: man * 2^-24.
+        0 => {
+            let magnitude = man as f32 * 2f32.powi(-24);
+            if sign == 0 {
+                magnitude
+            } else {
+                -magnitude
+            }
+        }
+        // Infinity and NaN.
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (man << 13)),
+        _ => f32::from_bits(sign | ((exp + 127 - 15) << 23) | (man << 13)),
+    }
+}
+
+/// Convert a single precision value to half precision.
+///
+/// The value is rounded to the nearest half-precision value, ties are
+/// rounded to even. Values that are too large are converted to infinity.
+pub(crate) fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = (bits >> 16) & 0x8000;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let man = bits & 0x7f_ffff;
+
+    // Infinity and NaN.
+    if exp == 0xff {
+        let nan_bit = if man == 0 { 0 } else { 0x200 };
+        return (sign | 0x7c00 | nan_bit) as u16;
+    }
+
+    let half_exp = exp - 127 + 15;
+
+    // Overflow to infinity.
+    if half_exp >= 0x1f {
+        return (sign | 0x7c00) as u16;
+    }
+
+    // Subnormal numbers and underflow to zero.
+    if half_exp <= 0 {
+        if half_exp < -10 {
+            return sign as u16;
+        }
+
+        let man = man | 0x80_0000;
+        let shift = (14 - half_exp) as u32;
+        let round_bit = 1 << (shift - 1);
+        let mut half_man = man >> shift;
+        if man & round_bit != 0 && man & (3 * round_bit - 1) != 0 {
+            half_man += 1;
+        }
+        return (sign | half_man) as u16;
+    }
+
+    // Normal numbers. A carry of the rounding can increment the exponent,
+    // which is the correctly rounded result.
+    let round_bit = 0x1000;
+    let mut half = sign | ((half_exp as u32) << 10) | (man >> 13);
+    if man & round_bit != 0 && man & (3 * round_bit - 1) != 0 {
+        half += 1;
+    }
+    half as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+
+    use super::{bf16_to_f32, f16_to_f32, f32_to_bf16, f32_to_f16, EmbeddingMatrix};
+    use crate::config::Precision;
+    use crate::util::all_close;
+
+    #[test]
+    fn f16_round_trip() {
+        for &value in &[
+            0.,
+            -0.,
+            1.,
+            -2.,
+            0.5,
+            65504.,
+            6.103_515_6e-5,
+            5.960_464_5e-8,
+        ] {
+            assert_eq!(f16_to_f32(f32_to_f16(value)).to_bits(), value.to_bits());
+        }
+
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert_eq!(f32_to_f16(1e-10), 0);
+        assert!(f16_to_f32(f32_to_f16(std::f32::NAN)).is_nan());
+
+        // 1 + 2^-11 is halfway between 1 and 1 + 2^-10, round to even.
+        assert_eq!(f32_to_f16(1. + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_f16(1. + 3. * 2f32.powi(-11)), 0x3c02);
+    }
+
+    #[test]
+    fn bf16_round_trip() {
+        for &value in &[0., -0., 1., -2., 0.5, 3.0e38, 1.0e-30] {
+            let rounded = bf16_to_f32(f32_to_bf16(value));
+            assert!((rounded - value).abs() <= value.abs() / 128.);
+        }
+
+        assert_eq!(f32_to_bf16(1.0), 0x3f80);
+        assert_eq!(f32_to_bf16(-2.0), 0xc000);
+        assert!(bf16_to_f32(f32_to_bf16(std::f32::NAN)).is_nan());
+
+        // 1 + 2^-8 is halfway between 1 and 1 + 2^-7, round to even.
+        assert_eq!(f32_to_bf16(1. + 2f32.powi(-8)), 0x3f80);
+        assert_eq!(f32_to_bf16(1. + 3. * 2f32.powi(-8)), 0x3f82);
+    }
+
+    #[test]
+    fn embedding_matrix_precisions() {
+        let array = Array2::from_shape_vec((2, 3), vec![1., 2., 3., 0.25, 0.5, -1.]).unwrap();
+
+        for &precision in &[Precision::F32, Precision::F16, Precision::BF16] {
+            let mut matrix = EmbeddingMatrix::new(array.clone(), precision);
+            assert_eq!(matrix.ncols(), 3);
+            assert!(all_close(
+                matrix.row(1).as_slice().unwrap(),
+                &[0.25, 0.5, -1.],
+                1e-5
+            ));
+
+            matrix.update_row(0, |mut embed| embed *= 2.);
+            assert!(all_close(
+                matrix.row(0).as_slice().unwrap(),
+                &[2., 4., 6.],
+                1e-5
+            ));
+
+            let array = matrix.to_array();
+            assert!(all_close(
+                array.as_slice().unwrap(),
+                &[2., 4., 6., 0.25, 0.5, -1.],
+                1e-5
+            ));
+        }
+    }
+}
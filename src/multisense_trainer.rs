@@ -0,0 +1,435 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::io::{Seek, Write};
+use std::iter::FusedIterator;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use finalfusion::io::WriteEmbeddings;
+use finalfusion::norms::NdNorms;
+use finalfusion::prelude::Embeddings;
+use finalfusion::storage::NdArray;
+use finalfusion::vocab::SimpleVocab as FiFuSimpleVocab;
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+use rand::{Rng, RngCore, SeedableRng};
+use serde::Serialize;
+
+use crate::hogwild::{HogwildArray1, HogwildArray2};
+use crate::idx::{BagOfWordsIdx, WordIdx};
+use crate::sampling::{sample_context_window, NegativeSamplingRangeGenerator, RangeGenerator};
+use crate::train_model::{NegativeSamples, SelectSense, TrainIterFrom, TrainModel, Trainer};
+use crate::util::{Reseed, ReseedOnCloneRng};
+use crate::vec_simd::{dot, l2_normalize, scaled_add};
+use crate::{CommonConfig, MultisenseConfig, Vocab};
+
+/// Multi-sense skip-gram Trainer
+///
+/// The `MultisenseTrainer` holds the information and logic necessary to
+/// transform a tokenized sentence into an iterator of focus word, context
+/// bag, and context word tuples. Every word has up to a configured number
+/// of sense embeddings, which are stored after the word and subword
+/// embeddings in the input matrix. Senses are allocated during training
+/// by clustering the contexts of a word (Neelakantan et al., 2014).
+///
+/// The struct is cheap to clone because the vocabulary and the sense
+/// clusters are shared between clones.
+#[derive(Clone)]
+pub struct MultisenseTrainer<R, V> {
+    vocab: Arc<V>,
+    rng: R,
+    range_gen: NegativeSamplingRangeGenerator<R>,
+    common_config: CommonConfig,
+    multisense_config: MultisenseConfig,
+    cluster_centers: HogwildArray2<f32>,
+    n_senses: HogwildArray1<usize>,
+}
+
+impl<R, V> MultisenseTrainer<ReseedOnCloneRng<R>, V>
+where
+    R: Rng + Clone + SeedableRng,
+    V: Vocab,
+{
+    /// Constructs a new `MultisenseTrainer`.
+    pub fn new(
+        vocab: V,
+        rng: R,
+        common_config: CommonConfig,
+        multisense_config: MultisenseConfig,
+    ) -> Self {
+        let vocab = Arc::new(vocab);
+        let rng = ReseedOnCloneRng::new(rng);
+        let range_gen = NegativeSamplingRangeGenerator::new(
+            rng.clone(),
+            vocab.types().iter().map(|word| word.count()),
+            &common_config,
+        );
+        let cluster_centers = Array2::zeros((
+            vocab.len() * multisense_config.senses as usize,
+            common_config.dims as usize,
+        ))
+        .into();
+        let n_senses = Array1::zeros((vocab.len(),)).into();
+        MultisenseTrainer {
+            vocab,
+            rng,
+            range_gen,
+            common_config,
+            multisense_config,
+            cluster_centers,
+            n_senses,
+        }
+    }
+}
+
+impl<R, V> MultisenseTrainer<R, V>
+where
+    V: Vocab,
+{
+    /// Get the number of senses that are allocated for a word.
+    pub fn n_senses(&self, word: usize) -> usize {
+        self.n_senses.view()[word]
+    }
+
+    /// Get the input index of a word sense.
+    fn sense_idx(&self, word: usize, sense: usize) -> u64 {
+        let max_senses = self.multisense_config.senses as usize;
+        assert!(
+            sense < max_senses,
+            "Sense {} out of index [0, {})",
+            sense,
+            max_senses
+        );
+
+        (self.vocab.n_input_types() + word * max_senses + sense) as u64
+    }
+}
+
+impl<R, V> SelectSense for MultisenseTrainer<R, V>
+where
+    V: Vocab,
+{
+    fn select_sense(&mut self, word: usize, context_embed: ArrayView1<f32>) -> u64 {
+        let max_senses = self.multisense_config.senses as usize;
+        let n_senses = self.n_senses(word);
+
+        // Find the sense with the most similar cluster of contexts.
+        let context_norm = dot(context_embed, context_embed).sqrt();
+        let mut best = None;
+        for sense in 0..n_senses {
+            let center = self
+                .cluster_centers
+                .subview(Axis(0), word * max_senses + sense);
+            let similarity =
+                dot(center, context_embed) / (dot(center, center).sqrt() * context_norm);
+            match best {
+                Some((_, best_similarity)) if best_similarity >= similarity => (),
+                _ => best = Some((sense, similarity)),
+            }
+        }
+
+        // Allocate a new sense if no sense is sufficiently similar.
+        let sense = match best {
+            Some((sense, similarity))
+                if similarity >= self.multisense_config.sense_threshold
+                    || n_senses == max_senses =>
+            {
+                sense
+            }
+            _ => {
+                self.n_senses.view_mut()[word] += 1;
+                n_senses
+            }
+        };
+
+        scaled_add(
+            self.cluster_centers
+                .subview_mut(Axis(0), word * max_senses + sense),
+            context_embed,
+            1.0,
+        );
+
+        self.sense_idx(word, sense)
+    }
+}
+
+impl<'a, S, R, V, I> TrainIterFrom<'a, [S]> for MultisenseTrainer<R, V>
+where
+    S: Hash + Eq,
+    R: Rng + Clone,
+    V: Vocab<IdxType = I>,
+    V::VocabType: Borrow<S>,
+    I: WordIdx,
+    for<'b> &'b I: IntoIterator<Item = u64>,
+{
+    type Iter = MultisenseIter<R, I>;
+    type Focus = (I, BagOfWordsIdx);
+    type Contexts = Vec<usize>;
+
+    fn train_iter_from(&mut self, sequence: &[S]) -> Self::Iter {
+        let mut ids = Vec::new();
+        for t in sequence {
+            if let Some(mut idx) = self.vocab.idx(t) {
+                if self.rng.gen_range(0f32..1f32) < self.vocab.discard(idx.word_idx() as usize) {
+                    idx.drop_subwords(&mut self.rng, self.common_config.subword_dropout);
+                    ids.push(idx);
+                }
+            }
+        }
+        MultisenseIter::new(self.rng.clone(), ids, self.multisense_config)
+    }
+}
+
+impl<R, V> NegativeSamples for MultisenseTrainer<R, V>
+where
+    R: Rng,
+{
+    fn negative_sample(&mut self, output: usize) -> usize {
+        loop {
+            let negative = self.range_gen.next().unwrap();
+            if negative != output {
+                return negative;
+            }
+        }
+    }
+
+    fn noise_prob(&self, output: usize) -> f32 {
+        self.range_gen.prob(output) as f32
+    }
+}
+
+impl<R, V> Reseed for MultisenseTrainer<R, V>
+where
+    R: Reseed + RngCore,
+{
+    fn reseed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+        self.range_gen.reseed(self.rng.next_u64());
+    }
+}
+
+impl<R, V> Trainer for MultisenseTrainer<R, V>
+where
+    R: Rng + Clone,
+    V: Vocab,
+    V::Config: Serialize,
+{
+    type InputVocab = V;
+    type Metadata = MultisenseMetadata<V::Config>;
+
+    fn input_vocab(&self) -> &V {
+        &self.vocab
+    }
+
+    fn try_into_input_vocab(self) -> Result<V> {
+        match Arc::try_unwrap(self.vocab) {
+            Ok(vocab) => Ok(vocab),
+            Err(_) => bail!("Cannot unwrap input vocab."),
+        }
+    }
+
+    fn n_input_types(&self) -> usize {
+        self.input_vocab().n_input_types()
+            + self.vocab.len() * self.multisense_config.senses as usize
+    }
+
+    fn n_output_types(&self) -> usize {
+        self.vocab.len()
+    }
+
+    fn config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+
+    fn to_metadata(&self) -> MultisenseMetadata<V::Config> {
+        MultisenseMetadata {
+            common_config: self.common_config,
+            multisense_config: self.multisense_config,
+            vocab_config: self.vocab.config(),
+        }
+    }
+}
+
+impl<R, V> TrainModel<MultisenseTrainer<R, V>>
+where
+    R: Rng + Clone,
+    V: Vocab,
+    V::VocabType: ToString,
+    V::Config: Serialize,
+{
+    /// Write the sense embeddings in finalfusion format.
+    ///
+    /// The vocabulary of the written embeddings consists of the senses
+    /// that were allocated during training. Senses are suffixed by their
+    /// number, e.g. the second sense of *bank* is stored as `bank#2`.
+    pub fn write_sense_embeddings<W>(&mut self, write: &mut W) -> Result<()>
+    where
+        W: Seek + Write,
+    {
+        let trainer = self.trainer();
+        let mut senses = Vec::new();
+        let mut indices = Vec::new();
+        for (word, word_type) in trainer.vocab.types().iter().enumerate() {
+            for sense in 0..trainer.n_senses(word) {
+                senses.push(format!("{}#{}", word_type.label().to_string(), sense + 1));
+                indices.push(trainer.sense_idx(word, sense) as usize);
+            }
+        }
+
+        let mut embeds = Array2::zeros((senses.len(), self.config().dims as usize));
+        let mut norms = Array1::zeros((senses.len(),));
+        for ((mut embed, norm), &idx) in embeds.outer_iter_mut().zip(norms.iter_mut()).zip(&indices)
+        {
+            embed.assign(&self.input_embedding(idx));
+            *norm = l2_normalize(embed.view_mut());
+        }
+
+        Embeddings::new(
+            None,
+            FiFuSimpleVocab::new(senses),
+            NdArray::new(embeds),
+            NdNorms::new(norms),
+        )
+        .write_embeddings(write)?;
+
+        Ok(())
+    }
+}
+
+/// Metadata for multi-sense embeddings.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct MultisenseMetadata<V> {
+    common_config: CommonConfig,
+    #[serde(rename = "model_config")]
+    multisense_config: MultisenseConfig,
+    vocab_config: V,
+}
+
+/// Iterator over the focus word, context bag, and context words of each
+/// token in a sentence.
+///
+/// The context bag contains the indices of all context words (including
+/// their subword units) and is used to select the sense of the focus word.
+/// The outputs are the context words.
+pub struct MultisenseIter<R, I> {
+    ids: Vec<I>,
+    rng: R,
+    i: usize,
+    ctx_size: usize,
+}
+
+impl<R, I> MultisenseIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+{
+    /// Constructs a new `MultisenseIter`.
+    ///
+    /// The `rng` is used to determine the window size for each focus token.
+    pub fn new(rng: R, ids: Vec<I>, multisense_config: MultisenseConfig) -> Self {
+        MultisenseIter {
+            ids,
+            rng,
+            i: 0,
+            ctx_size: multisense_config.context_size as usize,
+        }
+    }
+}
+
+impl<R, I> Iterator for MultisenseIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+    for<'a> &'a I: IntoIterator<Item = u64>,
+{
+    type Item = ((I, BagOfWordsIdx), Vec<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.i < self.ids.len() {
+            let window =
+                sample_context_window(&mut self.rng, self.i, self.ids.len(), self.ctx_size);
+
+            let mut bag = BagOfWordsIdx::default();
+            let mut contexts = Vec::with_capacity(window.len());
+            for idx in window.filter(|&idx| idx != self.i) {
+                bag.extend(&self.ids[idx]);
+                contexts.push(self.ids[idx].word_idx() as usize);
+            }
+
+            let focus = self.ids[self.i].clone();
+            self.i += 1;
+
+            // Sentences with a single token do not have a context.
+            if !contexts.is_empty() {
+                return Some(((focus, bag), contexts));
+            }
+        }
+
+        None
+    }
+}
+
+impl<R, I> FusedIterator for MultisenseIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+    for<'a> &'a I: IntoIterator<Item = u64>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array1};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::MultisenseTrainer;
+    use crate::{
+        CommonConfig, Cutoff, MultisenseConfig, SelectSense, SimpleVocab, SimpleVocabConfig,
+        VocabBuilder,
+    };
+
+    const TEST_COMMON_CONFIG: CommonConfig = CommonConfig {
+        dims: 2,
+        ..CommonConfig::for_test()
+    };
+
+    const TEST_MULTISENSE_CONFIG: MultisenseConfig = MultisenseConfig {
+        context_size: 5,
+        senses: 2,
+        sense_threshold: 0.5,
+    };
+
+    #[test]
+    fn select_sense_test() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            cutoff: Cutoff::MinCount(1),
+            discard_threshold: 1e-4,
+        });
+        builder.count("bank");
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let mut trainer = MultisenseTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            TEST_MULTISENSE_CONFIG,
+        );
+
+        let river: Array1<f32> = array![1., 0.];
+        let money: Array1<f32> = array![0., 1.];
+
+        // The first occurrence allocates the first sense, similar contexts
+        // use the same sense.
+        assert_eq!(trainer.select_sense(0, river.view()), 1);
+        assert_eq!(trainer.select_sense(0, (&river * 2.).view()), 1);
+        assert_eq!(trainer.n_senses(0), 1);
+
+        // A dissimilar context allocates a new sense.
+        assert_eq!(trainer.select_sense(0, money.view()), 2);
+        assert_eq!(trainer.n_senses(0), 2);
+
+        // No senses are allocated beyond the maximum number of senses.
+        assert_eq!(trainer.select_sense(0, array![-0.5, -1.].view()), 1);
+        assert_eq!(trainer.n_senses(0), 2);
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+use std::io::BufRead;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::TokenNormalization;
+
+/// Separator that is used to join the tokens of a multi-word expression.
+const SEPARATOR: &str = "_";
+
+/// Lexicon of multi-word expressions.
+///
+/// Multi-word expressions in a sentence are merged into a single token,
+/// joining the tokens with `_`. Expressions are merged greedily from left
+/// to right, preferring the longest expression at each position.
+#[derive(Clone, Debug, Default)]
+pub struct MultiwordLexicon {
+    expressions: HashSet<Vec<String>>,
+    max_len: usize,
+}
+
+impl MultiwordLexicon {
+    /// Read a lexicon.
+    ///
+    /// The lexicon contains one expression per line, with its tokens
+    /// separated by whitespace. Empty lines are ignored. The tokens are
+    /// normalized using `normalization`, so that they match normalized
+    /// corpus tokens.
+    pub fn read<R>(read: R, normalization: TokenNormalization) -> Result<Self>
+    where
+        R: BufRead,
+    {
+        let mut lexicon = MultiwordLexicon::default();
+
+        for (idx, line) in read.lines().enumerate() {
+            let line = line.context("Cannot read multi-word expression")?;
+            let expression = line
+                .split_whitespace()
+                .map(|token| normalization.normalize(token).into_owned())
+                .collect::<Vec<_>>();
+
+            if expression.is_empty() {
+                continue;
+            }
+
+            ensure!(
+                expression.len() > 1,
+                "Multi-word expression on line {} consists of a single token: {}",
+                idx + 1,
+                line.trim()
+            );
+
+            lexicon.max_len = lexicon.max_len.max(expression.len());
+            lexicon.expressions.insert(expression);
+        }
+
+        Ok(lexicon)
+    }
+
+    /// Merge the multi-word expressions in a sentence.
+    pub fn merge(&self, sentence: Vec<String>) -> Vec<String> {
+        if self.is_empty() {
+            return sentence;
+        }
+
+        let mut merged = Vec::with_capacity(sentence.len());
+        let mut idx = 0;
+        while idx < sentence.len() {
+            let max_len = self.max_len.min(sentence.len() - idx);
+            match (2..=max_len)
+                .rev()
+                .find(|&len| self.expressions.contains(&sentence[idx..idx + len]))
+            {
+                Some(len) => {
+                    merged.push(sentence[idx..idx + len].join(SEPARATOR));
+                    idx += len;
+                }
+                None => {
+                    merged.push(sentence[idx].clone());
+                    idx += 1;
+                }
+            }
+        }
+
+        merged
+    }
+
+    /// Returns `true` if the lexicon does not contain expressions.
+    pub fn is_empty(&self) -> bool {
+        self.expressions.is_empty()
+    }
+
+    /// Get the number of expressions in the lexicon.
+    pub fn len(&self) -> usize {
+        self.expressions.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MultiwordLexicon;
+    use crate::{CaseFolding, TokenNormalization};
+
+    static LEXICON: &str = "New York\nNew York City\n\nhot dog\n";
+
+    fn sentence(s: &str) -> Vec<String> {
+        s.split_whitespace().map(ToOwned::to_owned).collect()
+    }
+
+    #[test]
+    fn merges_longest_expressions() {
+        let lexicon =
+            MultiwordLexicon::read(LEXICON.as_bytes(), TokenNormalization::default()).unwrap();
+        assert_eq!(lexicon.len(), 3);
+
+        assert_eq!(
+            lexicon.merge(sentence("a hot dog in New York City")),
+            sentence("a hot_dog in New_York_City")
+        );
+        assert_eq!(
+            lexicon.merge(sentence("New York New hot")),
+            sentence("New_York New hot")
+        );
+        assert_eq!(lexicon.merge(sentence("York")), sentence("York"));
+    }
+
+    #[test]
+    fn normalizes_expressions() {
+        let normalization = TokenNormalization {
+            case_folding: CaseFolding::Lowercase,
+            ..TokenNormalization::default()
+        };
+        let lexicon = MultiwordLexicon::read(LEXICON.as_bytes(), normalization).unwrap();
+        assert_eq!(lexicon.merge(sentence("new york")), sentence("new_york"));
+    }
+
+    #[test]
+    fn rejects_single_tokens() {
+        assert!(MultiwordLexicon::read(
+            "hot dog\nYork\n".as_bytes(),
+            TokenNormalization::default()
+        )
+        .is_err());
+    }
+}
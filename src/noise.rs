@@ -0,0 +1,354 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+use anyhow::{bail, Error, Result};
+use serde::Serialize;
+
+use crate::io::Tokenizer;
+
+/// The token that replaces URLs with `NoiseAction::Placeholder`.
+pub const URL_PLACEHOLDER: &str = "<url>";
+
+/// The token that replaces e-mail addresses with `NoiseAction::Placeholder`.
+pub const EMAIL_PLACEHOLDER: &str = "<email>";
+
+/// The token that replaces @handles with `NoiseAction::Placeholder`.
+pub const HANDLE_PLACEHOLDER: &str = "<user>";
+
+/// Punctuation that can follow a noise token in running text.
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '"', '\''];
+
+/// Named HTML entities that are decoded.
+static HTML_ENTITIES: &[(&str, &str)] = &[
+    ("amp", "&"),
+    ("apos", "'"),
+    ("bdquo", "„"),
+    ("copy", "©"),
+    ("euro", "€"),
+    ("gt", ">"),
+    ("hellip", "…"),
+    ("laquo", "«"),
+    ("ldquo", "“"),
+    ("lsquo", "‘"),
+    ("lt", "<"),
+    ("mdash", "—"),
+    ("nbsp", " "),
+    ("ndash", "–"),
+    ("quot", "\""),
+    ("raquo", "»"),
+    ("rdquo", "”"),
+    ("reg", "®"),
+    ("rsquo", "’"),
+    ("sbquo", "‚"),
+    ("shy", ""),
+    ("trade", "™"),
+];
+
+/// Treatment of a kind of noise token.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseAction {
+    /// Tokens are used as-is.
+    Keep,
+
+    /// Tokens are dropped.
+    Drop,
+
+    /// Tokens are replaced by a placeholder, such as `URL_PLACEHOLDER`.
+    Placeholder,
+}
+
+impl Default for NoiseAction {
+    fn default() -> Self {
+        NoiseAction::Keep
+    }
+}
+
+impl TryFrom<&str> for NoiseAction {
+    type Error = Error;
+
+    fn try_from(action: &str) -> Result<Self> {
+        match action {
+            "keep" => Ok(NoiseAction::Keep),
+            "drop" => Ok(NoiseAction::Drop),
+            "placeholder" => Ok(NoiseAction::Placeholder),
+            _ => bail!("Unknown noise token action: {}", action),
+        }
+    }
+}
+
+/// Treatment of HTML entities, such as `&amp;`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HtmlEntities {
+    /// Entities are used as-is.
+    Keep,
+
+    /// Entities are replaced by the characters that they encode.
+    Decode,
+
+    /// Entities are removed.
+    Remove,
+}
+
+impl Default for HtmlEntities {
+    fn default() -> Self {
+        HtmlEntities::Keep
+    }
+}
+
+impl TryFrom<&str> for HtmlEntities {
+    type Error = Error;
+
+    fn try_from(entities: &str) -> Result<Self> {
+        match entities {
+            "keep" => Ok(HtmlEntities::Keep),
+            "decode" => Ok(HtmlEntities::Decode),
+            "remove" => Ok(HtmlEntities::Remove),
+            _ => bail!("Unknown HTML entity handling: {}", entities),
+        }
+    }
+}
+
+/// Filters of noise tokens.
+///
+/// Web corpora contain many URLs, e-mail addresses, @handles, and HTML
+/// entities. These tokens are rarely useful, but take up vocabulary
+/// entries and n-gram buckets. The filters drop such tokens or replace
+/// them by placeholders. HTML entities are decoded or removed before
+/// a text is tokenized.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct NoiseFilters {
+    /// Treatment of URLs.
+    pub urls: NoiseAction,
+
+    /// Treatment of e-mail addresses.
+    pub emails: NoiseAction,
+
+    /// Treatment of @handles.
+    pub handles: NoiseAction,
+
+    /// Treatment of HTML entities.
+    pub html_entities: HtmlEntities,
+}
+
+impl NoiseFilters {
+    /// Returns `true` if the filters do not change any token.
+    pub fn is_empty(&self) -> bool {
+        *self == NoiseFilters::default()
+    }
+
+    /// Tokenize a text, applying the noise filters.
+    ///
+    /// Noise tokens are recognized in the whitespace-separated words of
+    /// the text, possibly followed by punctuation, before the remainder
+    /// is tokenized with `tokenizer`. This ensures that e.g. URLs are
+    /// not split by the tokenizer and that placeholders are kept intact.
+    pub fn tokenize(&self, text: &str, tokenizer: Tokenizer) -> Vec<String> {
+        if self.is_empty() {
+            return tokenizer.tokenize(text);
+        }
+
+        let text = self.apply_html_entities(text);
+        let mut tokens = Vec::new();
+        for word in text.split_whitespace() {
+            let core = word.trim_end_matches(TRAILING_PUNCTUATION);
+            match self.action(core) {
+                Some((NoiseAction::Drop, _)) => (),
+                Some((NoiseAction::Placeholder, placeholder)) => {
+                    tokens.push(placeholder.to_owned())
+                }
+                Some((NoiseAction::Keep, _)) | None => {
+                    tokens.extend(tokenizer.tokenize(core));
+                }
+            }
+            tokens.extend(tokenizer.tokenize(&word[core.len()..]));
+        }
+
+        tokens
+    }
+
+    /// Apply the noise filters to tokens.
+    ///
+    /// This is used for corpora that are already tokenized.
+    pub fn filter_tokens(&self, tokens: Vec<String>) -> Vec<String> {
+        if self.is_empty() {
+            return tokens;
+        }
+
+        tokens
+            .into_iter()
+            .filter_map(|token| {
+                let token = self.apply_html_entities(&token).into_owned();
+                match self.action(&token) {
+                    Some((NoiseAction::Drop, _)) => None,
+                    Some((NoiseAction::Placeholder, placeholder)) => Some(placeholder.to_owned()),
+                    Some((NoiseAction::Keep, _)) | None => Some(token),
+                }
+            })
+            .filter(|token| !token.trim().is_empty())
+            .collect()
+    }
+
+    /// Get the action and placeholder of a token, if it is noise.
+    fn action(&self, token: &str) -> Option<(NoiseAction, &'static str)> {
+        if self.urls != NoiseAction::Keep && is_url(token) {
+            Some((self.urls, URL_PLACEHOLDER))
+        } else if self.emails != NoiseAction::Keep && is_email(token) {
+            Some((self.emails, EMAIL_PLACEHOLDER))
+        } else if self.handles != NoiseAction::Keep && is_handle(token) {
+            Some((self.handles, HANDLE_PLACEHOLDER))
+        } else {
+            None
+        }
+    }
+
+    /// Decode or remove the HTML entities in a text.
+    fn apply_html_entities<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.html_entities == HtmlEntities::Keep || !text.contains('&') {
+            return Cow::Borrowed(text);
+        }
+
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('&') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let entity = rest[1..]
+                .find(';')
+                .map(|end| &rest[1..end + 1])
+                .filter(|name| !name.is_empty() && !name.contains(char::is_whitespace));
+            let decoded = entity.and_then(decode_html_entity);
+            match (entity, decoded) {
+                (Some(entity), Some(decoded)) => {
+                    if self.html_entities == HtmlEntities::Decode {
+                        result.push_str(&decoded);
+                    }
+                    rest = &rest[entity.len() + 2..];
+                }
+                _ => {
+                    result.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        result.push_str(rest);
+
+        Cow::Owned(result)
+    }
+}
+
+/// Decode an HTML entity, given without `&` and `;`.
+fn decode_html_entity(entity: &str) -> Option<Cow<'static, str>> {
+    if let Some(code) = entity.strip_prefix('#') {
+        let code = match code.strip_prefix(|c: char| c == 'x' || c == 'X') {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => code.parse().ok()?,
+        };
+        return std::char::from_u32(code).map(|c| Cow::Owned(c.to_string()));
+    }
+
+    HTML_ENTITIES
+        .iter()
+        .find(|(name, _)| *name == entity)
+        .map(|&(_, decoded)| Cow::Borrowed(decoded))
+}
+
+/// Check whether a token is a URL.
+fn is_url(token: &str) -> bool {
+    ["http://", "https://", "ftp://", "www."]
+        .iter()
+        .any(|prefix| {
+            token.len() > prefix.len()
+                && token
+                    .get(..prefix.len())
+                    .map(|start| start.eq_ignore_ascii_case(prefix))
+                    .unwrap_or(false)
+        })
+}
+
+/// Check whether a token is an e-mail address.
+fn is_email(token: &str) -> bool {
+    let at = match token.find('@') {
+        Some(at) => at,
+        None => return false,
+    };
+    let (local, domain) = (&token[..at], &token[at + 1..]);
+
+    !local.is_empty()
+        && local
+            .chars()
+            .all(|c| c.is_alphanumeric() || "._%+-".contains(c))
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+}
+
+/// Check whether a token is an @handle.
+fn is_handle(token: &str) -> bool {
+    match token.strip_prefix('@') {
+        Some(name) => !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HtmlEntities, NoiseAction, NoiseFilters};
+    use crate::io::Tokenizer;
+
+    fn tokens(sentence: &str) -> Vec<String> {
+        sentence.split_whitespace().map(ToOwned::to_owned).collect()
+    }
+
+    #[test]
+    fn noise_filters_tokenize_test() {
+        let filters = NoiseFilters {
+            urls: NoiseAction::Placeholder,
+            emails: NoiseAction::Drop,
+            handles: NoiseAction::Placeholder,
+            html_entities: HtmlEntities::Decode,
+        };
+
+        assert_eq!(
+            filters.tokenize(
+                "@jane: see https://example.com/a?b=c, mail me@example.org &amp; more&hellip;",
+                Tokenizer::Punctuation
+            ),
+            vec!["<user>", ":", "see", "<url>", ",", "mail", "&", "more", "…"]
+        );
+        assert_eq!(
+            filters.tokenize("Mail jane@example.org.", Tokenizer::Whitespace),
+            vec!["Mail", "."]
+        );
+    }
+
+    #[test]
+    fn noise_filters_tokens_test() {
+        let filters = NoiseFilters {
+            urls: NoiseAction::Drop,
+            emails: NoiseAction::Keep,
+            handles: NoiseAction::Drop,
+            html_entities: HtmlEntities::Remove,
+        };
+
+        assert_eq!(
+            filters.filter_tokens(tokens(
+                "www.example.com @jane me@example.org &nbsp; &#8220;Hi&#x201D; R&D &unknown;"
+            )),
+            tokens("me@example.org Hi R&D &unknown;")
+        );
+    }
+
+    #[test]
+    fn noise_filters_empty_test() {
+        let filters = NoiseFilters::default();
+        assert!(filters.is_empty());
+        assert_eq!(
+            filters.tokenize("@jane https://example.com &amp;", Tokenizer::Whitespace),
+            tokens("@jane https://example.com &amp;")
+        );
+    }
+}
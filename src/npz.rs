@@ -0,0 +1,274 @@
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+use anyhow::{anyhow, Result};
+use flate2::Crc;
+use ndarray::ArrayView2;
+
+use crate::config::Precision;
+use crate::matrix::f32_to_f16;
+
+/// The date of zip members, 1980-01-01 in MS-DOS format.
+const ZIP_DATE: u16 = 0x21;
+
+/// The zip version that is needed to extract members.
+const ZIP_VERSION: u16 = 20;
+
+/// Write word embeddings as a NumPy `.npz` archive.
+///
+/// The archive contains the embedding matrix as `embeddings.npy` and the
+/// vocabulary as `vocab.txt`, with one word per line. Row *i* of the
+/// matrix is the embedding of the word on line *i*. The archive can be
+/// read with `numpy.load`.
+///
+/// The matrix is stored in half precision if `precision` is
+/// `Precision::F16`, and in single precision otherwise. NumPy does not
+/// support bfloat16.
+pub(crate) fn write_npz<W>(
+    write: &mut W,
+    words: &[String],
+    embeds: ArrayView2<f32>,
+    precision: Precision,
+) -> Result<()>
+where
+    W: Write,
+{
+    let mut npz = NpzWriter::new(write);
+
+    npz.add_member("embeddings.npy", |write| {
+        write_npy(write, embeds, precision)
+    })?;
+
+    npz.add_member("vocab.txt", |write| {
+        for word in words {
+            writeln!(write, "{}", word)?;
+        }
+        Ok(())
+    })?;
+
+    npz.finish()
+}
+
+/// Write an embedding matrix as a NumPy `.npy` file.
+///
+/// The matrix is stored in half precision if `precision` is
+/// `Precision::F16`, and in single precision otherwise.
+pub(crate) fn write_npy(
+    write: &mut dyn Write,
+    embeds: ArrayView2<f32>,
+    precision: Precision,
+) -> io::Result<()> {
+    match precision {
+        Precision::F16 => {
+            write.write_all(&npy_header("<f2", embeds.nrows(), embeds.ncols()))?;
+            for &v in embeds.iter() {
+                write.write_all(&f32_to_f16(v).to_le_bytes())?;
+            }
+        }
+        Precision::F32 | Precision::BF16 => {
+            write.write_all(&npy_header("<f4", embeds.nrows(), embeds.ncols()))?;
+            for &v in embeds.iter() {
+                write.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the header of a `.npy` file with a matrix of type `descr`.
+fn npy_header(descr: &str, rows: usize, cols: usize) -> Vec<u8> {
+    let mut dict = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': ({}, {}), }}",
+        descr, rows, cols
+    );
+
+    // The data must be aligned to 64 bytes. The header consists of the
+    // magic string, version, header length, and the dictionary, which is
+    // padded with spaces and terminated by a newline.
+    let unpadded_len = 10 + dict.len() + 1;
+    dict.extend(std::iter::repeat(' ').take((64 - unpadded_len % 64) % 64));
+    dict.push('\n');
+
+    let mut header = b"\x93NUMPY\x01\x00".to_vec();
+    header.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    header.extend_from_slice(dict.as_bytes());
+    header
+}
+
+/// Writer of uncompressed zip archives.
+struct NpzWriter<'a, W> {
+    write: &'a mut W,
+    central_directory: Vec<u8>,
+    n_members: u16,
+    offset: u64,
+}
+
+impl<'a, W> NpzWriter<'a, W>
+where
+    W: Write,
+{
+    fn new(write: &'a mut W) -> Self {
+        NpzWriter {
+            write,
+            central_directory: Vec::new(),
+            n_members: 0,
+            offset: 0,
+        }
+    }
+
+    /// Add a member to the archive.
+    ///
+    /// The data of the member is written by `write_data`. Since the
+    /// checksum and length of the data precede the data, `write_data`
+    /// is called twice.
+    fn add_member<F>(&mut self, name: &str, mut write_data: F) -> Result<()>
+    where
+        F: FnMut(&mut dyn Write) -> io::Result<()>,
+    {
+        let mut checksum = Checksum::default();
+        write_data(&mut checksum)?;
+        let len = zip_u32(checksum.len)?;
+        let crc = checksum.crc.sum();
+        let offset = zip_u32(self.offset)?;
+
+        let mut local_header = Vec::new();
+        local_header.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        local_header.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+        write_member_info(&mut local_header, crc, len, name);
+        local_header.extend_from_slice(name.as_bytes());
+        self.write.write_all(&local_header)?;
+        write_data(&mut *self.write)?;
+
+        let directory = &mut self.central_directory;
+        directory.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        directory.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+        directory.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+        write_member_info(directory, crc, len, name);
+        // Comment length, disk number, and file attributes.
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+
+        self.n_members += 1;
+        self.offset += local_header.len() as u64 + checksum.len;
+
+        Ok(())
+    }
+
+    /// Write the central directory of the archive.
+    fn finish(self) -> Result<()> {
+        let offset = zip_u32(self.offset)?;
+        self.write.write_all(&self.central_directory)?;
+
+        let mut end = Vec::new();
+        end.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        // Disk numbers.
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&self.n_members.to_le_bytes());
+        end.extend_from_slice(&self.n_members.to_le_bytes());
+        end.extend_from_slice(&(self.central_directory.len() as u32).to_le_bytes());
+        end.extend_from_slice(&offset.to_le_bytes());
+        // Comment length.
+        end.extend_from_slice(&[0; 2]);
+        self.write.write_all(&end)?;
+
+        Ok(())
+    }
+}
+
+/// Write the member information that is shared by local headers and the
+/// central directory.
+fn write_member_info(header: &mut Vec<u8>, crc: u32, len: u32, name: &str) {
+    // Flags, compression method (stored), and time.
+    header.extend_from_slice(&[0; 6]);
+    header.extend_from_slice(&ZIP_DATE.to_le_bytes());
+    header.extend_from_slice(&crc.to_le_bytes());
+    // Compressed and uncompressed size.
+    header.extend_from_slice(&len.to_le_bytes());
+    header.extend_from_slice(&len.to_le_bytes());
+    header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    // Extra field length.
+    header.extend_from_slice(&[0; 2]);
+}
+
+fn zip_u32(v: u64) -> Result<u32> {
+    u32::try_from(v).map_err(|_| anyhow!("Embeddings are too large for an npz file (> 4GiB)"))
+}
+
+/// Writer that computes the checksum and length of the data.
+#[derive(Default)]
+struct Checksum {
+    crc: Crc,
+    len: u64,
+}
+
+impl Write for Checksum {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.crc.update(buf);
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::{npy_header, write_npz};
+    use crate::config::Precision;
+
+    #[test]
+    fn npy_header_is_aligned() {
+        for &(rows, cols) in &[(0, 0), (2, 3), (2_000_000, 300)] {
+            let header = npy_header("<f4", rows, cols);
+            assert_eq!(header.len() % 64, 0);
+            assert!(header.starts_with(b"\x93NUMPY\x01\x00"));
+            assert_eq!(*header.last().unwrap(), b'\n');
+        }
+    }
+
+    #[test]
+    fn write_npz_test() {
+        let words = vec!["hello".to_string(), "world".to_string()];
+        let embeds = array![[1f32, 2., 3.], [4., 5., 6.]];
+
+        let mut data = Vec::new();
+        write_npz(&mut data, &words, embeds.view(), Precision::F32).unwrap();
+
+        assert!(data.starts_with(b"PK\x03\x04"));
+        let end = &data[data.len() - 22..];
+        assert!(end.starts_with(b"PK\x05\x06"));
+        // Number of members.
+        assert_eq!(&end[10..12], &[2, 0]);
+
+        // The matrix is stored after the local header and the npy header.
+        let matrix_offset = 30 + "embeddings.npy".len() + 64;
+        assert_eq!(&data[matrix_offset..matrix_offset + 4], &1f32.to_le_bytes());
+
+        let vocab = b"hello\nworld\n";
+        assert!(data.windows(vocab.len()).any(|window| window == vocab));
+    }
+
+    #[test]
+    fn write_npz_half_precision() {
+        let words = vec!["hello".to_string()];
+        let embeds = array![[1f32, -2.]];
+
+        let mut data = Vec::new();
+        write_npz(&mut data, &words, embeds.view(), Precision::F16).unwrap();
+
+        let header = npy_header("<f2", 1, 2);
+        let matrix_offset = 30 + "embeddings.npy".len();
+        assert_eq!(&data[matrix_offset..matrix_offset + header.len()], &*header);
+        let matrix_offset = matrix_offset + header.len();
+        assert_eq!(
+            &data[matrix_offset..matrix_offset + 4],
+            &[0x00, 0x3c, 0x00, 0xc0]
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+use ndarray::{s, Array1, Array2, ArrayView2};
+
+/// Directory with the NUMA nodes in sysfs.
+const SYSFS_NODES: &str = "/sys/devices/system/node";
+
+/// NUMA node.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct NumaNode {
+    id: usize,
+    cpus: Vec<usize>,
+}
+
+/// NUMA topology.
+///
+/// The topology consists of the NUMA nodes of the machine and their CPUs.
+/// It is used to place training threads and the embedding matrices on
+/// the nodes. Hogwild threads on different sockets update the same
+/// matrices, so without placement, all memory traffic goes to the node
+/// that allocated the matrices.
+///
+/// Thread pinning and memory placement are only supported on Linux.
+#[derive(Clone, Debug)]
+pub struct NumaTopology {
+    nodes: Vec<NumaNode>,
+}
+
+impl NumaTopology {
+    /// Detect the NUMA topology of the machine.
+    ///
+    /// The topology is read from sysfs. Nodes without CPUs (memory-only
+    /// nodes) are ignored. If the topology is not available, all CPUs are
+    /// assumed to be in a single node.
+    pub fn detect() -> Result<Self> {
+        Self::detect_in(Path::new(SYSFS_NODES))
+    }
+
+    /// Detect the NUMA topology from the node directories in `nodes_dir`.
+    fn detect_in(nodes_dir: &Path) -> Result<Self> {
+        if !nodes_dir.is_dir() {
+            return Ok(Self::single_node());
+        }
+
+        let mut nodes = Vec::new();
+        for entry in fs::read_dir(nodes_dir).context("Cannot read NUMA nodes")? {
+            let entry = entry.context("Cannot read NUMA node")?;
+            let id = match entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("node"))
+                .and_then(|id| id.parse().ok())
+            {
+                Some(id) => id,
+                None => continue,
+            };
+
+            let cpu_list = fs::read_to_string(entry.path().join("cpulist"))
+                .with_context(|| format!("Cannot read CPUs of NUMA node {}", id))?;
+            let cpus = parse_cpu_list(cpu_list.trim())
+                .with_context(|| format!("Cannot parse CPUs of NUMA node {}", id))?;
+            if !cpus.is_empty() {
+                nodes.push(NumaNode { id, cpus });
+            }
+        }
+
+        if nodes.is_empty() {
+            return Ok(Self::single_node());
+        }
+
+        nodes.sort_by_key(|node| node.id);
+
+        Ok(NumaTopology { nodes })
+    }
+
+    /// Construct a topology with a single node that has all CPUs.
+    fn single_node() -> Self {
+        NumaTopology {
+            nodes: vec![NumaNode {
+                id: 0,
+                cpus: (0..num_cpus::get()).collect(),
+            }],
+        }
+    }
+
+    /// Get the number of NUMA nodes.
+    pub fn n_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Get the CPUs on which training thread `thread` is placed.
+    ///
+    /// Threads are assigned to the nodes in turn, so that every node
+    /// gets the same share of the threads.
+    pub fn thread_cpus(&self, thread: usize) -> &[usize] {
+        &self.nodes[thread % self.nodes.len()].cpus
+    }
+
+    /// Pin the calling thread to the CPUs of training thread `thread`.
+    pub fn pin_thread(&self, thread: usize) -> Result<()> {
+        set_affinity(self.thread_cpus(thread))
+    }
+
+    /// Interleave the pages of `data` over the NUMA nodes.
+    ///
+    /// Pages that were already allocated are moved to their nodes. `data`
+    /// must start on a page boundary, see `page_aligned`.
+    pub(crate) fn interleave<A>(&self, data: &mut [A]) -> Result<()> {
+        if self.nodes.len() == 1 {
+            return Ok(());
+        }
+
+        ensure!(
+            data.as_ptr().align_offset(page_size()) == 0,
+            "Memory to interleave does not start on a page boundary"
+        );
+
+        let nodes = self.nodes.iter().map(|node| node.id).collect::<Vec<_>>();
+        interleave_memory(data, &nodes)
+    }
+}
+
+/// Copy `array` to memory that is page-aligned.
+///
+/// The copy starts on a page boundary and no other data is allocated on
+/// its last page, so that changing the placement of its pages does not
+/// affect other data.
+pub(crate) fn page_aligned<A>(array: ArrayView2<A>) -> Array2<A>
+where
+    A: Clone + Default,
+{
+    let page_size = page_size();
+    let elem_size = mem::size_of::<A>();
+    assert_eq!(
+        page_size % elem_size,
+        0,
+        "Page size is not a multiple of the element size"
+    );
+    let page_len = page_size / elem_size;
+
+    // Allocate an additional page before and after the data, so that
+    // the start of the data can be moved to a page boundary and its
+    // last page can be padded.
+    let data = Array1::from(vec![A::default(); array.len() + 2 * page_len]);
+    let offset = data.as_ptr().align_offset(page_size);
+    let mut aligned = data
+        .slice_move(s![offset..offset + array.len()])
+        .into_shape(array.raw_dim())
+        .expect("Aligned array has incorrect length");
+    aligned.assign(&array);
+
+    aligned
+}
+
+/// Get the page size of the operating system.
+#[cfg(unix)]
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[cfg(not(unix))]
+fn page_size() -> usize {
+    4096
+}
+
+/// Parse a CPU list, such as `0-3,8-11`.
+fn parse_cpu_list(cpu_list: &str) -> Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in cpu_list.split(',').filter(|range| !range.is_empty()) {
+        let (first, last) = match range.find('-') {
+            Some(idx) => (&range[..idx], &range[idx + 1..]),
+            None => (range, range),
+        };
+        let first: usize = first
+            .parse()
+            .with_context(|| format!("Cannot parse CPU: {}", first))?;
+        let last: usize = last
+            .parse()
+            .with_context(|| format!("Cannot parse CPU: {}", last))?;
+        ensure!(first <= last, "Invalid CPU range: {}", range);
+        cpus.extend(first..=last);
+    }
+
+    Ok(cpus)
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: &[usize]) -> Result<()> {
+    use std::io;
+
+    // The CPU set is zeroed and then filled, CPUs beyond the set size
+    // cannot be represented.
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for &cpu in cpus {
+        ensure!(
+            cpu < libc::CPU_SETSIZE as usize,
+            "CPU {} cannot be used for thread pinning",
+            cpu
+        );
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+
+    if unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(io::Error::last_os_error()).context("Cannot pin thread to CPUs");
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cpus: &[usize]) -> Result<()> {
+    anyhow::bail!("Thread pinning is only supported on Linux")
+}
+
+#[cfg(target_os = "linux")]
+fn interleave_memory<A>(data: &mut [A], nodes: &[usize]) -> Result<()> {
+    use std::io;
+
+    // Interleave memory policy of `mbind`.
+    const MPOL_INTERLEAVE: libc::c_int = 3;
+
+    // Move pages that were already allocated to conform to the policy.
+    const MPOL_MF_MOVE: libc::c_uint = 1 << 1;
+
+    let start = data.as_mut_ptr() as usize;
+    let end = start + mem::size_of_val(data);
+    if start == end {
+        return Ok(());
+    }
+
+    // The kernel uses one bit less than the given number of bits of the
+    // node mask, so make room for one more node than necessary.
+    let bits = mem::size_of::<libc::c_ulong>() * 8;
+    let n_bits = nodes.iter().max().unwrap() + 2;
+    let mut mask = vec![0 as libc::c_ulong; n_bits.div_ceil(bits)];
+    for &node in nodes {
+        mask[node / bits] |= 1 << (node % bits);
+    }
+
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            start,
+            end - start,
+            MPOL_INTERLEAVE,
+            mask.as_ptr(),
+            mask.len() * bits,
+            MPOL_MF_MOVE,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error()).context("Cannot interleave memory over NUMA nodes");
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn interleave_memory<A>(_data: &mut [A], _nodes: &[usize]) -> Result<()> {
+    anyhow::bail!("NUMA memory placement is only supported on Linux")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use ndarray::Array2;
+
+    use super::{page_aligned, page_size, parse_cpu_list, NumaNode, NumaTopology};
+
+    #[test]
+    fn parse_cpu_list_test() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11").unwrap(),
+            vec![0, 1, 2, 3, 8, 10, 11]
+        );
+        assert!(parse_cpu_list("").unwrap().is_empty());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+    }
+
+    #[test]
+    fn threads_are_spread_over_nodes() {
+        let topology = NumaTopology {
+            nodes: vec![
+                NumaNode {
+                    id: 0,
+                    cpus: vec![0, 1],
+                },
+                NumaNode {
+                    id: 1,
+                    cpus: vec![2, 3],
+                },
+            ],
+        };
+
+        assert_eq!(topology.thread_cpus(0), &[0, 1]);
+        assert_eq!(topology.thread_cpus(1), &[2, 3]);
+        assert_eq!(topology.thread_cpus(2), &[0, 1]);
+    }
+
+    #[test]
+    fn detect_reads_nodes() {
+        let topology = NumaTopology::detect_in(Path::new("testdata/numa")).unwrap();
+
+        // Memory-only nodes and other files are ignored.
+        assert_eq!(
+            topology.nodes,
+            vec![
+                NumaNode {
+                    id: 0,
+                    cpus: vec![0, 1, 4],
+                },
+                NumaNode {
+                    id: 1,
+                    cpus: vec![2, 3],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn detect_without_sysfs_uses_single_node() {
+        let topology = NumaTopology::detect_in(Path::new("testdata/numa/nonexistent")).unwrap();
+        assert_eq!(topology.n_nodes(), 1);
+        assert!(!topology.thread_cpus(0).is_empty());
+    }
+
+    #[test]
+    fn page_aligned_copies_to_page_boundary() {
+        let array = Array2::from_shape_fn((7, 3), |(row, col)| (row * 3 + col) as f32);
+        let aligned = page_aligned(array.view());
+        assert_eq!(aligned, array);
+        assert_eq!(aligned.as_ptr().align_offset(page_size()), 0);
+        assert!(aligned.is_standard_layout());
+    }
+}
@@ -0,0 +1,350 @@
+use anyhow::{bail, ensure, Result};
+use ndarray::{Array1, Array2, ArrayView1, ArrayViewMut1, Axis};
+
+use crate::config::OptimizerType;
+use crate::hogwild::{HogwildArray1, HogwildArray2};
+use crate::vec_simd::scaled_add;
+
+/// Exponential decay rate of the first moment estimates of Adam.
+const ADAM_BETA1: f32 = 0.9;
+
+/// Exponential decay rate of the second moment estimates of Adam.
+const ADAM_BETA2: f32 = 0.999;
+
+/// Constant for numerical stability of Adam.
+const ADAM_EPSILON: f32 = 1e-8;
+
+/// Optimizer
+///
+/// An optimizer defines the update rule of the rows of an embedding
+/// matrix. `Sgd` uses one optimizer for the input matrix and one for the
+/// output matrix.
+///
+/// Training threads work on clones of the same optimizer. Similar to the
+/// embedding matrices, clones should share their state (e.g. using
+/// Hogwild arrays).
+pub trait Optimizer: Clone {
+    /// Update row `idx` with the gradient `scale * grad`.
+    ///
+    /// `param` is the embedding in row `idx`. The gradient points in the
+    /// direction that decreases the loss, so plain stochastic gradient
+    /// descent adds `lr * scale * grad` to `param`.
+    fn update(
+        &mut self,
+        param: ArrayViewMut1<f32>,
+        idx: usize,
+        grad: ArrayView1<f32>,
+        scale: f32,
+        lr: f32,
+    );
+}
+
+/// Stochastic gradient descent.
+///
+/// This optimizer does not have a state.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlainSgd;
+
+impl Optimizer for PlainSgd {
+    fn update(
+        &mut self,
+        param: ArrayViewMut1<f32>,
+        _idx: usize,
+        grad: ArrayView1<f32>,
+        scale: f32,
+        lr: f32,
+    ) {
+        scaled_add(param, grad, lr * scale);
+    }
+}
+
+/// AdaGrad (Duchi et al., 2011) with an accumulator per row.
+///
+/// Storing the mean squared gradient of every row rather than the
+/// squared gradient of every parameter reduces the memory use of the
+/// accumulators by a factor of the dimensionality.
+#[derive(Clone)]
+pub struct AdaGrad {
+    grad_sq: HogwildArray1<f32>,
+}
+
+impl AdaGrad {
+    /// Construct AdaGrad for a matrix with `n_rows` rows.
+    pub fn new(n_rows: usize) -> Self {
+        // Accumulators are initialized to one, as in GloVe.
+        AdaGrad {
+            grad_sq: Array1::ones((n_rows,)).into(),
+        }
+    }
+}
+
+impl Optimizer for AdaGrad {
+    fn update(
+        &mut self,
+        param: ArrayViewMut1<f32>,
+        idx: usize,
+        grad: ArrayView1<f32>,
+        scale: f32,
+        lr: f32,
+    ) {
+        adagrad_update(param, &mut self.grad_sq.view_mut()[idx], grad, scale, lr);
+    }
+}
+
+/// Adam (Kingma and Ba, 2015) with lazy updates.
+///
+/// Only the moments of the rows that are updated are decayed. Every row
+/// has its own step count for bias correction.
+#[derive(Clone)]
+pub struct Adam {
+    m: HogwildArray2<f32>,
+    v: HogwildArray2<f32>,
+    steps: HogwildArray1<u64>,
+}
+
+impl Adam {
+    /// Construct Adam for a matrix with the shape `(n_rows, dims)`.
+    pub fn new(n_rows: usize, dims: usize) -> Self {
+        Adam {
+            m: Array2::zeros((n_rows, dims)).into(),
+            v: Array2::zeros((n_rows, dims)).into(),
+            steps: Array1::zeros((n_rows,)).into(),
+        }
+    }
+}
+
+impl Optimizer for Adam {
+    fn update(
+        &mut self,
+        param: ArrayViewMut1<f32>,
+        idx: usize,
+        grad: ArrayView1<f32>,
+        scale: f32,
+        lr: f32,
+    ) {
+        adam_update(
+            param,
+            self.m.subview_mut(Axis(0), idx),
+            self.v.subview_mut(Axis(0), idx),
+            &mut self.steps.view_mut()[idx],
+            grad,
+            scale,
+            lr,
+        );
+    }
+}
+
+/// Optimizer wrapper
+///
+/// This wrapper is used to select one of the optimizers of this crate
+/// through `OptimizerType`.
+#[derive(Clone)]
+pub enum OptimizerWrap {
+    Sgd(PlainSgd),
+    AdaGrad(AdaGrad),
+    Adam(Adam),
+}
+
+impl OptimizerWrap {
+    /// Construct an optimizer for a matrix with the shape `(n_rows, dims)`.
+    pub fn new(optimizer: OptimizerType, n_rows: usize, dims: usize) -> Self {
+        match optimizer {
+            OptimizerType::Sgd => OptimizerWrap::Sgd(PlainSgd),
+            OptimizerType::AdaGrad => OptimizerWrap::AdaGrad(AdaGrad::new(n_rows)),
+            OptimizerType::Adam => OptimizerWrap::Adam(Adam::new(n_rows, dims)),
+        }
+    }
+}
+
+impl OptimizerWrap {
+    /// Copy the state of the optimizer.
+    pub(crate) fn state(&self) -> OptimizerState {
+        match self {
+            OptimizerWrap::Sgd(_) => OptimizerState::Sgd,
+            OptimizerWrap::AdaGrad(optimizer) => {
+                OptimizerState::AdaGrad(optimizer.grad_sq.view().to_owned())
+            }
+            OptimizerWrap::Adam(optimizer) => OptimizerState::Adam {
+                m: optimizer.m.view().to_owned(),
+                v: optimizer.v.view().to_owned(),
+                steps: optimizer.steps.view().to_owned(),
+            },
+        }
+    }
+
+    /// Restore the state of the optimizer.
+    ///
+    /// The state is replaced in place, so that clones of the optimizer
+    /// share the restored state.
+    pub(crate) fn restore(&mut self, state: &OptimizerState) -> Result<()> {
+        match (self, state) {
+            (OptimizerWrap::Sgd(_), OptimizerState::Sgd) => (),
+            (OptimizerWrap::AdaGrad(optimizer), OptimizerState::AdaGrad(grad_sq)) => {
+                ensure!(
+                    optimizer.grad_sq.view().dim() == grad_sq.dim(),
+                    "Shape of the AdaGrad state differs"
+                );
+                optimizer.grad_sq.view_mut().assign(grad_sq);
+            }
+            (OptimizerWrap::Adam(optimizer), OptimizerState::Adam { m, v, steps }) => {
+                ensure!(
+                    optimizer.m.view().dim() == m.dim()
+                        && optimizer.v.view().dim() == v.dim()
+                        && optimizer.steps.view().dim() == steps.dim(),
+                    "Shape of the Adam state differs"
+                );
+                optimizer.m.view_mut().assign(m);
+                optimizer.v.view_mut().assign(v);
+                optimizer.steps.view_mut().assign(steps);
+            }
+            _ => bail!("The optimizer state is of a different optimizer"),
+        }
+
+        Ok(())
+    }
+}
+
+impl Optimizer for OptimizerWrap {
+    fn update(
+        &mut self,
+        param: ArrayViewMut1<f32>,
+        idx: usize,
+        grad: ArrayView1<f32>,
+        scale: f32,
+        lr: f32,
+    ) {
+        match self {
+            OptimizerWrap::Sgd(optimizer) => optimizer.update(param, idx, grad, scale, lr),
+            OptimizerWrap::AdaGrad(optimizer) => optimizer.update(param, idx, grad, scale, lr),
+            OptimizerWrap::Adam(optimizer) => optimizer.update(param, idx, grad, scale, lr),
+        }
+    }
+}
+
+/// Copy of the state of an optimizer.
+///
+/// The state is stored in checkpoints, so that the optimizer can continue
+/// where it stopped.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum OptimizerState {
+    Sgd,
+    AdaGrad(Array1<f32>),
+    Adam {
+        m: Array2<f32>,
+        v: Array2<f32>,
+        steps: Array1<u64>,
+    },
+}
+
+/// Apply an AdaGrad update with the gradient `scale * grad` to a row.
+///
+/// The mean squared gradient of the row is accumulated in `grad_sq`.
+fn adagrad_update(
+    param: ArrayViewMut1<f32>,
+    grad_sq: &mut f32,
+    grad: ArrayView1<f32>,
+    scale: f32,
+    lr: f32,
+) {
+    scaled_add(param, grad, lr * scale / grad_sq.sqrt());
+    *grad_sq += scale * scale * grad.dot(&grad) / grad.len() as f32;
+}
+
+/// Apply a lazy Adam update with the gradient `scale * grad` to a row.
+///
+/// The first and second moment estimates of the row are stored in `m` and
+/// `v`, the number of updates of the row in `steps`.
+fn adam_update(
+    param: ArrayViewMut1<f32>,
+    m: ArrayViewMut1<f32>,
+    v: ArrayViewMut1<f32>,
+    steps: &mut u64,
+    grad: ArrayView1<f32>,
+    scale: f32,
+    lr: f32,
+) {
+    *steps += 1;
+    let m_correction = 1. - ADAM_BETA1.powf(*steps as f32);
+    let v_correction = 1. - ADAM_BETA2.powf(*steps as f32);
+
+    for (((p, m), v), &g) in param.into_iter().zip(m).zip(v).zip(grad) {
+        let g = scale * g;
+        *m = ADAM_BETA1 * *m + (1. - ADAM_BETA1) * g;
+        *v = ADAM_BETA2 * *v + (1. - ADAM_BETA2) * g * g;
+        *p += lr * (*m / m_correction) / ((*v / v_correction).sqrt() + ADAM_EPSILON);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array2, Axis};
+
+    use super::{adagrad_update, adam_update, AdaGrad, Optimizer};
+    use crate::util::{all_close, close};
+
+    #[test]
+    fn adagrad_update_test() {
+        let mut param = array![1., 1.];
+        let mut grad_sq = 4.;
+        adagrad_update(
+            param.view_mut(),
+            &mut grad_sq,
+            array![2., 0.].view(),
+            0.5,
+            0.1,
+        );
+        assert!(all_close(param.as_slice().unwrap(), &[1.05, 1.], 1e-5));
+        assert!(close(grad_sq, 4.5, 1e-5));
+    }
+
+    #[test]
+    fn adagrad_clones_share_state() {
+        let mut params = Array2::ones((2, 2));
+        let mut optimizer = AdaGrad::new(2);
+        let mut clone = optimizer.clone();
+
+        // The accumulator of row 1 becomes 1 + 0.5 * 2^2 = 3.
+        optimizer.update(
+            params.index_axis_mut(Axis(0), 1),
+            1,
+            array![2., 0.].view(),
+            1.0,
+            0.1,
+        );
+        clone.update(
+            params.index_axis_mut(Axis(0), 1),
+            1,
+            array![0., 3.].view(),
+            1.0,
+            0.1,
+        );
+        assert!(all_close(
+            params.as_slice().unwrap(),
+            &[1., 1., 1.2, 1. + 0.3 / 3f32.sqrt()],
+            1e-5
+        ));
+    }
+
+    #[test]
+    fn adam_update_test() {
+        let mut param = array![1., 1.];
+        let mut m = array![0., 0.];
+        let mut v = array![0., 0.];
+        let mut steps = 0;
+
+        // Due to bias correction, the first update is lr * sign(grad).
+        adam_update(
+            param.view_mut(),
+            m.view_mut(),
+            v.view_mut(),
+            &mut steps,
+            array![4., -1.].view(),
+            0.5,
+            0.1,
+        );
+        assert!(all_close(param.as_slice().unwrap(), &[1.1, 0.9], 1e-5));
+        assert!(all_close(m.as_slice().unwrap(), &[0.2, -0.05], 1e-5));
+        assert!(all_close(v.as_slice().unwrap(), &[0.004, 0.00025], 1e-5));
+        assert_eq!(steps, 1);
+    }
+}
@@ -0,0 +1,600 @@
+use std::borrow::Borrow;
+use std::cmp;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use ndarray::{s, Array1, Array2, ArrayView2, Axis};
+use ndarray_rand::rand_distr::StandardNormal;
+use ndarray_rand::RandomExt;
+use rand::Rng;
+use serde::Serialize;
+
+use crate::glove::CooccurrenceMatrix;
+use crate::idx::WordIdx;
+use crate::train_model::{TrainModel, Trainer};
+use crate::vec_simd::{dot, scale, scaled_add};
+use crate::{CommonConfig, PmiConfig, Vocab};
+
+/// The maximum number of sweeps of the Jacobi eigenvalue algorithm.
+const MAX_JACOBI_SWEEPS: usize = 100;
+
+/// Convergence tolerance of the Jacobi eigenvalue algorithm, relative
+/// to the squared norm of the diagonal.
+const JACOBI_TOLERANCE: f64 = 1e-20;
+
+/// PMI Trainer.
+///
+/// The `PmiTrainer` counts the co-occurrences in tokenized sentences and
+/// trains embeddings by factorizing the shifted positive PMI matrix of
+/// the co-occurrence counts (Levy et al., 2015). Frequent words are
+/// discarded before counting using the discard probabilities of the
+/// vocabulary. The struct is cheap to clone because the vocabulary is
+/// shared between clones.
+#[derive(Clone)]
+pub struct PmiTrainer<R, V> {
+    vocab: Arc<V>,
+    rng: R,
+    common_config: CommonConfig,
+    pmi_config: PmiConfig,
+}
+
+impl<R, V> PmiTrainer<R, V>
+where
+    R: Rng,
+    V: Vocab,
+{
+    /// Constructs a new `PmiTrainer`.
+    pub fn new(vocab: V, rng: R, common_config: CommonConfig, pmi_config: PmiConfig) -> Self {
+        PmiTrainer {
+            vocab: Arc::new(vocab),
+            rng,
+            common_config,
+            pmi_config,
+        }
+    }
+
+    /// Get the PMI hyperparameters.
+    pub fn pmi_config(&self) -> PmiConfig {
+        self.pmi_config
+    }
+
+    /// Count the co-occurrences in a sentence.
+    ///
+    /// Tokens that are not in the vocabulary or that are discarded are
+    /// removed from the sentence before counting. Every pair of tokens
+    /// within the context window is counted once in both directions.
+    pub fn count_cooccurrences<S>(&mut self, sequence: &[S], matrix: &mut CooccurrenceMatrix)
+    where
+        S: Hash + Eq,
+        V::VocabType: Borrow<S>,
+    {
+        let mut ids = Vec::new();
+        for t in sequence {
+            if let Some(idx) = self.vocab.idx(t) {
+                let word_idx = idx.word_idx() as usize;
+                if self.rng.gen_range(0f32..1f32) < self.vocab.discard(word_idx) {
+                    ids.push(word_idx as u32);
+                }
+            }
+        }
+
+        let ctx_size = self.pmi_config.context_size as usize;
+        for (i, &focus) in ids.iter().enumerate() {
+            for &context in ids[i + 1..].iter().take(ctx_size) {
+                matrix.count(focus, context, 1.);
+                matrix.count(context, focus, 1.);
+            }
+        }
+    }
+
+    /// Compute the shifted positive PMI matrix of co-occurrence counts.
+    ///
+    /// The context distribution is smoothed by raising the context counts
+    /// to the power of the context smoothing exponent. Only cells with a
+    /// positive shifted PMI are stored.
+    pub fn ppmi_matrix(&self, matrix: &CooccurrenceMatrix) -> SparseMatrix {
+        let n_words = self.vocab.len();
+
+        let mut focus_counts = vec![0f64; n_words];
+        let mut context_counts = vec![0f64; n_words];
+        for cooccurrence in matrix.iter() {
+            focus_counts[cooccurrence.focus as usize] += cooccurrence.count as f64;
+            context_counts[cooccurrence.context as usize] += cooccurrence.count as f64;
+        }
+
+        let context_smoothing = self.pmi_config.context_smoothing as f64;
+        for count in &mut context_counts {
+            *count = count.powf(context_smoothing);
+        }
+        let context_total: f64 = context_counts.iter().sum();
+        let ln_shift = (self.pmi_config.shift as f64).ln();
+
+        // PMI(w, c) = log(p(w, c) / (p(w) p(c))), where the total count
+        // of the joint and focus distribution cancels out.
+        let triplets = matrix
+            .iter()
+            .filter_map(|cooccurrence| {
+                let focus = cooccurrence.focus as usize;
+                let context = cooccurrence.context as usize;
+                let pmi = (cooccurrence.count as f64 * context_total
+                    / (focus_counts[focus] * context_counts[context]))
+                    .ln()
+                    - ln_shift;
+                if pmi > 0. {
+                    Some((cooccurrence.focus, cooccurrence.context, pmi as f32))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        SparseMatrix::from_triplets(n_words, n_words, triplets)
+    }
+
+    /// Train embeddings from co-occurrence counts.
+    ///
+    /// The shifted positive PMI matrix is factorized using randomized
+    /// SVD. The embedding of a word is its left singular vector, weighted
+    /// by the singular values raised to the power of the eigenvalue
+    /// weight. Embeddings of subword units are zero.
+    pub fn train(mut self, matrix: &CooccurrenceMatrix) -> TrainModel<Self>
+    where
+        V::Config: Serialize,
+    {
+        let ppmi = self.ppmi_matrix(matrix);
+        let (u, sigma) = randomized_svd(
+            &ppmi,
+            self.common_config.dims as usize,
+            self.pmi_config.oversampling as usize,
+            self.pmi_config.power_iterations as usize,
+            &mut self.rng,
+        );
+
+        let mut input =
+            Array2::zeros((self.vocab.n_input_types(), self.common_config.dims as usize));
+        let weights = sigma.mapv(|s| s.powf(self.pmi_config.eigenvalue_weight));
+        input
+            .slice_mut(s![..u.nrows(), ..u.ncols()])
+            .assign(&(u * &weights));
+
+        TrainModel::from_input_matrix(self, input)
+    }
+}
+
+impl<R, V> Trainer for PmiTrainer<R, V>
+where
+    V: Vocab,
+    V::Config: Serialize,
+{
+    type InputVocab = V;
+    type Metadata = PmiMetadata<V::Config>;
+
+    fn input_vocab(&self) -> &V {
+        &self.vocab
+    }
+
+    fn try_into_input_vocab(self) -> Result<V> {
+        match Arc::try_unwrap(self.vocab) {
+            Ok(vocab) => Ok(vocab),
+            Err(_) => bail!("Cannot unwrap input vocab."),
+        }
+    }
+
+    fn n_input_types(&self) -> usize {
+        self.input_vocab().n_input_types()
+    }
+
+    /// PMI models do not have output embeddings.
+    fn n_output_types(&self) -> usize {
+        0
+    }
+
+    fn config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+
+    fn to_metadata(&self) -> PmiMetadata<V::Config> {
+        PmiMetadata {
+            common_config: self.common_config,
+            pmi_config: self.pmi_config,
+            vocab_config: self.vocab.config(),
+        }
+    }
+}
+
+/// Metadata for PMI embeddings.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct PmiMetadata<V> {
+    common_config: CommonConfig,
+    #[serde(rename = "model_config")]
+    pmi_config: PmiConfig,
+    vocab_config: V,
+}
+
+/// Sparse matrix in compressed sparse row format.
+#[derive(Clone, Debug)]
+pub struct SparseMatrix {
+    n_cols: usize,
+    row_offsets: Vec<usize>,
+    cols: Vec<u32>,
+    values: Vec<f32>,
+}
+
+impl SparseMatrix {
+    /// Construct a sparse matrix from (row, column, value) triplets.
+    ///
+    /// The triplets must not contain duplicate cells.
+    pub fn from_triplets(n_rows: usize, n_cols: usize, mut triplets: Vec<(u32, u32, f32)>) -> Self {
+        triplets.sort_unstable_by_key(|&(row, col, _)| (row, col));
+
+        let mut row_offsets = vec![0; n_rows + 1];
+        for &(row, col, _) in &triplets {
+            assert!(
+                (row as usize) < n_rows && (col as usize) < n_cols,
+                "Cell ({}, {}) is out of bounds",
+                row,
+                col
+            );
+            row_offsets[row as usize + 1] += 1;
+        }
+        for row in 0..n_rows {
+            row_offsets[row + 1] += row_offsets[row];
+        }
+
+        SparseMatrix {
+            n_cols,
+            row_offsets,
+            cols: triplets.iter().map(|&(_, col, _)| col).collect(),
+            values: triplets.iter().map(|&(_, _, value)| value).collect(),
+        }
+    }
+
+    /// Get the number of rows.
+    pub fn n_rows(&self) -> usize {
+        self.row_offsets.len() - 1
+    }
+
+    /// Get the number of columns.
+    pub fn n_cols(&self) -> usize {
+        self.n_cols
+    }
+
+    /// Get the number of non-zero cells.
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Get the value of a cell.
+    pub fn get(&self, row: usize, col: usize) -> f32 {
+        let range = self.row_offsets[row]..self.row_offsets[row + 1];
+        match self.cols[range.clone()].binary_search(&(col as u32)) {
+            Ok(idx) => self.values[range.start + idx],
+            Err(_) => 0.,
+        }
+    }
+
+    /// Compute the product of this matrix and `rhs`.
+    pub fn dot(&self, rhs: ArrayView2<f32>) -> Array2<f32> {
+        assert_eq!(self.n_cols, rhs.nrows(), "Incompatible matrix shapes");
+
+        let rhs = rhs.as_standard_layout();
+        let mut result = Array2::zeros((self.n_rows(), rhs.ncols()));
+        for (row, mut result_row) in result.outer_iter_mut().enumerate() {
+            for (&col, &value) in self.row(row) {
+                scaled_add(result_row.view_mut(), rhs.row(col as usize), value);
+            }
+        }
+
+        result
+    }
+
+    /// Compute the product of the transpose of this matrix and `rhs`.
+    pub fn t_dot(&self, rhs: ArrayView2<f32>) -> Array2<f32> {
+        assert_eq!(self.n_rows(), rhs.nrows(), "Incompatible matrix shapes");
+
+        let rhs = rhs.as_standard_layout();
+        let mut result = Array2::zeros((self.n_cols, rhs.ncols()));
+        for (row, rhs_row) in rhs.outer_iter().enumerate() {
+            for (&col, &value) in self.row(row) {
+                scaled_add(result.index_axis_mut(Axis(0), col as usize), rhs_row, value);
+            }
+        }
+
+        result
+    }
+
+    /// Get an iterator over the columns and values of the non-zero cells
+    /// of a row.
+    fn row(&self, row: usize) -> impl Iterator<Item = (&u32, &f32)> {
+        let range = self.row_offsets[row]..self.row_offsets[row + 1];
+        self.cols[range.clone()].iter().zip(&self.values[range])
+    }
+}
+
+/// Compute a truncated singular value decomposition.
+///
+/// The range of `matrix` is approximated with `rank + oversampling`
+/// random projections, refined with `power_iterations` power iterations
+/// (Halko et al., 2011). Returns the left singular vectors and singular
+/// values for the `rank` largest singular values.
+fn randomized_svd<R>(
+    matrix: &SparseMatrix,
+    rank: usize,
+    oversampling: usize,
+    power_iterations: usize,
+    rng: &mut R,
+) -> (Array2<f32>, Array1<f32>)
+where
+    R: Rng,
+{
+    let n_samples = cmp::min(
+        rank + oversampling,
+        cmp::min(matrix.n_rows(), matrix.n_cols()),
+    );
+    let rank = cmp::min(rank, n_samples);
+
+    let omega: Array2<f32> =
+        Array2::random_using((matrix.n_cols(), n_samples), StandardNormal, rng);
+    let mut q = orthonormalize(matrix.dot(omega.view()));
+    for _ in 0..power_iterations {
+        let z = orthonormalize(matrix.t_dot(q.view()));
+        q = orthonormalize(matrix.dot(z.view()));
+    }
+
+    // The left singular vectors of B = Q^T M are the eigenvectors of
+    // B B^T, where B^T = M^T Q.
+    let b_t = matrix.t_dot(q.view());
+    let (eigenvalues, eigenvectors) = symmetric_eigen(b_t.t().dot(&b_t).mapv(f64::from));
+
+    let u = q.dot(&eigenvectors.slice(s![.., ..rank]).mapv(|v| v as f32));
+    let sigma = eigenvalues
+        .slice(s![..rank])
+        .mapv(|lambda| lambda.max(0.).sqrt() as f32);
+
+    (u, sigma)
+}
+
+/// Orthonormalize the columns of a matrix.
+///
+/// The columns are orthonormalized using the modified Gram-Schmidt
+/// process. Columns that are linearly dependent on the preceding
+/// columns are set to zero.
+fn orthonormalize(matrix: Array2<f32>) -> Array2<f32> {
+    let mut basis = matrix.reversed_axes().as_standard_layout().into_owned();
+
+    for i in 0..basis.nrows() {
+        let (prev, mut rest) = basis.view_mut().split_at(Axis(0), i);
+        let mut v = rest.index_axis_mut(Axis(0), 0);
+        for u in prev.outer_iter() {
+            let projection = dot(u, v.view());
+            scaled_add(v.view_mut(), u, -projection);
+        }
+
+        let norm = dot(v.view(), v.view()).sqrt();
+        if norm > 1e-6 {
+            scale(v, 1. / norm);
+        } else {
+            v.fill(0.);
+        }
+    }
+
+    basis.reversed_axes().as_standard_layout().into_owned()
+}
+
+/// Compute the eigenvalues and eigenvectors of a symmetric matrix.
+///
+/// The eigendecomposition is computed with the cyclic Jacobi eigenvalue
+/// algorithm. The eigenvalues are returned in descending order, the
+/// columns of the second matrix are the corresponding eigenvectors.
+fn symmetric_eigen(mut a: Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+    assert_eq!(a.nrows(), a.ncols(), "Matrix is not square");
+
+    let n = a.nrows();
+    let mut v = Array2::eye(n);
+
+    for _ in 0..MAX_JACOBI_SWEEPS {
+        let off_diagonal: f64 = a
+            .indexed_iter()
+            .filter(|((i, j), _)| i != j)
+            .map(|(_, &a_ij)| a_ij * a_ij)
+            .sum();
+        let diagonal: f64 = a.diag().iter().map(|&a_ii| a_ii * a_ii).sum();
+        if off_diagonal <= JACOBI_TOLERANCE * diagonal {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                let a_pq = a[[p, q]];
+                if a_pq == 0. {
+                    continue;
+                }
+
+                // Rotate rows and columns p and q, such that a_pq becomes zero.
+                let theta = (a[[q, q]] - a[[p, p]]) / (2. * a_pq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.).sqrt());
+                let c = 1. / (t * t + 1.).sqrt();
+                let s = t * c;
+
+                for k in 0..n {
+                    let (a_kp, a_kq) = (a[[k, p]], a[[k, q]]);
+                    a[[k, p]] = c * a_kp - s * a_kq;
+                    a[[k, q]] = s * a_kp + c * a_kq;
+                }
+                for k in 0..n {
+                    let (a_pk, a_qk) = (a[[p, k]], a[[q, k]]);
+                    a[[p, k]] = c * a_pk - s * a_qk;
+                    a[[q, k]] = s * a_pk + c * a_qk;
+                }
+                for k in 0..n {
+                    let (v_kp, v_kq) = (v[[k, p]], v[[k, q]]);
+                    v[[k, p]] = c * v_kp - s * v_kq;
+                    v[[k, q]] = s * v_kp + c * v_kq;
+                }
+            }
+        }
+    }
+
+    let mut order = (0..n).collect::<Vec<_>>();
+    order.sort_unstable_by(|&i, &j| a[[j, j]].partial_cmp(&a[[i, i]]).unwrap());
+
+    let eigenvalues = order.iter().map(|&i| a[[i, i]]).collect();
+    let eigenvectors = v.select(Axis(1), &order);
+
+    (eigenvalues, eigenvectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array2};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::{orthonormalize, randomized_svd, symmetric_eigen, PmiTrainer, SparseMatrix};
+    use crate::util::{all_close, close};
+    use crate::{
+        CommonConfig, CooccurrenceMatrix, Cutoff, PmiConfig, SimpleVocab, SimpleVocabConfig, Vocab,
+        VocabBuilder,
+    };
+
+    const TEST_COMMON_CONFIG: CommonConfig = CommonConfig {
+        dims: 2,
+        ..CommonConfig::for_test()
+    };
+
+    const TEST_PMI_CONFIG: PmiConfig = PmiConfig {
+        context_size: 1,
+        context_smoothing: 1.,
+        shift: 1.,
+        eigenvalue_weight: 0.5,
+        power_iterations: 2,
+        oversampling: 10,
+    };
+
+    fn test_trainer() -> PmiTrainer<XorShiftRng, SimpleVocab<&'static str>> {
+        // With a discard threshold of one, no tokens are discarded.
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(SimpleVocabConfig {
+            cutoff: Cutoff::MinCount(1),
+            discard_threshold: 1.,
+        });
+        for token in &["a", "a", "b", "c"] {
+            builder.count(*token);
+        }
+
+        PmiTrainer::new(
+            builder.into(),
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            TEST_PMI_CONFIG,
+        )
+    }
+
+    #[test]
+    fn count_cooccurrences() {
+        let mut trainer = test_trainer();
+        let a = trainer.vocab.idx("a").unwrap().idx() as u32;
+        let b = trainer.vocab.idx("b").unwrap().idx() as u32;
+        let c = trainer.vocab.idx("c").unwrap().idx() as u32;
+
+        let mut matrix = CooccurrenceMatrix::default();
+        trainer.count_cooccurrences(&["a", "d", "b", "c", "a"], &mut matrix);
+
+        assert!(close(matrix.get(a, b), 1.0, 1e-5));
+        assert!(close(matrix.get(b, a), 1.0, 1e-5));
+        assert!(close(matrix.get(b, c), 1.0, 1e-5));
+        assert!(close(matrix.get(c, a), 1.0, 1e-5));
+        assert!(close(matrix.get(a, a), 0.0, 1e-5));
+        assert_eq!(matrix.len(), 6);
+    }
+
+    #[test]
+    fn ppmi_matrix() {
+        let mut trainer = test_trainer();
+        trainer.pmi_config.shift = 1.5;
+        let a = trainer.vocab.idx("a").unwrap().idx() as usize;
+        let b = trainer.vocab.idx("b").unwrap().idx() as usize;
+        let c = trainer.vocab.idx("c").unwrap().idx() as usize;
+
+        let mut matrix = CooccurrenceMatrix::default();
+        matrix.count(a as u32, b as u32, 2.);
+        matrix.count(b as u32, a as u32, 2.);
+        matrix.count(a as u32, c as u32, 1.);
+        matrix.count(c as u32, a as u32, 1.);
+        matrix.count(b as u32, c as u32, 1.);
+        matrix.count(c as u32, b as u32, 1.);
+
+        // Total count: 8, focus/context counts: a = 3, b = 3, c = 2. The
+        // PMI of (a, c) and (b, c) is log(4/3), which is smaller than the
+        // shift log(1.5).
+        let ppmi = trainer.ppmi_matrix(&matrix);
+        assert!(close(ppmi.get(a, b), (2f32 * 8. / 9. / 1.5).ln(), 1e-5));
+        assert!(close(ppmi.get(b, a), (2f32 * 8. / 9. / 1.5).ln(), 1e-5));
+        assert!(close(ppmi.get(a, c), 0., 1e-5));
+        assert!(close(ppmi.get(c, b), 0., 1e-5));
+        assert_eq!(ppmi.nnz(), 2);
+    }
+
+    #[test]
+    fn sparse_matrix_products() {
+        let matrix = SparseMatrix::from_triplets(2, 3, vec![(1, 2, 3.), (0, 0, 1.), (1, 0, 2.)]);
+        let rhs = array![[1., 2.], [3., 4.], [5., 6.]];
+        assert!(all_close(
+            matrix.dot(rhs.view()).as_slice().unwrap(),
+            &[1., 2., 17., 22.],
+            1e-5
+        ));
+
+        let rhs = array![[1., 2.], [3., 4.]];
+        assert!(all_close(
+            matrix.t_dot(rhs.view()).as_slice().unwrap(),
+            &[7., 10., 0., 0., 9., 12.],
+            1e-5
+        ));
+    }
+
+    #[test]
+    fn orthonormalize_columns() {
+        let q = orthonormalize(array![[1., 1., 2.], [0., 1., 2.], [0., 0., 0.]]);
+
+        // The third column is linearly dependent on the first two.
+        assert!(all_close(
+            q.as_slice().unwrap(),
+            &[1., 0., 0., 0., 1., 0., 0., 0., 0.],
+            1e-5
+        ));
+    }
+
+    #[test]
+    fn symmetric_eigen_test() {
+        let (eigenvalues, eigenvectors) = symmetric_eigen(array![[2., 1.], [1., 2.]]);
+        assert!((eigenvalues[0] - 3.).abs() < 1e-10);
+        assert!((eigenvalues[1] - 1.).abs() < 1e-10);
+
+        let reconstruction = eigenvectors
+            .dot(&Array2::from_diag(&eigenvalues))
+            .dot(&eigenvectors.t());
+        for (&v, &expected) in reconstruction.iter().zip(&[2., 1., 1., 2.]) {
+            assert!((v - expected).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn randomized_svd_test() {
+        let matrix =
+            SparseMatrix::from_triplets(4, 4, vec![(0, 1, 4.), (1, 0, 1.), (2, 3, 3.), (3, 2, 2.)]);
+        let (u, sigma) = randomized_svd(&matrix, 2, 2, 2, &mut XorShiftRng::seed_from_u64(42));
+
+        assert!(all_close(sigma.as_slice().unwrap(), &[4., 3.], 1e-4));
+        assert!(all_close(
+            u.column(0).mapv(f32::abs).to_vec().as_slice(),
+            &[1., 0., 0., 0.],
+            1e-4
+        ));
+        assert!(all_close(
+            u.column(1).mapv(f32::abs).to_vec().as_slice(),
+            &[0., 0., 1., 0.],
+            1e-4
+        ));
+    }
+}
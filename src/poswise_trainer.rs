@@ -0,0 +1,307 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::iter::FusedIterator;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use rand::{Rng, RngCore, SeedableRng};
+use serde::Serialize;
+
+use crate::idx::WordIdx;
+use crate::sampling::{sample_context_window, NegativeSamplingRangeGenerator, RangeGenerator};
+use crate::skipgram_trainer::SkipgramMetadata;
+use crate::train_model::{NegativeSamples, OutputLabels, TrainIterFrom, Trainer};
+use crate::util::{Reseed, ReseedOnCloneRng};
+use crate::{CommonConfig, SkipGramConfig, Vocab};
+
+/// Position-weighted continuous bag-of-words Trainer
+///
+/// The `PoswiseTrainer` holds the information and logic necessary to transform a tokenized
+/// sentence into an iterator of positional contexts and focus word tuples (Mikolov et al.,
+/// 2018). Every relative position in the context window has a learned weight vector, which
+/// is stored after the word and subword embeddings in the input matrix. The struct is cheap
+/// to clone because the vocabulary is shared between clones.
+#[derive(Clone)]
+pub struct PoswiseTrainer<R, V> {
+    vocab: Arc<V>,
+    rng: R,
+    range_gen: NegativeSamplingRangeGenerator<R>,
+    common_config: CommonConfig,
+    skipgram_config: SkipGramConfig,
+}
+
+impl<R, V> PoswiseTrainer<ReseedOnCloneRng<R>, V>
+where
+    R: Rng + Clone + SeedableRng,
+    V: Vocab,
+{
+    /// Constructs a new `PoswiseTrainer`.
+    pub fn new(
+        vocab: V,
+        rng: R,
+        common_config: CommonConfig,
+        skipgram_config: SkipGramConfig,
+    ) -> Self {
+        let vocab = Arc::new(vocab);
+        let rng = ReseedOnCloneRng::new(rng);
+        let range_gen = NegativeSamplingRangeGenerator::new(
+            rng.clone(),
+            vocab.types().iter().map(|word| word.count()),
+            &common_config,
+        );
+        PoswiseTrainer {
+            vocab,
+            rng,
+            range_gen,
+            common_config,
+            skipgram_config,
+        }
+    }
+}
+
+impl<'a, S, R, V, I> TrainIterFrom<'a, [S]> for PoswiseTrainer<R, V>
+where
+    S: Hash + Eq,
+    R: Rng + Clone,
+    V: Vocab<IdxType = I>,
+    V::VocabType: Borrow<S>,
+    I: WordIdx,
+    for<'b> &'b I: IntoIterator<Item = u64>,
+{
+    type Iter = PoswiseIter<R, I>;
+    type Focus = Vec<(u64, I)>;
+    type Contexts = Vec<usize>;
+
+    fn train_iter_from(&mut self, sequence: &[S]) -> Self::Iter {
+        let mut ids = Vec::new();
+        for t in sequence {
+            if let Some(mut idx) = self.vocab.idx(t) {
+                if self.rng.gen_range(0f32..1f32) < self.vocab.discard(idx.word_idx() as usize) {
+                    idx.drop_subwords(&mut self.rng, self.common_config.subword_dropout);
+                    ids.push(idx);
+                }
+            }
+        }
+        PoswiseIter::new(
+            self.rng.clone(),
+            ids,
+            self.vocab.n_input_types() as u64,
+            self.skipgram_config,
+        )
+    }
+}
+
+impl<R, V> NegativeSamples for PoswiseTrainer<R, V>
+where
+    R: Rng,
+{
+    fn negative_sample(&mut self, output: usize) -> usize {
+        loop {
+            let negative = self.range_gen.next().unwrap();
+            if negative != output {
+                return negative;
+            }
+        }
+    }
+
+    fn noise_prob(&self, output: usize) -> f32 {
+        self.range_gen.prob(output) as f32
+    }
+}
+
+impl<R, V> Reseed for PoswiseTrainer<R, V>
+where
+    R: Reseed + RngCore,
+{
+    fn reseed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+        self.range_gen.reseed(self.rng.next_u64());
+    }
+}
+
+impl<R, V> OutputLabels for PoswiseTrainer<R, V>
+where
+    V: Vocab,
+    V::VocabType: ToString,
+{
+    fn output_label(&self, output: usize) -> String {
+        self.vocab.types()[output].label().to_string()
+    }
+}
+
+impl<R, V> Trainer for PoswiseTrainer<R, V>
+where
+    R: Rng + Clone,
+    V: Vocab,
+    V::Config: Serialize,
+{
+    type InputVocab = V;
+    type Metadata = SkipgramMetadata<V::Config>;
+
+    fn input_vocab(&self) -> &V {
+        &self.vocab
+    }
+
+    fn try_into_input_vocab(self) -> Result<V> {
+        match Arc::try_unwrap(self.vocab) {
+            Ok(vocab) => Ok(vocab),
+            Err(_) => bail!("Cannot unwrap input vocab."),
+        }
+    }
+
+    fn n_input_types(&self) -> usize {
+        self.input_vocab().n_input_types() + 2 * self.skipgram_config.context_size as usize
+    }
+
+    fn n_output_types(&self) -> usize {
+        self.vocab.len()
+    }
+
+    fn config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+
+    fn to_metadata(&self) -> SkipgramMetadata<V::Config> {
+        SkipgramMetadata::new(
+            self.common_config,
+            self.skipgram_config,
+            self.vocab.config(),
+        )
+    }
+}
+
+/// Iterator over the positional context and focus identifier of each token in a sentence.
+///
+/// The input of a training instance is the list of context words (including their subword
+/// units), where each word is paired with the input index of the weights of its position
+/// relative to the focus word. The output is the focus word.
+pub struct PoswiseIter<R, I> {
+    ids: Vec<I>,
+    rng: R,
+    i: usize,
+    ctx_size: usize,
+    positions_offset: u64,
+}
+
+impl<R, I> PoswiseIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+{
+    /// Constructs a new `PoswiseIter`.
+    ///
+    /// `positions_offset` is the input index of the weights of the first
+    /// position. The `rng` is used to determine the window size for each
+    /// focus token.
+    pub fn new(rng: R, ids: Vec<I>, positions_offset: u64, skip_config: SkipGramConfig) -> Self {
+        PoswiseIter {
+            ids,
+            rng,
+            i: 0,
+            ctx_size: skip_config.context_size as usize,
+            positions_offset,
+        }
+    }
+
+    /// Get the input index of the weights of the context token at `idx`.
+    ///
+    /// Positions are ordered from the leftmost to the rightmost position
+    /// in the maximum window, skipping the position of the focus token.
+    fn position_idx(&self, idx: usize) -> u64 {
+        let position = if idx < self.i {
+            self.ctx_size - (self.i - idx)
+        } else {
+            self.ctx_size + (idx - self.i) - 1
+        };
+
+        self.positions_offset + position as u64
+    }
+}
+
+impl<R, I> Iterator for PoswiseIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+{
+    type Item = (Vec<(u64, I)>, Vec<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.i < self.ids.len() {
+            let window =
+                sample_context_window(&mut self.rng, self.i, self.ids.len(), self.ctx_size);
+
+            let context = window
+                .filter(|&idx| idx != self.i)
+                .map(|idx| (self.position_idx(idx), self.ids[idx].clone()))
+                .collect::<Vec<_>>();
+
+            let focus = self.ids[self.i].word_idx() as usize;
+            self.i += 1;
+
+            // Sentences with a single token do not have a context.
+            if !context.is_empty() {
+                return Some((context, vec![focus]));
+            }
+        }
+
+        None
+    }
+}
+
+impl<R, I> FusedIterator for PoswiseIter<R, I>
+where
+    R: Rng + Clone,
+    I: WordIdx,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::PoswiseIter;
+    use crate::idx::{SingleIdx, WordIdx};
+    use crate::{ModelType, SkipGramConfig};
+
+    #[test]
+    fn poswise_iter_test() {
+        let ids = vec![
+            SingleIdx::from_word_idx(0),
+            SingleIdx::from_word_idx(1),
+            SingleIdx::from_word_idx(2),
+        ];
+        let iter = PoswiseIter::new(
+            XorShiftRng::seed_from_u64(42),
+            ids,
+            10,
+            SkipGramConfig {
+                context_size: 1,
+                model: ModelType::PositionWeighted,
+                harmonic_weighting: false,
+                context_cutoff: None,
+                word_ngrams: 1,
+            },
+        );
+
+        let instances = iter
+            .map(|(context, focus)| {
+                (
+                    context
+                        .into_iter()
+                        .map(|(position, word)| (position, word.word_idx()))
+                        .collect::<Vec<_>>(),
+                    focus,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            instances,
+            vec![
+                (vec![(11, 1)], vec![0]),
+                (vec![(10, 0), (11, 2)], vec![1]),
+                (vec![(10, 1)], vec![2])
+            ]
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use anyhow::{Context, Result};
+use ndarray::{Array1, ArrayViewMut2, Axis};
+
+use crate::config::RetrofitConfig;
+use crate::vec_simd::{scale, scaled_add};
+
+/// Semantic lexicon.
+///
+/// A lexicon stores the neighbors of words in a semantic graph, such as
+/// synonyms or paraphrases.
+#[derive(Clone, Debug, Default)]
+pub struct Lexicon {
+    neighbors: HashMap<String, Vec<String>>,
+}
+
+impl Lexicon {
+    /// Read a lexicon.
+    ///
+    /// Every line of the lexicon consists of a word, followed by its
+    /// neighbors. The word and neighbors are separated by tabs or spaces.
+    pub fn read<R>(read: R) -> Result<Self>
+    where
+        R: BufRead,
+    {
+        let mut neighbors: HashMap<String, Vec<String>> = HashMap::new();
+        for line in read.lines() {
+            let line = line.context("Cannot read lexicon line")?;
+            let mut words = line.split_whitespace();
+            let word = match words.next() {
+                Some(word) => word,
+                None => continue,
+            };
+
+            neighbors
+                .entry(word.to_owned())
+                .or_default()
+                .extend(words.map(ToOwned::to_owned));
+        }
+
+        Ok(Lexicon { neighbors })
+    }
+
+    /// Get the neighbors of `word`.
+    pub fn neighbors(&self, word: &str) -> Option<&[String]> {
+        self.neighbors.get(word).map(Vec::as_slice)
+    }
+
+    /// Get an iterator over the words and their neighbors.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.neighbors
+            .iter()
+            .map(|(word, neighbors)| (word.as_str(), neighbors.as_slice()))
+    }
+
+    /// Get the neighbor indices of the given words.
+    ///
+    /// Returns for each word the indices of its neighbors in `words`.
+    /// Neighbors that are not in `words` are ignored.
+    pub fn neighbor_indices(&self, words: &[String]) -> Vec<Vec<usize>> {
+        let indices: HashMap<&str, usize> = words
+            .iter()
+            .enumerate()
+            .map(|(idx, word)| (word.as_str(), idx))
+            .collect();
+
+        words
+            .iter()
+            .map(|word| {
+                self.neighbors(word)
+                    .unwrap_or(&[])
+                    .iter()
+                    .filter_map(|neighbor| indices.get(neighbor.as_str()).copied())
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Retrofit embeddings to a semantic lexicon (Faruqui et al., 2015).
+///
+/// Every row of `embeds` that has neighbors in `neighbors` is moved
+/// towards the embeddings of its neighbors, while staying close to its
+/// original embedding. The embedding *q_i* is iteratively updated as:
+///
+/// *q_i = (alpha q'_i + 1/|N_i| sum_{j in N_i} q_j) / (alpha + 1)*
+///
+/// where *q'_i* is the original embedding and *N_i* the neighbors of *i*.
+pub fn retrofit(mut embeds: ArrayViewMut2<f32>, neighbors: &[Vec<usize>], config: RetrofitConfig) {
+    assert_eq!(
+        embeds.nrows(),
+        neighbors.len(),
+        "Number of embeddings and neighbor lists differ"
+    );
+
+    let original = embeds.to_owned();
+
+    for _ in 0..config.iterations {
+        for (idx, word_neighbors) in neighbors.iter().enumerate() {
+            if word_neighbors.is_empty() {
+                continue;
+            }
+
+            let mut embed = Array1::zeros(embeds.ncols());
+            for &neighbor in word_neighbors {
+                scaled_add(
+                    embed.view_mut(),
+                    embeds.index_axis(Axis(0), neighbor),
+                    1.0 / word_neighbors.len() as f32,
+                );
+            }
+            scaled_add(
+                embed.view_mut(),
+                original.index_axis(Axis(0), idx),
+                config.alpha,
+            );
+            scale(embed.view_mut(), 1.0 / (config.alpha + 1.0));
+
+            embeds.index_axis_mut(Axis(0), idx).assign(&embed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::{retrofit, Lexicon};
+    use crate::util::all_close;
+    use crate::RetrofitConfig;
+
+    #[test]
+    fn read_lexicon() {
+        let lexicon = Lexicon::read("happy\tglad cheerful\nsad unhappy\n\n".as_bytes()).unwrap();
+        assert_eq!(
+            lexicon.neighbors("happy"),
+            Some(&["glad".to_owned(), "cheerful".to_owned()][..])
+        );
+        assert_eq!(lexicon.neighbors("sad"), Some(&["unhappy".to_owned()][..]));
+        assert_eq!(lexicon.neighbors("glad"), None);
+    }
+
+    #[test]
+    fn neighbor_indices() {
+        let lexicon = Lexicon::read("happy glad cheerful\n".as_bytes()).unwrap();
+        let words = vec!["glad".to_owned(), "happy".to_owned()];
+        assert_eq!(lexicon.neighbor_indices(&words), vec![vec![], vec![0]]);
+    }
+
+    #[test]
+    fn retrofit_single_iteration() {
+        let mut embeds = array![[1., 0.], [0., 1.], [1., 1.]];
+        retrofit(
+            embeds.view_mut(),
+            &[vec![1], vec![], vec![0, 1]],
+            RetrofitConfig {
+                alpha: 1.0,
+                iterations: 1,
+            },
+        );
+
+        // Row 0 moves to the midpoint of itself and row 1. Row 2 is then
+        // averaged with the mean of the updated row 0 and row 1.
+        assert!(all_close(
+            embeds.as_slice().unwrap(),
+            &[0.5, 0.5, 0., 1., 0.625, 0.875],
+            1e-5
+        ));
+    }
+}
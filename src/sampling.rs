@@ -1,10 +1,20 @@
+use std::ops::Range;
+use std::sync::Arc;
+
 use rand::distributions::{Distribution, Uniform};
-use rand::Rng;
+use rand::{Rng, RngCore};
+use superslice::Ext;
 use zipf::ZipfDistribution;
 
+use crate::config::{CommonConfig, NegativeSamplingDistribution};
+use crate::util::Reseed;
+
 pub trait RangeGenerator: Iterator<Item = usize> {
     /// Get the upper bound in *[0, upper_bound)*.
     fn upper_bound(&self) -> usize;
+
+    /// Get the probability of drawing `idx`.
+    fn prob(&self, idx: usize) -> f64;
 }
 
 /// Exponent to use for the Zipf's distribution.
@@ -73,6 +83,15 @@ where
     fn upper_bound(&self) -> usize {
         self.prefix_sum.len()
     }
+
+    fn prob(&self, idx: usize) -> f64 {
+        let weight = match idx {
+            0 => self.prefix_sum[0],
+            idx => self.prefix_sum[idx] - self.prefix_sum[idx - 1],
+        };
+
+        weight as f64 / (self.upper - 1) as f64
+    }
 }
 
 /// An iterator that draws from *[0, n)* with a Zipfian distribution.
@@ -88,6 +107,7 @@ where
 pub struct ZipfRangeGenerator<R> {
     upper_bound: usize,
     exponent: f64,
+    harmonic: f64,
     rng: R,
     dist: ZipfDistribution,
 }
@@ -100,6 +120,7 @@ where
         ZipfRangeGenerator {
             upper_bound: self.upper_bound,
             exponent: self.exponent,
+            harmonic: self.harmonic,
             rng: self.rng.clone(),
             dist: ZipfDistribution::new(self.upper_bound, self.exponent).unwrap(),
         }
@@ -116,9 +137,14 @@ where
     }
 
     pub fn new_with_exponent(rng: R, upper_bound: usize, exponent: f64) -> Self {
+        // Generalized harmonic number H_{N, s}, used to normalize
+        // probabilities.
+        let harmonic = (1..=upper_bound).map(|k| (k as f64).powf(-exponent)).sum();
+
         ZipfRangeGenerator {
             upper_bound,
             exponent,
+            harmonic,
             rng,
             dist: ZipfDistribution::new(upper_bound, exponent).unwrap(),
         }
@@ -144,6 +170,180 @@ where
     fn upper_bound(&self) -> usize {
         self.upper_bound
     }
+
+    fn prob(&self, idx: usize) -> f64 {
+        ((idx + 1) as f64).powf(-self.exponent) / self.harmonic
+    }
+}
+
+impl<R> Reseed for ZipfRangeGenerator<R>
+where
+    R: Reseed,
+{
+    fn reseed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+    }
+}
+
+/// An iterator that draws from *[0, n)* with a smoothed unigram distribution.
+///
+/// This iterator returns integers from *[0, n)*, where the probability of
+/// each integer is proportional to its count raised to a power. Word2vec
+/// uses this distribution with power 0.75 to draw negative samples, which
+/// increases the probability of infrequent words.
+///
+/// The cumulative distribution is shared between clones.
+#[derive(Clone)]
+pub struct UnigramRangeGenerator<R> {
+    cumulative: Arc<Vec<f64>>,
+    rng: R,
+}
+
+impl<R> UnigramRangeGenerator<R>
+where
+    R: Rng,
+{
+    pub fn new(rng: R, counts: impl IntoIterator<Item = usize>, power: f64) -> Self {
+        let mut cumulative = Vec::new();
+        let mut sum = 0.;
+        for count in counts {
+            sum += (count as f64).powf(power);
+            cumulative.push(sum);
+        }
+
+        assert!(sum > 0., "Cannot sample from zero elements.");
+
+        UnigramRangeGenerator {
+            cumulative: Arc::new(cumulative),
+            rng,
+        }
+    }
+
+    fn total(&self) -> f64 {
+        *self.cumulative.last().unwrap()
+    }
+}
+
+impl<R> Iterator for UnigramRangeGenerator<R>
+where
+    R: Rng,
+{
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let val = self.rng.gen_range(0f64..self.total());
+        let idx = self
+            .cumulative
+            .upper_bound_by(|sum| sum.partial_cmp(&val).unwrap());
+
+        // Guard against rounding errors at the upper bound.
+        Some(idx.min(self.cumulative.len() - 1))
+    }
+}
+
+impl<R> RangeGenerator for UnigramRangeGenerator<R>
+where
+    R: Rng,
+{
+    fn upper_bound(&self) -> usize {
+        self.cumulative.len()
+    }
+
+    fn prob(&self, idx: usize) -> f64 {
+        let weight = match idx {
+            0 => self.cumulative[0],
+            idx => self.cumulative[idx] - self.cumulative[idx - 1],
+        };
+
+        weight / self.total()
+    }
+}
+
+impl<R> Reseed for UnigramRangeGenerator<R>
+where
+    R: Reseed,
+{
+    fn reseed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+    }
+}
+
+/// Range generator for negative samples.
+///
+/// This generator draws from the negative sampling distribution of the
+/// model configuration.
+#[derive(Clone)]
+pub enum NegativeSamplingRangeGenerator<R> {
+    Zipf(ZipfRangeGenerator<R>),
+    Unigram(UnigramRangeGenerator<R>),
+}
+
+impl<R> NegativeSamplingRangeGenerator<R>
+where
+    R: Rng,
+{
+    /// Construct a generator for outputs with the given counts.
+    ///
+    /// The outputs must be sorted by descending frequency.
+    pub fn new(
+        rng: R,
+        counts: impl ExactSizeIterator<Item = usize>,
+        config: &CommonConfig,
+    ) -> Self {
+        match config.ns_distribution {
+            NegativeSamplingDistribution::Zipf => NegativeSamplingRangeGenerator::Zipf(
+                ZipfRangeGenerator::new_with_exponent(rng, counts.len(), config.zipf_exponent),
+            ),
+            NegativeSamplingDistribution::Unigram => NegativeSamplingRangeGenerator::Unigram(
+                UnigramRangeGenerator::new(rng, counts, config.unigram_power),
+            ),
+        }
+    }
+}
+
+impl<R> Iterator for NegativeSamplingRangeGenerator<R>
+where
+    R: Rng,
+{
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            NegativeSamplingRangeGenerator::Zipf(range_gen) => range_gen.next(),
+            NegativeSamplingRangeGenerator::Unigram(range_gen) => range_gen.next(),
+        }
+    }
+}
+
+impl<R> RangeGenerator for NegativeSamplingRangeGenerator<R>
+where
+    R: Rng,
+{
+    fn upper_bound(&self) -> usize {
+        match self {
+            NegativeSamplingRangeGenerator::Zipf(range_gen) => range_gen.upper_bound(),
+            NegativeSamplingRangeGenerator::Unigram(range_gen) => range_gen.upper_bound(),
+        }
+    }
+
+    fn prob(&self, idx: usize) -> f64 {
+        match self {
+            NegativeSamplingRangeGenerator::Zipf(range_gen) => range_gen.prob(idx),
+            NegativeSamplingRangeGenerator::Unigram(range_gen) => range_gen.prob(idx),
+        }
+    }
+}
+
+impl<R> Reseed for NegativeSamplingRangeGenerator<R>
+where
+    R: Reseed,
+{
+    fn reseed(&mut self, seed: u64) {
+        match self {
+            NegativeSamplingRangeGenerator::Zipf(range_gen) => range_gen.reseed(seed),
+            NegativeSamplingRangeGenerator::Unigram(range_gen) => range_gen.reseed(seed),
+        }
+    }
 }
 
 /// A banded range generator.
@@ -202,6 +402,47 @@ where
     fn upper_bound(&self) -> usize {
         self.inner.upper_bound() * self.band_size
     }
+
+    fn prob(&self, idx: usize) -> f64 {
+        self.inner.prob(idx / self.band_size) / self.band_size as f64
+    }
+}
+
+impl<R, G> Reseed for BandedRangeGenerator<R, G>
+where
+    R: Reseed + RngCore,
+    G: Reseed,
+{
+    fn reseed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+        self.inner.reseed(self.rng.next_u64());
+    }
+}
+
+/// Get the context window of the token at position `focus`.
+///
+/// Returns the positions of the tokens that are at most `size` positions
+/// away from the focus token in a sentence of `len` tokens. The focus
+/// position itself is part of the window.
+pub(crate) fn context_window(focus: usize, len: usize, size: usize) -> Range<usize> {
+    focus - focus.min(size)..(focus + size + 1).min(len)
+}
+
+/// Sample the context window of the token at position `focus`.
+///
+/// The window size is sampled uniformly from *[1, max_size]*, as in
+/// word2vec and fastText (Bojanowski et al., 2017). This implicitly
+/// weights contexts by their distance to the focus token.
+pub(crate) fn sample_context_window<R>(
+    rng: &mut R,
+    focus: usize,
+    len: usize,
+    max_size: usize,
+) -> Range<usize>
+where
+    R: Rng,
+{
+    context_window(focus, len, rng.gen_range(1..max_size + 1))
 }
 
 #[cfg(test)]
@@ -209,7 +450,10 @@ mod tests {
     use rand::SeedableRng;
     use rand_xorshift::XorShiftRng;
 
-    use super::{BandedRangeGenerator, RangeGenerator, WeightedRangeGenerator, ZipfRangeGenerator};
+    use super::{
+        context_window, sample_context_window, BandedRangeGenerator, RangeGenerator,
+        UnigramRangeGenerator, WeightedRangeGenerator, ZipfRangeGenerator,
+    };
     use crate::util::{all_close, close};
 
     const SEED: [u8; 16] = [
@@ -252,6 +496,30 @@ mod tests {
         assert!(all_close(&[0.4, 0.1, 0.3, 0.2], &probs, 1e-2));
     }
 
+    #[test]
+    fn weighted_range_generator_prob_test() {
+        let rng = XorShiftRng::from_seed(SEED);
+        let weighted_gen = WeightedRangeGenerator::new(rng, &[4, 1, 3, 2]);
+
+        let probs: Vec<_> = (0..weighted_gen.upper_bound())
+            .map(|idx| weighted_gen.prob(idx) as f32)
+            .collect();
+        assert!(all_close(&[0.4, 0.1, 0.3, 0.2], &probs, 1e-5));
+    }
+
+    #[test]
+    fn zipf_range_generator_prob_test() {
+        let rng = XorShiftRng::from_seed(SEED);
+        let zipf_gen = ZipfRangeGenerator::new_with_exponent(rng, 4, 1.0);
+
+        // H_{4,1} = 1 + 1/2 + 1/3 + 1/4 = 25/12
+        let probs: Vec<_> = (0..zipf_gen.upper_bound())
+            .map(|idx| zipf_gen.prob(idx) as f32)
+            .collect();
+        assert!(all_close(&[0.48, 0.24, 0.16, 0.12], &probs, 1e-5));
+        assert!(close(1.0f32, probs.iter().cloned().sum(), 1e-5));
+    }
+
     #[test]
     fn zipf_range_generator_test() {
         const DRAWS: usize = 20_000;
@@ -280,6 +548,39 @@ mod tests {
         assert!(close(1.0f32, probs.iter().cloned().sum(), 1e-2));
     }
 
+    #[test]
+    fn unigram_range_generator_prob_test() {
+        let rng = XorShiftRng::from_seed(SEED);
+        let unigram_gen = UnigramRangeGenerator::new(rng, vec![16, 4, 1, 1], 0.5);
+
+        let probs: Vec<_> = (0..unigram_gen.upper_bound())
+            .map(|idx| unigram_gen.prob(idx) as f32)
+            .collect();
+        assert!(all_close(&[0.5, 0.25, 0.125, 0.125], &probs, 1e-5));
+    }
+
+    #[test]
+    fn unigram_range_generator_test() {
+        const DRAWS: usize = 20_000;
+
+        let rng = XorShiftRng::from_seed(SEED);
+        let unigram_gen = UnigramRangeGenerator::new(rng, vec![16, 4, 1, 1], 0.5);
+
+        // Sample using the smoothed counts.
+        let mut hits = vec![0; unigram_gen.upper_bound()];
+        for idx in unigram_gen.take(DRAWS) {
+            hits[idx] += 1;
+        }
+
+        // Convert counts to a probability distribution.
+        let probs: Vec<_> = hits
+            .into_iter()
+            .map(|count| count as f32 / DRAWS as f32)
+            .collect();
+
+        assert!(all_close(&[0.5, 0.25, 0.125, 0.125], &probs, 1e-2));
+    }
+
     #[test]
     fn banded_range_generator_test() {
         const DRAWS: usize = 20_000;
@@ -315,4 +616,72 @@ mod tests {
         ));
         assert!(close(1.0f32, probs.iter().cloned().sum(), 1e-2));
     }
+
+    #[test]
+    fn banded_range_generator_prob_test() {
+        let rng = XorShiftRng::from_seed(SEED);
+        let inner_gen = ZipfRangeGenerator::new_with_exponent(rng, 4, 1.0);
+
+        let rng = XorShiftRng::from_seed(SEED2);
+        let banded_gen = BandedRangeGenerator::new(rng, inner_gen, 2);
+
+        let probs: Vec<_> = (0..banded_gen.upper_bound())
+            .map(|idx| banded_gen.prob(idx) as f32)
+            .collect();
+        assert!(all_close(
+            &[0.24, 0.24, 0.12, 0.12, 0.08, 0.08, 0.06, 0.06],
+            &probs,
+            1e-5
+        ));
+    }
+
+    #[test]
+    fn context_window_is_clamped_to_sentence() {
+        assert_eq!(context_window(0, 5, 2), 0..3);
+        assert_eq!(context_window(2, 5, 2), 0..5);
+        assert_eq!(context_window(4, 5, 2), 2..5);
+        assert_eq!(context_window(0, 1, 3), 0..1);
+    }
+
+    #[test]
+    fn context_window_size_is_uniform() {
+        const MAX_SIZE: usize = 5;
+        const DRAWS: usize = 50_000;
+
+        let mut rng = XorShiftRng::from_seed(SEED);
+        let mut counts = [0usize; MAX_SIZE + 1];
+        for _ in 0..DRAWS {
+            // Window around the middle of a sentence that is not clamped.
+            let window = sample_context_window(&mut rng, 10, 21, MAX_SIZE);
+            assert_eq!(10 - window.start, window.end - 11);
+            counts[10 - window.start] += 1;
+        }
+
+        assert_eq!(counts[0], 0);
+        for &count in &counts[1..] {
+            assert!(close(
+                count as f32 / DRAWS as f32,
+                1. / MAX_SIZE as f32,
+                0.01
+            ));
+        }
+    }
+
+    #[test]
+    fn sampled_context_window_stays_in_sentence() {
+        const MAX_SIZE: usize = 3;
+
+        let mut rng = XorShiftRng::from_seed(SEED);
+        for len in 1..8 {
+            for focus in 0..len {
+                for _ in 0..100 {
+                    let window = sample_context_window(&mut rng, focus, len, MAX_SIZE);
+                    assert!(window.contains(&focus));
+                    assert!(window.end <= len);
+                    assert!(focus - window.start <= MAX_SIZE);
+                    assert!(window.end - focus <= MAX_SIZE + 1);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,303 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::iter::FusedIterator;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use rand::{Rng, RngCore, SeedableRng};
+use serde::Serialize;
+
+use crate::idx::{BagOfWordsIdx, WordIdx};
+use crate::sampling::{NegativeSamplingRangeGenerator, RangeGenerator};
+use crate::train_model::{NegativeSamples, OutputLabels, Subsampling, TrainIterFrom, Trainer};
+use crate::util::{Reseed, ReseedOnCloneRng};
+use crate::vocab::word_ngram_indices;
+use crate::{CommonConfig, Sent2vecConfig, Vocab};
+
+/// Sent2vec Trainer
+///
+/// The `Sent2vecTrainer` holds the information and logic necessary to
+/// transform a tokenized sentence into an iterator of context bags and
+/// target word tuples (Pagliardini et al., 2018). In contrast to CBOW, the
+/// context of a target word is the complete sentence, extended with the
+/// word n-grams of the sentence. The struct is cheap to clone because the
+/// vocabulary is shared between clones.
+#[derive(Clone)]
+pub struct Sent2vecTrainer<R, V> {
+    vocab: Arc<V>,
+    rng: R,
+    range_gen: NegativeSamplingRangeGenerator<R>,
+    common_config: CommonConfig,
+    sent2vec_config: Sent2vecConfig,
+    subsampling: bool,
+}
+
+impl<R, V> Sent2vecTrainer<ReseedOnCloneRng<R>, V>
+where
+    R: Rng + Clone + SeedableRng,
+    V: Vocab,
+{
+    /// Constructs a new `Sent2vecTrainer`.
+    pub fn new(
+        vocab: V,
+        rng: R,
+        common_config: CommonConfig,
+        sent2vec_config: Sent2vecConfig,
+    ) -> Self {
+        let vocab = Arc::new(vocab);
+        let rng = ReseedOnCloneRng::new(rng);
+        let range_gen = NegativeSamplingRangeGenerator::new(
+            rng.clone(),
+            vocab.types().iter().map(|word| word.count()),
+            &common_config,
+        );
+        Sent2vecTrainer {
+            vocab,
+            rng,
+            range_gen,
+            common_config,
+            sent2vec_config,
+            subsampling: true,
+        }
+    }
+}
+
+impl<'a, S, R, V, I> TrainIterFrom<'a, [S]> for Sent2vecTrainer<R, V>
+where
+    S: Hash + Eq + AsRef<str>,
+    R: Rng + Clone,
+    V: Vocab<IdxType = I>,
+    V::VocabType: Borrow<S>,
+    I: WordIdx,
+    for<'b> &'b I: IntoIterator<Item = u64>,
+{
+    type Iter = Sent2vecIter<I>;
+    type Focus = BagOfWordsIdx;
+    type Contexts = Vec<usize>;
+
+    fn train_iter_from(&mut self, sequence: &[S]) -> Self::Iter {
+        let ngrams = word_ngram_indices(
+            &*self.vocab,
+            sequence,
+            self.sent2vec_config.word_ngrams as usize,
+        );
+
+        // All words are used as context, but only words that are not
+        // discarded are used as targets.
+        let mut ids = Vec::new();
+        let mut targets = Vec::new();
+        for t in sequence {
+            if let Some(mut idx) = self.vocab.idx(t) {
+                targets.push(
+                    !self.subsampling
+                        || self.rng.gen_range(0f32..1f32)
+                            < self.vocab.discard(idx.word_idx() as usize),
+                );
+                if self.subsampling {
+                    idx.drop_subwords(&mut self.rng, self.common_config.subword_dropout);
+                }
+                ids.push(idx);
+            }
+        }
+
+        Sent2vecIter::new(ids, targets, ngrams)
+    }
+}
+
+impl<R, V> Subsampling for Sent2vecTrainer<R, V> {
+    fn set_subsampling(&mut self, subsampling: bool) {
+        self.subsampling = subsampling;
+    }
+}
+
+impl<R, V> NegativeSamples for Sent2vecTrainer<R, V>
+where
+    R: Rng,
+{
+    fn negative_sample(&mut self, output: usize) -> usize {
+        loop {
+            let negative = self.range_gen.next().unwrap();
+            if negative != output {
+                return negative;
+            }
+        }
+    }
+
+    fn noise_prob(&self, output: usize) -> f32 {
+        self.range_gen.prob(output) as f32
+    }
+}
+
+impl<R, V> Reseed for Sent2vecTrainer<R, V>
+where
+    R: Reseed + RngCore,
+{
+    fn reseed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+        self.range_gen.reseed(self.rng.next_u64());
+    }
+}
+
+impl<R, V> OutputLabels for Sent2vecTrainer<R, V>
+where
+    V: Vocab,
+    V::VocabType: ToString,
+{
+    fn output_label(&self, output: usize) -> String {
+        self.vocab.types()[output].label().to_string()
+    }
+}
+
+impl<R, V> Trainer for Sent2vecTrainer<R, V>
+where
+    R: Rng + Clone,
+    V: Vocab,
+    V::Config: Serialize,
+{
+    type InputVocab = V;
+    type Metadata = Sent2vecMetadata<V::Config>;
+
+    fn input_vocab(&self) -> &V {
+        &self.vocab
+    }
+
+    fn try_into_input_vocab(self) -> Result<V> {
+        match Arc::try_unwrap(self.vocab) {
+            Ok(vocab) => Ok(vocab),
+            Err(_) => bail!("Cannot unwrap input vocab."),
+        }
+    }
+
+    fn n_input_types(&self) -> usize {
+        self.input_vocab().n_input_types()
+    }
+
+    fn n_output_types(&self) -> usize {
+        self.vocab.len()
+    }
+
+    fn config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+
+    fn to_metadata(&self) -> Sent2vecMetadata<V::Config> {
+        Sent2vecMetadata {
+            common_config: self.common_config,
+            sent2vec_config: self.sent2vec_config,
+            vocab_config: self.vocab.config(),
+        }
+    }
+}
+
+/// Metadata for sent2vec embeddings.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Sent2vecMetadata<V> {
+    common_config: CommonConfig,
+    #[serde(rename = "model_config")]
+    sent2vec_config: Sent2vecConfig,
+    vocab_config: V,
+}
+
+/// Iterator over the context bag and target identifier of each token in a sentence.
+///
+/// The input of a training instance is the bag of the word n-grams of the
+/// sentence and the indices of all words (including their subword units),
+/// except for the target word. The output is the target word.
+pub struct Sent2vecIter<I> {
+    ids: Vec<I>,
+    targets: Vec<bool>,
+    ngrams: Vec<u64>,
+    i: usize,
+}
+
+impl<I> Sent2vecIter<I>
+where
+    I: WordIdx,
+{
+    /// Constructs a new `Sent2vecIter`.
+    ///
+    /// `targets` indicates for each word in `ids` whether it should be
+    /// used as a target.
+    pub fn new(ids: Vec<I>, targets: Vec<bool>, ngrams: Vec<u64>) -> Self {
+        assert_eq!(
+            ids.len(),
+            targets.len(),
+            "Number of words and target indicators differ"
+        );
+
+        Sent2vecIter {
+            ids,
+            targets,
+            ngrams,
+            i: 0,
+        }
+    }
+}
+
+impl<I> Iterator for Sent2vecIter<I>
+where
+    I: WordIdx,
+    for<'a> &'a I: IntoIterator<Item = u64>,
+{
+    type Item = (BagOfWordsIdx, Vec<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.i < self.ids.len() {
+            let target = self.i;
+            self.i += 1;
+
+            if !self.targets[target] {
+                continue;
+            }
+
+            let mut bag = BagOfWordsIdx::new(self.ngrams.as_slice());
+            for idx in (0..self.ids.len()).filter(|&idx| idx != target) {
+                bag.extend(&self.ids[idx]);
+            }
+
+            // Sentences with a single token do not have a context.
+            if !bag.is_empty() {
+                return Some((bag, vec![self.ids[target].word_idx() as usize]));
+            }
+        }
+
+        None
+    }
+}
+
+impl<I> FusedIterator for Sent2vecIter<I>
+where
+    I: WordIdx,
+    for<'a> &'a I: IntoIterator<Item = u64>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sent2vecIter;
+    use crate::idx::{SingleIdx, WordIdx};
+
+    #[test]
+    fn sent2vec_iter_test() {
+        let ids = vec![
+            SingleIdx::from_word_idx(0),
+            SingleIdx::from_word_idx(1),
+            SingleIdx::from_word_idx(2),
+        ];
+        let iter = Sent2vecIter::new(ids, vec![true, false, true], vec![10, 11]);
+
+        let instances = iter
+            .map(|(bag, targets)| (bag.into_iter().collect::<Vec<_>>(), targets))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            instances,
+            vec![(vec![10, 11, 1, 2], vec![0]), (vec![10, 11, 0, 1], vec![2])]
+        );
+    }
+
+    #[test]
+    fn sent2vec_iter_single_token_test() {
+        let iter = Sent2vecIter::new(vec![SingleIdx::from_word_idx(0)], vec![true], vec![]);
+        assert_eq!(iter.count(), 0);
+    }
+}
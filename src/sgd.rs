@@ -1,43 +1,236 @@
-use ndarray::{Array1, ArrayView1, ArrayViewMut1};
+use std::collections::HashMap;
+use std::iter;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::vec;
 
+use anyhow::{ensure, Result};
+use ndarray::{s, Array1, Array2, ArrayView1, ArrayView2, ArrayViewMut1, Axis};
+
+use crate::checkpoint::Checkpoint;
+use crate::config::{CommonConfig, LossType};
+use crate::constraints::LexicalConstraint;
+#[cfg(feature = "gpu")]
+use crate::gpu::Gpu;
 use crate::hogwild::Hogwild;
-use crate::idx::WordIdx;
-use crate::loss::log_logistic_loss;
-use crate::train_model::{NegativeSamples, TrainIterFrom, TrainModel, Trainer};
-use crate::vec_simd::scaled_add;
+use crate::idx::{BagOfWordsIdx, WordIdx};
+use crate::loss::{
+    info_nce_loss, log_logistic_loss, log_logistic_loss_from_score, margin_ranking_loss,
+    margin_ranking_loss_from_score, nce_loss,
+};
+use crate::optimizer::{Optimizer, OptimizerWrap};
+use crate::train_model::{
+    ConcatInputs, NegativeSamples, SelectSense, Subsampling, TrainIterFrom, TrainModel, Trainer,
+};
+use crate::util::Reseed;
+use crate::vec_simd::{dot, scale, scaled_add};
+use crate::vocab::Vocab;
+
+/// Maximum number of rejected draws of a negative sample.
+///
+/// When negative samples that are outputs of the focus are rejected, a
+/// negative is redrawn at most this number of times. This avoids that
+/// sampling does not terminate when (nearly) all outputs are in the
+/// context window of a focus.
+const MAX_NEGATIVE_REJECTIONS: usize = 10;
+
+/// Training instances of a sentence.
+///
+/// When in-batch negatives are used, the training instances of a sentence
+/// are collected, since their outputs are the negatives of the batch.
+/// Otherwise, the training instances are streamed from the iterator of
+/// the trainer.
+enum TrainBatch<I, F> {
+    Collected(vec::IntoIter<(F, Vec<usize>)>),
+    Streamed(I),
+}
+
+impl<I, F, C> Iterator for TrainBatch<I, F>
+where
+    I: Iterator<Item = (F, C)>,
+    C: IntoIterator<Item = usize>,
+{
+    type Item = (F, Vec<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            TrainBatch::Collected(instances) => instances.next(),
+            // The contexts of the trainers are vectors, which are
+            // collected in place.
+            TrainBatch::Streamed(iter) => iter
+                .next()
+                .map(|(focus, contexts)| (focus, contexts.into_iter().collect())),
+        }
+    }
+}
 
 /// Stochastic gradient descent
 ///
-/// This data type applies stochastic gradient descent on sentences.
+/// This data type applies stochastic gradient descent on sentences. The
+/// embeddings are updated using the update rule of the optimizer `O`.
 #[derive(Clone)]
-pub struct Sgd<T> {
+pub struct Sgd<T, O = OptimizerWrap> {
+    batch_size: usize,
+    epoch_size: usize,
+    eval_scores: Arc<Mutex<Vec<(String, f32)>>>,
     loss: Hogwild<f32>,
     model: TrainModel<T>,
     n_examples: Hogwild<usize>,
     n_tokens_processed: Hogwild<usize>,
-    sgd_impl: NegativeSamplingSgd,
+    pending: PendingBatch,
+    sgd_impl: NegativeSamplingSgd<O>,
+    validation_loss: Hogwild<Option<f32>>,
 }
 
 impl<T> Sgd<T>
 where
     T: Trainer,
 {
-    pub fn into_model(self) -> TrainModel<T> {
+    /// Construct a new SGD instance,
+    ///
+    /// The optimizer is the optimizer of the model configuration. When
+    /// embeddings are tied, the input and output matrix share the
+    /// optimizer state.
+    pub fn new(mut model: TrainModel<T>) -> Self {
+        let optimizer = model.config().optimizer;
+        let dims = model.config().dims as usize;
+        let input_optimizer = OptimizerWrap::new(optimizer, model.trainer().n_input_types(), dims);
+        let output_optimizer = if model.config().tied {
+            input_optimizer.clone()
+        } else {
+            OptimizerWrap::new(optimizer, model.trainer().n_output_types(), dims)
+        };
+
+        Self::with_optimizers(model, input_optimizer, output_optimizer)
+    }
+}
+
+impl<T, V> Sgd<T>
+where
+    T: Trainer<InputVocab = V>,
+    V: Vocab<VocabType = String>,
+{
+    /// Take a checkpoint of the training state.
+    ///
+    /// The checkpoint copies the matrices and optimizer states, so it
+    /// can be taken while other clones of this SGD continue training.
+    /// The positions of the training threads are not known to the SGD,
+    /// they are set with `Checkpoint::with_threads`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        let tied = self.model.config().tied;
+        let (input, output) = self.model.to_arrays();
+
+        Checkpoint {
+            vocab: self
+                .model
+                .input_vocab()
+                .types()
+                .iter()
+                .map(|t| (t.label().clone(), t.count()))
+                .collect(),
+            n_tokens_processed: *self.n_tokens_processed,
+            n_examples: *self.n_examples,
+            loss: *self.loss,
+            input,
+            output: if tied { None } else { Some(output) },
+            input_optimizer: self.sgd_impl.input_optimizer.state(),
+            output_optimizer: if tied {
+                None
+            } else {
+                Some(self.sgd_impl.output_optimizer.state())
+            },
+            threads: Vec::new(),
+            validation_seed: 0,
+        }
+    }
+
+    /// Restore the training state from a checkpoint.
+    ///
+    /// The vocabulary of the checkpoint must be the vocabulary of this
+    /// SGD's model. Clones of this SGD share the restored state.
+    pub fn restore(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        let types = self.model.input_vocab().types();
+        ensure!(
+            types.len() == checkpoint.vocab.len()
+                && types
+                    .iter()
+                    .zip(&checkpoint.vocab)
+                    .all(|(t, (word, count))| t.label() == word && t.count() == *count),
+            "The vocabulary of the checkpoint differs from the vocabulary of the model"
+        );
+        ensure!(
+            checkpoint.output.is_none() == self.model.config().tied,
+            "Checkpoint and model differ in tying of embeddings"
+        );
+
+        let output = checkpoint.output.as_ref().unwrap_or(&checkpoint.input);
         self.model
+            .assign_arrays(checkpoint.input.view(), output.view())?;
+
+        self.sgd_impl
+            .input_optimizer
+            .restore(&checkpoint.input_optimizer)?;
+        if let Some(output_optimizer) = &checkpoint.output_optimizer {
+            self.sgd_impl.output_optimizer.restore(output_optimizer)?;
+        }
+
+        *self.n_tokens_processed = checkpoint.n_tokens_processed;
+        *self.n_examples = checkpoint.n_examples;
+        *self.loss = checkpoint.loss;
+
+        Ok(())
     }
+}
 
-    /// Construct a new SGD instance,
-    pub fn new(model: TrainModel<T>) -> Self {
-        let sgd_impl = NegativeSamplingSgd::new(model.config().negative_samples as usize);
+impl<T, O> Sgd<T, O>
+where
+    T: Reseed,
+{
+    /// Reseed the random number generators of the trainer.
+    ///
+    /// Every training thread reseeds its copy of the SGD once, so that
+    /// the threads draw from independent generators.
+    pub fn reseed(&mut self, seed: u64) {
+        self.model.trainer().reseed(seed);
+
+        #[cfg(feature = "gpu")]
+        {
+            self.sgd_impl.gpu_seed = seed;
+        }
+    }
+}
+
+impl<T, O> Sgd<T, O>
+where
+    T: Trainer,
+    O: Optimizer,
+{
+    /// Construct a new SGD instance with the given optimizers.
+    ///
+    /// `input_optimizer` and `output_optimizer` update the rows of the
+    /// input and output matrix respectively.
+    pub fn with_optimizers(model: TrainModel<T>, input_optimizer: O, output_optimizer: O) -> Self {
+        let sgd_impl = NegativeSamplingSgd::new(model.config(), input_optimizer, output_optimizer);
+        let pending = PendingBatch::new(1, model.config().dims as usize);
 
         Sgd {
+            batch_size: 1,
+            epoch_size: model.input_vocab().n_types(),
+            eval_scores: Arc::new(Mutex::new(Vec::new())),
             loss: Hogwild::default(),
             model,
             n_examples: Hogwild::default(),
             n_tokens_processed: Hogwild::default(),
+            pending,
             sgd_impl,
+            validation_loss: Hogwild::default(),
         }
     }
+
+    pub fn into_model(self) -> TrainModel<T> {
+        self.model
+    }
+
     /// Get the training model associated with this SGD.
     pub fn model(&self) -> &TrainModel<T> {
         &self.model
@@ -48,6 +241,49 @@ where
         *self.n_tokens_processed
     }
 
+    /// Set the number of tokens that are processed per epoch.
+    ///
+    /// The epoch size is used to determine when quantization-aware
+    /// training starts. By default, the epoch size is the number of tokens
+    /// in the corpus that the input vocabulary was constructed from.
+    pub fn set_epoch_size(&mut self, epoch_size: usize) {
+        self.epoch_size = epoch_size;
+    }
+
+    /// Set the number of training instances per update.
+    ///
+    /// With a batch size larger than one, `update_sentence` accumulates
+    /// batches of training instances and updates the parameters once per
+    /// batch. By default, the parameters are updated after every training
+    /// instance. Pending training instances are discarded, so the batch
+    /// size should be set before training.
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        assert!(batch_size > 0, "The batch size should be positive");
+        self.batch_size = batch_size;
+        self.pending = PendingBatch::new(batch_size, self.model.config().dims as usize);
+    }
+
+    /// Run the batch operations on the GPU.
+    ///
+    /// This opens the GPU and uploads the noise distribution of the
+    /// trainer. With a batch size larger than one, the matrix products of
+    /// batches and the negatives of their training instances are computed
+    /// on the GPU. Negatives that are outputs of the same focus are
+    /// rejected on the GPU as well, but negatives are not shared. Clones
+    /// of this SGD share the GPU.
+    #[cfg(feature = "gpu")]
+    pub fn use_gpu(&mut self) -> Result<()>
+    where
+        T: NegativeSamples,
+    {
+        let trainer = self.model.trainer();
+        let noise_probs = (0..trainer.n_output_types())
+            .map(|output| trainer.noise_prob(output))
+            .collect::<Vec<_>>();
+        self.sgd_impl.gpu = Some(Arc::new(Gpu::new(&noise_probs)?));
+        Ok(())
+    }
+
     /// Get the average training loss of this SGD.
     ///
     /// This returns the average training loss over all instances seen by
@@ -56,21 +292,105 @@ where
         *self.loss / *self.n_examples as f32
     }
 
+    /// Get the most recent validation loss.
+    ///
+    /// Returns `None` if no validation loss was set.
+    pub fn validation_loss(&self) -> Option<f32> {
+        *self.validation_loss
+    }
+
+    /// Set the validation loss.
+    ///
+    /// The validation loss is shared between clones of this SGD, so that
+    /// it can be computed by one clone and reported by another.
+    pub fn set_validation_loss(&mut self, loss: f32) {
+        *self.validation_loss = Some(loss);
+    }
+
+    /// Get the most recent evaluation scores.
+    ///
+    /// Returns the dataset names and their scores.
+    pub fn eval_scores(&self) -> Vec<(String, f32)> {
+        self.eval_scores
+            .lock()
+            .expect("Evaluation scores lock poisoned")
+            .clone()
+    }
+
+    /// Set the evaluation scores.
+    ///
+    /// Like the validation loss, the evaluation scores are shared between
+    /// clones of this SGD.
+    pub fn set_eval_scores(&mut self, scores: Vec<(String, f32)>) {
+        *self
+            .eval_scores
+            .lock()
+            .expect("Evaluation scores lock poisoned") = scores;
+    }
+
+    /// Compute the loss of the given sentence.
+    ///
+    /// The loss is computed as in `update_sentence`, but the parameters
+    /// and the training loss are not updated. Frequent words are not
+    /// subsampled and subwords are not dropped. Negatives are drawn by a
+    /// copy of the trainer that is seeded with `seed`, so that the random
+    /// number generators of the trainer are not advanced. Returns the sum
+    /// of the losses and the number of training instances of the sentence.
+    pub fn sentence_loss<'b, S>(&mut self, sentence: &S, seed: u64) -> (f32, usize)
+    where
+        S: ?Sized,
+        T: TrainIterFrom<'b, S> + Trainer + NegativeSamples + Reseed + Subsampling + Clone,
+        for<'a> &'a T::Focus: IntoIterator<Item = u64>,
+    {
+        let mut validation_trainer = self.model.trainer().clone();
+        validation_trainer.set_subsampling(false);
+        validation_trainer.reseed(seed);
+        let trainer = mem::replace(self.model.trainer(), validation_trainer);
+
+        let mut loss = 0.;
+        let mut n_examples = 0;
+        for (focus, contexts) in self.train_batch(sentence) {
+            let input_embed = self.model.mean_input_embedding(&focus);
+            self.sgd_impl.set_window(&contexts);
+
+            for context in contexts {
+                loss += self
+                    .sgd_impl
+                    .output_loss(&mut self.model, input_embed.view(), context);
+                n_examples += 1;
+            }
+        }
+
+        *self.model.trainer() = trainer;
+
+        (loss, n_examples)
+    }
+
     /// Update the model parameters using the given sentence.
     ///
     /// This applies a gradient descent step on the sentence, with the given
-    /// learning rate.
+    /// learning rate. If the batch size is larger than one, the training
+    /// instances of the sentence are applied in batches, see
+    /// `update_sentence_batched`. Batches span sentences, so
+    /// `flush_batch` must be called after the last sentence.
     pub fn update_sentence<'b, S>(&mut self, sentence: &S, lr: f32)
     where
         S: ?Sized,
         T: TrainIterFrom<'b, S> + Trainer + NegativeSamples,
         for<'a> &'a T::Focus: IntoIterator<Item = u64>,
-        T::Focus: WordIdx,
     {
-        for (focus, contexts) in self.model.trainer().train_iter_from(sentence) {
+        self.update_quantization();
+
+        if self.batch_size > 1 {
+            self.update_sentence_batched(sentence, lr);
+            return;
+        }
+
+        for (focus, contexts) in self.train_batch(sentence) {
             // Update parameters for the token focus token i and the
             // context token j.
             let input_embed = self.model.mean_input_embedding(&focus);
+            self.sgd_impl.set_window(&contexts);
 
             for context in contexts {
                 *self.loss += self.sgd_impl.sgd_step(
@@ -85,144 +405,1702 @@ where
             *self.n_tokens_processed += 1;
         }
     }
-}
 
-/// Log-logistic loss SGD with negative sampling.
-///
-/// This type implements gradient descent for log-logistic loss with negative
-/// sampling (Mikolov, 2013).
-///
-/// In this approach, word embeddings training is shaped as a
-/// prediction task. The word vectors should be parametrized such that
-/// words that co-occur with a given input get an estimated probability
-/// of 1.0, whereas words that do not co-occur with the input get an
-/// estimated probability of 0.0.
-///
-/// The probability is computed from the inner product of two word
-/// vectors by applying the logistic function. The loss is the negative
-/// log likelihood.
-///
-/// Due to the vocabulary sizes, it is not possible to update the vectors
-/// for all words that do not co-occur in every step. Instead, such
-/// negatives are sampled, weighted by word frequency.
-#[derive(Clone)]
-pub struct NegativeSamplingSgd {
-    negative_samples: usize,
-}
+    /// Update the model parameters using batches of training instances of
+    /// the given sentence.
+    ///
+    /// The outputs and negatives of training instances are added to the
+    /// pending batch, which is applied with `apply_pending_batch` once it
+    /// holds `batch_size` instances. Batches span sentences, the last
+    /// instances of a sentence remain pending until the batch is full or
+    /// `flush_batch` is called. As in unbatched updates, the input
+    /// embedding of a focus is read once for all its instances.
+    fn update_sentence_batched<'b, S>(&mut self, sentence: &S, lr: f32)
+    where
+        S: ?Sized,
+        T: TrainIterFrom<'b, S> + Trainer + NegativeSamples,
+        for<'a> &'a T::Focus: IntoIterator<Item = u64>,
+    {
+        for (focus, contexts) in self.train_batch(sentence) {
+            let input_embed = self.model.mean_input_embedding(&focus);
+            self.sgd_impl.set_window(&contexts);
 
-impl NegativeSamplingSgd {
-    /// Create a new loss function.
-    pub fn new(negative_samples: usize) -> Self {
-        NegativeSamplingSgd { negative_samples }
+            let inputs = (&focus).into_iter().collect::<Vec<_>>();
+            let mut focus_pending = false;
+            for context in contexts {
+                // The focus is added again when its previous instances
+                // were applied.
+                if !focus_pending {
+                    self.pending.add_focus(inputs.clone());
+                    focus_pending = true;
+                }
+                let outputs = self.sgd_impl.batch_outputs(&mut self.model, context);
+                self.pending.add_instance(input_embed.view(), outputs, lr);
+
+                if self.pending.len() == self.batch_size {
+                    self.apply_pending_batch();
+                    focus_pending = false;
+                }
+            }
+            *self.n_tokens_processed += 1;
+        }
     }
 
-    /// Perform a step of gradient descent.
-    ///
-    /// This method will estimate the probability of `output` and randomly
-    /// chosen negative samples, given the input. It will then update the
-    /// embeddings of the positive/negative outputs and the input (and its
-    /// subwords).
+    /// Apply the pending batch of training instances.
     ///
-    /// The function returns the sum of losses.
-    pub fn sgd_step<T>(
-        &mut self,
-        model: &mut TrainModel<T>,
-        input: impl IntoIterator<Item = u64>,
-        input_embed: ArrayView1<f32>,
-        output: usize,
-        lr: f32,
-    ) -> f32
+    /// When the batch size is larger than one, the last training instances
+    /// that were passed to `update_sentence` may not be applied yet. This
+    /// method applies them, it should be called before the parameters are
+    /// copied, such as at the end of training and before a checkpoint.
+    pub fn flush_batch(&mut self)
     where
         T: NegativeSamples,
     {
-        let mut loss = 0.0;
-        let mut input_delta = Array1::zeros(input_embed.shape()[0]);
+        if !self.pending.is_empty() {
+            self.apply_pending_batch();
+        }
+    }
 
-        // Update the output embedding of the positive instance.
-        loss += self.update_output(
-            model,
-            input_embed.view(),
-            input_delta.view_mut(),
-            output,
-            true,
-            lr,
+    /// Apply the pending batch of training instances.
+    ///
+    /// The batch is cleared afterwards.
+    fn apply_pending_batch(&mut self)
+    where
+        T: NegativeSamples,
+    {
+        let pending = &mut self.pending;
+        #[cfg(feature = "gpu")]
+        self.sgd_impl.sample_batch_negatives(&mut pending.outputs);
+        let (loss, input_deltas) = self.sgd_impl.batch_output_step(
+            &mut self.model,
+            pending.input_embeds.slice(s![..pending.len(), ..]),
+            &pending.outputs,
+            &pending.lrs,
         );
+        *self.loss += loss;
+        *self.n_examples += pending.len();
 
-        // Pick the negative examples and update their output embeddings.
-        loss += self.negative_samples(model, input_embed, input_delta.view_mut(), output, lr);
-
-        // Update the input embeddings with the accumulated gradient.
-        for idx in input {
-            let input_embed = model.input_embedding_mut(idx as usize);
-            scaled_add(input_embed, input_delta.view(), 1.0);
+        // The instances of a focus are consecutive and share the learning
+        // rate of their sentence, so their gradients can be summed over a
+        // range of rows.
+        let mut start = 0;
+        while start < pending.len() {
+            let focus = pending.instance_foci[start];
+            let end = pending.instance_foci[start..]
+                .iter()
+                .position(|&instance_focus| instance_focus != focus)
+                .map_or(pending.len(), |len| start + len);
+            let input_delta = input_deltas.slice(s![start..end, ..]).sum_axis(Axis(0));
+            for &idx in &pending.foci[focus] {
+                self.sgd_impl.update_input_embedding(
+                    &mut self.model,
+                    idx as usize,
+                    input_delta.view(),
+                    pending.lrs[start],
+                );
+            }
+            start = end;
         }
 
-        loss
+        pending.clear();
     }
 
-    /// Pick, predict and update negative samples.
-    fn negative_samples<T>(
-        &mut self,
-        model: &mut TrainModel<T>,
-        input_embed: ArrayView1<f32>,
-        mut input_delta: ArrayViewMut1<f32>,
-        output: usize,
-        lr: f32,
-    ) -> f32
+    /// Update the model parameters using the given sentence with
+    /// position-weighted inputs.
+    ///
+    /// Every input word is paired with the input index of the weights of
+    /// its position. The input embedding is the average of the
+    /// element-wise products of the position weights and the word
+    /// embeddings (Mikolov et al., 2018). Position weights are stored as
+    /// offsets from one, so that randomly initialized weights are close
+    /// to one.
+    pub fn update_sentence_positional<'b, S, I>(&mut self, sentence: &S, lr: f32)
     where
-        T: NegativeSamples,
+        S: ?Sized,
+        T: TrainIterFrom<'b, S, Focus = Vec<(u64, I)>> + Trainer + NegativeSamples,
+        for<'a> &'a I: IntoIterator<Item = u64>,
     {
-        let mut loss = 0f32;
+        self.update_quantization();
 
-        for _ in 0..self.negative_samples {
-            let negative = model.trainer().negative_sample(output);
-            // Update input and output for this negative sample.
-            loss += self.update_output(
-                model,
-                input_embed.view(),
-                input_delta.view_mut(),
-                negative,
-                false,
-                lr,
-            );
+        for (focus, contexts) in self.train_batch(sentence) {
+            let word_embeds = focus
+                .iter()
+                .map(|(_, word)| self.model.mean_input_embedding(word))
+                .collect::<Vec<_>>();
+            let weights = focus
+                .iter()
+                .map(|&(position, _)| {
+                    self.model
+                        .input_embedding(position as usize)
+                        .mapv(|w| 1.0 + w)
+                })
+                .collect::<Vec<_>>();
+
+            let mut input_embed = Array1::zeros(self.model.config().dims as usize);
+            for (weight, word_embed) in weights.iter().zip(&word_embeds) {
+                input_embed += &(weight * word_embed);
+            }
+            scale(input_embed.view_mut(), 1.0 / focus.len() as f32);
+            self.sgd_impl.set_window(&contexts);
+
+            for context in contexts {
+                let (loss, input_delta) =
+                    self.sgd_impl
+                        .output_step(&mut self.model, input_embed.view(), context, lr);
+                *self.loss += loss;
+
+                // The update of a word embedding is scaled by the position
+                // weights and the update of the position weights is scaled
+                // by the word embedding.
+                for (((position, word), weight), word_embed) in
+                    focus.iter().zip(&weights).zip(&word_embeds)
+                {
+                    let word_delta = &input_delta * weight;
+                    for idx in word {
+                        self.sgd_impl.update_input_embedding(
+                            &mut self.model,
+                            idx as usize,
+                            word_delta.view(),
+                            lr,
+                        );
+                    }
+
+                    self.sgd_impl.update_input_embedding(
+                        &mut self.model,
+                        *position as usize,
+                        (&input_delta * word_embed).view(),
+                        lr,
+                    );
+                }
+                *self.n_examples += 1;
+            }
+            *self.n_tokens_processed += 1;
         }
+    }
 
-        loss
+    /// Update the model parameters of a multi-sense model using the given
+    /// sentence.
+    ///
+    /// The focus of each training instance is a word and the bag of its
+    /// context words. The sense of the word is selected using the mean
+    /// embedding of the context words (Neelakantan et al., 2014). Both the
+    /// embedding of the word and the embedding of the selected sense are
+    /// trained to predict the outputs.
+    pub fn update_sentence_multisense<'b, S, I>(&mut self, sentence: &S, lr: f32)
+    where
+        S: ?Sized,
+        T: TrainIterFrom<'b, S, Focus = (I, BagOfWordsIdx)>
+            + Trainer
+            + NegativeSamples
+            + SelectSense,
+        I: WordIdx,
+        for<'a> &'a I: IntoIterator<Item = u64>,
+    {
+        self.update_quantization();
+
+        for ((word, context), outputs) in self.train_batch(sentence) {
+            let context_embed = self.model.mean_input_embedding(&context);
+            let sense = self
+                .model
+                .trainer()
+                .select_sense(word.word_idx() as usize, context_embed.view());
+
+            let word_embed = self.model.mean_input_embedding(&word);
+            let sense_embed = self.model.input_embedding(sense as usize).to_owned();
+            self.sgd_impl.set_window(&outputs);
+
+            for output in outputs {
+                *self.loss += self.sgd_impl.sgd_step(
+                    &mut self.model,
+                    (&word).into_iter(),
+                    word_embed.view(),
+                    output,
+                    lr,
+                );
+                *self.loss += self.sgd_impl.sgd_step(
+                    &mut self.model,
+                    iter::once(sense),
+                    sense_embed.view(),
+                    output,
+                    lr,
+                );
+                *self.n_examples += 2;
+            }
+            *self.n_tokens_processed += 1;
+        }
+    }
+
+    /// Update the model parameters of a model with concatenated inputs
+    /// using the given sentence.
+    ///
+    /// Every input word is paired with its position. The input embedding
+    /// is the concatenation of the embeddings of the input words in their
+    /// positions (Ling et al., 2015). Positions without an input word are
+    /// zero-filled.
+    pub fn update_sentence_concat<'b, S, I>(&mut self, sentence: &S, lr: f32)
+    where
+        S: ?Sized,
+        T: TrainIterFrom<'b, S, Focus = Vec<(usize, I)>> + Trainer + NegativeSamples + ConcatInputs,
+        for<'a> &'a I: IntoIterator<Item = u64>,
+    {
+        self.update_quantization();
+
+        let dims = self.model.config().dims as usize;
+        let n_positions = self.model.trainer().n_positions();
+
+        for (focus, contexts) in self.train_batch(sentence) {
+            let mut input_embed = Array1::zeros(n_positions * dims);
+            for (position, word) in &focus {
+                input_embed
+                    .slice_mut(s![position * dims..(position + 1) * dims])
+                    .assign(&self.model.mean_input_embedding(word));
+            }
+            self.sgd_impl.set_window(&contexts);
+
+            for context in contexts {
+                *self.loss += self.sgd_impl.concat_sgd_step(
+                    &mut self.model,
+                    &focus,
+                    input_embed.view(),
+                    context,
+                    lr,
+                );
+                *self.n_examples += 1;
+            }
+            *self.n_tokens_processed += 1;
+        }
     }
 
-    /// Update an output embedding.
+    /// Enable simulated quantization of the input embeddings in the
+    /// quantization-aware training epochs.
     ///
-    /// This also accumulates an update for the input embedding.
+    /// Quantization-aware training is done in the last `qat_epochs`
+    /// epochs of training.
+    fn update_quantization(&mut self) {
+        let config = *self.model.config();
+        if config.qat_epochs == 0 {
+            return;
+        }
+
+        let qat_start = (config.epochs - config.qat_epochs) as usize * self.epoch_size;
+        self.model
+            .set_quantize_inputs(*self.n_tokens_processed >= qat_start);
+    }
+
+    /// Get the training instances of a sentence as a batch.
     ///
-    /// The method returns the loss for predicting the output.
-    fn update_output<T>(
+    /// The outputs of the training instances are the outputs of the
+    /// batch, which the InfoNCE loss uses as in-batch negatives. Without
+    /// in-batch negatives, the training instances are streamed.
+    fn train_batch<'b, S>(
         &mut self,
-        model: &mut TrainModel<T>,
-        input_embed: ArrayView1<f32>,
-        input_delta: ArrayViewMut1<f32>,
-        output: usize,
-        label: bool,
-        lr: f32,
-    ) -> f32 {
-        let (loss, part_gradient) =
-            log_logistic_loss(input_embed.view(), model.output_embedding(output), label);
-
-        // Update the input weight: u_n += lr * u_n' v_n. We are not updating
-        // the weight immediately, but accumulating the weight updates in
-        // input_delta.
-        scaled_add(
-            input_delta,
-            model.output_embedding(output),
-            lr * part_gradient,
+        sentence: &S,
+    ) -> TrainBatch<<T as TrainIterFrom<'b, S>>::Iter, <T as TrainIterFrom<'b, S>>::Focus>
+    where
+        S: ?Sized,
+        T: TrainIterFrom<'b, S>,
+    {
+        let iter = self.model.trainer().train_iter_from(sentence);
+        if !self.sgd_impl.uses_batch() {
+            return TrainBatch::Streamed(iter);
+        }
+
+        let instances = iter
+            .map(|(focus, contexts)| (focus, contexts.into_iter().collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        self.sgd_impl.set_batch(
+            instances
+                .iter()
+                .flat_map(|(_, contexts)| contexts.iter().cloned()),
         );
+        TrainBatch::Collected(instances.into_iter())
+    }
+
+    /// Update the model parameters using a lexical constraint.
+    ///
+    /// This applies a gradient descent step on the logistic loss of the
+    /// inner product of the input embeddings of the constrained words.
+    /// The loss of the constraint is returned, it is not included in the
+    /// training loss.
+    pub fn update_constraint(&mut self, constraint: &LexicalConstraint, lr: f32) -> f32 {
+        let first_embed = self.model.mean_input_embedding(constraint.first());
+        let second_embed = self.model.mean_input_embedding(constraint.second());
 
-        // Update the output weight: v_n += lr * v_n' u_n.
-        scaled_add(
-            model.output_embedding_mut(output),
-            input_embed.view(),
-            lr * part_gradient,
+        let (loss, part_gradient) = log_logistic_loss(
+            first_embed.view(),
+            second_embed.view(),
+            constraint.attract(),
         );
 
+        let first_delta = &second_embed * part_gradient;
+        for idx in constraint.first() {
+            self.sgd_impl.update_input_embedding(
+                &mut self.model,
+                idx as usize,
+                first_delta.view(),
+                lr,
+            );
+        }
+
+        let second_delta = &first_embed * part_gradient;
+        for idx in constraint.second() {
+            self.sgd_impl.update_input_embedding(
+                &mut self.model,
+                idx as usize,
+                second_delta.view(),
+                lr,
+            );
+        }
+
         loss
     }
 }
+
+/// Training instances that are not applied yet.
+///
+/// Instance *i* of the batch has the input embedding `input_embeds[i]`,
+/// the outputs `outputs[i]`, and the learning rate `lrs[i]`. Its focus
+/// has the input indices `foci[instance_foci[i]]`.
+#[derive(Clone)]
+struct PendingBatch {
+    input_embeds: Array2<f32>,
+    foci: Vec<Vec<u64>>,
+    instance_foci: Vec<usize>,
+    outputs: Vec<Vec<(usize, bool)>>,
+    lrs: Vec<f32>,
+}
+
+impl PendingBatch {
+    /// Construct an empty batch of at most `batch_size` instances.
+    fn new(batch_size: usize, dims: usize) -> Self {
+        PendingBatch {
+            input_embeds: Array2::zeros((batch_size, dims)),
+            foci: Vec::new(),
+            instance_foci: Vec::with_capacity(batch_size),
+            outputs: Vec::with_capacity(batch_size),
+            lrs: Vec::with_capacity(batch_size),
+        }
+    }
+
+    /// Add a focus with the given input indices.
+    ///
+    /// The instances that are added subsequently are instances of this
+    /// focus.
+    fn add_focus(&mut self, inputs: Vec<u64>) {
+        self.foci.push(inputs);
+    }
+
+    /// Add an instance of the last focus.
+    fn add_instance(&mut self, input_embed: ArrayView1<f32>, outputs: Vec<(usize, bool)>, lr: f32) {
+        assert!(!self.foci.is_empty(), "Instance added without a focus");
+        self.input_embeds
+            .row_mut(self.outputs.len())
+            .assign(&input_embed);
+        self.instance_foci.push(self.foci.len() - 1);
+        self.outputs.push(outputs);
+        self.lrs.push(lr);
+    }
+
+    fn clear(&mut self) {
+        self.foci.clear();
+        self.instance_foci.clear();
+        self.outputs.clear();
+        self.lrs.clear();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.outputs.len()
+    }
+}
+
+/// Log-logistic loss SGD with negative sampling.
+///
+/// This type implements gradient descent for log-logistic loss with negative
+/// sampling (Mikolov, 2013).
+///
+/// In this approach, word embeddings training is shaped as a
+/// prediction task. The word vectors should be parametrized such that
+/// words that co-occur with a given input get an estimated probability
+/// of 1.0, whereas words that do not co-occur with the input get an
+/// estimated probability of 0.0.
+///
+/// The probability is computed from the inner product of two word
+/// vectors by applying the logistic function. The loss is the negative
+/// log likelihood.
+///
+/// Due to the vocabulary sizes, it is not possible to update the vectors
+/// for all words that do not co-occur in every step. Instead, such
+/// negatives are sampled, weighted by word frequency.
+///
+/// Negative samples never coincide with the true output. Optionally,
+/// negative samples that are other outputs of the same focus (such as the
+/// other words in the context window) are rejected as well.
+///
+/// When the loss is `LossType::Nce`, the sampled outputs are treated as
+/// noise samples in noise-contrastive estimation (Gutmann and Hyvärinen,
+/// 2012) and the predictions are corrected for the noise distribution.
+/// When the loss is `LossType::MarginRanking`, the positive output
+/// should outscore every sampled output by a margin. When the loss is
+/// `LossType::InfoNce`, the positive output is classified among the
+/// sampled outputs and the outputs of other training instances in the
+/// batch.
+///
+/// The embeddings are updated using the optimizers `O`.
+#[derive(Clone)]
+pub struct NegativeSamplingSgd<O> {
+    negative_samples: usize,
+    loss: LossType,
+    margin: f32,
+    batch_negatives: usize,
+    batch: Vec<usize>,
+    batch_offset: usize,
+    exclude_window: bool,
+    window: Vec<usize>,
+    share_negatives: bool,
+    negatives: Vec<usize>,
+    outputs: Vec<(usize, bool)>,
+    part_gradients: Vec<f32>,
+    clip_norm: Option<f32>,
+    clip_per_row: bool,
+    weight_decay: f32,
+    input_optimizer: O,
+    output_optimizer: O,
+    #[cfg(feature = "gpu")]
+    gpu: Option<Arc<Gpu>>,
+    #[cfg(feature = "gpu")]
+    gpu_seed: u64,
+    #[cfg(feature = "gpu")]
+    gpu_windows: Vec<u32>,
+    #[cfg(feature = "gpu")]
+    gpu_window_ranges: Vec<[u32; 2]>,
+    #[cfg(feature = "gpu")]
+    gpu_window_start: Option<usize>,
+}
+
+impl<O> NegativeSamplingSgd<O>
+where
+    O: Optimizer,
+{
+    /// Create a new loss function.
+    ///
+    /// The number of negatives, the loss, gradient clipping, and weight
+    /// decay are configured by `config`. `input_optimizer` and
+    /// `output_optimizer` update the rows of the input and output matrix
+    /// respectively.
+    pub fn new(config: &CommonConfig, input_optimizer: O, output_optimizer: O) -> Self {
+        NegativeSamplingSgd {
+            negative_samples: config.negative_samples as usize,
+            loss: config.loss,
+            margin: config.margin,
+            batch_negatives: config.batch_negatives as usize,
+            batch: Vec::new(),
+            batch_offset: 0,
+            exclude_window: config.ns_exclude_window,
+            window: Vec::new(),
+            share_negatives: config.ns_shared,
+            negatives: Vec::new(),
+            outputs: Vec::new(),
+            part_gradients: Vec::new(),
+            clip_norm: config.clip_norm,
+            clip_per_row: config.clip_per_row,
+            weight_decay: config.weight_decay,
+            input_optimizer,
+            output_optimizer,
+            #[cfg(feature = "gpu")]
+            gpu: None,
+            #[cfg(feature = "gpu")]
+            gpu_seed: 0,
+            #[cfg(feature = "gpu")]
+            gpu_windows: Vec::new(),
+            #[cfg(feature = "gpu")]
+            gpu_window_ranges: Vec::new(),
+            #[cfg(feature = "gpu")]
+            gpu_window_start: None,
+        }
+    }
+
+    /// Check whether the outputs of the batch are used as negatives.
+    ///
+    /// This is the case for the InfoNCE loss with in-batch negatives.
+    pub fn uses_batch(&self) -> bool {
+        matches!(self.loss, LossType::InfoNce) && self.batch_negatives > 0
+    }
+
+    /// Set the outputs of the current batch.
+    ///
+    /// With the InfoNCE loss, every training instance uses the outputs of
+    /// the other training instances in the batch as negatives. The
+    /// outputs are ignored by other losses.
+    pub fn set_batch(&mut self, outputs: impl IntoIterator<Item = usize>) {
+        self.batch.clear();
+        self.batch_offset = 0;
+
+        if self.uses_batch() {
+            self.batch.extend(outputs);
+            self.batch.sort_unstable();
+            self.batch.dedup();
+        }
+    }
+
+    /// Set the outputs of the current focus.
+    ///
+    /// If negative samples that are outputs of the same focus should be
+    /// rejected, negative samples are redrawn when they are in `outputs`.
+    /// If negative samples are shared, a new set of negative samples is
+    /// drawn for the focus.
+    pub fn set_window(&mut self, outputs: &[usize]) {
+        self.window.clear();
+        self.negatives.clear();
+        #[cfg(feature = "gpu")]
+        {
+            self.gpu_window_start = None;
+        }
+
+        if self.exclude_window {
+            self.window.extend_from_slice(outputs);
+            self.window.sort_unstable();
+            self.window.dedup();
+        }
+    }
+
+    /// Perform a step of gradient descent.
+    ///
+    /// This method will estimate the probability of `output` and randomly
+    /// chosen negative samples, given the input. It will then update the
+    /// embeddings of the positive/negative outputs and the input (and its
+    /// subwords).
+    ///
+    /// The function returns the sum of losses.
+    pub fn sgd_step<T>(
+        &mut self,
+        model: &mut TrainModel<T>,
+        input: impl IntoIterator<Item = u64>,
+        input_embed: ArrayView1<f32>,
+        output: usize,
+        lr: f32,
+    ) -> f32
+    where
+        T: NegativeSamples,
+    {
+        let (loss, input_delta) = self.output_step(model, input_embed, output, lr);
+
+        // Update the input embeddings with the accumulated gradient.
+        for idx in input {
+            self.update_input_embedding(model, idx as usize, input_delta.view(), lr);
+        }
+
+        loss
+    }
+
+    /// Perform a step of gradient descent on the outputs.
+    ///
+    /// This method will estimate the probability of `output` and randomly
+    /// chosen negative samples, given the input. It will then update the
+    /// embeddings of the positive/negative outputs.
+    ///
+    /// The function returns the sum of losses and the accumulated gradient
+    /// of the input embedding. The gradient is not scaled by the learning
+    /// rate, it should be applied using `update_input_embedding`.
+    pub fn output_step<T>(
+        &mut self,
+        model: &mut TrainModel<T>,
+        input_embed: ArrayView1<f32>,
+        output: usize,
+        lr: f32,
+    ) -> (f32, Array1<f32>)
+    where
+        T: NegativeSamples,
+    {
+        // The buffers of the outputs and their gradients are reused
+        // across training instances.
+        let mut outputs = mem::take(&mut self.outputs);
+        let mut part_gradients = mem::take(&mut self.part_gradients);
+        self.sample_outputs_into(model, output, &mut outputs);
+        let loss = self.predict_outputs(model, input_embed, &outputs, &mut part_gradients);
+        self.rank_positive(&mut part_gradients);
+
+        // Accumulate the gradient of the input weight: u_n += lr * u_n' v_n.
+        let mut input_delta = Array1::zeros(input_embed.len());
+        for (&(output, _), &part_gradient) in outputs.iter().zip(&part_gradients) {
+            scaled_add(
+                input_delta.view_mut(),
+                model.output_embedding(output).view(),
+                part_gradient,
+            );
+        }
+
+        let clip = self.global_clip_factor(input_embed, input_delta.view(), &part_gradients);
+        input_delta *= clip;
+
+        // Update the output weights: v_n += lr * v_n' u_n.
+        for (&(output, _), &part_gradient) in outputs.iter().zip(&part_gradients) {
+            self.update_output_embedding(model, output, input_embed, clip * part_gradient, lr);
+        }
+
+        self.outputs = outputs;
+        self.part_gradients = part_gradients;
+
+        (loss, input_delta)
+    }
+
+    /// Perform a step of gradient descent on the outputs of a batch.
+    ///
+    /// This is the batched counterpart of `output_step`. Row *i* of
+    /// `input_embeds` is the input embedding of training instance *i*,
+    /// whose outputs (the positive output followed by the negatives) are
+    /// `outputs[i]` and whose learning rate is `lrs[i]`. The embeddings
+    /// of the outputs of the batch are gathered once, so that the scores
+    /// and gradients of all instances are computed in bulk, on the GPU if
+    /// one is set. The gradients of an output that occurs in several
+    /// instances are summed and applied in a single update. Each gradient
+    /// is weighted by the learning rate of its instance relative to the
+    /// largest learning rate of the batch, which is the learning rate of
+    /// the update.
+    ///
+    /// The function returns the sum of losses and the accumulated gradients
+    /// of the input embeddings, one row per instance. The gradients are
+    /// not scaled by the learning rate, they should be applied using
+    /// `update_input_embedding`.
+    pub fn batch_output_step<T>(
+        &mut self,
+        model: &mut TrainModel<T>,
+        input_embeds: ArrayView2<f32>,
+        outputs: &[Vec<(usize, bool)>],
+        lrs: &[f32],
+    ) -> (f32, Array2<f32>)
+    where
+        T: NegativeSamples,
+    {
+        assert_eq!(
+            input_embeds.nrows(),
+            outputs.len(),
+            "Number of input embeddings and training instances differ"
+        );
+        assert_eq!(
+            lrs.len(),
+            outputs.len(),
+            "Number of learning rates and training instances differ"
+        );
+
+        // Gather the embeddings of the distinct outputs of the batch.
+        let mut columns = HashMap::new();
+        let mut batch_outputs = Vec::new();
+        for &(output, _) in outputs.iter().flatten() {
+            columns.entry(output).or_insert_with(|| {
+                batch_outputs.push(output);
+                batch_outputs.len() - 1
+            });
+        }
+        let mut output_embeds = Array2::zeros((batch_outputs.len(), input_embeds.ncols()));
+        for (mut output_embed, &output) in output_embeds.outer_iter_mut().zip(&batch_outputs) {
+            output_embed.assign(&model.output_embedding(output));
+        }
+
+        // Pair every instance with the columns of its outputs. The pairs
+        // of instance i are offsets[i]..offsets[i + 1].
+        let mut pairs = Vec::new();
+        let mut offsets = Vec::with_capacity(outputs.len() + 1);
+        offsets.push(0);
+        for (instance, instance_outputs) in outputs.iter().enumerate() {
+            pairs.extend(
+                instance_outputs
+                    .iter()
+                    .map(|(output, _)| [instance, columns[output]]),
+            );
+            offsets.push(pairs.len());
+        }
+        let scores = self.dot_pairs(input_embeds, output_embeds.view(), &pairs);
+
+        let mut loss = 0.;
+        let mut part_gradients = Vec::with_capacity(pairs.len());
+        for (instance_outputs, range) in outputs.iter().zip(offsets.windows(2)) {
+            let (instance_loss, mut instance_gradients) =
+                self.score_outputs(model, instance_outputs, &scores[range[0]..range[1]]);
+            self.rank_positive(&mut instance_gradients);
+            loss += instance_loss;
+            part_gradients.extend(instance_gradients);
+        }
+
+        // Accumulate the gradients of the input weights: u_n += lr * u_n' v_n.
+        let pair_columns = pairs.iter().map(|&[_, column]| column).collect::<Vec<_>>();
+        let mut input_deltas = self.sum_scaled_rows(
+            output_embeds.view(),
+            &offsets,
+            &pair_columns,
+            &part_gradients,
+        );
+
+        for (instance, range) in offsets.windows(2).enumerate() {
+            let instance_gradients = &mut part_gradients[range[0]..range[1]];
+            let clip = self.global_clip_factor(
+                input_embeds.row(instance),
+                input_deltas.row(instance),
+                instance_gradients,
+            );
+            let mut input_delta = input_deltas.row_mut(instance);
+            input_delta *= clip;
+            for part_gradient in instance_gradients {
+                *part_gradient *= clip;
+            }
+        }
+
+        // Group the pairs by output to accumulate the gradients of the
+        // output weights: v_n += lr * v_n' u_n.
+        let mut column_counts = vec![0; batch_outputs.len()];
+        for &column in &pair_columns {
+            column_counts[column] += 1;
+        }
+        let column_offsets = iter::once(0)
+            .chain(column_counts.iter().scan(0, |offset, &count| {
+                *offset += count;
+                Some(*offset)
+            }))
+            .collect::<Vec<_>>();
+        let mut next = column_offsets.clone();
+        let mut column_instances = vec![0; pairs.len()];
+        let mut column_gradients = vec![0.; pairs.len()];
+        let lr = lrs.iter().copied().fold(0., f32::max);
+        for (&[instance, column], &part_gradient) in pairs.iter().zip(&part_gradients) {
+            column_instances[next[column]] = instance;
+            if lr > 0. {
+                column_gradients[next[column]] = part_gradient * lrs[instance] / lr;
+            }
+            next[column] += 1;
+        }
+        let output_deltas = self.sum_scaled_rows(
+            input_embeds,
+            &column_offsets,
+            &column_instances,
+            &column_gradients,
+        );
+        for (&output, output_delta) in batch_outputs.iter().zip(output_deltas.outer_iter()) {
+            self.update_output_embedding(model, output, output_delta, 1.0, lr);
+        }
+
+        (loss, input_deltas)
+    }
+
+    /// Compute the loss of predicting `output`.
+    ///
+    /// The loss is computed as in `output_step`, with newly sampled
+    /// negatives, but the embeddings are not updated.
+    pub fn output_loss<T>(
+        &mut self,
+        model: &mut TrainModel<T>,
+        input_embed: ArrayView1<f32>,
+        output: usize,
+    ) -> f32
+    where
+        T: NegativeSamples,
+    {
+        let mut outputs = mem::take(&mut self.outputs);
+        let mut part_gradients = mem::take(&mut self.part_gradients);
+        self.sample_outputs_into(model, output, &mut outputs);
+        let loss = self.predict_outputs(model, input_embed, &outputs, &mut part_gradients);
+        self.outputs = outputs;
+        self.part_gradients = part_gradients;
+        loss
+    }
+
+    /// Predict the outputs of a training instance.
+    ///
+    /// The first output is the positive output. Returns the sum of losses,
+    /// the partial gradients of the outputs are stored in
+    /// `part_gradients`. Only the InfoNCE loss, which normalizes over all
+    /// outputs, collects the scores of the outputs.
+    fn predict_outputs<T>(
+        &self,
+        model: &mut TrainModel<T>,
+        input_embed: ArrayView1<f32>,
+        outputs: &[(usize, bool)],
+        part_gradients: &mut Vec<f32>,
+    ) -> f32
+    where
+        T: NegativeSamples,
+    {
+        part_gradients.clear();
+
+        if let LossType::InfoNce = self.loss {
+            let scores = outputs
+                .iter()
+                .map(|&(output, _)| dot(input_embed, model.output_embedding(output).view()))
+                .collect::<Vec<_>>();
+            let (loss, gradients) = info_nce_loss(&scores);
+            part_gradients.extend(gradients);
+            return loss;
+        }
+
+        let mut loss = 0.;
+        let mut positive_score = 0.;
+        for (idx, &(output, _)) in outputs.iter().enumerate() {
+            let score = dot(input_embed, model.output_embedding(output).view());
+            if idx == 0 {
+                positive_score = score;
+            }
+            let (output_loss, part_gradient) =
+                self.score_output(model, outputs, idx, score, positive_score);
+            loss += output_loss;
+            part_gradients.push(part_gradient);
+        }
+
+        loss
+    }
+
+    /// Get the log of the expected number of noise samples of `output`.
+    ///
+    /// This is the correction *log(k q(v))* of noise-contrastive
+    /// estimation. Negative samples are redrawn when they coincide with
+    /// the `positive` output, so the noise distribution of a training
+    /// instance is *q* renormalized over the outputs other than
+    /// `positive`. Rejections of other outputs of the focus
+    /// (`--ns-exclude-window`) and in-batch negatives are not corrected
+    /// for.
+    fn nce_log_noise<T>(&self, model: &mut TrainModel<T>, output: usize, positive: usize) -> f32
+    where
+        T: NegativeSamples,
+    {
+        let noise_prob = model.trainer().noise_prob(output);
+        let rejection_prob = model.trainer().noise_prob(positive);
+        (self.negative_samples as f32 * noise_prob / (1. - rejection_prob)).ln()
+    }
+
+    /// Compute the losses of the outputs of a training instance from
+    /// their scores.
+    ///
+    /// `scores` are the inner products of the input embedding and the
+    /// embeddings of the `outputs`. Returns the sum of losses and the
+    /// partial gradients of the outputs.
+    fn score_outputs<T>(
+        &self,
+        model: &mut TrainModel<T>,
+        outputs: &[(usize, bool)],
+        scores: &[f32],
+    ) -> (f32, Vec<f32>)
+    where
+        T: NegativeSamples,
+    {
+        if let LossType::InfoNce = self.loss {
+            return info_nce_loss(scores);
+        }
+
+        let mut loss = 0.;
+        let mut part_gradients = Vec::with_capacity(outputs.len());
+        for (idx, &score) in scores.iter().enumerate() {
+            let (output_loss, part_gradient) =
+                self.score_output(model, outputs, idx, score, scores[0]);
+            loss += output_loss;
+            part_gradients.push(part_gradient);
+        }
+
+        (loss, part_gradients)
+    }
+
+    /// Compute the loss of output `idx` of a training instance from its
+    /// score.
+    ///
+    /// The first of the `outputs` is the positive output, its score is
+    /// `positive_score`. This method does not handle the InfoNCE loss,
+    /// since its loss depends on the scores of all outputs. Returns the
+    /// loss and the partial gradient of the output.
+    fn score_output<T>(
+        &self,
+        model: &mut TrainModel<T>,
+        outputs: &[(usize, bool)],
+        idx: usize,
+        score: f32,
+        positive_score: f32,
+    ) -> (f32, f32)
+    where
+        T: NegativeSamples,
+    {
+        let (output, label) = outputs[idx];
+        match self.loss {
+            LossType::MarginRanking => {
+                margin_ranking_loss_from_score(score, label, positive_score, self.margin)
+            }
+            LossType::Nce => {
+                let log_noise = self.nce_log_noise(model, output, outputs[0].0);
+                log_logistic_loss_from_score(score - log_noise, label)
+            }
+            _ => log_logistic_loss_from_score(score, label),
+        }
+    }
+
+    /// Perform a step of gradient descent with concatenated inputs.
+    ///
+    /// This method is the counterpart of `sgd_step` for models that
+    /// concatenate the embeddings of their input words. `input_embed` is
+    /// the concatenation of the embeddings of the `inputs` in their
+    /// positions. The output embedding of an output for position *p* is
+    /// stored in row *output * n_positions + p* of the output matrix.
+    ///
+    /// The function returns the sum of losses.
+    pub fn concat_sgd_step<T, I>(
+        &mut self,
+        model: &mut TrainModel<T>,
+        inputs: &[(usize, I)],
+        input_embed: ArrayView1<f32>,
+        output: usize,
+        lr: f32,
+    ) -> f32
+    where
+        T: NegativeSamples + ConcatInputs,
+        for<'a> &'a I: IntoIterator<Item = u64>,
+    {
+        let n_positions = model.trainer().n_positions();
+        let dims = input_embed.len() / n_positions;
+        let outputs = self.sample_outputs(model, output);
+        let output_embeds = outputs
+            .iter()
+            .map(|&(output, _)| {
+                let mut output_embed = Array1::zeros(input_embed.len());
+                for position in 0..n_positions {
+                    output_embed
+                        .slice_mut(s![position * dims..(position + 1) * dims])
+                        .assign(&model.output_embedding(output * n_positions + position));
+                }
+                output_embed
+            })
+            .collect::<Vec<_>>();
+        let positive_score = self.positive_score(input_embed, output_embeds[0].view());
+
+        // Predict the outputs.
+        let (loss, mut part_gradients) = match self.loss {
+            LossType::InfoNce => {
+                let scores = output_embeds
+                    .iter()
+                    .map(|output_embed| dot(input_embed, output_embed.view()))
+                    .collect::<Vec<_>>();
+                info_nce_loss(&scores)
+            }
+            _ => {
+                let (losses, part_gradients): (Vec<_>, Vec<_>) = outputs
+                    .iter()
+                    .zip(&output_embeds)
+                    .map(|(&(output, label), output_embed)| match self.loss {
+                        LossType::Nce => {
+                            let log_noise = self.nce_log_noise(model, output, outputs[0].0);
+                            nce_loss(input_embed, output_embed.view(), label, log_noise)
+                        }
+                        LossType::MarginRanking => margin_ranking_loss(
+                            input_embed,
+                            output_embed.view(),
+                            label,
+                            positive_score,
+                            self.margin,
+                        ),
+                        _ => log_logistic_loss(input_embed, output_embed.view(), label),
+                    })
+                    .unzip();
+                (losses.iter().sum(), part_gradients)
+            }
+        };
+        self.rank_positive(&mut part_gradients);
+
+        // Accumulate the gradient of the input weight.
+        let mut input_delta = Array1::zeros(input_embed.len());
+        for (output_embed, &part_gradient) in output_embeds.iter().zip(&part_gradients) {
+            scaled_add(input_delta.view_mut(), output_embed.view(), part_gradient);
+        }
+
+        let clip = self.global_clip_factor(input_embed, input_delta.view(), &part_gradients);
+        input_delta *= clip;
+
+        // Update the output weights of all positions.
+        for (&(output, _), part_gradient) in outputs.iter().zip(part_gradients) {
+            for position in 0..n_positions {
+                self.update_output_embedding(
+                    model,
+                    output * n_positions + position,
+                    input_embed.slice(s![position * dims..(position + 1) * dims]),
+                    clip * part_gradient,
+                    lr,
+                );
+            }
+        }
+
+        // Update the input embeddings with the accumulated gradient of
+        // their positions.
+        for (position, word) in inputs {
+            let position_delta = input_delta.slice(s![position * dims..(position + 1) * dims]);
+            for idx in word {
+                self.update_input_embedding(model, idx as usize, position_delta, lr);
+            }
+        }
+
+        loss
+    }
+
+    /// Get the outputs of a training instance.
+    ///
+    /// Returns `output`, the sampled negatives, and the in-batch
+    /// negatives, paired with their labels. When negatives are shared,
+    /// the sampled negatives are drawn once per focus. The in-batch negatives are
+    /// taken in turn from the outputs of the batch that differ from
+    /// `output`.
+    fn sample_outputs<T>(&mut self, model: &mut TrainModel<T>, output: usize) -> Vec<(usize, bool)>
+    where
+        T: NegativeSamples,
+    {
+        let mut outputs = Vec::with_capacity(self.negative_samples + self.batch_negatives + 1);
+        self.sample_outputs_into(model, output, &mut outputs);
+        outputs
+    }
+
+    /// Get the outputs of a training instance in `outputs`.
+    ///
+    /// This is the counterpart of `sample_outputs` that reuses the
+    /// buffer `outputs`, which is cleared first.
+    fn sample_outputs_into<T>(
+        &mut self,
+        model: &mut TrainModel<T>,
+        output: usize,
+        outputs: &mut Vec<(usize, bool)>,
+    ) where
+        T: NegativeSamples,
+    {
+        outputs.clear();
+        outputs.push((output, true));
+        if self.share_negatives {
+            if self.negatives.is_empty() {
+                for _ in 0..self.negative_samples {
+                    let negative = self.negative_sample(model, output);
+                    self.negatives.push(negative);
+                }
+            }
+
+            // Shared negatives were drawn for another output of the focus,
+            // so they could coincide with this output.
+            for idx in 0..self.negatives.len() {
+                let negative = match self.negatives[idx] {
+                    negative if negative == output => self.negative_sample(model, output),
+                    negative => negative,
+                };
+                outputs.push((negative, false));
+            }
+        } else {
+            for _ in 0..self.negative_samples {
+                outputs.push((self.negative_sample(model, output), false));
+            }
+        }
+
+        let mut n_batch_negatives = 0;
+        for _ in 0..self.batch.len() {
+            if n_batch_negatives == self.batch_negatives {
+                break;
+            }
+
+            let negative = self.batch[self.batch_offset];
+            self.batch_offset = (self.batch_offset + 1) % self.batch.len();
+            if negative != output {
+                outputs.push((negative, false));
+                n_batch_negatives += 1;
+            }
+        }
+    }
+
+    /// Get the outputs of a training instance of a batch.
+    ///
+    /// When negatives are sampled on the GPU, only the positive output is
+    /// returned. Its negatives are added by `sample_batch_negatives` when
+    /// the batch is applied. The window of the focus is recorded, so that
+    /// the GPU can reject negatives in the window.
+    fn batch_outputs<T>(&mut self, model: &mut TrainModel<T>, output: usize) -> Vec<(usize, bool)>
+    where
+        T: NegativeSamples,
+    {
+        #[cfg(feature = "gpu")]
+        {
+            if self.gpu.is_some() {
+                // The instances of a focus share its window.
+                let window = &self.window;
+                let windows = &mut self.gpu_windows;
+                let start = *self.gpu_window_start.get_or_insert_with(|| {
+                    let start = windows.len();
+                    windows.extend(window.iter().map(|&output| output as u32));
+                    start
+                });
+                self.gpu_window_ranges
+                    .push([start as u32, self.window.len() as u32]);
+
+                let mut outputs = Vec::with_capacity(self.negative_samples + 1);
+                outputs.push((output, true));
+                return outputs;
+            }
+        }
+
+        self.sample_outputs(model, output)
+    }
+
+    /// Sample the negatives of the training instances of a batch on the
+    /// GPU.
+    ///
+    /// The negatives are appended to the positive output of each
+    /// instance. Negatives in the window of the focus of an instance are
+    /// rejected as in `negative_sample`, except that rejections of the
+    /// positive output are also bounded by the maximum number of
+    /// rejections. Does nothing when negatives are sampled on the CPU.
+    #[cfg(feature = "gpu")]
+    fn sample_batch_negatives(&mut self, outputs: &mut [Vec<(usize, bool)>]) {
+        // The windows of the batch are not used after sampling. The
+        // window of the current focus is recorded again for its
+        // remaining instances.
+        let windows = mem::take(&mut self.gpu_windows);
+        let window_ranges = mem::take(&mut self.gpu_window_ranges);
+        self.gpu_window_start = None;
+
+        let gpu = match &self.gpu {
+            Some(gpu) if self.negative_samples != 0 => gpu,
+            _ => return,
+        };
+
+        let positives = outputs
+            .iter()
+            .map(|instance_outputs| instance_outputs[0].0 as u32)
+            .collect::<Vec<_>>();
+        let negatives = gpu.sample_negatives(
+            &positives,
+            &windows,
+            &window_ranges,
+            self.negative_samples,
+            self.gpu_seed,
+        );
+        self.gpu_seed = splitmix64(self.gpu_seed);
+
+        for (instance_outputs, instance_negatives) in outputs
+            .iter_mut()
+            .zip(negatives.chunks(self.negative_samples))
+        {
+            instance_outputs.extend(
+                instance_negatives
+                    .iter()
+                    .map(|&negative| (negative as usize, false)),
+            );
+        }
+    }
+
+    /// Compute the inner products of pairs of rows of `a` and `b`.
+    ///
+    /// Inner product *i* is the inner product of row `pairs[i][0]` of `a`
+    /// and row `pairs[i][1]` of `b`. The inner products are computed on
+    /// the GPU if one is set.
+    fn dot_pairs(&self, a: ArrayView2<f32>, b: ArrayView2<f32>, pairs: &[[usize; 2]]) -> Vec<f32> {
+        #[cfg(feature = "gpu")]
+        {
+            if let Some(gpu) = &self.gpu {
+                let pairs = pairs
+                    .iter()
+                    .map(|&[row_a, row_b]| [row_a as u32, row_b as u32])
+                    .collect::<Vec<_>>();
+                return gpu.dot_pairs(a, b, &pairs);
+            }
+        }
+
+        pairs
+            .iter()
+            .map(|&[row_a, row_b]| dot(a.row(row_a), b.row(row_b)))
+            .collect()
+    }
+
+    /// Compute sums of scaled rows of `b`.
+    ///
+    /// Row *r* of the result is the sum of the rows `sources[e]` of `b`
+    /// scaled by `coeffs[e]`, for *e* in `offsets[r]..offsets[r + 1]`.
+    /// The sums are computed on the GPU if one is set.
+    fn sum_scaled_rows(
+        &self,
+        b: ArrayView2<f32>,
+        offsets: &[usize],
+        sources: &[usize],
+        coeffs: &[f32],
+    ) -> Array2<f32> {
+        #[cfg(feature = "gpu")]
+        {
+            if let Some(gpu) = &self.gpu {
+                let to_u32 =
+                    |indices: &[usize]| indices.iter().map(|&idx| idx as u32).collect::<Vec<_>>();
+                return gpu.scaled_add(b, &to_u32(offsets), &to_u32(sources), coeffs);
+            }
+        }
+
+        let mut sums = Array2::zeros((offsets.len() - 1, b.ncols()));
+        for (mut sum, range) in sums.outer_iter_mut().zip(offsets.windows(2)) {
+            for (&source, &coeff) in sources[range[0]..range[1]]
+                .iter()
+                .zip(&coeffs[range[0]..range[1]])
+            {
+                scaled_add(sum.view_mut(), b.row(source), coeff);
+            }
+        }
+        sums
+    }
+
+    /// Draw a negative sample for `output`.
+    ///
+    /// The trainer rejects `output` itself. Negatives that are outputs of
+    /// the focus are redrawn at most `MAX_NEGATIVE_REJECTIONS` times.
+    fn negative_sample<T>(&self, model: &mut TrainModel<T>, output: usize) -> usize
+    where
+        T: NegativeSamples,
+    {
+        let mut negative = model.trainer().negative_sample(output);
+        for _ in 0..MAX_NEGATIVE_REJECTIONS {
+            if self.window.binary_search(&negative).is_err() {
+                break;
+            }
+            negative = model.trainer().negative_sample(output);
+        }
+        negative
+    }
+
+    /// Get the score of the positive output for the margin ranking loss.
+    ///
+    /// Returns zero for other losses, since they do not use the score.
+    fn positive_score(&self, input_embed: ArrayView1<f32>, output_embed: ArrayView1<f32>) -> f32 {
+        match self.loss {
+            LossType::MarginRanking => dot(input_embed, output_embed),
+            _ => 0.0,
+        }
+    }
+
+    /// Set the partial gradient of the positive output.
+    ///
+    /// In margin ranking, the positive output is pushed up by every
+    /// negative that violates the margin. So, the partial gradient of the
+    /// positive output is the negated sum of the partial gradients of the
+    /// negatives. This gradient is stored as the first partial gradient.
+    /// Other losses already computed the partial gradient of their
+    /// positive output.
+    fn rank_positive(&self, part_gradients: &mut [f32]) {
+        if let LossType::MarginRanking = self.loss {
+            part_gradients[0] = -part_gradients[1..].iter().sum::<f32>();
+        }
+    }
+
+    /// Get the factor by which the gradient of a training instance is
+    /// scaled to clip its norm.
+    ///
+    /// The gradient of a training instance consists of the gradient of
+    /// the input embedding (`input_delta`) and the gradients of the output
+    /// embeddings, which are the input embedding scaled by
+    /// `part_gradients`.
+    fn global_clip_factor(
+        &self,
+        input_embed: ArrayView1<f32>,
+        input_delta: ArrayView1<f32>,
+        part_gradients: &[f32],
+    ) -> f32 {
+        let max_norm = match self.clip_norm {
+            Some(max_norm) if !self.clip_per_row => max_norm,
+            _ => return 1.0,
+        };
+
+        let part_gradients_sq = part_gradients.iter().map(|g| g * g).sum::<f32>();
+        let norm = (part_gradients_sq * input_embed.dot(&input_embed)
+            + input_delta.dot(&input_delta))
+        .sqrt();
+
+        clip_factor(norm, max_norm)
+    }
+
+    /// Get the factor by which the gradient `scale * grad` of a row is
+    /// scaled to clip its norm.
+    fn row_clip_factor(&self, grad: ArrayView1<f32>, scale: f32) -> f32 {
+        match self.clip_norm {
+            Some(max_norm) if self.clip_per_row => {
+                clip_factor(scale.abs() * grad.dot(&grad).sqrt(), max_norm)
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// Update an input embedding with the given gradient.
+    pub fn update_input_embedding<T>(
+        &mut self,
+        model: &mut TrainModel<T>,
+        idx: usize,
+        grad: ArrayView1<f32>,
+        lr: f32,
+    ) {
+        let weight_decay = self.weight_decay;
+        let scale = self.row_clip_factor(grad, 1.0);
+        let optimizer = &mut self.input_optimizer;
+        model.update_input_embedding(idx, |mut embed| {
+            decay(embed.view_mut(), weight_decay, lr);
+            optimizer.update(embed, idx, grad, scale, lr);
+        });
+    }
+
+    /// Update an output embedding with the gradient `scale * grad`.
+    fn update_output_embedding<T>(
+        &mut self,
+        model: &mut TrainModel<T>,
+        idx: usize,
+        grad: ArrayView1<f32>,
+        scale: f32,
+        lr: f32,
+    ) {
+        let weight_decay = self.weight_decay;
+        let scale = scale * self.row_clip_factor(grad, scale);
+        let optimizer = &mut self.output_optimizer;
+        model.update_output_embedding(idx, |mut embed| {
+            decay(embed.view_mut(), weight_decay, lr);
+            optimizer.update(embed, idx, grad, scale, lr);
+        });
+    }
+}
+
+/// Apply weight decay to an embedding that is about to be updated.
+///
+/// The decay is decoupled from the gradient (Loshchilov and Hutter,
+/// 2019), so that it does not interact with adaptive optimizers.
+fn decay(embed: ArrayViewMut1<f32>, weight_decay: f32, lr: f32) {
+    if weight_decay > 0. {
+        scale(embed, 1. - lr * weight_decay);
+    }
+}
+
+/// Get the next seed of a sequence of seeds.
+///
+/// This is the SplitMix64 generator (Steele et al., 2014), which maps
+/// nearby seeds to unrelated seeds.
+#[cfg(feature = "gpu")]
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Get the factor by which a gradient with norm `norm` is scaled to clip
+/// its norm to `max_norm`.
+fn clip_factor(norm: f32, max_norm: f32) -> f32 {
+    if norm > max_norm {
+        max_norm / norm
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::{clip_factor, NegativeSamplingSgd, Sgd, TrainBatch};
+    use crate::loss::log_logistic_loss;
+    use crate::optimizer::PlainSgd;
+    use crate::train_model::{TrainModel, Trainer};
+    use crate::util::{all_close, array_all_close, close};
+    use crate::{
+        CommonConfig, Cutoff, LossType, ModelType, SimpleVocab, SimpleVocabConfig, SkipGramConfig,
+        SkipgramTrainer, VocabBuilder,
+    };
+
+    const TEST_COMMON_CONFIG: CommonConfig = CommonConfig {
+        negative_samples: 0,
+        seed: Some(42),
+        ..CommonConfig::for_test()
+    };
+
+    const TEST_SKIP_CONFIG: SkipGramConfig = SkipGramConfig {
+        context_size: 5,
+        model: ModelType::SkipGram,
+        harmonic_weighting: false,
+        context_cutoff: None,
+        word_ngrams: 1,
+    };
+
+    #[test]
+    fn batch_output_step_sums_gradients() {
+        let mut builder: VocabBuilder<SimpleVocabConfig, String> =
+            VocabBuilder::new(SimpleVocabConfig {
+                discard_threshold: 1e-4,
+                cutoff: Cutoff::MinCount(1),
+            });
+        for word in &["a", "a", "a", "b", "b", "c"] {
+            builder.count(word.to_string());
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        let mut model: TrainModel<_> = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            TEST_SKIP_CONFIG,
+        )
+        .into();
+
+        // Without negatives, the outputs of the instances are known.
+        let mut input_embeds = Array2::zeros((2, 3));
+        input_embeds.row_mut(0).assign(&model.input_embedding(0));
+        input_embeds.row_mut(1).assign(&model.input_embedding(1));
+        let output_embed = model.output_embedding(2).into_owned();
+        let (loss0, grad0) = log_logistic_loss(input_embeds.row(0), output_embed.view(), true);
+        let (loss1, grad1) = log_logistic_loss(input_embeds.row(1), output_embed.view(), true);
+
+        let mut sgd_impl = NegativeSamplingSgd::new(&TEST_COMMON_CONFIG, PlainSgd, PlainSgd);
+        let (loss, input_deltas) = sgd_impl.batch_output_step(
+            &mut model,
+            input_embeds.view(),
+            &[vec![(2, true)], vec![(2, true)]],
+            &[0.5, 0.25],
+        );
+
+        assert!(close(loss, loss0 + loss1, 1e-5));
+        assert!(all_close(
+            input_deltas.row(0).as_slice().unwrap(),
+            (&output_embed * grad0).as_slice().unwrap(),
+            1e-5
+        ));
+        assert!(all_close(
+            input_deltas.row(1).as_slice().unwrap(),
+            (&output_embed * grad1).as_slice().unwrap(),
+            1e-5
+        ));
+
+        // The output is updated once with the sum of the gradients,
+        // weighted by the learning rates of the instances.
+        let expected = &output_embed
+            + &(&input_embeds.row(0) * (0.5 * grad0))
+            + &input_embeds.row(1) * (0.25 * grad1);
+        assert!(all_close(
+            model.output_embedding(2).as_slice().unwrap(),
+            expected.as_slice().unwrap(),
+            1e-5
+        ));
+    }
+
+    fn test_sentence() -> Vec<String> {
+        ["a", "b", "a", "c", "b", "a"]
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    fn test_sgd(config: CommonConfig) -> Sgd<SkipgramTrainer<XorShiftRng, SimpleVocab<String>>> {
+        let mut builder: VocabBuilder<SimpleVocabConfig, String> =
+            VocabBuilder::new(SimpleVocabConfig {
+                discard_threshold: 1.,
+                cutoff: Cutoff::MinCount(1),
+            });
+        for word in test_sentence() {
+            builder.count(word);
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        Sgd::new(
+            SkipgramTrainer::new(
+                vocab,
+                XorShiftRng::seed_from_u64(42),
+                config,
+                TEST_SKIP_CONFIG,
+            )
+            .into(),
+        )
+    }
+
+    #[test]
+    fn batch_size_one_matches_unbatched_updates() {
+        let config = CommonConfig {
+            negative_samples: 2,
+            ..TEST_COMMON_CONFIG
+        };
+        let sentence = test_sentence();
+
+        let mut unbatched = test_sgd(config);
+        let mut batched = test_sgd(config);
+        for _ in 0..2 {
+            unbatched.update_sentence(sentence.as_slice(), 0.05);
+            batched.update_sentence_batched(sentence.as_slice(), 0.05);
+        }
+        batched.flush_batch();
+
+        assert!(close(batched.train_loss(), unbatched.train_loss(), 1e-5));
+        let (batched_input, batched_output) = batched.model().to_arrays();
+        let (unbatched_input, unbatched_output) = unbatched.model().to_arrays();
+        assert!(array_all_close(
+            batched_input.view(),
+            unbatched_input.view(),
+            1e-5
+        ));
+        assert!(array_all_close(
+            batched_output.view(),
+            unbatched_output.view(),
+            1e-5
+        ));
+    }
+
+    #[test]
+    fn batches_span_sentences() {
+        let sentence = test_sentence();
+        let mut sgd = test_sgd(TEST_COMMON_CONFIG);
+        sgd.set_batch_size(100);
+        let initial = sgd.model().to_arrays();
+
+        // The instances of both sentences fit in one batch.
+        sgd.update_sentence(sentence.as_slice(), 0.05);
+        sgd.update_sentence(sentence.as_slice(), 0.05);
+        assert_eq!(sgd.model().to_arrays(), initial);
+        assert_eq!(sgd.n_tokens_processed(), 2 * sentence.len());
+
+        sgd.flush_batch();
+        assert_ne!(sgd.model().to_arrays(), initial);
+        assert!(sgd.train_loss().is_finite());
+    }
+
+    #[test]
+    fn batches_apply_learning_rates_per_sentence() {
+        let sentence = test_sentence();
+        let reversed = sentence.iter().rev().cloned().collect::<Vec<_>>();
+
+        // All gradients of a batch are computed with the initial
+        // parameters, so with plain SGD the update of a batch is the sum
+        // of the updates of its sentences. A learning rate of zero
+        // removes a sentence without changing the random draws.
+        let update = |lrs: [f32; 2]| {
+            let mut sgd = test_sgd(TEST_COMMON_CONFIG);
+            sgd.set_batch_size(100);
+            sgd.update_sentence(sentence.as_slice(), lrs[0]);
+            sgd.update_sentence(reversed.as_slice(), lrs[1]);
+            sgd.flush_batch();
+            let (input, output) = sgd.model().to_arrays();
+            let (initial_input, initial_output) = test_sgd(TEST_COMMON_CONFIG).model().to_arrays();
+            (input - initial_input, output - initial_output)
+        };
+
+        let (both_input, both_output) = update([0.05, 0.01]);
+        let (first_input, first_output) = update([0.05, 0.]);
+        let (second_input, second_output) = update([0., 0.01]);
+        assert!(array_all_close(
+            both_input.view(),
+            (first_input + second_input).view(),
+            1e-6
+        ));
+        assert!(array_all_close(
+            both_output.view(),
+            (first_output + second_output).view(),
+            1e-6
+        ));
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn gpu_batches_match_cpu_batches() {
+        let sentence = test_sentence();
+        let mut cpu = test_sgd(TEST_COMMON_CONFIG);
+        let mut gpu = test_sgd(TEST_COMMON_CONFIG);
+
+        // The test is skipped when there is no GPU.
+        if gpu.use_gpu().is_err() {
+            return;
+        }
+
+        cpu.set_batch_size(4);
+        gpu.set_batch_size(4);
+        for _ in 0..2 {
+            cpu.update_sentence(sentence.as_slice(), 0.05);
+            gpu.update_sentence(sentence.as_slice(), 0.05);
+        }
+        cpu.flush_batch();
+        gpu.flush_batch();
+
+        assert!(close(gpu.train_loss(), cpu.train_loss(), 1e-5));
+        let (gpu_input, gpu_output) = gpu.model().to_arrays();
+        let (cpu_input, cpu_output) = cpu.model().to_arrays();
+        assert!(array_all_close(gpu_input.view(), cpu_input.view(), 1e-5));
+        assert!(array_all_close(gpu_output.view(), cpu_output.view(), 1e-5));
+    }
+
+    #[test]
+    fn instances_are_collected_for_batch_negatives() {
+        let sentence = test_sentence();
+
+        let mut streamed = test_sgd(TEST_COMMON_CONFIG);
+        let streamed_batch = streamed.train_batch(sentence.as_slice());
+        assert!(matches!(streamed_batch, TrainBatch::Streamed(_)));
+
+        let mut collected = test_sgd(CommonConfig {
+            loss: LossType::InfoNce,
+            batch_negatives: 2,
+            ..TEST_COMMON_CONFIG
+        });
+        let collected_batch = collected.train_batch(sentence.as_slice());
+        assert!(matches!(collected_batch, TrainBatch::Collected(_)));
+
+        assert_eq!(
+            streamed_batch
+                .map(|(_, contexts)| contexts)
+                .collect::<Vec<_>>(),
+            collected_batch
+                .map(|(_, contexts)| contexts)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn nce_noise_matches_negative_distribution() {
+        const DRAWS: usize = 20_000;
+
+        let config = CommonConfig {
+            loss: LossType::Nce,
+            negative_samples: 2,
+            ..TEST_COMMON_CONFIG
+        };
+        let mut sgd = test_sgd(config);
+        let n_outputs = sgd.model.trainer().n_output_types();
+
+        // Negatives are never the positive, so the noise distribution
+        // of an instance is renormalized over the other outputs.
+        let positive = 0;
+        let mut hits = vec![0; n_outputs];
+        for _ in 0..DRAWS {
+            hits[sgd.sgd_impl.negative_sample(&mut sgd.model, positive)] += 1;
+        }
+        assert_eq!(hits[positive], 0);
+
+        for output in 1..n_outputs {
+            let noise_prob = sgd
+                .sgd_impl
+                .nce_log_noise(&mut sgd.model, output, positive)
+                .exp()
+                / 2.;
+            assert!(close(hits[output] as f32 / DRAWS as f32, noise_prob, 1e-2));
+        }
+    }
+
+    #[test]
+    fn sentence_loss_is_seeded_and_keeps_trainer_state() {
+        let config = CommonConfig {
+            negative_samples: 2,
+            ..TEST_COMMON_CONFIG
+        };
+        let sentence = test_sentence();
+
+        let mut sgd = test_sgd(config);
+        let (loss, n_examples) = sgd.sentence_loss(sentence.as_slice(), 7);
+        assert!(n_examples > 0);
+        assert_eq!(
+            sgd.sentence_loss(sentence.as_slice(), 7),
+            (loss, n_examples)
+        );
+
+        // Computing the loss does not advance the random number
+        // generators of the trainer.
+        let mut reference = test_sgd(config);
+        sgd.update_sentence(sentence.as_slice(), 0.05);
+        reference.update_sentence(sentence.as_slice(), 0.05);
+        assert_eq!(sgd.train_loss(), reference.train_loss());
+        assert_eq!(sgd.model().to_arrays(), reference.model().to_arrays());
+    }
+
+    #[test]
+    fn clip_factor_test() {
+        assert!(close(clip_factor(0.5, 1.0), 1.0, 1e-6));
+        assert!(close(clip_factor(1.0, 1.0), 1.0, 1e-6));
+        assert!(close(clip_factor(4.0, 1.0), 0.25, 1e-6));
+    }
+}
@@ -1,17 +1,23 @@
 use std::borrow::Borrow;
 use std::hash::Hash;
 use std::iter::FusedIterator;
+use std::mem;
 use std::sync::Arc;
-use std::{cmp, mem};
 
 use anyhow::{bail, Result};
-use rand::{Rng, SeedableRng};
+use rand::{Rng, RngCore, SeedableRng};
 use serde::Serialize;
 
 use crate::idx::WordIdx;
-use crate::sampling::{BandedRangeGenerator, ZipfRangeGenerator};
-use crate::train_model::{NegativeSamples, TrainIterFrom, Trainer};
-use crate::util::ReseedOnCloneRng;
+use crate::sampling::{
+    context_window, sample_context_window, BandedRangeGenerator, NegativeSamplingRangeGenerator,
+    RangeGenerator,
+};
+use crate::train_model::{
+    positional_label, NegativeSamples, OutputLabels, Subsampling, TrainIterFrom, Trainer,
+};
+use crate::util::{Reseed, ReseedOnCloneRng};
+use crate::vocab::word_ngram_indices_at;
 use crate::{CommonConfig, ModelType, SkipGramConfig, Vocab};
 
 /// Skipgram Trainer
@@ -19,13 +25,20 @@ use crate::{CommonConfig, ModelType, SkipGramConfig, Vocab};
 /// The `SkipgramTrainer` holds the information and logic necessary to transform a tokenized
 /// sentence into an iterator of focus and context tuples. The struct is cheap to clone because
 /// the vocabulary is shared between clones.
+///
+/// The contexts are the words of the vocabulary that are retained by the
+/// context cutoff of the skip-gram configuration. Since the words of the
+/// vocabulary are sorted by frequency, the contexts are the `n_contexts`
+/// most frequent words.
 #[derive(Clone)]
 pub struct SkipgramTrainer<R, V> {
     vocab: Arc<V>,
+    n_contexts: usize,
     rng: R,
-    range_gen: BandedRangeGenerator<R, ZipfRangeGenerator<R>>,
+    range_gen: BandedRangeGenerator<R, NegativeSamplingRangeGenerator<R>>,
     common_config: CommonConfig,
     skipgram_config: SkipGramConfig,
+    subsampling: bool,
 }
 
 impl<R, V> SkipgramTrainer<ReseedOnCloneRng<R>, V>
@@ -41,35 +54,44 @@ where
         skipgram_config: SkipGramConfig,
     ) -> Self {
         let vocab = Arc::new(vocab);
-        let rng = ReseedOnCloneRng(rng);
+        let n_contexts = skipgram_config
+            .context_cutoff
+            .map(|cutoff| cutoff.n_retained(vocab.types()))
+            .unwrap_or_else(|| vocab.len());
+        let rng = ReseedOnCloneRng::new(rng);
         let band_size = match skipgram_config.model {
-            ModelType::SkipGram => 1,
+            ModelType::SkipGram
+            | ModelType::Cbow
+            | ModelType::PositionWeighted
+            | ModelType::ConcatenatedWindow => 1,
             ModelType::StructuredSkipGram => skipgram_config.context_size * 2,
             ModelType::DirectionalSkipgram => 2,
         };
 
         let range_gen = BandedRangeGenerator::new(
             rng.clone(),
-            ZipfRangeGenerator::new_with_exponent(
+            NegativeSamplingRangeGenerator::new(
                 rng.clone(),
-                vocab.len(),
-                common_config.zipf_exponent,
+                vocab.types()[..n_contexts].iter().map(|word| word.count()),
+                &common_config,
             ),
             band_size as usize,
         );
         SkipgramTrainer {
             vocab,
+            n_contexts,
             rng,
             range_gen,
             common_config,
             skipgram_config,
+            subsampling: true,
         }
     }
 }
 
 impl<'a, S, R, V, I> TrainIterFrom<'a, [S]> for SkipgramTrainer<R, V>
 where
-    S: Hash + Eq,
+    S: Hash + Eq + AsRef<str>,
     R: Rng + Clone,
     V: Vocab<IdxType = I>,
     V::VocabType: Borrow<S>,
@@ -81,14 +103,25 @@ where
 
     fn train_iter_from(&mut self, sequence: &[S]) -> Self::Iter {
         let mut ids = Vec::new();
-        for t in sequence {
-            if let Some(idx) = self.vocab.idx(t) {
-                if self.rng.gen_range(0f32..1f32) < self.vocab.discard(idx.word_idx() as usize) {
+        for (i, t) in sequence.iter().enumerate() {
+            if let Some(mut idx) = self.vocab.idx(t) {
+                if !self.subsampling
+                    || self.rng.gen_range(0f32..1f32) < self.vocab.discard(idx.word_idx() as usize)
+                {
+                    idx.extend_subwords(word_ngram_indices_at(
+                        &*self.vocab,
+                        sequence,
+                        i,
+                        self.skipgram_config.word_ngrams as usize,
+                    ));
+                    if self.subsampling {
+                        idx.drop_subwords(&mut self.rng, self.common_config.subword_dropout);
+                    }
                     ids.push(idx);
                 }
             }
         }
-        SkipGramIter::new(self.rng.clone(), ids, self.skipgram_config)
+        SkipGramIter::new(self.rng.clone(), ids, self.n_contexts, self.skipgram_config)
     }
 }
 
@@ -104,6 +137,52 @@ where
             }
         }
     }
+
+    fn noise_prob(&self, output: usize) -> f32 {
+        self.range_gen.prob(output) as f32
+    }
+}
+
+impl<R, V> Reseed for SkipgramTrainer<R, V>
+where
+    R: Reseed + RngCore,
+{
+    fn reseed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+        self.range_gen.reseed(self.rng.next_u64());
+    }
+}
+
+impl<R, V> Subsampling for SkipgramTrainer<R, V> {
+    fn set_subsampling(&mut self, subsampling: bool) {
+        self.subsampling = subsampling;
+    }
+}
+
+impl<R, V> OutputLabels for SkipgramTrainer<R, V>
+where
+    V: Vocab,
+    V::VocabType: ToString,
+{
+    fn output_label(&self, output: usize) -> String {
+        let context_size = self.skipgram_config.context_size as usize;
+        let word = |idx: usize| self.vocab.types()[idx].label().to_string();
+        match self.skipgram_config.model {
+            ModelType::StructuredSkipGram => positional_label(
+                &word(output / (2 * context_size)),
+                output % (2 * context_size),
+                context_size,
+            ),
+            ModelType::DirectionalSkipgram if output % 2 == 0 => {
+                format!("{}@left", word(output / 2))
+            }
+            ModelType::DirectionalSkipgram => format!("{}@right", word(output / 2)),
+            ModelType::SkipGram
+            | ModelType::Cbow
+            | ModelType::PositionWeighted
+            | ModelType::ConcatenatedWindow => word(output),
+        }
+    }
 }
 
 impl<R, V> Trainer for SkipgramTrainer<R, V>
@@ -133,10 +212,13 @@ where
     fn n_output_types(&self) -> usize {
         match self.skipgram_config.model {
             ModelType::StructuredSkipGram => {
-                self.vocab.len() * 2 * self.skipgram_config.context_size as usize
+                self.n_contexts * 2 * self.skipgram_config.context_size as usize
             }
-            ModelType::SkipGram => self.vocab.len(),
-            ModelType::DirectionalSkipgram => self.vocab.len() * 2,
+            ModelType::SkipGram
+            | ModelType::Cbow
+            | ModelType::PositionWeighted
+            | ModelType::ConcatenatedWindow => self.n_contexts,
+            ModelType::DirectionalSkipgram => self.n_contexts * 2,
         }
     }
 
@@ -145,21 +227,23 @@ where
     }
 
     fn to_metadata(&self) -> SkipgramMetadata<V::Config> {
-        SkipgramMetadata {
-            common_config: self.common_config,
-            skipgram_config: self.skipgram_config,
-            vocab_config: self.vocab.config(),
-        }
+        SkipgramMetadata::new(
+            self.common_config,
+            self.skipgram_config,
+            self.vocab.config(),
+        )
     }
 }
 
 /// Iterator over focus identifier and associated context identifiers in a sentence.
 pub struct SkipGramIter<R, I> {
     ids: Vec<I>,
+    n_contexts: usize,
     rng: R,
     i: usize,
     model_type: ModelType,
     ctx_size: usize,
+    harmonic_weighting: bool,
 }
 
 impl<R, I> SkipGramIter<R, I>
@@ -170,13 +254,17 @@ where
     /// Constructs a new `SkipGramIter`.
     ///
     /// The `rng` is used to determine the window size for each focus token.
-    pub fn new(rng: R, ids: Vec<I>, skip_config: SkipGramConfig) -> Self {
+    /// Only tokens with a word index smaller than `n_contexts` are used as
+    /// contexts.
+    pub fn new(rng: R, ids: Vec<I>, n_contexts: usize, skip_config: SkipGramConfig) -> Self {
         SkipGramIter {
             ids,
+            n_contexts,
             rng,
             i: 0,
             model_type: skip_config.model,
             ctx_size: skip_config.context_size as usize,
+            harmonic_weighting: skip_config.harmonic_weighting,
         }
     }
 
@@ -191,7 +279,10 @@ where
 
                 (token * self.ctx_size * 2) + offset
             }
-            ModelType::SkipGram => token,
+            ModelType::SkipGram
+            | ModelType::Cbow
+            | ModelType::PositionWeighted
+            | ModelType::ConcatenatedWindow => token,
             ModelType::DirectionalSkipgram => {
                 let offset = if offset_idx < focus_idx { 0 } else { 1 };
 
@@ -210,17 +301,35 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.i < self.ids.len() {
-            // Bojanowski, et al., 2017 uniformly sample the context size between 1 and c.
-            let context_size = self.rng.gen_range(1..self.ctx_size + 1) as usize;
-            let left = self.i - cmp::min(self.i, context_size);
-            let right = cmp::min(self.i + context_size + 1, self.ids.len());
-            let contexts = (left..right)
-                .filter(|&idx| idx != self.i)
-                .map(|idx| self.output_(self.ids[idx].word_idx() as usize, self.i, idx))
-                .fold(Vec::with_capacity(right - left), |mut contexts, idx| {
-                    contexts.push(idx);
-                    contexts
-                });
+            // Harmonic weighting replaces the sampled window size.
+            let window = if self.harmonic_weighting {
+                context_window(self.i, self.ids.len(), self.ctx_size)
+            } else {
+                sample_context_window(&mut self.rng, self.i, self.ids.len(), self.ctx_size)
+            };
+            let mut contexts = Vec::with_capacity(window.len());
+            for idx in window {
+                if idx == self.i {
+                    continue;
+                }
+
+                // Use a context at distance d with probability 1/d.
+                if self.harmonic_weighting {
+                    let distance = if idx < self.i {
+                        self.i - idx
+                    } else {
+                        idx - self.i
+                    };
+                    if distance > 1 && !self.rng.gen_bool(1.0 / distance as f64) {
+                        continue;
+                    }
+                }
+
+                let context = self.ids[idx].word_idx() as usize;
+                if context < self.n_contexts {
+                    contexts.push(self.output_(context, self.i, idx));
+                }
+            }
 
             // swap the representation possibly containing multiple indices with one that only
             // contains the distinct word index since we need the word index for context lookups.
@@ -248,3 +357,97 @@ pub struct SkipgramMetadata<V> {
     skipgram_config: SkipGramConfig,
     vocab_config: V,
 }
+
+impl<V> SkipgramMetadata<V> {
+    /// Construct new metadata for a skipgram-like model.
+    pub(crate) fn new(
+        common_config: CommonConfig,
+        skipgram_config: SkipGramConfig,
+        vocab_config: V,
+    ) -> Self {
+        SkipgramMetadata {
+            common_config,
+            skipgram_config,
+            vocab_config,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::SkipGramIter;
+    use crate::idx::{SingleIdx, WordIdx};
+    use crate::util::all_close;
+    use crate::{ModelType, SkipGramConfig};
+
+    #[test]
+    fn harmonic_weighting_test() {
+        const LEN: usize = 20_000;
+
+        let config = SkipGramConfig {
+            context_size: 3,
+            model: ModelType::SkipGram,
+            harmonic_weighting: true,
+            context_cutoff: None,
+            word_ngrams: 1,
+        };
+
+        // The output of a word is its position in the sentence.
+        let ids = (0..LEN as u64).map(SingleIdx::from_word_idx).collect();
+        let iter = SkipGramIter::new(XorShiftRng::seed_from_u64(42), ids, LEN, config);
+
+        // Count the contexts at each distance.
+        let mut hits = vec![0; 3];
+        for (focus, contexts) in iter {
+            let focus = focus.word_idx() as usize;
+            if focus < 3 || focus >= LEN - 3 {
+                continue;
+            }
+
+            for context in contexts {
+                let distance = if context < focus {
+                    focus - context
+                } else {
+                    context - focus
+                };
+                hits[distance - 1] += 1;
+            }
+        }
+
+        // Each distance occurs twice per focus word.
+        let probs = hits
+            .into_iter()
+            .map(|count| count as f32 / (2 * (LEN - 6)) as f32)
+            .collect::<Vec<_>>();
+        assert!(all_close(&[1.0, 0.5, 1. / 3.], &probs, 1e-2));
+    }
+
+    #[test]
+    fn contexts_are_restricted_to_context_vocab() {
+        let config = SkipGramConfig {
+            context_size: 2,
+            model: ModelType::SkipGram,
+            harmonic_weighting: true,
+            context_cutoff: None,
+            word_ngrams: 1,
+        };
+
+        let ids = vec![0, 3, 1, 4, 2]
+            .into_iter()
+            .map(SingleIdx::from_word_idx)
+            .collect();
+        let iter = SkipGramIter::new(XorShiftRng::seed_from_u64(42), ids, 2, config);
+
+        // Every token is a focus word, but only words 0 and 1 are contexts.
+        let foci = iter
+            .map(|(focus, contexts)| {
+                assert!(contexts.iter().all(|&context| context < 2));
+                focus.word_idx()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(foci, vec![0, 3, 1, 4, 2]);
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::Write;
+
+use anyhow::{ensure, Result};
+use ndarray::ArrayView2;
+
+#[cfg(feature = "sqlite-output")]
+use anyhow::Context;
+#[cfg(feature = "sqlite-output")]
+use rusqlite::{params, Connection};
+#[cfg(feature = "sqlite-output")]
+use std::fs::File;
+#[cfg(feature = "sqlite-output")]
+use std::io;
+#[cfg(feature = "sqlite-output")]
+use tempfile::NamedTempFile;
+
+/// Write word embeddings as a pgvector `COPY` dump.
+///
+/// Every line contains a word and its embedding, separated by a tab.
+/// Embeddings are written in pgvector's text representation, e.g.
+/// `[0.1,0.2]`. Words are escaped as required by the text format of
+/// PostgreSQL's `COPY`, so that the dump can be loaded into a table with
+/// a `text` and a `vector` column.
+pub(crate) fn write_pgvector_copy<W>(
+    write: &mut W,
+    words: &[String],
+    embeds: ArrayView2<f32>,
+) -> Result<()>
+where
+    W: Write,
+{
+    ensure!(
+        words.len() == embeds.nrows(),
+        "Number of words ({}) and embeddings ({}) differ",
+        words.len(),
+        embeds.nrows()
+    );
+
+    for (word, embed) in words.iter().zip(embeds.outer_iter()) {
+        write!(write, "{}\t[", copy_escape(word))?;
+        for (idx, v) in embed.iter().enumerate() {
+            if idx != 0 {
+                write!(write, ",")?;
+            }
+            write!(write, "{}", v)?;
+        }
+        writeln!(write, "]")?;
+    }
+
+    Ok(())
+}
+
+/// Escape a value for the text format of PostgreSQL's `COPY`.
+fn copy_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Write word embeddings as an SQLite database.
+///
+/// The database has an `embeddings` table with the columns `word` and
+/// `embedding`. The embedding is stored as a blob of little-endian single
+/// precision floats. The database is constructed in a temporary file,
+/// since SQLite cannot write to arbitrary writers, and copied to `write`
+/// afterwards.
+#[cfg(feature = "sqlite-output")]
+pub(crate) fn write_sqlite<W>(
+    write: &mut W,
+    words: &[String],
+    embeds: ArrayView2<f32>,
+) -> Result<()>
+where
+    W: Write,
+{
+    ensure!(
+        words.len() == embeds.nrows(),
+        "Number of words ({}) and embeddings ({}) differ",
+        words.len(),
+        embeds.nrows()
+    );
+
+    let db_file = NamedTempFile::new().context("Cannot create temporary SQLite database")?;
+    let mut db = Connection::open(db_file.path())?;
+    db.execute(
+        "CREATE TABLE embeddings (word TEXT PRIMARY KEY, embedding BLOB NOT NULL)",
+        [],
+    )?;
+
+    let transaction = db.transaction()?;
+    {
+        let mut insert =
+            transaction.prepare("INSERT INTO embeddings (word, embedding) VALUES (?, ?)")?;
+        for (word, embed) in words.iter().zip(embeds.outer_iter()) {
+            let blob = embed
+                .iter()
+                .flat_map(|v| v.to_le_bytes().to_vec())
+                .collect::<Vec<_>>();
+            insert.execute(params![word, blob])?;
+        }
+    }
+    transaction.commit()?;
+
+    db.close().map_err(|(_, err)| err)?;
+
+    io::copy(&mut File::open(db_file.path())?, write)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::write_pgvector_copy;
+
+    #[test]
+    fn write_pgvector_copy_test() {
+        let words = vec!["a\tb".to_string(), "c\\".to_string()];
+        let embeds = array![[1f32, -0.5], [0., 2.]];
+
+        let mut data = Vec::new();
+        write_pgvector_copy(&mut data, &words, embeds.view()).unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "a\\tb\t[1,-0.5]\nc\\\\\t[0,2]\n"
+        );
+    }
+
+    #[cfg(feature = "sqlite-output")]
+    #[test]
+    fn write_sqlite_test() {
+        use std::fs::File;
+
+        use rusqlite::Connection;
+        use tempfile::NamedTempFile;
+
+        use super::write_sqlite;
+
+        let words = vec!["a".to_string()];
+        let embeds = array![[1f32, -0.5]];
+
+        let db_file = NamedTempFile::new().unwrap();
+        write_sqlite(
+            &mut File::create(db_file.path()).unwrap(),
+            &words,
+            embeds.view(),
+        )
+        .unwrap();
+
+        let db = Connection::open(db_file.path()).unwrap();
+        let embedding: Vec<u8> = db
+            .query_row(
+                "SELECT embedding FROM embeddings WHERE word = 'a'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let mut expected = 1f32.to_le_bytes().to_vec();
+        expected.extend_from_slice(&(-0.5f32).to_le_bytes());
+        assert_eq!(embedding, expected);
+    }
+}
@@ -1,15 +1,293 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::sync::Arc;
+
 use anyhow::{Context, Result};
 use clap::ArgMatches;
 
-use finalfrontier::{BucketConfig, Cutoff, NGramConfig, SimpleVocabConfig, SubwordVocabConfig};
+use finalfrontier::io::{CorpusFormat, Tokenizer};
+use finalfrontier::{
+    read_bpe_merges, write_subword_counts, BpeConfig, BucketConfig, Cutoff, Dedup,
+    FrequencySplitter, LanguageFilter, MultiwordLexicon, NGramConfig, NoiseFilters,
+    SentencePieceConfig, SentencePieceModel, SimpleVocab, SimpleVocabConfig, SubwordVocab,
+    SubwordVocabConfig, TokenNormalization, TokenRules, Vocab,
+};
+use finalfusion::subword::{ExplicitIndexer, Indexer};
+
+/// The number of buckets with the most collisions that are reported in
+/// bucket statistics.
+const N_WORST_COLLISIONS: usize = 10;
 
-#[derive(Copy, Clone)]
+/// The type to which unknown tokens are mapped.
+const UNK: &str = "<unk>";
+
+#[derive(Clone)]
 pub enum VocabConfig {
     SubwordVocab(SubwordVocabConfig<BucketConfig>),
     NGramVocab(SubwordVocabConfig<NGramConfig>),
+    /// Byte pair encoding vocab, with an optional merges file.
+    BpeVocab(SubwordVocabConfig<BpeConfig>, Option<String>),
+    /// SentencePiece vocab, with the path of the SentencePiece model.
+    SentencePieceVocab(SubwordVocabConfig<SentencePieceConfig>, String),
     SimpleVocab(SimpleVocabConfig),
 }
 
+/// Options for building a vocabulary from a corpus.
+#[derive(Clone, Debug)]
+pub struct VocabOptions {
+    /// Words that are not counted.
+    pub stopwords: HashSet<String>,
+
+    /// Fixed vocabulary, the vocabulary is selected by frequency if absent.
+    pub vocab_words: Option<HashSet<String>>,
+
+    /// Maximum number of counted types, all types are counted if absent.
+    pub max_types: Option<usize>,
+
+    /// Maximum memory use of the counts in bytes, the memory use is not
+    /// bounded if absent.
+    pub max_memory: Option<usize>,
+
+    /// Normalization that is applied to corpus tokens.
+    pub normalization: TokenNormalization,
+
+    /// Minimum token length in characters.
+    pub min_token_len: Option<usize>,
+
+    /// Maximum token length in characters.
+    pub max_token_len: Option<usize>,
+
+    /// Rules for filtering and rewriting tokens, applied after
+    /// normalization.
+    pub rules: Arc<TokenRules>,
+
+    /// Multi-word expressions that are merged into single tokens,
+    /// applied after the token rules.
+    pub multiword: Arc<MultiwordLexicon>,
+
+    /// File to read the vocabulary counts from, the corpus is counted
+    /// if absent.
+    pub vocab_in: Option<String>,
+
+    /// File to write the vocabulary counts to.
+    pub vocab_out: Option<String>,
+
+    /// File to write the ngrams of an ngram vocabulary to.
+    pub ngrams_out: Option<String>,
+
+    /// Compute statistics of the mapping of ngrams to buckets.
+    pub bucket_statistics: bool,
+
+    /// Map tokens that are not in the vocabulary to a trainable unknown
+    /// word type.
+    pub unk: bool,
+
+    /// Number of subword units to retain, all units are retained if
+    /// absent.
+    pub prune_subwords: Option<usize>,
+
+    /// Extract the ngrams of compounds from their constituents.
+    pub split_compounds: bool,
+
+    /// Format of the corpus.
+    pub corpus_format: CorpusFormat,
+
+    /// Field with the text of JSON Lines records.
+    pub text_field: String,
+
+    /// Read sentence weights from the corpus.
+    ///
+    /// Weights are not used for counting, but must be separated from
+    /// the sentences.
+    pub weights: bool,
+
+    /// Field with the weight of JSON Lines records.
+    pub weight_field: String,
+
+    /// Tokenizer for the lines of a text corpus.
+    pub tokenizer: Tokenizer,
+
+    /// Filters of noise tokens, such as URLs.
+    pub noise: NoiseFilters,
+
+    /// Skip duplicate sentences, with the maximum memory use of the
+    /// filter in bytes. Duplicates are counted if absent.
+    pub dedup: Option<(Dedup, usize)>,
+
+    /// Skip sentences in other languages, sentences in all languages
+    /// are counted if absent.
+    pub language: Option<LanguageFilter>,
+}
+
+impl VocabOptions {
+    /// Returns `true` if the token should be counted.
+    ///
+    /// Stopwords and tokens that are too short or too long are not
+    /// counted.
+    pub fn is_counted(&self, token: &str) -> bool {
+        if self.stopwords.contains(token) {
+            return false;
+        }
+
+        if self.min_token_len.is_none() && self.max_token_len.is_none() {
+            return true;
+        }
+
+        let len = token.chars().count();
+        self.min_token_len.map(|min| len >= min).unwrap_or(true)
+            && self.max_token_len.map(|max| len <= max).unwrap_or(true)
+    }
+}
+
+/// Compute the bucket statistics of a bucket vocab, if requested.
+///
+/// A summary of the statistics is printed to standard error.
+pub fn add_bucket_statistics<I>(
+    vocab: SubwordVocab<BucketConfig, I>,
+    options: &VocabOptions,
+) -> SubwordVocab<BucketConfig, I>
+where
+    I: Indexer,
+{
+    if !options.bucket_statistics {
+        return vocab;
+    }
+
+    let vocab = vocab.with_bucket_statistics(N_WORST_COLLISIONS);
+    if let Some(statistics) = vocab.bucket_statistics() {
+        eprintln!(
+            "Bucket statistics: {} ngrams in {} of {} buckets, at most {} ngrams per bucket",
+            statistics.n_ngrams,
+            statistics.n_used_buckets,
+            statistics.n_buckets,
+            statistics.histogram.len() - 1
+        );
+    }
+
+    vocab
+}
+
+/// Add the unknown word type to a vocab, if requested.
+pub fn add_unk(vocab: SimpleVocab<String>, options: &VocabOptions) -> SimpleVocab<String> {
+    if !options.unk {
+        return vocab;
+    }
+
+    let vocab = vocab.with_unk(UNK.to_owned());
+    if let Some(idx) = vocab.idx(UNK) {
+        eprintln!(
+            "Unknown word type {} covers {} tokens",
+            UNK,
+            vocab.types()[idx.idx() as usize].count()
+        );
+    }
+
+    vocab
+}
+
+/// Prune the subword units of a vocab, if requested.
+///
+/// Only the most used subword units are retained.
+pub fn prune_subwords<C>(
+    vocab: SubwordVocab<C, ExplicitIndexer>,
+    options: &VocabOptions,
+) -> SubwordVocab<C, ExplicitIndexer>
+where
+    C: Copy,
+{
+    let max_units = match options.prune_subwords {
+        Some(max_units) => max_units,
+        None => return vocab,
+    };
+
+    let n_units = vocab.indexer().ngrams().len();
+    let vocab = vocab.prune_subwords(max_units);
+    eprintln!(
+        "Pruned subword units: retained {} of {}",
+        vocab.indexer().ngrams().len(),
+        n_units
+    );
+
+    vocab
+}
+
+/// Split the compounds of a vocab, if requested.
+///
+/// Compounds are split with a frequency-based splitter that uses the
+/// words of the vocab as constituents.
+pub fn split_compounds<C, I>(
+    vocab: SubwordVocab<C, I>,
+    options: &VocabOptions,
+) -> SubwordVocab<C, I>
+where
+    C: Copy,
+    I: Indexer,
+{
+    if !options.split_compounds {
+        return vocab;
+    }
+
+    let splitter = FrequencySplitter::from_words(vocab.types());
+    let (vocab, n_compounds) = vocab.split_compounds(&splitter);
+    eprintln!("Split {} compounds into their constituents", n_compounds);
+
+    vocab
+}
+
+/// Segment a byte pair encoding vocab with the merges from a file.
+///
+/// The vocab is returned as-is when no merges file is given.
+pub fn load_bpe_merges(
+    vocab: SubwordVocab<BpeConfig, ExplicitIndexer>,
+    merges_path: Option<&str>,
+) -> Result<SubwordVocab<BpeConfig, ExplicitIndexer>> {
+    let merges_path = match merges_path {
+        Some(merges_path) => merges_path,
+        None => return Ok(vocab),
+    };
+
+    let f = File::open(merges_path)
+        .with_context(|| format!("Cannot open merges file: {}", merges_path))?;
+    let merges = read_bpe_merges(BufReader::new(f))
+        .with_context(|| format!("Cannot read merges file: {}", merges_path))?;
+
+    Ok(vocab.with_merges(&merges))
+}
+
+/// Write the ngrams of an ngram vocab with their counts and indices.
+///
+/// Nothing is written when no output file is given.
+pub fn write_ngrams(
+    vocab: &SubwordVocab<NGramConfig, ExplicitIndexer>,
+    ngrams_path: Option<&str>,
+) -> Result<()> {
+    let ngrams_path = match ngrams_path {
+        Some(ngrams_path) => ngrams_path,
+        None => return Ok(()),
+    };
+
+    let mut write = BufWriter::new(
+        File::create(ngrams_path)
+            .with_context(|| format!("Cannot create ngrams file: {}", ngrams_path))?,
+    );
+    write_subword_counts(vocab, &mut write)
+        .with_context(|| format!("Cannot write ngrams file: {}", ngrams_path))
+}
+
+/// Segment a SentencePiece vocab with the model from a file.
+pub fn load_sentencepiece(
+    vocab: SubwordVocab<SentencePieceConfig, ExplicitIndexer>,
+    model_path: &str,
+) -> Result<SubwordVocab<SentencePieceConfig, ExplicitIndexer>> {
+    let f = File::open(model_path)
+        .with_context(|| format!("Cannot open SentencePiece model: {}", model_path))?;
+    let model = SentencePieceModel::read(BufReader::new(f))
+        .with_context(|| format!("Cannot read SentencePiece model: {}", model_path))?;
+
+    Ok(vocab.with_model(&model))
+}
+
 pub fn cutoff_from_matches(
     matches: &ArgMatches,
     mincount: &str,
@@ -1,36 +1,49 @@
 use std::cmp;
+use std::collections::HashMap;
+use std::convert::TryInto;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter};
-use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufWriter};
+use std::path::Path;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
 use clap::{App, Arg, ArgMatches};
 use conllu::io::{ReadSentence, Reader, Sentences};
-use finalfrontier::io::{thread_data_conllu, FileProgress, TrainInfo};
+use finalfrontier::io::{
+    open_corpora, ConlluFilter, CorpusFormat, SentenceSeparator, ThreadCorpus, TrainInfo,
+};
 use finalfrontier::{
     BucketIndexerType, CommonConfig, Cutoff, DepembedsConfig, DepembedsTrainer, Dependency,
-    DependencyIterator, Sgd, SimpleVocab, SimpleVocabConfig, SubwordVocab, Vocab, VocabBuilder,
+    DependencyIterator, LrSchedule, PosTags, Reseed, RngStream, Sgd, SimpleVocab,
+    SimpleVocabConfig, SubwordVocab, TokenNormalization, TokenRules, Vocab, VocabBuilder,
     WriteModelBinary,
 };
 use finalfusion::compat::fasttext::FastTextIndexer;
 use finalfusion::prelude::VocabWrap;
-use finalfusion::subword::FinalfusionHashIndexer;
-use rand::{Rng, SeedableRng};
+use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+use rand::{Rng, RngCore};
 use rand_xorshift::XorShiftRng;
 use serde::Serialize;
 use udgraph::graph::{Node, Sentence};
 use udgraph_projectivize::{HeadProjectivizer, Projectivize};
 
-use crate::subcommands::{cutoff_from_matches, show_progress, FinalfrontierApp, VocabConfig};
+use crate::subcommands::{
+    add_bucket_statistics, cutoff_from_matches, load_bpe_merges, load_sentencepiece,
+    prune_subwords, show_progress, split_compounds, write_ngrams, FinalfrontierApp, VocabConfig,
+    VocabOptions,
+};
 
 static CONTEXT_MINCOUNT: &str = "context-mincount";
 static CONTEXT_TARGET_SIZE: &str = "context-target-size";
 static CONTEXT_DISCARD: &str = "context-discard";
 static DEPENDENCY_DEPTH: &str = "dependency-depth";
+static LEMMAS: &str = "lemmas";
+static LEMMA_BACKOFF: &str = "lemma-backoff";
 static UNTYPED_DEPS: &str = "untyped";
 static NORMALIZE_CONTEXT: &str = "normalize";
+static POS_TAGS: &str = "pos-tags";
 static PROJECTIVIZE: &str = "projectivize";
 static USE_ROOT: &str = "use-root";
 
@@ -42,6 +55,8 @@ pub struct DepsApp {
     common_config: CommonConfig,
     depembeds_config: DepembedsConfig,
     input_vocab_config: VocabConfig,
+    segmentations: HashMap<String, Vec<String>>,
+    vocab_options: VocabOptions,
     output_vocab_config: SimpleVocabConfig,
 }
 
@@ -56,6 +71,13 @@ impl DepsApp {
         let normalize = matches.is_present(NORMALIZE_CONTEXT);
         let projectivize = matches.is_present(PROJECTIVIZE);
         let use_root = matches.is_present(USE_ROOT);
+        let lemmas = matches.is_present(LEMMAS);
+        let lemma_backoff = matches.is_present(LEMMA_BACKOFF);
+        let pos_tags = matches
+            .value_of(POS_TAGS)
+            .map(|v| v.try_into().context("Cannot parse part-of-speech tags"))
+            .transpose()?
+            .unwrap();
 
         Ok(DepembedsConfig {
             depth,
@@ -63,11 +85,14 @@ impl DepsApp {
             normalize,
             projectivize,
             untyped,
+            lemmas,
+            lemma_backoff,
+            pos_tags,
         })
     }
 
-    /// Get the corpus path.
-    pub fn corpus(&self) -> &str {
+    /// Get the corpus paths.
+    pub fn corpus(&self) -> &[String] {
         self.train_info().corpus()
     }
 
@@ -93,7 +118,7 @@ impl DepsApp {
 
     /// Get the input vocab config.
     pub fn input_vocab_config(&self) -> VocabConfig {
-        self.input_vocab_config
+        self.input_vocab_config.clone()
     }
 
     /// Get the output vocab config.
@@ -142,6 +167,26 @@ impl FinalfrontierApp for DepsApp {
                     .takes_value(true)
                     .default_value("1"),
             )
+            .arg(
+                Arg::with_name(LEMMAS)
+                    .long("lemmas")
+                    .help("Use lemmas rather than forms as words."),
+            )
+            .arg(
+                Arg::with_name(LEMMA_BACKOFF)
+                    .long("lemma-backoff")
+                    .help("Use the form for tokens without a lemma.")
+                    .requires(LEMMAS),
+            )
+            .arg(
+                Arg::with_name(POS_TAGS)
+                    .long("pos-tags")
+                    .value_name("TAGS")
+                    .help("Attach part-of-speech tags to words.")
+                    .takes_value(true)
+                    .default_value("none")
+                    .possible_values(&["none", "upos", "xpos"]),
+            )
             .arg(
                 Arg::with_name(UNTYPED_DEPS)
                     .long("untyped-deps")
@@ -165,7 +210,7 @@ impl FinalfrontierApp for DepsApp {
     }
 
     fn parse(matches: &ArgMatches) -> Result<Self> {
-        let corpus = matches.value_of(Self::CORPUS).unwrap().into();
+        let corpus = Self::parse_corpus(matches)?;
         let output = matches.value_of(Self::OUTPUT).unwrap().into();
         let n_threads = matches
             .value_of(Self::THREADS)
@@ -185,20 +230,53 @@ impl FinalfrontierApp for DepsApp {
             cutoff,
             discard_threshold,
         };
-        let train_info = TrainInfo::new(corpus, output, n_threads);
+        let mut train_info =
+            TrainInfo::new(corpus, output, n_threads)?.with_corpus_format(CorpusFormat::Conllu);
+        if let Some(ngram_output) = Self::parse_ngram_output(matches)? {
+            train_info = train_info.with_ngram_output(ngram_output);
+        }
+        if let Some(vectors_npy) = Self::parse_vectors_npy(matches)? {
+            train_info = train_info.with_vectors_npy(vectors_npy);
+        }
+        if let Some(ann_index) = Self::parse_ann_index(matches)? {
+            train_info = train_info.with_ann_index(ann_index);
+        }
+        if let Some(config_output) = Self::parse_config_output(matches) {
+            train_info = train_info.with_config_output(config_output);
+        }
         let common_config = Self::parse_common_config(&matches)?;
+        ensure!(
+            !common_config.tied,
+            "Tied embeddings are not supported by dependency embeddings"
+        );
+
+        let vocab_options = Self::parse_vocab_options(common_config, &matches)?;
+        ensure!(
+            vocab_options.vocab_in.is_none() && vocab_options.vocab_out.is_none(),
+            "The deps subcommand does not support reading or writing vocabulary counts"
+        );
+        ensure!(
+            vocab_options.multiword.is_empty(),
+            "The deps subcommand does not support multi-word expressions"
+        );
+        ensure!(
+            !vocab_options.unk,
+            "The deps subcommand does not support unknown word embeddings"
+        );
 
         Ok(DepsApp {
             train_info,
             common_config,
             depembeds_config: Self::depembeds_config_from_matches(&matches)?,
             input_vocab_config: Self::parse_vocab_config(common_config, &matches)?,
+            segmentations: Self::parse_segmentations(&matches)?,
+            vocab_options,
             output_vocab_config,
         })
     }
 
     fn run(&self) -> Result<()> {
-        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
         eprintln!("SIMD features: {}", Self::simd_features().join(" "));
 
         match self.input_vocab_config() {
@@ -208,6 +286,7 @@ impl FinalfrontierApp for DepsApp {
                     self.output_vocab_config(),
                     self.depembeds_config(),
                     self.corpus(),
+                    &self.vocab_options,
                 )?;
                 train(input_vocab, output_vocab, self)?;
             }
@@ -219,8 +298,23 @@ impl FinalfrontierApp for DepsApp {
                             self.output_vocab_config(),
                             self.depembeds_config(),
                             self.corpus(),
+                            &self.vocab_options,
                         )?;
-                    train(input_vocab, output_vocab, self)?
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let input_vocab = input_vocab.prune_ngrams(max_ngrams);
+                            let input_vocab = split_compounds(input_vocab, &self.vocab_options);
+                            let input_vocab = input_vocab.with_segmentations(&self.segmentations);
+                            train(input_vocab, output_vocab, self)?
+                        }
+                        None => {
+                            let input_vocab = split_compounds(input_vocab, &self.vocab_options);
+                            let input_vocab = input_vocab.with_segmentations(&self.segmentations);
+                            let input_vocab =
+                                add_bucket_statistics(input_vocab, &self.vocab_options);
+                            train(input_vocab, output_vocab, self)?
+                        }
+                    }
                 }
                 BucketIndexerType::FastText => {
                     let (input_vocab, output_vocab) =
@@ -229,17 +323,64 @@ impl FinalfrontierApp for DepsApp {
                             self.output_vocab_config(),
                             self.depembeds_config(),
                             self.corpus(),
+                            &self.vocab_options,
                         )?;
-                    train(input_vocab, output_vocab, self)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let input_vocab = input_vocab.prune_ngrams(max_ngrams);
+                            let input_vocab = split_compounds(input_vocab, &self.vocab_options);
+                            let input_vocab = input_vocab.with_segmentations(&self.segmentations);
+                            train(input_vocab, output_vocab, self)?
+                        }
+                        None => {
+                            let input_vocab = split_compounds(input_vocab, &self.vocab_options);
+                            let input_vocab = input_vocab.with_segmentations(&self.segmentations);
+                            let input_vocab =
+                                add_bucket_statistics(input_vocab, &self.vocab_options);
+                            train(input_vocab, output_vocab, self)?
+                        }
+                    }
                 }
             },
             VocabConfig::NGramVocab(config) => {
+                let (input_vocab, output_vocab) =
+                    build_vocab::<_, SubwordVocab<_, ExplicitIndexer>, _>(
+                        config,
+                        self.output_vocab_config(),
+                        self.depembeds_config(),
+                        self.corpus(),
+                        &self.vocab_options,
+                    )?;
+                let input_vocab = split_compounds(input_vocab, &self.vocab_options);
+                let input_vocab = input_vocab.with_segmentations(&self.segmentations);
+                let input_vocab = prune_subwords(input_vocab, &self.vocab_options);
+                write_ngrams(&input_vocab, self.vocab_options.ngrams_out.as_deref())?;
+                train(input_vocab, output_vocab, self)?;
+            }
+            VocabConfig::BpeVocab(config, merges) => {
+                let (input_vocab, output_vocab) = build_vocab::<_, SubwordVocab<_, _>, _>(
+                    config,
+                    self.output_vocab_config(),
+                    self.depembeds_config(),
+                    self.corpus(),
+                    &self.vocab_options,
+                )?;
+                let input_vocab = load_bpe_merges(input_vocab, merges.as_deref())?;
+                let input_vocab = input_vocab.with_segmentations(&self.segmentations);
+                let input_vocab = prune_subwords(input_vocab, &self.vocab_options);
+                train(input_vocab, output_vocab, self)?;
+            }
+            VocabConfig::SentencePieceVocab(config, model) => {
                 let (input_vocab, output_vocab) = build_vocab::<_, SubwordVocab<_, _>, _>(
                     config,
                     self.output_vocab_config(),
                     self.depembeds_config(),
                     self.corpus(),
+                    &self.vocab_options,
                 )?;
+                let input_vocab = load_sentencepiece(input_vocab, &model)?;
+                let input_vocab = input_vocab.with_segmentations(&self.segmentations);
+                let input_vocab = prune_subwords(input_vocab, &self.vocab_options);
                 train(input_vocab, output_vocab, self)?;
             }
         }
@@ -265,25 +406,31 @@ where
         output_vocab,
         app.common_config(),
         app.depembeds_config(),
-        XorShiftRng::from_entropy(),
+        app.common_config().rng::<XorShiftRng>(RngStream::Trainer),
     );
     let sgd = Sgd::new(trainer.into());
+    let lr_schedule = LrSchedule::new(
+        &common_config,
+        common_config.epochs as usize * sgd.model().input_vocab().n_types(),
+    );
 
-    let projectivize = app.depembeds_config().projectivize;
+    let dep_config = app.depembeds_config();
     let mut children = Vec::with_capacity(n_threads);
     for thread in 0..n_threads {
         let corpus = corpus.to_owned();
         let sgd = sgd.clone();
+        let rules = app.vocab_options.rules.clone();
 
         children.push(thread::spawn(move || {
             do_work(
-                corpus,
+                &corpus,
                 sgd,
+                rules,
                 thread,
                 n_threads,
                 common_config.epochs,
-                common_config.lr,
-                projectivize,
+                lr_schedule,
+                dep_config,
             )
         }));
     }
@@ -308,46 +455,67 @@ where
         .context("Cannot write model")
 }
 
+#[allow(clippy::too_many_arguments)]
 fn do_work<P, R, V>(
-    corpus_path: P,
+    corpus_paths: &[P],
     mut sgd: Sgd<DepembedsTrainer<R, V>>,
+    rules: Arc<TokenRules>,
     thread: usize,
     n_threads: usize,
     epochs: u32,
-    start_lr: f32,
-    projectivize: bool,
+    lr_schedule: LrSchedule,
+    dep_config: DepembedsConfig,
 ) -> Result<()>
 where
-    P: Into<PathBuf>,
-    R: Clone + Rng,
+    P: AsRef<Path>,
+    R: Clone + Rng + Reseed,
     V: Vocab<VocabType = String>,
     V::Config: Serialize,
     for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
 {
     let n_tokens = sgd.model().input_vocab().n_types();
+    let normalization = sgd.model().config().normalization;
 
-    let f = File::open(corpus_path.into()).context("Cannot open corpus for reading")?;
-    let (data, start) =
-        thread_data_conllu(&f, thread, n_threads).context("Could not get thread-specific data")?;
-    let projectivizer = if projectivize {
+    // Every thread draws from its own trainer generators.
+    let seed = sgd
+        .model()
+        .config()
+        .rng::<XorShiftRng>(RngStream::Corpus(thread))
+        .next_u64();
+    sgd.reseed(seed);
+
+    let data = ThreadCorpus::conllu(corpus_paths, thread, n_threads)?;
+    let projectivizer = if dep_config.projectivize {
         Some(HeadProjectivizer::new())
     } else {
         None
     };
 
-    let mut sentences = SentenceIter::new(BufReader::new(&data[start..]), projectivizer);
+    let mut sentences = SentenceIter::new(
+        data.first_pass()?,
+        projectivizer,
+        normalization,
+        rules.clone(),
+        dep_config,
+    );
     while sgd.n_tokens_processed() < epochs as usize * n_tokens {
-        let sentence = sentences
-            .next()
-            .or_else(|| {
-                sentences = SentenceIter::new(BufReader::new(&*data), projectivizer);
+        let sentence = match sentences.next() {
+            Some(sentence) => Some(sentence),
+            None => {
+                sentences = SentenceIter::new(
+                    data.pass()?,
+                    projectivizer,
+                    normalization,
+                    rules.clone(),
+                    dep_config,
+                );
                 sentences.next()
-            })
-            .transpose()?
-            .context("Cannot read sentence")?;
+            }
+        }
+        .transpose()?
+        .context("Cannot read sentence")?;
 
-        let lr = (1.0 - (sgd.n_tokens_processed() as f32 / (epochs as usize * n_tokens) as f32))
-            * start_lr;
+        let lr = lr_schedule.lr(sgd.n_tokens_processed());
         sgd.update_sentence(&sentence, lr);
     }
 
@@ -358,17 +526,21 @@ fn build_vocab<P, V, C>(
     input_config: C,
     output_config: SimpleVocabConfig,
     dep_config: DepembedsConfig,
-    corpus_path: P,
+    corpus_paths: &[P],
+    options: &VocabOptions,
 ) -> Result<(V, SimpleVocab<Dependency>)>
 where
     P: AsRef<Path>,
     V: Vocab<VocabType = String> + From<VocabBuilder<C, String>>,
     VocabBuilder<C, String>: Into<V>,
 {
-    let f = File::open(corpus_path).context("Cannot open corpus for reading")?;
-    let file_progress = FileProgress::new(f).context("Cannot create progress bar")?;
-    let mut input_builder = VocabBuilder::new(input_config);
-    let mut output_builder: VocabBuilder<_, Dependency> = VocabBuilder::new(output_config);
+    let corpus = open_corpora(corpus_paths, SentenceSeparator::EmptyLine)?;
+    let mut input_builder = VocabBuilder::new(input_config)
+        .with_max_types(options.max_types)
+        .with_max_memory(options.max_memory);
+    let mut output_builder: VocabBuilder<_, Dependency> = VocabBuilder::new(output_config)
+        .with_max_types(options.max_types)
+        .with_max_memory(options.max_memory);
 
     let projectivizer = if dep_config.projectivize {
         Some(HeadProjectivizer::new())
@@ -376,18 +548,34 @@ where
         None
     };
 
-    for sentence in SentenceIter::new(BufReader::new(file_progress), projectivizer) {
+    for sentence in SentenceIter::new(
+        corpus,
+        projectivizer,
+        options.normalization,
+        options.rules.clone(),
+        dep_config,
+    ) {
         let sentence = sentence?;
 
-        for token in sentence.iter().filter_map(Node::token) {
+        for token in sentence
+            .iter()
+            .filter_map(Node::token)
+            .filter(|token| !token.form().is_empty() && options.is_counted(token.form()))
+        {
             input_builder.count(token.form());
         }
 
-        for (_, context) in DependencyIterator::new_from_config(&sentence.dep_graph(), dep_config) {
+        for (_, context) in DependencyIterator::new_from_config(&sentence.dep_graph(), dep_config)
+            .filter(|(_, context)| !context.form().is_empty())
+        {
             output_builder.count(context);
         }
     }
 
+    if let Some(vocab_words) = &options.vocab_words {
+        input_builder.restrict(vocab_words.iter().cloned());
+    }
+
     Ok((input_builder.into(), output_builder.into()))
 }
 
@@ -397,16 +585,28 @@ where
 {
     inner: Sentences<R>,
     projectivizer: Option<P>,
+    normalization: TokenNormalization,
+    rules: Arc<TokenRules>,
+    dep_config: DepembedsConfig,
 }
 
-impl<P, R> SentenceIter<P, Reader<R>>
+impl<P, R> SentenceIter<P, Reader<ConlluFilter<R>>>
 where
     R: BufRead,
 {
-    fn new(read: R, projectivizer: Option<P>) -> Self {
+    fn new(
+        read: R,
+        projectivizer: Option<P>,
+        normalization: TokenNormalization,
+        rules: Arc<TokenRules>,
+        dep_config: DepembedsConfig,
+    ) -> Self {
         SentenceIter {
-            inner: Reader::new(read).into_iter(),
+            inner: Reader::new(ConlluFilter::new(read)).into_iter(),
             projectivizer,
+            normalization,
+            rules,
+            dep_config,
         }
     }
 }
@@ -425,6 +625,31 @@ where
             err @ Err(_) => return Some(err),
         };
 
+        // Tokens cannot be removed without changing the dependency
+        // structure. So, the forms of tokens that are dropped by the token
+        // rules or that lack a lemma are set to the empty string, which is
+        // never counted.
+        // Part-of-speech tags are attached after the rules are applied.
+        if !self.normalization.is_identity()
+            || !self.rules.is_empty()
+            || self.dep_config.lemmas
+            || self.dep_config.pos_tags != PosTags::None
+        {
+            for token in sentence.iter_mut().filter_map(Node::token_mut) {
+                let word = self
+                    .dep_config
+                    .token_word(token)
+                    .map(|word| self.normalization.normalize(word));
+                let word = word.as_ref().and_then(|word| self.rules.apply(word));
+                let form = match (word, self.dep_config.pos_tag(token)) {
+                    (Some(word), Some(tag)) => format!("{}_{}", word, tag),
+                    (Some(word), None) => word.into_owned(),
+                    (None, _) => String::new(),
+                };
+                token.set_form(form);
+            }
+        }
+
         if let Some(proj) = &self.projectivizer {
             // Rewrap error.
             if let Err(err) = proj.projectivize(&mut sentence) {
@@ -0,0 +1,408 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufRead, BufWriter};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{ensure, Context, Result};
+use clap::{App, Arg, ArgMatches};
+use finalfrontier::idx::BagOfWordsIdx;
+use finalfrontier::io::{open_corpus, ThreadData, TrainInfo};
+use finalfrontier::{
+    BucketIndexerType, CommonConfig, Doc2vecConfig, Doc2vecModelType, Doc2vecTrainer, LrSchedule,
+    MultiwordLexicon, NegativeSamples, Reseed, RngStream, SentenceIterator, Sgd, SimpleVocab,
+    SubwordVocab, TaggedDocument, TokenRules, TrainIterFrom, Trainer, Vocab, WriteModelBinary,
+};
+use finalfusion::compat::fasttext::FastTextIndexer;
+use finalfusion::prelude::VocabWrap;
+use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+use rand::RngCore;
+use rand_xorshift::XorShiftRng;
+use serde::Serialize;
+
+use crate::subcommands::skipgram::build_vocab;
+use crate::subcommands::{
+    add_bucket_statistics, add_unk, load_bpe_merges, load_sentencepiece, prune_subwords,
+    show_progress, split_compounds, write_ngrams, FinalfrontierApp, VocabConfig, VocabOptions,
+};
+
+static CONTEXT: &str = "context";
+static DOC_OUTPUT: &str = "doc-output";
+static MODEL: &str = "model";
+
+const PROGRESS_UPDATE_INTERVAL: u64 = 200;
+
+/// Subcommand for training paragraph vectors.
+pub struct Doc2vecApp {
+    train_info: TrainInfo,
+    doc_output: String,
+    common_config: CommonConfig,
+    doc2vec_config: Doc2vecConfig,
+    vocab_config: VocabConfig,
+    segmentations: HashMap<String, Vec<String>>,
+    vocab_options: VocabOptions,
+}
+
+impl Doc2vecApp {
+    /// Get the corpus paths.
+    pub fn corpus(&self) -> &[String] {
+        &self.train_info.corpus()
+    }
+
+    /// Get the document embeddings output path.
+    pub fn doc_output(&self) -> &str {
+        &self.doc_output
+    }
+
+    /// Get the common config.
+    pub fn common_config(&self) -> CommonConfig {
+        self.common_config
+    }
+
+    /// Get the paragraph vector config.
+    pub fn doc2vec_config(&self) -> Doc2vecConfig {
+        self.doc2vec_config
+    }
+
+    /// Get the vocab config.
+    pub fn vocab_config(&self) -> VocabConfig {
+        self.vocab_config.clone()
+    }
+
+    /// Get the train information.
+    pub fn train_info(&self) -> &TrainInfo {
+        &self.train_info
+    }
+
+    fn doc2vec_config_from_matches(matches: &ArgMatches) -> Result<Doc2vecConfig> {
+        let context_size = matches
+            .value_of(CONTEXT)
+            .map(|v| v.parse().context("Cannot parse context size"))
+            .transpose()?
+            .unwrap();
+        let model = matches
+            .value_of(MODEL)
+            .map(|v| Doc2vecModelType::try_from(v).context("Cannot parse model type"))
+            .transpose()?
+            .unwrap();
+
+        Ok(Doc2vecConfig {
+            model,
+            context_size,
+        })
+    }
+}
+
+impl FinalfrontierApp for Doc2vecApp {
+    fn app() -> App<'static, 'static> {
+        Self::common_opts("doc2vec")
+            .about("Train paragraph vectors")
+            .arg(
+                Arg::with_name(CONTEXT)
+                    .long("context")
+                    .value_name("CONTEXT_SIZE")
+                    .help("Context size of the distributed memory model")
+                    .takes_value(true)
+                    .default_value("5"),
+            )
+            .arg(
+                Arg::with_name(DOC_OUTPUT)
+                    .long("doc-output")
+                    .value_name("FILE")
+                    .help("Document embeddings output (default: OUTPUT.docs)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(MODEL)
+                    .long(MODEL)
+                    .value_name("MODEL")
+                    .help("Model")
+                    .takes_value(true)
+                    .possible_values(&["dbow", "dm"])
+                    .default_value("dm"),
+            )
+    }
+
+    fn parse(matches: &ArgMatches) -> Result<Self> {
+        let corpus = Self::parse_corpus(matches)?;
+        ensure!(
+            corpus.len() == 1,
+            "Documents are tagged by their position in the corpus, so the corpus should be a single file"
+        );
+        let output: String = matches.value_of(Self::OUTPUT).unwrap().into();
+        let n_threads = matches
+            .value_of(Self::THREADS)
+            .map(|v| v.parse().context("Cannot parse number of threads"))
+            .transpose()?
+            .unwrap_or_else(|| cmp::min(num_cpus::get() / 2, 20));
+        let doc_output = matches
+            .value_of(DOC_OUTPUT)
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| format!("{}.docs", output));
+        let mut train_info = TrainInfo::new(corpus, output, n_threads)?;
+        if let Some(ngram_output) = Self::parse_ngram_output(matches)? {
+            train_info = train_info.with_ngram_output(ngram_output);
+        }
+        if let Some(vectors_npy) = Self::parse_vectors_npy(matches)? {
+            train_info = train_info.with_vectors_npy(vectors_npy);
+        }
+        if let Some(ann_index) = Self::parse_ann_index(matches)? {
+            train_info = train_info.with_ann_index(ann_index);
+        }
+        if let Some(config_output) = Self::parse_config_output(matches) {
+            train_info = train_info.with_config_output(config_output);
+        }
+
+        let common_config = Self::parse_common_config(&matches)?;
+
+        Ok(Doc2vecApp {
+            train_info,
+            doc_output,
+            common_config,
+            doc2vec_config: Self::doc2vec_config_from_matches(&matches)?,
+            vocab_config: Self::parse_vocab_config(common_config, &matches)?,
+            segmentations: Self::parse_segmentations(&matches)?,
+            vocab_options: Self::parse_vocab_options(common_config, &matches)?,
+        })
+    }
+
+    fn run(&self) -> Result<()> {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+        eprintln!("SIMD features: {}", Self::simd_features().join(" "));
+
+        match self.vocab_config() {
+            VocabConfig::SubwordVocab(config) => match config.indexer.indexer_type {
+                BucketIndexerType::Finalfusion => {
+                    let vocab: SubwordVocab<_, FinalfusionHashIndexer> =
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, self)
+                        }
+                    }
+                }
+                BucketIndexerType::FastText => {
+                    let vocab: SubwordVocab<_, FastTextIndexer> =
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, self)
+                        }
+                    }
+                }
+            },
+            VocabConfig::SimpleVocab(config) => {
+                let vocab: SimpleVocab<String> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = add_unk(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+            VocabConfig::NGramVocab(config) => {
+                let vocab: SubwordVocab<_, ExplicitIndexer> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = split_compounds(vocab, &self.vocab_options);
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                write_ngrams(&vocab, self.vocab_options.ngrams_out.as_deref())?;
+                train(vocab, self)
+            }
+            VocabConfig::BpeVocab(config, merges) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_bpe_merges(vocab, merges.as_deref())?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+            VocabConfig::SentencePieceVocab(config, model) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_sentencepiece(vocab, &model)?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+        }
+    }
+}
+
+fn train<V>(vocab: V, app: &Doc2vecApp) -> Result<()>
+where
+    V: Vocab<VocabType = String> + Into<VocabWrap> + Clone + Send + Sync + 'static,
+    V::Config: Serialize,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let common_config = app.common_config();
+    let train_info = app.train_info();
+    let n_threads = train_info.n_threads();
+    let corpus = &train_info.corpus()[0];
+    let mut output_writer = BufWriter::new(
+        File::create(train_info.output()).context("Cannot open output file for writing.")?,
+    );
+    let mut doc_output_writer = BufWriter::new(
+        File::create(app.doc_output())
+            .context("Cannot open document embeddings output file for writing.")?,
+    );
+
+    let n_docs = count_documents(open_corpus(corpus)?)?;
+
+    let trainer = Doc2vecTrainer::new(
+        vocab,
+        common_config.rng::<XorShiftRng>(RngStream::Trainer),
+        common_config,
+        app.doc2vec_config(),
+        n_docs,
+    );
+    let sgd = Sgd::new(trainer.into());
+    let lr_schedule = LrSchedule::new(
+        &common_config,
+        common_config.epochs as usize * sgd.model().input_vocab().n_types(),
+    );
+
+    let mut children = Vec::with_capacity(n_threads);
+    for thread in 0..n_threads {
+        let corpus = corpus.to_owned();
+        let sgd = sgd.clone();
+        let rules = app.vocab_options.rules.clone();
+        let multiword = app.vocab_options.multiword.clone();
+
+        children.push(thread::spawn(move || {
+            do_work(
+                corpus,
+                sgd,
+                rules,
+                multiword,
+                thread,
+                n_threads,
+                common_config.epochs,
+                lr_schedule,
+            )
+        }));
+    }
+
+    show_progress(
+        &common_config,
+        &sgd,
+        Duration::from_millis(PROGRESS_UPDATE_INTERVAL),
+    );
+
+    // Wait until all threads have finished.
+    for child in children {
+        child.join().expect("Thread panicked")?;
+    }
+
+    let mut model = sgd.into_model();
+    model
+        .write_document_embeddings(&mut doc_output_writer)
+        .context("Cannot write document embeddings")?;
+    model
+        .write_model_binary(&mut output_writer, train_info.clone(), common_config.format)
+        .context("Cannot write model")
+}
+
+#[allow(clippy::too_many_arguments)]
+fn do_work<P, T, V>(
+    corpus_path: P,
+    mut sgd: Sgd<T>,
+    rules: Arc<TokenRules>,
+    multiword: Arc<MultiwordLexicon>,
+    thread: usize,
+    n_threads: usize,
+    epochs: u32,
+    lr_schedule: LrSchedule,
+) -> Result<()>
+where
+    P: Into<PathBuf>,
+    T: Trainer<InputVocab = V>
+        + for<'a> TrainIterFrom<'a, TaggedDocument<String>, Focus = BagOfWordsIdx>
+        + NegativeSamples
+        + Reseed,
+    V: Vocab<VocabType = String>,
+{
+    let n_tokens = sgd.model().input_vocab().n_types();
+    let normalization = sgd.model().config().normalization;
+
+    // Every thread draws from its own trainer generators.
+    let seed = sgd
+        .model()
+        .config()
+        .rng::<XorShiftRng>(RngStream::Corpus(thread))
+        .next_u64();
+    sgd.reseed(seed);
+
+    let corpus_path: PathBuf = corpus_path.into();
+    let data = ThreadData::text(&corpus_path, thread, n_threads)?;
+
+    // Documents are tagged by their position in the corpus.
+    let (offset, stride) = data.stride();
+    let first_tag = offset + count_documents(data.first_pass_prefix()?)?;
+    let mut tag = first_tag;
+    let mut sentences = SentenceIterator::new(data.first_pass()?).with_normalization(normalization);
+    while sgd.n_tokens_processed() < epochs as usize * n_tokens {
+        let sentence = if let Some(sentence) = sentences.next() {
+            sentence
+        } else {
+            sentences = SentenceIterator::new(data.pass()?).with_normalization(normalization);
+            tag = first_tag;
+            sentences
+                .next()
+                .context("Iterator does not provide sentences")?
+        }
+        .context("Cannot read sentence")?;
+
+        // Token rules and multi-word expressions are applied here rather
+        // than by the sentence iterator, since documents of which all
+        // tokens are dropped must still be tagged.
+        let sentence: Vec<_> = sentence
+            .iter()
+            .filter_map(|token| rules.apply(token).map(|token| token.into_owned()))
+            .collect();
+        let sentence = multiword.merge(sentence);
+
+        if !sentence.is_empty() {
+            let lr = lr_schedule.lr(sgd.n_tokens_processed());
+            sgd.update_sentence(&TaggedDocument::new(tag, sentence), lr);
+        }
+        tag += stride;
+    }
+
+    Ok(())
+}
+
+/// Count the documents in a corpus.
+///
+/// Every line that is not empty is a document, consistent with
+/// `SentenceIterator`.
+fn count_documents<R>(read: R) -> Result<usize>
+where
+    R: BufRead,
+{
+    let mut n_docs = 0;
+    for line in read.lines() {
+        if !line.context("Cannot read line")?.trim().is_empty() {
+            n_docs += 1;
+        }
+    }
+
+    Ok(n_docs)
+}
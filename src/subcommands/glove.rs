@@ -0,0 +1,398 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{ensure, Context, Result};
+use clap::{App, Arg, ArgMatches};
+use finalfrontier::io::{open_corpora, SentenceSeparator, TrainInfo};
+use finalfrontier::{
+    BucketIndexerType, CommonConfig, Cooccurrence, CooccurrenceMatrix, GloveConfig, GloveSgd,
+    GloveTrainer, LossType, LrScheduleType, MultiwordLexicon, OptimizerType, RngStream,
+    SentenceIterator, SimpleVocab, SubwordVocab, TokenNormalization, TokenRules, Vocab,
+    WriteModelBinary,
+};
+use finalfusion::compat::fasttext::FastTextIndexer;
+use finalfusion::prelude::VocabWrap;
+use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+use indicatif::{ProgressBar, ProgressStyle};
+use rand_xorshift::XorShiftRng;
+use serde::Serialize;
+
+use crate::subcommands::skipgram::build_vocab;
+use crate::subcommands::{
+    add_bucket_statistics, add_unk, load_bpe_merges, load_sentencepiece, prune_subwords,
+    split_compounds, write_ngrams, FinalfrontierApp, VocabConfig, VocabOptions,
+};
+
+static ALPHA: &str = "alpha";
+static CONTEXT: &str = "context";
+static X_MAX: &str = "x-max";
+
+const PROGRESS_UPDATE_INTERVAL: u64 = 200;
+
+/// Subcommand for training GloVe models.
+pub struct GloveApp {
+    train_info: TrainInfo,
+    common_config: CommonConfig,
+    glove_config: GloveConfig,
+    vocab_config: VocabConfig,
+    segmentations: HashMap<String, Vec<String>>,
+    vocab_options: VocabOptions,
+}
+
+impl GloveApp {
+    /// Get the corpus paths.
+    pub fn corpus(&self) -> &[String] {
+        &self.train_info.corpus()
+    }
+
+    /// Get the output path.
+    pub fn output(&self) -> &str {
+        &self.train_info.output()
+    }
+
+    /// Get the number of threads.
+    pub fn n_threads(&self) -> usize {
+        self.train_info.n_threads()
+    }
+
+    /// Get the common config.
+    pub fn common_config(&self) -> CommonConfig {
+        self.common_config
+    }
+
+    /// Get the GloVe config.
+    pub fn glove_config(&self) -> GloveConfig {
+        self.glove_config
+    }
+
+    /// Get the vocab config.
+    pub fn vocab_config(&self) -> VocabConfig {
+        self.vocab_config.clone()
+    }
+
+    /// Get the train information.
+    pub fn train_info(&self) -> &TrainInfo {
+        &self.train_info
+    }
+
+    fn glove_config_from_matches(matches: &ArgMatches) -> Result<GloveConfig> {
+        let context_size = matches
+            .value_of(CONTEXT)
+            .map(|v| v.parse().context("Cannot parse context size"))
+            .transpose()?
+            .unwrap();
+        let x_max = matches
+            .value_of(X_MAX)
+            .map(|v| v.parse().context("Cannot parse x_max"))
+            .transpose()?
+            .unwrap();
+        let alpha = matches
+            .value_of(ALPHA)
+            .map(|v| v.parse().context("Cannot parse alpha"))
+            .transpose()?
+            .unwrap();
+
+        Ok(GloveConfig {
+            context_size,
+            x_max,
+            alpha,
+        })
+    }
+}
+
+impl FinalfrontierApp for GloveApp {
+    fn app() -> App<'static, 'static> {
+        Self::common_opts("glove")
+            .about("Train a GloVe model")
+            .arg(
+                Arg::with_name(ALPHA)
+                    .long("alpha")
+                    .value_name("EXP")
+                    .help("Exponent of the co-occurrence weighting function")
+                    .takes_value(true)
+                    .default_value("0.75"),
+            )
+            .arg(
+                Arg::with_name(CONTEXT)
+                    .long("context")
+                    .value_name("CONTEXT_SIZE")
+                    .help("Context size")
+                    .takes_value(true)
+                    .default_value("15"),
+            )
+            .arg(
+                Arg::with_name(X_MAX)
+                    .long("x-max")
+                    .value_name("COUNT")
+                    .help("Co-occurrence count at which the weighting function saturates")
+                    .takes_value(true)
+                    .default_value("100"),
+            )
+    }
+
+    fn parse(matches: &ArgMatches) -> Result<Self> {
+        let corpus = Self::parse_corpus(matches)?;
+        let output = matches.value_of(Self::OUTPUT).unwrap().into();
+        let n_threads = matches
+            .value_of(Self::THREADS)
+            .map(|v| v.parse().context("Cannot parse number of threads"))
+            .transpose()?
+            .unwrap_or_else(|| cmp::min(num_cpus::get() / 2, 20));
+        let mut train_info = TrainInfo::new(corpus, output, n_threads)?;
+        if let Some(ngram_output) = Self::parse_ngram_output(matches)? {
+            train_info = train_info.with_ngram_output(ngram_output);
+        }
+        if let Some(vectors_npy) = Self::parse_vectors_npy(matches)? {
+            train_info = train_info.with_vectors_npy(vectors_npy);
+        }
+        if let Some(ann_index) = Self::parse_ann_index(matches)? {
+            train_info = train_info.with_ann_index(ann_index);
+        }
+        if let Some(config_output) = Self::parse_config_output(matches) {
+            train_info = train_info.with_config_output(config_output);
+        }
+
+        // The loss is part of the common options, but GloVe always
+        // optimizes the weighted least squares loss.
+        ensure!(
+            matches.occurrences_of("loss") == 0,
+            "GloVe is trained with the weighted least squares loss, --loss cannot be used"
+        );
+        let mut common_config = Self::parse_common_config(&matches)?;
+        common_config.loss = LossType::WeightedLeastSquares;
+        common_config.optimizer = OptimizerType::AdaGrad;
+        common_config.lr_schedule = LrScheduleType::Constant;
+        common_config.warmup = 0;
+
+        Ok(GloveApp {
+            train_info,
+            common_config,
+            glove_config: Self::glove_config_from_matches(&matches)?,
+            vocab_config: Self::parse_vocab_config(common_config, &matches)?,
+            segmentations: Self::parse_segmentations(&matches)?,
+            vocab_options: Self::parse_vocab_options(common_config, &matches)?,
+        })
+    }
+
+    fn run(&self) -> Result<()> {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+        eprintln!("SIMD features: {}", Self::simd_features().join(" "));
+
+        match self.vocab_config() {
+            VocabConfig::SubwordVocab(config) => match config.indexer.indexer_type {
+                BucketIndexerType::Finalfusion => {
+                    let vocab: SubwordVocab<_, FinalfusionHashIndexer> =
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, self)
+                        }
+                    }
+                }
+                BucketIndexerType::FastText => {
+                    let vocab: SubwordVocab<_, FastTextIndexer> =
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, self)
+                        }
+                    }
+                }
+            },
+            VocabConfig::SimpleVocab(config) => {
+                let vocab: SimpleVocab<String> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = add_unk(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+            VocabConfig::NGramVocab(config) => {
+                let vocab: SubwordVocab<_, ExplicitIndexer> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = split_compounds(vocab, &self.vocab_options);
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                write_ngrams(&vocab, self.vocab_options.ngrams_out.as_deref())?;
+                train(vocab, self)
+            }
+            VocabConfig::BpeVocab(config, merges) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_bpe_merges(vocab, merges.as_deref())?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+            VocabConfig::SentencePieceVocab(config, model) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_sentencepiece(vocab, &model)?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+        }
+    }
+}
+
+fn train<V>(vocab: V, app: &GloveApp) -> Result<()>
+where
+    V: Vocab<VocabType = String> + Into<VocabWrap> + Clone + Send + Sync + 'static,
+    V::Config: Serialize,
+    V::IdxType: Send + Sync,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let common_config = app.common_config();
+    let n_threads = app.n_threads();
+    let mut output_writer =
+        BufWriter::new(File::create(app.output()).context("Cannot open output file for writing.")?);
+
+    let trainer = GloveTrainer::new(vocab, common_config, app.glove_config());
+    let cooccurrences = Arc::new(count_cooccurrences(
+        &trainer,
+        app.corpus(),
+        app.common_config().normalization,
+        app.vocab_options.rules.clone(),
+        app.vocab_options.multiword.clone(),
+        common_config.rng(RngStream::Corpus(0)),
+    )?);
+    let sgd = GloveSgd::new(trainer.into());
+
+    let mut children = Vec::with_capacity(n_threads);
+    for thread in 0..n_threads {
+        let cooccurrences = cooccurrences.clone();
+        let sgd = sgd.clone();
+
+        children.push(thread::spawn(move || {
+            do_work(
+                &cooccurrences,
+                sgd,
+                thread,
+                n_threads,
+                common_config.epochs,
+                common_config.lr,
+            )
+        }));
+    }
+
+    show_progress(
+        &common_config,
+        &sgd,
+        cooccurrences.len(),
+        Duration::from_millis(PROGRESS_UPDATE_INTERVAL),
+    );
+
+    // Wait until all threads have finished.
+    for child in children {
+        child.join().expect("Thread panicked");
+    }
+
+    sgd.into_model()
+        .write_model_binary(
+            &mut output_writer,
+            app.train_info().clone(),
+            app.common_config.format,
+        )
+        .context("Cannot write model")
+}
+
+fn do_work<V>(
+    cooccurrences: &[Cooccurrence],
+    mut sgd: GloveSgd<V>,
+    thread: usize,
+    n_threads: usize,
+    epochs: u32,
+    lr: f32,
+) where
+    V: Vocab,
+    V::Config: Serialize,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let chunk_size = cooccurrences.len() / n_threads;
+    let start = thread * chunk_size;
+    let end = if thread + 1 == n_threads {
+        cooccurrences.len()
+    } else {
+        start + chunk_size
+    };
+
+    for _ in 0..epochs {
+        for &cooccurrence in &cooccurrences[start..end] {
+            sgd.update_cooccurrence(cooccurrence, lr);
+        }
+    }
+}
+
+fn count_cooccurrences<V>(
+    trainer: &GloveTrainer<V>,
+    corpus_paths: &[String],
+    normalization: TokenNormalization,
+    rules: Arc<TokenRules>,
+    multiword: Arc<MultiwordLexicon>,
+    mut rng: XorShiftRng,
+) -> Result<Vec<Cooccurrence>>
+where
+    V: Vocab<VocabType = String>,
+{
+    let corpus = open_corpora(corpus_paths, SentenceSeparator::Line)?;
+
+    let sentences = SentenceIterator::new(corpus)
+        .with_normalization(normalization)
+        .with_rules(rules)
+        .with_multiword(multiword);
+
+    let mut matrix = CooccurrenceMatrix::default();
+    for sentence in sentences {
+        let sentence = sentence.context("Cannot read sentence")?;
+        trainer.count_cooccurrences(&sentence, &mut matrix);
+    }
+
+    Ok(matrix.into_shuffled(&mut rng))
+}
+
+fn show_progress<V>(
+    config: &CommonConfig,
+    sgd: &GloveSgd<V>,
+    n_cooccurrences: usize,
+    update_interval: Duration,
+) where
+    V: Vocab,
+    V::Config: Serialize,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let n_examples = n_cooccurrences * config.epochs as usize;
+
+    let pb = ProgressBar::new(n_examples as u64);
+    pb.set_style(
+        ProgressStyle::default_bar().template("{bar:30} {percent}% {msg} ETA: {eta_precise}"),
+    );
+
+    while sgd.n_examples() < n_examples {
+        pb.set_position(sgd.n_examples() as u64);
+        pb.set_message(&format!("loss: {:.*}", 5, sgd.train_loss()));
+
+        thread::sleep(update_interval);
+    }
+
+    pb.finish();
+}
@@ -1,14 +1,41 @@
 mod config;
-pub use self::config::{cutoff_from_matches, VocabConfig};
+pub use self::config::{
+    add_bucket_statistics, add_unk, cutoff_from_matches, load_bpe_merges, load_sentencepiece,
+    prune_subwords, split_compounds, write_ngrams, VocabConfig, VocabOptions,
+};
 
 mod deps;
 pub use self::deps::DepsApp;
 
+mod doc2vec;
+pub use self::doc2vec::Doc2vecApp;
+
+mod glove;
+pub use self::glove::GloveApp;
+
+mod multisense;
+pub use self::multisense::MultisenseApp;
+
+mod pmi;
+pub use self::pmi::PmiApp;
+
 mod progress;
 pub use self::progress::show_progress;
 
+mod retrofit;
+pub use self::retrofit::RetrofitApp;
+
+mod sent2vec;
+pub use self::sent2vec::Sent2vecApp;
+
 mod skipgram;
 pub use self::skipgram::SkipgramApp;
 
+mod supervised;
+pub use self::supervised::SupervisedApp;
+
 mod traits;
 pub use self::traits::FinalfrontierApp;
+
+mod vocab;
+pub use self::vocab::VocabApp;
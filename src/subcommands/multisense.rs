@@ -0,0 +1,290 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+
+use anyhow::{ensure, Context, Result};
+use clap::{App, Arg, ArgMatches};
+use finalfrontier::io::TrainInfo;
+use finalfrontier::{
+    BucketIndexerType, CommonConfig, MultisenseConfig, MultisenseTrainer, RngStream, SimpleVocab,
+    SubwordVocab, Vocab, WriteModelBinary,
+};
+use finalfusion::compat::fasttext::FastTextIndexer;
+use finalfusion::prelude::VocabWrap;
+use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+use rand_xorshift::XorShiftRng;
+use serde::Serialize;
+
+use crate::subcommands::skipgram::{build_vocab, train_model};
+use crate::subcommands::{
+    add_bucket_statistics, add_unk, load_bpe_merges, load_sentencepiece, prune_subwords,
+    split_compounds, write_ngrams, FinalfrontierApp, VocabConfig, VocabOptions,
+};
+
+static CONTEXT: &str = "context";
+static SENSE_OUTPUT: &str = "sense-output";
+static SENSE_THRESHOLD: &str = "sense-threshold";
+static SENSES: &str = "senses";
+
+/// Subcommand for training multi-sense embeddings.
+pub struct MultisenseApp {
+    train_info: TrainInfo,
+    sense_output: String,
+    common_config: CommonConfig,
+    multisense_config: MultisenseConfig,
+    vocab_config: VocabConfig,
+    segmentations: HashMap<String, Vec<String>>,
+    vocab_options: VocabOptions,
+}
+
+impl MultisenseApp {
+    /// Get the corpus paths.
+    pub fn corpus(&self) -> &[String] {
+        &self.train_info.corpus()
+    }
+
+    /// Get the sense embeddings output path.
+    pub fn sense_output(&self) -> &str {
+        &self.sense_output
+    }
+
+    /// Get the common config.
+    pub fn common_config(&self) -> CommonConfig {
+        self.common_config
+    }
+
+    /// Get the multi-sense config.
+    pub fn multisense_config(&self) -> MultisenseConfig {
+        self.multisense_config
+    }
+
+    /// Get the vocab config.
+    pub fn vocab_config(&self) -> VocabConfig {
+        self.vocab_config.clone()
+    }
+
+    /// Get the train information.
+    pub fn train_info(&self) -> &TrainInfo {
+        &self.train_info
+    }
+
+    fn multisense_config_from_matches(matches: &ArgMatches) -> Result<MultisenseConfig> {
+        let context_size = matches
+            .value_of(CONTEXT)
+            .map(|v| v.parse().context("Cannot parse context size"))
+            .transpose()?
+            .unwrap();
+        let senses = matches
+            .value_of(SENSES)
+            .map(|v| v.parse().context("Cannot parse number of senses"))
+            .transpose()?
+            .unwrap();
+        ensure!(senses > 0, "The number of senses should be at least 1");
+        let sense_threshold = matches
+            .value_of(SENSE_THRESHOLD)
+            .map(|v| v.parse().context("Cannot parse sense threshold"))
+            .transpose()?
+            .unwrap();
+
+        Ok(MultisenseConfig {
+            context_size,
+            senses,
+            sense_threshold,
+        })
+    }
+}
+
+impl FinalfrontierApp for MultisenseApp {
+    fn app() -> App<'static, 'static> {
+        Self::common_opts("multisense")
+            .about("Train multi-sense embeddings")
+            .arg(
+                Arg::with_name(CONTEXT)
+                    .long("context")
+                    .value_name("CONTEXT_SIZE")
+                    .help("Context size")
+                    .takes_value(true)
+                    .default_value("5"),
+            )
+            .arg(
+                Arg::with_name(SENSE_OUTPUT)
+                    .long("sense-output")
+                    .value_name("FILE")
+                    .help("Sense embeddings output (default: OUTPUT.senses)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(SENSE_THRESHOLD)
+                    .long("sense-threshold")
+                    .value_name("SIMILARITY")
+                    .help("Context similarity below which a new sense is allocated")
+                    .takes_value(true)
+                    .allow_hyphen_values(true)
+                    .default_value("-0.5"),
+            )
+            .arg(
+                Arg::with_name(SENSES)
+                    .long("senses")
+                    .value_name("N")
+                    .help("Maximum number of senses per word")
+                    .takes_value(true)
+                    .default_value("3"),
+            )
+    }
+
+    fn parse(matches: &ArgMatches) -> Result<Self> {
+        let corpus = Self::parse_corpus(matches)?;
+        let output: String = matches.value_of(Self::OUTPUT).unwrap().into();
+        let n_threads = matches
+            .value_of(Self::THREADS)
+            .map(|v| v.parse().context("Cannot parse number of threads"))
+            .transpose()?
+            .unwrap_or_else(|| cmp::min(num_cpus::get() / 2, 20));
+        let sense_output = matches
+            .value_of(SENSE_OUTPUT)
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| format!("{}.senses", output));
+        let mut train_info = TrainInfo::new(corpus, output, n_threads)?;
+        if let Some(ngram_output) = Self::parse_ngram_output(matches)? {
+            train_info = train_info.with_ngram_output(ngram_output);
+        }
+        if let Some(vectors_npy) = Self::parse_vectors_npy(matches)? {
+            train_info = train_info.with_vectors_npy(vectors_npy);
+        }
+        if let Some(ann_index) = Self::parse_ann_index(matches)? {
+            train_info = train_info.with_ann_index(ann_index);
+        }
+        if let Some(config_output) = Self::parse_config_output(matches) {
+            train_info = train_info.with_config_output(config_output);
+        }
+
+        let common_config = Self::parse_common_config(&matches)?;
+
+        Ok(MultisenseApp {
+            train_info,
+            sense_output,
+            common_config,
+            multisense_config: Self::multisense_config_from_matches(&matches)?,
+            vocab_config: Self::parse_vocab_config(common_config, &matches)?,
+            segmentations: Self::parse_segmentations(&matches)?,
+            vocab_options: Self::parse_vocab_options(common_config, &matches)?,
+        })
+    }
+
+    fn run(&self) -> Result<()> {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+        eprintln!("SIMD features: {}", Self::simd_features().join(" "));
+
+        match self.vocab_config() {
+            VocabConfig::SubwordVocab(config) => match config.indexer.indexer_type {
+                BucketIndexerType::Finalfusion => {
+                    let vocab: SubwordVocab<_, FinalfusionHashIndexer> =
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, self)
+                        }
+                    }
+                }
+                BucketIndexerType::FastText => {
+                    let vocab: SubwordVocab<_, FastTextIndexer> =
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, self)
+                        }
+                    }
+                }
+            },
+            VocabConfig::SimpleVocab(config) => {
+                let vocab: SimpleVocab<String> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = add_unk(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+            VocabConfig::NGramVocab(config) => {
+                let vocab: SubwordVocab<_, ExplicitIndexer> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = split_compounds(vocab, &self.vocab_options);
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                write_ngrams(&vocab, self.vocab_options.ngrams_out.as_deref())?;
+                train(vocab, self)
+            }
+            VocabConfig::BpeVocab(config, merges) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_bpe_merges(vocab, merges.as_deref())?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+            VocabConfig::SentencePieceVocab(config, model) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_sentencepiece(vocab, &model)?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+        }
+    }
+}
+
+fn train<V>(vocab: V, app: &MultisenseApp) -> Result<()>
+where
+    V: Vocab<VocabType = String> + Into<VocabWrap> + Clone + Send + Sync + 'static,
+    V::Config: Serialize,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let common_config = app.common_config();
+    let mut train_info = app.train_info().clone();
+    let mut output_writer = BufWriter::new(
+        File::create(train_info.output()).context("Cannot open output file for writing.")?,
+    );
+    let mut sense_output_writer = BufWriter::new(
+        File::create(app.sense_output())
+            .context("Cannot open sense embeddings output file for writing.")?,
+    );
+
+    let trainer = MultisenseTrainer::new(
+        vocab,
+        common_config.rng::<XorShiftRng>(RngStream::Trainer),
+        common_config,
+        app.multisense_config(),
+    );
+
+    let mut model = train_model(
+        trainer,
+        &mut train_info,
+        common_config,
+        None,
+        app.vocab_options.rules.clone(),
+        app.vocab_options.multiword.clone(),
+        |sgd, sentence, lr| sgd.update_sentence_multisense(sentence, lr),
+        None,
+    )?;
+    model
+        .write_sense_embeddings(&mut sense_output_writer)
+        .context("Cannot write sense embeddings")?;
+    model
+        .write_model_binary(&mut output_writer, train_info, common_config.format)
+        .context("Cannot write model")
+}
@@ -0,0 +1,252 @@
+use std::cmp;
+use std::fs::File;
+use std::io::BufWriter;
+
+use anyhow::{ensure, Context, Result};
+use clap::{App, Arg, ArgMatches};
+use finalfrontier::io::{open_corpora, SentenceSeparator, TrainInfo};
+use finalfrontier::{
+    CommonConfig, CooccurrenceMatrix, PmiConfig, PmiTrainer, RngStream, SentenceIterator,
+    SimpleVocab, Vocab, WriteModelBinary,
+};
+use finalfusion::prelude::VocabWrap;
+use rand_xorshift::XorShiftRng;
+use serde::Serialize;
+
+use crate::subcommands::skipgram::build_vocab;
+use crate::subcommands::{add_unk, FinalfrontierApp, VocabConfig, VocabOptions};
+
+static CONTEXT: &str = "context";
+static CONTEXT_SMOOTHING: &str = "context_smoothing";
+static EIGENVALUE_WEIGHT: &str = "eigenvalue_weight";
+static OVERSAMPLING: &str = "oversampling";
+static POWER_ITERATIONS: &str = "power_iterations";
+static SHIFT: &str = "shift";
+
+/// Subcommand for training PMI models.
+pub struct PmiApp {
+    train_info: TrainInfo,
+    common_config: CommonConfig,
+    pmi_config: PmiConfig,
+    vocab_config: VocabConfig,
+    vocab_options: VocabOptions,
+}
+
+impl PmiApp {
+    /// Get the corpus paths.
+    pub fn corpus(&self) -> &[String] {
+        &self.train_info.corpus()
+    }
+
+    /// Get the output path.
+    pub fn output(&self) -> &str {
+        &self.train_info.output()
+    }
+
+    /// Get the common config.
+    pub fn common_config(&self) -> CommonConfig {
+        self.common_config
+    }
+
+    /// Get the PMI config.
+    pub fn pmi_config(&self) -> PmiConfig {
+        self.pmi_config
+    }
+
+    /// Get the train information.
+    pub fn train_info(&self) -> &TrainInfo {
+        &self.train_info
+    }
+
+    fn pmi_config_from_matches(matches: &ArgMatches) -> Result<PmiConfig> {
+        let context_size = matches
+            .value_of(CONTEXT)
+            .map(|v| v.parse().context("Cannot parse context size"))
+            .transpose()?
+            .unwrap();
+        let context_smoothing = matches
+            .value_of(CONTEXT_SMOOTHING)
+            .map(|v| v.parse().context("Cannot parse context smoothing exponent"))
+            .transpose()?
+            .unwrap();
+        let shift = matches
+            .value_of(SHIFT)
+            .map(|v| v.parse().context("Cannot parse PMI shift"))
+            .transpose()?
+            .unwrap();
+        ensure!(
+            shift >= 1f32,
+            "PMI shift should be at least 1, was: {}",
+            shift
+        );
+        let eigenvalue_weight = matches
+            .value_of(EIGENVALUE_WEIGHT)
+            .map(|v| v.parse().context("Cannot parse eigenvalue weight"))
+            .transpose()?
+            .unwrap();
+        let power_iterations = matches
+            .value_of(POWER_ITERATIONS)
+            .map(|v| v.parse().context("Cannot parse number of power iterations"))
+            .transpose()?
+            .unwrap();
+        let oversampling = matches
+            .value_of(OVERSAMPLING)
+            .map(|v| v.parse().context("Cannot parse oversampling"))
+            .transpose()?
+            .unwrap();
+
+        Ok(PmiConfig {
+            context_size,
+            context_smoothing,
+            shift,
+            eigenvalue_weight,
+            power_iterations,
+            oversampling,
+        })
+    }
+}
+
+impl FinalfrontierApp for PmiApp {
+    fn app() -> App<'static, 'static> {
+        Self::common_opts("pmi")
+            .about("Train a PMI model")
+            .arg(
+                Arg::with_name(CONTEXT)
+                    .long("context")
+                    .value_name("CONTEXT_SIZE")
+                    .help("Context size")
+                    .takes_value(true)
+                    .default_value("5"),
+            )
+            .arg(
+                Arg::with_name(CONTEXT_SMOOTHING)
+                    .long("context-smoothing")
+                    .value_name("EXP")
+                    .help("Exponent used to smooth the context distribution")
+                    .takes_value(true)
+                    .default_value("0.75"),
+            )
+            .arg(
+                Arg::with_name(EIGENVALUE_WEIGHT)
+                    .long("eigenvalue-weight")
+                    .value_name("EXP")
+                    .help("Exponent of the singular values that weight the embeddings")
+                    .takes_value(true)
+                    .default_value("0.5"),
+            )
+            .arg(
+                Arg::with_name(OVERSAMPLING)
+                    .long("oversampling")
+                    .value_name("N")
+                    .help("Number of additional random projections")
+                    .takes_value(true)
+                    .default_value("10"),
+            )
+            .arg(
+                Arg::with_name(POWER_ITERATIONS)
+                    .long("power-iterations")
+                    .value_name("N")
+                    .help("Number of power iterations")
+                    .takes_value(true)
+                    .default_value("2"),
+            )
+            .arg(
+                Arg::with_name(SHIFT)
+                    .long("shift")
+                    .value_name("K")
+                    .help("Shift PMI values by log(K)")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+    }
+
+    fn parse(matches: &ArgMatches) -> Result<Self> {
+        let corpus = Self::parse_corpus(matches)?;
+        let output = matches.value_of(Self::OUTPUT).unwrap().into();
+        let n_threads = matches
+            .value_of(Self::THREADS)
+            .map(|v| v.parse().context("Cannot parse number of threads"))
+            .transpose()?
+            .unwrap_or_else(|| cmp::min(num_cpus::get() / 2, 20));
+        let mut train_info = TrainInfo::new(corpus, output, n_threads)?;
+        if let Some(ngram_output) = Self::parse_ngram_output(matches)? {
+            train_info = train_info.with_ngram_output(ngram_output);
+        }
+        if let Some(vectors_npy) = Self::parse_vectors_npy(matches)? {
+            train_info = train_info.with_vectors_npy(vectors_npy);
+        }
+        if let Some(ann_index) = Self::parse_ann_index(matches)? {
+            train_info = train_info.with_ann_index(ann_index);
+        }
+        if let Some(config_output) = Self::parse_config_output(matches) {
+            train_info = train_info.with_config_output(config_output);
+        }
+
+        let common_config = Self::parse_common_config(&matches)?;
+        let vocab_config = Self::parse_vocab_config(common_config, &matches)?;
+        ensure!(
+            matches!(vocab_config, VocabConfig::SimpleVocab(_)),
+            "PMI models do not support subwords, use: --subwords none"
+        );
+
+        Ok(PmiApp {
+            train_info,
+            common_config,
+            pmi_config: Self::pmi_config_from_matches(&matches)?,
+            vocab_config,
+            vocab_options: Self::parse_vocab_options(common_config, &matches)?,
+        })
+    }
+
+    fn run(&self) -> Result<()> {
+        match self.vocab_config {
+            VocabConfig::SimpleVocab(config) => {
+                let vocab: SimpleVocab<String> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = add_unk(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn train<V>(vocab: V, app: &PmiApp) -> Result<()>
+where
+    V: Vocab<VocabType = String> + Into<VocabWrap>,
+    V::Config: Serialize,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let mut output_writer =
+        BufWriter::new(File::create(app.output()).context("Cannot open output file for writing.")?);
+
+    let mut trainer = PmiTrainer::new(
+        vocab,
+        app.common_config().rng::<XorShiftRng>(RngStream::Trainer),
+        app.common_config(),
+        app.pmi_config(),
+    );
+
+    let corpus = open_corpora(app.corpus(), SentenceSeparator::Line)?;
+
+    let sentences = SentenceIterator::new(corpus)
+        .with_normalization(app.common_config().normalization)
+        .with_rules(app.vocab_options.rules.clone())
+        .with_multiword(app.vocab_options.multiword.clone());
+
+    let mut matrix = CooccurrenceMatrix::default();
+    for sentence in sentences {
+        let sentence = sentence.context("Cannot read sentence")?;
+        trainer.count_cooccurrences(&sentence, &mut matrix);
+    }
+
+    eprintln!("Factorizing PMI matrix of {} co-occurrences", matrix.len());
+    trainer
+        .train(&matrix)
+        .write_model_binary(
+            &mut output_writer,
+            app.train_info().clone(),
+            app.common_config().format,
+        )
+        .context("Cannot write model")
+}
@@ -1,7 +1,7 @@
 use std::thread;
 use std::time::Duration;
 
-use finalfrontier::{CommonConfig, Sgd, Trainer, Vocab};
+use finalfrontier::{CommonConfig, LrSchedule, Sgd, Trainer, Vocab};
 use indicatif::{ProgressBar, ProgressStyle};
 
 pub fn show_progress<T, V>(config: &CommonConfig, sgd: &Sgd<T>, update_interval: Duration)
@@ -10,6 +10,7 @@ where
     V: Vocab,
 {
     let n_tokens = sgd.model().input_vocab().n_types();
+    let lr_schedule = LrSchedule::new(config, config.epochs as usize * n_tokens);
 
     let pb = ProgressBar::new(u64::from(config.epochs) * n_tokens as u64);
     pb.set_style(
@@ -17,15 +18,24 @@ where
     );
 
     while sgd.n_tokens_processed() < n_tokens * config.epochs as usize {
-        let lr = (1.0
-            - (sgd.n_tokens_processed() as f32 / (config.epochs as usize * n_tokens) as f32))
-            * config.lr;
+        let lr = lr_schedule.lr(sgd.n_tokens_processed());
 
         pb.set_position(sgd.n_tokens_processed() as u64);
+        let validation_loss = sgd
+            .validation_loss()
+            .map(|loss| format!(" validation loss: {:.*}", 5, loss))
+            .unwrap_or_default();
+        let eval_scores = sgd
+            .eval_scores()
+            .iter()
+            .map(|(name, score)| format!(" {}: {:.*}", name, 4, score))
+            .collect::<String>();
         pb.set_message(&format!(
-            "loss: {:.*} lr: {:.*}",
+            "loss: {:.*}{}{} lr: {:.*}",
             5,
             sgd.train_loss(),
+            validation_loss,
+            eval_scores,
             5,
             lr
         ));
@@ -0,0 +1,139 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+use anyhow::{ensure, Context, Result};
+use clap::{App, Arg, ArgMatches};
+use finalfrontier::vec_simd::l2_normalize;
+use finalfrontier::{retrofit, Lexicon, RetrofitConfig};
+use finalfusion::io::{ReadEmbeddings, WriteEmbeddings};
+use finalfusion::norms::NdNorms;
+use finalfusion::prelude::{Embeddings, StorageViewWrap, VocabWrap};
+use finalfusion::storage::{NdArray, StorageView};
+use finalfusion::vocab::Vocab;
+use ndarray::{s, Array1, Axis};
+
+use crate::subcommands::traits::DEFAULT_CLAP_SETTINGS;
+use crate::subcommands::FinalfrontierApp;
+
+static ALPHA: &str = "alpha";
+static INPUT: &str = "INPUT";
+static ITERATIONS: &str = "iterations";
+static LEXICON: &str = "LEXICON";
+static OUTPUT: &str = "OUTPUT";
+
+/// Subcommand for retrofitting embeddings to a semantic lexicon.
+pub struct RetrofitApp {
+    lexicon: String,
+    input: String,
+    output: String,
+    retrofit_config: RetrofitConfig,
+}
+
+impl RetrofitApp {
+    fn retrofit_config_from_matches(matches: &ArgMatches) -> Result<RetrofitConfig> {
+        let alpha = matches
+            .value_of(ALPHA)
+            .map(|v| v.parse().context("Cannot parse alpha"))
+            .transpose()?
+            .unwrap();
+        ensure!(alpha > 0f32, "Alpha should be positive, was: {}", alpha);
+        let iterations = matches
+            .value_of(ITERATIONS)
+            .map(|v| v.parse().context("Cannot parse number of iterations"))
+            .transpose()?
+            .unwrap();
+
+        Ok(RetrofitConfig { alpha, iterations })
+    }
+}
+
+impl FinalfrontierApp for RetrofitApp {
+    fn app() -> App<'static, 'static> {
+        App::new("retrofit")
+            .settings(DEFAULT_CLAP_SETTINGS)
+            .about("Retrofit embeddings to a semantic lexicon")
+            .arg(
+                Arg::with_name(ALPHA)
+                    .long("alpha")
+                    .value_name("WEIGHT")
+                    .help("Weight of the original embeddings")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::with_name(ITERATIONS)
+                    .long("iterations")
+                    .value_name("N")
+                    .help("Number of iterations")
+                    .takes_value(true)
+                    .default_value("10"),
+            )
+            .arg(
+                Arg::with_name(LEXICON)
+                    .help("Semantic lexicon")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name(INPUT)
+                    .help("Input embeddings")
+                    .index(2)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name(OUTPUT)
+                    .help("Retrofitted embeddings output")
+                    .index(3)
+                    .required(true),
+            )
+    }
+
+    fn parse(matches: &ArgMatches) -> Result<Self> {
+        Ok(RetrofitApp {
+            lexicon: matches.value_of(LEXICON).unwrap().into(),
+            input: matches.value_of(INPUT).unwrap().into(),
+            output: matches.value_of(OUTPUT).unwrap().into(),
+            retrofit_config: Self::retrofit_config_from_matches(matches)?,
+        })
+    }
+
+    fn run(&self) -> Result<()> {
+        let lexicon_file = File::open(&self.lexicon).context("Cannot open lexicon")?;
+        let lexicon = Lexicon::read(BufReader::new(lexicon_file))?;
+
+        let mut input_reader =
+            BufReader::new(File::open(&self.input).context("Cannot open input embeddings")?);
+        let embeddings: Embeddings<VocabWrap, StorageViewWrap> =
+            Embeddings::read_embeddings(&mut input_reader)
+                .context("Cannot read input embeddings")?;
+        let (metadata, vocab, storage, norms) = embeddings.into_parts();
+
+        // Retrofitting is applied to the unnormalized embeddings of words.
+        // Subword embeddings are not changed.
+        let mut matrix = storage.view().to_owned();
+        if let Some(norms) = norms {
+            for (mut embed, &norm) in matrix.outer_iter_mut().zip(norms.iter()) {
+                embed *= norm;
+            }
+        }
+
+        let neighbors = lexicon.neighbor_indices(vocab.words());
+        retrofit(
+            matrix.slice_mut(s![..vocab.words_len(), ..]),
+            &neighbors,
+            self.retrofit_config,
+        );
+
+        let mut norms = Array1::zeros(vocab.words_len());
+        for (norm, embed) in norms.iter_mut().zip(matrix.axis_iter_mut(Axis(0))) {
+            *norm = l2_normalize(embed);
+        }
+
+        let mut output_writer = BufWriter::new(
+            File::create(&self.output).context("Cannot open output file for writing.")?,
+        );
+        Embeddings::new(metadata, vocab, NdArray::new(matrix), NdNorms::new(norms))
+            .write_embeddings(&mut output_writer)
+            .context("Cannot write retrofitted embeddings")
+    }
+}
@@ -0,0 +1,227 @@
+use std::cmp;
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use clap::{App, Arg, ArgMatches};
+use finalfrontier::io::TrainInfo;
+use finalfrontier::{
+    BucketIndexerType, CommonConfig, RngStream, Sent2vecConfig, Sent2vecTrainer, SimpleVocab,
+    SubwordVocab, Vocab,
+};
+use finalfusion::compat::fasttext::FastTextIndexer;
+use finalfusion::prelude::VocabWrap;
+use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+use rand_xorshift::XorShiftRng;
+use serde::Serialize;
+
+use crate::subcommands::skipgram::{build_vocab, train_with_trainer};
+use crate::subcommands::{
+    add_bucket_statistics, add_unk, load_bpe_merges, load_sentencepiece, prune_subwords,
+    split_compounds, write_ngrams, FinalfrontierApp, VocabConfig, VocabOptions,
+};
+
+static CONTEXT_OUTPUT: &str = "context-output";
+static WORD_NGRAMS: &str = "word-ngrams";
+
+/// Subcommand for training sent2vec models.
+pub struct Sent2vecApp {
+    train_info: TrainInfo,
+    common_config: CommonConfig,
+    sent2vec_config: Sent2vecConfig,
+    vocab_config: VocabConfig,
+    segmentations: HashMap<String, Vec<String>>,
+    vocab_options: VocabOptions,
+}
+
+impl Sent2vecApp {
+    /// Get the corpus paths.
+    pub fn corpus(&self) -> &[String] {
+        &self.train_info.corpus()
+    }
+
+    /// Get the common config.
+    pub fn common_config(&self) -> CommonConfig {
+        self.common_config
+    }
+
+    /// Get the sent2vec config.
+    pub fn sent2vec_config(&self) -> Sent2vecConfig {
+        self.sent2vec_config
+    }
+
+    /// Get the vocab config.
+    pub fn vocab_config(&self) -> VocabConfig {
+        self.vocab_config.clone()
+    }
+
+    /// Get the train information.
+    pub fn train_info(&self) -> &TrainInfo {
+        &self.train_info
+    }
+
+    fn sent2vec_config_from_matches(matches: &ArgMatches) -> Result<Sent2vecConfig> {
+        let word_ngrams = matches
+            .value_of(WORD_NGRAMS)
+            .map(|v| v.parse().context("Cannot parse word n-gram length"))
+            .transpose()?
+            .unwrap();
+
+        Ok(Sent2vecConfig { word_ngrams })
+    }
+}
+
+impl FinalfrontierApp for Sent2vecApp {
+    fn app() -> App<'static, 'static> {
+        Self::common_opts("sent2vec")
+            .about("Train a sent2vec model")
+            .arg(
+                Arg::with_name(CONTEXT_OUTPUT)
+                    .long("context-output")
+                    .value_name("FILE")
+                    .help("Write the output (context) embeddings to FILE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(WORD_NGRAMS)
+                    .long("word-ngrams")
+                    .value_name("LEN")
+                    .help("Maximum length of word n-gram features")
+                    .takes_value(true)
+                    .default_value("2"),
+            )
+    }
+
+    fn parse(matches: &ArgMatches) -> Result<Self> {
+        let corpus = Self::parse_corpus(matches)?;
+        let output = matches.value_of(Self::OUTPUT).unwrap().into();
+        let n_threads = matches
+            .value_of(Self::THREADS)
+            .map(|v| v.parse().context("Cannot parse number of threads"))
+            .transpose()?
+            .unwrap_or_else(|| cmp::min(num_cpus::get() / 2, 20));
+        let mut train_info = TrainInfo::new(corpus, output, n_threads)?;
+        if let Some(context_output) = matches.value_of(CONTEXT_OUTPUT) {
+            train_info = train_info.with_context_output(context_output);
+        }
+        if let Some(ngram_output) = Self::parse_ngram_output(matches)? {
+            train_info = train_info.with_ngram_output(ngram_output);
+        }
+        if let Some(vectors_npy) = Self::parse_vectors_npy(matches)? {
+            train_info = train_info.with_vectors_npy(vectors_npy);
+        }
+        if let Some(ann_index) = Self::parse_ann_index(matches)? {
+            train_info = train_info.with_ann_index(ann_index);
+        }
+        if let Some(config_output) = Self::parse_config_output(matches) {
+            train_info = train_info.with_config_output(config_output);
+        }
+
+        let common_config = Self::parse_common_config(&matches)?;
+
+        Ok(Sent2vecApp {
+            train_info,
+            common_config,
+            sent2vec_config: Self::sent2vec_config_from_matches(&matches)?,
+            vocab_config: Self::parse_vocab_config(common_config, &matches)?,
+            segmentations: Self::parse_segmentations(&matches)?,
+            vocab_options: Self::parse_vocab_options(common_config, &matches)?,
+        })
+    }
+
+    fn run(&self) -> Result<()> {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+        eprintln!("SIMD features: {}", Self::simd_features().join(" "));
+
+        match self.vocab_config() {
+            VocabConfig::SubwordVocab(config) => match config.indexer.indexer_type {
+                BucketIndexerType::Finalfusion => {
+                    let vocab: SubwordVocab<_, FinalfusionHashIndexer> =
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, self)
+                        }
+                    }
+                }
+                BucketIndexerType::FastText => {
+                    let vocab: SubwordVocab<_, FastTextIndexer> =
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, self)
+                        }
+                    }
+                }
+            },
+            VocabConfig::SimpleVocab(config) => {
+                let vocab: SimpleVocab<String> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = add_unk(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+            VocabConfig::NGramVocab(config) => {
+                let vocab: SubwordVocab<_, ExplicitIndexer> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = split_compounds(vocab, &self.vocab_options);
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                write_ngrams(&vocab, self.vocab_options.ngrams_out.as_deref())?;
+                train(vocab, self)
+            }
+            VocabConfig::BpeVocab(config, merges) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_bpe_merges(vocab, merges.as_deref())?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+            VocabConfig::SentencePieceVocab(config, model) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_sentencepiece(vocab, &model)?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+        }
+    }
+}
+
+fn train<V>(vocab: V, app: &Sent2vecApp) -> Result<()>
+where
+    V: Vocab<VocabType = String> + Into<VocabWrap> + Clone + Send + Sync + 'static,
+    V::Config: Serialize,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let trainer = Sent2vecTrainer::new(
+        vocab,
+        app.common_config().rng::<XorShiftRng>(RngStream::Trainer),
+        app.common_config(),
+        app.sent2vec_config(),
+    );
+    train_with_trainer(
+        trainer,
+        app.train_info(),
+        app.common_config(),
+        None,
+        app.vocab_options.rules.clone(),
+        app.vocab_options.multiword.clone(),
+    )
+}
@@ -1,56 +1,106 @@
 use std::cmp;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
 use clap::{App, Arg, ArgMatches};
-use finalfrontier::io::{thread_data_text, FileProgress, TrainInfo};
+use finalfrontier::io::{
+    open_corpora, open_parquet_corpora, open_stream, CorpusFormat, Device, EmbeddingFormat,
+    ShuffleBuffer, ThreadCorpus, Tokenizer, TrainInfo, DEFAULT_TEXT_FIELD, DEFAULT_WEIGHT_FIELD,
+};
 use finalfrontier::{
-    BucketIndexerType, CommonConfig, ModelType, SentenceIterator, Sgd, SimpleVocab, SkipGramConfig,
-    SkipgramTrainer, SubwordVocab, Vocab, VocabBuilder, WriteModelBinary,
+    read_vocab_counts, write_vocab_counts, AnalogyDataset, BucketIndexerType, CbowTrainer,
+    Checkpoint, CommonConfig, CwindowTrainer, Dedup, DuplicateFilter, HtmlEntities, LanguageFilter,
+    LexicalConstraints, Lexicon, LrSchedule, LrScheduleType, ModelType, MultiwordLexicon,
+    NegativeSamples, NoiseAction, NoiseFilters, NumaTopology, OutputLabels, PoswiseTrainer, Reseed,
+    RngStream, SentenceIterator, Sgd, SimilarityDataset, SimpleVocab, SkipGramConfig,
+    SkipgramTrainer, Subsampling, SubwordVocab, ThreadCheckpoint, TokenNormalization, TokenRules,
+    TrainIterFrom, TrainModel, Trainer, Vocab, VocabBuilder, WriteModelBinary,
 };
 use finalfusion::compat::fasttext::FastTextIndexer;
 use finalfusion::prelude::VocabWrap;
-use finalfusion::subword::FinalfusionHashIndexer;
-use rand::{Rng, SeedableRng};
+use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+use indicatif::ProgressBar;
+use rand::{RngCore, SeedableRng};
 use rand_xorshift::XorShiftRng;
 use serde::Serialize;
+use tempfile::NamedTempFile;
 
-use crate::subcommands::{show_progress, FinalfrontierApp, VocabConfig};
+use crate::subcommands::{
+    add_bucket_statistics, add_unk, cutoff_from_matches, load_bpe_merges, load_sentencepiece,
+    prune_subwords, show_progress, split_compounds, write_ngrams, FinalfrontierApp, VocabConfig,
+    VocabOptions,
+};
 
+static ATTRACT: &str = "attract";
+static BATCH_SIZE: &str = "batch-size";
+static CHECKPOINT_INTERVAL: &str = "checkpoint-interval";
+static CONSTRAINT_WEIGHT: &str = "constraint_weight";
 static CONTEXT: &str = "context";
+static CONTEXT_MINCOUNT: &str = "context-mincount";
+static CONTEXT_OUTPUT: &str = "context-output";
+static CONTEXT_TARGET_SIZE: &str = "context-target-size";
+static CORPUS_FORMAT: &str = "corpus-format";
+static DEDUP: &str = "dedup";
+static DEDUP_MEMORY: &str = "dedup-memory";
+static DEVICE: &str = "device";
+static EMAILS: &str = "emails";
+static EVAL_ANALOGY: &str = "eval-analogy";
+static EVAL_INTERVAL: &str = "eval-interval";
+static EVAL_SIMILARITY: &str = "eval-similarity";
+static HANDLES: &str = "handles";
+static HARMONIC: &str = "harmonic";
+static HTML_ENTITIES: &str = "html-entities";
+static LANG: &str = "lang";
+static LANG_FIELD: &str = "lang-field";
 static MODEL: &str = "model";
+static NUMA: &str = "numa";
+static REPEL: &str = "repel";
+static RESUME: &str = "resume";
+static SAVE_EVERY_EPOCH: &str = "save-every-epoch";
+static SHUFFLE_BUFFER: &str = "shuffle-buffer";
+static SNAPSHOT_INTERVAL: &str = "snapshot-interval";
+static STREAM: &str = "stream";
+static TEXT_FIELD: &str = "text-field";
+static TOKENIZE: &str = "tokenize";
+static URLS: &str = "urls";
+static VALIDATION: &str = "validation";
+static VALIDATION_INTERVAL: &str = "validation-interval";
+static WEIGHTS: &str = "weights";
+static WEIGHT_FIELD: &str = "weight-field";
+static WORD_NGRAMS: &str = "word-ngrams";
 
 const PROGRESS_UPDATE_INTERVAL: u64 = 200;
 
+/// Computation of the loss of a sentence, see `Sgd::sentence_loss`.
+pub(crate) type SentenceLoss<T> = fn(&mut Sgd<T>, &[String], u64) -> (f32, usize);
+
 /// Subcommand for training skipgram models.
 pub struct SkipgramApp {
     train_info: TrainInfo,
     common_config: CommonConfig,
     skipgram_config: SkipGramConfig,
     vocab_config: VocabConfig,
+    segmentations: HashMap<String, Vec<String>>,
+    vocab_options: VocabOptions,
+    attract: Option<String>,
+    repel: Option<String>,
+    constraint_weight: f32,
 }
 
 impl SkipgramApp {
-    /// Get the corpus path.
-    pub fn corpus(&self) -> &str {
+    /// Get the corpus paths.
+    pub fn corpus(&self) -> &[String] {
         &self.train_info.corpus()
     }
 
-    /// Get the output path.
-    pub fn output(&self) -> &str {
-        &self.train_info.output()
-    }
-
-    /// Get the number of threads.
-    pub fn n_threads(&self) -> usize {
-        self.train_info.n_threads()
-    }
-
     /// Get the common config.
     pub fn common_config(&self) -> CommonConfig {
         self.common_config
@@ -63,7 +113,7 @@ impl SkipgramApp {
 
     /// Get the vocab config.
     pub fn vocab_config(&self) -> VocabConfig {
-        self.vocab_config
+        self.vocab_config.clone()
     }
 
     /// Get the train information.
@@ -71,6 +121,40 @@ impl SkipgramApp {
         &self.train_info
     }
 
+    /// Read the lexical constraints.
+    ///
+    /// Returns `None` if no constraint files were given.
+    fn read_constraints<V>(&self, vocab: &V) -> Result<Option<Arc<LexicalConstraints>>>
+    where
+        V: Vocab<VocabType = String>,
+        for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+    {
+        if self.attract.is_none() && self.repel.is_none() {
+            return Ok(None);
+        }
+
+        let mut constraints = LexicalConstraints::new(self.constraint_weight);
+        for (path, attract) in self
+            .attract
+            .iter()
+            .map(|path| (path, true))
+            .chain(self.repel.iter().map(|path| (path, false)))
+        {
+            let f = File::open(path)
+                .with_context(|| format!("Cannot open constraints file: {}", path))?;
+            let lexicon = Lexicon::read(BufReader::new(f))
+                .with_context(|| format!("Cannot read constraints file: {}", path))?;
+            let n_constraints = constraints.add_lexicon(&lexicon, vocab, attract);
+            eprintln!(
+                "Read {} {} constraints",
+                n_constraints,
+                if attract { "attract" } else { "repel" }
+            );
+        }
+
+        Ok(Some(Arc::new(constraints)))
+    }
+
     fn skipgram_config_from_matches(matches: &ArgMatches) -> Result<SkipGramConfig> {
         let context_size = matches
             .value_of(CONTEXT)
@@ -83,9 +167,55 @@ impl SkipgramApp {
             .transpose()?
             .unwrap();
 
+        let harmonic_weighting = matches.is_present(HARMONIC);
+        ensure!(
+            !harmonic_weighting
+                || matches!(
+                    model,
+                    ModelType::SkipGram
+                        | ModelType::StructuredSkipGram
+                        | ModelType::DirectionalSkipgram
+                ),
+            "Harmonic weighting is not supported by the {} model",
+            matches.value_of(MODEL).unwrap()
+        );
+
+        let context_cutoff = cutoff_from_matches(matches, CONTEXT_MINCOUNT, CONTEXT_TARGET_SIZE)?;
+        ensure!(
+            context_cutoff.is_none()
+                || matches!(
+                    model,
+                    ModelType::SkipGram
+                        | ModelType::StructuredSkipGram
+                        | ModelType::DirectionalSkipgram
+                ),
+            "A separate context vocabulary is not supported by the {} model",
+            matches.value_of(MODEL).unwrap()
+        );
+
+        let word_ngrams = matches
+            .value_of(WORD_NGRAMS)
+            .map(|v| v.parse().context("Cannot parse word n-gram length"))
+            .transpose()?
+            .unwrap();
+        ensure!(
+            word_ngrams <= 1
+                || matches!(
+                    model,
+                    ModelType::SkipGram
+                        | ModelType::StructuredSkipGram
+                        | ModelType::DirectionalSkipgram
+                ),
+            "Word n-grams are not supported by the {} model",
+            matches.value_of(MODEL).unwrap()
+        );
+
         Ok(SkipGramConfig {
             model,
             context_size,
+            harmonic_weighting,
+            context_cutoff,
+            word_ngrams,
         })
     }
 }
@@ -93,7 +223,37 @@ impl SkipgramApp {
 impl FinalfrontierApp for SkipgramApp {
     fn app() -> App<'static, 'static> {
         Self::common_opts("skipgram")
-            .about("Train a skip-gram model")
+            .about("Train a skip-gram or CBOW model")
+            .arg(
+                Arg::with_name(ATTRACT)
+                    .long("attract")
+                    .value_name("FILE")
+                    .help("Lexicon with synonyms whose embeddings should attract")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(BATCH_SIZE)
+                    .long("batch-size")
+                    .value_name("N")
+                    .help("Update the parameters once per batch of N training instances")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(CHECKPOINT_INTERVAL)
+                    .long("checkpoint-interval")
+                    .value_name("SECS")
+                    .help("Seconds between training checkpoints, written to OUTPUT.checkpoint")
+                    .takes_value(true)
+                    .conflicts_with(STREAM),
+            )
+            .arg(
+                Arg::with_name(CONSTRAINT_WEIGHT)
+                    .long("constraint-weight")
+                    .value_name("WEIGHT")
+                    .help("Learning rate scale of lexical constraint updates")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
             .arg(
                 Arg::with_name(CONTEXT)
                     .long("context")
@@ -102,60 +262,628 @@ impl FinalfrontierApp for SkipgramApp {
                     .takes_value(true)
                     .default_value("10"),
             )
+            .arg(
+                Arg::with_name(CONTEXT_MINCOUNT)
+                    .long("context-mincount")
+                    .value_name("FREQ")
+                    .help("Minimum count of context words. Default: all words are contexts")
+                    .takes_value(true)
+                    .conflicts_with(CONTEXT_TARGET_SIZE),
+            )
+            .arg(
+                Arg::with_name(CONTEXT_OUTPUT)
+                    .long("context-output")
+                    .value_name("FILE")
+                    .help("Write the output (context) embeddings to FILE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(CONTEXT_TARGET_SIZE)
+                    .long("context-target-size")
+                    .value_name("SIZE")
+                    .help("Target size of the context vocabulary")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(CORPUS_FORMAT)
+                    .long("corpus-format")
+                    .value_name("FORMAT")
+                    .help("Corpus format")
+                    .takes_value(true)
+                    .possible_values(&["conllu", "jsonl", "parquet", "text", "vertical"])
+                    .default_value("text"),
+            )
+            .arg(
+                Arg::with_name(DEVICE)
+                    .long("device")
+                    .value_name("DEVICE")
+                    .help("Device for batched updates")
+                    .takes_value(true)
+                    .possible_values(&["cpu", "gpu"])
+                    .default_value("cpu"),
+            )
+            .arg(
+                Arg::with_name(DEDUP)
+                    .long("dedup")
+                    .value_name("METHOD")
+                    .help("Skip duplicate sentences")
+                    .takes_value(true)
+                    .possible_values(&["bloom", "exact"]),
+            )
+            .arg(
+                Arg::with_name(DEDUP_MEMORY)
+                    .long("dedup-memory")
+                    .value_name("MB")
+                    .help("Maximum memory use of sentence deduplication")
+                    .takes_value(true)
+                    .default_value("1024"),
+            )
+            .arg(
+                Arg::with_name(EMAILS)
+                    .long("emails")
+                    .value_name("ACTION")
+                    .help("Keep, drop, or replace e-mail addresses by a placeholder")
+                    .takes_value(true)
+                    .possible_values(&["drop", "keep", "placeholder"])
+                    .default_value("keep"),
+            )
+            .arg(
+                Arg::with_name(EVAL_ANALOGY)
+                    .long("eval-analogy")
+                    .value_name("FILE")
+                    .help("Evaluate on the word analogy dataset in FILE during training")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .conflicts_with(STREAM),
+            )
+            .arg(
+                Arg::with_name(EVAL_INTERVAL)
+                    .long("eval-interval")
+                    .value_name("N")
+                    .help("Number of tokens between evaluations")
+                    .takes_value(true)
+                    .default_value("1000000"),
+            )
+            .arg(
+                Arg::with_name(EVAL_SIMILARITY)
+                    .long("eval-similarity")
+                    .value_name("FILE")
+                    .help("Evaluate on the word similarity dataset in FILE during training")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .conflicts_with(STREAM),
+            )
+            .arg(
+                Arg::with_name(HANDLES)
+                    .long("handles")
+                    .value_name("ACTION")
+                    .help("Keep, drop, or replace @handles by a placeholder")
+                    .takes_value(true)
+                    .possible_values(&["drop", "keep", "placeholder"])
+                    .default_value("keep"),
+            )
+            .arg(
+                Arg::with_name(HARMONIC)
+                    .long("harmonic")
+                    .help("Weight contexts by the inverse of their distance"),
+            )
+            .arg(
+                Arg::with_name(HTML_ENTITIES)
+                    .long("html-entities")
+                    .value_name("ACTION")
+                    .help("Keep, decode, or remove HTML entities")
+                    .takes_value(true)
+                    .possible_values(&["decode", "keep", "remove"])
+                    .default_value("keep"),
+            )
+            .arg(
+                Arg::with_name(LANG)
+                    .long("lang")
+                    .value_name("CODE")
+                    .help("Skip sentences that are not in the language with the ISO 639 code CODE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(LANG_FIELD)
+                    .long("lang-field")
+                    .value_name("FIELD")
+                    .help("Field with the language of JSON Lines records")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name(MODEL)
                     .long(MODEL)
                     .value_name("MODEL")
                     .help("Model")
                     .takes_value(true)
-                    .possible_values(&["dirgram", "skipgram", "structgram"])
+                    .possible_values(&[
+                        "cbow",
+                        "cwindow",
+                        "dirgram",
+                        "poswise",
+                        "skipgram",
+                        "structgram",
+                    ])
                     .default_value("skipgram"),
             )
+            .arg(
+                Arg::with_name(NUMA)
+                    .long("numa")
+                    .help("Pin threads to NUMA nodes and interleave the matrices over the nodes"),
+            )
+            .arg(
+                Arg::with_name(REPEL)
+                    .long("repel")
+                    .value_name("FILE")
+                    .help("Lexicon with antonyms whose embeddings should repel")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(RESUME)
+                    .long("resume")
+                    .value_name("CHECKPOINT")
+                    .help("Resume training from a checkpoint")
+                    .takes_value(true)
+                    .conflicts_with(STREAM),
+            )
+            .arg(
+                Arg::with_name(SAVE_EVERY_EPOCH)
+                    .long("save-every-epoch")
+                    .help("Write a model file for every completed epoch")
+                    .conflicts_with(STREAM),
+            )
+            .arg(
+                Arg::with_name(SHUFFLE_BUFFER)
+                    .long("shuffle-buffer")
+                    .value_name("N")
+                    .help("Shuffle sentences in a buffer of N sentences")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(SNAPSHOT_INTERVAL)
+                    .long("snapshot-interval")
+                    .value_name("SECS")
+                    .help("Seconds between model snapshots when training on a stream")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(STREAM)
+                    .long("stream")
+                    .help("Train continuously on a stream of sentences until it ends"),
+            )
+            .arg(
+                Arg::with_name(TEXT_FIELD)
+                    .long("text-field")
+                    .value_name("FIELD")
+                    .help("Field with the text of JSON Lines or Parquet records")
+                    .takes_value(true)
+                    .default_value(DEFAULT_TEXT_FIELD),
+            )
+            .arg(
+                Arg::with_name(TOKENIZE)
+                    .long("tokenize")
+                    .help("Split off punctuation, for corpora that are not tokenized"),
+            )
+            .arg(
+                Arg::with_name(URLS)
+                    .long("urls")
+                    .value_name("ACTION")
+                    .help("Keep, drop, or replace URLs by a placeholder")
+                    .takes_value(true)
+                    .possible_values(&["drop", "keep", "placeholder"])
+                    .default_value("keep"),
+            )
+            .arg(
+                Arg::with_name(VALIDATION)
+                    .long("validation")
+                    .value_name("FILE")
+                    .help("Compute the loss on the held-out corpus in FILE during training")
+                    .takes_value(true)
+                    .conflicts_with(STREAM),
+            )
+            .arg(
+                Arg::with_name(VALIDATION_INTERVAL)
+                    .long("validation-interval")
+                    .value_name("SECS")
+                    .help("Seconds between validation loss computations")
+                    .takes_value(true)
+                    .default_value("60"),
+            )
+            .arg(
+                Arg::with_name(WEIGHTS)
+                    .long("weights")
+                    .help("Scale the learning rate of sentences by their weights in the corpus"),
+            )
+            .arg(
+                Arg::with_name(WEIGHT_FIELD)
+                    .long("weight-field")
+                    .value_name("FIELD")
+                    .help("Field with the weight of JSON Lines records")
+                    .takes_value(true)
+                    .default_value(DEFAULT_WEIGHT_FIELD),
+            )
+            .arg(
+                Arg::with_name(WORD_NGRAMS)
+                    .long("word-ngrams")
+                    .value_name("LEN")
+                    .help("Maximum length of word n-grams added to focus words")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
     }
 
     fn parse(matches: &ArgMatches) -> Result<Self> {
-        let corpus = matches.value_of(Self::CORPUS).unwrap().into();
+        let corpus = Self::parse_corpus(matches)?;
         let output = matches.value_of(Self::OUTPUT).unwrap().into();
         let n_threads = matches
             .value_of(Self::THREADS)
             .map(|v| v.parse().context("Cannot parse number of threads"))
             .transpose()?
             .unwrap_or_else(|| cmp::min(num_cpus::get() / 2, 20));
-        let train_info = TrainInfo::new(corpus, output, n_threads);
+        let corpus_format = matches
+            .value_of(CORPUS_FORMAT)
+            .map(CorpusFormat::try_from)
+            .transpose()?
+            .unwrap();
+        let tokenizer = if matches.is_present(TOKENIZE) {
+            Tokenizer::Punctuation
+        } else {
+            Tokenizer::Whitespace
+        };
+        ensure!(
+            tokenizer == Tokenizer::Whitespace
+                || !matches!(corpus_format, CorpusFormat::Conllu | CorpusFormat::Vertical),
+            "CoNLL-U and vertical corpora cannot be tokenized"
+        );
+        let noise = NoiseFilters {
+            urls: NoiseAction::try_from(matches.value_of(URLS).unwrap())?,
+            emails: NoiseAction::try_from(matches.value_of(EMAILS).unwrap())?,
+            handles: NoiseAction::try_from(matches.value_of(HANDLES).unwrap())?,
+            html_entities: HtmlEntities::try_from(matches.value_of(HTML_ENTITIES).unwrap())?,
+        };
+        let text_field = matches.value_of(TEXT_FIELD).unwrap();
+        let stream = matches.is_present(STREAM);
+        let mut train_info = if stream {
+            ensure!(
+                corpus.len() == 1,
+                "Training on a stream requires exactly one corpus"
+            );
+            ensure!(
+                corpus_format != CorpusFormat::Parquet,
+                "Parquet corpora cannot be streamed"
+            );
+            let mut train_info = TrainInfo::new_stream(corpus[0].clone(), output, n_threads);
+            if let Some(snapshot_interval) = matches
+                .value_of(SNAPSHOT_INTERVAL)
+                .map(|v| v.parse().context("Cannot parse snapshot interval"))
+                .transpose()?
+            {
+                ensure!(
+                    snapshot_interval > 0,
+                    "The snapshot interval should be positive"
+                );
+                train_info = train_info.with_snapshot_interval(snapshot_interval);
+            }
+            train_info
+        } else {
+            ensure!(
+                !matches.is_present(SNAPSHOT_INTERVAL),
+                "Snapshots can only be written when training on a stream"
+            );
+            let mut train_info = TrainInfo::new(corpus, output, n_threads)?;
+            if let Some(checkpoint_interval) = matches
+                .value_of(CHECKPOINT_INTERVAL)
+                .map(|v| v.parse().context("Cannot parse checkpoint interval"))
+                .transpose()?
+            {
+                ensure!(
+                    checkpoint_interval > 0,
+                    "The checkpoint interval should be positive"
+                );
+                train_info = train_info.with_checkpoint_interval(checkpoint_interval);
+            }
+            if let Some(resume) = matches.value_of(RESUME) {
+                train_info = train_info.with_resume(resume);
+            }
+            if let Some(validation) = matches.value_of(VALIDATION) {
+                let validation_interval = matches
+                    .value_of(VALIDATION_INTERVAL)
+                    .map(|v| v.parse().context("Cannot parse validation interval"))
+                    .transpose()?
+                    .unwrap();
+                ensure!(
+                    validation_interval > 0,
+                    "The validation interval should be positive"
+                );
+                train_info = train_info.with_validation(validation, validation_interval);
+            }
+            let eval_similarity = matches
+                .values_of(EVAL_SIMILARITY)
+                .map(|v| v.map(ToOwned::to_owned).collect::<Vec<_>>())
+                .unwrap_or_default();
+            let eval_analogy = matches
+                .values_of(EVAL_ANALOGY)
+                .map(|v| v.map(ToOwned::to_owned).collect::<Vec<_>>())
+                .unwrap_or_default();
+            if !eval_similarity.is_empty() || !eval_analogy.is_empty() {
+                let eval_interval = matches
+                    .value_of(EVAL_INTERVAL)
+                    .map(|v| v.parse().context("Cannot parse evaluation interval"))
+                    .transpose()?
+                    .unwrap();
+                ensure!(
+                    eval_interval > 0,
+                    "The evaluation interval should be positive"
+                );
+                train_info = train_info.with_eval(eval_similarity, eval_analogy, eval_interval);
+            }
+            train_info.with_save_every_epoch(matches.is_present(SAVE_EVERY_EPOCH))
+        }
+        .with_corpus_format(corpus_format)
+        .with_tokenizer(tokenizer)
+        .with_noise(noise)
+        .with_numa(matches.is_present(NUMA));
+        if matches!(corpus_format, CorpusFormat::Jsonl | CorpusFormat::Parquet) {
+            train_info = train_info.with_text_field(text_field);
+        }
+        if let Some(context_output) = matches.value_of(CONTEXT_OUTPUT) {
+            train_info = train_info.with_context_output(context_output);
+        }
+        if let Some(ngram_output) = Self::parse_ngram_output(matches)? {
+            train_info = train_info.with_ngram_output(ngram_output);
+        }
+        if let Some(vectors_npy) = Self::parse_vectors_npy(matches)? {
+            train_info = train_info.with_vectors_npy(vectors_npy);
+        }
+        if let Some(ann_index) = Self::parse_ann_index(matches)? {
+            train_info = train_info.with_ann_index(ann_index);
+        }
+        if let Some(config_output) = Self::parse_config_output(matches) {
+            train_info = train_info.with_config_output(config_output);
+        }
+        let weights = matches.is_present(WEIGHTS);
+        ensure!(
+            !weights || matches!(corpus_format, CorpusFormat::Text | CorpusFormat::Jsonl),
+            "Sentence weights can only be read from text and JSON Lines corpora"
+        );
+        let weight_field = matches.value_of(WEIGHT_FIELD).unwrap();
+        if weights {
+            train_info = train_info.with_weights();
+            if corpus_format == CorpusFormat::Jsonl {
+                train_info = train_info.with_weight_field(weight_field);
+            }
+        }
+        if let Some(shuffle_buffer) = matches
+            .value_of(SHUFFLE_BUFFER)
+            .map(|v| v.parse().context("Cannot parse shuffle buffer size"))
+            .transpose()?
+        {
+            ensure!(
+                shuffle_buffer > 0,
+                "The shuffle buffer should hold at least one sentence"
+            );
+            train_info = train_info.with_shuffle_buffer(shuffle_buffer);
+        }
+        let language = match matches.value_of(LANG) {
+            Some(lang) => {
+                let mut language = LanguageFilter::new(lang)?;
+                if let Some(field) = matches.value_of(LANG_FIELD) {
+                    ensure!(
+                        corpus_format == CorpusFormat::Jsonl,
+                        "Languages can only be read from JSON Lines corpora"
+                    );
+                    language = language.with_field(field);
+                }
+                train_info = train_info.with_language(language.clone());
+                Some(language)
+            }
+            None => {
+                ensure!(
+                    !matches.is_present(LANG_FIELD),
+                    "A language field requires a language (--lang)"
+                );
+                None
+            }
+        };
+        let dedup = match matches.value_of(DEDUP).map(Dedup::try_from).transpose()? {
+            Some(dedup) => {
+                let dedup_memory: usize = matches
+                    .value_of(DEDUP_MEMORY)
+                    .map(|v| v.parse().context("Cannot parse deduplication memory"))
+                    .transpose()?
+                    .unwrap();
+                ensure!(
+                    dedup_memory > 0,
+                    "The deduplication memory should be positive"
+                );
+                let dedup_memory = dedup_memory * 1024 * 1024;
+                train_info = train_info.with_dedup(dedup, dedup_memory);
+                Some((dedup, dedup_memory))
+            }
+            None => None,
+        };
 
         let common_config = Self::parse_common_config(&matches)?;
+        let skipgram_config = Self::skipgram_config_from_matches(&matches)?;
+        ensure!(
+            !common_config.tied
+                || matches!(
+                    skipgram_config.model,
+                    ModelType::SkipGram | ModelType::Cbow | ModelType::PositionWeighted
+                ),
+            "Tied embeddings are not supported by the {} model",
+            matches.value_of(MODEL).unwrap()
+        );
+        ensure!(
+            !matches.is_present(VALIDATION)
+                || !matches!(
+                    skipgram_config.model,
+                    ModelType::ConcatenatedWindow | ModelType::PositionWeighted
+                ),
+            "The validation loss cannot be computed for the {} model",
+            matches.value_of(MODEL).unwrap()
+        );
+
+        let constraint_weight = matches
+            .value_of(CONSTRAINT_WEIGHT)
+            .map(|v| v.parse().context("Cannot parse constraint weight"))
+            .transpose()?
+            .unwrap();
+        ensure!(
+            constraint_weight > 0f32,
+            "Constraint weight should be positive, was: {}",
+            constraint_weight
+        );
+
+        if let Some(batch_size) = matches
+            .value_of(BATCH_SIZE)
+            .map(|v| v.parse().context("Cannot parse batch size"))
+            .transpose()?
+        {
+            ensure!(batch_size > 0, "The batch size should be positive");
+            ensure!(
+                batch_size == 1
+                    || matches!(
+                        skipgram_config.model,
+                        ModelType::SkipGram
+                            | ModelType::StructuredSkipGram
+                            | ModelType::DirectionalSkipgram
+                            | ModelType::Cbow
+                    ),
+                "Batched updates are not supported by the {} model",
+                matches.value_of(MODEL).unwrap()
+            );
+            train_info = train_info.with_batch_size(batch_size);
+        }
+
+        let device = matches
+            .value_of(DEVICE)
+            .map(Device::try_from)
+            .transpose()?
+            .unwrap();
+        if device == Device::Gpu {
+            ensure!(
+                cfg!(feature = "gpu"),
+                "Training on the GPU requires finalfrontier to be built with the gpu feature"
+            );
+            ensure!(
+                train_info.batch_size().unwrap_or(1) > 1,
+                "Training on the GPU requires batched updates (--batch-size)"
+            );
+            ensure!(
+                !common_config.ns_shared && common_config.batch_negatives == 0,
+                "Shared and in-batch negatives are not supported on the GPU"
+            );
+            train_info = train_info.with_device(device);
+        }
+
+        let mut vocab_options = Self::parse_vocab_options(common_config, &matches)?;
+        if stream {
+            // A stream can only be read once, so it cannot be counted
+            // before training.
+            ensure!(
+                vocab_options.vocab_in.is_some(),
+                "Training on a stream requires vocabulary counts (--vocab-in)"
+            );
+            ensure!(
+                matches!(common_config.lr_schedule, LrScheduleType::Constant),
+                "Training on a stream requires a constant learning rate (--lr-schedule constant)"
+            );
+        }
+        vocab_options.corpus_format = corpus_format;
+        vocab_options.tokenizer = tokenizer;
+        vocab_options.noise = noise;
+        vocab_options.text_field = text_field.to_owned();
+        vocab_options.weights = weights;
+        vocab_options.weight_field = weight_field.to_owned();
+        vocab_options.dedup = dedup;
+        vocab_options.language = language;
 
         Ok(SkipgramApp {
             train_info,
             common_config,
-            skipgram_config: Self::skipgram_config_from_matches(&matches)?,
+            skipgram_config,
             vocab_config: Self::parse_vocab_config(common_config, &matches)?,
+            segmentations: Self::parse_segmentations(&matches)?,
+            vocab_options,
+            attract: matches.value_of(ATTRACT).map(ToOwned::to_owned),
+            repel: matches.value_of(REPEL).map(ToOwned::to_owned),
+            constraint_weight,
         })
     }
 
     fn run(&self) -> Result<()> {
-        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
         eprintln!("SIMD features: {}", Self::simd_features().join(" "));
 
         match self.vocab_config() {
             VocabConfig::SubwordVocab(config) => match config.indexer.indexer_type {
                 BucketIndexerType::Finalfusion => {
                     let vocab: SubwordVocab<_, FinalfusionHashIndexer> =
-                        build_vocab(config, self.corpus())?;
-                    train(vocab, self)
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, self)
+                        }
+                    }
                 }
                 BucketIndexerType::FastText => {
                     let vocab: SubwordVocab<_, FastTextIndexer> =
-                        build_vocab(config, self.corpus())?;
-                    train(vocab, self)
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, self)
+                        }
+                    }
                 }
             },
             VocabConfig::SimpleVocab(config) => {
-                let vocab: SimpleVocab<String> = build_vocab(config, self.corpus())?;
+                let vocab: SimpleVocab<String> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = add_unk(vocab, &self.vocab_options);
                 train(vocab, self)
             }
             VocabConfig::NGramVocab(config) => {
-                let vocab: SubwordVocab<_, _> = build_vocab(config, self.corpus())?;
+                let vocab: SubwordVocab<_, ExplicitIndexer> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = split_compounds(vocab, &self.vocab_options);
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                write_ngrams(&vocab, self.vocab_options.ngrams_out.as_deref())?;
+                train(vocab, self)
+            }
+            VocabConfig::BpeVocab(config, merges) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_bpe_merges(vocab, merges.as_deref())?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                train(vocab, self)
+            }
+            VocabConfig::SentencePieceVocab(config, model) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_sentencepiece(vocab, &model)?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
                 train(vocab, self)
             }
         }
@@ -169,35 +897,407 @@ where
     for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
 {
     let common_config = app.common_config();
-    let n_threads = app.n_threads();
-    let corpus = app.corpus();
-    let mut output_writer =
-        BufWriter::new(File::create(app.output()).context("Cannot open output file for writing.")?);
-    let trainer = SkipgramTrainer::new(
-        vocab,
-        XorShiftRng::from_entropy(),
+    let skipgram_config = app.skipgram_config();
+    let constraints = app.read_constraints(&vocab)?;
+    let rules = app.vocab_options.rules.clone();
+    let multiword = app.vocab_options.multiword.clone();
+    match skipgram_config.model {
+        ModelType::Cbow => {
+            let trainer = CbowTrainer::new(
+                vocab,
+                common_config.rng::<XorShiftRng>(RngStream::Trainer),
+                common_config,
+                skipgram_config,
+            );
+            train_with_trainer(
+                trainer,
+                app.train_info(),
+                common_config,
+                constraints,
+                rules,
+                multiword,
+            )
+        }
+        ModelType::ConcatenatedWindow => {
+            let trainer = CwindowTrainer::new(
+                vocab,
+                common_config.rng::<XorShiftRng>(RngStream::Trainer),
+                common_config,
+                skipgram_config,
+            );
+            train_with_update(
+                trainer,
+                app.train_info(),
+                common_config,
+                constraints,
+                rules,
+                multiword,
+                |sgd, sentence, lr| sgd.update_sentence_concat(sentence, lr),
+                None,
+            )
+        }
+        ModelType::PositionWeighted => {
+            let trainer = PoswiseTrainer::new(
+                vocab,
+                common_config.rng::<XorShiftRng>(RngStream::Trainer),
+                common_config,
+                skipgram_config,
+            );
+            train_with_update(
+                trainer,
+                app.train_info(),
+                common_config,
+                constraints,
+                rules,
+                multiword,
+                |sgd, sentence, lr| sgd.update_sentence_positional(sentence, lr),
+                None,
+            )
+        }
+        _ => {
+            let trainer = SkipgramTrainer::new(
+                vocab,
+                common_config.rng::<XorShiftRng>(RngStream::Trainer),
+                common_config,
+                skipgram_config,
+            );
+            train_with_trainer(
+                trainer,
+                app.train_info(),
+                common_config,
+                constraints,
+                rules,
+                multiword,
+            )
+        }
+    }
+}
+
+pub(crate) fn train_with_trainer<T, V, F>(
+    trainer: T,
+    train_info: &TrainInfo,
+    common_config: CommonConfig,
+    constraints: Option<Arc<LexicalConstraints>>,
+    rules: Arc<TokenRules>,
+    multiword: Arc<MultiwordLexicon>,
+) -> Result<()>
+where
+    T: Trainer<InputVocab = V>
+        + for<'a> TrainIterFrom<'a, [String], Focus = F>
+        + NegativeSamples
+        + OutputLabels
+        + Reseed
+        + Subsampling
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    T::Metadata: Serialize,
+    V: Vocab<VocabType = String> + Into<VocabWrap>,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+    for<'a> &'a F: IntoIterator<Item = u64>,
+{
+    train_with_update(
+        trainer,
+        train_info,
         common_config,
-        app.skipgram_config(),
+        constraints,
+        rules,
+        multiword,
+        |sgd, sentence, lr| sgd.update_sentence(sentence, lr),
+        Some(|sgd, sentence, seed| sgd.sentence_loss(sentence, seed)),
+    )
+}
+
+/// Train a model, updating the parameters with `update` for each sentence.
+///
+/// The loss on a validation corpus is computed with `sentence_loss`. The
+/// validation loss is not available when `sentence_loss` is `None`.
+#[allow(clippy::too_many_arguments)]
+fn train_with_update<T, V, U>(
+    trainer: T,
+    train_info: &TrainInfo,
+    common_config: CommonConfig,
+    constraints: Option<Arc<LexicalConstraints>>,
+    rules: Arc<TokenRules>,
+    multiword: Arc<MultiwordLexicon>,
+    update: U,
+    sentence_loss: Option<SentenceLoss<T>>,
+) -> Result<()>
+where
+    T: Trainer<InputVocab = V>
+        + NegativeSamples
+        + OutputLabels
+        + Reseed
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    T::Metadata: Serialize,
+    V: Vocab<VocabType = String> + Into<VocabWrap>,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+    U: Fn(&mut Sgd<T>, &[String], f32) + Copy + Send + 'static,
+{
+    if train_info.stream() {
+        let model = train_model_stream(
+            trainer,
+            train_info,
+            common_config,
+            constraints,
+            rules,
+            multiword,
+            update,
+        )?;
+        write_context_embeddings(&model, train_info)?;
+        return write_model_replace(model, train_info, common_config.format);
+    }
+
+    let mut output_writer = BufWriter::new(
+        File::create(train_info.output()).context("Cannot open output file for writing.")?,
     );
-    let sgd = Sgd::new(trainer.into());
 
+    let mut train_info = train_info.clone();
+    let model = train_model(
+        trainer,
+        &mut train_info,
+        common_config,
+        constraints,
+        rules,
+        multiword,
+        update,
+        sentence_loss,
+    )?;
+    write_context_embeddings(&model, &train_info)?;
+    model
+        .write_model_binary(&mut output_writer, train_info, common_config.format)
+        .context("Cannot write model")
+}
+
+/// Write the output embeddings of `model` if a context output file is set.
+fn write_context_embeddings<T>(model: &TrainModel<T>, train_info: &TrainInfo) -> Result<()>
+where
+    T: Trainer + OutputLabels,
+{
+    if let Some(context_output) = train_info.context_output() {
+        let mut writer = BufWriter::new(
+            File::create(context_output)
+                .context("Cannot open context embeddings output file for writing.")?,
+        );
+        model
+            .write_output_embeddings(&mut writer)
+            .context("Cannot write context embeddings")?;
+    }
+
+    Ok(())
+}
+
+/// Train a model, updating the parameters with `update` for each sentence.
+///
+/// If `constraints` is given, a lexical constraint is applied after each
+/// sentence. The token `rules` are applied to the corpus sentences and
+/// the expressions in the `multiword` lexicon are merged. Returns the
+/// trained model.
+///
+/// If a checkpoint interval is set, a checkpoint of the training state is
+/// written after every interval. When training resumes from a checkpoint,
+/// the parameters, optimizer states, and number of processed tokens are
+/// restored and training continues until the remaining tokens are
+/// processed. Every thread continues at the sentence and with the random
+/// number generator seeds of its checkpointed position, so that a resumed
+/// single-threaded run is identical to an uninterrupted run.
+///
+/// If model files should be saved for every epoch, a snapshot of the
+/// model is written when an epoch is completed.
+///
+/// If a validation corpus is set, the loss on the validation corpus is
+/// computed with `sentence_loss` after every validation interval and
+/// after training. The final validation loss is stored in `train_info`.
+///
+/// If evaluation datasets are set, the word embeddings are evaluated
+/// after every evaluation interval and after training. The final scores
+/// are stored in `train_info`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn train_model<T, V, U>(
+    trainer: T,
+    train_info: &mut TrainInfo,
+    common_config: CommonConfig,
+    constraints: Option<Arc<LexicalConstraints>>,
+    rules: Arc<TokenRules>,
+    multiword: Arc<MultiwordLexicon>,
+    update: U,
+    sentence_loss: Option<SentenceLoss<T>>,
+) -> Result<TrainModel<T>>
+where
+    T: Trainer<InputVocab = V> + NegativeSamples + Reseed + Clone + Send + Sync + 'static,
+    T::Metadata: Serialize,
+    V: Vocab<VocabType = String> + Into<VocabWrap>,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+    U: Fn(&mut Sgd<T>, &[String], f32) + Copy + Send + 'static,
+{
+    let n_threads = train_info.n_threads();
+    let mut model: TrainModel<T> = trainer.into();
+    let topology = numa_topology(train_info, &mut model)?;
+    let mut sgd = Sgd::new(model);
+    if let Some(batch_size) = train_info.batch_size() {
+        sgd.set_batch_size(batch_size);
+    }
+    #[cfg(feature = "gpu")]
+    if train_info.device() == Device::Gpu {
+        sgd.use_gpu().context("Cannot train on the GPU")?;
+    }
+    let mut positions = thread_positions(&common_config, n_threads);
+    let mut validation_seed = common_config
+        .rng::<XorShiftRng>(RngStream::Validation)
+        .next_u64();
+    if let Some(resume) = train_info.resume() {
+        let f = File::open(resume)
+            .with_context(|| format!("Cannot open checkpoint file: {}", resume))?;
+        let checkpoint = Checkpoint::read(&mut BufReader::new(f))
+            .with_context(|| format!("Cannot read checkpoint file: {}", resume))?;
+        if checkpoint.threads().is_empty() {
+            eprintln!(
+                "Checkpoint does not store the positions of the training threads, \
+                 threads read the corpus from its beginning"
+            );
+        } else {
+            ensure!(
+                checkpoint.threads().len() == n_threads,
+                "The checkpoint was written by {} threads, resume with --threads {}",
+                checkpoint.threads().len(),
+                checkpoint.threads().len()
+            );
+            positions = checkpoint.threads().to_vec();
+            validation_seed = checkpoint.validation_seed();
+        }
+        sgd.restore(checkpoint)
+            .context("Cannot resume training from checkpoint")?;
+    }
+
+    let validation = match train_info.validation() {
+        Some(validation) => {
+            let sentence_loss =
+                sentence_loss.context("The validation loss cannot be computed for this model")?;
+            let mut validation_rng = XorShiftRng::seed_from_u64(validation_seed);
+            let sentences = read_validation_sentences(
+                validation,
+                train_info,
+                sgd.model().config(),
+                rules.clone(),
+                multiword.clone(),
+                validation_rng.next_u64(),
+            )?;
+            Some((
+                sentence_loss,
+                Arc::new(sentences),
+                validation_rng.next_u64(),
+            ))
+        }
+        None => None,
+    };
+
+    let eval_datasets = Arc::new(read_eval_datasets(train_info)?);
+
+    let n_train_tokens = common_config.epochs as usize * sgd.model().input_vocab().n_types();
+    let lr_schedule = LrSchedule::new(&common_config, n_train_tokens);
+
+    let sync = Arc::new(CheckpointSync::new(positions.clone()));
     let mut children = Vec::with_capacity(n_threads);
-    for thread in 0..n_threads {
-        let corpus = corpus.to_owned();
+    for (thread, position) in positions.into_iter().enumerate() {
+        let train_info = train_info.clone();
         let sgd = sgd.clone();
+        let constraints = constraints.clone();
+        let rules = rules.clone();
+        let multiword = multiword.clone();
+        let topology = topology.clone();
+        let sync = sync.clone();
 
         children.push(thread::spawn(move || {
+            if let Some(topology) = topology {
+                if let Err(err) = topology.pin_thread(thread) {
+                    sync.finish(thread, position);
+                    return Err(err);
+                }
+            }
+
             do_work(
-                corpus,
+                &train_info,
                 sgd,
+                constraints,
+                rules,
+                multiword,
+                update,
                 thread,
                 n_threads,
-                common_config.epochs,
-                common_config.lr,
+                n_train_tokens,
+                lr_schedule,
+                position,
+                &sync,
             )
         }));
     }
 
+    let checkpointer = train_info.checkpoint_interval().map(|interval| {
+        let sgd = sgd.clone();
+        let sync = sync.clone();
+        let checkpoint_output = train_info.checkpoint_output();
+        thread::spawn(move || {
+            write_checkpoints(
+                &sgd,
+                &sync,
+                validation_seed,
+                &checkpoint_output,
+                Duration::from_secs(interval),
+                n_train_tokens,
+            )
+        })
+    });
+
+    let epoch_writer = if train_info.save_every_epoch() {
+        let sgd = sgd.clone();
+        let mut train_info = train_info.clone();
+        Some(thread::spawn(move || {
+            // Hash the corpus once, rather than for every epoch.
+            train_info.compute_corpus_sha256()?;
+            write_epoch_snapshots(&sgd, &train_info, common_config)
+        }))
+    } else {
+        None
+    };
+
+    let validator = match (&validation, train_info.validation_interval()) {
+        (Some((sentence_loss, sentences, loss_seed)), Some(interval)) => {
+            let sgd = sgd.clone();
+            let sentence_loss = *sentence_loss;
+            let sentences = sentences.clone();
+            let loss_seed = *loss_seed;
+            Some(thread::spawn(move || {
+                compute_validation_losses(
+                    sgd,
+                    &sentences,
+                    sentence_loss,
+                    loss_seed,
+                    Duration::from_secs(interval),
+                    n_train_tokens,
+                )
+            }))
+        }
+        _ => None,
+    };
+
+    let evaluator = train_info.eval_interval().map(|interval| {
+        let sgd = sgd.clone();
+        let eval_datasets = eval_datasets.clone();
+        thread::spawn(move || {
+            compute_eval_scores(
+                sgd,
+                &eval_datasets.0,
+                &eval_datasets.1,
+                interval,
+                n_train_tokens,
+            )
+        })
+    });
+
     show_progress(
         &common_config,
         &sgd,
@@ -208,77 +1308,1070 @@ where
     for child in children {
         child.join().expect("Thread panicked")?;
     }
+    if let Some(checkpointer) = checkpointer {
+        checkpointer.join().expect("Checkpoint thread panicked");
+    }
+    if let Some(epoch_writer) = epoch_writer {
+        epoch_writer
+            .join()
+            .expect("Epoch snapshot thread panicked")?;
+    }
+    if let Some(validator) = validator {
+        validator.join().expect("Validation thread panicked");
+    }
+    if let Some(evaluator) = evaluator {
+        evaluator.join().expect("Evaluation thread panicked");
+    }
 
-    sgd.into_model()
-        .write_model_binary(
-            &mut output_writer,
-            app.train_info().clone(),
-            app.common_config.format,
-        )
-        .context("Cannot write model")
+    if let Some((sentence_loss, sentences, loss_seed)) = validation {
+        let loss = validation_loss(&mut sgd, &sentences, sentence_loss, loss_seed);
+        eprintln!("Validation loss: {:.*}", 5, loss);
+        train_info.set_validation_loss(loss);
+    }
+
+    if train_info.eval_interval().is_some() {
+        for (name, score) in eval_scores(sgd.model(), &eval_datasets.0, &eval_datasets.1) {
+            eprintln!("{}: {:.*}", name, 4, score);
+            train_info.set_eval_score(name, score);
+        }
+    }
+
+    Ok(sgd.into_model())
 }
 
-fn do_work<P, R, V>(
-    corpus_path: P,
-    mut sgd: Sgd<SkipgramTrainer<R, V>>,
+/// Read the word similarity and word analogy evaluation datasets.
+///
+/// The name of a dataset is its file name without extension.
+fn read_eval_datasets(
+    train_info: &TrainInfo,
+) -> Result<(Vec<SimilarityDataset>, Vec<AnalogyDataset>)> {
+    let similarity = train_info
+        .eval_similarity()
+        .iter()
+        .map(|path| {
+            let f = File::open(path)
+                .with_context(|| format!("Cannot open similarity dataset: {}", path))?;
+            SimilarityDataset::read(dataset_name(path), BufReader::new(f))
+                .with_context(|| format!("Cannot read similarity dataset: {}", path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let analogy = train_info
+        .eval_analogy()
+        .iter()
+        .map(|path| {
+            let f = File::open(path)
+                .with_context(|| format!("Cannot open analogy dataset: {}", path))?;
+            AnalogyDataset::read(dataset_name(path), BufReader::new(f))
+                .with_context(|| format!("Cannot read analogy dataset: {}", path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((similarity, analogy))
+}
+
+/// Get the name of an evaluation dataset from its path.
+fn dataset_name(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_owned())
+}
+
+/// Evaluate the model every `interval` tokens until `n_train_tokens`
+/// tokens are processed.
+///
+/// The scores are shared with the other clones of `sgd`, so that they
+/// are shown in the progress output.
+fn compute_eval_scores<T, V>(
+    mut sgd: Sgd<T>,
+    similarity: &[SimilarityDataset],
+    analogy: &[AnalogyDataset],
+    interval: usize,
+    n_train_tokens: usize,
+) where
+    T: Trainer<InputVocab = V>,
+    V: Vocab<VocabType = String>,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let mut next_eval = (sgd.n_tokens_processed() / interval + 1) * interval;
+    while sgd.n_tokens_processed() < n_train_tokens {
+        let n_tokens_processed = sgd.n_tokens_processed();
+        if n_tokens_processed >= next_eval {
+            let scores = eval_scores(sgd.model(), similarity, analogy);
+            sgd.set_eval_scores(scores);
+            next_eval = (n_tokens_processed / interval + 1) * interval;
+        }
+
+        thread::sleep(Duration::from_millis(PROGRESS_UPDATE_INTERVAL));
+    }
+}
+
+/// Evaluate the word embeddings of a model on the given datasets.
+///
+/// Returns the name and score of every dataset that could be evaluated.
+fn eval_scores<T, V>(
+    model: &TrainModel<T>,
+    similarity: &[SimilarityDataset],
+    analogy: &[AnalogyDataset],
+) -> Vec<(String, f32)>
+where
+    T: Trainer<InputVocab = V>,
+    V: Vocab<VocabType = String>,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let similarity_scores = similarity
+        .iter()
+        .filter_map(|dataset| Some((dataset.name().to_owned(), dataset.evaluate(model)?)));
+    let analogy_scores = analogy
+        .iter()
+        .filter_map(|dataset| Some((dataset.name().to_owned(), dataset.evaluate(model)?)));
+    similarity_scores.chain(analogy_scores).collect()
+}
+
+/// Read the sentences of a validation corpus.
+///
+/// The sentences are preprocessed in the same way as the training
+/// corpus. The shuffle buffer is seeded with `seed`.
+fn read_validation_sentences(
+    path: &str,
+    train_info: &TrainInfo,
+    common_config: &CommonConfig,
+    rules: Arc<TokenRules>,
+    multiword: Arc<MultiwordLexicon>,
+    seed: u64,
+) -> Result<Vec<Vec<String>>> {
+    let corpus = match train_info.corpus_format() {
+        CorpusFormat::Parquet => open_parquet_corpora(
+            &[path],
+            train_info.text_field().unwrap_or(DEFAULT_TEXT_FIELD),
+        )?,
+        corpus_format => open_corpora(&[path], corpus_format.separator())?,
+    };
+
+    read_sentences(
+        corpus,
+        train_info,
+        common_config.normalization,
+        rules,
+        multiword,
+        1,
+        XorShiftRng::seed_from_u64(seed),
+    )
+    .map(|sentence| sentence.map(|(_, sentence)| sentence))
+    .collect::<Result<Vec<_>>>()
+    .with_context(|| format!("Cannot read validation corpus: {}", path))
+}
+
+/// Compute the validation loss every `interval` until `n_train_tokens`
+/// tokens are processed.
+///
+/// The validation loss is shared with the other clones of `sgd`, so that
+/// it is shown in the progress output. The negatives are drawn from
+/// `seed`, see `validation_loss`.
+fn compute_validation_losses<T, V>(
+    mut sgd: Sgd<T>,
+    sentences: &[Vec<String>],
+    sentence_loss: SentenceLoss<T>,
+    seed: u64,
+    interval: Duration,
+    n_train_tokens: usize,
+) where
+    T: Trainer<InputVocab = V>,
+    V: Vocab,
+{
+    let mut last_validation = Instant::now();
+    while sgd.n_tokens_processed() < n_train_tokens {
+        if last_validation.elapsed() >= interval {
+            let loss = validation_loss(&mut sgd, sentences, sentence_loss, seed);
+            sgd.set_validation_loss(loss);
+            last_validation = Instant::now();
+        }
+
+        thread::sleep(Duration::from_millis(PROGRESS_UPDATE_INTERVAL));
+    }
+}
+
+/// Compute the average loss of the training instances of `sentences`.
+///
+/// The negatives of each sentence are drawn from a seed that is derived
+/// from `seed`, so that every validation uses the same negatives.
+fn validation_loss<T>(
+    sgd: &mut Sgd<T>,
+    sentences: &[Vec<String>],
+    sentence_loss: SentenceLoss<T>,
+    seed: u64,
+) -> f32 {
+    let mut rng = XorShiftRng::seed_from_u64(seed);
+    let (loss, n_examples) = sentences.iter().fold((0., 0), |(loss, n), sentence| {
+        let (sentence_loss, sentence_n) = sentence_loss(sgd, sentence, rng.next_u64());
+        (loss + sentence_loss, n + sentence_n)
+    });
+
+    if n_examples == 0 {
+        0.
+    } else {
+        loss / n_examples as f32
+    }
+}
+
+/// Write a snapshot of the model for every completed epoch.
+///
+/// Training continues while a snapshot is written. If writing takes
+/// longer than an epoch, the snapshot of the latest completed epoch is
+/// written next.
+fn write_epoch_snapshots<T, V>(
+    sgd: &Sgd<T>,
+    train_info: &TrainInfo,
+    common_config: CommonConfig,
+) -> Result<()>
+where
+    T: Trainer<InputVocab = V> + Clone,
+    T::Metadata: Serialize,
+    V: Vocab<VocabType = String> + Into<VocabWrap>,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let epoch_size = sgd.model().input_vocab().n_types();
+    let epochs = common_config.epochs as usize;
+    let mut last_epoch = sgd.n_tokens_processed() / epoch_size;
+    while last_epoch < epochs {
+        let epoch = cmp::min(sgd.n_tokens_processed() / epoch_size, epochs);
+        if epoch > last_epoch {
+            let epoch_info = train_info.epoch_snapshot(epoch as u32);
+            write_model_replace(sgd.model().snapshot(), &epoch_info, common_config.format)
+                .with_context(|| format!("Cannot write model of epoch {}", epoch))?;
+            last_epoch = epoch;
+        } else {
+            thread::sleep(Duration::from_millis(PROGRESS_UPDATE_INTERVAL));
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a checkpoint every `interval` until `n_train_tokens` tokens are
+/// processed.
+fn write_checkpoints<T, V>(
+    sgd: &Sgd<T>,
+    sync: &CheckpointSync,
+    validation_seed: u64,
+    path: &str,
+    interval: Duration,
+    n_train_tokens: usize,
+) where
+    T: Trainer<InputVocab = V>,
+    V: Vocab<VocabType = String>,
+{
+    let mut last_checkpoint = Instant::now();
+    while sgd.n_tokens_processed() < n_train_tokens {
+        if last_checkpoint.elapsed() >= interval {
+            let checkpoint = sync.checkpoint(sgd).with_validation_seed(validation_seed);
+
+            // A failed checkpoint should not stop training, the next
+            // checkpoint may succeed.
+            if let Err(err) = write_checkpoint(&checkpoint, path) {
+                eprintln!("Cannot write checkpoint: {:?}", err);
+            }
+            last_checkpoint = Instant::now();
+        }
+
+        thread::sleep(Duration::from_millis(PROGRESS_UPDATE_INTERVAL));
+    }
+}
+
+/// Write a checkpoint of the training state, replacing `path`.
+///
+/// The checkpoint is first written to a temporary file, so that a
+/// failure while writing does not destroy the previous checkpoint.
+fn write_checkpoint(checkpoint: &Checkpoint, path: &str) -> Result<()> {
+    let path = Path::new(path);
+    let mut checkpoint_file = NamedTempFile::new_in(parent_dir(path))
+        .context("Cannot create temporary checkpoint file for writing")?;
+
+    {
+        let mut writer = BufWriter::new(checkpoint_file.as_file_mut());
+        checkpoint
+            .write(&mut writer)
+            .context("Cannot write checkpoint")?;
+        writer.flush().context("Cannot write checkpoint")?;
+    }
+
+    checkpoint_file
+        .persist(path)
+        .with_context(|| format!("Cannot replace checkpoint file: {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Synchronization of the training threads for checkpoints.
+///
+/// A checkpoint is only consistent with the positions of the training
+/// threads when no thread is training on a sentence. When a checkpoint
+/// is requested, every training thread pauses after its current sentence
+/// and reports its position. The checkpoint is taken once all threads
+/// that are still training are paused.
+struct CheckpointSync {
+    requested: AtomicBool,
+    state: Mutex<CheckpointSyncState>,
+    changed: Condvar,
+}
+
+struct CheckpointSyncState {
+    /// Number of threads that are still training.
+    active: usize,
+
+    /// Number of threads that are paused for the requested checkpoint.
+    paused: usize,
+
+    /// Number of checkpoints that were taken.
+    generation: usize,
+
+    /// The latest reported position of every thread.
+    threads: Vec<ThreadCheckpoint>,
+}
+
+impl CheckpointSync {
+    /// Construct the synchronization for threads at the given positions.
+    fn new(threads: Vec<ThreadCheckpoint>) -> Self {
+        CheckpointSync {
+            requested: AtomicBool::new(false),
+            state: Mutex::new(CheckpointSyncState {
+                active: threads.len(),
+                paused: 0,
+                generation: 0,
+                threads,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Take a checkpoint of `sgd`.
+    ///
+    /// Blocks until all training threads are paused. The threads
+    /// continue training as soon as the parameters are copied.
+    fn checkpoint<T, V>(&self, sgd: &Sgd<T>) -> Checkpoint
+    where
+        T: Trainer<InputVocab = V>,
+        V: Vocab<VocabType = String>,
+    {
+        let state = self.state.lock().expect("Checkpoint lock was poisoned");
+        self.requested.store(true, Ordering::SeqCst);
+        let mut state = self
+            .changed
+            .wait_while(state, |state| state.paused < state.active)
+            .expect("Checkpoint lock was poisoned");
+
+        let checkpoint = sgd.checkpoint().with_threads(state.threads.clone());
+
+        self.requested.store(false, Ordering::SeqCst);
+        state.paused = 0;
+        state.generation += 1;
+        self.changed.notify_all();
+
+        checkpoint
+    }
+
+    /// Pause the calling training thread if a checkpoint is requested.
+    ///
+    /// `position` is the position of the thread after its last sentence.
+    /// Before the thread pauses, `flush` is called to apply updates that
+    /// the thread has not applied yet.
+    fn pause_if_requested(&self, thread: usize, position: ThreadCheckpoint, flush: impl FnOnce()) {
+        if !self.requested.load(Ordering::SeqCst) {
+            return;
+        }
+
+        flush();
+
+        let mut state = self.state.lock().expect("Checkpoint lock was poisoned");
+        // The checkpoint may have been taken in the meanwhile.
+        if !self.requested.load(Ordering::SeqCst) {
+            return;
+        }
+
+        state.threads[thread] = position;
+        state.paused += 1;
+        self.changed.notify_all();
+
+        let generation = state.generation;
+        let _state = self
+            .changed
+            .wait_while(state, |state| state.generation == generation)
+            .expect("Checkpoint lock was poisoned");
+    }
+
+    /// Record the final position of a thread that stopped training.
+    fn finish(&self, thread: usize, position: ThreadCheckpoint) {
+        let mut state = self.state.lock().expect("Checkpoint lock was poisoned");
+        state.threads[thread] = position;
+        state.active -= 1;
+        self.changed.notify_all();
+    }
+}
+
+/// Get the directory of a file path.
+///
+/// Returns the working directory for bare file names.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Train a model on a stream, updating the parameters with `update` for
+/// each sentence.
+///
+/// The threads train on the stream until it ends. If a snapshot interval
+/// is set, a snapshot of the model is written to the output file after
+/// every interval. Returns the trained model.
+fn train_model_stream<T, V, U>(
+    trainer: T,
+    train_info: &TrainInfo,
+    common_config: CommonConfig,
+    constraints: Option<Arc<LexicalConstraints>>,
+    rules: Arc<TokenRules>,
+    multiword: Arc<MultiwordLexicon>,
+    update: U,
+) -> Result<TrainModel<T>>
+where
+    T: Trainer<InputVocab = V> + NegativeSamples + Reseed + Clone + Send + Sync + 'static,
+    T::Metadata: Serialize,
+    V: Vocab<VocabType = String> + Into<VocabWrap>,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+    U: Fn(&mut Sgd<T>, &[String], f32) + Copy + Send + 'static,
+{
+    let n_threads = train_info.n_threads();
+    let mut model: TrainModel<T> = trainer.into();
+    let topology = numa_topology(train_info, &mut model)?;
+    let mut sgd = Sgd::new(model);
+    if let Some(batch_size) = train_info.batch_size() {
+        sgd.set_batch_size(batch_size);
+    }
+    #[cfg(feature = "gpu")]
+    if train_info.device() == Device::Gpu {
+        sgd.use_gpu().context("Cannot train on the GPU")?;
+    }
+
+    // The length of a stream is not known, so the learning rate does
+    // not decay.
+    let lr_schedule = LrSchedule::new(&common_config, usize::MAX);
+
+    let sentences = Arc::new(Mutex::new(read_sentences(
+        open_stream(&train_info.corpus()[0])?,
+        train_info,
+        sgd.model().config().normalization,
+        rules,
+        multiword,
+        1,
+        common_config.rng::<XorShiftRng>(RngStream::Corpus(0)),
+    )));
+
+    let running = Arc::new(AtomicUsize::new(n_threads));
+    let mut children = Vec::with_capacity(n_threads);
+    for thread in 0..n_threads {
+        let sgd = sgd.clone();
+        let sentences = sentences.clone();
+        let constraints = constraints.clone();
+        let running = running.clone();
+        let topology = topology.clone();
+
+        children.push(thread::spawn(move || {
+            let result = match topology {
+                Some(topology) => topology.pin_thread(thread),
+                None => Ok(()),
+            }
+            .and_then(|_| {
+                do_stream_work(
+                    sgd,
+                    sentences,
+                    constraints,
+                    update,
+                    thread,
+                    n_threads,
+                    lr_schedule,
+                )
+            });
+            running.fetch_sub(1, Ordering::SeqCst);
+            result
+        }));
+    }
+
+    let pb = ProgressBar::new_spinner();
+    let mut last_snapshot = Instant::now();
+    while running.load(Ordering::SeqCst) > 0 {
+        pb.set_message(&format!(
+            "tokens: {} loss: {:.*}",
+            sgd.n_tokens_processed(),
+            5,
+            sgd.train_loss()
+        ));
+
+        if let Some(interval) = train_info.snapshot_interval() {
+            if last_snapshot.elapsed() >= Duration::from_secs(interval) {
+                // A failed snapshot should not stop training, the next
+                // snapshot may succeed.
+                if let Err(err) =
+                    write_model_replace(sgd.model().snapshot(), train_info, common_config.format)
+                {
+                    pb.println(format!("Cannot write snapshot: {:?}", err));
+                }
+                last_snapshot = Instant::now();
+            }
+        }
+
+        thread::sleep(Duration::from_millis(PROGRESS_UPDATE_INTERVAL));
+    }
+    pb.finish();
+
+    // Wait until all threads have finished.
+    for child in children {
+        child.join().expect("Thread panicked")?;
+    }
+
+    Ok(sgd.into_model())
+}
+
+/// Write a model, replacing the output file.
+///
+/// The model is first written to a temporary file, which then replaces
+/// the output file. This ensures that readers of the output file never
+/// see a partially written model.
+fn write_model_replace<T, V>(
+    model: TrainModel<T>,
+    train_info: &TrainInfo,
+    format: EmbeddingFormat,
+) -> Result<()>
+where
+    T: Trainer<InputVocab = V>,
+    T::Metadata: Serialize,
+    V: Vocab + Into<VocabWrap>,
+    V::VocabType: ToString,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let output = Path::new(train_info.output());
+    let mut output_file = NamedTempFile::new_in(parent_dir(output))
+        .context("Cannot create temporary output file for writing")?;
+
+    {
+        let mut output_writer = BufWriter::new(output_file.as_file_mut());
+        model
+            .write_model_binary(&mut output_writer, train_info.clone(), format)
+            .context("Cannot write model")?;
+        output_writer.flush().context("Cannot write model")?;
+    }
+
+    output_file
+        .persist(output)
+        .with_context(|| format!("Cannot replace output file: {}", output.display()))?;
+
+    Ok(())
+}
+
+/// Detect the NUMA topology when NUMA-aware placement is enabled.
+///
+/// The embedding matrices of `model` are interleaved over the nodes of
+/// the topology. Returns `None` when NUMA-aware placement is disabled.
+fn numa_topology<T>(
+    train_info: &TrainInfo,
+    model: &mut TrainModel<T>,
+) -> Result<Option<Arc<NumaTopology>>> {
+    if !train_info.numa() {
+        return Ok(None);
+    }
+
+    let topology = NumaTopology::detect().context("Cannot detect NUMA topology")?;
+    eprintln!("NUMA nodes: {}", topology.n_nodes());
+    model
+        .interleave(&topology)
+        .context("Cannot place embedding matrices on NUMA nodes")?;
+
+    Ok(Some(Arc::new(topology)))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn do_work<T, V, U>(
+    train_info: &TrainInfo,
+    mut sgd: Sgd<T>,
+    constraints: Option<Arc<LexicalConstraints>>,
+    rules: Arc<TokenRules>,
+    multiword: Arc<MultiwordLexicon>,
+    update: U,
     thread: usize,
     n_threads: usize,
-    epochs: u32,
-    start_lr: f32,
+    n_train_tokens: usize,
+    lr_schedule: LrSchedule,
+    mut position: ThreadCheckpoint,
+    sync: &CheckpointSync,
 ) -> Result<()>
 where
-    P: Into<PathBuf>,
-    R: Clone + Rng,
+    T: Trainer<InputVocab = V> + NegativeSamples + Reseed,
     V: Vocab<VocabType = String>,
-    V::Config: Serialize,
-    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+    U: Fn(&mut Sgd<T>, &[String], f32),
 {
-    let n_tokens = sgd.model().input_vocab().n_types();
+    let normalization = sgd.model().config().normalization;
 
-    let f = File::open(corpus_path.into()).context("Cannot open corpus for reading")?;
-    let (data, start) =
-        thread_data_text(&f, thread, n_threads).context("Could not get thread-specific data")?;
+    // Threads cycle through disjoint subsets of the constraints.
+    let mut constraint_idx = thread + position.n_sentences as usize * n_threads;
 
-    let mut sentences = SentenceIterator::new(&data[start..]);
-    while sgd.n_tokens_processed() < epochs as usize * n_tokens {
-        let sentence = if let Some(sentence) = sentences.next() {
-            sentence
-        } else {
-            sentences = SentenceIterator::new(&*data);
-            sentences
+    // The final position of the thread is recorded, also when training
+    // fails, so that checkpoints do not wait for the thread.
+    let mut train = || -> Result<()> {
+        let corpus_paths = train_info.corpus();
+        let corpus_format = train_info.corpus_format();
+        let text_field = train_info.text_field().unwrap_or(DEFAULT_TEXT_FIELD);
+        let data = match corpus_format {
+            CorpusFormat::Parquet => {
+                ThreadCorpus::parquet(corpus_paths, text_field, thread, n_threads)?
+            }
+            _ => ThreadCorpus::new(corpus_paths, thread, n_threads, corpus_format.separator())?,
+        };
+
+        // Sentences are shuffled differently in every pass. Duplicates are
+        // skipped within a pass, so that every epoch sees every sentence
+        // once. The threads share the memory of the duplicate filters.
+        let corpus_seed = position.corpus_seed;
+        let pass_sentences = |pass: u64| -> Result<_> {
+            let read = if pass == 0 {
+                data.first_pass()?
+            } else {
+                data.pass()?
+            };
+            Ok(read_sentences(
+                read,
+                train_info,
+                normalization,
+                rules.clone(),
+                multiword.clone(),
+                n_threads,
+                XorShiftRng::seed_from_u64(corpus_seed.wrapping_add(pass)),
+            ))
+        };
+
+        // Skip the sentences that were read before the checkpoint that
+        // training resumes from.
+        let mut sentences = pass_sentences(position.pass)?;
+        for _ in 0..position.pass_sentences {
+            if sentences
                 .next()
-                .context("Iterator does not provide sentences")?
+                .transpose()
+                .context("Cannot read sentence")?
+                .is_none()
+            {
+                break;
+            }
+        }
+
+        while sgd.n_tokens_processed() < n_train_tokens {
+            let (weight, sentence) = if let Some(sentence) = sentences.next() {
+                sentence
+            } else {
+                position.pass += 1;
+                position.pass_sentences = 0;
+                sentences = pass_sentences(position.pass)?;
+                sentences
+                    .next()
+                    .context("Iterator does not provide sentences")?
+            }
+            .context("Cannot read sentence")?;
+            position.pass_sentences += 1;
+
+            let lr = lr_schedule.lr(sgd.n_tokens_processed());
+
+            // The trainer is reseeded for every sentence, so that the
+            // random draws do not depend on where training resumed.
+            sgd.reseed(position.trainer_seed.wrapping_add(position.n_sentences));
+
+            // The weight of a sentence scales its learning rate.
+            update(&mut sgd, sentence.as_slice(), weight * lr);
+
+            apply_constraint(
+                &mut sgd,
+                constraints.as_deref(),
+                &mut constraint_idx,
+                n_threads,
+                lr,
+            );
+
+            position.n_sentences += 1;
+            sync.pause_if_requested(thread, position, || sgd.flush_batch());
         }
-        .context("Cannot read sentence")?;
 
-        let lr = (1.0 - (sgd.n_tokens_processed() as f32 / (epochs as usize * n_tokens) as f32))
-            * start_lr;
+        sgd.flush_batch();
+
+        Ok(())
+    };
+
+    let result = train();
+    sync.finish(thread, position);
+    result
+}
+
+/// Train on the sentences of a stream until it ends.
+///
+/// The training threads share the stream, every sentence is used by
+/// one thread.
+fn do_stream_work<T, V, U, S>(
+    mut sgd: Sgd<T>,
+    sentences: Arc<Mutex<S>>,
+    constraints: Option<Arc<LexicalConstraints>>,
+    update: U,
+    thread: usize,
+    n_threads: usize,
+    lr_schedule: LrSchedule,
+) -> Result<()>
+where
+    T: Trainer<InputVocab = V> + NegativeSamples + Reseed,
+    V: Vocab<VocabType = String>,
+    U: Fn(&mut Sgd<T>, &[String], f32),
+    S: Iterator<Item = Result<(f32, Vec<String>)>>,
+{
+    // Every thread draws from its own trainer generators.
+    let seed = sgd
+        .model()
+        .config()
+        .rng::<XorShiftRng>(RngStream::Corpus(thread))
+        .next_u64();
+    sgd.reseed(seed);
+
+    // Threads cycle through disjoint subsets of the constraints.
+    let mut constraint_idx = thread;
+
+    loop {
+        let sentence = sentences.lock().expect("Stream lock was poisoned").next();
+        let (weight, sentence) = match sentence {
+            Some(sentence) => sentence.context("Cannot read sentence")?,
+            None => {
+                sgd.flush_batch();
+                return Ok(());
+            }
+        };
+
+        let lr = lr_schedule.lr(sgd.n_tokens_processed());
 
-        sgd.update_sentence(&sentence, lr);
+        // The weight of a sentence scales its learning rate.
+        update(&mut sgd, sentence.as_slice(), weight * lr);
+
+        apply_constraint(
+            &mut sgd,
+            constraints.as_deref(),
+            &mut constraint_idx,
+            n_threads,
+            lr,
+        );
     }
+}
 
-    Ok(())
+/// Get the positions of training threads that start training.
+///
+/// The seeds of a thread are drawn from its corpus random number
+/// generator.
+fn thread_positions(common_config: &CommonConfig, n_threads: usize) -> Vec<ThreadCheckpoint> {
+    (0..n_threads)
+        .map(|thread| {
+            let mut rng: XorShiftRng = common_config.rng(RngStream::Corpus(thread));
+            ThreadCheckpoint::new(rng.next_u64(), rng.next_u64())
+        })
+        .collect()
+}
+
+/// Apply the next lexical constraint of a thread, if any.
+fn apply_constraint<T>(
+    sgd: &mut Sgd<T>,
+    constraints: Option<&LexicalConstraints>,
+    constraint_idx: &mut usize,
+    n_threads: usize,
+    lr: f32,
+) where
+    T: Trainer,
+{
+    if let Some(constraints) = constraints.filter(|c| !c.is_empty()) {
+        let constraint = constraints
+            .get(*constraint_idx % constraints.len())
+            .unwrap();
+        sgd.update_constraint(constraint, constraints.weight() * lr);
+        *constraint_idx += n_threads;
+    }
+}
+
+/// Read the training sentences of (a part of) the corpus.
+///
+/// Sentences are shuffled in a buffer when a shuffle buffer is
+/// configured. Duplicate sentences are skipped when deduplication is
+/// enabled, the filter uses the memory bound divided by `n_readers`.
+fn read_sentences<R>(
+    read: R,
+    train_info: &TrainInfo,
+    normalization: TokenNormalization,
+    rules: Arc<TokenRules>,
+    multiword: Arc<MultiwordLexicon>,
+    n_readers: usize,
+    rng: XorShiftRng,
+) -> ShuffleBuffer<impl Iterator<Item = Result<(f32, Vec<String>)>>, XorShiftRng>
+where
+    R: BufRead,
+{
+    let mut duplicates = train_info
+        .dedup()
+        .map(|(dedup, dedup_memory)| DuplicateFilter::new(dedup, dedup_memory / n_readers));
+    let mut sentences = SentenceIterator::new(read)
+        .with_format(train_info.corpus_format())
+        .with_text_field(train_info.text_field().unwrap_or(DEFAULT_TEXT_FIELD))
+        .with_weights(train_info.weights())
+        .with_weight_field(train_info.weight_field().unwrap_or(DEFAULT_WEIGHT_FIELD))
+        .with_tokenizer(train_info.tokenizer())
+        .with_noise(train_info.noise())
+        .with_normalization(normalization)
+        .with_rules(rules)
+        .with_multiword(multiword);
+    if let Some(language) = train_info.language() {
+        sentences = sentences.with_language(language.clone());
+    }
+    let is_new = move |sentence: &Result<(f32, Vec<String>)>| {
+        if let (Ok((_, sentence)), Some(duplicates)) = (sentence, duplicates.as_mut()) {
+            return !duplicates.is_duplicate(sentence);
+        }
+
+        true
+    };
+
+    ShuffleBuffer::new(
+        sentences.weighted().filter(is_new),
+        train_info.shuffle_buffer().unwrap_or(1),
+        rng,
+    )
 }
 
-fn build_vocab<P, V, C>(config: C, corpus_path: P) -> Result<V>
+/// Build a vocabulary from a corpus.
+///
+/// Stopwords and tokens that do not satisfy the token length limits are
+/// not counted and thus excluded from the vocabulary. Tokens that are
+/// dropped by the token rules are not counted either. Multi-word
+/// expressions are counted as single tokens. If a fixed vocabulary is
+/// given, the vocabulary consists of exactly these words, with their
+/// counts in the corpus.
+///
+/// If a vocabulary counts file is given, the counts are read from that
+/// file rather than the corpus. The counts of the vocabulary are written
+/// to a file if requested.
+pub(crate) fn build_vocab<P, V, C>(
+    config: C,
+    corpus_paths: &[P],
+    options: &VocabOptions,
+) -> Result<V>
 where
     P: AsRef<Path>,
     V: Vocab<VocabType = String> + From<VocabBuilder<C, String>>,
+    V::Config: Serialize,
     VocabBuilder<C, String>: Into<V>,
 {
-    let f = File::open(corpus_path).context("Cannot open corpus for reading")?;
-    let file_progress = FileProgress::new(f).context("Cannot create progress bar")?;
+    let mut builder = match &options.vocab_in {
+        Some(vocab_in) => read_vocab_builder(config, vocab_in, options)?,
+        None => count_vocab_builder(config, corpus_paths, options)?,
+    };
+
+    if let Some(vocab_words) = &options.vocab_words {
+        builder.restrict(vocab_words.iter().cloned());
+    }
+
+    let vocab: V = builder.into();
+
+    if let Some(vocab_out) = &options.vocab_out {
+        let mut write = BufWriter::new(
+            File::create(vocab_out)
+                .with_context(|| format!("Cannot create vocabulary file: {}", vocab_out))?,
+        );
+        write_vocab_counts(&vocab, &mut write)
+            .with_context(|| format!("Cannot write vocabulary file: {}", vocab_out))?;
+    }
+
+    Ok(vocab)
+}
+
+/// Count the vocabulary in a corpus.
+fn count_vocab_builder<P, C>(
+    config: C,
+    corpus_paths: &[P],
+    options: &VocabOptions,
+) -> Result<VocabBuilder<C, String>>
+where
+    P: AsRef<Path>,
+{
+    let corpus = match options.corpus_format {
+        CorpusFormat::Parquet => open_parquet_corpora(corpus_paths, &options.text_field)?,
+        _ => open_corpora(corpus_paths, options.corpus_format.separator())?,
+    };
+
+    let mut sentences = SentenceIterator::new(corpus)
+        .with_format(options.corpus_format)
+        .with_text_field(options.text_field.as_str())
+        .with_weights(options.weights)
+        .with_weight_field(options.weight_field.as_str())
+        .with_tokenizer(options.tokenizer)
+        .with_noise(options.noise)
+        .with_normalization(options.normalization)
+        .with_rules(options.rules.clone())
+        .with_multiword(options.multiword.clone());
+    if let Some(language) = &options.language {
+        sentences = sentences.with_language(language.clone());
+    }
 
-    let sentences = SentenceIterator::new(BufReader::new(file_progress));
+    let mut duplicates = options
+        .dedup
+        .map(|(dedup, dedup_memory)| DuplicateFilter::new(dedup, dedup_memory));
 
-    let mut builder = VocabBuilder::new(config);
+    let mut builder = VocabBuilder::new(config)
+        .with_max_types(options.max_types)
+        .with_max_memory(options.max_memory);
     for sentence in sentences {
         let sentence = sentence.context("Cannot read sentence")?;
 
+        if let Some(duplicates) = duplicates.as_mut() {
+            if duplicates.is_duplicate(&sentence) {
+                continue;
+            }
+        }
+
         for token in sentence {
-            builder.count(token);
+            if options.is_counted(&token) {
+                builder.count(token);
+            }
+        }
+    }
+
+    Ok(builder)
+}
+
+/// Read vocabulary counts from a file.
+///
+/// Stopwords and tokens that do not satisfy the token length limits are
+/// removed from the counts.
+fn read_vocab_builder<C>(
+    config: C,
+    vocab_in: &str,
+    options: &VocabOptions,
+) -> Result<VocabBuilder<C, String>> {
+    let f = File::open(vocab_in)
+        .with_context(|| format!("Cannot open vocabulary file: {}", vocab_in))?;
+    let mut builder = read_vocab_counts(config, BufReader::new(f))
+        .with_context(|| format!("Cannot read vocabulary file: {}", vocab_in))?;
+    builder.retain(|token| options.is_counted(token));
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use clap::ArgMatches;
+    use finalfrontier::{
+        Checkpoint, LrSchedule, RngStream, Sgd, SimpleVocab, SkipgramTrainer, Vocab,
+    };
+    use rand_xorshift::XorShiftRng;
+    use tempfile::NamedTempFile;
+
+    use super::{build_vocab, do_work, thread_positions, CheckpointSync, SkipgramApp};
+    use crate::subcommands::{FinalfrontierApp, VocabConfig};
+
+    static SENTENCES: &[&str] = &[
+        "the cat sat on the mat",
+        "a dog sat on the log",
+        "the cat chased the dog",
+        "a bird sang on the roof of the house",
+        "the dog and the cat slept",
+    ];
+
+    /// Train with one thread until `n_tokens` tokens are processed.
+    ///
+    /// Training starts from `resume` if it is given. Returns the
+    /// checkpoint after training.
+    fn train(
+        app: &SkipgramApp,
+        vocab: &SimpleVocab<String>,
+        resume: Option<Checkpoint>,
+        n_tokens: usize,
+    ) -> Checkpoint {
+        let common_config = app.common_config();
+        let trainer = SkipgramTrainer::new(
+            vocab.clone(),
+            common_config.rng::<XorShiftRng>(RngStream::Trainer),
+            common_config,
+            app.skipgram_config(),
+        );
+        let mut sgd = Sgd::new(trainer.into());
+
+        let mut position = thread_positions(&common_config, 1)[0];
+        if let Some(checkpoint) = resume {
+            position = checkpoint.threads()[0];
+            sgd.restore(checkpoint).unwrap();
         }
+
+        let n_train_tokens = common_config.epochs as usize * vocab.n_types();
+        let sync = CheckpointSync::new(vec![position]);
+        do_work(
+            app.train_info(),
+            sgd.clone(),
+            None,
+            app.vocab_options.rules.clone(),
+            app.vocab_options.multiword.clone(),
+            |sgd, sentence, lr| sgd.update_sentence(sentence, lr),
+            0,
+            1,
+            n_tokens,
+            LrSchedule::new(&common_config, n_train_tokens),
+            position,
+            &sync,
+        )
+        .unwrap();
+
+        sync.checkpoint(&sgd)
     }
 
-    Ok(builder.into())
+    #[test]
+    fn resumed_training_matches_uninterrupted_training() {
+        let mut corpus = NamedTempFile::new().unwrap();
+        for i in 0..50 {
+            writeln!(corpus, "{}", SENTENCES[i % SENTENCES.len()]).unwrap();
+        }
+        let corpus_path = corpus.path().to_str().unwrap();
+
+        let matches: ArgMatches = SkipgramApp::app().get_matches_from(vec![
+            "skipgram",
+            "--threads",
+            "1",
+            "--seed",
+            "42",
+            "--subwords",
+            "none",
+            "--mincount",
+            "1",
+            "--dims",
+            "8",
+            "--epochs",
+            "4",
+            corpus_path,
+            "unused.fifu",
+        ]);
+        let app = SkipgramApp::parse(&matches).unwrap();
+        let vocab: SimpleVocab<String> = match app.vocab_config() {
+            VocabConfig::SimpleVocab(config) => {
+                build_vocab(config, app.corpus(), &app.vocab_options).unwrap()
+            }
+            _ => unreachable!(),
+        };
+        let n_train_tokens = app.common_config().epochs as usize * vocab.n_types();
+
+        let uninterrupted = train(&app, &vocab, None, n_train_tokens);
+
+        // Interrupt training halfway through the second epoch and
+        // resume from the checkpoint that is written to disk.
+        let interrupted = train(&app, &vocab, None, n_train_tokens * 3 / 8);
+        assert!(interrupted.n_tokens_processed() < n_train_tokens);
+        let mut data = Vec::new();
+        interrupted.write(&mut data).unwrap();
+        let interrupted = Checkpoint::read(&mut Cursor::new(data)).unwrap();
+        let resumed = train(&app, &vocab, Some(interrupted), n_train_tokens);
+
+        assert_eq!(resumed, uninterrupted);
+    }
 }
@@ -0,0 +1,505 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{ensure, Context, Result};
+use clap::{App, Arg, ArgMatches};
+use finalfrontier::io::{open_corpora, SentenceSeparator, ThreadCorpus, TrainInfo};
+use finalfrontier::{
+    BucketIndexerType, CommonConfig, Cutoff, LrSchedule, NegativeSamples, Reseed, RngStream,
+    SentenceIterator, Sgd, SimpleVocab, SimpleVocabConfig, SubwordVocab, SupervisedConfig,
+    SupervisedTrainer, TokenNormalization, TokenRules, TrainIterFrom, Trainer, Vocab, VocabBuilder,
+    WriteModelBinary, LABEL_PREFIX,
+};
+use finalfusion::compat::fasttext::FastTextIndexer;
+use finalfusion::prelude::VocabWrap;
+use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::RngCore;
+use rand_xorshift::XorShiftRng;
+use serde::Serialize;
+
+use crate::subcommands::{
+    add_bucket_statistics, add_unk, load_bpe_merges, load_sentencepiece, prune_subwords,
+    split_compounds, write_ngrams, FinalfrontierApp, VocabConfig, VocabOptions,
+};
+
+static CLASSIFIER_OUTPUT: &str = "classifier-output";
+static WORD_NGRAMS: &str = "word-ngrams";
+
+const PROGRESS_UPDATE_INTERVAL: u64 = 200;
+
+/// Subcommand for training supervised classifiers.
+pub struct SupervisedApp {
+    train_info: TrainInfo,
+    classifier_output: String,
+    common_config: CommonConfig,
+    supervised_config: SupervisedConfig,
+    vocab_config: VocabConfig,
+    segmentations: HashMap<String, Vec<String>>,
+    vocab_options: VocabOptions,
+}
+
+impl SupervisedApp {
+    /// Get the corpus paths.
+    pub fn corpus(&self) -> &[String] {
+        &self.train_info.corpus()
+    }
+
+    /// Get the classifier output path.
+    pub fn classifier_output(&self) -> &str {
+        &self.classifier_output
+    }
+
+    /// Get the common config.
+    pub fn common_config(&self) -> CommonConfig {
+        self.common_config
+    }
+
+    /// Get the supervised config.
+    pub fn supervised_config(&self) -> SupervisedConfig {
+        self.supervised_config
+    }
+
+    /// Get the vocab config.
+    pub fn vocab_config(&self) -> VocabConfig {
+        self.vocab_config.clone()
+    }
+
+    /// Get the train information.
+    pub fn train_info(&self) -> &TrainInfo {
+        &self.train_info
+    }
+
+    fn supervised_config_from_matches(matches: &ArgMatches) -> Result<SupervisedConfig> {
+        let word_ngrams = matches
+            .value_of(WORD_NGRAMS)
+            .map(|v| v.parse().context("Cannot parse word n-gram length"))
+            .transpose()?
+            .unwrap();
+
+        Ok(SupervisedConfig { word_ngrams })
+    }
+}
+
+impl FinalfrontierApp for SupervisedApp {
+    fn app() -> App<'static, 'static> {
+        Self::common_opts("supervised")
+            .about("Train a supervised classifier")
+            .arg(
+                Arg::with_name(CLASSIFIER_OUTPUT)
+                    .long("classifier-output")
+                    .value_name("FILE")
+                    .help("Classifier output (default: OUTPUT.classifier)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(WORD_NGRAMS)
+                    .long("word-ngrams")
+                    .value_name("LEN")
+                    .help("Maximum length of word n-gram features")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+    }
+
+    fn parse(matches: &ArgMatches) -> Result<Self> {
+        let corpus = Self::parse_corpus(matches)?;
+        let output: String = matches.value_of(Self::OUTPUT).unwrap().into();
+        let n_threads = matches
+            .value_of(Self::THREADS)
+            .map(|v| v.parse().context("Cannot parse number of threads"))
+            .transpose()?
+            .unwrap_or_else(|| cmp::min(num_cpus::get() / 2, 20));
+        let classifier_output = matches
+            .value_of(CLASSIFIER_OUTPUT)
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| format!("{}.classifier", output));
+        let mut train_info = TrainInfo::new(corpus, output, n_threads)?;
+        if let Some(ngram_output) = Self::parse_ngram_output(matches)? {
+            train_info = train_info.with_ngram_output(ngram_output);
+        }
+        if let Some(vectors_npy) = Self::parse_vectors_npy(matches)? {
+            train_info = train_info.with_vectors_npy(vectors_npy);
+        }
+        if let Some(ann_index) = Self::parse_ann_index(matches)? {
+            train_info = train_info.with_ann_index(ann_index);
+        }
+        if let Some(config_output) = Self::parse_config_output(matches) {
+            train_info = train_info.with_config_output(config_output);
+        }
+
+        let common_config = Self::parse_common_config(&matches)?;
+        ensure!(
+            !common_config.tied,
+            "Tied embeddings are not supported by supervised training"
+        );
+
+        let vocab_options = Self::parse_vocab_options(common_config, &matches)?;
+        ensure!(
+            vocab_options.vocab_in.is_none() && vocab_options.vocab_out.is_none(),
+            "The supervised subcommand does not support reading or writing vocabulary counts"
+        );
+        ensure!(
+            vocab_options.multiword.is_empty(),
+            "The supervised subcommand does not support multi-word expressions"
+        );
+
+        Ok(SupervisedApp {
+            train_info,
+            classifier_output,
+            common_config,
+            supervised_config: Self::supervised_config_from_matches(&matches)?,
+            vocab_config: Self::parse_vocab_config(common_config, &matches)?,
+            segmentations: Self::parse_segmentations(&matches)?,
+            vocab_options,
+        })
+    }
+
+    fn run(&self) -> Result<()> {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+        eprintln!("SIMD features: {}", Self::simd_features().join(" "));
+
+        match self.vocab_config() {
+            VocabConfig::SubwordVocab(config) => match config.indexer.indexer_type {
+                BucketIndexerType::Finalfusion => {
+                    let (vocab, labels): (SubwordVocab<_, FinalfusionHashIndexer>, _) =
+                        build_vocabs(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, labels, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, labels, self)
+                        }
+                    }
+                }
+                BucketIndexerType::FastText => {
+                    let (vocab, labels): (SubwordVocab<_, FastTextIndexer>, _) =
+                        build_vocabs(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            train(vocab, labels, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            train(vocab, labels, self)
+                        }
+                    }
+                }
+            },
+            VocabConfig::SimpleVocab(config) => {
+                let (vocab, labels): (SimpleVocab<String>, _) =
+                    build_vocabs(config, self.corpus(), &self.vocab_options)?;
+                let vocab = add_unk(vocab, &self.vocab_options);
+                train(vocab, labels, self)
+            }
+            VocabConfig::NGramVocab(config) => {
+                let (vocab, labels): (SubwordVocab<_, ExplicitIndexer>, _) =
+                    build_vocabs(config, self.corpus(), &self.vocab_options)?;
+                let vocab = split_compounds(vocab, &self.vocab_options);
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                write_ngrams(&vocab, self.vocab_options.ngrams_out.as_deref())?;
+                train(vocab, labels, self)
+            }
+            VocabConfig::BpeVocab(config, merges) => {
+                let (vocab, labels) = build_vocabs(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_bpe_merges(vocab, merges.as_deref())?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                train(vocab, labels, self)
+            }
+            VocabConfig::SentencePieceVocab(config, model) => {
+                let (vocab, labels) = build_vocabs(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_sentencepiece(vocab, &model)?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                train(vocab, labels, self)
+            }
+        }
+    }
+}
+
+fn train<V>(vocab: V, labels: SimpleVocab<String>, app: &SupervisedApp) -> Result<()>
+where
+    V: Vocab<VocabType = String> + Into<VocabWrap> + Clone + Send + Sync + 'static,
+    V::Config: Serialize,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    ensure!(
+        labels.len() > 1,
+        "The corpus should have at least two labels, found: {}",
+        labels.len()
+    );
+
+    let common_config = app.common_config();
+    let train_info = app.train_info();
+    let n_threads = train_info.n_threads();
+    let corpus = train_info.corpus();
+    let mut output_writer = BufWriter::new(
+        File::create(train_info.output()).context("Cannot open output file for writing.")?,
+    );
+    let mut classifier_output_writer = BufWriter::new(
+        File::create(app.classifier_output())
+            .context("Cannot open classifier output file for writing.")?,
+    );
+
+    let mut trainer = SupervisedTrainer::new(
+        vocab,
+        labels,
+        common_config.rng::<XorShiftRng>(RngStream::Trainer),
+        common_config,
+        app.supervised_config(),
+    );
+    let n_examples = count_examples(
+        &mut trainer,
+        corpus,
+        common_config.normalization,
+        &app.vocab_options.rules,
+    )?;
+    ensure!(
+        n_examples > 0,
+        "The corpus does not contain labeled examples"
+    );
+    let mut sgd = Sgd::new(trainer.into());
+    sgd.set_epoch_size(n_examples);
+
+    let mut children = Vec::with_capacity(n_threads);
+    for thread in 0..n_threads {
+        let corpus = corpus.to_owned();
+        let sgd = sgd.clone();
+        let rules = app.vocab_options.rules.clone();
+
+        children.push(thread::spawn(move || {
+            do_work(
+                &corpus,
+                sgd,
+                rules,
+                thread,
+                n_threads,
+                common_config.epochs as usize * n_examples,
+                LrSchedule::new(&common_config, common_config.epochs as usize * n_examples),
+            )
+        }));
+    }
+
+    show_progress(
+        &common_config,
+        &sgd,
+        n_examples,
+        Duration::from_millis(PROGRESS_UPDATE_INTERVAL),
+    );
+
+    // Wait until all threads have finished.
+    for child in children {
+        child.join().expect("Thread panicked")?;
+    }
+
+    let mut model = sgd.into_model();
+    model
+        .write_classifier(&mut classifier_output_writer)
+        .context("Cannot write classifier")?;
+    model
+        .write_model_binary(&mut output_writer, train_info.clone(), common_config.format)
+        .context("Cannot write model")
+}
+
+fn do_work<P, T, V, F>(
+    corpus_paths: &[P],
+    mut sgd: Sgd<T>,
+    rules: Arc<TokenRules>,
+    thread: usize,
+    n_threads: usize,
+    n_examples: usize,
+    lr_schedule: LrSchedule,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    T: Trainer<InputVocab = V>
+        + for<'a> TrainIterFrom<'a, [String], Focus = F>
+        + NegativeSamples
+        + Reseed,
+    V: Vocab<VocabType = String>,
+    for<'a> &'a F: IntoIterator<Item = u64>,
+{
+    let normalization = sgd.model().config().normalization;
+
+    // Every thread draws from its own trainer generators.
+    let seed = sgd
+        .model()
+        .config()
+        .rng::<XorShiftRng>(RngStream::Corpus(thread))
+        .next_u64();
+    sgd.reseed(seed);
+
+    let data = ThreadCorpus::text(corpus_paths, thread, n_threads)?;
+
+    let mut sentences = SentenceIterator::new(data.first_pass()?);
+    while sgd.n_tokens_processed() < n_examples {
+        let sentence = if let Some(sentence) = sentences.next() {
+            sentence
+        } else {
+            sentences = SentenceIterator::new(data.pass()?);
+            sentences
+                .next()
+                .context("Iterator does not provide sentences")?
+        }
+        .context("Cannot read sentence")?;
+        let sentence = normalize_inputs(sentence, normalization, &rules);
+
+        let lr = lr_schedule.lr(sgd.n_tokens_processed());
+
+        sgd.update_sentence(sentence.as_slice(), lr);
+    }
+
+    Ok(())
+}
+
+/// Build the input vocabulary and the label vocabulary.
+///
+/// Every token with the label prefix is counted as a label, all other
+/// tokens are counted as inputs. All labels are retained. Stopwords,
+/// tokens that do not satisfy the token length limits, and tokens that
+/// are dropped by the token rules are not counted. If a fixed vocabulary
+/// is given, the input vocabulary consists of exactly these words.
+fn build_vocabs<P, V, C>(
+    config: C,
+    corpus_paths: &[P],
+    options: &VocabOptions,
+) -> Result<(V, SimpleVocab<String>)>
+where
+    P: AsRef<Path>,
+    V: Vocab<VocabType = String> + From<VocabBuilder<C, String>>,
+    VocabBuilder<C, String>: Into<V>,
+{
+    let corpus = open_corpora(corpus_paths, SentenceSeparator::Line)?;
+
+    let sentences = SentenceIterator::new(corpus);
+
+    let mut builder = VocabBuilder::new(config)
+        .with_max_types(options.max_types)
+        .with_max_memory(options.max_memory);
+    let mut label_builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+        cutoff: Cutoff::MinCount(1),
+        discard_threshold: 1.0,
+    });
+    for sentence in sentences {
+        let sentence = sentence.context("Cannot read sentence")?;
+
+        for token in normalize_inputs(sentence, options.normalization, &options.rules) {
+            if token.starts_with(LABEL_PREFIX) {
+                label_builder.count(token);
+            } else if options.is_counted(&token) {
+                builder.count(token);
+            }
+        }
+    }
+
+    if let Some(vocab_words) = &options.vocab_words {
+        builder.restrict(vocab_words.iter().cloned());
+    }
+    Ok((builder.into(), label_builder.into()))
+}
+
+/// Count the training examples in a corpus.
+fn count_examples<T>(
+    trainer: &mut T,
+    corpus_paths: &[String],
+    normalization: TokenNormalization,
+    rules: &TokenRules,
+) -> Result<usize>
+where
+    T: for<'a> TrainIterFrom<'a, [String]>,
+{
+    let corpus = open_corpora(corpus_paths, SentenceSeparator::Line)?;
+
+    let mut n_examples = 0;
+    for sentence in SentenceIterator::new(corpus) {
+        let sentence = normalize_inputs(
+            sentence.context("Cannot read sentence")?,
+            normalization,
+            rules,
+        );
+        n_examples += trainer.train_iter_from(sentence.as_slice()).count();
+    }
+
+    Ok(n_examples)
+}
+
+/// Normalize the input tokens of a sentence and apply the token rules.
+///
+/// Labels are not normalized or rewritten, so that they are stored as
+/// they occur in the corpus.
+fn normalize_inputs(
+    sentence: Vec<String>,
+    normalization: TokenNormalization,
+    rules: &TokenRules,
+) -> Vec<String> {
+    if normalization.is_identity() && rules.is_empty() {
+        return sentence;
+    }
+
+    sentence
+        .into_iter()
+        .filter_map(|token| {
+            if token.starts_with(LABEL_PREFIX) {
+                Some(token)
+            } else {
+                let token = normalization.normalize(&token);
+                rules.apply(&token).map(|token| token.into_owned())
+            }
+        })
+        .collect()
+}
+
+fn show_progress<T, V>(
+    config: &CommonConfig,
+    sgd: &Sgd<T>,
+    n_examples: usize,
+    update_interval: Duration,
+) where
+    T: Trainer<InputVocab = V>,
+    V: Vocab,
+{
+    let n_examples = n_examples * config.epochs as usize;
+    let lr_schedule = LrSchedule::new(config, n_examples);
+
+    let pb = ProgressBar::new(n_examples as u64);
+    pb.set_style(
+        ProgressStyle::default_bar().template("{bar:30} {percent}% {msg} ETA: {eta_precise}"),
+    );
+
+    while sgd.n_tokens_processed() < n_examples {
+        let lr = lr_schedule.lr(sgd.n_tokens_processed());
+
+        pb.set_position(sgd.n_tokens_processed() as u64);
+        pb.set_message(&format!(
+            "loss: {:.*} lr: {:.*}",
+            5,
+            sgd.train_loss(),
+            5,
+            lr
+        ));
+
+        thread::sleep(update_interval);
+    }
+
+    pb.finish();
+}
@@ -1,38 +1,160 @@
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use anyhow::{ensure, Context, Result};
+use anyhow::{anyhow, ensure, Context, Result};
 use clap::{App, AppSettings, Arg, ArgMatches};
-use finalfrontier::io::EmbeddingFormat;
+use finalfrontier::io::{
+    is_remote_corpus, CorpusFormat, EmbeddingFormat, Tokenizer, DEFAULT_TEXT_FIELD,
+    DEFAULT_WEIGHT_FIELD,
+};
 use finalfrontier::{
-    BucketConfig, BucketIndexerType, CommonConfig, Cutoff, LossType, NGramConfig,
-    SimpleVocabConfig, SubwordVocabConfig,
+    read_segmentations, BpeConfig, BucketConfig, BucketIndexerType, CaseFolding, CommonConfig,
+    Cutoff, LossType, MultiwordLexicon, NGramConfig, NoiseFilters, Precision, QuantizerConfig,
+    ScriptNGrams, SentencePieceConfig, SimpleVocabConfig, SubwordVocabConfig, TokenNormalization,
+    TokenRules, WordBoundaries,
 };
+use glob::Pattern;
 
-use crate::subcommands::{cutoff_from_matches, VocabConfig};
+use crate::subcommands::{cutoff_from_matches, VocabConfig, VocabOptions};
 
-static DEFAULT_CLAP_SETTINGS: &[AppSettings] = &[
+pub(crate) static DEFAULT_CLAP_SETTINGS: &[AppSettings] = &[
     AppSettings::DontCollapseArgsInUsage,
     AppSettings::UnifiedHelpMessage,
 ];
 
 // Option constants
+static ANN_INDEX: &str = "ann-index";
+static BATCH_NEGATIVES: &str = "batch-negatives";
+static BPE_MERGES: &str = "bpe-merges";
+static BPE_MERGES_FILE: &str = "bpe-merges-file";
+static BOUNDARIES: &str = "boundaries";
 static BUCKETS: &str = "buckets";
+static BUCKET_STATISTICS: &str = "bucket-statistics";
+static CASEFOLD: &str = "casefold";
+static CLIP_NORM: &str = "clip-norm";
+static CLIP_PER_ROW: &str = "clip-per-row";
 static DIMS: &str = "dims";
 static DISCARD: &str = "discard";
 static EPOCHS: &str = "epochs";
+static EXCLUDE_WORD: &str = "exclude-word";
 static FORMAT: &str = "format";
 static HASH_INDEXER_TYPE: &str = "hash-indexer";
+static INCLUDE: &str = "include";
+static LOSS: &str = "loss";
+static LOWERCASE: &str = "lowercase";
 static LR: &str = "lr";
+static LR_SCHEDULE: &str = "lr-schedule";
+static MARGIN: &str = "margin";
+static MAX_COUNT_MEMORY: &str = "max-count-memory";
+static MAX_COUNT_TYPES: &str = "max-count-types";
+static MAX_TOKEN_LEN: &str = "max-token-len";
 static MINCOUNT: &str = "mincount";
+static MIN_TOKEN_LEN: &str = "min-token-len";
+static MULTIWORD: &str = "multiword";
 static TARGET_SIZE: &str = "target-size";
 static MINN: &str = "minn";
 static MAXN: &str = "maxn";
 static NGRAM_MINCOUNT: &str = "ngram-mincount";
+static NGRAM_OUTPUT: &str = "ngram-output";
 static NGRAM_TARGET_SIZE: &str = "ngram-target-size";
+static NGRAMS_OUT: &str = "ngrams-out";
+static NORMALIZATION: &str = "normalization";
+static SCRIPT_NGRAMS: &str = "script-ngrams";
+static SUBWORD_DROPOUT: &str = "subword-dropout";
 static SUBWORDS: &str = "subwords";
+static TIED: &str = "tied";
+static TOKEN_RULES: &str = "token-rules";
+static UNK: &str = "unk";
+static UNIGRAM_POWER: &str = "unigram-power";
+static VECTORS_NPY: &str = "vectors-npy";
+static WARMUP: &str = "warmup";
+static WEIGHT_DECAY: &str = "weight-decay";
+static WRITE_CONFIG: &str = "write-config";
+static WRITE_TOP_K: &str = "write-top-k";
 static NS: &str = "ns";
+static NS_DISTRIBUTION: &str = "ns-distribution";
+static NS_EXCLUDE_WINDOW: &str = "ns-exclude-window";
+static NS_SHARED: &str = "ns-shared";
+static NUMBERS: &str = "numbers";
+static OPTIMIZER: &str = "optimizer";
+static OUTPUT_PRECISION: &str = "output-precision";
+static PQ_ATTEMPTS: &str = "pq-attempts";
+static PQ_BITS: &str = "pq-bits";
+static PQ_ITERATIONS: &str = "pq-iterations";
+static PQ_SUBQUANTIZERS: &str = "pq-subquantizers";
+static PRECISION: &str = "precision";
+static PRUNE_SUBWORDS: &str = "prune-subwords";
+static QAT_EPOCHS: &str = "qat-epochs";
+static SEED: &str = "seed";
+static SEGMENTATIONS: &str = "segmentations";
+static SPLIT_COMPOUNDS: &str = "split-compounds";
+static SPM: &str = "spm";
+static STOPWORDS: &str = "stopwords";
+static VOCAB: &str = "vocab";
+static VOCAB_IN: &str = "vocab-in";
+static VOCAB_OUT: &str = "vocab-out";
 static ZIPF_EXPONENT: &str = "zipf";
 
+/// Convert a corpus path to a string.
+fn path_to_string(path: PathBuf) -> Result<String> {
+    path.into_os_string()
+        .into_string()
+        .map_err(|path| anyhow!("Invalid corpus path: {}", Path::new(&path).display()))
+}
+
+/// Recursively add the files in a corpus directory.
+///
+/// Only files of which the name matches `include` are added. The
+/// entries of a directory are sorted by name, so that the files are
+/// always read in the same order.
+fn read_corpus_dir(dir: &Path, include: &Pattern, corpus: &mut Vec<String>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .with_context(|| format!("Cannot read corpus directory: {}", dir.display()))?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            read_corpus_dir(&path, include, corpus)?;
+        } else if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| include.matches(name))
+            .unwrap_or(false)
+        {
+            corpus.push(path_to_string(path)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a word list with one word per line.
+///
+/// Surrounding whitespace is removed and empty lines are ignored.
+fn read_words(path: &str, description: &str) -> Result<HashSet<String>> {
+    let f =
+        File::open(path).with_context(|| format!("Cannot open {} file: {}", description, path))?;
+    let mut words = HashSet::new();
+    for line in BufReader::new(f).lines() {
+        let line = line.with_context(|| format!("Cannot read {} file: {}", description, path))?;
+        let word = line.trim();
+        if !word.is_empty() {
+            words.insert(word.to_owned());
+        }
+    }
+
+    Ok(words)
+}
+
 const FASTTEXT_FORMAT_ERROR: &str = "Only embeddings trained with:
 
   --subwords buckets --hash-indexer fasttext
@@ -54,6 +176,21 @@ where
     fn run(&self) -> Result<()>;
 
     fn common_opts<'a, 'b>(name: &str) -> App<'a, 'b> {
+        Self::common_corpus_opts(name)
+            .setting(AppSettings::LowIndexMultiplePositional)
+            .arg(
+                Arg::with_name(Self::OUTPUT)
+                    .help("Embeddings output")
+                    .index(2)
+                    .required(true),
+            )
+    }
+
+    /// Common options without the output argument.
+    ///
+    /// Used by subcommands that process a corpus without training a
+    /// model.
+    fn common_corpus_opts<'a, 'b>(name: &str) -> App<'a, 'b> {
         let version = if let Some(git_desc) = option_env!("MAYBE_FINALFRONTIER_GIT_DESC") {
             git_desc
         } else {
@@ -62,6 +199,36 @@ where
         App::new(name)
             .settings(DEFAULT_CLAP_SETTINGS)
             .version(version)
+            .arg(
+                Arg::with_name(ANN_INDEX)
+                    .long("ann-index")
+                    .value_name("FILE")
+                    .help("Write an approximate nearest neighbor index of the word embeddings to FILE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(BATCH_NEGATIVES)
+                    .long("batch-negatives")
+                    .value_name("N")
+                    .help("Number of in-batch negatives of the InfoNCE loss")
+                    .takes_value(true)
+                    .default_value("10"),
+            )
+            .arg(
+                Arg::with_name(BPE_MERGES)
+                    .long("bpe-merges")
+                    .value_name("N")
+                    .help("Number of byte pair encoding merges to learn")
+                    .takes_value(true)
+                    .default_value("10000"),
+            )
+            .arg(
+                Arg::with_name(BPE_MERGES_FILE)
+                    .long("bpe-merges-file")
+                    .value_name("FILE")
+                    .help("Read byte pair encoding merges from FILE")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name(BUCKETS)
                     .long("buckets")
@@ -70,6 +237,32 @@ where
                     .takes_value(true)
                     .default_value("21"),
             )
+            .arg(
+                Arg::with_name(BUCKET_STATISTICS)
+                    .long("bucket-statistics")
+                    .help(
+                        "Report ngram collisions in buckets and store them in the model metadata",
+                    ),
+            )
+            .arg(
+                Arg::with_name(CASEFOLD)
+                    .long("casefold")
+                    .help("Apply Unicode case folding to all tokens")
+                    .conflicts_with(LOWERCASE),
+            )
+            .arg(
+                Arg::with_name(CLIP_NORM)
+                    .long("clip-norm")
+                    .value_name("NORM")
+                    .help("Clip the gradient norm of training instances to NORM")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(CLIP_PER_ROW)
+                    .long("clip-per-row")
+                    .help("Clip the gradient norm of every embedding row")
+                    .requires(CLIP_NORM),
+            )
             .arg(
                 Arg::with_name(DIMS)
                     .long("dims")
@@ -94,7 +287,21 @@ where
                     .help("Output format")
                     .takes_value(true)
                     .default_value("finalfusion")
-                    .possible_values(&["fasttext", "finalfusion", "word2vec", "text", "textdims"]),
+                    .possible_values(&[
+                        "fasttext",
+                        "finalfusion",
+                        "finalfusion-quantized",
+                        "word2vec",
+                        "text",
+                        "glove",
+                        "textdims",
+                        "floret",
+                        "gensim",
+                        "magnitude",
+                        "npz",
+                        "pgvector",
+                        "sqlite",
+                    ]),
             )
             .arg(
                 Arg::with_name(HASH_INDEXER_TYPE)
@@ -105,6 +312,14 @@ where
                     .default_value("finalfusion")
                     .possible_values(&["finalfusion", "fasttext"]),
             )
+            .arg(
+                Arg::with_name(INCLUDE)
+                    .long("include")
+                    .value_name("PATTERN")
+                    .help("Glob pattern of the files to read from corpus directories")
+                    .takes_value(true)
+                    .default_value("*"),
+            )
             .arg(
                 Arg::with_name(EPOCHS)
                     .long("epochs")
@@ -113,6 +328,25 @@ where
                     .takes_value(true)
                     .default_value("15"),
             )
+            .arg(
+                Arg::with_name(EXCLUDE_WORD)
+                    .long("exclude-word")
+                    .help("Represent words by their subword units only"),
+            )
+            .arg(
+                Arg::with_name(LOSS)
+                    .long("loss")
+                    .value_name("LOSS")
+                    .help("Loss function")
+                    .takes_value(true)
+                    .default_value("ns")
+                    .possible_values(&["infonce", "margin", "nce", "ns"]),
+            )
+            .arg(
+                Arg::with_name(LOWERCASE)
+                    .long("lowercase")
+                    .help("Lowercase all tokens"),
+            )
             .arg(
                 Arg::with_name(LR)
                     .long("lr")
@@ -121,6 +355,51 @@ where
                     .takes_value(true)
                     .default_value("0.05"),
             )
+            .arg(
+                Arg::with_name(LR_SCHEDULE)
+                    .long("lr-schedule")
+                    .value_name("SCHEDULE")
+                    .help("Learning rate schedule")
+                    .takes_value(true)
+                    .default_value("linear")
+                    .possible_values(&["constant", "cosine", "linear", "step"]),
+            )
+            .arg(
+                Arg::with_name(MARGIN)
+                    .long("margin")
+                    .value_name("MARGIN")
+                    .help("Margin of the margin ranking loss")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::with_name(MAX_COUNT_MEMORY)
+                    .long("max-count-memory")
+                    .value_name("MIB")
+                    .help("Approximate counts, using at most MIB mebibytes for the counts")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(MAX_COUNT_TYPES)
+                    .long("max-count-types")
+                    .value_name("N")
+                    .help("Approximate counts, keeping at most N types in memory")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(MAX_TOKEN_LEN)
+                    .long("max-token-len")
+                    .value_name("LEN")
+                    .help("Do not count tokens longer than LEN characters")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(MIN_TOKEN_LEN)
+                    .long("min-token-len")
+                    .value_name("LEN")
+                    .help("Do not count tokens shorter than LEN characters")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name(MINCOUNT)
                     .long("mincount")
@@ -136,6 +415,35 @@ where
                     .help("Target vocab size.")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name(MULTIWORD)
+                    .long("multiword")
+                    .value_name("FILE")
+                    .help("Merge the multi-word expressions in FILE (one per line) into tokens")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(VOCAB)
+                    .long("vocab")
+                    .value_name("FILE")
+                    .help("Train only the words in FILE (one per line)")
+                    .takes_value(true)
+                    .conflicts_with_all(&[MINCOUNT, TARGET_SIZE]),
+            )
+            .arg(
+                Arg::with_name(VOCAB_IN)
+                    .long("vocab-in")
+                    .value_name("FILE")
+                    .help("Read the vocabulary counts from FILE instead of counting the corpus")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(VOCAB_OUT)
+                    .long("vocab-out")
+                    .value_name("FILE")
+                    .help("Write the vocabulary counts to FILE")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name(MINN)
                     .long("minn")
@@ -152,12 +460,68 @@ where
                     .takes_value(true)
                     .default_value("6"),
             )
+            .arg(
+                Arg::with_name(BOUNDARIES)
+                    .long("boundaries")
+                    .value_name("MARKERS")
+                    .help("Word boundary markers of ngrams, e.g. '<>', or 'none'")
+                    .takes_value(true)
+                    .default_value("<>"),
+            )
+            .arg(
+                Arg::with_name(SCRIPT_NGRAMS)
+                    .long("script-ngrams")
+                    .value_name("LENGTHS")
+                    .help("Ngram lengths per script, e.g. han:1-2,latin:3-6")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(SEED)
+                    .long("seed")
+                    .value_name("SEED")
+                    .help("Seed the random number generators for reproducible training")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(SEGMENTATIONS)
+                    .long("segmentations")
+                    .value_name("FILE")
+                    .help("Use the word segmentations from FILE as subword units")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(SPLIT_COMPOUNDS)
+                    .long("split-compounds")
+                    .help("Extract the ngrams of compounds from their constituents"),
+            )
+            .arg(
+                Arg::with_name(SPM)
+                    .long("spm")
+                    .value_name("FILE")
+                    .help("SentencePiece model for sentencepiece subwords")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(STOPWORDS)
+                    .long("stopwords")
+                    .value_name("FILE")
+                    .help("Ignore the words in FILE (one per line) during training")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(SUBWORD_DROPOUT)
+                    .long("subword-dropout")
+                    .value_name("PROB")
+                    .help("Probability of dropping a subword unit during training")
+                    .takes_value(true)
+                    .default_value("0"),
+            )
             .arg(
                 Arg::with_name(SUBWORDS)
                     .long("subwords")
                     .takes_value(true)
                     .value_name("SUBWORDS")
-                    .possible_values(&["buckets", "ngrams", "none"])
+                    .possible_values(&["bpe", "buckets", "ngrams", "none", "sentencepiece"])
                     .default_value("buckets")
                     .help("What kind of subwords to use."),
             )
@@ -169,6 +533,13 @@ where
                     .takes_value(true)
                     .conflicts_with(NGRAM_TARGET_SIZE),
             )
+            .arg(
+                Arg::with_name(NGRAM_OUTPUT)
+                    .long("ngram-output")
+                    .value_name("FILE")
+                    .help("Write the embeddings of the retained ngrams to FILE")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name(NGRAM_TARGET_SIZE)
                     .long("ngram-target-size")
@@ -176,6 +547,31 @@ where
                     .help("Target ngram vocab size")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name(NGRAMS_OUT)
+                    .long("ngrams-out")
+                    .value_name("FILE")
+                    .help("Write the retained ngrams with their counts and indices to FILE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(NORMALIZATION)
+                    .long("normalization")
+                    .value_name("FORM")
+                    .help("Unicode normalization form of tokens")
+                    .takes_value(true)
+                    .default_value("none")
+                    .possible_values(&["none", "nfc", "nfd", "nfkc", "nfkd"]),
+            )
+            .arg(
+                Arg::with_name(NUMBERS)
+                    .long("numbers")
+                    .value_name("MODE")
+                    .help("Normalization of numbers")
+                    .takes_value(true)
+                    .default_value("none")
+                    .possible_values(&["none", "digits", "placeholder"]),
+            )
             .arg(
                 Arg::with_name(NS)
                     .long("ns")
@@ -184,6 +580,98 @@ where
                     .takes_value(true)
                     .default_value("5"),
             )
+            .arg(
+                Arg::with_name(NS_DISTRIBUTION)
+                    .long("ns-distribution")
+                    .value_name("DISTRIBUTION")
+                    .help("Distribution of negative samples and NCE noise")
+                    .takes_value(true)
+                    .default_value("zipf")
+                    .possible_values(&["unigram", "zipf"]),
+            )
+            .arg(
+                Arg::with_name(NS_EXCLUDE_WINDOW)
+                    .long("ns-exclude-window")
+                    .help("Reject negative samples that are outputs of the same focus"),
+            )
+            .arg(
+                Arg::with_name(NS_SHARED)
+                    .long("ns-shared")
+                    .help("Share negative samples across the outputs of a focus"),
+            )
+            .arg(
+                Arg::with_name(OPTIMIZER)
+                    .long("optimizer")
+                    .value_name("OPTIMIZER")
+                    .help("Optimizer")
+                    .takes_value(true)
+                    .default_value("sgd")
+                    .possible_values(&["adagrad", "adam", "sgd"]),
+            )
+            .arg(
+                Arg::with_name(OUTPUT_PRECISION)
+                    .long("output-precision")
+                    .value_name("PRECISION")
+                    .help("Precision of the written embeddings (f16 requires npz output)")
+                    .takes_value(true)
+                    .default_value("f32")
+                    .possible_values(&["f16", "f32"]),
+            )
+            .arg(
+                Arg::with_name(PQ_ATTEMPTS)
+                    .long("pq-attempts")
+                    .value_name("N")
+                    .help("Number of k-means attempts of product quantization")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::with_name(PQ_BITS)
+                    .long("pq-bits")
+                    .value_name("N")
+                    .help("Number of bits of product quantization codes")
+                    .takes_value(true)
+                    .default_value("8"),
+            )
+            .arg(
+                Arg::with_name(PQ_ITERATIONS)
+                    .long("pq-iterations")
+                    .value_name("N")
+                    .help("Number of k-means iterations of product quantization")
+                    .takes_value(true)
+                    .default_value("100"),
+            )
+            .arg(
+                Arg::with_name(PQ_SUBQUANTIZERS)
+                    .long("pq-subquantizers")
+                    .value_name("N")
+                    .help("Number of subquantizers of product quantization (default: dims/2)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(PRECISION)
+                    .long("precision")
+                    .value_name("PRECISION")
+                    .help("Precision of the stored embedding matrices")
+                    .takes_value(true)
+                    .default_value("f32")
+                    .possible_values(&["bf16", "f16", "f32"]),
+            )
+            .arg(
+                Arg::with_name(PRUNE_SUBWORDS)
+                    .long("prune-subwords")
+                    .value_name("N")
+                    .help("Retain only the N most used subword units")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(QAT_EPOCHS)
+                    .long("qat-epochs")
+                    .value_name("N")
+                    .help("Number of final epochs with quantization-aware training")
+                    .takes_value(true)
+                    .default_value("0"),
+            )
             .arg(
                 Arg::with_name(Self::THREADS)
                     .long("threads")
@@ -191,6 +679,64 @@ where
                     .help("Number of threads (default: min(logical_cpus / 2, 20))")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name(TIED)
+                    .long("tied")
+                    .help("Tie the output embeddings to the input embeddings"),
+            )
+            .arg(
+                Arg::with_name(TOKEN_RULES)
+                    .long("token-rules")
+                    .value_name("FILE")
+                    .help("Filter and rewrite tokens using the regex rules in FILE (TOML)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(UNK)
+                    .long("unk")
+                    .help("Train an embedding for tokens that are not in the vocabulary"),
+            )
+            .arg(
+                Arg::with_name(UNIGRAM_POWER)
+                    .long("unigram-power")
+                    .value_name("POWER")
+                    .help("Power of counts in the unigram negative sampling distribution")
+                    .takes_value(true)
+                    .default_value("0.75"),
+            )
+            .arg(
+                Arg::with_name(VECTORS_NPY)
+                    .long("vectors-npy")
+                    .help("Also write the embedding matrix of gensim output to OUTPUT.vectors.npy"),
+            )
+            .arg(
+                Arg::with_name(WARMUP)
+                    .long("warmup")
+                    .value_name("N")
+                    .help("Number of tokens with learning rate warmup")
+                    .takes_value(true)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::with_name(WEIGHT_DECAY)
+                    .long("weight-decay")
+                    .value_name("DECAY")
+                    .help("Weight decay of updated embeddings")
+                    .takes_value(true)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::with_name(WRITE_CONFIG)
+                    .long("write-config")
+                    .help("Also write the effective training configuration to OUTPUT.toml"),
+            )
+            .arg(
+                Arg::with_name(WRITE_TOP_K)
+                    .long("write-top-k")
+                    .value_name("N")
+                    .help("Only write the embeddings of the N most frequent words")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name(ZIPF_EXPONENT)
                     .long("zipf")
@@ -201,62 +747,341 @@ where
             )
             .arg(
                 Arg::with_name(Self::CORPUS)
-                    .help("Tokenized corpus")
+                    .help("Tokenized corpus files or glob patterns, or - to read the corpus from stdin")
                     .index(1)
+                    .multiple(true)
                     .required(true),
             )
-            .arg(
-                Arg::with_name(Self::OUTPUT)
-                    .help("Embeddings output")
-                    .index(2)
-                    .required(true),
-            )
+    }
+
+    /// Get the corpus files from `matches`.
+    ///
+    /// Glob patterns are expanded, so that a corpus that consists of
+    /// many files can be given without exceeding the maximum length of
+    /// the command line. Directories are read recursively, see
+    /// `read_corpus_dir`.
+    fn parse_corpus(matches: &ArgMatches) -> Result<Vec<String>> {
+        let include = matches.value_of(INCLUDE).unwrap();
+        let include = Pattern::new(include)
+            .with_context(|| format!("Invalid include pattern: {}", include))?;
+
+        let mut corpus = Vec::new();
+        for pattern in matches.values_of(Self::CORPUS).unwrap() {
+            if is_remote_corpus(pattern) {
+                corpus.push(pattern.to_owned());
+                continue;
+            }
+
+            let paths = if Path::new(pattern).exists() || !pattern.contains(&['*', '?', '['][..]) {
+                vec![PathBuf::from(pattern)]
+            } else {
+                let paths = glob::glob(pattern)
+                    .with_context(|| format!("Invalid corpus pattern: {}", pattern))?
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("Cannot expand corpus pattern")?;
+                ensure!(
+                    !paths.is_empty(),
+                    "Corpus pattern does not match any files: {}",
+                    pattern
+                );
+                paths
+            };
+
+            for path in paths {
+                if path.is_dir() {
+                    let n_files = corpus.len();
+                    read_corpus_dir(&path, &include, &mut corpus)?;
+                    ensure!(
+                        corpus.len() > n_files,
+                        "Corpus directory does not contain files matching {}: {}",
+                        include,
+                        path.display()
+                    );
+                } else {
+                    corpus.push(path_to_string(path)?);
+                }
+            }
+        }
+
+        Ok(corpus)
     }
 
     /// Construct `CommonConfig` from `matches`.
     fn parse_common_config(matches: &ArgMatches) -> Result<CommonConfig> {
+        let batch_negatives: u32 = matches
+            .value_of(BATCH_NEGATIVES)
+            .map(|v| {
+                v.parse()
+                    .context("Cannot parse number of in-batch negatives")
+            })
+            .transpose()?
+            .unwrap();
+        let clip_norm = matches
+            .value_of(CLIP_NORM)
+            .map(|v| v.parse().context("Cannot parse gradient clipping norm"))
+            .transpose()?;
+        if let Some(clip_norm) = clip_norm {
+            ensure!(
+                clip_norm > 0f32,
+                "Gradient clipping norm should be positive, was: {}",
+                clip_norm
+            );
+        }
+        let clip_per_row = matches.is_present(CLIP_PER_ROW);
         let dims = matches
             .value_of(DIMS)
             .map(|v| v.parse().context("Cannot parse dimensionality"))
             .transpose()?
             .unwrap();
-        let epochs = matches
+        let epochs: u32 = matches
             .value_of(EPOCHS)
             .map(|v| v.parse().context("Cannot parse number of epochs"))
             .transpose()?
             .unwrap();
-        let format = matches
+        let qat_epochs: u32 = matches
+            .value_of(QAT_EPOCHS)
+            .map(|v| {
+                v.parse()
+                    .context("Cannot parse number of quantization-aware epochs")
+            })
+            .transpose()?
+            .unwrap();
+        ensure!(
+            qat_epochs <= epochs,
+            "The number of quantization-aware epochs ({}) exceeds the number of epochs ({})",
+            qat_epochs,
+            epochs
+        );
+        let mut format = matches
             .value_of(FORMAT)
             .map(|v| v.try_into().context("Cannot parse output format"))
             .transpose()?
             .unwrap();
+        if let EmbeddingFormat::FinalFusionQuantized(ref mut quantizer) = format {
+            *quantizer = Self::parse_quantizer_config(matches, dims)?;
+        }
+        let loss = matches
+            .value_of(LOSS)
+            .map(|v| v.try_into().context("Cannot parse loss function"))
+            .transpose()?
+            .unwrap();
         let lr = matches
             .value_of(LR)
             .map(|v| v.parse().context("Cannot parse learning rate"))
             .transpose()?
             .unwrap();
-        let negative_samples = matches
+        let lr_schedule = matches
+            .value_of(LR_SCHEDULE)
+            .map(|v| v.try_into().context("Cannot parse learning rate schedule"))
+            .transpose()?
+            .unwrap();
+        let negative_samples: u32 = matches
             .value_of(NS)
             .map(|v| v.parse().context("Cannot parse number of negative samples"))
             .transpose()?
             .unwrap();
+        let ns_distribution = matches
+            .value_of(NS_DISTRIBUTION)
+            .map(|v| {
+                v.try_into()
+                    .context("Cannot parse negative sampling distribution")
+            })
+            .transpose()?
+            .unwrap();
+        let precision = matches
+            .value_of(PRECISION)
+            .map(|v| v.try_into().context("Cannot parse precision"))
+            .transpose()?
+            .unwrap();
+        let output_precision = matches
+            .value_of(OUTPUT_PRECISION)
+            .map(|v| v.try_into().context("Cannot parse output precision"))
+            .transpose()?
+            .unwrap();
+        // The finalfusion format does not have a half-precision storage
+        // chunk, so only npz files can be written in half precision.
+        ensure!(
+            output_precision == Precision::F32 || format == EmbeddingFormat::Npz,
+            "Half-precision output is only supported by the npz format"
+        );
+        let ns_exclude_window = matches.is_present(NS_EXCLUDE_WINDOW);
+        let ns_shared = matches.is_present(NS_SHARED);
+        let optimizer = matches
+            .value_of(OPTIMIZER)
+            .map(|v| v.try_into().context("Cannot parse optimizer"))
+            .transpose()?
+            .unwrap();
+        let subword_dropout = matches
+            .value_of(SUBWORD_DROPOUT)
+            .map(|v| {
+                v.parse()
+                    .context("Cannot parse subword dropout probability")
+            })
+            .transpose()?
+            .unwrap();
+        ensure!(
+            (0f32..1f32).contains(&subword_dropout),
+            "Subword dropout probability should be in [0, 1), was: {}",
+            subword_dropout
+        );
+        let tied = matches.is_present(TIED);
+        let unigram_power = matches
+            .value_of(UNIGRAM_POWER)
+            .map(|v| v.parse().context("Cannot parse unigram power"))
+            .transpose()?
+            .unwrap();
+        let warmup = matches
+            .value_of(WARMUP)
+            .map(|v| v.parse().context("Cannot parse number of warmup tokens"))
+            .transpose()?
+            .unwrap();
+        let weight_decay = matches
+            .value_of(WEIGHT_DECAY)
+            .map(|v| v.parse().context("Cannot parse weight decay"))
+            .transpose()?
+            .unwrap();
+        ensure!(
+            weight_decay >= 0f32,
+            "Weight decay should be non-negative, was: {}",
+            weight_decay
+        );
+        if let LossType::InfoNce = loss {
+            ensure!(
+                negative_samples + batch_negatives > 0,
+                "The InfoNCE loss requires sampled or in-batch negatives"
+            );
+        }
+        let margin = matches
+            .value_of(MARGIN)
+            .map(|v| v.parse().context("Cannot parse margin"))
+            .transpose()?
+            .unwrap();
+        ensure!(margin > 0f32, "Margin should be positive, was: {}", margin);
+        let write_top_k = matches
+            .value_of(WRITE_TOP_K)
+            .map(|v| v.parse().context("Cannot parse number of words to write"))
+            .transpose()?;
+        ensure!(
+            write_top_k.is_none() || format != EmbeddingFormat::Floret,
+            "floret files do not have a vocabulary, cannot restrict the words that are written"
+        );
         let zipf_exponent = matches
             .value_of(ZIPF_EXPONENT)
             .map(|v| v.parse().context("Cannot parse exponent zipf distribution"))
             .transpose()?
             .unwrap();
+        let case_folding = if matches.is_present(CASEFOLD) {
+            CaseFolding::Casefold
+        } else if matches.is_present(LOWERCASE) {
+            CaseFolding::Lowercase
+        } else {
+            CaseFolding::None
+        };
+        let form = matches
+            .value_of(NORMALIZATION)
+            .map(|v| v.try_into().context("Cannot parse normalization form"))
+            .transpose()?
+            .unwrap();
+        let numbers = matches
+            .value_of(NUMBERS)
+            .map(|v| v.try_into().context("Cannot parse number normalization"))
+            .transpose()?
+            .unwrap();
+        let seed = matches
+            .value_of(SEED)
+            .map(|v| v.parse().context("Cannot parse seed"))
+            .transpose()?;
 
         Ok(CommonConfig {
-            loss: LossType::LogisticNegativeSampling,
+            batch_negatives,
+            clip_norm,
+            clip_per_row,
+            loss,
             dims,
             epochs,
             format,
             lr,
+            lr_schedule,
+            margin,
+            normalization: TokenNormalization {
+                form,
+                case_folding,
+                numbers,
+            },
+            optimizer,
+            output_precision,
+            precision,
+            qat_epochs,
             negative_samples,
+            ns_distribution,
+            ns_exclude_window,
+            ns_shared,
+            seed,
+            subword_dropout,
+            tied,
+            unigram_power,
+            warmup,
+            weight_decay,
+            write_top_k,
             zipf_exponent,
         })
     }
 
+    /// Construct `QuantizerConfig` from `matches`.
+    fn parse_quantizer_config(matches: &ArgMatches, dims: u32) -> Result<QuantizerConfig> {
+        let n_subquantizers = matches
+            .value_of(PQ_SUBQUANTIZERS)
+            .map(|v| v.parse().context("Cannot parse number of subquantizers"))
+            .transpose()?;
+        let n_subquantizer_bits = matches
+            .value_of(PQ_BITS)
+            .map(|v| {
+                v.parse()
+                    .context("Cannot parse number of quantization bits")
+            })
+            .transpose()?
+            .unwrap();
+        let n_iterations = matches
+            .value_of(PQ_ITERATIONS)
+            .map(|v| {
+                v.parse()
+                    .context("Cannot parse number of k-means iterations")
+            })
+            .transpose()?
+            .unwrap();
+        let n_attempts = matches
+            .value_of(PQ_ATTEMPTS)
+            .map(|v| v.parse().context("Cannot parse number of k-means attempts"))
+            .transpose()?
+            .unwrap();
+
+        let quantizer = QuantizerConfig {
+            n_subquantizers,
+            n_subquantizer_bits,
+            n_iterations,
+            n_attempts,
+        };
+
+        let n_subquantizers = quantizer.n_subquantizers.unwrap_or(dims / 2);
+        ensure!(
+            n_subquantizers > 0 && dims % n_subquantizers == 0,
+            "The number of dimensions ({}) must be a multiple of the number of subquantizers ({})",
+            dims,
+            n_subquantizers
+        );
+        ensure!(
+            (1..=8).contains(&quantizer.n_subquantizer_bits),
+            "The number of quantization bits must be between 1 and 8, was: {}",
+            quantizer.n_subquantizer_bits
+        );
+        ensure!(
+            quantizer.n_iterations > 0 && quantizer.n_attempts > 0,
+            "The number of k-means iterations and attempts must be positive"
+        );
+
+        Ok(quantizer)
+    }
+
     /// Construct `SubwordVocabConfig` from `matches`.
     fn parse_vocab_config(
         common_config: CommonConfig,
@@ -267,8 +1092,13 @@ where
             .map(|v| v.parse().context("Cannot parse discard threshold"))
             .transpose()?
             .unwrap();
-        let cutoff =
-            cutoff_from_matches(matches, MINCOUNT, TARGET_SIZE)?.unwrap_or(Cutoff::MinCount(5));
+        // With a fixed vocabulary, all words from the vocabulary file are
+        // retained, including words that do not occur in the corpus.
+        let cutoff = if matches.is_present(VOCAB) {
+            Cutoff::MinCount(0)
+        } else {
+            cutoff_from_matches(matches, MINCOUNT, TARGET_SIZE)?.unwrap_or(Cutoff::MinCount(5))
+        };
         let min_n = matches
             .value_of(MINN)
             .map(|v| v.parse().context("Cannot parse minimum n-gram length"))
@@ -279,6 +1109,29 @@ where
             .map(|v| v.parse().context("Cannot parse maximum n-gram length"))
             .transpose()?
             .unwrap();
+        let script_ngrams = matches
+            .value_of(SCRIPT_NGRAMS)
+            .map(|v| {
+                v.try_into()
+                    .context("Cannot parse ngram lengths per script")
+            })
+            .transpose()?
+            .unwrap_or_else(ScriptNGrams::new);
+        let boundaries: WordBoundaries = matches
+            .value_of(BOUNDARIES)
+            .map(|v| v.try_into().context("Cannot parse word boundary markers"))
+            .transpose()?
+            .unwrap();
+        ensure!(
+            boundaries == WordBoundaries::BRACKETS
+                || matches!(matches.value_of(SUBWORDS), Some("buckets") | Some("ngrams")),
+            "Word boundary markers can only be changed with ngram subwords"
+        );
+        let include_word = !matches.is_present(EXCLUDE_WORD);
+        ensure!(
+            include_word || common_config.format != EmbeddingFormat::FastText,
+            "Words must be included in their representations in fastText format"
+        );
         match matches.value_of(SUBWORDS).unwrap() {
             "buckets" => {
                 let buckets_exp = matches
@@ -297,12 +1150,25 @@ where
                         || indexer == BucketIndexerType::FastText,
                     FASTTEXT_FORMAT_ERROR
                 );
+                ensure!(
+                    common_config.format != EmbeddingFormat::FastText || script_ngrams.is_empty(),
+                    "Ngram lengths per script cannot be used with the fastText format"
+                );
+                ensure!(
+                    common_config.format != EmbeddingFormat::FastText
+                        || boundaries == WordBoundaries::BRACKETS,
+                    "Word boundary markers cannot be changed in fastText format"
+                );
 
                 Ok(VocabConfig::SubwordVocab(SubwordVocabConfig {
                     discard_threshold,
                     cutoff,
                     max_n,
                     min_n,
+                    include_word,
+                    script_ngrams,
+                    split_compounds: false,
+                    boundaries,
                     indexer: BucketConfig {
                         buckets_exp,
                         indexer_type: indexer,
@@ -322,16 +1188,92 @@ where
                     cutoff,
                     max_n,
                     min_n,
+                    include_word,
+                    script_ngrams,
+                    split_compounds: false,
+                    boundaries,
                     indexer: NGramConfig {
                         cutoff: ngram_cutoff,
                     },
                 }))
             }
+            "bpe" => {
+                ensure!(
+                    common_config.format != EmbeddingFormat::FastText,
+                    FASTTEXT_FORMAT_ERROR
+                );
+
+                let merges_file = matches.value_of(BPE_MERGES_FILE).map(ToOwned::to_owned);
+
+                // Merges are not learned when they are read from a file.
+                let merges = if merges_file.is_some() {
+                    0
+                } else {
+                    matches
+                        .value_of(BPE_MERGES)
+                        .map(|v| v.parse().context("Cannot parse number of merges"))
+                        .transpose()?
+                        .unwrap()
+                };
+
+                Ok(VocabConfig::BpeVocab(
+                    SubwordVocabConfig {
+                        discard_threshold,
+                        cutoff,
+                        max_n,
+                        min_n,
+                        include_word,
+                        script_ngrams,
+                        split_compounds: false,
+                        boundaries,
+                        indexer: BpeConfig { merges },
+                    },
+                    merges_file,
+                ))
+            }
+            "sentencepiece" => {
+                ensure!(
+                    common_config.format != EmbeddingFormat::FastText,
+                    FASTTEXT_FORMAT_ERROR
+                );
+
+                let model = matches
+                    .value_of(SPM)
+                    .context("sentencepiece subwords require a model: --spm FILE")?;
+
+                Ok(VocabConfig::SentencePieceVocab(
+                    SubwordVocabConfig {
+                        discard_threshold,
+                        cutoff,
+                        max_n,
+                        min_n,
+                        include_word,
+                        script_ngrams,
+                        split_compounds: false,
+                        boundaries,
+                        // Set when the model is loaded.
+                        indexer: SentencePieceConfig { pieces: 0 },
+                    },
+                    model.to_owned(),
+                ))
+            }
             "none" => {
                 ensure!(
                     common_config.format != EmbeddingFormat::FastText,
                     FASTTEXT_FORMAT_ERROR
                 );
+                ensure!(
+                    matches.value_of(SEGMENTATIONS).is_none(),
+                    "Segmentations can only be used with subwords"
+                );
+                ensure!(
+                    include_word,
+                    "Words can only be excluded from their representations with subwords"
+                );
+                ensure!(
+                    script_ngrams.is_empty(),
+                    "Ngram lengths per script can only be used with subwords"
+                );
 
                 Ok(VocabConfig::SimpleVocab(SimpleVocabConfig {
                     cutoff,
@@ -344,6 +1286,277 @@ where
         }
     }
 
+    /// Read word segmentations from the file in `matches`.
+    ///
+    /// No words are segmented when no segmentation file is given.
+    fn parse_segmentations(matches: &ArgMatches) -> Result<HashMap<String, Vec<String>>> {
+        let path = match matches.value_of(SEGMENTATIONS) {
+            Some(path) => path,
+            None => return Ok(HashMap::new()),
+        };
+
+        let f =
+            File::open(path).with_context(|| format!("Cannot open segmentation file: {}", path))?;
+        read_segmentations(BufReader::new(f))
+            .with_context(|| format!("Cannot read segmentation file: {}", path))
+    }
+
+    /// Construct `VocabOptions` from `matches`.
+    ///
+    /// The token normalization of the common config is also applied to
+    /// the stopwords and the words of a fixed vocabulary.
+    fn parse_vocab_options(
+        common_config: CommonConfig,
+        matches: &ArgMatches,
+    ) -> Result<VocabOptions> {
+        let min_token_len = matches
+            .value_of(MIN_TOKEN_LEN)
+            .map(|v| v.parse().context("Cannot parse minimum token length"))
+            .transpose()?;
+        let max_token_len = matches
+            .value_of(MAX_TOKEN_LEN)
+            .map(|v| v.parse().context("Cannot parse maximum token length"))
+            .transpose()?;
+        if let (Some(min_token_len), Some(max_token_len)) = (min_token_len, max_token_len) {
+            ensure!(
+                min_token_len <= max_token_len,
+                "The minimum token length ({}) exceeds the maximum token length ({})",
+                min_token_len,
+                max_token_len
+            );
+        }
+
+        let bucket_statistics = matches.is_present(BUCKET_STATISTICS);
+        ensure!(
+            !bucket_statistics || matches.value_of(SUBWORDS) == Some("buckets"),
+            "Bucket statistics can only be computed when bucket subwords are used"
+        );
+
+        let ngrams_out = matches.value_of(NGRAMS_OUT).map(ToOwned::to_owned);
+        ensure!(
+            ngrams_out.is_none() || matches.value_of(SUBWORDS) == Some("ngrams"),
+            "Ngrams can only be written when ngram subwords are used"
+        );
+
+        let unk = matches.is_present(UNK);
+        ensure!(
+            !unk || matches.value_of(SUBWORDS) == Some("none"),
+            "Unknown word embeddings can only be trained without subwords, use: --subwords none"
+        );
+
+        let prune_subwords = matches
+            .value_of(PRUNE_SUBWORDS)
+            .map(|v| v.parse().context("Cannot parse number of subword units"))
+            .transpose()?;
+        ensure!(
+            prune_subwords.is_none() || matches.value_of(SUBWORDS) != Some("none"),
+            "Subword units can only be pruned when subwords are used"
+        );
+        ensure!(
+            prune_subwords.is_none() || !bucket_statistics,
+            "Bucket statistics cannot be computed when subword units are pruned"
+        );
+
+        let split_compounds = matches.is_present(SPLIT_COMPOUNDS);
+        ensure!(
+            !split_compounds
+                || matches!(matches.value_of(SUBWORDS), Some("buckets") | Some("ngrams")),
+            "Compounds can only be split with ngram subwords, use: --subwords buckets or ngrams"
+        );
+        ensure!(
+            !split_compounds || common_config.format != EmbeddingFormat::FastText,
+            "Compounds cannot be split in fastText format"
+        );
+
+        let normalization = common_config.normalization;
+        let normalize = |words: HashSet<String>| {
+            words
+                .iter()
+                .map(|word| normalization.normalize(word).into_owned())
+                .collect::<HashSet<_>>()
+        };
+
+        Ok(VocabOptions {
+            stopwords: normalize(Self::parse_stopwords(matches)?),
+            vocab_words: Self::parse_vocab_words(matches)?.map(normalize),
+            max_types: Self::parse_max_count_types(matches)?,
+            max_memory: Self::parse_max_count_memory(matches)?,
+            normalization,
+            min_token_len,
+            max_token_len,
+            rules: Arc::new(Self::parse_token_rules(matches)?),
+            multiword: Arc::new(Self::parse_multiword_lexicon(matches, normalization)?),
+            vocab_in: matches.value_of(VOCAB_IN).map(ToOwned::to_owned),
+            vocab_out: matches.value_of(VOCAB_OUT).map(ToOwned::to_owned),
+            ngrams_out,
+            bucket_statistics,
+            unk,
+            prune_subwords,
+            split_compounds,
+            corpus_format: CorpusFormat::default(),
+            text_field: DEFAULT_TEXT_FIELD.to_owned(),
+            weights: false,
+            weight_field: DEFAULT_WEIGHT_FIELD.to_owned(),
+            tokenizer: Tokenizer::default(),
+            noise: NoiseFilters::default(),
+            dedup: None,
+            language: None,
+        })
+    }
+
+    /// Get the output file of the ngram embeddings from `matches`.
+    ///
+    /// Returns `None` when the ngram embeddings should not be written
+    /// separately.
+    fn parse_ngram_output(matches: &ArgMatches) -> Result<Option<String>> {
+        let ngram_output = matches.value_of(NGRAM_OUTPUT).map(ToOwned::to_owned);
+        ensure!(
+            ngram_output.is_none() || matches.value_of(SUBWORDS) == Some("ngrams"),
+            "Ngram embeddings can only be written when ngram subwords are used"
+        );
+
+        Ok(ngram_output)
+    }
+
+    /// Get the output file of the approximate nearest neighbor index from
+    /// `matches`.
+    ///
+    /// Returns `None` when no index should be built.
+    fn parse_ann_index(matches: &ArgMatches) -> Result<Option<String>> {
+        let ann_index = matches.value_of(ANN_INDEX).map(ToOwned::to_owned);
+        ensure!(
+            ann_index.is_none() || matches.value_of(FORMAT) != Some("floret"),
+            "floret files do not have a vocabulary, cannot build a nearest neighbor index"
+        );
+
+        Ok(ann_index)
+    }
+
+    /// Get the output file of the embedding matrix of gensim output from
+    /// `matches`.
+    ///
+    /// The matrix is written next to the embeddings output, with the
+    /// suffix `.vectors.npy`. Returns `None` when the matrix should not
+    /// be written.
+    fn parse_vectors_npy(matches: &ArgMatches) -> Result<Option<String>> {
+        if !matches.is_present(VECTORS_NPY) {
+            return Ok(None);
+        }
+
+        ensure!(
+            matches.value_of(FORMAT) == Some("gensim"),
+            "The embedding matrix can only be written separately with: --format gensim"
+        );
+
+        Ok(Some(format!(
+            "{}.vectors.npy",
+            matches.value_of(Self::OUTPUT).unwrap()
+        )))
+    }
+
+    /// Get the output file of the training configuration from `matches`.
+    ///
+    /// The configuration is written next to the embeddings output, with
+    /// the suffix `.toml`. Returns `None` when the configuration should
+    /// not be written.
+    fn parse_config_output(matches: &ArgMatches) -> Option<String> {
+        if matches.is_present(WRITE_CONFIG) {
+            Some(format!("{}.toml", matches.value_of(Self::OUTPUT).unwrap()))
+        } else {
+            None
+        }
+    }
+
+    /// Read stopwords from the file in `matches`.
+    ///
+    /// The file contains one stopword per line. No words are ignored
+    /// when no stopword file is given.
+    fn parse_stopwords(matches: &ArgMatches) -> Result<HashSet<String>> {
+        match matches.value_of(STOPWORDS) {
+            Some(path) => read_words(path, "stopword"),
+            None => Ok(HashSet::new()),
+        }
+    }
+
+    /// Get the maximum number of types to count from `matches`.
+    ///
+    /// Returns `None` when all types should be counted exactly.
+    fn parse_max_count_types(matches: &ArgMatches) -> Result<Option<usize>> {
+        let max_types = matches
+            .value_of(MAX_COUNT_TYPES)
+            .map(|v| {
+                v.parse()
+                    .context("Cannot parse maximum number of counted types")
+            })
+            .transpose()?;
+        ensure!(
+            max_types != Some(0),
+            "The maximum number of counted types should be positive"
+        );
+
+        Ok(max_types)
+    }
+
+    /// Get the maximum memory use of the counts in bytes from `matches`.
+    ///
+    /// Returns `None` when the memory use of the counts is not bounded.
+    fn parse_max_count_memory(matches: &ArgMatches) -> Result<Option<usize>> {
+        let max_memory: Option<usize> = matches
+            .value_of(MAX_COUNT_MEMORY)
+            .map(|v| v.parse().context("Cannot parse maximum count memory"))
+            .transpose()?;
+        ensure!(
+            max_memory != Some(0),
+            "The maximum count memory should be positive"
+        );
+
+        Ok(max_memory.map(|mib| mib * 1024 * 1024))
+    }
+
+    /// Read the fixed vocabulary from the file in `matches`.
+    ///
+    /// The file contains one word per line. Returns `None` when the
+    /// vocabulary should be selected from the corpus.
+    fn parse_vocab_words(matches: &ArgMatches) -> Result<Option<HashSet<String>>> {
+        matches
+            .value_of(VOCAB)
+            .map(|path| read_words(path, "vocabulary"))
+            .transpose()
+    }
+
+    /// Read token rules from the file in `matches`.
+    ///
+    /// Tokens are not filtered or rewritten when no rules file is given.
+    fn parse_token_rules(matches: &ArgMatches) -> Result<TokenRules> {
+        match matches.value_of(TOKEN_RULES) {
+            Some(path) => {
+                let f = File::open(path)
+                    .with_context(|| format!("Cannot open token rules file: {}", path))?;
+                TokenRules::read(BufReader::new(f))
+                    .with_context(|| format!("Cannot read token rules file: {}", path))
+            }
+            None => Ok(TokenRules::default()),
+        }
+    }
+
+    /// Read the multi-word expression lexicon from the file in `matches`.
+    ///
+    /// No expressions are merged when no lexicon is given.
+    fn parse_multiword_lexicon(
+        matches: &ArgMatches,
+        normalization: TokenNormalization,
+    ) -> Result<MultiwordLexicon> {
+        match matches.value_of(MULTIWORD) {
+            Some(path) => {
+                let f = File::open(path)
+                    .with_context(|| format!("Cannot open multi-word expression file: {}", path))?;
+                MultiwordLexicon::read(BufReader::new(f), normalization)
+                    .with_context(|| format!("Cannot read multi-word expression file: {}", path))
+            }
+            None => Ok(MultiwordLexicon::default()),
+        }
+    }
+
     /// Get features that will be used by SIMD code paths.
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn simd_features() -> Vec<&'static str> {
@@ -367,6 +1580,22 @@ where
             features.push("-fma");
         }
 
+        if cfg!(feature = "avx512") && is_x86_feature_detected!("avx512f") {
+            features.push("+avx512f");
+        } else {
+            features.push("-avx512f");
+        }
+
         features
     }
+
+    /// Get features that will be used by SIMD code paths.
+    #[cfg(target_arch = "aarch64")]
+    fn simd_features() -> Vec<&'static str> {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            vec!["+neon"]
+        } else {
+            vec!["-neon"]
+        }
+    }
 }
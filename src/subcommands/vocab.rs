@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use clap::{App, Arg, ArgMatches};
+use finalfrontier::{
+    BucketIndexerType, CommonConfig, OptimizerType, Precision, SimpleVocab, SubwordVocab, Vocab,
+};
+use finalfusion::compat::fasttext::FastTextIndexer;
+use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+
+use crate::subcommands::skipgram::build_vocab;
+use crate::subcommands::{
+    add_bucket_statistics, add_unk, load_bpe_merges, load_sentencepiece, prune_subwords,
+    split_compounds, write_ngrams, FinalfrontierApp, VocabConfig, VocabOptions,
+};
+
+static TOP: &str = "top";
+
+const MIB: f64 = 1024. * 1024.;
+
+/// Subcommand for reporting vocabulary statistics.
+///
+/// This subcommand builds the vocabulary of a corpus with the same
+/// options as the training subcommands, but reports statistics of
+/// the vocabulary rather than training a model.
+pub struct VocabApp {
+    corpus: Vec<String>,
+    common_config: CommonConfig,
+    vocab_config: VocabConfig,
+    segmentations: HashMap<String, Vec<String>>,
+    vocab_options: VocabOptions,
+    top: usize,
+}
+
+impl VocabApp {
+    /// Get the corpus paths.
+    pub fn corpus(&self) -> &[String] {
+        &self.corpus
+    }
+
+    /// Get the common config.
+    pub fn common_config(&self) -> CommonConfig {
+        self.common_config
+    }
+}
+
+impl FinalfrontierApp for VocabApp {
+    fn app() -> App<'static, 'static> {
+        Self::common_corpus_opts("vocab")
+            .about("Report vocabulary statistics of a corpus")
+            .arg(
+                Arg::with_name(TOP)
+                    .long("top")
+                    .value_name("N")
+                    .help("Number of most frequent words to report")
+                    .takes_value(true)
+                    .default_value("10"),
+            )
+    }
+
+    fn parse(matches: &ArgMatches) -> Result<Self> {
+        let corpus = Self::parse_corpus(matches)?;
+
+        let common_config = Self::parse_common_config(&matches)?;
+
+        let top = matches
+            .value_of(TOP)
+            .map(|v| v.parse().context("Cannot parse number of top words"))
+            .transpose()?
+            .unwrap();
+
+        Ok(VocabApp {
+            corpus,
+            common_config,
+            vocab_config: Self::parse_vocab_config(common_config, &matches)?,
+            segmentations: Self::parse_segmentations(&matches)?,
+            vocab_options: Self::parse_vocab_options(common_config, &matches)?,
+            top,
+        })
+    }
+
+    fn run(&self) -> Result<()> {
+        match self.vocab_config.clone() {
+            VocabConfig::SubwordVocab(config) => match config.indexer.indexer_type {
+                BucketIndexerType::Finalfusion => {
+                    let vocab: SubwordVocab<_, FinalfusionHashIndexer> =
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            report(&vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            report(&vocab, self)
+                        }
+                    }
+                }
+                BucketIndexerType::FastText => {
+                    let vocab: SubwordVocab<_, FastTextIndexer> =
+                        build_vocab(config, self.corpus(), &self.vocab_options)?;
+                    match self.vocab_options.prune_subwords {
+                        Some(max_ngrams) => {
+                            let vocab = vocab.prune_ngrams(max_ngrams);
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            report(&vocab, self)
+                        }
+                        None => {
+                            let vocab = split_compounds(vocab, &self.vocab_options);
+                            let vocab = vocab.with_segmentations(&self.segmentations);
+                            let vocab = add_bucket_statistics(vocab, &self.vocab_options);
+                            report(&vocab, self)
+                        }
+                    }
+                }
+            },
+            VocabConfig::SimpleVocab(config) => {
+                let vocab: SimpleVocab<String> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = add_unk(vocab, &self.vocab_options);
+                report(&vocab, self);
+            }
+            VocabConfig::NGramVocab(config) => {
+                let vocab: SubwordVocab<_, ExplicitIndexer> =
+                    build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = split_compounds(vocab, &self.vocab_options);
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                write_ngrams(&vocab, self.vocab_options.ngrams_out.as_deref())?;
+                report(&vocab, self);
+            }
+            VocabConfig::BpeVocab(config, merges) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_bpe_merges(vocab, merges.as_deref())?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                report(&vocab, self);
+            }
+            VocabConfig::SentencePieceVocab(config, model) => {
+                let vocab = build_vocab(config, self.corpus(), &self.vocab_options)?;
+                let vocab = load_sentencepiece(vocab, &model)?;
+                let vocab = vocab.with_segmentations(&self.segmentations);
+                let vocab = prune_subwords(vocab, &self.vocab_options);
+                report(&vocab, self);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Print statistics of the vocabulary.
+fn report<V>(vocab: &V, app: &VocabApp)
+where
+    V: Vocab<VocabType = String>,
+{
+    let n_tokens = vocab.n_types();
+    let n_covered: usize = vocab.types().iter().map(|t| t.count()).sum();
+    let coverage = if n_tokens == 0 {
+        0.
+    } else {
+        100. * n_covered as f64 / n_tokens as f64
+    };
+
+    println!("Vocabulary size: {}", vocab.len());
+    println!("Input types: {}", vocab.n_input_types());
+    println!("Corpus tokens: {}", n_tokens);
+    println!("Token coverage: {:.2}%", coverage);
+
+    println!();
+    println!("Most frequent words:");
+    println!(
+        "{:>6}\t{:<24}\t{:>12}\t{:>8}",
+        "rank", "word", "count", "discard"
+    );
+    for (idx, word) in vocab.types().iter().take(app.top).enumerate() {
+        println!(
+            "{:>6}\t{:<24}\t{:>12}\t{:>8.4}",
+            idx + 1,
+            word.word(),
+            word.count(),
+            vocab.discard(idx)
+        );
+    }
+
+    let common_config = app.common_config();
+    let (input, output, optimizer) = memory_footprint(common_config, vocab);
+    println!();
+    println!("Estimated memory use (skip-gram model):");
+    println!("Input matrix: {:.1} MiB", input as f64 / MIB);
+    println!("Output matrix: {:.1} MiB", output as f64 / MIB);
+    println!("Optimizer state: {:.1} MiB", optimizer as f64 / MIB);
+    println!(
+        "Total: {:.1} MiB",
+        (input + output + optimizer) as f64 / MIB
+    );
+}
+
+/// Estimate the memory use of the embedding matrices in bytes.
+///
+/// Returns the sizes of the input matrix, output matrix, and optimizer
+/// state. The output matrix is assumed to have a row per word in the
+/// vocabulary, as in the skip-gram model.
+fn memory_footprint<V>(common_config: CommonConfig, vocab: &V) -> (usize, usize, usize)
+where
+    V: Vocab,
+{
+    let dims = common_config.dims as usize;
+    let bytes_per_value = match common_config.precision {
+        Precision::F32 => 4,
+        Precision::F16 | Precision::BF16 => 2,
+    };
+
+    let input_rows = vocab.n_input_types();
+    let output_rows = if common_config.tied { 0 } else { vocab.len() };
+
+    let input = input_rows * dims * bytes_per_value;
+    let output = output_rows * dims * bytes_per_value;
+
+    let rows = input_rows + output_rows;
+    let optimizer = match common_config.optimizer {
+        OptimizerType::Sgd => 0,
+        // Accumulator per row.
+        OptimizerType::AdaGrad => rows * 4,
+        // First and second moments per parameter, step count per row.
+        OptimizerType::Adam => rows * (2 * dims * 4 + 8),
+    };
+
+    (input, output, optimizer)
+}
@@ -0,0 +1,328 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::io::{Seek, Write};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use finalfusion::io::WriteEmbeddings;
+use finalfusion::norms::NdNorms;
+use finalfusion::prelude::Embeddings;
+use finalfusion::storage::NdArray;
+use finalfusion::vocab::SimpleVocab as FiFuSimpleVocab;
+use ndarray::{Array1, Array2};
+use rand::{Rng, RngCore, SeedableRng};
+use serde::Serialize;
+
+use crate::idx::{BagOfWordsIdx, WordIdx};
+use crate::sampling::{NegativeSamplingRangeGenerator, RangeGenerator};
+use crate::train_model::{NegativeSamples, TrainIterFrom, TrainModel, Trainer};
+use crate::util::{Reseed, ReseedOnCloneRng};
+use crate::vec_simd::l2_normalize;
+use crate::vocab::word_ngram_indices;
+use crate::{CommonConfig, SimpleVocab, SupervisedConfig, Vocab};
+
+/// Prefix that marks a token as a label.
+pub const LABEL_PREFIX: &str = "__label__";
+
+/// Supervised classifier Trainer
+///
+/// The `SupervisedTrainer` holds the information and logic necessary to
+/// transform a labeled sequence into a training example for a linear
+/// classifier (Joulin et al., 2017). Tokens that start with
+/// `LABEL_PREFIX` are the labels of the sequence, the remaining tokens
+/// form the input. The input is the average of the embeddings of the
+/// words (including their subword units) and word n-grams of the
+/// sequence. The outputs are the labels. The struct is cheap to clone
+/// because the vocabularies are shared between clones.
+#[derive(Clone)]
+pub struct SupervisedTrainer<R, V> {
+    vocab: Arc<V>,
+    labels: Arc<SimpleVocab<String>>,
+    rng: R,
+    range_gen: NegativeSamplingRangeGenerator<R>,
+    common_config: CommonConfig,
+    supervised_config: SupervisedConfig,
+}
+
+impl<R, V> SupervisedTrainer<ReseedOnCloneRng<R>, V>
+where
+    R: Rng + Clone + SeedableRng,
+    V: Vocab,
+{
+    /// Constructs a new `SupervisedTrainer`.
+    ///
+    /// Negative labels are sampled from the negative sampling
+    /// distribution over the labels, ranked by frequency.
+    pub fn new(
+        vocab: V,
+        labels: SimpleVocab<String>,
+        rng: R,
+        common_config: CommonConfig,
+        supervised_config: SupervisedConfig,
+    ) -> Self {
+        let vocab = Arc::new(vocab);
+        let labels = Arc::new(labels);
+        let rng = ReseedOnCloneRng::new(rng);
+        let range_gen = NegativeSamplingRangeGenerator::new(
+            rng.clone(),
+            labels.types().iter().map(|word| word.count()),
+            &common_config,
+        );
+        SupervisedTrainer {
+            vocab,
+            labels,
+            rng,
+            range_gen,
+            common_config,
+            supervised_config,
+        }
+    }
+}
+
+impl<R, V> SupervisedTrainer<R, V> {
+    /// Get the label vocabulary.
+    pub fn labels(&self) -> &SimpleVocab<String> {
+        &self.labels
+    }
+}
+
+impl<'a, S, R, V> TrainIterFrom<'a, [S]> for SupervisedTrainer<R, V>
+where
+    S: Hash + Eq + AsRef<str>,
+    R: Rng + Clone,
+    V: Vocab,
+    V::VocabType: Borrow<S>,
+    for<'b> &'b V::IdxType: IntoIterator<Item = u64>,
+{
+    type Iter = std::option::IntoIter<(BagOfWordsIdx, Vec<usize>)>;
+    type Focus = BagOfWordsIdx;
+    type Contexts = Vec<usize>;
+
+    fn train_iter_from(&mut self, sequence: &[S]) -> Self::Iter {
+        let (labels, words): (Vec<_>, Vec<_>) = sequence
+            .iter()
+            .partition(|t| t.as_ref().starts_with(LABEL_PREFIX));
+
+        let labels = labels
+            .into_iter()
+            .filter_map(|t| self.labels.idx::<str>(t.as_ref()))
+            .map(|idx| idx.word_idx() as usize)
+            .collect::<Vec<_>>();
+
+        let mut bag = BagOfWordsIdx::new(word_ngram_indices(
+            &*self.vocab,
+            &words,
+            self.supervised_config.word_ngrams as usize,
+        ));
+        for t in words {
+            if let Some(mut idx) = self.vocab.idx(t) {
+                idx.drop_subwords(&mut self.rng, self.common_config.subword_dropout);
+                bag.extend(&idx);
+            }
+        }
+
+        // Sequences without labels or known inputs are not examples.
+        if labels.is_empty() || bag.is_empty() {
+            None.into_iter()
+        } else {
+            Some((bag, labels)).into_iter()
+        }
+    }
+}
+
+impl<R, V> NegativeSamples for SupervisedTrainer<R, V>
+where
+    R: Rng,
+{
+    fn negative_sample(&mut self, output: usize) -> usize {
+        loop {
+            let negative = self.range_gen.next().unwrap();
+            if negative != output {
+                return negative;
+            }
+        }
+    }
+
+    fn noise_prob(&self, output: usize) -> f32 {
+        self.range_gen.prob(output) as f32
+    }
+}
+
+impl<R, V> Reseed for SupervisedTrainer<R, V>
+where
+    R: Reseed + RngCore,
+{
+    fn reseed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+        self.range_gen.reseed(self.rng.next_u64());
+    }
+}
+
+impl<R, V> Trainer for SupervisedTrainer<R, V>
+where
+    R: Rng + Clone,
+    V: Vocab,
+    V::Config: Serialize,
+{
+    type InputVocab = V;
+    type Metadata = SupervisedMetadata<V::Config>;
+
+    fn input_vocab(&self) -> &V {
+        &self.vocab
+    }
+
+    fn try_into_input_vocab(self) -> Result<V> {
+        match Arc::try_unwrap(self.vocab) {
+            Ok(vocab) => Ok(vocab),
+            Err(_) => bail!("Cannot unwrap input vocab."),
+        }
+    }
+
+    fn n_input_types(&self) -> usize {
+        self.input_vocab().n_input_types()
+    }
+
+    fn n_output_types(&self) -> usize {
+        self.labels.len()
+    }
+
+    fn config(&self) -> &CommonConfig {
+        &self.common_config
+    }
+
+    fn to_metadata(&self) -> SupervisedMetadata<V::Config> {
+        SupervisedMetadata {
+            common_config: self.common_config,
+            supervised_config: self.supervised_config,
+            vocab_config: self.vocab.config(),
+        }
+    }
+}
+
+impl<R, V> TrainModel<SupervisedTrainer<R, V>>
+where
+    R: Rng + Clone,
+    V: Vocab,
+    V::Config: Serialize,
+{
+    /// Write the classifier in finalfusion format.
+    ///
+    /// The classifier consists of the output embeddings of the labels.
+    /// The vocabulary of the written embeddings consists of the labels,
+    /// including `LABEL_PREFIX`. The score of a label is the inner product
+    /// of its embedding and the averaged input embedding, so the norms
+    /// must be used to restore the unnormalized label embeddings.
+    pub fn write_classifier<W>(&mut self, write: &mut W) -> Result<()>
+    where
+        W: Seek + Write,
+    {
+        let dims = self.config().dims as usize;
+        let labels = self
+            .trainer()
+            .labels()
+            .types()
+            .iter()
+            .map(|label| label.label().clone())
+            .collect::<Vec<_>>();
+
+        let mut embeds = Array2::zeros((labels.len(), dims));
+        for (idx, mut embed) in embeds.outer_iter_mut().enumerate() {
+            embed.assign(&self.output_embedding(idx));
+        }
+
+        let mut norms = Array1::zeros((embeds.nrows(),));
+        for (mut embed, norm) in embeds.outer_iter_mut().zip(norms.iter_mut()) {
+            *norm = l2_normalize(embed.view_mut());
+        }
+
+        Embeddings::new(
+            None,
+            FiFuSimpleVocab::new(labels),
+            NdArray::new(embeds),
+            NdNorms::new(norms),
+        )
+        .write_embeddings(write)?;
+
+        Ok(())
+    }
+}
+
+/// Metadata for supervised classifiers.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct SupervisedMetadata<V> {
+    common_config: CommonConfig,
+    #[serde(rename = "model_config")]
+    supervised_config: SupervisedConfig,
+    vocab_config: V,
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::SupervisedTrainer;
+    use crate::util::ReseedOnCloneRng;
+    use crate::{
+        CommonConfig, Cutoff, SimpleVocab, SimpleVocabConfig, SupervisedConfig, TrainIterFrom,
+        VocabBuilder,
+    };
+
+    const TEST_COMMON_CONFIG: CommonConfig = CommonConfig::for_test();
+
+    const TEST_VOCAB_CONFIG: SimpleVocabConfig = SimpleVocabConfig {
+        cutoff: Cutoff::MinCount(1),
+        discard_threshold: 1e-4,
+    };
+
+    fn simple_vocab(counts: &[(&str, usize)]) -> SimpleVocab<String> {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(TEST_VOCAB_CONFIG);
+        for &(item, count) in counts {
+            for _ in 0..count {
+                builder.count(item);
+            }
+        }
+        builder.into()
+    }
+
+    fn test_trainer() -> SupervisedTrainer<ReseedOnCloneRng<XorShiftRng>, SimpleVocab<String>> {
+        SupervisedTrainer::new(
+            simple_vocab(&[("a", 3), ("b", 2)]),
+            simple_vocab(&[("__label__x", 2), ("__label__y", 1)]),
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SupervisedConfig { word_ngrams: 1 },
+        )
+    }
+
+    fn tokens(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn supervised_iter_test() {
+        let mut trainer = test_trainer();
+        let instances = trainer
+            .train_iter_from(tokens(&["__label__y", "b", "c", "a", "__label__x"]).as_slice())
+            .map(|(bag, labels)| (bag.into_iter().collect::<Vec<_>>(), labels))
+            .collect::<Vec<_>>();
+        assert_eq!(instances, vec![(vec![1, 0], vec![1, 0])]);
+    }
+
+    #[test]
+    fn supervised_iter_unlabeled_test() {
+        let mut trainer = test_trainer();
+        assert_eq!(
+            trainer
+                .train_iter_from(tokens(&["a", "b"]).as_slice())
+                .count(),
+            0
+        );
+        assert_eq!(
+            trainer
+                .train_iter_from(tokens(&["__label__x", "c"]).as_slice())
+                .count(),
+            0
+        );
+    }
+}
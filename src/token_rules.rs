@@ -0,0 +1,201 @@
+use std::borrow::Cow;
+use std::io::Read;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+/// Token rule as specified in a rules file.
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase", deny_unknown_fields)]
+enum TokenRuleSpec {
+    Drop {
+        pattern: String,
+    },
+    Replace {
+        pattern: String,
+        replacement: String,
+    },
+}
+
+/// Rules file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenRulesSpec {
+    #[serde(default)]
+    rule: Vec<TokenRuleSpec>,
+}
+
+/// Token filtering or rewriting rule.
+#[derive(Clone, Debug)]
+enum TokenRule {
+    /// Drop tokens that match the pattern.
+    Drop(Regex),
+
+    /// Replace all matches of the pattern by the replacement.
+    Replace(Regex, String),
+}
+
+/// Pipeline of token filtering and rewriting rules.
+///
+/// Rules are applied in order. A token that is dropped by a rule is not
+/// processed by subsequent rules. Rewriting rules use the replacement
+/// syntax of the `regex` crate, e.g. `$1` refers to the first capture
+/// group. Tokens that are rewritten to the empty string are dropped.
+///
+/// Rules are read from a TOML file with a `rule` table per rule:
+///
+/// ```toml
+/// [[rule]]
+/// action = "drop"
+/// pattern = "^</?[a-z]+>$"
+///
+/// [[rule]]
+/// action = "replace"
+/// pattern = "^@\\w+$"
+/// replacement = "@user"
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TokenRules {
+    rules: Vec<TokenRule>,
+}
+
+impl TokenRules {
+    /// Read rules from a TOML file.
+    pub fn read<R>(mut read: R) -> Result<Self>
+    where
+        R: Read,
+    {
+        let mut data = String::new();
+        read.read_to_string(&mut data)
+            .context("Cannot read token rules")?;
+        let spec: TokenRulesSpec = toml::from_str(&data).context("Cannot parse token rules")?;
+
+        let mut rules = Vec::with_capacity(spec.rule.len());
+        for rule in spec.rule {
+            rules.push(match rule {
+                TokenRuleSpec::Drop { pattern } => TokenRule::Drop(compile(&pattern)?),
+                TokenRuleSpec::Replace {
+                    pattern,
+                    replacement,
+                } => TokenRule::Replace(compile(&pattern)?, replacement),
+            });
+        }
+
+        Ok(TokenRules { rules })
+    }
+
+    /// Apply the rules to a token.
+    ///
+    /// Returns `None` if the token is dropped.
+    pub fn apply<'a>(&self, token: &'a str) -> Option<Cow<'a, str>> {
+        let mut token = Cow::Borrowed(token);
+
+        for rule in &self.rules {
+            match rule {
+                TokenRule::Drop(pattern) => {
+                    if pattern.is_match(&token) {
+                        return None;
+                    }
+                }
+                TokenRule::Replace(pattern, replacement) => {
+                    // The token only needs to be updated if there was a match.
+                    let replaced = match pattern.replace_all(&token, replacement.as_str()) {
+                        Cow::Owned(replaced) => Some(replaced),
+                        Cow::Borrowed(_) => None,
+                    };
+
+                    if let Some(replaced) = replaced {
+                        token = Cow::Owned(replaced);
+                    }
+                }
+            }
+        }
+
+        if token.is_empty() {
+            None
+        } else {
+            Some(token)
+        }
+    }
+
+    /// Returns `true` if there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Get the number of rules.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).with_context(|| format!("Invalid token rule pattern: {}", pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenRules;
+
+    static RULES: &str = r#"
+[[rule]]
+action = "drop"
+pattern = "^</?[a-z]+>$"
+
+[[rule]]
+action = "replace"
+pattern = "^@\\w+$"
+replacement = "@user"
+
+[[rule]]
+action = "replace"
+pattern = "^(\\w+)'s$"
+replacement = "$1"
+
+[[rule]]
+action = "drop"
+pattern = "^@user$"
+"#;
+
+    #[test]
+    fn applies_rules_in_order() {
+        let rules = TokenRules::read(RULES.as_bytes()).unwrap();
+        assert_eq!(rules.len(), 4);
+
+        assert_eq!(rules.apply("<br>"), None);
+        assert_eq!(rules.apply("house").unwrap(), "house");
+        assert_eq!(rules.apply("John's").unwrap(), "John");
+
+        // Rewritten tokens are processed by subsequent rules.
+        assert_eq!(rules.apply("@danieldk"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        assert!(TokenRules::read(
+            r#"[[rule]]
+action = "drop"
+pattern = "("
+"#
+            .as_bytes()
+        )
+        .is_err());
+
+        assert!(TokenRules::read(
+            r#"[[rule]]
+action = "replace"
+pattern = "a"
+"#
+            .as_bytes()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn empty_rules_keep_tokens() {
+        let rules = TokenRules::read("".as_bytes()).unwrap();
+        assert!(rules.is_empty());
+        assert_eq!(rules.apply("house").unwrap(), "house");
+    }
+}
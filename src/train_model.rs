@@ -1,27 +1,72 @@
-use std::io::{Seek, Write};
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+use std::mem::size_of;
+use std::ops::Range;
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use finalfusion::chunks::io::{ChunkIdentifier, Header, WriteChunk};
 use finalfusion::compat::fasttext::WriteFastText;
 use finalfusion::compat::text::{WriteText, WriteTextDims};
 use finalfusion::compat::word2vec::WriteWord2Vec;
+use finalfusion::embeddings::Quantize;
 use finalfusion::io::WriteEmbeddings;
 use finalfusion::metadata::Metadata;
 use finalfusion::norms::NdNorms;
 use finalfusion::prelude::{Embeddings, VocabWrap};
-use finalfusion::storage::NdArray;
-use ndarray::{Array1, Array2, ArrayView1, ArrayView2, ArrayViewMut1, Axis};
+use finalfusion::storage::{NdArray, Storage};
+use finalfusion::subword::{Indexer, NGrams, StrWithCharLen};
+use finalfusion::vocab::{
+    SimpleVocab as FiFuSimpleVocab, SubwordVocab as FiFuSubwordVocab, Vocab as FiFuVocab,
+};
+use ndarray::{s, Array1, Array2, ArrayView1, ArrayView2, ArrayViewMut1, CowArray, Ix1};
 use ndarray_rand::rand_distr::Uniform;
 use ndarray_rand::RandomExt;
+use rand_xorshift::XorShiftRng;
+use reductive::pq::Pq;
 use serde::Serialize;
 use toml::Value;
 
-use crate::hogwild::HogwildArray2;
-use crate::idx::WordIdx;
-use crate::io::{EmbeddingFormat, TrainInfo};
-use crate::util::VersionInfo;
+use crate::config::RngStream;
+use crate::floret::FloretTable;
+use crate::gensim::write_word2vec_gensim;
+use crate::hnsw::{HnswIndex, HNSW_DEFAULT_EF_CONSTRUCTION, HNSW_DEFAULT_M};
+use crate::io::{write_progress, EmbeddingFormat, TrainInfo};
+#[cfg(feature = "magnitude-output")]
+use crate::magnitude::write_magnitude;
+use crate::matrix::EmbeddingMatrix;
+use crate::npz::{write_npy, write_npz};
+use crate::numa::NumaTopology;
+use crate::sql::write_pgvector_copy;
+#[cfg(feature = "sqlite-output")]
+use crate::sql::write_sqlite;
+use crate::util::{HardwareInfo, VersionInfo};
 use crate::vec_simd::{l2_normalize, scale, scaled_add};
-use crate::{CommonConfig, Vocab, WriteModelBinary};
+use crate::{
+    CommonConfig, Precision, Vocab, WriteModelBinary, WriteModelFastText, WriteModelFloret,
+};
+
+/// Magic number of fastText model files.
+const FASTTEXT_MAGIC: i32 = 793_712_314;
+
+/// Version of the fastText model format.
+const FASTTEXT_VERSION: i32 = 12;
+
+/// fastText identifiers of the CBOW and skip-gram models.
+const FASTTEXT_MODEL_CBOW: i32 = 1;
+const FASTTEXT_MODEL_SKIPGRAM: i32 = 2;
+
+/// fastText identifier of the negative sampling loss.
+const FASTTEXT_LOSS_NS: i32 = 2;
+
+/// fastText's default learning rate update rate.
+const FASTTEXT_LR_UPDATE_RATE: i32 = 100;
+
+/// fastText entry type of words in the dictionary.
+const FASTTEXT_ENTRY_WORD: u8 = 0;
+
+/// finalfusion type identifier of single-precision floats.
+const FINALFUSION_F32_TYPE_ID: u32 = 10;
 
 /// Training model.
 ///
@@ -34,14 +79,20 @@ use crate::{CommonConfig, Vocab, WriteModelBinary};
 /// provides the logic to transform some input to an iterator of training
 /// examples.
 ///
-/// `TrainModel` stores the matrices as `HogwildArray`s to share parameters
-/// between clones of the same model. The trainer is also shared between
-/// clones due to memory considerations.
+/// `TrainModel` stores the matrices as `EmbeddingMatrix`s, which use
+/// `HogwildArray`s to share parameters between clones of the same model.
+/// The trainer is also shared between clones due to memory considerations.
+/// The matrices can be stored in half precision to reduce memory use.
+///
+/// During quantization-aware training, input embeddings are quantized
+/// before they are used in predictions. The gradients are applied to the
+/// unquantized embeddings (straight-through estimation).
 #[derive(Clone)]
 pub struct TrainModel<T> {
     trainer: T,
-    input: HogwildArray2<f32>,
-    output: HogwildArray2<f32>,
+    input: EmbeddingMatrix,
+    output: EmbeddingMatrix,
+    quantize_inputs: bool,
 }
 
 impl<T> From<T> for TrainModel<T>
@@ -51,30 +102,50 @@ where
     /// Construct a model from a Trainer.
     ///
     /// This randomly initializes the input and output matrices using a
-    /// uniform distribution in the range [-1/dims, 1/dims).
+    /// uniform distribution in the range [-1/dims, 1/dims). The
+    /// initialization is deterministic if a seed is configured.
+    ///
+    /// The number of rows of the input matrix is the number of possible
+    /// inputs for the model, typically the vocabulary size plus the number
+    /// of buckets for subword units. The number of rows of the output
+    /// matrix is the number of possible outputs for the model.
+    ///
+    /// If tied embeddings are configured, the output matrix is the input
+    /// matrix, so output *i* is represented by the embedding of input *i*.
     ///
-    /// The number of rows of the input matrix is the vocabulary size
-    /// plus the number of buckets for subword units. The number of rows
-    /// of the output matrix is the number of possible outputs for the model.
+    /// The matrices are stored in the precision of the model
+    /// configuration.
     fn from(trainer: T) -> TrainModel<T> {
         let config = *trainer.config();
         let init_bound = 1.0 / config.dims as f32;
         let distribution = Uniform::new_inclusive(-init_bound, init_bound);
+        let mut rng: XorShiftRng = config.rng(RngStream::Init);
 
-        let input = Array2::random(
-            (trainer.input_vocab().n_input_types(), config.dims as usize),
-            distribution,
-        )
-        .into();
-        let output = Array2::random(
-            (trainer.n_output_types(), config.dims as usize),
-            distribution,
-        )
-        .into();
+        let input = EmbeddingMatrix::new(
+            Array2::random_using(
+                (trainer.n_input_types(), config.dims as usize),
+                distribution,
+                &mut rng,
+            ),
+            config.precision,
+        );
+        let output = if config.tied {
+            input.clone()
+        } else {
+            EmbeddingMatrix::new(
+                Array2::random_using(
+                    (trainer.n_output_types(), config.dims as usize),
+                    distribution,
+                    &mut rng,
+                ),
+                config.precision,
+            )
+        };
         TrainModel {
             trainer,
             input,
             output,
+            quantize_inputs: false,
         }
     }
 }
@@ -83,10 +154,41 @@ impl<T> TrainModel<T>
 where
     T: Trainer,
 {
+    /// Construct a model from a trainer and its input matrix.
+    ///
+    /// This is used by models that are not trained with gradient descent.
+    /// The output matrix of such models is empty.
+    pub(crate) fn from_input_matrix(trainer: T, input: Array2<f32>) -> Self {
+        assert_eq!(
+            input.nrows(),
+            trainer.n_input_types(),
+            "Number of input embeddings and input types differ"
+        );
+        let output = Array2::zeros((0, input.ncols())).into();
+
+        TrainModel {
+            trainer,
+            input: input.into(),
+            output,
+            quantize_inputs: false,
+        }
+    }
+
     /// Get the model configuration.
     pub fn config(&self) -> &CommonConfig {
         &self.trainer.config()
     }
+
+    /// Take a snapshot of the model.
+    ///
+    /// The snapshot has a copy of the input matrix, so it can be written
+    /// while training continues. The output matrix is not copied.
+    pub fn snapshot(&self) -> Self
+    where
+        T: Clone,
+    {
+        Self::from_input_matrix(self.trainer.clone(), self.input.to_array())
+    }
 }
 
 impl<V, T> TrainModel<T>
@@ -100,42 +202,125 @@ where
     }
 }
 
+impl<V, T> TrainModel<T>
+where
+    T: Trainer<InputVocab = V>,
+    V: Vocab<VocabType = String>,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    /// Get the embedding of a word.
+    ///
+    /// The embedding is the mean of the input embeddings of the word and
+    /// its subword units, as in the written model, but it is not
+    /// normalized. Returns `None` if the word has no embedding.
+    pub fn word_embedding(&self, word: &str) -> Option<Array1<f32>> {
+        let idx = self.input_vocab().idx(word)?;
+        if (&idx).into_iter().next().is_none() {
+            return None;
+        }
+
+        Some(self.mean_input_embedding(&idx))
+    }
+}
+
 impl<T> TrainModel<T> {
     /// Get this model's trainer mutably.
     pub fn trainer(&mut self) -> &mut T {
         &mut self.trainer
     }
 
+    /// Interleave the input and output matrices over the NUMA nodes of
+    /// `topology`.
+    ///
+    /// Hogwild threads on all nodes update the matrices. Spreading their
+    /// pages over the nodes balances the memory traffic, rather than
+    /// directing all traffic to the node that allocated the matrices.
+    pub fn interleave(&mut self, topology: &NumaTopology) -> Result<()> {
+        self.input.interleave(topology)?;
+        if self.config().tied {
+            // Interleaving copies the input matrix, share the copy.
+            self.output = self.input.clone();
+            Ok(())
+        } else {
+            self.output.interleave(topology)
+        }
+    }
+
+    /// Copy the input and output matrices in single precision.
+    pub(crate) fn to_arrays(&self) -> (Array2<f32>, Array2<f32>) {
+        (self.input.to_array(), self.output.to_array())
+    }
+
+    /// Replace the embeddings of the input and output matrices.
+    ///
+    /// The embeddings are converted to the precision of the model. Clones
+    /// of the model share the new embeddings.
+    pub(crate) fn assign_arrays(
+        &mut self,
+        input: ArrayView2<f32>,
+        output: ArrayView2<f32>,
+    ) -> Result<()> {
+        self.input.assign(input)?;
+        self.output.assign(output)
+    }
+
+    /// Enable or disable simulated quantization of input embeddings.
+    pub(crate) fn set_quantize_inputs(&mut self, quantize_inputs: bool) {
+        self.quantize_inputs = quantize_inputs;
+    }
+
     /// Get the mean input embedding of the given indices.
+    ///
+    /// If simulated quantization is enabled, the embeddings are quantized
+    /// before computing the mean.
     pub(crate) fn mean_input_embedding<'a, I>(&self, idx: &'a I) -> Array1<f32>
     where
-        I: WordIdx,
         &'a I: IntoIterator<Item = u64>,
     {
-        if idx.len() == 1 {
-            self.input
-                .view()
-                .row(idx.into_iter().next().unwrap() as usize)
-                .to_owned()
-        } else {
-            Self::mean_embedding(self.input.view(), idx)
+        let mut indices = idx.into_iter();
+        if let (Some(single), None) = (indices.next(), indices.next()) {
+            return self.quantized_input_embedding(single as usize).into_owned();
         }
+
+        let mut embed = Array1::zeros((self.input.ncols(),));
+        let mut len = 0;
+        for input_idx in idx {
+            scaled_add(
+                embed.view_mut(),
+                self.quantized_input_embedding(input_idx as usize).view(),
+                1.0,
+            );
+            len += 1;
+        }
+
+        scale(embed.view_mut(), 1.0 / len as f32);
+
+        embed
     }
 
-    /// Get the mean input embedding of the given indices.
-    fn mean_embedding<'a, I>(embeds: ArrayView2<f32>, indices: &'a I) -> Array1<f32>
+    /// Get the input embedding with the given index, quantized if
+    /// simulated quantization is enabled.
+    fn quantized_input_embedding(&self, idx: usize) -> CowArray<f32, Ix1> {
+        let embed = self.input.row(idx);
+        if !self.quantize_inputs {
+            return embed;
+        }
+
+        let mut quantized = embed.into_owned();
+        fake_quantize(quantized.view_mut());
+        quantized.into()
+    }
+
+    /// Get the mean embedding of the given indices.
+    fn mean_embedding<'a, I>(embeds: &EmbeddingMatrix, indices: &'a I) -> Array1<f32>
     where
-        I: WordIdx,
         &'a I: IntoIterator<Item = u64>,
     {
         let mut embed = Array1::zeros((embeds.ncols(),));
-        let len = indices.len();
+        let mut len = 0;
         for idx in indices {
-            scaled_add(
-                embed.view_mut(),
-                embeds.index_axis(Axis(0), idx as usize),
-                1.0,
-            );
+            scaled_add(embed.view_mut(), embeds.row(idx as usize).view(), 1.0);
+            len += 1;
         }
 
         scale(embed.view_mut(), 1.0 / len as f32);
@@ -146,35 +331,120 @@ impl<T> TrainModel<T> {
     /// Get the input embedding with the given index.
     #[allow(dead_code)]
     #[inline]
-    pub(crate) fn input_embedding(&self, idx: usize) -> ArrayView1<f32> {
-        self.input.subview(Axis(0), idx)
+    pub(crate) fn input_embedding(&self, idx: usize) -> CowArray<f32, Ix1> {
+        self.input.row(idx)
     }
 
-    /// Get the input embedding with the given index mutably.
+    /// Update the input embedding with the given index.
+    ///
+    /// The embedding is passed to `update` in single precision.
     #[inline]
-    pub(crate) fn input_embedding_mut(&mut self, idx: usize) -> ArrayViewMut1<f32> {
-        self.input.subview_mut(Axis(0), idx)
-    }
-
-    pub(crate) fn into_parts(self) -> Result<(T, Array2<f32>)> {
-        let input = match Arc::try_unwrap(self.input.into_inner()) {
-            Ok(input) => input.into_inner(),
-            Err(_) => bail!("Cannot unwrap input matrix."),
-        };
-
-        Ok((self.trainer, input))
+    pub(crate) fn update_input_embedding<F>(&mut self, idx: usize, update: F)
+    where
+        F: FnOnce(ArrayViewMut1<f32>),
+    {
+        self.input.update_row(idx, update)
     }
 
     /// Get the output embedding with the given index.
     #[inline]
-    pub(crate) fn output_embedding(&self, idx: usize) -> ArrayView1<f32> {
-        self.output.subview(Axis(0), idx)
+    pub(crate) fn output_embedding(&self, idx: usize) -> CowArray<f32, Ix1> {
+        self.output.row(idx)
     }
 
-    /// Get the output embedding with the given index mutably.
+    /// Update the output embedding with the given index.
+    ///
+    /// The embedding is passed to `update` in single precision.
     #[inline]
-    pub(crate) fn output_embedding_mut(&mut self, idx: usize) -> ArrayViewMut1<f32> {
-        self.output.subview_mut(Axis(0), idx)
+    pub(crate) fn update_output_embedding<F>(&mut self, idx: usize, update: F)
+    where
+        F: FnOnce(ArrayViewMut1<f32>),
+    {
+        self.output.update_row(idx, update)
+    }
+}
+
+impl<T> TrainModel<T>
+where
+    T: OutputLabels + Trainer,
+{
+    /// Write the output embeddings in finalfusion format.
+    ///
+    /// The output embeddings are sometimes called context embeddings. The
+    /// vocabulary of the written embeddings consists of the output labels
+    /// of the trainer.
+    pub fn write_output_embeddings<W>(&self, write: &mut W) -> Result<()>
+    where
+        W: Seek + Write,
+    {
+        let n_outputs = self.trainer.n_output_types();
+        let dims = self.trainer.config().dims as usize;
+
+        let mut embeds = Array2::zeros((n_outputs, dims));
+        let mut norms = Array1::zeros((n_outputs,));
+        for (idx, (mut embed, norm)) in embeds.outer_iter_mut().zip(norms.iter_mut()).enumerate() {
+            embed.assign(&self.output_embedding(idx));
+            *norm = l2_normalize(embed.view_mut());
+        }
+
+        let labels = (0..n_outputs)
+            .map(|output| self.trainer.output_label(output))
+            .collect::<Vec<_>>();
+        Embeddings::new(
+            None,
+            FiFuSimpleVocab::new(labels),
+            NdArray::new(embeds),
+            NdNorms::new(norms),
+        )
+        .write_embeddings(write)?;
+
+        Ok(())
+    }
+}
+
+impl<T, V, M> TrainModel<T>
+where
+    T: Trainer<InputVocab = V, Metadata = M>,
+    V: Vocab,
+    M: Serialize,
+{
+    /// Get the metadata of the model.
+    ///
+    /// The metadata consists of the configuration of the trainer, the
+    /// training information, information about the build and hardware,
+    /// and the vocabulary sizes.
+    fn metadata(&self, train_info: TrainInfo) -> Result<Value> {
+        let trainer = &self.trainer;
+        let mut metadata = Value::try_from(trainer.to_metadata())?;
+        let metadata_table = metadata
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("Metadata has to be 'Table'."))?;
+        metadata_table.insert(
+            "version_info".to_string(),
+            Value::try_from(VersionInfo::new())?,
+        );
+        metadata_table.insert(
+            "hardware_info".to_string(),
+            Value::try_from(HardwareInfo::new())?,
+        );
+        metadata_table.insert("training_info".to_string(), Value::try_from(train_info)?);
+        metadata_table.insert(
+            "vocab_info".to_string(),
+            Value::try_from(VocabInfo {
+                n_tokens: trainer.input_vocab().n_types(),
+                n_words: trainer.input_vocab().len(),
+                n_input_types: trainer.n_input_types(),
+                n_output_types: trainer.n_output_types(),
+            })?,
+        );
+        if let Some(statistics) = trainer.input_vocab().bucket_statistics() {
+            metadata_table.insert(
+                "bucket_statistics".to_string(),
+                Value::try_from(statistics)?,
+            );
+        }
+
+        Ok(metadata)
     }
 }
 
@@ -187,63 +457,752 @@ where
     for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
     M: Serialize,
 {
-    fn write_model_binary(
-        self,
-        write: &mut W,
-        mut train_info: TrainInfo,
-        format: EmbeddingFormat,
-    ) -> Result<()> {
-        let (trainer, mut input_matrix) = self.into_parts()?;
-        let mut metadata = Value::try_from(trainer.to_metadata())?;
-        let build_info = Value::try_from(VersionInfo::new())?;
-        let metadata_table = metadata
-            .as_table_mut()
-            .ok_or_else(|| anyhow!("Metadata has to be 'Table'."))?;
-        metadata_table.insert("version_info".to_string(), build_info);
-        train_info.set_end();
-        let train_info = Value::try_from(train_info)?;
-        metadata_table.insert("training_info".to_string(), train_info);
+    fn write_model_binary(
+        self,
+        write: &mut W,
+        train_info: TrainInfo,
+        format: EmbeddingFormat,
+    ) -> Result<()> {
+        let progress = write_progress();
+        let result = self.write_model(&mut progress.wrap_write(write), train_info, format);
+        progress.finish_at_current_pos();
+        result
+    }
+}
+
+impl<T, V, M> TrainModel<T>
+where
+    T: Trainer<InputVocab = V, Metadata = M>,
+    V: Vocab + Into<VocabWrap>,
+    V::VocabType: ToString,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+    M: Serialize,
+{
+    /// Write the model in the given format.
+    ///
+    /// The input matrix is not copied in single precision: word
+    /// embeddings are computed before writing, subword embeddings are
+    /// written row by row from the input matrix.
+    fn write_model<W>(
+        self,
+        write: &mut W,
+        mut train_info: TrainInfo,
+        format: EmbeddingFormat,
+    ) -> Result<()>
+    where
+        W: Seek + Write,
+    {
+        let ngram_output = train_info.ngram_output().map(ToOwned::to_owned);
+        let vectors_npy = train_info.vectors_npy().map(ToOwned::to_owned);
+        let ann_index = train_info.ann_index().map(ToOwned::to_owned);
+        let config_output = train_info.config_output().map(ToOwned::to_owned);
+        train_info.set_end();
+        train_info.compute_corpus_sha256()?;
+        let metadata = self.metadata(train_info)?;
+
+        if let Some(config_output) = config_output {
+            let mut writer = BufWriter::new(
+                File::create(config_output)
+                    .context("Cannot open configuration output file for writing.")?,
+            );
+            writer
+                .write_all(toml::to_string_pretty(&metadata)?.as_bytes())
+                .context("Cannot write configuration")?;
+        }
+
+        match format {
+            EmbeddingFormat::Floret => {
+                ensure!(
+                    ngram_output.is_none(),
+                    "Ngram embeddings cannot be written separately with floret files"
+                );
+                return self.write_model_floret(write);
+            }
+            // Write skip-gram and CBOW models as complete fastText models.
+            EmbeddingFormat::FastText if fasttext_model(&metadata).is_some() => {
+                ensure!(
+                    ann_index.is_none(),
+                    "A nearest neighbor index cannot be built for complete fastText models"
+                );
+                return self.write_model_fasttext(write);
+            }
+            _ => (),
+        }
+
+        let TrainModel {
+            trainer,
+            input,
+            output,
+            ..
+        } = self;
+
+        // The output matrix is not written, release it before the word
+        // embeddings are computed.
+        drop(output);
+
+        // Compute word embeddings. Only the embeddings of the input
+        // vocabulary are stored, trainers can have additional inputs,
+        // such as document embeddings.
+        let n_words = trainer.input_vocab().len();
+        let n_vocab_inputs = trainer.input_vocab().n_input_types();
+        let mut words = Array2::zeros((n_words, input.ncols()));
+        let mut norms = vec![0f32; n_words];
+        for ((mut embed, norm), word) in words
+            .outer_iter_mut()
+            .zip(norms.iter_mut())
+            .zip(trainer.input_vocab().types())
+        {
+            let indices = trainer.input_vocab().idx(word.label()).unwrap();
+            embed.assign(&Self::mean_embedding(&input, &indices));
+            *norm = l2_normalize(embed);
+        }
+        let mut storage = InputEmbeddings::new(words, &input, n_words..n_vocab_inputs);
+
+        // Only write the most frequent words. Vocabularies are sorted by
+        // frequency.
+        let n_written = trainer
+            .config()
+            .write_top_k
+            .map(|k| k.min(n_words))
+            .unwrap_or(n_words);
+
+        let output_precision = trainer.config().output_precision;
+        let mut vocab: VocabWrap = trainer.try_into_input_vocab()?.into();
+        if let Some(ngram_output) = ngram_output {
+            let mut writer = BufWriter::new(
+                File::create(ngram_output)
+                    .context("Cannot open ngram embeddings output file for writing.")?,
+            );
+            write_ngram_embeddings(&mut writer, &vocab, &storage)
+                .context("Cannot write ngram embeddings")?;
+        }
+
+        if let Some(ann_index) = ann_index {
+            let index = HnswIndex::build(
+                storage.words.slice(s![..n_written, ..]),
+                HNSW_DEFAULT_M,
+                HNSW_DEFAULT_EF_CONSTRUCTION,
+            );
+            let mut writer = BufWriter::new(
+                File::create(ann_index)
+                    .context("Cannot open nearest neighbor index output file for writing.")?,
+            );
+            index
+                .write(&mut writer)
+                .context("Cannot write nearest neighbor index")?;
+        }
+
+        #[cfg(feature = "magnitude-output")]
+        {
+            if format == EmbeddingFormat::Magnitude {
+                return write_magnitude(
+                    write,
+                    &vocab.words()[..n_written],
+                    storage.words.slice(s![..n_written, ..]),
+                    &norms[..n_written],
+                );
+            }
+        }
+
+        // Formats that store the unnormalized word embeddings only.
+        let unnormalized = match format {
+            EmbeddingFormat::Gensim | EmbeddingFormat::Npz | EmbeddingFormat::PgVector => true,
+            #[cfg(feature = "sqlite-output")]
+            EmbeddingFormat::Sqlite => true,
+            _ => false,
+        };
+        if unnormalized {
+            let mut embeds = storage.words.slice_mut(s![..n_written, ..]);
+            for (embed, &norm) in embeds.outer_iter_mut().zip(&norms) {
+                scale(embed, norm);
+            }
+            let words = &vocab.words()[..n_written];
+
+            match format {
+                EmbeddingFormat::Npz => {
+                    return write_npz(write, words, embeds.view(), output_precision)
+                }
+                EmbeddingFormat::PgVector => {
+                    return write_pgvector_copy(write, words, embeds.view())
+                }
+                #[cfg(feature = "sqlite-output")]
+                EmbeddingFormat::Sqlite => return write_sqlite(write, words, embeds.view()),
+                _ => (),
+            }
+
+            if let Some(vectors_npy) = vectors_npy {
+                let mut writer = BufWriter::new(
+                    File::create(vectors_npy)
+                        .context("Cannot open vectors output file for writing.")?,
+                );
+                write_npy(&mut writer, embeds.view(), Precision::F32)
+                    .context("Cannot write vectors")?;
+            }
+            return write_word2vec_gensim(write, words, embeds.view());
+        }
+
+        if n_written < n_words {
+            vocab = truncate_vocab(vocab, n_written);
+            storage.truncate_words(n_written);
+            norms.truncate(n_written);
+        }
+        let metadata = Metadata::new(metadata);
+        let norms = NdNorms::new(Array1::from(norms));
+
+        use self::EmbeddingFormat::*;
+        match format {
+            FastText => {
+                let vocab = match vocab {
+                    VocabWrap::FastTextSubwordVocab(vocab) => vocab,
+                    _ => bail!("Only fastText vocabularies can be written to fastText files"),
+                };
+                Embeddings::new(Some(metadata), vocab, storage, norms).write_fasttext(write)?
+            }
+            FinalFusion => write_finalfusion(write, &metadata, &vocab, &storage, &norms)?,
+            FinalFusionQuantized(quantizer) => {
+                // Product quantization is trained on the complete matrix,
+                // so the matrix is copied in single precision.
+                let storage = NdArray::new(storage.to_array());
+                let n_subquantizers = quantizer
+                    .n_subquantizers
+                    .unwrap_or(storage.shape().1 as u32 / 2);
+                Embeddings::new(Some(metadata), vocab, storage, norms)
+                    .quantize::<Pq<f32>>(
+                        n_subquantizers as usize,
+                        quantizer.n_subquantizer_bits,
+                        quantizer.n_iterations as usize,
+                        quantizer.n_attempts as usize,
+                        true,
+                    )?
+                    .write_embeddings(write)?
+            }
+            Word2Vec => Embeddings::new(Some(metadata), vocab, storage, norms)
+                .write_word2vec_binary(write, true)?,
+            Text => {
+                Embeddings::new(Some(metadata), vocab, storage, norms).write_text(write, true)?
+            }
+            TextDims => Embeddings::new(Some(metadata), vocab, storage, norms)
+                .write_text_dims(write, true)?,
+            Floret | Gensim | Npz | PgVector => unreachable!(),
+            #[cfg(feature = "magnitude-output")]
+            Magnitude => unreachable!(),
+            #[cfg(feature = "sqlite-output")]
+            Sqlite => unreachable!(),
+        };
+
+        Ok(())
+    }
+}
+
+impl<W, T, V, M> WriteModelFastText<W> for TrainModel<T>
+where
+    W: Write,
+    T: Trainer<InputVocab = V, Metadata = M>,
+    V: Vocab,
+    V::VocabType: ToString,
+    M: Serialize,
+{
+    fn write_model_fasttext(self, write: &mut W) -> Result<()> {
+        let metadata = Value::try_from(self.trainer.to_metadata())?;
+        let model = fasttext_model(&metadata).ok_or_else(|| {
+            anyhow!("Only skip-gram and CBOW models can be written to fastText files")
+        })?;
+        let model_config = &metadata["model_config"];
+        let vocab_config = metadata
+            .get("vocab_config")
+            .ok_or_else(|| anyhow!("Model does not have a vocabulary configuration"))?;
+        ensure!(
+            vocab_config
+                .get("indexer")
+                .and_then(|indexer| indexer.get("indexer_type"))
+                .and_then(Value::as_str)
+                == Some("FastText"),
+            "Only fastText vocabularies can be written to fastText files"
+        );
+        ensure!(
+            vocab_config.get("include_word").and_then(Value::as_bool) == Some(true)
+                && vocab_config
+                    .get("boundaries")
+                    .map(|boundaries| {
+                        boundaries.get("bow").and_then(Value::as_str) == Some("<")
+                            && boundaries.get("eow").and_then(Value::as_str) == Some(">")
+                    })
+                    .unwrap_or(false),
+            "fastText files require word embeddings and the word boundaries < and >"
+        );
+        // fastText extracts the n-grams of every word with the same
+        // lengths and from the word itself, and does not use word n-grams
+        // in skip-gram and CBOW models.
+        ensure!(
+            vocab_config
+                .get("script_ngrams")
+                .and_then(Value::as_table)
+                .map(|script_ngrams| script_ngrams.is_empty())
+                .unwrap_or(true),
+            "Per-script n-gram lengths cannot be written to fastText files"
+        );
+        ensure!(
+            vocab_config.get("split_compounds").and_then(Value::as_bool) != Some(true),
+            "Models with split compounds cannot be written to fastText files"
+        );
+        ensure!(
+            model_config.get("word_ngrams").and_then(Value::as_integer) == Some(1),
+            "Models with word n-grams cannot be written to fastText files"
+        );
+
+        let config = *self.trainer.config();
+        let vocab = self.trainer.input_vocab();
+        let n_words = vocab.len();
+        let n_written = config
+            .write_top_k
+            .map(|k| k.min(n_words))
+            .unwrap_or(n_words);
+        let words = vocab
+            .types()
+            .iter()
+            .take(n_written)
+            .map(|word| (word.label().to_string(), word.count()))
+            .collect::<Vec<_>>();
+        let n_tokens = vocab.n_types();
+        let n_vocab_inputs = vocab.n_input_types();
+        let n_buckets = n_vocab_inputs - n_words;
+
+        let int_config = |config: &Value, key: &str| {
+            config
+                .get(key)
+                .and_then(Value::as_integer)
+                .map(|v| v as i32)
+                .ok_or_else(|| anyhow!("Model configuration does not have {}", key))
+        };
+
+        write_i32(write, FASTTEXT_MAGIC)?;
+        write_i32(write, FASTTEXT_VERSION)?;
+
+        // Hyperparameters.
+        write_i32(write, config.dims as i32)?;
+        write_i32(write, int_config(model_config, "context_size")?)?;
+        write_i32(write, config.epochs as i32)?;
+        write_i32(
+            write,
+            words.iter().map(|(_, count)| *count).min().unwrap_or(1) as i32,
+        )?;
+        write_i32(write, config.negative_samples as i32)?;
+        write_i32(write, int_config(model_config, "word_ngrams")?)?;
+        write_i32(write, FASTTEXT_LOSS_NS)?;
+        write_i32(write, model)?;
+        write_i32(write, n_buckets as i32)?;
+        write_i32(write, int_config(vocab_config, "min_n")?)?;
+        write_i32(write, int_config(vocab_config, "max_n")?)?;
+        write_i32(write, FASTTEXT_LR_UPDATE_RATE)?;
+        write.write_all(
+            &vocab_config
+                .get("discard_threshold")
+                .and_then(Value::as_float)
+                .unwrap_or(1e-4)
+                .to_le_bytes(),
+        )?;
+
+        // Dictionary, without labels and pruned n-grams.
+        write_i32(write, words.len() as i32)?;
+        write_i32(write, words.len() as i32)?;
+        write_i32(write, 0)?;
+        write.write_all(&(n_tokens as i64).to_le_bytes())?;
+        write.write_all(&(-1i64).to_le_bytes())?;
+        for (word, count) in &words {
+            write.write_all(word.as_bytes())?;
+            write.write_all(&[0])?;
+            write.write_all(&(*count as i64).to_le_bytes())?;
+            write.write_all(&[FASTTEXT_ENTRY_WORD])?;
+        }
+
+        // Unquantized input and output matrices. The matrices are written
+        // row by row, so that they are not copied in single precision.
+        let dims = self.input.ncols();
+        write.write_all(&[0])?;
+        write_fasttext_matrix(
+            write,
+            n_written + n_buckets,
+            dims,
+            (0..n_written)
+                .chain(n_words..n_vocab_inputs)
+                .map(|idx| self.input.row(idx)),
+        )?;
+
+        // fastText has an output embedding for every word. Words that are
+        // not used as contexts get zero embeddings.
+        let n_outputs = self.output.nrows().min(words.len());
+        let zeros = Array1::zeros((dims,));
+        write.write_all(&[0])?;
+        write_fasttext_matrix(
+            write,
+            words.len(),
+            dims,
+            (0..words.len()).map(|idx| {
+                if idx < n_outputs {
+                    self.output.row(idx)
+                } else {
+                    zeros.view().into()
+                }
+            }),
+        )?;
+
+        Ok(())
+    }
+}
+
+impl<W, T, V, M> WriteModelFloret<W> for TrainModel<T>
+where
+    W: Write,
+    T: Trainer<InputVocab = V, Metadata = M>,
+    V: Vocab,
+    V::VocabType: ToString,
+    M: Serialize,
+{
+    fn write_model_floret(self, write: &mut W) -> Result<()> {
+        let metadata = Value::try_from(self.trainer.to_metadata())?;
+        let vocab_config = metadata
+            .get("vocab_config")
+            .ok_or_else(|| anyhow!("Model does not have a vocabulary configuration"))?;
+        ensure!(
+            matches!(
+                vocab_config
+                    .get("indexer")
+                    .and_then(|indexer| indexer.get("type"))
+                    .and_then(Value::as_str),
+                Some("Buckets") | Some("NGrams")
+            ),
+            "Only models with n-gram subword units can be written to floret files"
+        );
+        let boundary = |key: &str| {
+            vocab_config
+                .get("boundaries")
+                .and_then(|boundaries| boundaries.get(key))
+                .and_then(Value::as_str)
+                .map(ToOwned::to_owned)
+                .ok_or_else(|| anyhow!("floret files require word boundary markers"))
+        };
+        let (bow, eow) = (boundary("bow")?, boundary("eow")?);
+        let ngram_length = |key: &str| {
+            vocab_config
+                .get(key)
+                .and_then(Value::as_integer)
+                .map(|v| v as usize)
+                .ok_or_else(|| anyhow!("Vocabulary configuration does not have {}", key))
+        };
+        let (min_n, max_n) = (ngram_length("min_n")?, ngram_length("max_n")?);
+        let include_word = vocab_config
+            .get("include_word")
+            .and_then(Value::as_bool)
+            .unwrap_or(true);
+
+        let vocab = self.trainer.input_vocab();
+        let n_buckets = vocab.n_input_types() - vocab.len();
+        ensure!(n_buckets > 0, "Model does not have subword embeddings");
+
+        // Add the embeddings of the words and their distinct n-grams to
+        // the buckets that floret hashes them to.
+        let mut table = FloretTable::new(n_buckets, self.input.ncols());
+        let mut seen = HashSet::new();
+        for (idx, word) in vocab.types().iter().enumerate() {
+            let bracketed = format!("{}{}{}", bow, word.label().to_string(), eow);
+            if include_word {
+                table.add(&bracketed, self.input.row(idx).view());
+            }
+
+            for ngram in NGrams::new(&bracketed, min_n, max_n).map(|ngram| ngram.to_string()) {
+                if seen.contains(&ngram) {
+                    continue;
+                }
+
+                if let Some(ngram_idx) = vocab.word_ngram_idx(&ngram) {
+                    table.add(&ngram, self.input.row(ngram_idx as usize).view());
+                }
+                seen.insert(ngram);
+            }
+        }
+
+        table.write(write, min_n, max_n, &bow, &eow)
+    }
+}
+
+/// Sizes of the vocabularies of a model.
+#[derive(Serialize)]
+struct VocabInfo {
+    /// The number of tokens in the training corpus.
+    n_tokens: usize,
+
+    /// The number of words in the vocabulary.
+    n_words: usize,
+
+    /// The number of inputs, including subword units.
+    n_input_types: usize,
+
+    /// The number of outputs.
+    n_output_types: usize,
+}
+
+/// Get the label of an output word in a position of the context window.
+///
+/// Positions are ordered from the leftmost to the rightmost position in a
+/// window of `context_size` words on each side, skipping the focus word.
+/// The label is the word, followed by its offset from the focus word, e.g.
+/// `word@-1`.
+pub(crate) fn positional_label(word: &str, position: usize, context_size: usize) -> String {
+    let offset = if position < context_size {
+        position as isize - context_size as isize
+    } else {
+        (position - context_size) as isize + 1
+    };
+    format!("{}@{:+}", word, offset)
+}
+
+/// Input embeddings that are written to a file.
+///
+/// The word embeddings are the normalized means of the embeddings of the
+/// words and their subword units, they are computed before writing. The
+/// subword embeddings are read from the input matrix of the model, so
+/// that the input matrix is not copied in single precision.
+struct InputEmbeddings<'a> {
+    words: Array2<f32>,
+    input: &'a EmbeddingMatrix,
+    subwords: Range<usize>,
+}
+
+impl<'a> InputEmbeddings<'a> {
+    /// Construct input embeddings from word embeddings and the rows of
+    /// the input matrix with subword embeddings.
+    fn new(words: Array2<f32>, input: &'a EmbeddingMatrix, subwords: Range<usize>) -> Self {
+        InputEmbeddings {
+            words,
+            input,
+            subwords,
+        }
+    }
+
+    /// Copy the embeddings in single precision.
+    fn to_array(&self) -> Array2<f32> {
+        let mut array = Array2::zeros(self.shape());
+        for (idx, mut embed) in array.outer_iter_mut().enumerate() {
+            embed.assign(&self.embedding(idx));
+        }
+        array
+    }
+
+    /// Retain only the first `n_words` word embeddings.
+    ///
+    /// The subword embeddings follow the retained word embeddings.
+    fn truncate_words(&mut self, n_words: usize) {
+        self.words.slice_collapse(s![..n_words, ..]);
+    }
+
+    /// Write the embeddings as a finalfusion array chunk.
+    ///
+    /// The chunk is identical to the chunk of an `NdArray` storage, but
+    /// the embeddings are written row by row.
+    fn write_ndarray_chunk<W>(&self, write: &mut W) -> Result<()>
+    where
+        W: Seek + Write,
+    {
+        let (n_rows, n_cols) = self.shape();
+        let float_size = size_of::<f32>() as u64;
+
+        write.write_all(&(ChunkIdentifier::NdArray as u32).to_le_bytes())?;
+
+        // The matrix is aligned to the float size. finalfusion computes
+        // the padding from the position after the chunk identifier.
+        let n_padding = float_size - (write.stream_position()? % float_size);
+
+        // Chunk size: rows (u64), columns (u32), type id (u32),
+        // padding, matrix.
+        let chunk_len = (size_of::<u64>() + 2 * size_of::<u32>()) as u64
+            + n_padding
+            + (n_rows * n_cols) as u64 * float_size;
+        write.write_all(&chunk_len.to_le_bytes())?;
+        write.write_all(&(n_rows as u64).to_le_bytes())?;
+        write.write_all(&(n_cols as u32).to_le_bytes())?;
+        write.write_all(&FINALFUSION_F32_TYPE_ID.to_le_bytes())?;
+        write.write_all(&vec![0; n_padding as usize])?;
+
+        let mut row = Vec::with_capacity(n_cols * size_of::<f32>());
+        for idx in 0..n_rows {
+            row.clear();
+            for &v in self.embedding(idx).iter() {
+                row.extend_from_slice(&v.to_le_bytes());
+            }
+            write.write_all(&row)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Storage for InputEmbeddings<'a> {
+    fn embedding(&self, idx: usize) -> CowArray<f32, Ix1> {
+        if idx < self.words.nrows() {
+            self.words.row(idx).into()
+        } else {
+            self.input
+                .row(self.subwords.start + idx - self.words.nrows())
+        }
+    }
+
+    fn shape(&self) -> (usize, usize) {
+        (self.words.nrows() + self.subwords.len(), self.words.ncols())
+    }
+}
+
+/// Write embeddings in finalfusion format.
+///
+/// This writes the same chunks as `WriteEmbeddings`, except that the
+/// embedding matrix is written by `InputEmbeddings`.
+fn write_finalfusion<W>(
+    write: &mut W,
+    metadata: &Metadata,
+    vocab: &VocabWrap,
+    storage: &InputEmbeddings,
+    norms: &NdNorms,
+) -> Result<()>
+where
+    W: Seek + Write,
+{
+    Header::new(vec![
+        metadata.chunk_identifier(),
+        vocab.chunk_identifier(),
+        ChunkIdentifier::NdArray,
+        norms.chunk_identifier(),
+    ])
+    .write_chunk(write)?;
+    metadata.write_chunk(write)?;
+    vocab.write_chunk(write)?;
+    storage.write_ndarray_chunk(write)?;
+    norms.write_chunk(write)?;
+
+    Ok(())
+}
+
+/// Write the embeddings of the ngrams of an explicit ngram vocabulary.
+///
+/// The ngram embeddings are written in finalfusion format, with the
+/// ngrams as the vocabulary. `storage` contains the input embeddings of
+/// the model.
+fn write_ngram_embeddings<W, S>(write: &mut W, vocab: &VocabWrap, storage: &S) -> Result<()>
+where
+    W: Seek + Write,
+    S: Storage,
+{
+    let vocab = match vocab {
+        VocabWrap::ExplicitSubwordVocab(vocab) => vocab,
+        _ => bail!("Ngram embeddings can only be written for models with ngram subwords"),
+    };
+
+    let ngrams = vocab.indexer().ngrams().to_vec();
+    let mut embeds = Array2::zeros((ngrams.len(), storage.shape().1));
+    let mut norms = Array1::zeros((ngrams.len(),));
+    for ((ngram, mut embed), norm) in ngrams
+        .iter()
+        .zip(embeds.outer_iter_mut())
+        .zip(norms.iter_mut())
+    {
+        let idx = vocab
+            .indexer()
+            .index_ngram(&StrWithCharLen::new(ngram))
+            .ok_or_else(|| anyhow!("Ngram is not in the vocabulary: {}", ngram))?;
+        embed.assign(&storage.embedding(vocab.words_len() + idx as usize));
+        *norm = l2_normalize(embed.view_mut());
+    }
+
+    Embeddings::new(
+        None,
+        FiFuSimpleVocab::new(ngrams),
+        NdArray::new(embeds),
+        NdNorms::new(norms),
+    )
+    .write_embeddings(write)?;
+
+    Ok(())
+}
+
+/// Restrict a vocabulary to its first `n_words` words.
+///
+/// The subword units of the vocabulary are retained.
+fn truncate_vocab(vocab: VocabWrap, n_words: usize) -> VocabWrap {
+    match vocab {
+        VocabWrap::SimpleVocab(vocab) => FiFuSimpleVocab::new(&vocab.words()[..n_words]).into(),
+        VocabWrap::ExplicitSubwordVocab(vocab) => truncate_subword_vocab(vocab, n_words).into(),
+        VocabWrap::FastTextSubwordVocab(vocab) => truncate_subword_vocab(vocab, n_words).into(),
+        VocabWrap::BucketSubwordVocab(vocab) => truncate_subword_vocab(vocab, n_words).into(),
+    }
+}
+
+fn truncate_subword_vocab<I>(vocab: FiFuSubwordVocab<I>, n_words: usize) -> FiFuSubwordVocab<I>
+where
+    I: Clone + Indexer,
+{
+    FiFuSubwordVocab::new(
+        &vocab.words()[..n_words],
+        vocab.min_n(),
+        vocab.max_n(),
+        vocab.indexer().clone(),
+    )
+}
+
+/// Get the fastText model type of a model, given its metadata.
+///
+/// Returns `None` if the model is not a skip-gram or CBOW model.
+fn fasttext_model(metadata: &Value) -> Option<i32> {
+    match metadata.get("model_config")?.get("model")?.as_str()? {
+        "Cbow" => Some(FASTTEXT_MODEL_CBOW),
+        "SkipGram" => Some(FASTTEXT_MODEL_SKIPGRAM),
+        _ => None,
+    }
+}
+
+fn write_i32<W>(write: &mut W, v: i32) -> Result<()>
+where
+    W: Write,
+{
+    write.write_all(&v.to_le_bytes())?;
+    Ok(())
+}
 
-        // Compute and write word embeddings.
-        let mut norms = vec![0f32; trainer.input_vocab().len()];
-        for (i, (norm, word)) in norms
-            .iter_mut()
-            .zip(trainer.input_vocab().types())
-            .take(trainer.input_vocab().len())
-            .enumerate()
-        {
-            let input = trainer.input_vocab().idx(word.label()).unwrap();
-            let mut embed = Self::mean_embedding(input_matrix.view(), &input);
-            *norm = l2_normalize(embed.view_mut());
-            input_matrix.index_axis_mut(Axis(0), i).assign(&embed);
+/// Write a matrix in fastText's dense matrix format.
+///
+/// The matrix is written from an iterator over its rows, which allows
+/// writing matrices without constructing them in memory.
+fn write_fasttext_matrix<'a, W, I>(
+    write: &mut W,
+    n_rows: usize,
+    n_cols: usize,
+    rows: I,
+) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = CowArray<'a, f32, Ix1>>,
+{
+    write.write_all(&(n_rows as i64).to_le_bytes())?;
+    write.write_all(&(n_cols as i64).to_le_bytes())?;
+    for row in rows {
+        for &v in row.iter() {
+            write.write_all(&v.to_le_bytes())?;
         }
+    }
 
-        let vocab: VocabWrap = trainer.try_into_input_vocab()?.into();
-        let storage = NdArray::new(input_matrix);
-        let norms = NdNorms::new(Array1::from(norms));
-
-        use self::EmbeddingFormat::*;
-        match format {
-            FastText => {
-                let vocab = match vocab {
-                    VocabWrap::FastTextSubwordVocab(vocab) => vocab,
-                    _ => bail!("Only fastText vocabularies can be written to fastText files"),
-                };
-                Embeddings::new(Some(Metadata::new(metadata)), vocab, storage, norms)
-                    .write_fasttext(write)?
-            }
-            FinalFusion => Embeddings::new(Some(Metadata::new(metadata)), vocab, storage, norms)
-                .write_embeddings(write)?,
-            Word2Vec => Embeddings::new(Some(Metadata::new(metadata)), vocab, storage, norms)
-                .write_word2vec_binary(write, true)?,
-            Text => Embeddings::new(Some(Metadata::new(metadata)), vocab, storage, norms)
-                .write_text(write, true)?,
-            TextDims => Embeddings::new(Some(Metadata::new(metadata)), vocab, storage, norms)
-                .write_text_dims(write, true)?,
-        };
+    Ok(())
+}
 
-        Ok(())
+/// Simulate 8-bit quantization of an embedding.
+///
+/// The embedding is quantized symmetrically to the integers in
+/// [-127, 127], using the largest absolute component as the scale. The
+/// quantized embedding is then mapped back to floating point values.
+fn fake_quantize(mut embed: ArrayViewMut1<f32>) {
+    let max_abs = embed.iter().fold(0f32, |max, v| max.max(v.abs()));
+    if max_abs == 0. {
+        return;
     }
+
+    let step = max_abs / 127.;
+    embed.mapv_inplace(|v| (v / step).round() * step);
 }
 
 /// Trainer Trait.
@@ -290,46 +1249,98 @@ where
     fn train_iter_from(&mut self, sequence: &S) -> Self::Iter;
 }
 
+/// Sense selection
+///
+/// This trait defines how the sense of a word is selected, given the
+/// embedding of the context in which the word occurs.
+pub trait SelectSense {
+    /// Select the sense of `word` in a context with embedding `context_embed`.
+    ///
+    /// Returns the input index of the embedding of the selected sense.
+    fn select_sense(&mut self, word: usize, context_embed: ArrayView1<f32>) -> u64;
+}
+
+/// Concatenated inputs
+///
+/// This trait is implemented by trainers whose input embedding is the
+/// concatenation of the embeddings of the input words in their positions.
+pub trait ConcatInputs {
+    /// Get the number of input positions.
+    fn n_positions(&self) -> usize;
+}
+
+/// Output labels
+///
+/// This trait is implemented by trainers whose outputs have labels, so
+/// that the output embeddings can be written.
+pub trait OutputLabels {
+    /// Get the label of `output`.
+    fn output_label(&self, output: usize) -> String;
+}
+
 /// Negative Samples
 ///
 /// This trait defines a method on how to draw a negative sample given some output. The return value
 /// should follow the distribution of the underlying output vocabulary.
 pub trait NegativeSamples {
     fn negative_sample(&mut self, output: usize) -> usize;
+
+    /// Get the probability of drawing `output` as a negative sample.
+    ///
+    /// This is the probability of `output` in the noise distribution,
+    /// which is used by noise-contrastive estimation.
+    fn noise_prob(&self, output: usize) -> f32;
+}
+
+/// Subsampling
+///
+/// This trait is implemented by trainers that subsample frequent words
+/// and drop subwords at random. Both are disabled when the loss of
+/// held-out sentences is computed.
+pub trait Subsampling {
+    /// Enable or disable subsampling and subword dropout.
+    fn set_subsampling(&mut self, subsampling: bool);
 }
 
 #[cfg(test)]
 mod tests {
-    use finalfusion::subword::FinalfusionHashIndexer;
-    use ndarray::Array2;
+    use std::io::{Cursor, Write};
+
+    use finalfusion::compat::fasttext::FastTextIndexer;
+    use finalfusion::io::ReadEmbeddings;
+    use finalfusion::prelude::{Embeddings, StorageWrap, VocabWrap};
+    use finalfusion::storage::{NdArray, Storage};
+    use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+    use finalfusion::vocab::{SubwordVocab as FiFuSubwordVocab, Vocab as FiFuVocab};
+    use ndarray::{array, Array1, Array2};
     use rand::SeedableRng;
     use rand_xorshift::XorShiftRng;
+    use tempfile::NamedTempFile;
 
-    use super::TrainModel;
-    use crate::config::BucketIndexerType::Finalfusion;
+    use super::{
+        fake_quantize, positional_label, write_ngram_embeddings, TrainModel, Trainer,
+        FASTTEXT_MAGIC, FASTTEXT_VERSION,
+    };
+    use crate::config::BucketIndexerType::{self, Finalfusion};
     use crate::config::SubwordVocabConfig;
     use crate::idx::WordWithSubwordsIdx;
-    use crate::io::EmbeddingFormat;
+    use crate::io::{EmbeddingFormat, TrainInfo};
     use crate::skipgram_trainer::SkipgramTrainer;
     use crate::util::all_close;
     use crate::{
-        BucketConfig, CommonConfig, Cutoff, LossType, ModelType, SkipGramConfig, SubwordVocab,
-        VocabBuilder,
+        BucketConfig, CommonConfig, Cutoff, ModelType, Precision, Script, ScriptNGrams,
+        SkipGramConfig, SubwordVocab, Vocab, VocabBuilder, WordBoundaries, WriteModelBinary,
+        WriteModelFastText,
     };
 
-    const TEST_COMMON_CONFIG: CommonConfig = CommonConfig {
-        dims: 3,
-        epochs: 5,
-        format: EmbeddingFormat::FinalFusion,
-        loss: LossType::LogisticNegativeSampling,
-        lr: 0.05,
-        negative_samples: 5,
-        zipf_exponent: 0.5,
-    };
+    const TEST_COMMON_CONFIG: CommonConfig = CommonConfig::for_test();
 
     const TEST_SKIP_CONFIG: SkipGramConfig = SkipGramConfig {
         context_size: 5,
         model: ModelType::SkipGram,
+        harmonic_weighting: false,
+        context_cutoff: None,
+        word_ngrams: 1,
     };
 
     const VOCAB_CONF: SubwordVocabConfig<BucketConfig> = SubwordVocabConfig {
@@ -337,6 +1348,10 @@ mod tests {
         cutoff: Cutoff::MinCount(2),
         max_n: 6,
         min_n: 3,
+        include_word: true,
+        script_ngrams: ScriptNGrams::new(),
+        split_compounds: false,
+        boundaries: WordBoundaries::BRACKETS,
         indexer: BucketConfig {
             buckets_exp: 21,
             indexer_type: Finalfusion,
@@ -371,6 +1386,7 @@ mod tests {
             ),
             input,
             output,
+            quantize_inputs: false,
         };
 
         // Input embeddings
@@ -386,16 +1402,12 @@ mod tests {
         ));
 
         // Mutable input embeddings
-        assert!(all_close(
-            model.input_embedding_mut(0).as_slice().unwrap(),
-            &[1., 2., 3.],
-            1e-5
-        ));
-        assert!(all_close(
-            model.input_embedding_mut(1).as_slice().unwrap(),
-            &[4., 5., 6.],
-            1e-5
-        ));
+        model.update_input_embedding(0, |embed| {
+            assert!(all_close(embed.as_slice().unwrap(), &[1., 2., 3.], 1e-5));
+        });
+        model.update_input_embedding(1, |embed| {
+            assert!(all_close(embed.as_slice().unwrap(), &[4., 5., 6.], 1e-5));
+        });
 
         // Output embeddings
         assert!(all_close(
@@ -410,16 +1422,12 @@ mod tests {
         ));
 
         // Mutable output embeddings
-        assert!(all_close(
-            model.output_embedding_mut(0).as_slice().unwrap(),
-            &[-1., -2., -3.],
-            1e-5
-        ));
-        assert!(all_close(
-            model.output_embedding_mut(1).as_slice().unwrap(),
-            &[-4., -5., -6.],
-            1e-5
-        ));
+        model.update_output_embedding(0, |embed| {
+            assert!(all_close(embed.as_slice().unwrap(), &[-1., -2., -3.], 1e-5));
+        });
+        model.update_output_embedding(1, |embed| {
+            assert!(all_close(embed.as_slice().unwrap(), &[-4., -5., -6.], 1e-5));
+        });
 
         // Mean input embedding.
         assert!(all_close(
@@ -431,4 +1439,429 @@ mod tests {
             1e-5
         ));
     }
+
+    #[test]
+    pub fn fake_quantize_test() {
+        let mut embed = Array1::from(vec![1.27, -0.5, 0.004, 0.]);
+        fake_quantize(embed.view_mut());
+        assert!(all_close(
+            embed.as_slice().unwrap(),
+            &[1.27, -0.5, 0., 0.],
+            1e-5
+        ));
+
+        // Zero embeddings are not changed.
+        let mut embed = Array1::zeros(3);
+        fake_quantize(embed.view_mut());
+        assert!(all_close(embed.as_slice().unwrap(), &[0., 0., 0.], 1e-5));
+    }
+
+    #[test]
+    pub fn tied_model_shares_matrices() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.cutoff = Cutoff::MinCount(1);
+        vocab_config.indexer.buckets_exp = 4;
+
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.tied = true;
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        builder.count("bla".to_string());
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        let mut model: TrainModel<_> = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            common_config,
+            TEST_SKIP_CONFIG,
+        )
+        .into();
+
+        model.update_output_embedding(0, |mut embed| embed.fill(1.));
+        assert!(all_close(
+            model.input_embedding(0).as_slice().unwrap(),
+            &[1., 1., 1.],
+            1e-5
+        ));
+    }
+
+    #[test]
+    pub fn context_cutoff_restricts_outputs() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.cutoff = Cutoff::MinCount(1);
+        vocab_config.indexer.buckets_exp = 4;
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        for word in &["a", "a", "a", "b", "b", "c"] {
+            builder.count(word.to_string());
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        let mut skipgram_config = TEST_SKIP_CONFIG;
+        skipgram_config.context_cutoff = Some(Cutoff::MinCount(2));
+        let model: TrainModel<_> = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            skipgram_config,
+        )
+        .into();
+
+        assert_eq!(model.input_vocab().len(), 3);
+        assert_eq!(model.trainer.n_output_types(), 2);
+    }
+
+    #[test]
+    pub fn positional_label_test() {
+        assert_eq!(positional_label("a", 0, 2), "a@-2");
+        assert_eq!(positional_label("a", 1, 2), "a@-1");
+        assert_eq!(positional_label("a", 2, 2), "a@+1");
+        assert_eq!(positional_label("a", 3, 2), "a@+2");
+    }
+
+    #[test]
+    pub fn write_output_embeddings_test() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.cutoff = Cutoff::MinCount(1);
+        vocab_config.indexer.buckets_exp = 4;
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        for word in &["a", "a", "a", "b", "b", "c"] {
+            builder.count(word.to_string());
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        let mut skipgram_config = TEST_SKIP_CONFIG;
+        skipgram_config.context_cutoff = Some(Cutoff::MinCount(2));
+        let model: TrainModel<_> = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            skipgram_config,
+        )
+        .into();
+
+        let mut data = Vec::new();
+        model
+            .write_output_embeddings(&mut Cursor::new(&mut data))
+            .unwrap();
+
+        let embeddings: Embeddings<VocabWrap, StorageWrap> =
+            Embeddings::read_embeddings(&mut Cursor::new(data)).unwrap();
+        assert_eq!(embeddings.vocab().words(), &["a", "b"]);
+        assert_eq!(embeddings.dims(), 3);
+    }
+
+    #[test]
+    pub fn write_model_binary_stores_norms() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.cutoff = Cutoff::MinCount(1);
+        vocab_config.indexer.buckets_exp = 4;
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        for word in &["a", "a", "b"] {
+            builder.count(word.to_string());
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        let model: TrainModel<_> = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            TEST_SKIP_CONFIG,
+        )
+        .into();
+        let idx = model.input_vocab().idx("a").unwrap();
+        let unnormalized = model.mean_input_embedding(&idx);
+
+        let embeddings = write_read_model(model, "a a b\n");
+
+        // The original embeddings can be recovered from the normalized
+        // embeddings and the norms.
+        let with_norm = embeddings.embedding_with_norm("a").unwrap();
+        let recovered = with_norm.embedding.to_owned() * with_norm.norm;
+        assert!(all_close(
+            recovered.as_slice().unwrap(),
+            unnormalized.as_slice().unwrap(),
+            1e-5
+        ));
+    }
+
+    #[test]
+    pub fn write_model_binary_half_precision_top_k() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.cutoff = Cutoff::MinCount(1);
+        vocab_config.indexer.buckets_exp = 4;
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        for word in &["a", "a", "a", "b", "b", "c"] {
+            builder.count(word.to_string());
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        let common_config = CommonConfig {
+            precision: Precision::F16,
+            write_top_k: Some(2),
+            ..TEST_COMMON_CONFIG
+        };
+        let model: TrainModel<_> = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            common_config,
+            TEST_SKIP_CONFIG,
+        )
+        .into();
+        let idx = model.input_vocab().idx("b").unwrap();
+        let word = model.mean_input_embedding(&idx);
+        let subword = model.input_embedding(3).into_owned();
+
+        let embeddings = write_read_model(model, "a a a b b c\n");
+
+        // The word c is not written, the subword embeddings follow the
+        // embeddings of a and b.
+        assert_eq!(embeddings.vocab().words_len(), 2);
+        assert_eq!(embeddings.storage().shape(), (18, 3));
+        let with_norm = embeddings.embedding_with_norm("b").unwrap();
+        let recovered = with_norm.embedding.to_owned() * with_norm.norm;
+        assert!(all_close(
+            recovered.as_slice().unwrap(),
+            word.as_slice().unwrap(),
+            1e-5
+        ));
+        assert!(all_close(
+            embeddings.storage().embedding(2).as_slice().unwrap(),
+            subword.as_slice().unwrap(),
+            1e-5
+        ));
+    }
+
+    #[test]
+    pub fn write_model_binary_metadata() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.cutoff = Cutoff::MinCount(1);
+        vocab_config.indexer.buckets_exp = 4;
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        for word in &["a", "a", "b"] {
+            builder.count(word.to_string());
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        let model: TrainModel<_> = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            TEST_SKIP_CONFIG,
+        )
+        .into();
+        let embeddings = write_read_model(model, "a a b\n");
+
+        let metadata = embeddings.metadata().unwrap();
+        assert_eq!(
+            metadata["training_info"]["corpus_sha256"].as_str(),
+            Some("ad4b4f8322851da300e597f8858e0032c119193e7c7e731782fd83609543a273")
+        );
+        assert!(metadata["training_info"].get("duration_secs").is_some());
+        assert_eq!(metadata["vocab_info"]["n_tokens"].as_integer(), Some(3));
+        assert_eq!(metadata["vocab_info"]["n_words"].as_integer(), Some(2));
+        assert_eq!(
+            metadata["vocab_info"]["n_input_types"].as_integer(),
+            Some(18)
+        );
+        assert!(metadata["hardware_info"]["n_cpus"].as_integer().unwrap() > 0);
+    }
+
+    /// Write a model in finalfusion format and read it back.
+    ///
+    /// `corpus` is the content of the corpus that the model was trained on.
+    fn write_read_model<T>(model: T, corpus: &str) -> Embeddings<VocabWrap, StorageWrap>
+    where
+        T: WriteModelBinary<Cursor<Vec<u8>>>,
+    {
+        let mut corpus_file = NamedTempFile::new().unwrap();
+        corpus_file.write_all(corpus.as_bytes()).unwrap();
+        let train_info = TrainInfo::new(
+            vec![corpus_file.path().to_str().unwrap().to_string()],
+            "output".to_string(),
+            1,
+        )
+        .unwrap();
+
+        let mut data = Cursor::new(Vec::new());
+        model
+            .write_model_binary(&mut data, train_info, EmbeddingFormat::FinalFusion)
+            .unwrap();
+
+        Embeddings::read_embeddings(&mut Cursor::new(data.into_inner())).unwrap()
+    }
+
+    #[test]
+    pub fn write_ngram_embeddings_test() {
+        let vocab: VocabWrap = FiFuSubwordVocab::new(
+            vec!["a".to_string()],
+            2,
+            2,
+            ExplicitIndexer::new(vec!["<a".to_string(), "a>".to_string()]),
+        )
+        .into();
+        let matrix = array![[1f32, 0.], [0., 2.], [3., 4.]];
+
+        let mut data = Vec::new();
+        write_ngram_embeddings(&mut Cursor::new(&mut data), &vocab, &NdArray::new(matrix)).unwrap();
+
+        let embeddings: Embeddings<VocabWrap, StorageWrap> =
+            Embeddings::read_embeddings(&mut Cursor::new(data)).unwrap();
+        assert_eq!(embeddings.vocab().words(), &["<a", "a>"]);
+        let embed = embeddings.embedding("a>").unwrap();
+        assert!(all_close(embed.as_slice().unwrap(), &[0.6, 0.8], 1e-5));
+    }
+
+    #[test]
+    pub fn write_model_fasttext_test() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.cutoff = Cutoff::MinCount(1);
+        vocab_config.indexer.buckets_exp = 4;
+        vocab_config.indexer.indexer_type = BucketIndexerType::FastText;
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        for word in &["a", "a", "a", "b", "b", "c"] {
+            builder.count(word.to_string());
+        }
+        let vocab: SubwordVocab<_, FastTextIndexer> = builder.into();
+
+        let mut skipgram_config = TEST_SKIP_CONFIG;
+        skipgram_config.context_cutoff = Some(Cutoff::MinCount(2));
+        let model: TrainModel<_> = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            skipgram_config,
+        )
+        .into();
+
+        let mut data = Vec::new();
+        model.write_model_fasttext(&mut data).unwrap();
+
+        let read_i32 = |offset: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&data[offset..offset + 4]);
+            i32::from_le_bytes(bytes)
+        };
+        assert_eq!(read_i32(0), FASTTEXT_MAGIC);
+        assert_eq!(read_i32(4), FASTTEXT_VERSION);
+        // Dimensionality, context size, and number of buckets.
+        assert_eq!(read_i32(8), 3);
+        assert_eq!(read_i32(12), 5);
+        assert_eq!(read_i32(40), 16);
+
+        // Header, dictionary, input matrix (3 words + 16 buckets), and
+        // output matrix (3 words, padded).
+        let dict_len = 28 + 3 * (1 + 1 + 8 + 1);
+        let input_len = 17 + 19 * 3 * 4;
+        let output_len = 17 + 3 * 3 * 4;
+        assert_eq!(data.len(), 64 + dict_len + input_len + output_len);
+    }
+
+    #[test]
+    pub fn write_model_fasttext_top_k() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.cutoff = Cutoff::MinCount(1);
+        vocab_config.indexer.buckets_exp = 4;
+        vocab_config.indexer.indexer_type = BucketIndexerType::FastText;
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        for word in &["a", "a", "a", "b", "b", "c"] {
+            builder.count(word.to_string());
+        }
+        let vocab: SubwordVocab<_, FastTextIndexer> = builder.into();
+
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.write_top_k = Some(2);
+        let model: TrainModel<_> = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            common_config,
+            TEST_SKIP_CONFIG,
+        )
+        .into();
+
+        let mut data = Vec::new();
+        model.write_model_fasttext(&mut data).unwrap();
+
+        // Only the words a and b are written, the buckets are retained.
+        let dict_len = 28 + 2 * (1 + 1 + 8 + 1);
+        let input_len = 17 + 18 * 3 * 4;
+        let output_len = 17 + 2 * 3 * 4;
+        assert_eq!(data.len(), 64 + dict_len + input_len + output_len);
+        assert_eq!(&data[64 + 28..64 + 30], b"a\0");
+    }
+
+    #[test]
+    pub fn write_model_fasttext_rejects_finalfusion_vocab() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.cutoff = Cutoff::MinCount(1);
+        vocab_config.indexer.buckets_exp = 4;
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        builder.count("bla".to_string());
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        let model: TrainModel<_> = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            TEST_SKIP_CONFIG,
+        )
+        .into();
+
+        assert!(model.write_model_fasttext(&mut Vec::new()).is_err());
+    }
+
+    fn write_fasttext_with(
+        mut vocab_config: SubwordVocabConfig<BucketConfig>,
+        skipgram_config: SkipGramConfig,
+    ) -> anyhow::Result<Vec<u8>> {
+        vocab_config.cutoff = Cutoff::MinCount(1);
+        vocab_config.indexer.buckets_exp = 4;
+        vocab_config.indexer.indexer_type = BucketIndexerType::FastText;
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        for word in &["a", "a", "a", "b", "b", "c"] {
+            builder.count(word.to_string());
+        }
+        let vocab: SubwordVocab<_, FastTextIndexer> = builder.into();
+
+        let model: TrainModel<_> = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            skipgram_config,
+        )
+        .into();
+
+        let mut data = Vec::new();
+        model.write_model_fasttext(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    pub fn write_model_fasttext_rejects_script_ngrams() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.script_ngrams.set(Script::Han, 1, 1);
+        assert!(write_fasttext_with(vocab_config, TEST_SKIP_CONFIG).is_err());
+    }
+
+    #[test]
+    pub fn write_model_fasttext_rejects_split_compounds() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.split_compounds = true;
+        assert!(write_fasttext_with(vocab_config, TEST_SKIP_CONFIG).is_err());
+    }
+
+    #[test]
+    pub fn write_model_fasttext_rejects_word_ngrams() {
+        let mut skipgram_config = TEST_SKIP_CONFIG;
+        skipgram_config.word_ngrams = 2;
+        assert!(write_fasttext_with(VOCAB_CONF, skipgram_config).is_err());
+        assert!(write_fasttext_with(VOCAB_CONF, TEST_SKIP_CONFIG).is_ok());
+    }
 }
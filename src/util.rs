@@ -1,3 +1,5 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
 use rand::SeedableRng;
 use rand_core::{self, RngCore};
 use serde::Serialize;
@@ -11,20 +13,55 @@ pub fn safe_ln(v: f32) -> f32 {
     (v + NEGATIVE_TOLERANCE).ln()
 }
 
+/// Increment of the SplitMix64 seed generator.
+const SPLITMIX64_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Reseeding of random number generators.
+///
+/// This trait is implemented by types that hold random number
+/// generators, so that the state of all their generators can be set from
+/// a single seed.
+pub trait Reseed {
+    /// Reseed the random number generators from `seed`.
+    fn reseed(&mut self, seed: u64);
+}
+
 /// RNG that reseeds on clone.
 ///
 /// This is a wrapper struct for RNGs implementing the `RngCore`
 /// trait.  It adds the following simple behavior: when a
-/// `ReseedOnCloneRng` is cloned, the clone is constructed using fresh
-/// entropy. This assures that the state of the clone is not related
+/// `ReseedOnCloneRng` is cloned, the clone is constructed using a fresh
+/// seed. This assures that the state of the clone is not related
 /// to the cloned RNG.
 ///
+/// The seeds of clones are drawn from the seed generator of the cloned
+/// RNG. The seed generator is itself seeded by the wrapped RNG, so that
+/// clones are reproducible when the wrapped RNG is seeded
+/// deterministically and clones are made in the same order. Every clone
+/// gets its own seed generator, so clones in different threads do not
+/// synchronize.
+///
 /// The `rand` crate provides similar behavior in the `ReseedingRng`
 /// struct. However, `ReseedingRng` requires that the RNG is
 /// `BlockRngCore`.
-pub struct ReseedOnCloneRng<R>(pub R)
+pub struct ReseedOnCloneRng<R>
+where
+    R: RngCore + SeedableRng,
+{
+    rng: R,
+    seeder: Seeder,
+}
+
+impl<R> ReseedOnCloneRng<R>
 where
-    R: RngCore + SeedableRng;
+    R: RngCore + SeedableRng,
+{
+    /// Wrap an RNG.
+    pub fn new(mut rng: R) -> Self {
+        let seeder = Seeder::new(rng.next_u64());
+        ReseedOnCloneRng { rng, seeder }
+    }
+}
 
 impl<R> RngCore for ReseedOnCloneRng<R>
 where
@@ -32,22 +69,22 @@ where
 {
     #[inline]
     fn next_u32(&mut self) -> u32 {
-        self.0.next_u32()
+        self.rng.next_u32()
     }
 
     #[inline]
     fn next_u64(&mut self) -> u64 {
-        self.0.next_u64()
+        self.rng.next_u64()
     }
 
     #[inline]
     fn fill_bytes(&mut self, dest: &mut [u8]) {
-        self.0.fill_bytes(dest)
+        self.rng.fill_bytes(dest)
     }
 
     #[inline]
     fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
-        self.0.try_fill_bytes(dest)
+        self.rng.try_fill_bytes(dest)
     }
 }
 
@@ -56,7 +93,44 @@ where
     R: RngCore + SeedableRng,
 {
     fn clone(&self) -> Self {
-        ReseedOnCloneRng(R::from_entropy())
+        ReseedOnCloneRng {
+            rng: R::seed_from_u64(self.seeder.next_seed()),
+            seeder: Seeder::new(self.seeder.next_seed()),
+        }
+    }
+}
+
+impl<R> Reseed for ReseedOnCloneRng<R>
+where
+    R: RngCore + SeedableRng,
+{
+    fn reseed(&mut self, seed: u64) {
+        let seeder = Seeder::new(seed);
+        self.rng = R::seed_from_u64(seeder.next_seed());
+        self.seeder = seeder;
+    }
+}
+
+/// Seed generator.
+///
+/// This is a SplitMix64 generator with an atomic state, so that seeds
+/// can be drawn through a shared reference without locking.
+struct Seeder(AtomicU64);
+
+impl Seeder {
+    fn new(seed: u64) -> Self {
+        Seeder(AtomicU64::new(seed))
+    }
+
+    /// Draw the next seed.
+    fn next_seed(&self) -> u64 {
+        let mut z = self
+            .0
+            .fetch_add(SPLITMIX64_GAMMA, Ordering::Relaxed)
+            .wrapping_add(SPLITMIX64_GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
     }
 }
 
@@ -64,6 +138,7 @@ where
 pub(crate) struct VersionInfo {
     finalfusion_version: &'static str,
     git_desc: Option<&'static str>,
+    git_revision: Option<&'static str>,
 }
 
 impl VersionInfo {
@@ -71,6 +146,27 @@ impl VersionInfo {
         VersionInfo {
             finalfusion_version: env!("CARGO_PKG_VERSION"),
             git_desc: option_env!("MAYBE_FINALFRONTIER_GIT_DESC"),
+            git_revision: option_env!("MAYBE_FINALFRONTIER_GIT_REVISION"),
+        }
+    }
+}
+
+/// Information about the machine that a model was trained on.
+#[derive(Serialize)]
+pub(crate) struct HardwareInfo {
+    os: &'static str,
+    arch: &'static str,
+    n_cpus: usize,
+    n_physical_cpus: usize,
+}
+
+impl HardwareInfo {
+    pub(crate) fn new() -> Self {
+        HardwareInfo {
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            n_cpus: num_cpus::get(),
+            n_physical_cpus: num_cpus::get_physical(),
         }
     }
 }
@@ -83,8 +179,9 @@ mod test {
     use ndarray::{ArrayView, Dimension};
     use rand::SeedableRng;
     use rand_core::{self, impls, le, RngCore};
+    use rand_xorshift::XorShiftRng;
 
-    use super::ReseedOnCloneRng;
+    use super::{Reseed, ReseedOnCloneRng};
 
     #[derive(Clone)]
     struct BogusRng(pub u64);
@@ -157,9 +254,34 @@ mod test {
         let bogus_rng_clone = bogus_rng.clone();
         assert_eq!(bogus_rng.0, bogus_rng_clone.0);
 
-        let reseed = ReseedOnCloneRng(bogus_rng);
+        let reseed = ReseedOnCloneRng::new(bogus_rng);
         let reseed_clone = reseed.clone();
-        // One in 2^64 probability of collision given good entropy source.
-        assert_ne!((reseed.0).0, (reseed_clone.0).0);
+        assert_ne!(reseed.rng.0, reseed_clone.rng.0);
+    }
+
+    #[test]
+    fn reseed_on_clone_rng_is_reproducible() {
+        let mut reseed = ReseedOnCloneRng::new(XorShiftRng::seed_from_u64(42));
+        let mut reseed_clone = reseed.clone();
+        let mut reseed2 = ReseedOnCloneRng::new(XorShiftRng::seed_from_u64(42));
+        let mut reseed2_clone = reseed2.clone();
+        assert_eq!(reseed.next_u64(), reseed2.next_u64());
+        assert_eq!(reseed_clone.next_u64(), reseed2_clone.next_u64());
+    }
+
+    #[test]
+    fn reseed_sets_state_of_rng_and_clones() {
+        let mut reseed = ReseedOnCloneRng::new(XorShiftRng::seed_from_u64(42));
+        let mut reseed2 = ReseedOnCloneRng::new(XorShiftRng::seed_from_u64(43));
+        reseed2.next_u64();
+        let _ = reseed2.clone();
+
+        reseed.reseed(7);
+        reseed2.reseed(7);
+        let mut reseed_clone = reseed.clone();
+        let mut reseed2_clone = reseed2.clone();
+        assert_eq!(reseed.next_u64(), reseed2.next_u64());
+        assert_eq!(reseed_clone.next_u64(), reseed2_clone.next_u64());
+        assert_ne!(reseed.next_u64(), reseed_clone.next_u64());
     }
 }
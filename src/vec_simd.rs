@@ -2,24 +2,143 @@
 //!
 //! This module is not for public consumption, but is made public
 //! for benchmarking.
+//!
+//! The instruction set is detected at run time, so that a single binary
+//! uses the widest vectors that the CPU supports. On ARM, NEON is used.
+//! SVE is not supported yet, since its intrinsics are not stable.
+//!
+//! The AVX-512 kernels are only compiled with the `avx512` feature,
+//! since their intrinsics require Rust 1.89.
+
+use std::sync::atomic::{AtomicU8, Ordering};
 
 use ndarray::{ArrayView1, ArrayViewMut1};
 
+/// Marker of an instruction set that was not detected yet.
+const SIMD_UNDETECTED: u8 = u8::MAX;
+
+/// Instruction set that is used by the vectorized operators.
+static SIMD_LEVEL: AtomicU8 = AtomicU8::new(SIMD_UNDETECTED);
+
+/// SIMD instruction set.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SimdLevel {
+    /// No SIMD instructions, scalar operations.
+    None,
+
+    /// SSE, 128-bit vectors.
+    Sse,
+
+    /// AVX, 256-bit vectors.
+    Avx,
+
+    /// AVX with fused multiply-add, as in AVX2-capable CPUs.
+    AvxFma,
+
+    /// AVX-512 Foundation, 512-bit vectors.
+    #[cfg(feature = "avx512")]
+    Avx512,
+
+    /// ARM NEON, 128-bit vectors.
+    Neon,
+}
+
+impl SimdLevel {
+    /// Get the widest instruction set that is supported by the CPU.
+    ///
+    /// The instruction set is detected on the first call and cached.
+    pub fn detect() -> Self {
+        let level = SIMD_LEVEL.load(Ordering::Relaxed);
+        if level != SIMD_UNDETECTED {
+            return Self::from_u8(level);
+        }
+
+        let level = Self::detect_uncached();
+        SIMD_LEVEL.store(level as u8, Ordering::Relaxed);
+        level
+    }
+
+    /// Get the name of the instruction set.
+    pub fn name(self) -> &'static str {
+        match self {
+            SimdLevel::None => "none",
+            SimdLevel::Sse => "sse",
+            SimdLevel::Avx => "avx",
+            SimdLevel::AvxFma => "avx+fma",
+            #[cfg(feature = "avx512")]
+            SimdLevel::Avx512 => "avx512f",
+            SimdLevel::Neon => "neon",
+        }
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn detect_uncached() -> Self {
+        #[cfg(feature = "avx512")]
+        {
+            if is_x86_feature_detected!("avx512f") {
+                return SimdLevel::Avx512;
+            }
+        }
+
+        if is_x86_feature_detected!("avx") && is_x86_feature_detected!("fma") {
+            SimdLevel::AvxFma
+        } else if is_x86_feature_detected!("avx") {
+            SimdLevel::Avx
+        } else if is_x86_feature_detected!("sse") {
+            SimdLevel::Sse
+        } else {
+            SimdLevel::None
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn detect_uncached() -> Self {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            SimdLevel::Neon
+        } else {
+            SimdLevel::None
+        }
+    }
+
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    fn detect_uncached() -> Self {
+        SimdLevel::None
+    }
+
+    fn from_u8(level: u8) -> Self {
+        match level {
+            l if l == SimdLevel::Sse as u8 => SimdLevel::Sse,
+            l if l == SimdLevel::Avx as u8 => SimdLevel::Avx,
+            l if l == SimdLevel::AvxFma as u8 => SimdLevel::AvxFma,
+            #[cfg(feature = "avx512")]
+            l if l == SimdLevel::Avx512 as u8 => SimdLevel::Avx512,
+            l if l == SimdLevel::Neon as u8 => SimdLevel::Neon,
+            _ => SimdLevel::None,
+        }
+    }
+}
+
 /// Dot product: u · v
 ///
-/// If the CPU supports SSE or AVX instructions, the dot
+/// If the CPU supports SSE, AVX, AVX-512, or NEON instructions, the dot
 /// product is SIMD-vectorized.
 pub fn dot(u: ArrayView1<f32>, v: ArrayView1<f32>) -> f32 {
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     {
-        if is_x86_feature_detected!("avx") {
-            if is_x86_feature_detected!("fma") {
-                return unsafe { avx_fma::dot(u, v) };
-            }
+        match SimdLevel::detect() {
+            #[cfg(feature = "avx512")]
+            SimdLevel::Avx512 => return unsafe { avx512::dot(u, v) },
+            SimdLevel::AvxFma => return unsafe { avx_fma::dot(u, v) },
+            SimdLevel::Avx => return unsafe { avx::dot(u, v) },
+            SimdLevel::Sse => return unsafe { sse::dot(u, v) },
+            SimdLevel::None | SimdLevel::Neon => (),
+        }
+    }
 
-            return unsafe { avx::dot(u, v) };
-        } else if is_x86_feature_detected!("sse") {
-            return unsafe { sse::dot(u, v) };
+    #[cfg(target_arch = "aarch64")]
+    {
+        if let SimdLevel::Neon = SimdLevel::detect() {
+            return unsafe { neon::dot(u, v) };
         }
     }
 
@@ -31,15 +150,24 @@ pub fn dot(u: ArrayView1<f32>, v: ArrayView1<f32>) -> f32 {
 
 /// Scaling: u = au
 ///
-/// If the CPU supports SSE or AVX instructions, scaling is
-/// SIMD-vectorized.
+/// If the CPU supports SSE, AVX, AVX-512, or NEON instructions, scaling
+/// is SIMD-vectorized.
 pub fn scale(mut u: ArrayViewMut1<f32>, a: f32) {
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     {
-        if is_x86_feature_detected!("avx") {
-            return unsafe { avx::scale(u, a) };
-        } else if is_x86_feature_detected!("sse") {
-            return unsafe { sse::scale(u, a) };
+        match SimdLevel::detect() {
+            #[cfg(feature = "avx512")]
+            SimdLevel::Avx512 => return unsafe { avx512::scale(u, a) },
+            SimdLevel::AvxFma | SimdLevel::Avx => return unsafe { avx::scale(u, a) },
+            SimdLevel::Sse => return unsafe { sse::scale(u, a) },
+            SimdLevel::None | SimdLevel::Neon => (),
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        if let SimdLevel::Neon = SimdLevel::detect() {
+            return unsafe { neon::scale(u, a) };
         }
     }
 
@@ -48,15 +176,25 @@ pub fn scale(mut u: ArrayViewMut1<f32>, a: f32) {
 
 /// Scaled addition: *u = u + av*
 ///
-/// If the CPU supports SSE or AVX instructions, scaled addition is
-/// SIMD-vectorized.
+/// If the CPU supports SSE, AVX, AVX-512, or NEON instructions, scaled
+/// addition is SIMD-vectorized.
 pub fn scaled_add(mut u: ArrayViewMut1<f32>, v: ArrayView1<f32>, a: f32) {
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     {
-        if is_x86_feature_detected!("avx") {
-            return unsafe { avx::scaled_add(u, v, a) };
-        } else if is_x86_feature_detected!("sse") {
-            return unsafe { sse::scaled_add(u, v, a) };
+        match SimdLevel::detect() {
+            #[cfg(feature = "avx512")]
+            SimdLevel::Avx512 => return unsafe { avx512::scaled_add(u, v, a) },
+            SimdLevel::AvxFma => return unsafe { avx_fma::scaled_add(u, v, a) },
+            SimdLevel::Avx => return unsafe { avx::scaled_add(u, v, a) },
+            SimdLevel::Sse => return unsafe { sse::scaled_add(u, v, a) },
+            SimdLevel::None | SimdLevel::Neon => (),
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        if let SimdLevel::Neon = SimdLevel::detect() {
+            return unsafe { neon::scaled_add(u, v, a) };
         }
     }
 
@@ -292,9 +430,9 @@ pub mod avx_fma {
     #[cfg(target_arch = "x86_64")]
     use std::arch::x86_64::*;
 
-    use ndarray::ArrayView1;
+    use ndarray::{ArrayView1, ArrayViewMut1};
 
-    use super::dot_unvectorized;
+    use super::{dot_unvectorized, scaled_add_unvectorized};
 
     #[target_feature(enable = "avx", enable = "fma")]
     #[allow(clippy::missing_safety_doc)]
@@ -331,6 +469,251 @@ pub mod avx_fma {
 
         super::avx::avx_add(sums) + dot_unvectorized(u, v)
     }
+
+    #[target_feature(enable = "avx", enable = "fma")]
+    pub(crate) unsafe fn scaled_add(mut u: ArrayViewMut1<f32>, v: ArrayView1<f32>, a: f32) {
+        assert_eq!(u.len(), v.len());
+
+        let mut u = u
+            .as_slice_mut()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.");
+        let mut v = &v
+            .as_slice()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.")[..u.len()];
+
+        let ax8 = _mm256_set1_ps(a);
+
+        while u.len() >= 8 {
+            let mut ux8 = _mm256_loadu_ps(&u[0] as *const f32);
+            let vx8 = _mm256_loadu_ps(&v[0] as *const f32);
+
+            ux8 = _mm256_fmadd_ps(vx8, ax8, ux8);
+
+            _mm256_storeu_ps(&mut u[0] as *mut f32, ux8);
+            u = &mut { u }[8..];
+            v = &v[8..];
+        }
+
+        scaled_add_unvectorized(u, v, a);
+    }
+}
+
+#[cfg(all(feature = "avx512", any(target_arch = "x86", target_arch = "x86_64")))]
+pub mod avx512 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    use ndarray::{ArrayView1, ArrayViewMut1};
+
+    /// Mask of the first `n` lanes of a vector, where `n < 16`.
+    ///
+    /// Remainders are processed with masked loads and stores, rather than
+    /// scalar operations.
+    #[inline(always)]
+    fn tail_mask(n: usize) -> __mmask16 {
+        ((1u32 << n) - 1) as __mmask16
+    }
+
+    #[target_feature(enable = "avx512f")]
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn dot(u: ArrayView1<f32>, v: ArrayView1<f32>) -> f32 {
+        assert_eq!(u.len(), v.len());
+
+        let mut u = u
+            .as_slice()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.");
+        let mut v = &v
+            .as_slice()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.")[..u.len()];
+
+        let mut sums0 = _mm512_setzero_ps();
+        let mut sums1 = _mm512_setzero_ps();
+
+        while u.len() >= 32 {
+            let ux16 = _mm512_loadu_ps(&u[0] as *const f32);
+            let vx16 = _mm512_loadu_ps(&v[0] as *const f32);
+
+            sums0 = _mm512_fmadd_ps(ux16, vx16, sums0);
+
+            let ux16 = _mm512_loadu_ps(&u[16] as *const f32);
+            let vx16 = _mm512_loadu_ps(&v[16] as *const f32);
+
+            sums1 = _mm512_fmadd_ps(ux16, vx16, sums1);
+
+            u = &u[32..];
+            v = &v[32..];
+        }
+
+        if u.len() >= 16 {
+            let ux16 = _mm512_loadu_ps(&u[0] as *const f32);
+            let vx16 = _mm512_loadu_ps(&v[0] as *const f32);
+
+            sums0 = _mm512_fmadd_ps(ux16, vx16, sums0);
+
+            u = &u[16..];
+            v = &v[16..];
+        }
+
+        if !u.is_empty() {
+            let mask = tail_mask(u.len());
+            let ux16 = _mm512_maskz_loadu_ps(mask, u.as_ptr());
+            let vx16 = _mm512_maskz_loadu_ps(mask, v.as_ptr());
+
+            sums1 = _mm512_fmadd_ps(ux16, vx16, sums1);
+        }
+
+        _mm512_reduce_add_ps(_mm512_add_ps(sums0, sums1))
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub(crate) unsafe fn scale(mut u: ArrayViewMut1<f32>, a: f32) {
+        let mut u = u
+            .as_slice_mut()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.");
+
+        let ax16 = _mm512_set1_ps(a);
+
+        while u.len() >= 16 {
+            let mut ux16 = _mm512_loadu_ps(&u[0] as *const f32);
+            ux16 = _mm512_mul_ps(ux16, ax16);
+            _mm512_storeu_ps(&mut u[0] as *mut f32, ux16);
+            u = &mut { u }[16..];
+        }
+
+        if !u.is_empty() {
+            let mask = tail_mask(u.len());
+            let ux16 = _mm512_maskz_loadu_ps(mask, u.as_ptr());
+            _mm512_mask_storeu_ps(u.as_mut_ptr(), mask, _mm512_mul_ps(ux16, ax16));
+        }
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub(crate) unsafe fn scaled_add(mut u: ArrayViewMut1<f32>, v: ArrayView1<f32>, a: f32) {
+        assert_eq!(u.len(), v.len());
+
+        let mut u = u
+            .as_slice_mut()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.");
+        let mut v = &v
+            .as_slice()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.")[..u.len()];
+
+        let ax16 = _mm512_set1_ps(a);
+
+        while u.len() >= 16 {
+            let mut ux16 = _mm512_loadu_ps(&u[0] as *const f32);
+            let vx16 = _mm512_loadu_ps(&v[0] as *const f32);
+
+            ux16 = _mm512_fmadd_ps(vx16, ax16, ux16);
+
+            _mm512_storeu_ps(&mut u[0] as *mut f32, ux16);
+            u = &mut { u }[16..];
+            v = &v[16..];
+        }
+
+        if !u.is_empty() {
+            let mask = tail_mask(u.len());
+            let ux16 = _mm512_maskz_loadu_ps(mask, u.as_ptr());
+            let vx16 = _mm512_maskz_loadu_ps(mask, v.as_ptr());
+            _mm512_mask_storeu_ps(u.as_mut_ptr(), mask, _mm512_fmadd_ps(vx16, ax16, ux16));
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub mod neon {
+    use std::arch::aarch64::*;
+
+    use ndarray::{ArrayView1, ArrayViewMut1};
+
+    use super::{dot_unvectorized, scale_unvectorized, scaled_add_unvectorized};
+
+    #[target_feature(enable = "neon")]
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn dot(u: ArrayView1<f32>, v: ArrayView1<f32>) -> f32 {
+        assert_eq!(u.len(), v.len());
+
+        let mut u = u
+            .as_slice()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.");
+        let mut v = &v
+            .as_slice()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.")[..u.len()];
+
+        let mut sums0 = vdupq_n_f32(0.);
+        let mut sums1 = vdupq_n_f32(0.);
+
+        while u.len() >= 8 {
+            let ux4 = vld1q_f32(&u[0] as *const f32);
+            let vx4 = vld1q_f32(&v[0] as *const f32);
+
+            sums0 = vfmaq_f32(sums0, ux4, vx4);
+
+            let ux4 = vld1q_f32(&u[4] as *const f32);
+            let vx4 = vld1q_f32(&v[4] as *const f32);
+
+            sums1 = vfmaq_f32(sums1, ux4, vx4);
+
+            u = &u[8..];
+            v = &v[8..];
+        }
+
+        if u.len() >= 4 {
+            let ux4 = vld1q_f32(&u[0] as *const f32);
+            let vx4 = vld1q_f32(&v[0] as *const f32);
+
+            sums0 = vfmaq_f32(sums0, ux4, vx4);
+
+            u = &u[4..];
+            v = &v[4..];
+        }
+
+        vaddvq_f32(vaddq_f32(sums0, sums1)) + dot_unvectorized(u, v)
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(crate) unsafe fn scale(mut u: ArrayViewMut1<f32>, a: f32) {
+        let mut u = u
+            .as_slice_mut()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.");
+
+        let ax4 = vdupq_n_f32(a);
+
+        while u.len() >= 4 {
+            let ux4 = vld1q_f32(&u[0] as *const f32);
+            vst1q_f32(&mut u[0] as *mut f32, vmulq_f32(ux4, ax4));
+            u = &mut { u }[4..];
+        }
+
+        scale_unvectorized(u, a);
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(crate) unsafe fn scaled_add(mut u: ArrayViewMut1<f32>, v: ArrayView1<f32>, a: f32) {
+        assert_eq!(u.len(), v.len());
+
+        let mut u = u
+            .as_slice_mut()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.");
+        let mut v = &v
+            .as_slice()
+            .expect("Cannot apply SIMD instructions on non-contiguous data.")[..u.len()];
+
+        let ax4 = vdupq_n_f32(a);
+
+        while u.len() >= 4 {
+            let ux4 = vld1q_f32(&u[0] as *const f32);
+            let vx4 = vld1q_f32(&v[0] as *const f32);
+            vst1q_f32(&mut u[0] as *mut f32, vfmaq_f32(ux4, vx4, ax4));
+            u = &mut { u }[4..];
+            v = &v[4..];
+        }
+
+        scaled_add_unvectorized(u, v, a);
+    }
 }
 
 pub fn dot_unvectorized(u: &[f32], v: &[f32]) -> f32 {
@@ -365,7 +748,13 @@ fn scale_unvectorized(u: &mut [f32], a: f32) {
 #[inline]
 pub fn l2_normalize(v: ArrayViewMut1<f32>) -> f32 {
     let norm = dot(v.view(), v.view()).sqrt();
-    scale(v, 1.0 / norm);
+
+    // Zero vectors, such as PMI embeddings of words without
+    // co-occurrences, cannot be normalized.
+    if norm > 0. {
+        scale(v, 1.0 / norm);
+    }
+
     norm
 }
 
@@ -377,16 +766,18 @@ mod tests {
 
     use crate::util::{all_close, array_all_close, close};
 
-    use super::{dot_unvectorized, l2_normalize, scale_unvectorized, scaled_add_unvectorized};
+    use super::{
+        dot_unvectorized, l2_normalize, scale_unvectorized, scaled_add_unvectorized, SimdLevel,
+    };
 
-    #[cfg(target_feature = "sse")]
-    use super::sse;
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    use super::{avx, avx_fma, sse};
 
-    #[cfg(target_feature = "avx")]
-    use super::avx;
+    #[cfg(all(feature = "avx512", any(target_arch = "x86", target_arch = "x86_64")))]
+    use super::avx512;
 
-    #[cfg(all(target_feature = "avx", target_feature = "fma"))]
-    use super::avx_fma;
+    #[cfg(target_arch = "aarch64")]
+    use super::neon;
 
     #[test]
     fn add_unvectorized_test() {
@@ -397,8 +788,12 @@ mod tests {
     }
 
     #[test]
-    #[cfg(target_feature = "sse")]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn add_sse_test() {
+        if !is_x86_feature_detected!("sse") {
+            return;
+        }
+
         let mut u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let v = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let mut check = u.clone();
@@ -408,8 +803,12 @@ mod tests {
     }
 
     #[test]
-    #[cfg(target_feature = "avx")]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn add_avx_test() {
+        if !is_x86_feature_detected!("avx") {
+            return;
+        }
+
         let mut u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let v = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let mut check = u.clone();
@@ -419,8 +818,38 @@ mod tests {
     }
 
     #[test]
-    #[cfg(target_feature = "sse")]
+    #[cfg(all(feature = "avx512", any(target_arch = "x86", target_arch = "x86_64")))]
+    fn add_avx512_test() {
+        if !is_x86_feature_detected!("avx512f") {
+            return;
+        }
+
+        let mut u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
+        let v = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
+        let mut check = u.clone();
+        scaled_add_unvectorized(check.as_slice_mut().unwrap(), v.as_slice().unwrap(), 1.0);
+        unsafe { avx512::scaled_add(u.view_mut(), v.view(), 1.0) };
+        assert!(array_all_close(check.view(), u.view(), 1e-5));
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn add_neon_test() {
+        let mut u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
+        let v = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
+        let mut check = u.clone();
+        scaled_add_unvectorized(check.as_slice_mut().unwrap(), v.as_slice().unwrap(), 1.0);
+        unsafe { neon::scaled_add(u.view_mut(), v.view(), 1.0) };
+        assert!(array_all_close(check.view(), u.view(), 1e-5));
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn dot_sse_test() {
+        if !is_x86_feature_detected!("sse") {
+            return;
+        }
+
         let u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let v = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         assert!(close(
@@ -431,8 +860,12 @@ mod tests {
     }
 
     #[test]
-    #[cfg(target_feature = "avx")]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn dot_avx_test() {
+        if !is_x86_feature_detected!("avx") {
+            return;
+        }
+
         let u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let v = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         assert!(close(
@@ -443,8 +876,12 @@ mod tests {
     }
 
     #[test]
-    #[cfg(all(target_feature = "avx", target_feature = "fma"))]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn dot_avx_fma_test() {
+        if !(is_x86_feature_detected!("avx") && is_x86_feature_detected!("fma")) {
+            return;
+        }
+
         let u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let v = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         assert!(close(
@@ -454,6 +891,40 @@ mod tests {
         ));
     }
 
+    #[test]
+    #[cfg(all(feature = "avx512", any(target_arch = "x86", target_arch = "x86_64")))]
+    fn dot_avx512_test() {
+        if !is_x86_feature_detected!("avx512f") {
+            return;
+        }
+
+        // Cover the unrolled loop, a full vector, and a masked remainder.
+        for &len in &[5, 16, 102] {
+            let u = Array1::random((len,), Uniform::new_inclusive(-1.0, 1.0));
+            let v = Array1::random((len,), Uniform::new_inclusive(-1.0, 1.0));
+            assert!(close(
+                unsafe { avx512::dot(u.view(), v.view()) },
+                dot_unvectorized(u.as_slice().unwrap(), v.as_slice().unwrap()),
+                1e-5
+            ));
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn dot_neon_test() {
+        // Cover the unrolled loop, a single vector, and a scalar remainder.
+        for &len in &[3, 4, 102] {
+            let u = Array1::random((len,), Uniform::new_inclusive(-1.0, 1.0));
+            let v = Array1::random((len,), Uniform::new_inclusive(-1.0, 1.0));
+            assert!(close(
+                unsafe { neon::dot(u.view(), v.view()) },
+                dot_unvectorized(u.as_slice().unwrap(), v.as_slice().unwrap()),
+                1e-5
+            ));
+        }
+    }
+
     #[test]
     fn dot_unvectorized_test() {
         let u = [1f32, -2f32, -3f32];
@@ -474,8 +945,12 @@ mod tests {
     }
 
     #[test]
-    #[cfg(target_feature = "sse")]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn scaled_add_sse_test() {
+        if !is_x86_feature_detected!("sse") {
+            return;
+        }
+
         let mut u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let v = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let mut check = u.clone();
@@ -485,8 +960,12 @@ mod tests {
     }
 
     #[test]
-    #[cfg(target_feature = "avx")]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn scaled_add_avx_test() {
+        if !is_x86_feature_detected!("avx") {
+            return;
+        }
+
         let mut u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let v = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let mut check = u.clone();
@@ -495,6 +974,50 @@ mod tests {
         assert!(array_all_close(check.view(), u.view(), 1e-5));
     }
 
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn scaled_add_avx_fma_test() {
+        if !(is_x86_feature_detected!("avx") && is_x86_feature_detected!("fma")) {
+            return;
+        }
+
+        let mut u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
+        let v = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
+        let mut check = u.clone();
+        scaled_add_unvectorized(check.as_slice_mut().unwrap(), v.as_slice().unwrap(), 2.5);
+        unsafe { avx_fma::scaled_add(u.view_mut(), v.view(), 2.5) };
+        assert!(array_all_close(check.view(), u.view(), 1e-5));
+    }
+
+    #[test]
+    #[cfg(all(feature = "avx512", any(target_arch = "x86", target_arch = "x86_64")))]
+    fn scaled_add_avx512_test() {
+        if !is_x86_feature_detected!("avx512f") {
+            return;
+        }
+
+        // Cover a masked remainder, a full vector, and the loop.
+        for &len in &[5, 16, 102] {
+            let mut u = Array1::random((len,), Uniform::new_inclusive(-1.0, 1.0));
+            let v = Array1::random((len,), Uniform::new_inclusive(-1.0, 1.0));
+            let mut check = u.clone();
+            scaled_add_unvectorized(check.as_slice_mut().unwrap(), v.as_slice().unwrap(), 2.5);
+            unsafe { avx512::scaled_add(u.view_mut(), v.view(), 2.5) };
+            assert!(array_all_close(check.view(), u.view(), 1e-5));
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn scaled_add_neon_test() {
+        let mut u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
+        let v = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
+        let mut check = u.clone();
+        scaled_add_unvectorized(check.as_slice_mut().unwrap(), v.as_slice().unwrap(), 2.5);
+        unsafe { neon::scaled_add(u.view_mut(), v.view(), 2.5) };
+        assert!(array_all_close(check.view(), u.view(), 1e-5));
+    }
+
     #[test]
     fn scale_unvectorized_test() {
         let s = &mut [1., 2., 3., 4., 5.];
@@ -503,8 +1026,12 @@ mod tests {
     }
 
     #[test]
-    #[cfg(target_feature = "sse")]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn scale_sse_test() {
+        if !is_x86_feature_detected!("sse") {
+            return;
+        }
+
         let mut u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let mut check = u.clone();
         scale_unvectorized(check.as_slice_mut().unwrap(), 2.);
@@ -513,8 +1040,12 @@ mod tests {
     }
 
     #[test]
-    #[cfg(target_feature = "avx")]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn scale_avx_test() {
+        if !is_x86_feature_detected!("avx") {
+            return;
+        }
+
         let mut u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
         let mut check = u.clone();
         scale_unvectorized(check.as_slice_mut().unwrap(), 2.);
@@ -522,6 +1053,54 @@ mod tests {
         assert!(array_all_close(check.view(), u.view(), 1e-5));
     }
 
+    #[test]
+    #[cfg(all(feature = "avx512", any(target_arch = "x86", target_arch = "x86_64")))]
+    fn scale_avx512_test() {
+        if !is_x86_feature_detected!("avx512f") {
+            return;
+        }
+
+        // Cover a masked remainder, a full vector, and the loop.
+        for &len in &[5, 16, 102] {
+            let mut u = Array1::random((len,), Uniform::new_inclusive(-1.0, 1.0));
+            let mut check = u.clone();
+            scale_unvectorized(check.as_slice_mut().unwrap(), 2.);
+            unsafe { avx512::scale(u.view_mut(), 2.) };
+            assert!(array_all_close(check.view(), u.view(), 1e-5));
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn scale_neon_test() {
+        let mut u = Array1::random((102,), Uniform::new_inclusive(-1.0, 1.0));
+        let mut check = u.clone();
+        scale_unvectorized(check.as_slice_mut().unwrap(), 2.);
+        unsafe { neon::scale(u.view_mut(), 2.) };
+        assert!(array_all_close(check.view(), u.view(), 1e-5));
+    }
+
+    #[test]
+    fn simd_level_roundtrip() {
+        for &level in &[
+            SimdLevel::None,
+            SimdLevel::Sse,
+            SimdLevel::Avx,
+            SimdLevel::AvxFma,
+            SimdLevel::Neon,
+        ] {
+            assert_eq!(SimdLevel::from_u8(level as u8), level);
+        }
+
+        #[cfg(feature = "avx512")]
+        assert_eq!(
+            SimdLevel::from_u8(SimdLevel::Avx512 as u8),
+            SimdLevel::Avx512
+        );
+
+        assert_eq!(SimdLevel::detect(), SimdLevel::detect_uncached());
+    }
+
     #[test]
     fn l2_normalize_test() {
         let mut u = Array1::from(vec![1., -2., -1., 3., -3., 1.]);
@@ -0,0 +1,216 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io::BufRead;
+
+use anyhow::{bail, Context, Result};
+
+use crate::vocab::bracket;
+
+type Pair = (String, String);
+
+/// Learn byte pair encoding merges.
+///
+/// Merges are learned from bracketed words, which are initially split
+/// into characters. In every step, the most frequent pair of adjacent
+/// symbols is merged into a new symbol. Ties are broken by the
+/// lexicographical order of the pairs. Learning stops after `n_merges`
+/// merges or when no pairs are left.
+pub(crate) fn learn_merges<'a>(
+    words: impl IntoIterator<Item = (&'a str, usize)>,
+    n_merges: usize,
+) -> Vec<Pair> {
+    let mut words = words
+        .into_iter()
+        .map(|(word, count)| (initial_symbols(word), count))
+        .collect::<Vec<_>>();
+
+    let mut pair_counts: HashMap<Pair, usize> = HashMap::new();
+    let mut pair_words: HashMap<Pair, HashSet<usize>> = HashMap::new();
+    for (idx, (symbols, count)) in words.iter().enumerate() {
+        for pair in symbol_pairs(symbols) {
+            *pair_counts.entry(pair.clone()).or_default() += count;
+            pair_words.entry(pair).or_default().insert(idx);
+        }
+    }
+
+    // The heap can contain stale counts, these are skipped when a pair
+    // is popped.
+    let mut heap = pair_counts
+        .iter()
+        .map(|(pair, &count)| (count, Reverse(pair.clone())))
+        .collect::<BinaryHeap<_>>();
+
+    let mut merges = Vec::with_capacity(n_merges);
+    while merges.len() < n_merges {
+        let pair = match heap.pop() {
+            Some((count, Reverse(pair))) => {
+                if count == 0 || pair_counts.get(&pair) != Some(&count) {
+                    continue;
+                }
+                pair
+            }
+            None => break,
+        };
+
+        let mut changed = HashSet::new();
+        for idx in pair_words.remove(&pair).unwrap_or_default() {
+            let (symbols, count) = &mut words[idx];
+
+            for old_pair in symbol_pairs(symbols) {
+                *pair_counts.get_mut(&old_pair).unwrap() -= *count;
+                changed.insert(old_pair);
+            }
+
+            *symbols = merge_pair(symbols, &pair);
+
+            for new_pair in symbol_pairs(symbols) {
+                *pair_counts.entry(new_pair.clone()).or_default() += *count;
+                pair_words.entry(new_pair.clone()).or_default().insert(idx);
+                changed.insert(new_pair);
+            }
+        }
+
+        for changed_pair in changed {
+            let count = pair_counts[&changed_pair];
+            if count == 0 {
+                pair_counts.remove(&changed_pair);
+            } else {
+                heap.push((count, Reverse(changed_pair)));
+            }
+        }
+
+        merges.push(pair);
+    }
+
+    merges
+}
+
+/// Read byte pair encoding merges.
+///
+/// Every line contains a merge, consisting of the two symbols that are
+/// merged separated by a space. Merges should be listed in the order in
+/// which they are applied. Word boundaries are marked by `<` and `>`.
+/// Empty lines and lines starting with `#` are ignored.
+pub fn read_bpe_merges<R>(read: R) -> Result<Vec<(String, String)>>
+where
+    R: BufRead,
+{
+    let mut merges = Vec::new();
+    for (idx, line) in read.lines().enumerate() {
+        let line = line.context("Cannot read merge")?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut symbols = line.split_whitespace();
+        match (symbols.next(), symbols.next(), symbols.next()) {
+            (Some(left), Some(right), None) => merges.push((left.to_owned(), right.to_owned())),
+            _ => bail!("Merge on line {} does not consist of two symbols", idx + 1),
+        }
+    }
+
+    Ok(merges)
+}
+
+/// Segment a word using byte pair encoding merges.
+///
+/// The word is bracketed and split into characters. Then the merge
+/// with the lowest rank that applies to the word is applied, until no
+/// merge applies.
+pub(crate) fn segment(word: &str, ranks: &HashMap<Pair, usize>) -> Vec<String> {
+    let mut symbols = initial_symbols(word);
+
+    loop {
+        let best = symbol_pairs(&symbols)
+            .filter_map(|pair| ranks.get(&pair).map(|&rank| (rank, pair)))
+            .min();
+
+        match best {
+            Some((_, pair)) => symbols = merge_pair(&symbols, &pair),
+            None => break,
+        }
+    }
+
+    symbols
+}
+
+/// Split a bracketed word into characters.
+fn initial_symbols(word: &str) -> Vec<String> {
+    bracket(word).chars().map(|c| c.to_string()).collect()
+}
+
+/// Merge all non-overlapping occurrences of a pair, from left to right.
+fn merge_pair(symbols: &[String], pair: &Pair) -> Vec<String> {
+    let mut merged = Vec::with_capacity(symbols.len());
+    let mut i = 0;
+    while i < symbols.len() {
+        if i + 1 < symbols.len() && symbols[i] == pair.0 && symbols[i + 1] == pair.1 {
+            merged.push(format!("{}{}", pair.0, pair.1));
+            i += 2;
+        } else {
+            merged.push(symbols[i].clone());
+            i += 1;
+        }
+    }
+
+    merged
+}
+
+fn symbol_pairs(symbols: &[String]) -> impl Iterator<Item = Pair> + '_ {
+    symbols
+        .windows(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{learn_merges, read_bpe_merges, segment};
+
+    fn pair(left: &str, right: &str) -> (String, String) {
+        (left.to_owned(), right.to_owned())
+    }
+
+    #[test]
+    fn learns_most_frequent_pairs() {
+        // Ties are broken by the lexicographical order of the pairs.
+        let merges = learn_merges(vec![("ab", 3), ("abc", 1)], 2);
+        assert_eq!(merges, vec![pair("<", "a"), pair("<a", "b")]);
+    }
+
+    #[test]
+    fn stops_without_pairs() {
+        let merges = learn_merges(vec![("ab", 1)], 10);
+        assert_eq!(
+            merges,
+            vec![pair("<", "a"), pair("<a", "b"), pair("<ab", ">")]
+        );
+    }
+
+    #[test]
+    fn segments_by_merge_rank() {
+        let ranks = vec![
+            pair("e", "r"),
+            pair("l", "o"),
+            pair("lo", "w"),
+            pair("er", ">"),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(rank, pair)| (pair, rank))
+        .collect::<HashMap<_, _>>();
+
+        assert_eq!(segment("lower", &ranks), vec!["<", "low", "er>"]);
+        assert_eq!(segment("xy", &ranks), vec!["<", "x", "y", ">"]);
+    }
+
+    #[test]
+    fn reads_merges() {
+        let merges = read_bpe_merges("#version: 0.2\nl o\n\nlo w\n".as_bytes()).unwrap();
+        assert_eq!(merges, vec![pair("l", "o"), pair("lo", "w")]);
+
+        assert!(read_bpe_merges("l o w\n".as_bytes()).is_err());
+    }
+}
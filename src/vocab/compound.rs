@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::iter;
+
+use crate::Word;
+
+/// Default minimum length of compound constituents in characters.
+const DEFAULT_MIN_LEN: usize = 3;
+
+/// Default linking elements, which are common in Germanic compounds.
+const DEFAULT_LINKING_ELEMENTS: [&str; 3] = ["s", "es", "en"];
+
+/// Splitting of compounds into their constituents.
+///
+/// Compound splitters are used to extract the subword units of a
+/// compound from its constituents, rather than from the compound as a
+/// whole.
+pub trait CompoundSplitter {
+    /// Split a word into its constituents.
+    ///
+    /// Returns `None` if the word is not a compound.
+    fn split(&self, word: &str) -> Option<Vec<String>>;
+}
+
+/// Frequency-based compound splitter.
+///
+/// This splitter uses the method of Koehn & Knight (2003): a word is
+/// split into constituents that are known words, choosing the split
+/// with the highest geometric mean of the constituent counts. A word is
+/// only split when this mean is larger than the count of the word
+/// itself. Non-final constituents may be followed by a linking element,
+/// such as the *s* in German *Arbeitszeit*.
+#[derive(Clone, Debug)]
+pub struct FrequencySplitter {
+    counts: HashMap<String, usize>,
+    linking_elements: Vec<String>,
+    min_len: usize,
+}
+
+impl FrequencySplitter {
+    /// Construct a splitter from word counts.
+    pub fn new(counts: HashMap<String, usize>) -> Self {
+        FrequencySplitter {
+            counts,
+            linking_elements: DEFAULT_LINKING_ELEMENTS
+                .iter()
+                .map(|&link| link.to_owned())
+                .collect(),
+            min_len: DEFAULT_MIN_LEN,
+        }
+    }
+
+    /// Construct a splitter from the words of a vocabulary.
+    pub fn from_words(words: &[Word]) -> Self {
+        Self::new(
+            words
+                .iter()
+                .map(|word| (word.word().to_owned(), word.count()))
+                .collect(),
+        )
+    }
+
+    /// Set the linking elements.
+    pub fn with_linking_elements(mut self, linking_elements: Vec<String>) -> Self {
+        self.linking_elements = linking_elements;
+        self
+    }
+
+    /// Set the minimum length of constituents in characters.
+    pub fn with_min_len(mut self, min_len: usize) -> Self {
+        assert!(min_len > 0, "Minimum constituent length should be positive");
+        self.min_len = min_len;
+        self
+    }
+
+    /// Look up a constituent.
+    ///
+    /// Returns the constituent as it occurs in the vocabulary and its
+    /// count. Constituents that are not known are also looked up with
+    /// the first character in uppercase, since the constituents of
+    /// e.g. German compounds are lowercased. Linking elements are
+    /// stripped from non-final constituents.
+    fn constituent(&self, part: &str, is_final: bool) -> Option<(String, usize)> {
+        let candidates = iter::once(part).chain(
+            self.linking_elements
+                .iter()
+                .filter(|_| !is_final)
+                .filter_map(|link| part.strip_suffix(link.as_str())),
+        );
+
+        for candidate in candidates {
+            if candidate.chars().count() < self.min_len {
+                continue;
+            }
+
+            for form in iter::once(candidate.to_owned()).chain(uppercase_first(candidate)) {
+                if let Some(&count) = self.counts.get(&form) {
+                    return Some((form, count));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl CompoundSplitter for FrequencySplitter {
+    fn split(&self, word: &str) -> Option<Vec<String>> {
+        let bounds = word
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .chain(iter::once(word.len()))
+            .collect::<Vec<_>>();
+        let n_chars = bounds.len() - 1;
+        let max_parts = n_chars / self.min_len;
+        if max_parts < 2 {
+            return None;
+        }
+
+        // best[end][k] is the best split of the first end characters into
+        // k constituents, consisting of the sum of the log counts, the
+        // start of the last constituent, and the last constituent.
+        let mut best: Vec<Vec<Option<(f64, usize, String)>>> =
+            vec![vec![None; max_parts + 1]; n_chars + 1];
+        best[0][0] = Some((0., 0, String::new()));
+        for end in self.min_len..=n_chars {
+            for start in 0..=end - self.min_len {
+                let (constituent, count) =
+                    match self.constituent(&word[bounds[start]..bounds[end]], end == n_chars) {
+                        Some(constituent) => constituent,
+                        None => continue,
+                    };
+
+                for k in 1..=max_parts {
+                    let score = match &best[start][k - 1] {
+                        Some((score, _, _)) => score + (count as f64).ln(),
+                        None => continue,
+                    };
+
+                    if best[end][k]
+                        .as_ref()
+                        .map(|(best_score, _, _)| score > *best_score)
+                        .unwrap_or(true)
+                    {
+                        best[end][k] = Some((score, start, constituent.clone()));
+                    }
+                }
+            }
+        }
+
+        // Pick the number of constituents with the highest geometric mean.
+        let (n_parts, mean) = (2..=max_parts)
+            .filter_map(|k| {
+                best[n_chars][k]
+                    .as_ref()
+                    .map(|(score, _, _)| (k, (score / k as f64).exp()))
+            })
+            .max_by(|(_, mean1), (_, mean2)| mean1.partial_cmp(mean2).unwrap())?;
+
+        let word_count = self.counts.get(word).copied().unwrap_or(0);
+        if mean <= word_count as f64 {
+            return None;
+        }
+
+        let mut constituents = Vec::with_capacity(n_parts);
+        let mut end = n_chars;
+        for k in (1..=n_parts).rev() {
+            let (_, start, constituent) = best[end][k].as_ref().unwrap();
+            constituents.push(constituent.clone());
+            end = *start;
+        }
+        constituents.reverse();
+
+        Some(constituents)
+    }
+}
+
+/// Uppercase the first character of a string.
+///
+/// Returns `None` if uppercasing does not change the string.
+fn uppercase_first(s: &str) -> Option<String> {
+    let mut chars = s.chars();
+    let first = chars.next()?;
+    if first.is_uppercase() {
+        return None;
+    }
+
+    let upper = first.to_uppercase().chain(chars).collect::<String>();
+    if upper == s {
+        None
+    } else {
+        Some(upper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use maplit::hashmap;
+
+    use super::{CompoundSplitter, FrequencySplitter};
+
+    fn counts() -> HashMap<String, usize> {
+        hashmap! {
+            "Arbeit".to_owned() => 20,
+            "Zeit".to_owned() => 50,
+            "Haus".to_owned() => 40,
+            "Tür".to_owned() => 10,
+            "Haustür".to_owned() => 2,
+            "Bahnhof".to_owned() => 100,
+            "Bahn".to_owned() => 30,
+            "Hof".to_owned() => 20,
+        }
+    }
+
+    #[test]
+    fn splits_compounds() {
+        let splitter = FrequencySplitter::new(counts());
+        assert_eq!(
+            splitter.split("Haustür"),
+            Some(vec!["Haus".to_owned(), "Tür".to_owned()])
+        );
+        assert_eq!(
+            splitter.split("Haustürzeit"),
+            Some(vec!["Haus".to_owned(), "Tür".to_owned(), "Zeit".to_owned()])
+        );
+    }
+
+    #[test]
+    fn strips_linking_elements() {
+        let splitter = FrequencySplitter::new(counts());
+        assert_eq!(
+            splitter.split("Arbeitszeit"),
+            Some(vec!["Arbeit".to_owned(), "Zeit".to_owned()])
+        );
+        assert_eq!(splitter.split("Zeitarbeits"), None);
+    }
+
+    #[test]
+    fn does_not_split_frequent_words() {
+        let splitter = FrequencySplitter::new(counts());
+        assert_eq!(splitter.split("Bahnhof"), None);
+        assert_eq!(splitter.split("Haus"), None);
+        assert_eq!(splitter.split("Unbekannt"), None);
+    }
+
+    #[test]
+    fn respects_min_len() {
+        let splitter = FrequencySplitter::new(counts()).with_min_len(4);
+        assert_eq!(splitter.split("Haustür"), None);
+    }
+}
@@ -0,0 +1,239 @@
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Context, Result};
+use finalfusion::subword::ExplicitIndexer;
+use serde::Serialize;
+
+use crate::{SubwordVocab, SubwordVocabConfig, Vocab, VocabBuilder};
+
+/// Write the types and counts of a vocabulary.
+///
+/// The vocabulary configuration is written as a header of lines that
+/// start with `#`. The header is followed by a line with the number of
+/// tokens in the corpus and a line per type, consisting of the type and
+/// its count separated by a tab.
+pub fn write_vocab_counts<V, W>(vocab: &V, write: &mut W) -> Result<()>
+where
+    V: Vocab<VocabType = String>,
+    V::Config: Serialize,
+    W: Write,
+{
+    let config =
+        toml::Value::try_from(vocab.config()).context("Cannot serialize vocabulary config")?;
+    for line in config.to_string().lines() {
+        writeln!(write, "# {}", line).context("Cannot write vocabulary header")?;
+    }
+
+    writeln!(write, "{}", vocab.n_types()).context("Cannot write number of tokens")?;
+    for word in vocab.types() {
+        writeln!(write, "{}\t{}", word.word(), word.count())
+            .with_context(|| format!("Cannot write count of: {}", word.word()))?;
+    }
+
+    Ok(())
+}
+
+/// Write the subword units of a vocabulary with their counts and indices.
+///
+/// The vocabulary configuration is written as a header of lines that
+/// start with `#`, as in `write_vocab_counts`. The header is followed by
+/// a line per subword unit, consisting of the unit, its count, and the
+/// index of its embedding separated by tabs.
+pub fn write_subword_counts<C, W>(
+    vocab: &SubwordVocab<C, ExplicitIndexer>,
+    write: &mut W,
+) -> Result<()>
+where
+    C: Copy + Clone,
+    SubwordVocabConfig<C>: Serialize,
+    W: Write,
+{
+    let config =
+        toml::Value::try_from(vocab.config()).context("Cannot serialize vocabulary config")?;
+    for line in config.to_string().lines() {
+        writeln!(write, "# {}", line).context("Cannot write vocabulary header")?;
+    }
+
+    let n_words = vocab.len();
+    for (idx, (unit, count)) in vocab
+        .indexer()
+        .ngrams()
+        .iter()
+        .zip(vocab.subword_counts())
+        .enumerate()
+    {
+        writeln!(write, "{}\t{}\t{}", unit, count, n_words + idx)
+            .with_context(|| format!("Cannot write count of: {}", unit))?;
+    }
+
+    Ok(())
+}
+
+/// Read the types and counts of a vocabulary.
+///
+/// The counts should be in the format written by `write_vocab_counts`.
+/// The header with the vocabulary configuration is skipped, the counts
+/// are read into a builder with the given configuration.
+pub fn read_vocab_counts<C, R>(config: C, read: R) -> Result<VocabBuilder<C, String>>
+where
+    R: BufRead,
+{
+    let mut lines = read.lines().enumerate();
+
+    let n_tokens = loop {
+        match lines.next() {
+            Some((_, line)) => {
+                let line = line.context("Cannot read vocabulary header")?;
+                if !line.starts_with('#') {
+                    break line
+                        .trim()
+                        .parse()
+                        .context("Cannot parse number of tokens")?;
+                }
+            }
+            None => bail!("Vocabulary does not contain the number of tokens"),
+        }
+    };
+
+    let mut counts = Vec::new();
+    for (idx, line) in lines {
+        let line = line.context("Cannot read vocabulary count")?;
+        let mut parts = line.split('\t');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(word), Some(count), None) if !word.is_empty() => {
+                let count = count.parse().with_context(|| {
+                    format!("Cannot parse count on line {}: {}", idx + 1, count)
+                })?;
+                counts.push((word.to_owned(), count))
+            }
+            _ => bail!(
+                "Line {} does not consist of a type and a count separated by a tab",
+                idx + 1
+            ),
+        }
+    }
+
+    Ok(VocabBuilder::from_counts(config, counts, n_tokens))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{read_vocab_counts, write_subword_counts, write_vocab_counts};
+    use crate::{
+        BucketConfig, BucketIndexerType, CountedType, Cutoff, NGramConfig, ScriptNGrams,
+        SimpleVocab, SimpleVocabConfig, SubwordVocab, SubwordVocabConfig, Vocab, VocabBuilder,
+        WordBoundaries,
+    };
+    use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+
+    fn simple_config(cutoff: Cutoff) -> SimpleVocabConfig {
+        SimpleVocabConfig {
+            cutoff,
+            discard_threshold: 1e-4,
+        }
+    }
+
+    #[test]
+    fn vocab_counts_roundtrip() {
+        let mut builder: VocabBuilder<_, String> =
+            VocabBuilder::new(simple_config(Cutoff::MinCount(2)));
+        // Types that start with `#` must not be confused with the header.
+        for token in &["a", "b", "a", "c", "b", "a", "#", "#"] {
+            builder.count(*token);
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let mut data = Vec::new();
+        write_vocab_counts(&vocab, &mut data).unwrap();
+
+        let builder =
+            read_vocab_counts(simple_config(Cutoff::MinCount(2)), Cursor::new(data)).unwrap();
+        let read_vocab: SimpleVocab<String> = builder.into();
+
+        assert_eq!(read_vocab.types(), vocab.types());
+        assert_eq!(read_vocab.len(), 3);
+        assert_eq!(read_vocab.n_types(), 8);
+    }
+
+    #[test]
+    fn vocab_counts_use_given_config() {
+        let config = SubwordVocabConfig {
+            discard_threshold: 1e-4,
+            cutoff: Cutoff::MinCount(1),
+            min_n: 3,
+            include_word: true,
+            script_ngrams: ScriptNGrams::new(),
+            split_compounds: false,
+            boundaries: WordBoundaries::BRACKETS,
+            max_n: 6,
+            indexer: BucketConfig {
+                buckets_exp: 21,
+                indexer_type: BucketIndexerType::Finalfusion,
+            },
+        };
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(config);
+        for token in &["a", "b", "a"] {
+            builder.count(*token);
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        let mut data = Vec::new();
+        write_vocab_counts(&vocab, &mut data).unwrap();
+        assert!(String::from_utf8(data.clone())
+            .unwrap()
+            .contains("# buckets_exp = 21"));
+
+        // Cutoffs of later runs are applied to the stored counts.
+        let builder =
+            read_vocab_counts(simple_config(Cutoff::MinCount(2)), Cursor::new(data)).unwrap();
+        let read_vocab: SimpleVocab<String> = builder.into();
+        assert_eq!(read_vocab.types(), &[CountedType::new("a".to_string(), 2)]);
+        assert_eq!(read_vocab.n_types(), 3);
+    }
+
+    #[test]
+    fn subword_counts_have_embedding_indices() {
+        let config = SubwordVocabConfig {
+            discard_threshold: 1e-4,
+            cutoff: Cutoff::MinCount(1),
+            min_n: 3,
+            include_word: true,
+            script_ngrams: ScriptNGrams::new(),
+            split_compounds: false,
+            boundaries: WordBoundaries::BRACKETS,
+            max_n: 3,
+            indexer: NGramConfig {
+                cutoff: Cutoff::MinCount(3),
+            },
+        };
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(config);
+        for token in &["tot", "to", "tot"] {
+            builder.count(*token);
+        }
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+
+        let mut data = Vec::new();
+        write_subword_counts(&vocab, &mut data).unwrap();
+        let data = String::from_utf8(data).unwrap();
+        assert!(data.contains("# min_n = 3"));
+
+        // Only "<to" survives the cutoff, its embedding follows the
+        // embeddings of the two words.
+        let units = data
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert_eq!(units, &["<to\t3\t2"]);
+    }
+
+    #[test]
+    fn vocab_counts_rejects_malformed_lines() {
+        let data = "3\na\t2\nb 1\n";
+        assert!(read_vocab_counts(simple_config(Cutoff::MinCount(1)), data.as_bytes()).is_err());
+
+        let data = "# header only\n";
+        assert!(read_vocab_counts(simple_config(Cutoff::MinCount(1)), data.as_bytes()).is_err());
+    }
+}
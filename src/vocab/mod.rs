@@ -1,14 +1,21 @@
+pub(crate) mod bpe;
+pub(crate) mod compound;
+pub(crate) mod counts;
+pub(crate) mod sentencepiece;
 pub(crate) mod simple;
 pub(crate) mod subword;
 
 use std::borrow::Borrow;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::mem;
 
 use serde::Serialize;
 use superslice::Ext;
 
 use crate::idx::WordIdx;
+use crate::vocab::subword::BucketStatistics;
 use std::cmp::Reverse;
 
 const BOW: char = '<';
@@ -71,6 +78,15 @@ pub trait Vocab {
     /// Get the number of possible input types.
     fn n_input_types(&self) -> usize;
 
+    /// Get the input index of a word n-gram.
+    ///
+    /// Vocabularies with subword units map word n-grams into the same
+    /// input space as subword units. Other vocabularies do not have
+    /// indices for word n-grams and return `None`.
+    fn word_ngram_idx(&self, _ngram: &str) -> Option<u64> {
+        None
+    }
+
     /// Get all types in the vocabulary.
     fn types(&self) -> &[CountedType<Self::VocabType>];
 
@@ -80,6 +96,34 @@ pub trait Vocab {
     /// was constructed from, **before** removing types that are below the
     /// minimum count.
     fn n_types(&self) -> usize;
+
+    /// Get the bucket statistics of the vocabulary.
+    ///
+    /// Only vocabularies with bucketed subword units for which statistics
+    /// were computed have bucket statistics, other vocabularies return
+    /// `None`.
+    fn bucket_statistics(&self) -> Option<&BucketStatistics> {
+        None
+    }
+}
+
+/// Types of which the heap memory use can be estimated.
+pub trait HeapSize {
+    /// Get the approximate number of bytes that the type allocated on
+    /// the heap.
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for &str {
+    fn heap_size(&self) -> usize {
+        0
+    }
 }
 
 /// Generic builder struct to count types.
@@ -87,31 +131,182 @@ pub trait Vocab {
 /// Items are added to the vocabulary and counted using the `count` method.
 /// There is no explicit build method, conversion is done via implementing
 /// `From<VocabBuilder<T>>`.
+///
+/// By default, all types are counted exactly. For very large corpora,
+/// the number of types that is kept in memory can be bounded with
+/// `with_max_types` or `with_max_memory`, in which case counts are
+/// approximate.
 pub struct VocabBuilder<C, T> {
     config: C,
     items: HashMap<T, usize>,
     n_items: usize,
+    max_types: Option<usize>,
+    max_memory: Option<usize>,
+    memory: usize,
+    min_reduce: usize,
 }
 
 impl<C, T> VocabBuilder<C, T>
 where
-    T: Hash + Eq,
+    T: Hash + Eq + HeapSize,
 {
     pub fn new(config: C) -> Self {
         VocabBuilder {
             config,
             items: HashMap::new(),
             n_items: 0,
+            max_types: None,
+            max_memory: None,
+            memory: 0,
+            min_reduce: 1,
         }
     }
 
+    /// Construct a builder from previously counted items.
+    ///
+    /// `n_items` is the total number of items that were counted, which
+    /// can be larger than the sum of the counts of the given items.
+    pub fn from_counts<I, S>(config: C, counts: I, n_items: usize) -> Self
+    where
+        I: IntoIterator<Item = (S, usize)>,
+        S: Into<T>,
+    {
+        let items: HashMap<T, usize> = counts
+            .into_iter()
+            .map(|(item, count)| (item.into(), count))
+            .collect();
+        let memory = items.keys().map(Self::entry_size).sum();
+
+        VocabBuilder {
+            config,
+            items,
+            n_items,
+            max_types: None,
+            max_memory: None,
+            memory,
+            min_reduce: 1,
+        }
+    }
+
+    /// Bound the number of types that are counted.
+    ///
+    /// When more than `max_types` types are counted, the infrequent types
+    /// are pruned: first the types with a count of 1, then (on the next
+    /// pruning) the types with a count of at most 2, etc. This bounds
+    /// memory use in a single pass over the corpus, at the cost of
+    /// underestimating the counts of types that were pruned. With `None`,
+    /// all types are counted exactly.
+    pub fn with_max_types(mut self, max_types: Option<usize>) -> Self {
+        self.max_types = max_types;
+        self
+    }
+
+    /// Bound the memory use of the counts.
+    ///
+    /// When the estimated memory use of the counted types exceeds
+    /// `max_memory` bytes, the infrequent types are pruned in the same
+    /// manner as with `with_max_types`. The estimate includes the hash
+    /// table entries and the heap memory of the types. With `None`, the
+    /// memory use is not bounded.
+    pub fn with_max_memory(mut self, max_memory: Option<usize>) -> Self {
+        self.max_memory = max_memory;
+        self
+    }
+
     pub fn count<S>(&mut self, item: S)
     where
         S: Into<T>,
     {
         self.n_items += 1;
-        let cnt = self.items.entry(item.into()).or_insert(0);
-        *cnt += 1;
+        match self.items.entry(item.into()) {
+            Entry::Occupied(mut entry) => *entry.get_mut() += 1,
+            Entry::Vacant(entry) => {
+                self.memory += Self::entry_size(entry.key());
+                entry.insert(1);
+            }
+        }
+
+        while self.exceeds_limits() {
+            self.prune();
+        }
+    }
+
+    /// Get the estimated memory use of the counts in bytes.
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Estimate the memory use of the count of a type.
+    ///
+    /// This is the size of a hash table entry, plus one control byte
+    /// per entry, plus the heap memory of the type.
+    fn entry_size(item: &T) -> usize {
+        mem::size_of::<(T, usize)>() + 1 + item.heap_size()
+    }
+
+    /// Returns `true` if the counts exceed the type or memory bounds.
+    fn exceeds_limits(&self) -> bool {
+        self.max_types
+            .map(|max_types| self.items.len() > max_types)
+            .unwrap_or(false)
+            || self
+                .max_memory
+                .map(|max_memory| self.memory > max_memory)
+                .unwrap_or(false)
+    }
+
+    /// Remove the types with the lowest counts.
+    fn prune(&mut self) {
+        let min_reduce = self.min_reduce;
+        self.retain_counts(|_, count| count > min_reduce);
+        self.min_reduce += 1;
+    }
+
+    /// Retain the counts for which the predicate returns `true`.
+    fn retain_counts<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T, usize) -> bool,
+    {
+        let mut freed = 0;
+        self.items.retain(|item, count| {
+            let retain = f(item, *count);
+            if !retain {
+                freed += Self::entry_size(item);
+            }
+            retain
+        });
+        self.memory -= freed;
+    }
+
+    /// Retain only the items for which the predicate returns `true`.
+    ///
+    /// The number of counted items is not changed.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.retain_counts(|item, _| f(item));
+    }
+
+    /// Restrict the vocabulary to the given items.
+    ///
+    /// Items that were not counted are added with a count of zero. The
+    /// number of counted items is not changed, so that frequencies stay
+    /// relative to the full corpus.
+    pub fn restrict<I, S>(&mut self, items: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<T>,
+    {
+        let mut restricted = HashMap::new();
+        for item in items {
+            let item = item.into();
+            let count = self.items.get(&item).cloned().unwrap_or(0);
+            restricted.insert(item, count);
+        }
+
+        self.memory = restricted.keys().map(Self::entry_size).sum();
+        self.items = restricted;
     }
 }
 
@@ -152,6 +347,59 @@ where
     token_indices
 }
 
+/// Get the input indices of the word n-grams in a sequence.
+///
+/// This returns the indices of the word n-grams of length *[2, max_n]*.
+/// Unknown words are included in word n-grams, since n-grams are looked
+/// up by their form.
+pub(crate) fn word_ngram_indices<V, S>(vocab: &V, sequence: &[S], max_n: usize) -> Vec<u64>
+where
+    V: Vocab,
+    S: AsRef<str>,
+{
+    let mut indices = Vec::new();
+    for n in 2..=max_n {
+        for ngram in sequence.windows(n) {
+            if let Some(idx) = vocab.word_ngram_idx(&join_ngram(ngram)) {
+                indices.push(idx);
+            }
+        }
+    }
+
+    indices
+}
+
+/// Get the input indices of the word n-grams that end at a position.
+///
+/// This returns the indices of the word n-grams of length *[2, max_n]*
+/// whose last word is `sequence[end]`.
+pub(crate) fn word_ngram_indices_at<V, S>(
+    vocab: &V,
+    sequence: &[S],
+    end: usize,
+    max_n: usize,
+) -> Vec<u64>
+where
+    V: Vocab,
+    S: AsRef<str>,
+{
+    (2..=max_n.min(end + 1))
+        .filter_map(|n| vocab.word_ngram_idx(&join_ngram(&sequence[end + 1 - n..=end])))
+        .collect()
+}
+
+/// Join the words of a word n-gram.
+fn join_ngram<S>(ngram: &[S]) -> String
+where
+    S: AsRef<str>,
+{
+    ngram
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 /// Add begin/end-of-word brackets.
 pub(crate) fn bracket(word: &str) -> String {
     let mut bracketed = String::new();
@@ -189,6 +437,27 @@ impl Cutoff {
             Cutoff::TargetSize(target_size) => filter_targetsize(items, *target_size),
         }
     }
+
+    /// Get the number of types that are retained by the cutoff.
+    ///
+    /// The types must be sorted by descending count, so that the retained
+    /// types are a prefix of the types.
+    pub(crate) fn n_retained<S>(&self, types: &[CountedType<S>]) -> usize {
+        match self {
+            Cutoff::MinCount(min_count) => {
+                types.upper_bound_by_key(&Reverse(*min_count), |key| Reverse(key.count))
+            }
+            Cutoff::TargetSize(target_size) => {
+                if *target_size >= types.len() {
+                    return types.len();
+                }
+
+                types.lower_bound_by_key(&Reverse(types[*target_size].count), |key| {
+                    Reverse(key.count)
+                })
+            }
+        }
+    }
 }
 
 fn filter_minfreq<T, S>(
@@ -222,7 +491,7 @@ where
         .collect::<Vec<_>>();
     items.sort_unstable_by(|i1, i2| i2.cmp(&i1));
 
-    if target_size > items.len() {
+    if target_size >= items.len() {
         return items;
     }
 
@@ -234,7 +503,102 @@ where
 
 #[cfg(test)]
 mod test {
-    use crate::{Cutoff, Word};
+    use super::word_ngram_indices;
+    use crate::{CountedType, Cutoff, SimpleVocab, SimpleVocabConfig, Vocab, VocabBuilder, Word};
+
+    #[test]
+    pub fn simple_vocab_has_no_word_ngrams() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(SimpleVocabConfig {
+            cutoff: Cutoff::MinCount(1),
+            discard_threshold: 1e-4,
+        });
+        for token in &["a", "b", "c"] {
+            builder.count(*token);
+        }
+        let vocab: SimpleVocab<&str> = builder.into();
+
+        assert!(word_ngram_indices(&vocab, &["a", "b", "c"], 3).is_empty());
+    }
+
+    #[test]
+    pub fn max_types_prunes_infrequent_types() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(SimpleVocabConfig {
+            cutoff: Cutoff::MinCount(1),
+            discard_threshold: 1e-4,
+        })
+        .with_max_types(Some(2));
+        for token in &["a", "a", "b", "c", "a", "d", "d", "e"] {
+            builder.count(*token);
+        }
+        let vocab: SimpleVocab<&str> = builder.into();
+
+        // "b" and "c" are pruned with the types with a count of 1, "d"
+        // and "e" are pruned with the types with a count of at most 2.
+        assert_eq!(vocab.len(), 1);
+        assert_eq!(vocab.types()[0], CountedType::new("a", 3));
+        assert_eq!(vocab.n_types(), 8);
+    }
+
+    #[test]
+    pub fn max_memory_prunes_infrequent_types() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            cutoff: Cutoff::MinCount(1),
+            discard_threshold: 1e-4,
+        });
+        builder.count("a".to_owned());
+        let entry_size = builder.memory();
+        assert!(entry_size > 0);
+
+        let mut builder = builder.with_max_memory(Some(2 * entry_size));
+        for token in &["a", "b", "c", "a", "d", "d", "e"] {
+            builder.count(token.to_string());
+        }
+        assert!(builder.memory() <= 2 * entry_size);
+        let vocab: SimpleVocab<String> = builder.into();
+
+        // "b" and "c" are pruned with the types with a count of 1, "d"
+        // and "e" are pruned with the types with a count of at most 2.
+        assert_eq!(vocab.len(), 1);
+        assert_eq!(vocab.types()[0], CountedType::new("a".to_owned(), 3));
+        assert_eq!(vocab.n_types(), 8);
+    }
+
+    #[test]
+    pub fn restrict_keeps_counts_of_given_items() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(SimpleVocabConfig {
+            cutoff: Cutoff::MinCount(0),
+            discard_threshold: 1e-4,
+        });
+        for token in &["a", "b", "a", "c"] {
+            builder.count(*token);
+        }
+        builder.restrict(vec!["a", "d"]);
+        let vocab: SimpleVocab<&str> = builder.into();
+
+        assert_eq!(vocab.len(), 2);
+        assert_eq!(vocab.types()[0], CountedType::new("a", 2));
+        assert_eq!(vocab.types()[1], CountedType::new("d", 0));
+        assert_eq!(vocab.n_types(), 4);
+    }
+
+    #[test]
+    pub fn n_retained_is_consistent_with_filter() {
+        let items = vec![("a", 10), ("b", 3), ("c", 12), ("d", 5), ("e", 5), ("f", 1)];
+        for cutoff in &[
+            Cutoff::MinCount(0),
+            Cutoff::MinCount(5),
+            Cutoff::MinCount(6),
+            Cutoff::MinCount(13),
+            Cutoff::TargetSize(0),
+            Cutoff::TargetSize(3),
+            Cutoff::TargetSize(4),
+            Cutoff::TargetSize(10),
+        ] {
+            let types: Vec<Word> = Cutoff::MinCount(0).filter(items.clone());
+            let filtered: Vec<Word> = cutoff.filter(items.clone());
+            assert_eq!(cutoff.n_retained(&types), filtered.len(), "{:?}", cutoff);
+        }
+    }
 
     #[test]
     pub fn target_size_unique_counts() {
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::vocab::BOW;
+
+/// The whitespace marker of SentencePiece.
+const SPACE: char = '\u{2581}';
+
+/// Piece types that are used for segmentation.
+const PIECE_NORMAL: u64 = 1;
+const PIECE_USER_DEFINED: u64 = 4;
+
+/// The unigram language model type.
+const MODEL_UNIGRAM: u64 = 1;
+
+/// SentencePiece unigram language model.
+///
+/// Only the pieces and their scores are read from the model, the
+/// normalization rules of the model are not applied.
+#[derive(Clone, Debug)]
+pub struct SentencePieceModel {
+    pieces: HashMap<String, f32>,
+    max_piece_len: usize,
+    unk_score: f32,
+}
+
+impl SentencePieceModel {
+    /// Construct a model from pieces and their scores.
+    pub(crate) fn new(pieces: HashMap<String, f32>) -> Self {
+        let max_piece_len = pieces
+            .keys()
+            .map(|piece| piece.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        // SentencePiece penalizes unknown characters relative to the
+        // lowest piece score.
+        let min_score = pieces.values().cloned().fold(0f32, f32::min);
+
+        SentencePieceModel {
+            pieces,
+            max_piece_len,
+            unk_score: min_score - 10.,
+        }
+    }
+
+    /// Read a SentencePiece model.
+    ///
+    /// The model should be a serialized SentencePiece `ModelProto`, as
+    /// stored by the SentencePiece trainer.
+    pub fn read<R>(mut read: R) -> Result<Self>
+    where
+        R: Read,
+    {
+        let mut data = Vec::new();
+        read.read_to_end(&mut data)
+            .context("Cannot read SentencePiece model")?;
+
+        let mut pieces = HashMap::new();
+        let mut model_type = MODEL_UNIGRAM;
+        let mut fields = ProtoFields::new(&data);
+        while let Some((field, value)) = fields.next_field()? {
+            match (field, value) {
+                (1, ProtoValue::Bytes(piece)) => {
+                    if let Some((piece, score)) = read_piece(piece)? {
+                        pieces.insert(piece, score);
+                    }
+                }
+                (2, ProtoValue::Bytes(trainer_spec)) => model_type = read_model_type(trainer_spec)?,
+                _ => (),
+            }
+        }
+
+        ensure!(
+            model_type == MODEL_UNIGRAM,
+            "Only unigram SentencePiece models are supported"
+        );
+        ensure!(
+            !pieces.is_empty(),
+            "SentencePiece model does not contain pieces"
+        );
+
+        Ok(SentencePieceModel::new(pieces))
+    }
+
+    /// Returns `true` if the model does not have pieces.
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    /// Get the number of pieces.
+    pub fn len(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// Segment a word.
+    ///
+    /// The word is prefixed with the whitespace marker and segmented into
+    /// the pieces with the highest total score. Characters that are not
+    /// covered by any piece are unknown. Unknown characters are not part
+    /// of the segmentation. The whitespace marker is replaced by the
+    /// begin-of-word marker `<` in the returned pieces.
+    pub(crate) fn segment(&self, word: &str) -> Vec<String> {
+        let chars = std::iter::once(SPACE)
+            .chain(word.chars())
+            .collect::<Vec<_>>();
+
+        // The best segmentation of every prefix: its score, the start of
+        // its last piece, and whether the last piece is known.
+        let mut best = vec![(std::f32::NEG_INFINITY, 0, false); chars.len() + 1];
+        best[0] = (0., 0, true);
+
+        for end in 1..=chars.len() {
+            // Fall back to an unknown character.
+            best[end] = (best[end - 1].0 + self.unk_score, end - 1, false);
+
+            for start in end.saturating_sub(self.max_piece_len)..end {
+                let piece = chars[start..end].iter().collect::<String>();
+                if let Some(&score) = self.pieces.get(&piece) {
+                    let score = best[start].0 + score;
+                    if score > best[end].0 {
+                        best[end] = (score, start, true);
+                    }
+                }
+            }
+        }
+
+        let mut segments = Vec::new();
+        let mut end = chars.len();
+        while end > 0 {
+            let (_, start, known) = best[end];
+            if known {
+                segments.push(
+                    chars[start..end]
+                        .iter()
+                        .map(|&c| if c == SPACE { BOW } else { c })
+                        .collect(),
+                );
+            }
+            end = start;
+        }
+        segments.reverse();
+
+        segments
+    }
+}
+
+/// Read a piece and its score from a `SentencePiece` message.
+///
+/// Returns `None` for pieces that are not used for segmentation, such
+/// as control symbols.
+fn read_piece(data: &[u8]) -> Result<Option<(String, f32)>> {
+    let mut piece = None;
+    let mut score = 0.;
+    let mut piece_type = PIECE_NORMAL;
+
+    let mut fields = ProtoFields::new(data);
+    while let Some((field, value)) = fields.next_field()? {
+        match (field, value) {
+            (1, ProtoValue::Bytes(bytes)) => {
+                piece =
+                    Some(String::from_utf8(bytes.to_owned()).context("Piece is not valid UTF-8")?)
+            }
+            (2, ProtoValue::Fixed32(bits)) => score = f32::from_bits(bits),
+            (3, ProtoValue::Varint(value)) => piece_type = value,
+            _ => (),
+        }
+    }
+
+    let piece = piece.context("SentencePiece model contains an empty piece")?;
+    if piece_type == PIECE_NORMAL || piece_type == PIECE_USER_DEFINED {
+        Ok(Some((piece, score)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Read the model type from a `TrainerSpec` message.
+fn read_model_type(data: &[u8]) -> Result<u64> {
+    let mut model_type = MODEL_UNIGRAM;
+    let mut fields = ProtoFields::new(data);
+    while let Some((field, value)) = fields.next_field()? {
+        if let (3, ProtoValue::Varint(value)) = (field, value) {
+            model_type = value;
+        }
+    }
+
+    Ok(model_type)
+}
+
+/// Protocol buffer field value.
+enum ProtoValue<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Reader for the fields of a protocol buffer message.
+struct ProtoFields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ProtoFields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ProtoFields { data }
+    }
+
+    /// Read the next field number and value.
+    fn next_field(&mut self) -> Result<Option<(u64, ProtoValue<'a>)>> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+
+        let key = self.read_varint()?;
+        let value = match key & 0x7 {
+            0 => ProtoValue::Varint(self.read_varint()?),
+            1 => {
+                self.take(8)?;
+                ProtoValue::Fixed64
+            }
+            2 => {
+                let len = self.read_varint()? as usize;
+                ProtoValue::Bytes(self.take(len)?)
+            }
+            5 => {
+                let bytes = self.take(4)?;
+                ProtoValue::Fixed32(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+            wire_type => bail!("Unsupported protocol buffer wire type: {}", wire_type),
+        };
+
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let data = self.data;
+            let (&byte, rest) = data
+                .split_first()
+                .context("Truncated protocol buffer varint")?;
+            self.data = rest;
+
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        bail!("Protocol buffer varint is too long")
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(len <= self.data.len(), "Truncated protocol buffer field");
+        let data = self.data;
+        let (value, rest) = data.split_at(len);
+        self.data = rest;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SentencePieceModel;
+
+    fn varint_field(field: u8, value: u8) -> Vec<u8> {
+        vec![field << 3, value]
+    }
+
+    fn bytes_field(field: u8, bytes: &[u8]) -> Vec<u8> {
+        let mut data = vec![field << 3 | 2, bytes.len() as u8];
+        data.extend_from_slice(bytes);
+        data
+    }
+
+    fn piece(piece: &str, score: f32, piece_type: u8) -> Vec<u8> {
+        let mut data = bytes_field(1, piece.as_bytes());
+        data.push(2 << 3 | 5);
+        data.extend_from_slice(&score.to_le_bytes());
+        data.extend(varint_field(3, piece_type));
+        bytes_field(1, &data)
+    }
+
+    fn test_model() -> Vec<u8> {
+        let mut data = piece("<unk>", 0., 2);
+        for &(p, score) in &[
+            ("\u{2581}", -2.),
+            ("\u{2581}low", -3.),
+            ("\u{2581}lower", -6.),
+            ("er", -2.),
+            ("l", -4.),
+            ("o", -4.),
+            ("w", -4.),
+            ("e", -4.),
+            ("r", -4.),
+        ] {
+            data.extend(piece(p, score, 1));
+        }
+        data
+    }
+
+    #[test]
+    fn reads_unigram_model() {
+        let model = SentencePieceModel::read(test_model().as_slice()).unwrap();
+
+        // The unknown piece is not used for segmentation.
+        assert_eq!(model.len(), 9);
+    }
+
+    #[test]
+    fn rejects_bpe_model() {
+        let mut data = test_model();
+        data.extend(bytes_field(2, &varint_field(3, 2)));
+        assert!(SentencePieceModel::read(data.as_slice()).is_err());
+    }
+
+    #[test]
+    fn segments_with_highest_score() {
+        let model = SentencePieceModel::read(test_model().as_slice()).unwrap();
+
+        // "▁low" + "er" (-5) is preferred over "▁lower" (-6).
+        assert_eq!(model.segment("lower"), vec!["<low", "er"]);
+
+        // Unknown characters are not part of the segmentation.
+        assert_eq!(model.segment("lowx"), vec!["<low"]);
+        assert_eq!(model.segment("re"), vec!["<", "r", "e"]);
+    }
+}
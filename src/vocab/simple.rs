@@ -18,6 +18,7 @@ pub struct SimpleVocab<T> {
     index: HashMap<T, usize>,
     n_types: usize,
     discards: Vec<f32>,
+    unk: Option<usize>,
 }
 
 impl<T> SimpleVocab<T>
@@ -38,9 +39,32 @@ where
             index,
             n_types,
             discards,
+            unk: None,
         }
     }
 
+    /// Map unknown types to a trainable unknown type.
+    ///
+    /// `unk` is added to the vocabulary with the number of tokens that
+    /// are not in the vocabulary as its count. If the vocabulary already
+    /// contains `unk`, this number is added to its count instead. Lookups
+    /// of types that are not in the vocabulary return the index of `unk`.
+    pub fn with_unk(self, unk: T) -> Self {
+        let n_known: usize = self.types.iter().map(CountedType::count).sum();
+        let n_unknown = self.n_types.saturating_sub(n_known);
+
+        let mut types = self.types;
+        match types.iter_mut().find(|t| t.label == unk) {
+            Some(t) => t.count += n_unknown,
+            None => types.push(CountedType::new(unk.clone(), n_unknown)),
+        }
+        types.sort_unstable_by(|t1, t2| t2.cmp(&t1));
+
+        let mut vocab = SimpleVocab::new(self.config, types, self.n_types);
+        vocab.unk = vocab.index.get(&unk).cloned();
+        vocab
+    }
+
     /// Get a specific context
     pub fn get<Q>(&self, context: &Q) -> Option<&CountedType<T>>
     where
@@ -85,6 +109,7 @@ where
         self.index
             .get(key)
             .cloned()
+            .or(self.unk)
             .map(|idx| SingleIdx::from_word_idx(idx as u64))
     }
 
@@ -184,4 +209,55 @@ mod tests {
             1e-5
         ));
     }
+
+    #[test]
+    pub fn unknown_types_map_to_unk() {
+        let mut builder: VocabBuilder<SimpleVocabConfig, &str> =
+            VocabBuilder::new(TEST_SIMPLECONFIG);
+        for _ in 0..5 {
+            builder.count("a");
+        }
+        for _ in 0..3 {
+            builder.count("b");
+        }
+        builder.count("c");
+        builder.count("d");
+
+        let vocab: SimpleVocab<&str> = builder.into();
+        assert!(vocab.idx("c").is_none());
+
+        let vocab = vocab.with_unk("<unk>");
+        assert_eq!(vocab.len(), 3);
+        assert_eq!(vocab.n_types(), 10);
+
+        let unk = vocab.idx("<unk>").unwrap().word_idx();
+        assert_eq!(vocab.types()[unk as usize].count(), 2);
+        assert_eq!(vocab.idx("c").unwrap().word_idx(), unk);
+        assert_eq!(vocab.idx("e").unwrap().word_idx(), unk);
+        assert_ne!(vocab.idx("a").unwrap().word_idx(), unk);
+
+        // Types are still sorted by frequency.
+        assert_eq!(vocab.types()[0].label, "a");
+        assert_eq!(vocab.types()[1].label, "b");
+        assert_eq!(vocab.types()[2].label, "<unk>");
+    }
+
+    #[test]
+    pub fn existing_unk_type_is_reused() {
+        let mut builder: VocabBuilder<SimpleVocabConfig, &str> =
+            VocabBuilder::new(TEST_SIMPLECONFIG);
+        for _ in 0..3 {
+            builder.count("<unk>");
+        }
+        builder.count("c");
+
+        let vocab: SimpleVocab<&str> = builder.into();
+        let vocab = vocab.with_unk("<unk>");
+        assert_eq!(vocab.len(), 1);
+        assert_eq!(vocab.types()[0].count(), 4);
+        assert_eq!(
+            vocab.idx("c").unwrap().word_idx(),
+            vocab.idx("<unk>").unwrap().word_idx()
+        );
+    }
 }
@@ -1,18 +1,26 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
+use std::io::BufRead;
+
+use anyhow::{ensure, Context, Result};
 
 use finalfusion::compat::fasttext::FastTextIndexer;
 use finalfusion::subword::{
-    BucketIndexer, ExplicitIndexer, FinalfusionHashIndexer, Indexer, NGrams, SubwordIndices,
+    BucketIndexer, ExplicitIndexer, FinalfusionHashIndexer, Indexer, NGrams, StrWithCharLen,
+    SubwordIndices,
 };
 use finalfusion::vocab::{SubwordVocab as FiFuSubwordVocab, VocabWrap};
+use serde::Serialize;
 
 use crate::idx::{WordIdx, WordWithSubwordsIdx};
-use crate::vocab::{bracket, create_discards, create_indices};
+use crate::vocab::bpe::{learn_merges, segment};
+use crate::vocab::compound::CompoundSplitter;
+use crate::vocab::sentencepiece::SentencePieceModel;
+use crate::vocab::{create_discards, create_indices};
 use crate::{
-    BucketConfig, BucketIndexerType, CountedType, NGramConfig, SubwordVocabConfig, Vocab,
-    VocabBuilder, Word,
+    BpeConfig, BucketConfig, BucketIndexerType, CountedType, Cutoff, NGramConfig,
+    SentencePieceConfig, SubwordVocabConfig, Vocab, VocabBuilder, Word,
 };
 
 /// A corpus vocabulary with subword lookup.
@@ -25,6 +33,41 @@ pub struct SubwordVocab<C, I> {
     discards: Vec<f32>,
     index: HashMap<String, usize>,
     n_tokens: usize,
+    bucket_statistics: Option<BucketStatistics>,
+}
+
+/// N-grams that are mapped to the same bucket.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct BucketCollision {
+    /// The bucket.
+    pub bucket: u64,
+
+    /// The n-grams that are mapped to the bucket, sorted.
+    pub ngrams: Vec<String>,
+}
+
+/// Statistics of the mapping of n-grams to buckets.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct BucketStatistics {
+    /// The number of distinct n-grams of the words in the vocabulary.
+    pub n_ngrams: usize,
+
+    /// The number of buckets.
+    pub n_buckets: u64,
+
+    /// The number of buckets that at least one n-gram is mapped to.
+    pub n_used_buckets: usize,
+
+    /// Histogram of the number of n-grams per bucket.
+    ///
+    /// Element *i* is the number of buckets that *i* n-grams are mapped
+    /// to.
+    pub histogram: Vec<u64>,
+
+    /// The buckets with the most n-grams, in descending order.
+    ///
+    /// Only buckets that more than one n-gram is mapped to are included.
+    pub worst_collisions: Vec<BucketCollision>,
 }
 
 impl<C, I> SubwordVocab<C, I>
@@ -39,13 +82,20 @@ where
         n_tokens: usize,
         indexer: I,
     ) -> Self {
+        let subwords = Self::create_subword_indices(&config, &indexer, &words);
+        Self::from_parts(config, words, n_tokens, indexer, subwords)
+    }
+
+    /// Construct a new vocabulary from precomputed subword indices.
+    fn from_parts(
+        config: SubwordVocabConfig<C>,
+        words: Vec<Word>,
+        n_tokens: usize,
+        indexer: I,
+        subwords: Vec<Vec<u64>>,
+    ) -> Self {
+        assert_eq!(words.len(), subwords.len());
         let index = create_indices(&words);
-        let subwords = Self::create_subword_indices(
-            config.min_n as usize,
-            config.max_n as usize,
-            &indexer,
-            &words,
-        );
         let discards = create_discards(config.discard_threshold, &words, n_tokens);
         SubwordVocab {
             config,
@@ -55,20 +105,23 @@ where
             discards,
             index,
             n_tokens,
+            bucket_statistics: None,
         }
     }
 
     fn create_subword_indices(
-        min_n: usize,
-        max_n: usize,
+        config: &SubwordVocabConfig<C>,
         indexer: &I,
         words: &[Word],
     ) -> Vec<Vec<u64>> {
         let mut subword_indices = Vec::new();
 
         for word in words {
+            let (min_n, max_n) = config.ngram_lengths(word.word());
             subword_indices.push(
-                bracket(word.word())
+                config
+                    .boundaries
+                    .bracket(word.word())
                     .as_str()
                     .subword_indices(min_n, max_n, indexer)
                     .map(|idx| idx + words.len() as u64)
@@ -81,14 +134,79 @@ where
         subword_indices
     }
 
+    /// Extract the subword units of compounds from their constituents.
+    ///
+    /// The subword units of a word that is split by `splitter` are
+    /// replaced by the n-grams of its constituents. N-grams that are
+    /// not known to the indexer are ignored. Returns the vocabulary and
+    /// the number of words that were split.
+    pub fn split_compounds<S>(mut self, splitter: &S) -> (Self, usize)
+    where
+        S: CompoundSplitter,
+    {
+        let n_words = self.words.len() as u64;
+        let config = &self.config;
+        let indexer = &self.indexer;
+        let mut n_compounds = 0;
+        for (word, subwords) in self.words.iter().zip(self.subwords.iter_mut()) {
+            let constituents = match splitter.split(word.word()) {
+                Some(constituents) => constituents,
+                None => continue,
+            };
+
+            *subwords = constituents
+                .iter()
+                .flat_map(|constituent| {
+                    let (min_n, max_n) = config.ngram_lengths(constituent);
+                    config
+                        .boundaries
+                        .bracket(constituent)
+                        .as_str()
+                        .subword_indices(min_n, max_n, indexer)
+                        .map(|idx| idx + n_words)
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            n_compounds += 1;
+        }
+
+        if n_compounds > 0 {
+            self.config.split_compounds = true;
+        }
+
+        (self, n_compounds)
+    }
+
     /// Get the given word.
     pub fn word(&self, word: &str) -> Option<&Word> {
         self.idx(word)
             .map(|idx| &self.words[idx.word_idx() as usize])
     }
+
+    /// Replace the subword units of words by their segmentations.
+    ///
+    /// Segments that are not known to the indexer are ignored.
+    fn set_segmentations(&mut self, segmentations: &HashMap<String, Vec<String>>) {
+        let n_words = self.words.len() as u64;
+        let indexer = &self.indexer;
+        for (word, subwords) in self.words.iter().zip(self.subwords.iter_mut()) {
+            if let Some(segments) = segmentations.get(word.word()) {
+                *subwords = segments
+                    .iter()
+                    .filter_map(|segment| indexer.index_ngram(&StrWithCharLen::new(segment)))
+                    .map(|idx| idx + n_words)
+                    .collect();
+            }
+        }
+    }
 }
 
 impl<C, I> SubwordVocab<C, I> {
+    /// Get the subword indexer.
+    pub fn indexer(&self) -> &I {
+        &self.indexer
+    }
+
     pub(crate) fn subword_indices_idx(&self, idx: usize) -> Option<&[u64]> {
         self.subwords.get(idx).map(|v| v.as_slice())
     }
@@ -113,8 +231,9 @@ where
         Q: Hash + ?Sized + Eq,
     {
         self.index.get(key).and_then(|idx| {
-            self.subword_indices_idx(*idx)
-                .map(|v| WordWithSubwordsIdx::new(*idx as u64, v))
+            self.subword_indices_idx(*idx).map(|v| {
+                WordWithSubwordsIdx::new(*idx as u64, v).with_word(self.config.include_word)
+            })
         })
     }
 
@@ -126,6 +245,12 @@ where
         self.len() + self.indexer.upper_bound() as usize
     }
 
+    fn word_ngram_idx(&self, ngram: &str) -> Option<u64> {
+        self.indexer
+            .index_ngram(&StrWithCharLen::new(ngram))
+            .map(|idx| idx + self.words.len() as u64)
+    }
+
     fn types(&self) -> &[Word] {
         &self.words
     }
@@ -133,6 +258,138 @@ where
     fn n_types(&self) -> usize {
         self.n_tokens
     }
+
+    fn bucket_statistics(&self) -> Option<&BucketStatistics> {
+        self.bucket_statistics.as_ref()
+    }
+}
+
+impl<I> SubwordVocab<BucketConfig, I>
+where
+    I: Indexer,
+{
+    /// Compute statistics of the mapping of n-grams to buckets.
+    ///
+    /// The statistics are computed over the distinct n-grams of the
+    /// words in the vocabulary. At most `n_worst` buckets with the most
+    /// n-grams are reported with their n-grams.
+    pub fn compute_bucket_statistics(&self, n_worst: usize) -> BucketStatistics {
+        let mut ngram_buckets: HashMap<String, u64> = HashMap::new();
+        for word in &self.words {
+            let (min_n, max_n) = self.config.ngram_lengths(word.word());
+            for ngram in NGrams::new(&self.config.boundaries.bracket(word.word()), min_n, max_n)
+                .map(|ngram| ngram.to_string())
+            {
+                if ngram_buckets.contains_key(&ngram) {
+                    continue;
+                }
+
+                if let Some(bucket) = self.indexer.index_ngram(&StrWithCharLen::new(&ngram)) {
+                    ngram_buckets.insert(ngram, bucket);
+                }
+            }
+        }
+
+        let n_ngrams = ngram_buckets.len();
+        let mut bucket_ngrams: HashMap<u64, Vec<String>> = HashMap::new();
+        for (ngram, bucket) in ngram_buckets {
+            bucket_ngrams.entry(bucket).or_default().push(ngram);
+        }
+
+        let n_buckets = self.indexer.upper_bound();
+        let n_used_buckets = bucket_ngrams.len();
+        let max_ngrams = bucket_ngrams.values().map(Vec::len).max().unwrap_or(0);
+        let mut histogram = vec![0; max_ngrams + 1];
+        histogram[0] = n_buckets - n_used_buckets as u64;
+        for ngrams in bucket_ngrams.values() {
+            histogram[ngrams.len()] += 1;
+        }
+
+        let mut collisions = bucket_ngrams
+            .into_iter()
+            .filter(|(_, ngrams)| ngrams.len() > 1)
+            .collect::<Vec<_>>();
+        collisions.sort_unstable_by(|(bucket1, ngrams1), (bucket2, ngrams2)| {
+            ngrams2.len().cmp(&ngrams1.len()).then(bucket1.cmp(bucket2))
+        });
+        collisions.truncate(n_worst);
+
+        BucketStatistics {
+            n_ngrams,
+            n_buckets,
+            n_used_buckets,
+            histogram,
+            worst_collisions: collisions
+                .into_iter()
+                .map(|(bucket, mut ngrams)| {
+                    ngrams.sort_unstable();
+                    BucketCollision { bucket, ngrams }
+                })
+                .collect(),
+        }
+    }
+
+    /// Compute bucket statistics and store them in the vocabulary.
+    ///
+    /// The stored statistics are included in the metadata of trained
+    /// models. See `compute_bucket_statistics` for a description of
+    /// `n_worst`.
+    pub fn with_bucket_statistics(mut self, n_worst: usize) -> Self {
+        self.bucket_statistics = Some(self.compute_bucket_statistics(n_worst));
+        self
+    }
+
+    /// Replace the hashed n-grams by the most used n-grams.
+    ///
+    /// The usage of an n-gram is the sum of the counts of the words that
+    /// it occurs in. Only the `max_ngrams` n-grams with the highest usage
+    /// are retained, which are then indexed explicitly. Since the n-grams
+    /// that are retained are typically far fewer than the buckets, this
+    /// shrinks the subword embedding matrix.
+    pub fn prune_ngrams(self, max_ngrams: usize) -> SubwordVocab<NGramConfig, ExplicitIndexer> {
+        let config = SubwordVocabConfig {
+            cutoff: self.config.cutoff,
+            discard_threshold: self.config.discard_threshold,
+            min_n: self.config.min_n,
+            include_word: self.config.include_word,
+            max_n: self.config.max_n,
+            script_ngrams: self.config.script_ngrams,
+            split_compounds: self.config.split_compounds,
+            boundaries: self.config.boundaries,
+            indexer: NGramConfig {
+                cutoff: Cutoff::TargetSize(max_ngrams),
+            },
+        };
+
+        let ngrams = explicit_ngrams(&config, &self.words);
+        SubwordVocab::new(config, self.words, self.n_tokens, ngrams)
+    }
+}
+
+/// Create an indexer for the n-grams of the given words.
+///
+/// The n-grams are selected using the n-gram cutoff of the
+/// configuration, based on the sum of the counts of the words that an
+/// n-gram occurs in.
+fn explicit_ngrams(config: &SubwordVocabConfig<NGramConfig>, words: &[Word]) -> ExplicitIndexer {
+    let mut ngram_counts: HashMap<String, usize> = HashMap::new();
+    for word in words {
+        let (min_n, max_n) = config.ngram_lengths(word.label());
+        for ngram in NGrams::new(&config.boundaries.bracket(word.label()), min_n, max_n)
+            .map(|ngram| ngram.to_string())
+        {
+            let cnt = ngram_counts.entry(ngram).or_default();
+            *cnt += word.count;
+        }
+    }
+
+    let ngrams: Vec<CountedType<String>> = config.indexer.cutoff.filter(ngram_counts);
+    ExplicitIndexer::new(
+        ngrams
+            .into_iter()
+            .map(|counted| counted.label)
+            .collect::<Vec<_>>(),
+    )
 }
 
 /// Constructs a `SubwordVocab` from a `VocabBuilder<T>` where `T: Into<String>`.
@@ -161,40 +418,294 @@ where
     fn from(builder: VocabBuilder<SubwordVocabConfig<NGramConfig>, T>) -> Self {
         let config = builder.config;
         let words: Vec<Word> = builder.config.cutoff.filter(builder.items);
-        let mut ngram_counts: HashMap<String, usize> = HashMap::new();
-        for word in words.iter() {
-            for ngram in NGrams::new(
-                &bracket(word.label()),
-                config.min_n as usize,
-                config.max_n as usize,
-            )
-            .map(|ngram| ngram.to_string())
-            {
-                let cnt = ngram_counts.entry(ngram).or_default();
-                *cnt += word.count;
+        let ngrams = explicit_ngrams(&config, &words);
+        SubwordVocab::new(config, words, builder.n_items, ngrams)
+    }
+}
+
+/// Constructs a `SubwordVocab` from a `VocabBuilder<T>` where `T: Into<String>`.
+///
+/// The byte pair encoding merges are learned from the words that are
+/// retained after applying the cutoff.
+impl<T> From<VocabBuilder<SubwordVocabConfig<BpeConfig>, T>>
+    for SubwordVocab<BpeConfig, ExplicitIndexer>
+where
+    T: Hash + Eq + Into<String>,
+{
+    fn from(builder: VocabBuilder<SubwordVocabConfig<BpeConfig>, T>) -> Self {
+        let config = builder.config;
+        let words: Vec<Word> = config.cutoff.filter(builder.items);
+        let merges = learn_merges(
+            words.iter().map(|word| (word.word(), word.count)),
+            config.indexer.merges as usize,
+        );
+        SubwordVocab::new_bpe(config, words, builder.n_items, &merges)
+    }
+}
+
+impl<C> SubwordVocab<C, ExplicitIndexer>
+where
+    C: Copy + Clone,
+{
+    /// Construct a vocabulary from segmentations of the words.
+    ///
+    /// The subword units are the segments of the words. Units are
+    /// indexed in order of descending frequency.
+    fn from_segments(
+        config: SubwordVocabConfig<C>,
+        words: Vec<Word>,
+        n_tokens: usize,
+        segments: Vec<Vec<String>>,
+    ) -> Self {
+        let mut unit_counts: HashMap<String, usize> = HashMap::new();
+        for (word, word_segments) in words.iter().zip(&segments) {
+            for unit in word_segments {
+                *unit_counts.entry(unit.clone()).or_default() += word.count;
             }
         }
+        let units: Vec<CountedType<String>> = Cutoff::MinCount(1).filter(unit_counts);
+        let indexer = ExplicitIndexer::new(
+            units
+                .into_iter()
+                .map(|counted| counted.label)
+                .collect::<Vec<_>>(),
+        );
 
-        let ngrams: Vec<CountedType<String>> = config.indexer.cutoff.filter(ngram_counts);
-        let ngrams = ngrams
-            .into_iter()
-            .map(|counted| counted.label)
+        let subwords = segments
+            .iter()
+            .map(|word_segments| {
+                word_segments
+                    .iter()
+                    .map(|unit| {
+                        indexer
+                            .index_ngram(&StrWithCharLen::new(unit))
+                            .expect("Unknown subword unit")
+                            + words.len() as u64
+                    })
+                    .collect()
+            })
+            .collect();
+
+        SubwordVocab::from_parts(config, words, n_tokens, indexer, subwords)
+    }
+}
+
+impl<C> SubwordVocab<C, ExplicitIndexer>
+where
+    C: Copy + Clone,
+{
+    /// Replace the subword units of words by their segmentations.
+    ///
+    /// The subword units of a word that has a segmentation are replaced
+    /// by the segments of the word. Segments that are not subword units
+    /// of the vocabulary are added as subword units.
+    pub fn with_segmentations(mut self, segmentations: &HashMap<String, Vec<String>>) -> Self {
+        // New units are appended, so that existing units retain their
+        // indices.
+        let mut units = self.indexer.ngrams().to_vec();
+        let mut known = units.iter().cloned().collect::<HashSet<_>>();
+        for word in &self.words {
+            for segment in segmentations.get(word.word()).into_iter().flatten() {
+                if known.insert(segment.clone()) {
+                    units.push(segment.clone());
+                }
+            }
+        }
+        self.indexer = ExplicitIndexer::new(units);
+
+        self.set_segmentations(segmentations);
+        self
+    }
+
+    /// Get the counts of the subword units.
+    ///
+    /// The count of a unit is the sum of the counts of the words that it
+    /// occurs in, counting every occurrence. Counts are in the order of
+    /// the units of the indexer. The embedding of the unit at position
+    /// `i` has index `i + len`, where `len` is the number of words.
+    pub fn subword_counts(&self) -> Vec<usize> {
+        let n_words = self.words.len() as u64;
+        let mut counts = vec![0; self.indexer.ngrams().len()];
+        for (word, subwords) in self.words.iter().zip(&self.subwords) {
+            for &idx in subwords {
+                counts[(idx - n_words) as usize] += word.count();
+            }
+        }
+
+        counts
+    }
+
+    /// Prune the subword units by usage.
+    ///
+    /// The usage of a unit is its count, as returned by `subword_counts`.
+    /// Only the `max_units` units with the highest usage are retained.
+    /// When units with the same usage straddle the boundary, they are all
+    /// removed. The retained units are reindexed, so that the subword
+    /// embedding matrix shrinks accordingly.
+    pub fn prune_subwords(self, max_units: usize) -> Self {
+        let units = self.indexer.ngrams();
+        let retained: Vec<CountedType<String>> =
+            Cutoff::TargetSize(max_units).filter(units.iter().cloned().zip(self.subword_counts()));
+        let indexer = ExplicitIndexer::new(
+            retained
+                .into_iter()
+                .map(|counted| counted.label)
+                .collect::<Vec<_>>(),
+        );
+
+        let n_words = self.words.len() as u64;
+        let remap = units
+            .iter()
+            .map(|unit| indexer.index_ngram(&StrWithCharLen::new(unit)))
             .collect::<Vec<_>>();
-        SubwordVocab::new(config, words, builder.n_items, ExplicitIndexer::new(ngrams))
+        let subwords = self
+            .subwords
+            .iter()
+            .map(|word_subwords| {
+                word_subwords
+                    .iter()
+                    .filter_map(|&idx| remap[(idx - n_words) as usize])
+                    .map(|idx| idx + n_words)
+                    .collect()
+            })
+            .collect();
+
+        SubwordVocab::from_parts(self.config, self.words, self.n_tokens, indexer, subwords)
+    }
+}
+
+impl SubwordVocab<BpeConfig, ExplicitIndexer> {
+    /// Construct a vocabulary with byte pair encoding subword units.
+    fn new_bpe(
+        config: SubwordVocabConfig<BpeConfig>,
+        words: Vec<Word>,
+        n_tokens: usize,
+        merges: &[(String, String)],
+    ) -> Self {
+        let ranks = merges
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(rank, merge)| (merge, rank))
+            .collect::<HashMap<_, _>>();
+
+        let segments = words
+            .iter()
+            .map(|word| segment(word.word(), &ranks))
+            .collect();
+
+        SubwordVocab::from_segments(config, words, n_tokens, segments)
+    }
+
+    /// Replace the byte pair encoding merges.
+    ///
+    /// The words of the vocabulary are segmented using the given merges,
+    /// rather than the merges that were learned from the vocabulary.
+    pub fn with_merges(self, merges: &[(String, String)]) -> Self {
+        let mut config = self.config;
+        config.indexer.merges = merges.len() as u32;
+        SubwordVocab::new_bpe(config, self.words, self.n_tokens, merges)
+    }
+}
+
+/// Constructs a `SubwordVocab` from a `VocabBuilder<T>` where `T: Into<String>`.
+///
+/// The words are not segmented until a SentencePiece model is provided
+/// with `SubwordVocab::with_model`.
+impl<T> From<VocabBuilder<SubwordVocabConfig<SentencePieceConfig>, T>>
+    for SubwordVocab<SentencePieceConfig, ExplicitIndexer>
+where
+    T: Hash + Eq + Into<String>,
+{
+    fn from(builder: VocabBuilder<SubwordVocabConfig<SentencePieceConfig>, T>) -> Self {
+        let config = builder.config;
+        let words: Vec<Word> = config.cutoff.filter(builder.items);
+        let segments = vec![Vec::new(); words.len()];
+        SubwordVocab::from_segments(config, words, builder.n_items, segments)
     }
 }
 
+impl SubwordVocab<SentencePieceConfig, ExplicitIndexer> {
+    /// Segment the words of the vocabulary with a SentencePiece model.
+    ///
+    /// The pieces of the unigram language model segmentation of a word
+    /// are used as its subword units.
+    pub fn with_model(self, model: &SentencePieceModel) -> Self {
+        let mut config = self.config;
+        config.indexer.pieces = model.len() as u32;
+        let segments = self
+            .words
+            .iter()
+            .map(|word| model.segment(word.word()))
+            .collect();
+        SubwordVocab::from_segments(config, self.words, self.n_tokens, segments)
+    }
+}
+
+macro_rules! impl_with_segmentations (
+    ($vocab:ty) => {
+        impl $vocab {
+            /// Replace the subword units of words by their segmentations.
+            ///
+            /// The subword units of a word that has a segmentation are
+            /// replaced by the hashed segments of the word.
+            pub fn with_segmentations(
+                mut self,
+                segmentations: &HashMap<String, Vec<String>>,
+            ) -> Self {
+                self.set_segmentations(segmentations);
+                self
+            }
+        }
+    }
+);
+
+impl_with_segmentations!(SubwordVocab<BucketConfig, FinalfusionHashIndexer>);
+impl_with_segmentations!(SubwordVocab<BucketConfig, FastTextIndexer>);
+
+/// Read word segmentations.
+///
+/// Every line contains a word, followed by the segments of the word. The
+/// word and its segments are separated by whitespace. Empty lines and
+/// lines starting with `#` are ignored.
+pub fn read_segmentations<R>(read: R) -> Result<HashMap<String, Vec<String>>>
+where
+    R: BufRead,
+{
+    let mut segmentations = HashMap::new();
+    for (idx, line) in read.lines().enumerate() {
+        let line = line.context("Cannot read segmentation")?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace().map(ToOwned::to_owned);
+        let word = parts.next().unwrap();
+        let segments = parts.collect::<Vec<_>>();
+        ensure!(
+            !segments.is_empty(),
+            "Segmentation on line {} does not have segments",
+            idx + 1
+        );
+
+        segmentations.insert(word, segments);
+    }
+
+    Ok(segmentations)
+}
+
 macro_rules! impl_into_vocabwrap (
     ($vocab:ty) => {
         impl From<$vocab> for VocabWrap {
             fn from(vocab: $vocab) -> Self {
-                let config = vocab.config;
+                let (min_n, max_n) = vocab.config.ngram_bounds();
                 let words = vocab
                     .words
                     .into_iter()
                     .map(|word| word.label)
                     .collect::<Vec<_>>();
-                FiFuSubwordVocab::new(words, config.min_n, config.max_n, vocab.indexer).into()
+                FiFuSubwordVocab::new(words, min_n, max_n, vocab.indexer).into()
             }
         }
     }
@@ -204,12 +715,51 @@ impl_into_vocabwrap!(SubwordVocab<BucketConfig, FinalfusionHashIndexer>);
 impl_into_vocabwrap!(SubwordVocab<BucketConfig, FastTextIndexer>);
 impl_into_vocabwrap!(SubwordVocab<NGramConfig, ExplicitIndexer>);
 
+/// Subword units of explicit segmentations are stored as n-grams.
+///
+/// The n-gram range covers all units. For unknown words, finalfusion
+/// will then use all units that occur in the word, rather than a
+/// segmentation of the word.
+macro_rules! impl_segments_into_vocabwrap (
+    ($vocab:ty) => {
+        impl From<$vocab> for VocabWrap {
+            fn from(vocab: $vocab) -> Self {
+                let max_n = vocab
+                    .indexer
+                    .ngrams()
+                    .iter()
+                    .map(|unit| unit.chars().count())
+                    .max()
+                    .unwrap_or(1) as u32;
+                let words = vocab
+                    .words
+                    .into_iter()
+                    .map(|word| word.label)
+                    .collect::<Vec<_>>();
+                FiFuSubwordVocab::new(words, 1, max_n, vocab.indexer).into()
+            }
+        }
+    }
+);
+
+impl_segments_into_vocabwrap!(SubwordVocab<BpeConfig, ExplicitIndexer>);
+impl_segments_into_vocabwrap!(SubwordVocab<SentencePieceConfig, ExplicitIndexer>);
+
 #[cfg(test)]
 mod tests {
-    use super::{SubwordVocab, Vocab, VocabBuilder};
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    use super::{read_segmentations, SubwordVocab, Vocab, VocabBuilder};
     use crate::config::SubwordVocabConfig;
     use crate::idx::WordIdx;
-    use crate::{util, BucketConfig, Cutoff, NGramConfig};
+    use crate::vocab::compound::CompoundSplitter;
+    use crate::vocab::sentencepiece::SentencePieceModel;
+    use crate::vocab::{word_ngram_indices, word_ngram_indices_at};
+    use crate::{
+        util, BpeConfig, BucketConfig, Cutoff, NGramConfig, Script, ScriptNGrams,
+        SentencePieceConfig, WordBoundaries,
+    };
 
     use crate::config::BucketIndexerType::Finalfusion;
     use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer, Indexer};
@@ -219,6 +769,10 @@ mod tests {
         cutoff: Cutoff::MinCount(2),
         max_n: 6,
         min_n: 3,
+        include_word: true,
+        script_ngrams: ScriptNGrams::new(),
+        split_compounds: false,
+        boundaries: WordBoundaries::BRACKETS,
         indexer: BucketConfig {
             buckets_exp: 21,
             indexer_type: Finalfusion,
@@ -230,11 +784,39 @@ mod tests {
         cutoff: Cutoff::MinCount(2),
         max_n: 6,
         min_n: 3,
+        include_word: true,
+        script_ngrams: ScriptNGrams::new(),
+        split_compounds: false,
+        boundaries: WordBoundaries::BRACKETS,
         indexer: NGramConfig {
             cutoff: Cutoff::MinCount(2),
         },
     };
 
+    const TEST_BPECONFIG: SubwordVocabConfig<BpeConfig> = SubwordVocabConfig {
+        discard_threshold: 1e-4,
+        cutoff: Cutoff::MinCount(2),
+        max_n: 6,
+        min_n: 3,
+        include_word: true,
+        script_ngrams: ScriptNGrams::new(),
+        split_compounds: false,
+        boundaries: WordBoundaries::BRACKETS,
+        indexer: BpeConfig { merges: 2 },
+    };
+
+    const TEST_SENTENCEPIECECONFIG: SubwordVocabConfig<SentencePieceConfig> = SubwordVocabConfig {
+        discard_threshold: 1e-4,
+        cutoff: Cutoff::MinCount(2),
+        max_n: 6,
+        min_n: 3,
+        include_word: true,
+        script_ngrams: ScriptNGrams::new(),
+        split_compounds: false,
+        boundaries: WordBoundaries::BRACKETS,
+        indexer: SentencePieceConfig { pieces: 0 },
+    };
+
     #[test]
     pub fn vocab_is_sorted() {
         let mut config = TEST_SUBWORDCONFIG;
@@ -310,6 +892,161 @@ mod tests {
         assert!(vocab.idx("too").is_none());
     }
 
+    #[test]
+    pub fn test_bucket_vocab_statistics() {
+        let config = SubwordVocabConfig {
+            indexer: BucketConfig {
+                buckets_exp: 1,
+                indexer_type: Finalfusion,
+            },
+            ..TEST_SUBWORDCONFIG
+        };
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(config);
+        for token in &["to", "be", "to", "be"] {
+            builder.count(*token);
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+        assert!(vocab.bucket_statistics().is_none());
+
+        // ["<to", "to>", "<to>", "<be", "be>", "<be>"] in two buckets.
+        let vocab = vocab.with_bucket_statistics(1);
+        let statistics = vocab.bucket_statistics().unwrap();
+        assert_eq!(statistics.n_ngrams, 6);
+        assert_eq!(statistics.n_buckets, 2);
+        assert_eq!(statistics.histogram.iter().sum::<u64>(), 2);
+        assert_eq!(
+            statistics
+                .histogram
+                .iter()
+                .enumerate()
+                .map(|(n_ngrams, &n_buckets)| n_ngrams as u64 * n_buckets)
+                .sum::<u64>(),
+            6
+        );
+
+        // The worst bucket has at least half of the n-grams.
+        assert_eq!(statistics.worst_collisions.len(), 1);
+        let worst = &statistics.worst_collisions[0];
+        assert!(worst.ngrams.len() >= 3);
+        assert_eq!(worst.ngrams.len(), statistics.histogram.len() - 1);
+        assert_eq!(
+            statistics.n_used_buckets,
+            statistics.histogram[1..].iter().sum::<u64>() as usize
+        );
+    }
+
+    #[test]
+    pub fn test_bucket_vocab_exclude_word() {
+        let config = SubwordVocabConfig {
+            include_word: false,
+            ..TEST_SUBWORDCONFIG
+        };
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(config);
+        for token in &["to", "be", "to", "be"] {
+            builder.count(*token);
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        // Only the indices of ["<to", "to>", "<to>"].
+        let to = vocab.idx("to").unwrap();
+        assert_eq!(to.word_idx(), 0);
+        let indices = to.into_iter().collect::<Vec<_>>();
+        assert_eq!(indices.len(), 3);
+        assert!(indices.iter().all(|&idx| idx >= vocab.len() as u64));
+    }
+
+    #[test]
+    pub fn test_bucket_vocab_prune_ngrams() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_SUBWORDCONFIG);
+        for token in &["to", "be", "to", "be", "to"] {
+            builder.count(*token);
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+        assert_eq!(vocab.n_input_types(), 2 + 2usize.pow(21));
+
+        // The n-grams of "to" are used more than those of "be".
+        let vocab = vocab.prune_ngrams(3);
+        assert_eq!(vocab.config().indexer.cutoff, Cutoff::TargetSize(3));
+        assert_eq!(vocab.n_input_types(), 5);
+        let mut ngrams = vocab.indexer().ngrams().to_vec();
+        ngrams.sort_unstable();
+        assert_eq!(ngrams, &["<to", "<to>", "to>"]);
+
+        // The subword indices are followed by the word index.
+        assert_eq!(vocab.idx("to").unwrap().into_iter().count(), 4);
+        assert_eq!(
+            vocab.idx("be").unwrap().into_iter().collect::<Vec<_>>(),
+            vec![1]
+        );
+    }
+
+    struct TestSplitter;
+
+    impl CompoundSplitter for TestSplitter {
+        fn split(&self, word: &str) -> Option<Vec<String>> {
+            match word {
+                "haustür" => Some(vec!["haus".to_owned(), "tür".to_owned()]),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_bucket_vocab_split_compounds() {
+        let mut config = TEST_SUBWORDCONFIG;
+        config.cutoff = Cutoff::MinCount(1);
+
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(config);
+        for token in &["haus", "haus", "tür", "haustür"] {
+            builder.count(*token);
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+        let (vocab, n_compounds) = vocab.split_compounds(&TestSplitter);
+        assert_eq!(n_compounds, 1);
+        assert!(vocab.config().split_compounds);
+
+        let subwords = |word: &str| {
+            let mut indices = vocab.idx(word).unwrap().into_iter().collect::<Vec<_>>();
+            indices.pop();
+            indices
+        };
+
+        // The n-grams of the compound are those of its constituents.
+        let mut constituent_subwords = subwords("haus");
+        constituent_subwords.extend(subwords("tür"));
+        assert_eq!(subwords("haustür"), constituent_subwords);
+    }
+
+    #[test]
+    pub fn test_bucket_vocab_word_ngram_idx() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_SUBWORDCONFIG);
+        builder.count("to");
+        builder.count("be");
+        builder.count("to");
+        builder.count("be");
+
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        // Word n-grams are hashed into the subword buckets.
+        let idx = vocab.word_ngram_idx("to be").unwrap();
+        assert!(idx >= vocab.len() as u64);
+        assert!(idx < vocab.n_input_types() as u64);
+        assert_eq!(word_ngram_indices(&vocab, &["to", "be", "or"], 3).len(), 3);
+
+        // Word n-grams that end in a word.
+        let sentence = ["to", "be", "or"];
+        assert!(word_ngram_indices_at(&vocab, &sentence, 0, 3).is_empty());
+        assert_eq!(word_ngram_indices_at(&vocab, &sentence, 1, 3), vec![idx]);
+        assert_eq!(
+            word_ngram_indices_at(&vocab, &sentence, 2, 3),
+            vec![
+                vocab.word_ngram_idx("be or").unwrap(),
+                vocab.word_ngram_idx("to be or").unwrap()
+            ]
+        );
+        assert!(word_ngram_indices_at(&vocab, &sentence, 2, 1).is_empty());
+    }
+
     #[test]
     pub fn test_ngram_vocab_builder() {
         let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_NGRAMCONFIG);
@@ -369,4 +1106,287 @@ mod tests {
         // Check indices for an unknown word. Only "<to" is a known ngram.
         assert!(vocab.idx("too").is_none());
     }
+
+    #[test]
+    pub fn test_ngram_vocab_subword_counts() {
+        let config = SubwordVocabConfig {
+            cutoff: Cutoff::MinCount(1),
+            max_n: 3,
+            ..TEST_NGRAMCONFIG
+        };
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(config);
+        for token in &["tot", "to", "tot"] {
+            builder.count(*token);
+        }
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+
+        // "<to" occurs in both words, "to>" is removed by the cutoff.
+        let counts = vocab
+            .indexer()
+            .ngrams()
+            .iter()
+            .map(String::as_str)
+            .zip(vocab.subword_counts())
+            .collect::<HashMap<_, _>>();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts["<to"], 3);
+        assert_eq!(counts["tot"], 2);
+        assert_eq!(counts["ot>"], 2);
+    }
+
+    #[test]
+    pub fn test_ngram_vocab_script_ngrams() {
+        let mut script_ngrams = ScriptNGrams::new();
+        script_ngrams.set(Script::Han, 1, 1);
+        let config = SubwordVocabConfig {
+            cutoff: Cutoff::MinCount(1),
+            max_n: 3,
+            script_ngrams,
+            indexer: NGramConfig {
+                cutoff: Cutoff::MinCount(1),
+            },
+            ..TEST_NGRAMCONFIG
+        };
+        assert_eq!(config.ngram_bounds(), (1, 3));
+
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(config);
+        for token in &["tot", "中文"] {
+            builder.count(*token);
+        }
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+
+        let mut ngrams = vocab.indexer().ngrams().to_vec();
+        ngrams.sort_unstable();
+        assert_eq!(ngrams, &["<", "<to", ">", "ot>", "tot", "中", "文"]);
+    }
+
+    #[test]
+    pub fn test_ngram_vocab_boundaries() {
+        let vocab_with_boundaries = |boundaries| {
+            let config = SubwordVocabConfig {
+                cutoff: Cutoff::MinCount(1),
+                max_n: 3,
+                boundaries,
+                indexer: NGramConfig {
+                    cutoff: Cutoff::MinCount(1),
+                },
+                ..TEST_NGRAMCONFIG
+            };
+            let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(config);
+            builder.count("tot");
+            let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+            let mut ngrams = vocab.indexer().ngrams().to_vec();
+            ngrams.sort_unstable();
+            ngrams
+        };
+
+        assert_eq!(
+            vocab_with_boundaries(WordBoundaries::BRACKETS),
+            &["<to", "ot>", "tot"]
+        );
+        assert_eq!(
+            vocab_with_boundaries(WordBoundaries::try_from("^$").unwrap()),
+            &["^to", "ot$", "tot"]
+        );
+        assert_eq!(
+            vocab_with_boundaries(WordBoundaries::try_from("none").unwrap()),
+            &["tot"]
+        );
+        assert!(WordBoundaries::try_from("<").is_err());
+    }
+
+    #[test]
+    pub fn test_ngram_vocab_prune_subwords() {
+        let config = SubwordVocabConfig {
+            cutoff: Cutoff::MinCount(1),
+            max_n: 3,
+            ..TEST_NGRAMCONFIG
+        };
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(config);
+        for token in &["tot", "to", "tot"] {
+            builder.count(*token);
+        }
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+        assert_eq!(vocab.n_input_types(), 5);
+
+        // "tot" and "ot>" have the same usage, so both are removed.
+        let vocab = vocab.prune_subwords(2);
+        assert_eq!(vocab.indexer().ngrams(), &["<to"]);
+        assert_eq!(vocab.n_input_types(), 3);
+        assert_eq!(vocab.subword_counts(), vec![3]);
+        for (idx, word) in ["tot", "to"].iter().enumerate() {
+            assert_eq!(
+                vocab.idx(*word).unwrap().into_iter().collect::<Vec<_>>(),
+                vec![2, idx as u64]
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_bpe_vocab_builder() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_BPECONFIG);
+        builder.count("to");
+        builder.count("be");
+        builder.count("or");
+        builder.count("not");
+        builder.count("to");
+        builder.count("be");
+        builder.count("</s>");
+
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+
+        // 'or' and 'not' should be filtered due to the minimum count.
+        assert_eq!(vocab.len(), 2);
+        assert_eq!(vocab.n_types(), 7);
+
+        // All pairs have the same frequency, so the lexicographically
+        // smallest pairs are merged: "<" + "b" and "<" + "t".
+        // to: ["<t", "o", ">"], be: ["<b", "e", ">"]
+        // sorted [">", "o", "e", "<t", "<b"]
+        assert_eq!(&[">", "o", "e", "<t", "<b"], vocab.indexer.ngrams());
+        assert_eq!(vocab.n_input_types(), 7);
+        assert_eq!(
+            vec![5, 3, 2, 0],
+            vocab.idx("to").unwrap().into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![6, 4, 2, 1],
+            vocab.idx("be").unwrap().into_iter().collect::<Vec<_>>()
+        );
+
+        // Unknown words do not have a segmentation.
+        assert!(vocab.idx("too").is_none());
+    }
+
+    #[test]
+    pub fn test_bpe_vocab_with_merges() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_BPECONFIG);
+        builder.count("to");
+        builder.count("be");
+        builder.count("to");
+        builder.count("be");
+
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+        let vocab = vocab.with_merges(&[
+            ("t".to_string(), "o".to_string()),
+            ("<".to_string(), "to".to_string()),
+        ]);
+
+        assert_eq!(vocab.config().indexer.merges, 2);
+
+        // to: ["<to", ">"]
+        let to = vocab.idx("to").unwrap().into_iter().collect::<Vec<_>>();
+        assert_eq!(to.len(), 3);
+        assert_eq!(
+            vocab.word_ngram_idx("<to"),
+            Some(to[0]),
+            "Unit of 'to' should be '<to'"
+        );
+    }
+
+    #[test]
+    pub fn test_sentencepiece_vocab() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_SENTENCEPIECECONFIG);
+        builder.count("to");
+        builder.count("be");
+        builder.count("to");
+        builder.count("be");
+
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+
+        // Words are not segmented without a model.
+        assert_eq!(
+            vec![0],
+            vocab.idx("to").unwrap().into_iter().collect::<Vec<_>>()
+        );
+
+        let model = SentencePieceModel::new(
+            vec![("\u{2581}to", -1.), ("\u{2581}b", -2.), ("e", -2.)]
+                .into_iter()
+                .map(|(piece, score)| (piece.to_string(), score))
+                .collect(),
+        );
+        let vocab = vocab.with_model(&model);
+        assert_eq!(vocab.config().indexer.pieces, 3);
+
+        // to: ["<to"], be: ["<b", "e"]
+        // sorted ["e", "<to", "<b"]
+        assert_eq!(&["e", "<to", "<b"], vocab.indexer.ngrams());
+        assert_eq!(vocab.n_input_types(), 5);
+        assert_eq!(
+            vec![3, 0],
+            vocab.idx("to").unwrap().into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![4, 2, 1],
+            vocab.idx("be").unwrap().into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    fn test_segmentations() -> HashMap<String, Vec<String>> {
+        read_segmentations("# word segments\nto <to x\nunknown un known\n".as_bytes()).unwrap()
+    }
+
+    #[test]
+    pub fn test_read_segmentations() {
+        let segmentations = test_segmentations();
+        assert_eq!(segmentations.len(), 2);
+        assert_eq!(segmentations["to"], vec!["<to", "x"]);
+        assert_eq!(segmentations["unknown"], vec!["un", "known"]);
+
+        assert!(read_segmentations("to\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    pub fn test_bucket_vocab_segmentations() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_SUBWORDCONFIG);
+        builder.count("to");
+        builder.count("be");
+        builder.count("to");
+        builder.count("be");
+
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+        let be = vocab.idx("be").unwrap().into_iter().collect::<Vec<_>>();
+        let vocab = vocab.with_segmentations(&test_segmentations());
+
+        // The segments of 'to' are hashed.
+        assert_eq!(
+            vec![
+                vocab.word_ngram_idx("<to").unwrap(),
+                vocab.word_ngram_idx("x").unwrap(),
+                0
+            ],
+            vocab.idx("to").unwrap().into_iter().collect::<Vec<_>>()
+        );
+
+        // 'be' does not have a segmentation.
+        assert_eq!(be, vocab.idx("be").unwrap().into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn test_ngram_vocab_segmentations() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_NGRAMCONFIG);
+        builder.count("to");
+        builder.count("be");
+        builder.count("to");
+        builder.count("be");
+
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+        let vocab = vocab.with_segmentations(&test_segmentations());
+
+        // The unknown segment "x" is appended to the n-grams.
+        assert_eq!(
+            &["to>", "be>", "<to>", "<to", "<be>", "<be", "x"],
+            vocab.indexer.ngrams()
+        );
+        assert_eq!(vocab.n_input_types(), 9);
+        assert_eq!(
+            vec![5, 8, 0],
+            vocab.idx("to").unwrap().into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![6, 7, 3, 1],
+            vocab.idx("be").unwrap().into_iter().collect::<Vec<_>>()
+        );
+    }
 }